@@ -0,0 +1,39 @@
+use criterion::{criterion_group,criterion_main,black_box,Criterion,Throughput};
+use scc::{lex,lex::Lexer};
+
+/// Generates a C file of about `size` bytes: many small functions mixing identifiers,
+/// keywords, constants of each kind, string literals, operators and comments.
+fn generate_source(size: usize) -> String {
+    let mut source = String::with_capacity(size + 1024);
+    let mut index = 0;
+    while source.len() < size {
+        source.push_str(&format!(
+            "/* Function number {index}, which\n   sums things up */\n\
+             static long accumulate_{index}(long count, double scale) {{\n\
+             \x20   long total_{index} = 0x{index:x}L; // running total\n\
+             \x20   for (int i = 0; i < count; i++) {{\n\
+             \x20       total_{index} += (i * 3u) % 07 - (i >> 2) & 0b101;\n\
+             \x20       if (total_{index} >= 1000000 || scale != 2.5e-3)\n\
+             \x20           puts(\"overflow in accumulate_{index}\\n\");\n\
+             \x20   }}\n\
+             \x20   return total_{index} * (long)(scale * 1.0) + 'x';\n\
+             }}\n\n"
+        ));
+        index += 1;
+    }
+    source
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let source = generate_source(5 * 1024 * 1024);
+    let mut group = c.benchmark_group("lex");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(20);
+    group.bench_function("5 MB", |b| b.iter(|| lex(black_box(&source)).unwrap()));
+    // Streaming the tokens without collecting them, as a tool that only scans the source would
+    group.bench_function("5 MB streamed", |b| b.iter(|| Lexer::new(black_box(&source)).map(Result::unwrap).count()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer);
+criterion_main!(benches);
@@ -0,0 +1,1271 @@
+use std::collections::{BTreeMap,HashMap,HashSet};
+use crate::ast::*;
+use crate::ir::*;
+use crate::diagnostics::Diagnostic;
+use crate::target::{Arch, Os, SystemCallArgument, Target};
+use crate::assembly::{constant_to_string, file_directive, static_init_to_string};
+/// The number of integer arguments the AAPCS64 passes in registers, `x0`-`x7`, and of double
+/// arguments, which go in `d0`-`d7`.
+const ARG_REGISTER_COUNT: usize = 8;
+
+/// Converts an IR program to AArch64 assembly code for Linux or macOS.
+///
+/// Every IR variable lives in a stack slot below the frame pointer `x29`, 1 byte for a
+/// character, 4 for an `int` and 8 for a `long`, except for static variables and string
+/// constants, which are addressed by symbol. Each
+/// instruction loads its operands into the scratch registers `x9`-`x11`, using their 32-bit
+/// halves `w9`-`w11` for `int`s, computes the result there and stores it back, so no register
+/// allocation is needed. Doubles are computed in `d16` and `d17`, which unlike `d8`-`d15` need
+/// not be preserved across calls, and are copied as their bits through the general-purpose
+/// registers.
+///
+/// # Arguments
+///
+/// * `ir` - The IR program to be converted.
+/// * `os` - The operating system, which decides symbol names, platform directives and how
+///   arguments are laid out on the stack.
+/// * `debug_file` - The path of the source file, if debug information is to be emitted: the
+///   file the `.loc` directives refer to, along with call frame information for each function.
+///
+/// # Returns
+///
+/// * `Result<String, Diagnostic>` - The assembly code if conversion is successful, otherwise an error.
+pub fn generate_aarch64(ir: IrProgram, os: Os, debug_file: Option<&str>) -> Result<String, Diagnostic> {
+    let mut asm: String = String::new();
+
+    if let Some(path) = debug_file {
+        asm.push_str(&file_directive(path));
+    }
+    let statics = ir.static_names();
+    let constants: HashSet<Name> = ir.static_constants.iter().map(|constant| constant.name).collect();
+    for function in ir.functions {
+        asm.push_str(&function_to_string(function, os, (&statics, &constants), &ir.types, debug_file.is_some())?);
+    }
+    for variable in ir.static_variables {
+        asm.push_str(&static_variable_to_string(variable, os));
+    }
+    for constant in ir.static_constants {
+        let constant = AsmConstant { name: constant.name, alignment: constant.alignment, init: constant.init };
+        asm.push_str(&constant_to_string(constant, os));
+    }
+    asm.push_str(os.stack_note());
+    Ok(asm)
+}
+
+/// Converts a static variable to the directives that reserve and initialize its memory, in
+/// `.data`, or in `.bss` if it starts as all zeros.
+///
+/// # Arguments
+///
+/// * `variable` - The static variable to be converted.
+/// * `os` - The operating system, which decides the symbol name.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the variable.
+fn static_variable_to_string(variable: IrStaticVariable, os: Os) -> String {
+    let name = os.symbol(&variable.name);
+    let mut asm = String::new();
+    if variable.global {
+        asm.push_str(&format!(" .globl {}\n", name));
+    }
+    if variable.init.iter().all(StaticInit::is_zero) {
+        let size: usize = variable.init.iter().map(StaticInit::size).sum();
+        asm.push_str(&format!(" .bss\n .balign {}\n", variable.alignment));
+        asm.push_str(&format!("{}:\n    .zero {}\n", name, size));
+        return asm;
+    }
+    asm.push_str(&format!(" .data\n .balign {}\n{}:\n", variable.alignment, name));
+    for init in &variable.init {
+        asm.push_str(&static_init_to_string(init, os));
+    }
+    asm
+}
+
+/// Returns the name of a general-purpose register, in the width that holds a value of a type.
+///
+/// # Arguments
+///
+/// * `number` - The number of the register.
+/// * `ty` - The type of the value in it.
+///
+/// # Returns
+///
+/// * `String` - `x<number>` for an 8-byte type, `w<number>` otherwise.
+fn register(number: usize, ty: &Type) -> String {
+    match ty.size() {
+        8 => format!("x{}", number),
+        _ => format!("w{}", number),
+    }
+}
+
+/// Returns the suffix of the `ldr` and `str` instructions that move a value of a type
+/// between memory and a general-purpose register.
+///
+/// # Arguments
+///
+/// * `ty` - The type of the value.
+///
+/// # Returns
+///
+/// * `&str` - `b` for a character, whose load zero-extends it into a `w` register, and
+///   nothing otherwise.
+fn memory_suffix(ty: &Type) -> &'static str {
+    match ty.size() {
+        1 => "b",
+        _ => "",
+    }
+}
+
+/// Assigns the arguments of a call, or the parameters of a function, to the registers they
+/// are passed in: the first eight integers go in `x0`-`x7` and the first eight doubles in
+/// `d0`-`d7`. The rest are passed on the stack.
+///
+/// # Arguments
+///
+/// * `types` - The types of the arguments, in order.
+///
+/// # Returns
+///
+/// * `Vec<Option<usize>>` - The number of the register of each argument, or `None` for those
+///   passed on the stack.
+fn classify_arguments(types: &[Type]) -> Vec<Option<usize>> {
+    let (mut integers, mut doubles) = (0, 0);
+    types
+        .iter()
+        .map(|ty| {
+            let count = if *ty == Type::Double { &mut doubles } else { &mut integers };
+            *count += 1;
+            (*count <= ARG_REGISTER_COUNT).then_some(*count - 1)
+        })
+        .collect()
+}
+
+/// Returns the offsets from `sp` of the arguments a call passes on the stack, and the number
+/// of bytes they occupy. The AAPCS64 rounds every stack argument up to 8 bytes, while Apple's
+/// variant packs them at their natural size and alignment.
+///
+/// # Arguments
+///
+/// * `types` - The types of the stack arguments, in order.
+/// * `os` - The operating system.
+///
+/// # Returns
+///
+/// * `(Vec<usize>, usize)` - The offset of each argument and the total size.
+fn stack_argument_offsets(types: &[Type], os: Os) -> (Vec<usize>, usize) {
+    let mut offsets = Vec::with_capacity(types.len());
+    let mut size: usize = 0;
+    for ty in types {
+        let slot_size = match os {
+            Os::Linux => 8,
+            Os::Darwin => ty.size(),
+        };
+        size = size.div_ceil(slot_size) * slot_size;
+        offsets.push(size);
+        size += slot_size;
+    }
+    (offsets, size)
+}
+
+/// Converts one IR function to assembly, including the prologue that saves the frame
+/// pointer and link register and reserves the stack slots.
+///
+/// The function starts by copying its parameters into their slots: the first eight integers
+/// and the first eight doubles arrive in registers, the rest on the stack just above the saved
+/// `x29` and `x30`.
+///
+/// # Arguments
+///
+/// * `function` - The IR function to be converted.
+/// * `os` - The operating system, which decides symbol and label names.
+/// * `statics` - The names of the static variables and of the string constants.
+/// * `types` - The types of the variables and temporaries of the program.
+/// * `debug_info` - Whether to describe the stack frame with call frame information, so
+///   debuggers can unwind through the function.
+///
+/// # Returns
+///
+/// * `Result<String, Diagnostic>` - The assembly code of the function, or an error.
+fn function_to_string(
+    function: IrFunction,
+    os: Os,
+    statics: (&HashSet<Name>, &HashSet<Name>),
+    types: &BTreeMap<Name, Type>,
+    debug_info: bool,
+) -> Result<String, Diagnostic> {
+    let (statics, constants) = statics;
+    let mut emitter = FunctionEmitter {
+        slots: HashMap::new(),
+        stack_size: 0,
+        body: String::new(),
+        os,
+        statics,
+        constants,
+        types,
+        debug_info,
+    };
+    let params: Vec<IrValue> = function.params.into_iter().map(IrValue::Var).collect();
+    let param_types: Vec<Type> = params.iter().map(|param| emitter.type_of(param)).collect();
+    let registers = classify_arguments(&param_types);
+    let stack_params: Vec<&IrValue> =
+        params.iter().zip(&registers).filter(|(_, register)| register.is_none()).map(|(param, _)| param).collect();
+    let stack_types: Vec<Type> = stack_params.iter().map(|param| emitter.type_of(param)).collect();
+    let (offsets, _) = stack_argument_offsets(&stack_types, os);
+    for ((param, ty), register) in params.iter().zip(&param_types).zip(&registers) {
+        match (register, ty) {
+            (Some(number), Type::Double) => emitter.store_fp(*number, param)?,
+            (Some(number), _) => emitter.store(*number, param)?,
+            (None, _) => {}
+        }
+    }
+    for ((param, offset), ty) in stack_params.iter().zip(offsets).zip(&stack_types) {
+        emitter.emit(&format!("ldr{} {}, [x29, #{}]", memory_suffix(ty), register(9, ty), 16 + offset));
+        emitter.store(9, param)?;
+    }
+    for instruction in function.body {
+        emitter.instruction(instruction)?;
+    }
+
+    let mut asm: String = String::new();
+    let name = os.symbol(&function.name);
+    if function.global {
+        asm.push_str(&format!(" .globl {}\n", name));
+    }
+    asm.push_str(&format!("{}:\n", name));
+    // DWARF numbers the frame pointer 29, the link register 30 and sp 31
+    let cfi = |directives: &[&str]| directives.iter().map(|directive| format!("    .cfi_{}\n", directive)).collect::<String>();
+    if debug_info {
+        asm.push_str(&cfi(&["startproc"]));
+        if function.span.line != 0 {
+            asm.push_str(&format!("    .loc 1 {} {}\n", function.span.line, function.span.column));
+        }
+    }
+    asm.push_str("    stp x29, x30, [sp, #-16]!\n");
+    if debug_info {
+        asm.push_str(&cfi(&["def_cfa_offset 16", "offset 30, -8", "offset 29, -16"]));
+    }
+    asm.push_str("    mov x29, sp\n");
+    if debug_info {
+        asm.push_str(&cfi(&["def_cfa 29, 16"]));
+    }
+    // sp must stay 16-byte aligned at all times on AArch64
+    asm.push_str(&adjust_stack("sub", (emitter.stack_size + 15) / 16 * 16));
+    asm.push_str(&emitter.body);
+    if debug_info {
+        asm.push_str(&cfi(&["endproc"]));
+    }
+    Ok(asm)
+}
+
+/// Generates a minimal program entry point for freestanding executables that are linked
+/// without libc: it calls `main` and passes its return value to the `exit` system call.
+///
+/// # Arguments
+///
+/// * `entry` - The name of the entry symbol, `_start` unless the user asked otherwise.
+/// * `os` - The operating system, which decides symbol names and the system call convention.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the entry point.
+pub fn entry_point_to_string(entry: &str, os: Os) -> String {
+    let mut asm: String = String::new();
+
+    let entry = os.symbol(entry);
+    asm.push_str(&format!(" .globl {}\n{}:\n", entry, entry));
+    asm.push_str(&format!("    bl {}\n", os.symbol("main")));
+    match os {
+        Os::Linux => {
+            asm.push_str("    mov x8, #93\n");
+            asm.push_str("    svc #0\n");
+        }
+        Os::Darwin => {
+            asm.push_str("    mov x16, #1\n");
+            asm.push_str("    svc #0x80\n");
+        }
+    }
+    asm
+}
+
+/// State kept while emitting the body of one function.
+struct FunctionEmitter<'a> {
+    /// The distance below `x29` of each variable's slot.
+    slots: HashMap<Name, i32>,
+    /// The number of bytes of stack the slots occupy.
+    stack_size: i32,
+    body: String,
+    os: Os,
+    statics: &'a HashSet<Name>,
+    /// The names of the string constants, which have local labels rather than symbols.
+    constants: &'a HashSet<Name>,
+    types: &'a BTreeMap<Name, Type>,
+    /// Whether to emit call frame information around each return.
+    debug_info: bool,
+}
+
+impl FunctionEmitter<'_> {
+    /// Appends one instruction to the function body.
+    fn emit(&mut self, instruction: &str) {
+        self.body.push_str(&format!("    {}\n", instruction));
+    }
+
+    /// Appends a call frame information directive, if debug information is requested.
+    fn cfi(&mut self, directive: &str) {
+        if self.debug_info {
+            self.emit(&format!(".cfi_{}", directive));
+        }
+    }
+
+    /// Returns the type of an IR value.
+    fn type_of(&self, value: &IrValue) -> Type {
+        value_type(value, self.types)
+    }
+
+    /// Returns the symbol of a static variable or the label of a string constant, or `None`
+    /// for a variable that lives on the stack.
+    fn static_symbol(&self, name: Name) -> Option<String> {
+        if self.statics.contains(&name) {
+            Some(self.os.symbol(&name))
+        } else if self.constants.contains(&name) {
+            Some(self.os.local_label(&name))
+        } else {
+            None
+        }
+    }
+
+    /// Converts one IR instruction to assembly instructions.
+    fn instruction(&mut self, instruction: IrInstruction) -> Result<(), Diagnostic> {
+        match instruction {
+            // The immediate is what GCC uses for __builtin_trap
+            IrInstruction::Trap => self.emit("brk #1000"),
+            IrInstruction::Return(value) => {
+                if self.type_of(&value) == Type::Double {
+                    self.load_fp(&value, 0);
+                } else {
+                    self.load(&value, 0);
+                }
+                // Code after an early return still runs with the frame set up
+                self.cfi("remember_state");
+                self.emit("mov sp, x29");
+                self.emit("ldp x29, x30, [sp], #16");
+                self.cfi("def_cfa 31, 0");
+                self.cfi("restore 30");
+                self.cfi("restore 29");
+                self.emit("ret");
+                self.cfi("restore_state");
+            }
+            IrInstruction::Location(span) => self.emit(&format!(".loc 1 {} {}", span.line, span.column)),
+            IrInstruction::Copy { src, dst } => {
+                self.load(&src, 9);
+                self.store(9, &dst)?;
+            }
+            IrInstruction::GetAddress { src, dst } => {
+                let IrValue::Var(name) = &src else {
+                    return Err(Diagnostic::error_without_span("Cannot take the address of a constant"));
+                };
+                self.variable_address(*name, 9);
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Load { src_ptr, dst } => {
+                self.load(&src_ptr, 9);
+                if self.type_of(&dst) == Type::Double {
+                    self.emit("ldr d16, [x9]");
+                    self.store_fp(16, &dst)?;
+                } else {
+                    let ty = self.type_of(&dst);
+                    self.emit(&format!("ldr{} {}, [x9]", memory_suffix(&ty), register(10, &ty)));
+                    self.store(10, &dst)?;
+                }
+            }
+            IrInstruction::Store { src, dst_ptr } => {
+                // The value is loaded first, as building a double constant goes through x9
+                if self.type_of(&src) == Type::Double {
+                    self.load_fp(&src, 16);
+                    self.load(&dst_ptr, 9);
+                    self.emit("str d16, [x9]");
+                } else {
+                    self.load(&src, 10);
+                    self.load(&dst_ptr, 9);
+                    let ty = self.type_of(&src);
+                    self.emit(&format!("str{} {}, [x9]", memory_suffix(&ty), register(10, &ty)));
+                }
+            }
+            // The variables are addressed from x29 rather than sp, so moving sp leaves them be,
+            // and the epilogue restores sp from x29
+            IrInstruction::Alloca { size, dst } => {
+                self.load(&size, 9);
+                self.emit("add x9, x9, #15");
+                self.emit("and x9, x9, #-16");
+                self.emit("sub sp, sp, x9");
+                self.emit("mov x9, sp");
+                self.store(9, &dst)?;
+            }
+            // Only integers are atomic, and acquire loads and release stores are sequentially
+            // consistent with each other
+            IrInstruction::AtomicLoad { src_ptr, dst } => {
+                let ty = self.type_of(&dst);
+                self.load(&src_ptr, 9);
+                self.emit(&format!("ldar{} {}, [x9]", memory_suffix(&ty), register(10, &ty)));
+                self.store(10, &dst)?;
+            }
+            IrInstruction::AtomicStore { src, dst_ptr } => {
+                let ty = self.type_of(&src);
+                self.load(&src, 10);
+                self.load(&dst_ptr, 9);
+                self.emit(&format!("stlr{} {}, [x9]", memory_suffix(&ty), register(10, &ty)));
+            }
+            IrInstruction::CompareExchange { ptr, expected, desired, dst } => {
+                // The exclusive store fails if another thread wrote the object since the
+                // exclusive load, in which case the comparison is tried again
+                let ty = self.type_of(&expected);
+                let suffix = memory_suffix(&ty);
+                let (r10, r11, r12) = (register(10, &ty), register(11, &ty), register(12, &ty));
+                self.load(&expected, 10);
+                if ty.size() == 1 {
+                    // A byte loaded from memory is zero-extended, so the expected value must be too
+                    self.emit("uxtb w10, w10");
+                }
+                self.load(&desired, 11);
+                self.load(&ptr, 9);
+                self.body.push_str("1:\n");
+                self.emit(&format!("ldaxr{} {}, [x9]", suffix, r12));
+                self.emit(&format!("cmp {}, {}", r12, r10));
+                self.emit("b.ne 2f");
+                self.emit(&format!("stlxr{} w13, {}, [x9]", suffix, r11));
+                self.emit("cbnz w13, 1b");
+                self.body.push_str("2:\n");
+                self.emit("cset w12, eq");
+                self.store(12, &dst)?;
+            }
+            IrInstruction::AddPtr { ptr, index, scale, dst } => {
+                self.load(&ptr, 9);
+                self.load(&index, 10);
+                if scale.is_power_of_two() {
+                    self.emit(&format!("add x9, x9, x10, lsl #{}", scale.trailing_zeros()));
+                } else {
+                    self.body.push_str(&move_immediate("x11", scale as i64));
+                    self.emit("madd x9, x10, x11, x9");
+                }
+                self.store(9, &dst)?;
+            }
+            IrInstruction::CopyToOffset { src, dst, offset } => {
+                // Like a store through a pointer, the value is loaded before the address
+                let ty = self.type_of(&src);
+                let value = if ty == Type::Double {
+                    self.load_fp(&src, 16);
+                    "d16".to_string()
+                } else {
+                    self.load(&src, 10);
+                    register(10, &ty)
+                };
+                self.variable_address(dst, 9);
+                let suffix = memory_suffix(&ty);
+                if offset < 4096 {
+                    self.emit(&format!("str{} {}, [x9, #{}]", suffix, value, offset));
+                } else {
+                    self.body.push_str(&move_immediate("x11", offset as i64));
+                    self.emit(&format!("str{} {}, [x9, x11]", suffix, value));
+                }
+            }
+            IrInstruction::SignExtend { src, dst } => {
+                let mnemonic = if self.type_of(&src).size() == 1 { "sxtb" } else { "sxtw" };
+                self.load(&src, 9);
+                self.emit(&format!("{} {}, w9", mnemonic, register(9, &self.type_of(&dst))));
+                self.store(9, &dst)?;
+            }
+            IrInstruction::ZeroExtend { src, dst } => {
+                // Loading a byte or writing w9 clears the rest of x9
+                self.load(&src, 9);
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Truncate { src, dst } => {
+                // Storing w9, or its low byte, keeps the low bits
+                self.load(&src, 9);
+                self.store(9, &dst)?;
+            }
+            IrInstruction::IntToDouble { src, dst } | IrInstruction::UIntToDouble { src, dst } => {
+                let ty = self.type_of(&src);
+                let mnemonic = if ty.is_signed() { "scvtf" } else { "ucvtf" };
+                let r9 = register(9, &ty);
+                self.load(&src, 9);
+                if ty.size() == 1 && ty.is_signed() {
+                    self.emit("sxtb w9, w9");
+                }
+                self.emit(&format!("{} d16, {}", mnemonic, r9));
+                self.store_fp(16, &dst)?;
+            }
+            IrInstruction::DoubleToInt { src, dst } | IrInstruction::DoubleToUInt { src, dst } => {
+                let mnemonic = if self.type_of(&dst).is_signed() { "fcvtzs" } else { "fcvtzu" };
+                let r9 = register(9, &self.type_of(&dst));
+                self.load_fp(&src, 16);
+                self.emit(&format!("{} {}, d16", mnemonic, r9));
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Unary { op: UnaryOperator::Negate, src, dst } if self.type_of(&src) == Type::Double => {
+                self.load_fp(&src, 16);
+                self.emit("fneg d16, d16");
+                self.store_fp(16, &dst)?;
+            }
+            IrInstruction::Unary { op: UnaryOperator::Not, src, dst } if self.type_of(&src) == Type::Double => {
+                self.load_fp(&src, 16);
+                self.emit("fcmp d16, #0.0");
+                self.emit("cset w9, eq");
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Unary { op, src, dst } => {
+                let r9 = register(9, &self.type_of(&src));
+                self.load(&src, 9);
+                match op {
+                    UnaryOperator::Negate => self.emit(&format!("neg {}, {}", r9, r9)),
+                    UnaryOperator::Complement => self.emit(&format!("mvn {}, {}", r9, r9)),
+                    UnaryOperator::Not => {
+                        self.emit(&format!("cmp {}, #0", r9));
+                        self.emit("cset w9, eq");
+                    }
+                }
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Binary { op, src1, src2, dst } if self.type_of(&src1) == Type::Double => {
+                self.load_fp(&src1, 16);
+                self.load_fp(&src2, 17);
+                let mnemonic = match op {
+                    BinaryOperator::Add => "fadd",
+                    BinaryOperator::Subtract => "fsub",
+                    BinaryOperator::Multiply => "fmul",
+                    BinaryOperator::Divide => "fdiv",
+                    relational => {
+                        // Unordered operands set only the carry and overflow flags, which
+                        // makes every one of these conditions but ne false
+                        self.emit("fcmp d16, d17");
+                        self.emit(&format!("cset w9, {}", double_cond_code(relational)));
+                        return self.store(9, &dst);
+                    }
+                };
+                self.emit(&format!("{} d16, d16, d17", mnemonic));
+                self.store_fp(16, &dst)?;
+            }
+            IrInstruction::Binary { op, src1, src2, dst } => {
+                // A shift count may be narrower than the value shifted, but only its low bits matter
+                let ty = self.type_of(&src1);
+                let signed = ty.is_signed();
+                let (r9, r10, r11) = (register(9, &ty), register(10, &ty), register(11, &ty));
+                self.load(&src1, 9);
+                self.load(&src2, 10);
+                match op {
+                    BinaryOperator::Add => self.emit(&format!("add {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::Subtract => self.emit(&format!("sub {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::Multiply => self.emit(&format!("mul {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::Divide => self.emit(&format!("{} {}, {}, {}", divide(signed), r9, r9, r10)),
+                    BinaryOperator::Remainder => {
+                        // There is no remainder instruction: a % b = a - (a / b) * b
+                        self.emit(&format!("{} {}, {}, {}", divide(signed), r11, r9, r10));
+                        self.emit(&format!("msub {}, {}, {}, {}", r9, r11, r10, r9));
+                    }
+                    BinaryOperator::BitwiseAnd => self.emit(&format!("and {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::BitwiseOr => self.emit(&format!("orr {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::BitwiseXor => self.emit(&format!("eor {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::LeftShift => self.emit(&format!("lsl {}, {}, {}", r9, r9, r10)),
+                    BinaryOperator::RightShift => {
+                        let mnemonic = if signed { "asr" } else { "lsr" };
+                        self.emit(&format!("{} {}, {}, {}", mnemonic, r9, r9, r10));
+                    }
+                    BinaryOperator::And | BinaryOperator::Or => {
+                        return Err(Diagnostic::error_without_span("Logical operators must be lowered to jumps"));
+                    }
+                    relational => {
+                        self.emit(&format!("cmp {}, {}", r9, r10));
+                        self.emit(&format!("cset w9, {}", cond_code(relational, signed)));
+                    }
+                }
+                self.store(9, &dst)?;
+            }
+            IrInstruction::Jump(target) => self.emit(&format!("b {}", self.os.local_label(&target))),
+            IrInstruction::JumpIfZero(condition, target) if self.type_of(&condition) == Type::Double => {
+                self.load_fp(&condition, 16);
+                self.emit("fcmp d16, #0.0");
+                self.emit(&format!("b.eq {}", self.os.local_label(&target)));
+            }
+            IrInstruction::JumpIfZero(condition, target) => {
+                let r9 = register(9, &self.type_of(&condition));
+                self.load(&condition, 9);
+                self.emit(&format!("cbz {}, {}", r9, self.os.local_label(&target)));
+            }
+            IrInstruction::JumpIfNotZero(condition, target) if self.type_of(&condition) == Type::Double => {
+                self.load_fp(&condition, 16);
+                self.emit("fcmp d16, #0.0");
+                self.emit(&format!("b.ne {}", self.os.local_label(&target)));
+            }
+            IrInstruction::JumpIfNotZero(condition, target) => {
+                let r9 = register(9, &self.type_of(&condition));
+                self.load(&condition, 9);
+                self.emit(&format!("cbnz {}, {}", r9, self.os.local_label(&target)));
+            }
+            IrInstruction::Label(name) => self.body.push_str(&format!("{}:\n", self.os.local_label(&name))),
+            IrInstruction::FunCall { name, args, dst } => {
+                let arg_types: Vec<Type> = args.iter().map(|arg| self.type_of(arg)).collect();
+                let registers = classify_arguments(&arg_types);
+                let stack_args: Vec<&IrValue> =
+                    args.iter().zip(&registers).filter(|(_, register)| register.is_none()).map(|(arg, _)| arg).collect();
+                let stack_types: Vec<Type> = stack_args.iter().map(|arg| self.type_of(arg)).collect();
+                let (offsets, size) = stack_argument_offsets(&stack_types, self.os);
+                // sp must stay 16-byte aligned
+                let stack_bytes = (size as i32 + 15) / 16 * 16;
+                if stack_bytes != 0 {
+                    self.body.push_str(&adjust_stack("sub", stack_bytes));
+                }
+                for ((arg, offset), ty) in stack_args.iter().zip(offsets).zip(&stack_types) {
+                    self.load(arg, 9);
+                    self.emit(&format!("str{} {}, [sp, #{}]", memory_suffix(ty), register(9, ty), offset));
+                }
+                for ((arg, ty), register) in args.iter().zip(&arg_types).zip(&registers) {
+                    match (register, ty) {
+                        (Some(number), Type::Double) => self.load_fp(arg, *number),
+                        (Some(number), _) => self.load(arg, *number),
+                        (None, _) => {}
+                    }
+                }
+                self.emit(&format!("bl {}", self.os.symbol(&name)));
+                if stack_bytes != 0 {
+                    self.body.push_str(&adjust_stack("add", stack_bytes));
+                }
+                if self.type_of(&dst) == Type::Double {
+                    self.store_fp(0, &dst)?;
+                } else {
+                    self.store(0, &dst)?;
+                }
+            }
+            IrInstruction::SystemCall { call, args, dst } => {
+                // Variables live on the stack, so each argument is loaded straight into place
+                let (number, arguments) = Target { arch: Arch::Aarch64, os: self.os }.system_call(call);
+                for (position, argument) in arguments.iter().enumerate() {
+                    match argument {
+                        SystemCallArgument::Value(index) => self.load(&args[*index], position),
+                        SystemCallArgument::Constant(value) => {
+                            let instructions = move_immediate(&format!("x{}", position), *value);
+                            self.body.push_str(&instructions);
+                        }
+                    }
+                }
+                match self.os {
+                    Os::Linux => {
+                        self.emit(&format!("mov x8, #{}", number));
+                        self.emit("svc #0");
+                    }
+                    Os::Darwin => {
+                        self.emit(&format!("mov x16, #{}", number));
+                        self.emit("svc #0x80");
+                        // The carry flag is set on failure, when x0 holds the error number
+                        self.emit("csneg x0, x0, x0, cc");
+                    }
+                }
+                self.store(0, &dst)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads an IR value into a register, using its 32-bit half for an `int` or a character,
+    /// which is zero-extended. A double is loaded as its bits.
+    fn load(&mut self, value: &IrValue, number: usize) {
+        let ty = self.type_of(value);
+        let register = register(number, &ty);
+        match value {
+            IrValue::Constant(value) => {
+                let instructions = move_immediate(&register, value.storage_value());
+                self.body.push_str(&instructions);
+            }
+            IrValue::Var(name) => {
+                let address = self.slot_address(*name);
+                self.emit(&format!("ldr{} {}, {}", memory_suffix(&ty), register, address));
+            }
+        }
+    }
+
+    /// Stores a register into the slot of an IR variable, using its 32-bit half for an `int`
+    /// and its low byte for a character.
+    fn store(&mut self, number: usize, dst: &IrValue) -> Result<(), Diagnostic> {
+        match dst {
+            IrValue::Var(name) => {
+                let ty = self.type_of(dst);
+                let register = register(number, &ty);
+                let address = self.slot_address(*name);
+                self.emit(&format!("str{} {}, {}", memory_suffix(&ty), register, address));
+                Ok(())
+            }
+            IrValue::Constant(value) => Err(Diagnostic::error_without_span(format!("Cannot assign to constant {}", value))),
+        }
+    }
+
+    /// Loads a double into a floating-point register. A constant is built in `x9` first, as
+    /// `fmov` only encodes a few immediates.
+    fn load_fp(&mut self, value: &IrValue, number: usize) {
+        match value {
+            IrValue::Constant(value) => {
+                let instructions = move_immediate("x9", value.storage_value());
+                self.body.push_str(&instructions);
+                self.emit(&format!("fmov d{}, x9", number));
+            }
+            IrValue::Var(name) => {
+                let address = self.slot_address(*name);
+                self.emit(&format!("ldr d{}, {}", number, address));
+            }
+        }
+    }
+
+    /// Stores a floating-point register into the slot of an IR variable.
+    fn store_fp(&mut self, number: usize, dst: &IrValue) -> Result<(), Diagnostic> {
+        match dst {
+            IrValue::Var(name) => {
+                let address = self.slot_address(*name);
+                self.emit(&format!("str d{}, {}", number, address));
+                Ok(())
+            }
+            IrValue::Constant(value) => Err(Diagnostic::error_without_span(format!("Cannot assign to constant {}", value))),
+        }
+    }
+
+    /// Returns the address operand of a variable's slot, assigning the next free slot the
+    /// first time the variable is seen. Loads and stores only encode offsets down to -256,
+    /// so deeper slots are addressed through `x16`, as are static variables, whose page
+    /// address is put there with `adrp`.
+    fn slot_address(&mut self, name: Name) -> String {
+        if let Some(symbol) = self.static_symbol(name) {
+            return match self.os {
+                Os::Linux => {
+                    self.emit(&format!("adrp x16, {}", symbol));
+                    format!("[x16, :lo12:{}]", symbol)
+                }
+                Os::Darwin => {
+                    self.emit(&format!("adrp x16, {}@PAGE", symbol));
+                    format!("[x16, {}@PAGEOFF]", symbol)
+                }
+            };
+        }
+        let offset = self.slot_offset(name);
+        if offset <= 256 {
+            format!("[x29, #-{}]", offset)
+        } else {
+            self.body.push_str(&move_immediate("w16", i64::from(offset)));
+            self.emit("sub x16, x29, x16");
+            "[x16]".to_string()
+        }
+    }
+
+    /// Returns the distance below `x29` of a variable's slot, assigning the next free slot
+    /// the first time the variable is seen. A slot is aligned like the type of the variable,
+    /// so an 8-byte slot to 8 bytes and an array like its elements.
+    fn slot_offset(&mut self, name: Name) -> i32 {
+        match self.slots.get(&name) {
+            Some(offset) => *offset,
+            None => {
+                let ty = self.type_of(&IrValue::Var(name));
+                let (size, alignment) = (ty.size() as i32, ty.alignment() as i32);
+                self.stack_size = (self.stack_size + size + alignment - 1) / alignment * alignment;
+                self.slots.insert(name, self.stack_size);
+                self.stack_size
+            }
+        }
+    }
+
+    /// Puts the address of a variable in a register: its slot's address is computed from
+    /// `x29`, and a static variable's or string constant's from its page address.
+    fn variable_address(&mut self, name: Name, number: usize) {
+        if let Some(symbol) = self.static_symbol(name) {
+            match self.os {
+                Os::Linux => {
+                    self.emit(&format!("adrp x{}, {}", number, symbol));
+                    self.emit(&format!("add x{}, x{}, :lo12:{}", number, number, symbol));
+                }
+                Os::Darwin => {
+                    self.emit(&format!("adrp x{}, {}@PAGE", number, symbol));
+                    self.emit(&format!("add x{}, x{}, {}@PAGEOFF", number, number, symbol));
+                }
+            }
+            return;
+        }
+        let offset = self.slot_offset(name);
+        if offset < 4096 {
+            self.emit(&format!("sub x{}, x29, #{}", number, offset));
+        } else {
+            self.body.push_str(&move_immediate("w16", i64::from(offset)));
+            self.emit(&format!("sub x{}, x29, x16", number));
+        }
+    }
+}
+
+/// Generates the instructions that move `sp` down (`sub`) or up (`add`) by `bytes`.
+///
+/// # Arguments
+///
+/// * `mnemonic` - Either `sub` or `add`.
+/// * `bytes` - The number of bytes to move `sp` by.
+///
+/// # Returns
+///
+/// * `String` - The assembly code, going through `x16` when `bytes` does not fit in an immediate.
+fn adjust_stack(mnemonic: &str, bytes: i32) -> String {
+    if bytes < 4096 {
+        format!("    {} sp, sp, #{}\n", mnemonic, bytes)
+    } else {
+        format!("{}    {} sp, sp, x16\n", move_immediate("w16", i64::from(bytes)), mnemonic)
+    }
+}
+
+/// Generates the instructions that load a constant into a register. Values that do not fit
+/// in a single 16-bit `mov` are built from their 16-bit pieces with `movz` and `movk`: two
+/// for a 32-bit `w` register, up to four for a 64-bit `x` register.
+///
+/// # Arguments
+///
+/// * `register` - The register to load.
+/// * `value` - The constant, truncated to 32 bits for a `w` register.
+///
+/// # Returns
+///
+/// * `String` - The assembly code.
+fn move_immediate(register: &str, value: i64) -> String {
+    if (0..=0xffff).contains(&value) {
+        return format!("    mov {}, #{}\n", register, value);
+    }
+    let bits = if register.starts_with('x') { value as u64 } else { u64::from(value as u32) };
+    let mut asm = format!("    movz {}, #{}\n", register, bits & 0xffff);
+    let pieces = if register.starts_with('x') { 4 } else { 2 };
+    for piece in 1..pieces {
+        let half = (bits >> (16 * piece)) & 0xffff;
+        if half != 0 {
+            asm.push_str(&format!("    movk {}, #{}, lsl #{}\n", register, half, 16 * piece));
+        }
+    }
+    asm
+}
+
+/// Returns the division instruction for operands of a signedness.
+fn divide(signed: bool) -> &'static str {
+    if signed { "sdiv" } else { "udiv" }
+}
+
+/// Maps a relational or equality operator to the condition code that holds when the
+/// comparison is true.
+///
+/// # Arguments
+///
+/// * `op` - The binary operator.
+/// * `signed` - Whether the operands are signed. Unsigned operands use the higher and lower
+///   conditions.
+///
+/// # Returns
+///
+/// * `&str` - The condition code, as used by `cset`.
+fn cond_code(op: BinaryOperator, signed: bool) -> &'static str {
+    match (op, signed) {
+        (BinaryOperator::Equal, _) => "eq",
+        (BinaryOperator::NotEqual, _) => "ne",
+        (BinaryOperator::LessThan, true) => "lt",
+        (BinaryOperator::LessOrEqual, true) => "le",
+        (BinaryOperator::GreaterThan, true) => "gt",
+        (BinaryOperator::GreaterOrEqual, true) => "ge",
+        (BinaryOperator::LessThan, false) => "lo",
+        (BinaryOperator::LessOrEqual, false) => "ls",
+        (BinaryOperator::GreaterThan, false) => "hi",
+        (BinaryOperator::GreaterOrEqual, false) => "hs",
+        _ => unreachable!("only relational operators have condition codes"),
+    }
+}
+
+/// Maps a relational or equality operator to the condition code that holds after `fcmp`
+/// when the comparison of two doubles is true.
+///
+/// # Arguments
+///
+/// * `op` - The binary operator.
+///
+/// # Returns
+///
+/// * `&str` - The condition code, as used by `cset`.
+fn double_cond_code(op: BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Equal => "eq",
+        BinaryOperator::NotEqual => "ne",
+        BinaryOperator::LessThan => "mi",
+        BinaryOperator::LessOrEqual => "ls",
+        BinaryOperator::GreaterThan => "gt",
+        BinaryOperator::GreaterOrEqual => "ge",
+        _ => unreachable!("the type checker rejects integer operators on doubles"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(params: &[&str], body: Vec<IrInstruction>) -> IrProgram {
+        IrProgram {
+            functions: vec![IrFunction {
+                name: "f".into(),
+                global: true,
+                span: Span::default(),
+                params: params.iter().map(|&param| Name::from(param)).collect(),
+                body,
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        }
+    }
+
+    fn var(name: &str) -> IrValue {
+        IrValue::Var(Name::from(name))
+    }
+
+    #[test]
+    fn test_prologue_and_return() {
+        let asm = generate_aarch64(function(&[], vec![IrInstruction::Return(IrValue::Constant(Constant::Int(2)))]), Os::Linux, None).unwrap();
+        let expected = "\
+f:
+    stp x29, x30, [sp, #-16]!
+    mov x29, sp
+    sub sp, sp, #0
+    mov w0, #2
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    ret
+";
+        assert!(asm.starts_with(" .globl f\n"));
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_binary_operators() {
+        let body = vec![
+            IrInstruction::Binary { op: BinaryOperator::Remainder, src1: var("a"), src2: IrValue::Constant(Constant::Int(-7)), dst: var("b") },
+            IrInstruction::Binary { op: BinaryOperator::LessOrEqual, src1: var("b"), src2: var("a"), dst: var("c") },
+        ];
+        let asm = generate_aarch64(function(&["a"], body), Os::Linux, None).unwrap();
+        let expected = "\
+    sub sp, sp, #16
+    str w0, [x29, #-4]
+    ldr w9, [x29, #-4]
+    movz w10, #65529
+    movk w10, #65535, lsl #16
+    sdiv w11, w9, w10
+    msub w9, w11, w10, w9
+    str w9, [x29, #-8]
+    ldr w9, [x29, #-8]
+    ldr w10, [x29, #-4]
+    cmp w9, w10
+    cset w9, le
+    str w9, [x29, #-12]
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let body = vec![
+            IrInstruction::Binary { op: BinaryOperator::RightShift, src1: var("a"), src2: IrValue::Constant(Constant::Int(2)), dst: var("b") },
+            IrInstruction::Binary { op: BinaryOperator::BitwiseOr, src1: var("b"), src2: var("a"), dst: var("c") },
+        ];
+        let asm = generate_aarch64(function(&["a"], body), Os::Linux, None).unwrap();
+        assert!(asm.contains("    asr w9, w9, w10\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    orr w9, w9, w10\n"), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_static_variables() {
+        let mut ir = function(&[], vec![IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("x") }]);
+        ir.functions[0].global = false;
+        ir.static_variables.push(IrStaticVariable { name: "x".into(), global: true, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(0))] });
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        assert!(asm.contains("    mov w9, #1\n    adrp x16, x\n    str w9, [x16, :lo12:x]\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains(" .globl x\n .bss\n .balign 4\nx:\n    .zero 4\n"));
+        // A static function is not visible to other files
+        assert!(asm.starts_with("f:\n"), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_function_call_with_stack_arguments() {
+        let args = (1..=10).map(|value| IrValue::Constant(Constant::Int(value))).collect();
+        let body = vec![IrInstruction::FunCall { name: "g".into(), args, dst: var("r") }];
+        let asm = generate_aarch64(function(&[], body), Os::Linux, None).unwrap();
+        let expected = "\
+    sub sp, sp, #16
+    mov w9, #9
+    str w9, [sp, #0]
+    mov w9, #10
+    str w9, [sp, #8]
+    mov w0, #1
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    mov w7, #8\n    bl g\n    add sp, sp, #16\n    str w0, [x29, #-4]\n"));
+    }
+
+    #[test]
+    fn test_stack_parameters() {
+        let params: Vec<String> = (0..9).map(|index| format!("p{}", index)).collect();
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        let asm = generate_aarch64(function(&params, vec![]), Os::Linux, None).unwrap();
+        assert!(asm.contains("    str w7, [x29, #-32]\n    ldr w9, [x29, #16]\n    str w9, [x29, #-36]\n"));
+    }
+
+    #[test]
+    fn test_deep_slots() {
+        let body = (0..70).map(|index| IrInstruction::Copy { src: IrValue::Constant(Constant::Int(index)), dst: var(&format!("v{}", index)) }).collect();
+        let asm = generate_aarch64(function(&[], body), Os::Linux, None).unwrap();
+        assert!(asm.contains("    sub sp, sp, #288\n"));
+        assert!(asm.contains("    mov w9, #63\n    str w9, [x29, #-256]\n"));
+        assert!(asm.contains("    mov w9, #64\n    mov w16, #260\n    sub x16, x29, x16\n    str w9, [x16]\n"));
+    }
+
+    #[test]
+    fn test_darwin() {
+        let args = (1..=10).map(|value| IrValue::Constant(Constant::Int(value))).collect();
+        let body = vec![
+            IrInstruction::Jump("end.1".into()),
+            IrInstruction::Label("end.1".into()),
+            IrInstruction::FunCall { name: "g".into(), args, dst: var("r") },
+        ];
+        let params: Vec<String> = (0..10).map(|index| format!("p{}", index)).collect();
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        let asm = generate_aarch64(function(&params, body), Os::Darwin, None).unwrap();
+        assert!(asm.starts_with(" .globl _f\n_f:\n"));
+        assert!(asm.contains("    ldr w9, [x29, #16]\n    str w9, [x29, #-36]\n    ldr w9, [x29, #20]\n"));
+        assert!(asm.contains("    b Lend.1\nLend.1:\n"));
+        assert!(asm.contains("    mov w9, #9\n    str w9, [sp, #0]\n    mov w9, #10\n    str w9, [sp, #4]\n"));
+        assert!(asm.contains("    bl _g\n"));
+        assert!(!asm.contains("GNU-stack"));
+
+        let asm = entry_point_to_string("start", Os::Darwin);
+        assert!(asm.starts_with(" .globl _start\n_start:\n    bl _main\n    mov x16, #1\n    svc #0x80\n"));
+    }
+
+    #[test]
+    fn test_long_operations() {
+        let body = vec![
+            IrInstruction::SignExtend { src: var("a"), dst: var("b") },
+            IrInstruction::Binary { op: BinaryOperator::Multiply, src1: var("b"), src2: IrValue::Constant(Constant::Long(0x1_0000_0002)), dst: var("c") },
+            IrInstruction::Truncate { src: var("c"), dst: var("d") },
+        ];
+        let mut ir = function(&["a"], body);
+        ir.types = BTreeMap::from([
+            ("a".into(), Type::Int),
+            ("b".into(), Type::Long),
+            ("c".into(), Type::Long),
+            ("d".into(), Type::Int),
+        ]);
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        let expected = "\
+    ldr w9, [x29, #-4]
+    sxtw x9, w9
+    str x9, [x29, #-16]
+    ldr x9, [x29, #-16]
+    movz x10, #2
+    movk x10, #1, lsl #32
+    mul x9, x9, x10
+    str x9, [x29, #-24]
+    ldr x9, [x29, #-24]
+    str w9, [x29, #-28]
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_unsigned_operations() {
+        let body = vec![
+            IrInstruction::Binary { op: BinaryOperator::Remainder, src1: var("a"), src2: var("a"), dst: var("b") },
+            IrInstruction::Binary { op: BinaryOperator::LessThan, src1: var("a"), src2: var("b"), dst: var("c") },
+            IrInstruction::Binary { op: BinaryOperator::RightShift, src1: var("a"), src2: var("b"), dst: var("d") },
+            IrInstruction::ZeroExtend { src: var("a"), dst: var("e") },
+        ];
+        let mut ir = function(&["a"], body);
+        ir.types = BTreeMap::from([
+            ("a".into(), Type::UInt),
+            ("b".into(), Type::UInt),
+            ("d".into(), Type::UInt),
+            ("e".into(), Type::ULong),
+        ]);
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        assert!(asm.contains("    udiv w11, w9, w10\n    msub w9, w11, w10, w9\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    cset w9, lo\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    lsr w9, w9, w10\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    ldr w9, [x29, #-4]\n    str x9, [x29, #-24]\n"), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_double_operations() {
+        let body = vec![
+            IrInstruction::Binary { op: BinaryOperator::LessThan, src1: var("a"), src2: IrValue::Constant(Constant::Double(1.5)), dst: var("b") },
+            IrInstruction::DoubleToUInt { src: var("a"), dst: var("c") },
+            IrInstruction::JumpIfZero(var("a"), "end.1".into()),
+            IrInstruction::Label("end.1".into()),
+            IrInstruction::Return(var("a")),
+        ];
+        let mut ir = function(&["i", "a"], body);
+        ir.types = BTreeMap::from([
+            ("i".into(), Type::Int),
+            ("a".into(), Type::Double),
+            ("c".into(), Type::ULong),
+        ]);
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        let expected = "\
+    str w0, [x29, #-4]
+    str d0, [x29, #-16]
+    ldr d16, [x29, #-16]
+    movz x9, #0
+    movk x9, #16376, lsl #48
+    fmov d17, x9
+    fcmp d16, d17
+    cset w9, mi
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    fcvtzu x9, d16\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    fcmp d16, #0.0\n    b.eq .Lend.1\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    ldr d0, [x29, #-16]\n    mov sp, x29\n"), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_pointer_operations() {
+        let body = vec![
+            IrInstruction::GetAddress { src: var("x"), dst: var("p") },
+            IrInstruction::Store { src: IrValue::Constant(Constant::Double(2.0)), dst_ptr: var("p") },
+            IrInstruction::Load { src_ptr: var("p"), dst: var("y") },
+            IrInstruction::GetAddress { src: var("g"), dst: var("p") },
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let mut ir = function(&[], body);
+        ir.extern_variables = vec!["g".into()];
+        ir.types = BTreeMap::from([
+            ("x".into(), Type::Double),
+            ("y".into(), Type::Double),
+            ("g".into(), Type::Double),
+            ("p".into(), Type::Pointer(Box::new(Type::Double))),
+        ]);
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        let expected = "\
+    sub x9, x29, #8
+    str x9, [x29, #-16]
+    movz x9, #0
+    movk x9, #16384, lsl #48
+    fmov d16, x9
+    ldr x9, [x29, #-16]
+    str d16, [x9]
+    ldr x9, [x29, #-16]
+    ldr d16, [x9]
+    str d16, [x29, #-24]
+    adrp x9, g
+    add x9, x9, :lo12:g
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_array_operations() {
+        let body = vec![
+            IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Int(7)), dst: "a".into(), offset: 4 },
+            IrInstruction::GetAddress { src: var("a"), dst: var("p") },
+            IrInstruction::AddPtr { ptr: var("p"), index: var("i"), scale: 4, dst: var("p") },
+            IrInstruction::AddPtr { ptr: var("p"), index: var("i"), scale: 12, dst: var("p") },
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let mut ir = function(&[], body);
+        ir.types = BTreeMap::from([
+            ("a".into(), Type::Array(Box::new(Type::Int), 3)),
+            ("i".into(), Type::Long),
+            ("p".into(), Type::Pointer(Box::new(Type::Int))),
+        ]);
+        let asm = generate_aarch64(ir, Os::Linux, None).unwrap();
+        let expected = "\
+    mov w10, #7
+    sub x9, x29, #12
+    str w10, [x9, #4]
+    sub x9, x29, #12
+    str x9, [x29, #-24]
+    ldr x9, [x29, #-24]
+    ldr x10, [x29, #-32]
+    add x9, x9, x10, lsl #2
+    str x9, [x29, #-24]
+    ldr x9, [x29, #-24]
+    ldr x10, [x29, #-32]
+    mov x11, #12
+    madd x9, x10, x11, x9
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+    #[test]
+    fn test_character_operations() {
+        let body = vec![
+            IrInstruction::SignExtend { src: var("c"), dst: var("l") },
+            IrInstruction::ZeroExtend { src: var("u"), dst: var("i") },
+            IrInstruction::Truncate { src: var("l"), dst: var("c") },
+            IrInstruction::IntToDouble { src: var("c"), dst: var("d") },
+            IrInstruction::GetAddress { src: var("string.0"), dst: var("p") },
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let mut ir = function(&[], body);
+        ir.static_constants.push(IrStaticConstant { name: "string.0".into(), alignment: 1, init: StaticInit::String("hi".to_string(), true) });
+        ir.types = BTreeMap::from([
+            ("c".into(), Type::Char),
+            ("u".into(), Type::UChar),
+            ("i".into(), Type::Int),
+            ("l".into(), Type::Long),
+            ("d".into(), Type::Double),
+            ("p".into(), Type::Pointer(Box::new(Type::Char))),
+            ("string.0".into(), Type::Array(Box::new(Type::Char), 3)),
+        ]);
+        let asm = generate_aarch64(ir, Os::Darwin, None).unwrap();
+        let expected = "\
+    ldrb w9, [x29, #-1]
+    sxtb x9, w9
+    str x9, [x29, #-16]
+    ldrb w9, [x29, #-17]
+    str w9, [x29, #-24]
+    ldr x9, [x29, #-16]
+    strb w9, [x29, #-1]
+    ldrb w9, [x29, #-1]
+    sxtb w9, w9
+    scvtf d16, w9
+    str d16, [x29, #-32]
+    adrp x9, Lstring.0@PAGE
+    add x9, x9, Lstring.0@PAGEOFF
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains(" .cstring\n .balign 1\nLstring.0:\n    .asciz \"hi\"\n"), "{}", asm);
+    }
+
+    #[test]
+    fn test_debug_information() {
+        let body = vec![
+            IrInstruction::Location(Span { line: 2, column: 5 }),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(2))),
+        ];
+        let mut ir = function(&[], body);
+        ir.functions[0].span = Span { line: 1, column: 5 };
+        let asm = generate_aarch64(ir, Os::Linux, Some("prog.c")).unwrap();
+        assert!(asm.starts_with(" .file 1 \"prog.c\"\n .globl f\n"), "unexpected assembly:\n{}", asm);
+        let expected = "\
+f:
+    .cfi_startproc
+    .loc 1 1 5
+    stp x29, x30, [sp, #-16]!
+    .cfi_def_cfa_offset 16
+    .cfi_offset 30, -8
+    .cfi_offset 29, -16
+    mov x29, sp
+    .cfi_def_cfa 29, 16
+    sub sp, sp, #0
+    .loc 1 2 5
+    mov w0, #2
+    .cfi_remember_state
+    mov sp, x29
+    ldp x29, x30, [sp], #16
+    .cfi_def_cfa 31, 0
+    .cfi_restore 30
+    .cfi_restore 29
+    ret
+    .cfi_restore_state
+    .cfi_endproc
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+}
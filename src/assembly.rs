@@ -1,43 +1,1083 @@
+use std::collections::{BTreeMap,HashMap,HashSet};
 use crate::ast::*;
-/// Converts a C AST to an assembly AST.
+use crate::ir::*;
+use crate::diagnostics::Diagnostic;
+use crate::regalloc::allocate_registers;
+use crate::target::{Arch, Os, SystemCallArgument, Target};
+/// The registers that carry the first six integer arguments of a call in the System V ABI.
+pub(crate) const ARG_REGISTERS: [AsmRegister; 6] = [
+    AsmRegister::DI,
+    AsmRegister::SI,
+    AsmRegister::DX,
+    AsmRegister::CX,
+    AsmRegister::R8,
+    AsmRegister::R9,
+];
+/// The registers that carry the first eight double arguments of a call, and of which
+/// `%xmm0` also carries a double result.
+const DOUBLE_ARG_REGISTERS: [AsmRegister; 8] = [
+    AsmRegister::XMM0,
+    AsmRegister::XMM1,
+    AsmRegister::XMM2,
+    AsmRegister::XMM3,
+    AsmRegister::XMM4,
+    AsmRegister::XMM5,
+    AsmRegister::XMM6,
+    AsmRegister::XMM7,
+];
+
+/// Converts an IR program to an assembly AST.
+///
+/// Conversion happens in three passes: IR instructions are translated to assembly using
+/// pseudo registers for variables and temporaries, pseudo registers are assigned stack slots,
+/// or refer to memory of their own if they name static variables, and finally
+/// instructions with operand combinations x86-64 cannot encode are rewritten. When
+/// `allocate_registers` is set, pseudo registers are first assigned to hardware registers
+/// where possible, and only the rest get stack slots.
+///
+/// # Arguments
+///
+/// * `ir` - The IR program to be converted.
+/// * `allocate_registers` - Whether to keep values in registers instead of on the stack.
+///
+/// # Returns
+///
+/// * `Result<AssemblyProgram, Diagnostic>` - The assembly AST if conversion is successful, otherwise an error.
+pub fn generate_assembly(ir: IrProgram, allocate_registers: bool) -> Result<AsmProgram, Diagnostic> {
+    let statics = ir.static_names();
+    let string_names: HashSet<Name> = ir.static_constants.iter().map(|constant| constant.name).collect();
+    let mut selection = Selection::default();
+    let functions = ir.functions
+        .into_iter()
+        .map(|function| generate_function(function, &ir.types, (&statics, &string_names), &mut selection, allocate_registers))
+        .collect::<Result<Vec<_>, _>>()?;
+    let static_variables = ir.static_variables
+        .into_iter()
+        .map(|variable| AsmStaticVariable {
+            alignment: ir.types.get(&variable.name).map_or(variable.alignment, alignment),
+            name: variable.name,
+            global: variable.global,
+            init: variable.init,
+        })
+        .collect();
+    let mut constants = selection.constants;
+    constants.extend(ir.static_constants.into_iter().map(|constant| AsmConstant {
+        name: constant.name,
+        alignment: constant.alignment,
+        init: constant.init,
+    }));
+    Ok(AsmProgram { functions, static_variables, constants })
+}
+
+/// What instruction selection adds to a program besides the instructions of its functions.
+#[derive(Debug, Default)]
+struct Selection {
+    /// The double constants the functions use, each stored once per alignment.
+    constants: Vec<AsmConstant>,
+    /// The number of labels made up so far for the control flow within one IR instruction.
+    next_label: usize,
+}
+
+impl Selection {
+    /// Returns the operand that reads a double constant from memory, adding the constant to
+    /// the program the first time it is used.
+    fn constant(&mut self, value: f64, alignment: usize) -> AsmOperand {
+        let existing = self
+            .constants
+            .iter()
+            .find(|constant| {
+                matches!(constant.init, StaticInit::Scalar(Constant::Double(existing)) if existing.to_bits() == value.to_bits())
+                    && constant.alignment == alignment
+            });
+        let name = match existing {
+            Some(constant) => constant.name,
+            None => {
+                let name = Name::from(format!("double.{}", self.constants.len()));
+                let init = StaticInit::Scalar(Constant::Double(value));
+                self.constants.push(AsmConstant { name, alignment, init });
+                name
+            }
+        };
+        AsmOperand::Constant(name)
+    }
+
+    /// Creates a label unique within the program. IR labels never end in `.asm.` and a
+    /// number, so the two cannot collide.
+    fn make_label(&mut self, kind: &str) -> Name {
+        self.next_label += 1;
+        Name::from(format!("{}.asm.{}", kind, self.next_label - 1))
+    }
+}
+
+/// Converts one IR function to assembly, running all three passes over it.
+///
+/// The function starts by copying its parameters into pseudo registers: the first six
+/// integers and the first eight doubles arrive in registers, the rest on the stack above the
+/// return address and saved `%rbp`.
 ///
 /// # Arguments
 ///
-/// * `ast` - The C AST to be converted.
+/// * `function` - The IR function to be converted.
+/// * `types` - The types of the variables and temporaries of the program.
+/// * `statics` - The names of the static variables and of the string constants.
+/// * `selection` - The constants and labels made up for the program so far.
+/// * `allocate_registers` - Whether to keep values in registers instead of on the stack.
 ///
 /// # Returns
 ///
-/// * `Result<AssemblyProgram, String>` - The assembly AST if conversion is successful, otherwise an error message.
-pub fn generate_assembly(ast: Program) -> Result<AsmProgram,String> {
+/// * `Result<AsmFunction, Diagnostic>` - The assembly function, or an error.
+fn generate_function(
+    function: IrFunction,
+    types: &BTreeMap<Name, Type>,
+    statics: (&HashSet<Name>, &HashSet<Name>),
+    selection: &mut Selection,
+    allocate_registers: bool,
+) -> Result<AsmFunction, Diagnostic> {
     let mut instructions: Vec<AsmInstruction> = Vec::new();
-    if let Statement::Return(exp) = ast.func.body {
-        let operand: AsmOperand = generate_operand(exp)?;
-        instructions.push(AsmInstruction::Mov(operand, AsmOperand::Register));
-        instructions.push(AsmInstruction::Ret);
+    let param_types: Vec<AsmType> =
+        function.params.iter().map(|param| asm_type(&value_type(&IrValue::Var(*param), types))).collect();
+    let mut stack_offset = 16;
+    for ((param, ty), register) in function.params.into_iter().zip(&param_types).zip(classify_arguments(&param_types)) {
+        let src = match register {
+            Some(register) => AsmOperand::Reg(register),
+            None => {
+                stack_offset += 8;
+                AsmOperand::Stack(stack_offset - 8)
+            }
+        };
+        instructions.push(AsmInstruction::Mov(*ty, src, AsmOperand::Pseudo(param)));
+    }
+    for instruction in function.body {
+        generate_instruction(instruction, types, selection, &mut instructions)?;
+    }
+    let mut function = AsmFunction {
+        name: function.name,
+        global: function.global,
+        span: function.span,
+        instructions,
+    };
+    replace_static_variables(&mut function, statics.0, statics.1);
+    let callee_saved = if allocate_registers { self::allocate_registers(&mut function, types) } else { Vec::new() };
+    let stack_size = replace_pseudo_registers(&mut function, types);
+    fix_up_instructions(&mut function, stack_size, &callee_saved);
+    Ok(function)
+}
+
+/// Assigns the arguments of a call, or the parameters of a function, to the registers they
+/// are passed in: the first six integers go in the general-purpose argument registers and
+/// the first eight doubles in `%xmm0` to `%xmm7`. The rest are passed on the stack.
+///
+/// # Arguments
+///
+/// * `types` - The types of the arguments, in order.
+///
+/// # Returns
+///
+/// * `Vec<Option<AsmRegister>>` - The register of each argument, or `None` for those passed
+///   on the stack.
+fn classify_arguments(types: &[AsmType]) -> Vec<Option<AsmRegister>> {
+    let mut integers = ARG_REGISTERS.iter();
+    let mut doubles = DOUBLE_ARG_REGISTERS.iter();
+    types
+        .iter()
+        .map(|ty| match ty {
+            AsmType::Double => doubles.next().copied(),
+            _ => integers.next().copied(),
+        })
+        .collect()
+}
+
+/// Returns the size of the instructions that operate on values of a type.
+///
+/// # Arguments
+///
+/// * `ty` - The type of the values.
+///
+/// # Returns
+///
+/// * `AsmType` - `Byte` for characters, `Longword` for 4-byte integers, `Quadword` for
+///   8-byte integers and pointers, and `Double` for doubles.
+pub(crate) fn asm_type(ty: &Type) -> AsmType {
+    match ty {
+        Type::Long | Type::ULong | Type::Pointer(_) => AsmType::Quadword,
+        Type::Double => AsmType::Double,
+        Type::Char | Type::SChar | Type::UChar => AsmType::Byte,
+        _ => AsmType::Longword,
+    }
+}
+
+/// Returns the alignment of a variable of a type. The System V ABI aligns arrays of 16 bytes
+/// or more to 16, other types to their own alignment.
+///
+/// # Arguments
+///
+/// * `ty` - The type of the variable.
+///
+/// # Returns
+///
+/// * `usize` - The alignment in bytes.
+fn alignment(ty: &Type) -> usize {
+    match ty {
+        Type::Array(..) if ty.size() >= 16 => 16,
+        _ => ty.alignment(),
+    }
+}
+
+/// A helper function that converts one IR instruction to assembly instructions.
+///
+/// # Arguments
+///
+/// * `instruction` - The IR instruction to be converted.
+/// * `types` - The types of the variables and temporaries of the program, which decide the
+///   size of each instruction.
+/// * `selection` - The constants and labels made up for the program so far.
+/// * `instructions` - The instruction list the generated instructions are appended to.
+///
+/// # Returns
+///
+/// * `Result<(), Diagnostic>` - `Ok(())` if conversion is successful, otherwise an error.
+fn generate_instruction(
+    instruction: IrInstruction,
+    types: &BTreeMap<Name, Type>,
+    selection: &mut Selection,
+    instructions: &mut Vec<AsmInstruction>,
+) -> Result<(), Diagnostic> {
+    let ax = AsmOperand::Reg(AsmRegister::AX);
+    let xmm14 = AsmOperand::Reg(AsmRegister::XMM14);
+    let type_of = |value: &IrValue| asm_type(&value_type(value, types));
+    let is_double = |value: &IrValue| type_of(value) == AsmType::Double;
+    match instruction {
+        IrInstruction::Location(span) => instructions.push(AsmInstruction::Location(span)),
+        IrInstruction::Return(value) => {
+            let result = if is_double(&value) { AsmOperand::Reg(AsmRegister::XMM0) } else { ax };
+            instructions.push(AsmInstruction::Mov(type_of(&value), value_to_operand(value, selection), result));
+            instructions.push(AsmInstruction::Ret);
+        }
+        IrInstruction::Trap => instructions.push(AsmInstruction::Trap),
+        IrInstruction::Copy { src, dst } => {
+            instructions.push(AsmInstruction::Mov(type_of(&src), value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::SignExtend { src, dst } => {
+            let (src_type, dst_type) = (type_of(&src), type_of(&dst));
+            instructions.push(AsmInstruction::Movsx(src_type, dst_type, value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::ZeroExtend { src, dst } => {
+            let (src_type, dst_type) = (type_of(&src), type_of(&dst));
+            instructions.push(AsmInstruction::MovZeroExtend(src_type, dst_type, value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::Truncate { src, dst } => {
+            // The low bytes of an integer are the smaller integer it truncates to
+            instructions.push(AsmInstruction::Mov(type_of(&dst), value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::IntToDouble { src, dst } if type_of(&src) == AsmType::Byte => {
+            // cvtsi2sd has no 1-byte form, so a character is sign-extended first
+            instructions.push(AsmInstruction::Movsx(AsmType::Byte, AsmType::Longword, value_to_operand(src, selection), ax.clone()));
+            instructions.push(AsmInstruction::Cvtsi2sd(AsmType::Longword, ax, destination_operand(dst)?));
+        }
+        IrInstruction::IntToDouble { src, dst } => {
+            instructions.push(AsmInstruction::Cvtsi2sd(type_of(&src), value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::DoubleToInt { src, dst } | IrInstruction::DoubleToUInt { src, dst } if type_of(&dst) == AsmType::Byte => {
+            // Every character fits in an int, whose low byte is the result
+            instructions.push(AsmInstruction::Cvttsd2si(AsmType::Longword, value_to_operand(src, selection), ax.clone()));
+            instructions.push(AsmInstruction::Mov(AsmType::Byte, ax, destination_operand(dst)?));
+        }
+        IrInstruction::DoubleToInt { src, dst } => {
+            instructions.push(AsmInstruction::Cvttsd2si(type_of(&dst), value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        IrInstruction::UIntToDouble { src, dst } if type_of(&src) == AsmType::Byte => {
+            instructions.push(AsmInstruction::MovZeroExtend(AsmType::Byte, AsmType::Longword, value_to_operand(src, selection), ax.clone()));
+            instructions.push(AsmInstruction::Cvtsi2sd(AsmType::Longword, ax, destination_operand(dst)?));
+        }
+        IrInstruction::UIntToDouble { src, dst } if type_of(&src) == AsmType::Longword => {
+            // Zero-extended to 64 bits, every unsigned int is a non-negative long
+            let extend = AsmInstruction::MovZeroExtend(AsmType::Longword, AsmType::Quadword, value_to_operand(src, selection), ax.clone());
+            instructions.push(extend);
+            instructions.push(AsmInstruction::Cvtsi2sd(AsmType::Quadword, ax, destination_operand(dst)?));
+        }
+        IrInstruction::UIntToDouble { src, dst } => {
+            // An unsigned long above LONG_MAX is halved, keeping its lowest bit so the result
+            // rounds the same way, converted as a signed number and doubled again
+            let (large, end) = (selection.make_label("ulong_large"), selection.make_label("ulong_end"));
+            let src = value_to_operand(src, selection);
+            let dst = destination_operand(dst)?;
+            let dx = AsmOperand::Reg(AsmRegister::DX);
+            instructions.push(AsmInstruction::Cmp(AsmType::Quadword, AsmOperand::Imm(0), src.clone()));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::L, large));
+            instructions.push(AsmInstruction::Cvtsi2sd(AsmType::Quadword, src.clone(), dst.clone()));
+            instructions.push(AsmInstruction::Jmp(end));
+            instructions.push(AsmInstruction::Label(large));
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, src, ax.clone()));
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, ax.clone(), dx.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Shr, AsmType::Quadword, AsmOperand::Imm(1), dx.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::And, AsmType::Quadword, AsmOperand::Imm(1), ax.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Or, AsmType::Quadword, ax, dx.clone()));
+            instructions.push(AsmInstruction::Cvtsi2sd(AsmType::Quadword, dx, dst.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Double, dst.clone(), dst));
+            instructions.push(AsmInstruction::Label(end));
+        }
+        IrInstruction::DoubleToUInt { src, dst } if type_of(&dst) == AsmType::Longword => {
+            // Every unsigned int fits in a long, whose low half it is
+            instructions.push(AsmInstruction::Cvttsd2si(AsmType::Quadword, value_to_operand(src, selection), ax.clone()));
+            instructions.push(AsmInstruction::Mov(AsmType::Longword, ax, destination_operand(dst)?));
+        }
+        IrInstruction::DoubleToUInt { src, dst } => {
+            // A value of 2^63 or more is brought into the range of a long by subtracting
+            // 2^63 first, which is added back by setting the top bit
+            let (large, end) = (selection.make_label("double_large"), selection.make_label("double_end"));
+            let upper = selection.constant(9223372036854775808.0, 8);
+            let src = value_to_operand(src, selection);
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Cmp(AsmType::Double, upper.clone(), src.clone()));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::AE, large));
+            instructions.push(AsmInstruction::Cvttsd2si(AsmType::Quadword, src.clone(), dst.clone()));
+            instructions.push(AsmInstruction::Jmp(end));
+            instructions.push(AsmInstruction::Label(large));
+            instructions.push(AsmInstruction::Mov(AsmType::Double, src, xmm14.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Sub, AsmType::Double, upper, xmm14.clone()));
+            instructions.push(AsmInstruction::Cvttsd2si(AsmType::Quadword, xmm14, dst.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Quadword, AsmOperand::Imm(i64::MIN), dst));
+            instructions.push(AsmInstruction::Label(end));
+        }
+        IrInstruction::Unary { op: UnaryOperator::Not, src, dst } if is_double(&src) => {
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Xor, AsmType::Double, xmm14.clone(), xmm14.clone()));
+            instructions.push(AsmInstruction::Cmp(AsmType::Double, value_to_operand(src, selection), xmm14));
+            push_double_equality(instructions, true, dst);
+        }
+        IrInstruction::Unary { op: UnaryOperator::Not, src, dst } => {
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Cmp(type_of(&src), AsmOperand::Imm(0), value_to_operand(src, selection)));
+            instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), dst.clone()));
+            instructions.push(AsmInstruction::SetCC(AsmCondCode::E, dst));
+        }
+        IrInstruction::Unary { op: UnaryOperator::Negate, src, dst } if is_double(&src) => {
+            // Flipping the sign bit negates every double, including zeros and NaNs
+            let dst = destination_operand(dst)?;
+            let sign_bit = selection.constant(-0.0, 16);
+            instructions.push(AsmInstruction::Mov(AsmType::Double, value_to_operand(src, selection), dst.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Xor, AsmType::Double, sign_bit, dst));
+        }
+        IrInstruction::Unary { op, src, dst } => {
+            let ty = type_of(&src);
+            let dst = destination_operand(dst)?;
+            let operator = match op {
+                UnaryOperator::Negate => AsmUnaryOperator::Neg,
+                UnaryOperator::Complement => AsmUnaryOperator::Not,
+                UnaryOperator::Not => unreachable!("logical not is handled above"),
+            };
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(src, selection), dst.clone()));
+            instructions.push(AsmInstruction::Unary(operator, ty, dst));
+        }
+        IrInstruction::Jump(target) => instructions.push(AsmInstruction::Jmp(target)),
+        IrInstruction::JumpIfZero(condition, target) if is_double(&condition) => {
+            // Comparing a NaN sets the zero flag too, but a NaN is not zero
+            let skip = selection.make_label("nan");
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Xor, AsmType::Double, xmm14.clone(), xmm14.clone()));
+            instructions.push(AsmInstruction::Cmp(AsmType::Double, value_to_operand(condition, selection), xmm14));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::P, skip));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::E, target));
+            instructions.push(AsmInstruction::Label(skip));
+        }
+        IrInstruction::JumpIfZero(condition, target) => {
+            instructions.push(AsmInstruction::Cmp(type_of(&condition), AsmOperand::Imm(0), value_to_operand(condition, selection)));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::E, target));
+        }
+        IrInstruction::JumpIfNotZero(condition, target) if is_double(&condition) => {
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Xor, AsmType::Double, xmm14.clone(), xmm14.clone()));
+            instructions.push(AsmInstruction::Cmp(AsmType::Double, value_to_operand(condition, selection), xmm14));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::NE, target));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::P, target));
+        }
+        IrInstruction::JumpIfNotZero(condition, target) => {
+            instructions.push(AsmInstruction::Cmp(type_of(&condition), AsmOperand::Imm(0), value_to_operand(condition, selection)));
+            instructions.push(AsmInstruction::JmpCC(AsmCondCode::NE, target));
+        }
+        IrInstruction::Label(name) => instructions.push(AsmInstruction::Label(name)),
+        IrInstruction::GetAddress { src, dst } => {
+            instructions.push(AsmInstruction::Lea(value_to_operand(src, selection), destination_operand(dst)?));
+        }
+        // The size is rounded up to a multiple of 16 so that %rsp stays aligned for calls
+        IrInstruction::Alloca { size, dst } => {
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(size, selection), ax.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Quadword, AsmOperand::Imm(15), ax.clone()));
+            instructions.push(AsmInstruction::Binary(AsmBinaryOperator::And, AsmType::Quadword, AsmOperand::Imm(-16), ax.clone()));
+            instructions.push(AsmInstruction::AllocateDynamic(AsmRegister::AX));
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, ax, destination_operand(dst)?));
+        }
+        IrInstruction::Load { src_ptr, dst } => {
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(src_ptr, selection), ax.clone()));
+            instructions.push(AsmInstruction::Mov(type_of(&dst), AsmOperand::Memory(AsmRegister::AX, 0), destination_operand(dst)?));
+        }
+        IrInstruction::Store { src, dst_ptr } => {
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(dst_ptr, selection), ax));
+            instructions.push(AsmInstruction::Mov(type_of(&src), value_to_operand(src, selection), AsmOperand::Memory(AsmRegister::AX, 0)));
+        }
+        // Loads are never reordered with other loads or with earlier stores on x86-64, so
+        // only the stores of atomic objects need more than a `mov`
+        IrInstruction::AtomicLoad { src_ptr, dst } => {
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(src_ptr, selection), ax.clone()));
+            instructions.push(AsmInstruction::Mov(type_of(&dst), AsmOperand::Memory(AsmRegister::AX, 0), destination_operand(dst)?));
+        }
+        IrInstruction::AtomicStore { src, dst_ptr } => {
+            let ty = type_of(&src);
+            let dx = AsmOperand::Reg(AsmRegister::DX);
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(dst_ptr, selection), ax));
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(src, selection), dx.clone()));
+            instructions.push(AsmInstruction::Xchg(ty, dx, AsmOperand::Memory(AsmRegister::AX, 0)));
+        }
+        IrInstruction::CompareExchange { ptr, expected, desired, dst } => {
+            let ty = type_of(&expected);
+            let (cx, dx) = (AsmOperand::Reg(AsmRegister::CX), AsmOperand::Reg(AsmRegister::DX));
+            instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(ptr, selection), dx));
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(desired, selection), cx.clone()));
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(expected, selection), ax));
+            instructions.push(AsmInstruction::Cmpxchg(ty, cx, AsmOperand::Memory(AsmRegister::DX, 0)));
+            // A move leaves the flags alone
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), dst.clone()));
+            instructions.push(AsmInstruction::SetCC(AsmCondCode::E, dst));
+        }
+        IrInstruction::AddPtr { ptr, index, scale, dst } => {
+            let dst = destination_operand(dst)?;
+            let dx = AsmOperand::Reg(AsmRegister::DX);
+            let ptr = value_to_operand(ptr, selection);
+            let offset = match index {
+                IrValue::Constant(index) => i32::try_from(index.as_i64().wrapping_mul(scale as i64)).ok(),
+                IrValue::Var(_) => None,
+            };
+            // A constant offset is part of the address, and so is an index scaled by 1, 2, 4
+            // or 8. Any other scale is multiplied in first.
+            let address = match (offset, u8::try_from(scale)) {
+                (Some(offset), _) => {
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, ptr, ax));
+                    AsmOperand::Memory(AsmRegister::AX, offset)
+                }
+                (None, Ok(scale @ (1 | 2 | 4 | 8))) => {
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, ptr, ax));
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(index, selection), dx.clone()));
+                    AsmOperand::Indexed(AsmRegister::AX, AsmRegister::DX, scale)
+                }
+                (None, _) => {
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, value_to_operand(index, selection), dx.clone()));
+                    instructions.push(AsmInstruction::Binary(AsmBinaryOperator::Mult, AsmType::Quadword, AsmOperand::Imm(scale as i64), dx.clone()));
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, ptr, ax));
+                    AsmOperand::Indexed(AsmRegister::AX, AsmRegister::DX, 1)
+                }
+            };
+            instructions.push(AsmInstruction::Lea(address, dst));
+        }
+        IrInstruction::CopyToOffset { src, dst, offset } => {
+            let dst = AsmOperand::PseudoMem(dst, offset as i32);
+            instructions.push(AsmInstruction::Mov(type_of(&src), value_to_operand(src, selection), dst));
+        }
+        IrInstruction::FunCall { name, args, dst } => {
+            let arg_types: Vec<AsmType> = args.iter().map(type_of).collect();
+            let registers = classify_arguments(&arg_types);
+            let stack_args: Vec<&IrValue> =
+                args.iter().zip(&registers).filter(|(_, register)| register.is_none()).map(|(arg, _)| arg).collect();
+            // %rsp must be 16-byte aligned at the call, and every stack argument takes 8 bytes
+            let padding = if stack_args.len() % 2 == 1 { 8 } else { 0 };
+            if padding != 0 {
+                instructions.push(AsmInstruction::AllocateStack(padding));
+            }
+            for (arg, register) in args.iter().zip(&registers) {
+                if let Some(register) = register {
+                    instructions.push(AsmInstruction::Mov(type_of(arg), value_to_operand(arg.clone(), selection), AsmOperand::Reg(*register)));
+                }
+            }
+            // Stack arguments are pushed last to first, so the first one ends up lowest
+            for arg in stack_args.iter().rev() {
+                match (type_of(arg), value_to_operand((*arg).clone(), selection)) {
+                    (_, operand @ AsmOperand::Imm(_)) | (AsmType::Quadword | AsmType::Double, operand) => {
+                        instructions.push(AsmInstruction::Push(operand));
+                    }
+                    (ty @ (AsmType::Byte | AsmType::Longword), operand) => {
+                        // pushq reads 8 bytes, so a smaller slot goes through %eax first
+                        instructions.push(AsmInstruction::Mov(ty, operand, ax.clone()));
+                        instructions.push(AsmInstruction::Push(ax.clone()));
+                    }
+                }
+            }
+            let integer_registers = registers.iter().flatten().filter(|register| ARG_REGISTERS.contains(register)).count();
+            instructions.push(AsmInstruction::Call(name, integer_registers));
+            let bytes_to_remove = 8 * stack_args.len() as i32 + padding;
+            if bytes_to_remove != 0 {
+                instructions.push(AsmInstruction::DeallocateStack(bytes_to_remove));
+            }
+            let result = if is_double(&dst) { AsmOperand::Reg(AsmRegister::XMM0) } else { ax };
+            instructions.push(AsmInstruction::Mov(type_of(&dst), result, destination_operand(dst)?));
+        }
+        IrInstruction::SystemCall { call, args, dst } => {
+            for (arg, register) in args.iter().zip(ARG_REGISTERS) {
+                instructions.push(AsmInstruction::Mov(type_of(arg), value_to_operand(arg.clone(), selection), AsmOperand::Reg(register)));
+            }
+            instructions.push(AsmInstruction::SystemCall(call, args.len()));
+            instructions.push(AsmInstruction::Mov(type_of(&dst), ax, destination_operand(dst)?));
+        }
+        IrInstruction::Binary { op, src1, src2, dst } if is_double(&src1) && relational_cond_code(op, true).is_some() => {
+            // comisd sets the flags like an unsigned comparison, and sets the carry, zero and
+            // parity flags for unordered operands. a < b is tested as b > a, so that every
+            // relational comparison with a NaN is false
+            let dst = destination_operand(dst)?;
+            let (src1, src2) = (value_to_operand(src1, selection), value_to_operand(src2, selection));
+            match op {
+                BinaryOperator::Equal | BinaryOperator::NotEqual => {
+                    instructions.push(AsmInstruction::Cmp(AsmType::Double, src2, src1));
+                    push_double_equality(instructions, op == BinaryOperator::Equal, dst);
+                    return Ok(());
+                }
+                BinaryOperator::LessThan | BinaryOperator::LessOrEqual => {
+                    instructions.push(AsmInstruction::Cmp(AsmType::Double, src1, src2));
+                }
+                _ => instructions.push(AsmInstruction::Cmp(AsmType::Double, src2, src1)),
+            }
+            let cond = match op {
+                BinaryOperator::LessThan | BinaryOperator::GreaterThan => AsmCondCode::A,
+                _ => AsmCondCode::AE,
+            };
+            instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), dst.clone()));
+            instructions.push(AsmInstruction::SetCC(cond, dst));
+        }
+        IrInstruction::Binary { op, src1, src2, dst } if is_double(&src1) => {
+            let dst = destination_operand(dst)?;
+            let operator = match op {
+                BinaryOperator::Add => AsmBinaryOperator::Add,
+                BinaryOperator::Subtract => AsmBinaryOperator::Sub,
+                BinaryOperator::Multiply => AsmBinaryOperator::Mult,
+                BinaryOperator::Divide => AsmBinaryOperator::DivDouble,
+                _ => unreachable!("the type checker rejects integer operators on doubles"),
+            };
+            instructions.push(AsmInstruction::Mov(AsmType::Double, value_to_operand(src1, selection), dst.clone()));
+            instructions.push(AsmInstruction::Binary(operator, AsmType::Double, value_to_operand(src2, selection), dst));
+        }
+        IrInstruction::Binary { op: op @ (BinaryOperator::Divide | BinaryOperator::Remainder), src1, src2, dst } => {
+            // idiv divides %edx:%eax (or %rdx:%rax), leaving the quotient in %eax and the remainder in %edx.
+            // The upper half is the sign of the dividend for idiv, and zero for the unsigned div.
+            let ty = type_of(&src1);
+            let result = if op == BinaryOperator::Divide { AsmRegister::AX } else { AsmRegister::DX };
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(src1.clone(), selection), ax.clone()));
+            if value_type(&src1, types).is_signed() {
+                instructions.push(AsmInstruction::Cdq(ty));
+                instructions.push(AsmInstruction::Idiv(ty, value_to_operand(src2, selection)));
+            } else {
+                instructions.push(AsmInstruction::Mov(ty, AsmOperand::Imm(0), AsmOperand::Reg(AsmRegister::DX)));
+                instructions.push(AsmInstruction::Div(ty, value_to_operand(src2, selection)));
+            }
+            instructions.push(AsmInstruction::Mov(ty, AsmOperand::Reg(result), destination_operand(dst)?));
+        }
+        IrInstruction::Binary { op, src1, src2, dst } if relational_cond_code(op, true).is_some() => {
+            // cmp b, a sets the flags for a - b, which the condition code then tests
+            let cond = relational_cond_code(op, value_type(&src1, types).is_signed()).expect("checked by the guard");
+            let ty = type_of(&src1);
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Cmp(ty, value_to_operand(src2, selection), value_to_operand(src1, selection)));
+            instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), dst.clone()));
+            instructions.push(AsmInstruction::SetCC(cond, dst));
+        }
+        IrInstruction::Binary { op: op @ (BinaryOperator::LeftShift | BinaryOperator::RightShift), src1, src2, dst } => {
+            // A shift count that is not an immediate has to be in %cl
+            let operator = match op {
+                BinaryOperator::LeftShift => AsmBinaryOperator::Sal,
+                _ if value_type(&src1, types).is_signed() => AsmBinaryOperator::Sar,
+                _ => AsmBinaryOperator::Shr,
+            };
+            let ty = type_of(&src1);
+            let dst = destination_operand(dst)?;
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(src1, selection), dst.clone()));
+            let count_type = type_of(&src2);
+            let count = match value_to_operand(src2, selection) {
+                count @ AsmOperand::Imm(_) => count,
+                count => {
+                    let cx = AsmOperand::Reg(AsmRegister::CX);
+                    instructions.push(AsmInstruction::Mov(count_type, count, cx.clone()));
+                    cx
+                }
+            };
+            instructions.push(AsmInstruction::Binary(operator, ty, count, dst));
+        }
+        IrInstruction::Binary { op, src1, src2, dst } => {
+            let ty = type_of(&src1);
+            let dst = destination_operand(dst)?;
+            let operator = match op {
+                BinaryOperator::Add => AsmBinaryOperator::Add,
+                BinaryOperator::Subtract => AsmBinaryOperator::Sub,
+                BinaryOperator::Multiply => AsmBinaryOperator::Mult,
+                BinaryOperator::BitwiseAnd => AsmBinaryOperator::And,
+                BinaryOperator::BitwiseOr => AsmBinaryOperator::Or,
+                BinaryOperator::BitwiseXor => AsmBinaryOperator::Xor,
+                BinaryOperator::And | BinaryOperator::Or => {
+                    return Err(Diagnostic::error_without_span("Logical operators must be lowered to jumps"));
+                }
+                _ => unreachable!("division, shifts and comparisons are handled above"),
+            };
+            instructions.push(AsmInstruction::Mov(ty, value_to_operand(src1, selection), dst.clone()));
+            instructions.push(AsmInstruction::Binary(operator, ty, value_to_operand(src2, selection), dst));
+        }
+    }
+    Ok(())
+}
+
+/// Appends the instructions that set `dst` from the flags of a `comisd`: to whether its
+/// operands were equal, or to whether they were not. Unordered operands set the zero flag
+/// like equal ones, so the parity flag, which only they set, is tested as well.
+///
+/// # Arguments
+///
+/// * `instructions` - The instruction list the instructions are appended to.
+/// * `equal` - Whether to test for equality rather than inequality.
+/// * `dst` - The destination operand.
+fn push_double_equality(instructions: &mut Vec<AsmInstruction>, equal: bool, dst: AsmOperand) {
+    let r11 = AsmOperand::Reg(AsmRegister::R11);
+    let (cond, ordered, operator) = if equal {
+        (AsmCondCode::E, AsmCondCode::NP, AsmBinaryOperator::And)
     } else {
-        return Err("Invalid function body, expected a return statement.".to_string());
+        (AsmCondCode::NE, AsmCondCode::P, AsmBinaryOperator::Or)
+    };
+    instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), dst.clone()));
+    instructions.push(AsmInstruction::SetCC(cond, dst.clone()));
+    instructions.push(AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(0), r11.clone()));
+    instructions.push(AsmInstruction::SetCC(ordered, r11.clone()));
+    instructions.push(AsmInstruction::Binary(operator, AsmType::Longword, r11, dst));
+}
+
+/// Maps a relational or equality operator to the condition code that holds when the
+/// comparison is true.
+///
+/// # Arguments
+///
+/// * `op` - The binary operator.
+/// * `signed` - Whether the operands are signed, which selects the greater and less
+///   conditions over the above and below ones.
+///
+/// # Returns
+///
+/// * `Option<AsmCondCode>` - The condition code, or `None` for arithmetic operators.
+fn relational_cond_code(op: BinaryOperator, signed: bool) -> Option<AsmCondCode> {
+    match (op, signed) {
+        (BinaryOperator::Equal, _) => Some(AsmCondCode::E),
+        (BinaryOperator::NotEqual, _) => Some(AsmCondCode::NE),
+        (BinaryOperator::LessThan, true) => Some(AsmCondCode::L),
+        (BinaryOperator::LessOrEqual, true) => Some(AsmCondCode::LE),
+        (BinaryOperator::GreaterThan, true) => Some(AsmCondCode::G),
+        (BinaryOperator::GreaterOrEqual, true) => Some(AsmCondCode::GE),
+        (BinaryOperator::LessThan, false) => Some(AsmCondCode::B),
+        (BinaryOperator::LessOrEqual, false) => Some(AsmCondCode::BE),
+        (BinaryOperator::GreaterThan, false) => Some(AsmCondCode::A),
+        (BinaryOperator::GreaterOrEqual, false) => Some(AsmCondCode::AE),
+        _ => None,
     }
-    Ok(AsmProgram {
-        function: AsmFunction {
-            name: ast.func.name,
-            instructions,
-        }})
 }
 
-/// A helper function that converts an expression in the C AST to an operand in the assembly AST.
+/// Converts an IR value to an assembly operand: integer constants become immediates,
+/// double constants are read from memory, since no instruction takes a double immediate,
+/// and variables become pseudo registers.
 ///
 /// # Arguments
 ///
-/// * `exp` - The expression to be converted.
+/// * `value` - The IR value to be converted.
+/// * `selection` - The constants made up for the program so far.
 ///
 /// # Returns
 ///
-/// * `Result<Operand, String>` - The operand if conversion is successful, otherwise an error message.
-fn generate_operand(exp:Exp) -> Result<AsmOperand, String> {
-    match exp {
-        Exp::Const(value) => Ok(AsmOperand::Imm(value)),
-        _ => Err("Unsupported expression type.".to_string()),
+/// * `AsmOperand` - The corresponding operand.
+fn value_to_operand(value: IrValue, selection: &mut Selection) -> AsmOperand {
+    match value {
+        IrValue::Constant(Constant::Double(value)) => selection.constant(value, 8),
+        IrValue::Constant(value) => AsmOperand::Imm(value.as_i64()),
+        IrValue::Var(name) => AsmOperand::Pseudo(name),
+    }
+}
+
+/// Converts the destination of an IR instruction to an assembly operand.
+///
+/// # Arguments
+///
+/// * `dst` - The IR value being written.
+///
+/// # Returns
+///
+/// * `Result<AsmOperand, Diagnostic>` - The pseudo register, or an error if `dst` is a constant.
+fn destination_operand(dst: IrValue) -> Result<AsmOperand, Diagnostic> {
+    match dst {
+        IrValue::Var(name) => Ok(AsmOperand::Pseudo(name)),
+        IrValue::Constant(value) => Err(Diagnostic::error_without_span(format!("Cannot assign to constant {}", value))),
+    }
+}
+
+/// Replaces the pseudo registers that name static variables and string constants with
+/// references to their memory, so they are neither given registers nor stack slots.
+///
+/// # Arguments
+///
+/// * `function` - The function whose instructions are rewritten in place.
+/// * `statics` - The names of the static variables.
+/// * `constants` - The names of the string constants, which live in read-only data.
+fn replace_static_variables(function: &mut AsmFunction, statics: &HashSet<Name>, constants: &HashSet<Name>) {
+    let replace = |operand: &mut AsmOperand| {
+        if let AsmOperand::Pseudo(name) = operand {
+            if statics.contains(name) {
+                *operand = AsmOperand::Data(std::mem::take(name));
+            } else if constants.contains(name) {
+                *operand = AsmOperand::Constant(std::mem::take(name));
+            }
+        }
+    };
+    for instruction in &mut function.instructions {
+        match instruction {
+            AsmInstruction::Mov(_, src, dst)
+            | AsmInstruction::Movsx(_, _, src, dst)
+            | AsmInstruction::MovZeroExtend(_, _, src, dst)
+            | AsmInstruction::Binary(_, _, src, dst)
+            | AsmInstruction::Cmp(_, src, dst)
+            | AsmInstruction::Xchg(_, src, dst)
+            | AsmInstruction::Cmpxchg(_, src, dst)
+            | AsmInstruction::Cvttsd2si(_, src, dst)
+            | AsmInstruction::Cvtsi2sd(_, src, dst)
+            | AsmInstruction::Lea(src, dst) => {
+                replace(src);
+                replace(dst);
+            }
+            AsmInstruction::Unary(_, _, operand)
+            | AsmInstruction::Idiv(_, operand)
+            | AsmInstruction::Div(_, operand)
+            | AsmInstruction::SetCC(_, operand)
+            | AsmInstruction::Push(operand) => replace(operand),
+            _ => {}
+        }
+    }
+}
+
+/// Replaces every pseudo register in a function with a stack slot below `%rbp`, sized and
+/// aligned for its type: 4 bytes for an `int`, 8 bytes aligned to 8 for a `long` or a
+/// `double`, and the size of all its elements for an array. The same pseudo register is always mapped to the same slot, and
+/// an offset into an array to the memory that far into its slot.
+///
+/// # Arguments
+///
+/// * `function` - The function whose instructions are rewritten in place.
+/// * `types` - The types of the variables and temporaries of the program.
+///
+/// # Returns
+///
+/// * `i32` - The number of bytes of stack the slots occupy.
+fn replace_pseudo_registers(function: &mut AsmFunction, types: &BTreeMap<Name, Type>) -> i32 {
+    let mut offsets: HashMap<Name, i32> = HashMap::new();
+    let mut stack_size = 0;
+    let mut slot = |name: Name| {
+        *offsets.entry(name).or_insert_with(|| {
+            let ty = value_type(&IrValue::Var(name), types);
+            let alignment = alignment(&ty) as i32;
+            stack_size = (stack_size + ty.size() as i32 + alignment - 1) / alignment * alignment;
+            -stack_size
+        })
+    };
+    let mut replace = |operand: &mut AsmOperand| match operand {
+        AsmOperand::Pseudo(name) => *operand = AsmOperand::Stack(slot(*name)),
+        AsmOperand::PseudoMem(name, offset) => *operand = AsmOperand::Stack(slot(*name) + *offset),
+        _ => {}
+    };
+    for instruction in &mut function.instructions {
+        match instruction {
+            AsmInstruction::Mov(_, src, dst)
+            | AsmInstruction::Movsx(_, _, src, dst)
+            | AsmInstruction::MovZeroExtend(_, _, src, dst)
+            | AsmInstruction::Binary(_, _, src, dst)
+            | AsmInstruction::Cmp(_, src, dst)
+            | AsmInstruction::Xchg(_, src, dst)
+            | AsmInstruction::Cmpxchg(_, src, dst)
+            | AsmInstruction::Cvttsd2si(_, src, dst)
+            | AsmInstruction::Cvtsi2sd(_, src, dst)
+            | AsmInstruction::Lea(src, dst) => {
+                replace(src);
+                replace(dst);
+            }
+            AsmInstruction::Unary(_, _, operand)
+            | AsmInstruction::Idiv(_, operand)
+            | AsmInstruction::Div(_, operand)
+            | AsmInstruction::SetCC(_, operand)
+            | AsmInstruction::Push(operand) => replace(operand),
+            AsmInstruction::AllocateStack(_)
+            | AsmInstruction::DeallocateStack(_)
+            | AsmInstruction::AllocateDynamic(_)
+            | AsmInstruction::ResetStack(_)
+            | AsmInstruction::Pop(_)
+            | AsmInstruction::Call(..)
+            | AsmInstruction::SystemCall(..)
+            | AsmInstruction::Cdq(_)
+            | AsmInstruction::Jmp(_)
+            | AsmInstruction::JmpCC(_, _)
+            | AsmInstruction::Label(_)
+            | AsmInstruction::Ret
+            | AsmInstruction::Trap
+            | AsmInstruction::Location(_) => {}
+        }
+    }
+    stack_size
+}
+
+/// Inserts the stack allocation for a function's slots and rewrites instructions whose
+/// operands x86-64 cannot encode, routing them through the scratch registers `%r10`/`%r11`:
+/// memory-to-memory `mov`/`add`/`sub`/`cmp`, `imul` into memory, `idiv`/`div` of an
+/// immediate, `cmp` against an immediate, sign and zero extensions from an immediate or into
+/// memory, and immediates that do not fit in 32 bits anywhere but a `movq` into a register.
+/// Such an immediate in a `movl`, or one that does not fit in a byte in a `movb`, is
+/// truncated, as the conversion would. A zero extension from 4 bytes becomes a `movl`,
+/// through `%r11` when its destination is in memory.
+///
+/// Double instructions go through `%xmm15` instead: a memory-to-memory `movsd`, arithmetic
+/// into memory, a `comisd` against memory and a `cvtsi2sd` into memory, all of which need
+/// an XMM register as destination. A `cvttsd2si` into memory goes through `%r11`, and a
+/// `cvtsi2sd` of an immediate through `%r10`. So does a `leaq` into memory, whose
+/// destination must be a register too.
+///
+/// The callee-saved registers the function uses are pushed after the allocation and popped
+/// before every return. The allocation is sized so that together with them it is a multiple
+/// of 16 bytes, keeping `%rsp` aligned for the calls the function makes. If the function
+/// allocates stack dynamically, `%rsp` is moved back up to the registers before they are
+/// popped.
+///
+/// # Arguments
+///
+/// * `function` - The function whose instructions are rewritten in place.
+/// * `stack_size` - The number of bytes of stack the function's slots occupy.
+/// * `callee_saved` - The callee-saved registers the function uses.
+fn fix_up_instructions(function: &mut AsmFunction, stack_size: i32, callee_saved: &[AsmRegister]) {
+    let r10 = AsmOperand::Reg(AsmRegister::R10);
+    let r11 = AsmOperand::Reg(AsmRegister::R11);
+    let xmm15 = AsmOperand::Reg(AsmRegister::XMM15);
+    let saved_size = 8 * callee_saved.len() as i32;
+    let mut instructions = Vec::with_capacity(function.instructions.len() + 1 + callee_saved.len());
+    let allocation = (stack_size + saved_size + 15) / 16 * 16 - saved_size;
+    if allocation != 0 {
+        instructions.push(AsmInstruction::AllocateStack(allocation));
+    }
+    instructions.extend(callee_saved.iter().map(|register| AsmInstruction::Push(AsmOperand::Reg(*register))));
+    let allocates_dynamically =
+        function.instructions.iter().any(|instruction| matches!(instruction, AsmInstruction::AllocateDynamic(_)));
+    for instruction in function.instructions.drain(..) {
+        match instruction {
+            AsmInstruction::Ret => {
+                if allocates_dynamically && !callee_saved.is_empty() {
+                    instructions.push(AsmInstruction::ResetStack(allocation + saved_size));
+                }
+                instructions.extend(callee_saved.iter().rev().map(|register| AsmInstruction::Pop(*register)));
+                instructions.push(AsmInstruction::Ret);
+            }
+            AsmInstruction::Mov(AsmType::Double, src, dst) if is_memory(&src) && is_memory(&dst) => {
+                instructions.push(AsmInstruction::Mov(AsmType::Double, src, xmm15.clone()));
+                instructions.push(AsmInstruction::Mov(AsmType::Double, xmm15.clone(), dst));
+            }
+            AsmInstruction::Binary(operator, AsmType::Double, src, dst) if is_memory(&dst) => {
+                instructions.push(AsmInstruction::Mov(AsmType::Double, dst.clone(), xmm15.clone()));
+                instructions.push(AsmInstruction::Binary(operator, AsmType::Double, src, xmm15.clone()));
+                instructions.push(AsmInstruction::Mov(AsmType::Double, xmm15.clone(), dst));
+            }
+            AsmInstruction::Cmp(AsmType::Double, src, dst) if !matches!(dst, AsmOperand::Reg(_)) => {
+                instructions.push(AsmInstruction::Mov(AsmType::Double, dst, xmm15.clone()));
+                instructions.push(AsmInstruction::Cmp(AsmType::Double, src, xmm15.clone()));
+            }
+            AsmInstruction::Lea(src, dst) if is_memory(&dst) => {
+                instructions.push(AsmInstruction::Lea(src, r11.clone()));
+                instructions.push(AsmInstruction::Mov(AsmType::Quadword, r11.clone(), dst));
+            }
+            AsmInstruction::Cvttsd2si(ty, src, dst) if is_memory(&dst) => {
+                instructions.push(AsmInstruction::Cvttsd2si(ty, src, r11.clone()));
+                instructions.push(AsmInstruction::Mov(ty, r11.clone(), dst));
+            }
+            AsmInstruction::Cvtsi2sd(ty, src, dst) => {
+                let src = match src {
+                    AsmOperand::Imm(_) => {
+                        instructions.push(AsmInstruction::Mov(ty, src, r10.clone()));
+                        r10.clone()
+                    }
+                    src => src,
+                };
+                if is_memory(&dst) {
+                    instructions.push(AsmInstruction::Cvtsi2sd(ty, src, xmm15.clone()));
+                    instructions.push(AsmInstruction::Mov(AsmType::Double, xmm15.clone(), dst));
+                } else {
+                    instructions.push(AsmInstruction::Cvtsi2sd(ty, src, dst));
+                }
+            }
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(value), dst) if is_large(value) => {
+                let src = AsmOperand::Imm(i64::from(value as i32));
+                instructions.push(AsmInstruction::Mov(AsmType::Longword, src, dst));
+            }
+            AsmInstruction::Mov(AsmType::Byte, AsmOperand::Imm(value), dst) if i8::try_from(value).is_err() => {
+                let src = AsmOperand::Imm(i64::from(value as i8));
+                instructions.push(AsmInstruction::Mov(AsmType::Byte, src, dst));
+            }
+            AsmInstruction::Mov(ty, src, dst)
+                if (is_memory(&src) || is_large_immediate(&src)) && is_memory(&dst) =>
+            {
+                instructions.push(AsmInstruction::Mov(ty, src, r10.clone()));
+                instructions.push(AsmInstruction::Mov(ty, r10.clone(), dst));
+            }
+            AsmInstruction::Movsx(src_type, dst_type, src, dst) if matches!(src, AsmOperand::Imm(_)) || is_memory(&dst) => {
+                let src = match src {
+                    AsmOperand::Imm(_) => {
+                        instructions.push(AsmInstruction::Mov(src_type, src, r10.clone()));
+                        r10.clone()
+                    }
+                    src => src,
+                };
+                if is_memory(&dst) {
+                    instructions.push(AsmInstruction::Movsx(src_type, dst_type, src, r11.clone()));
+                    instructions.push(AsmInstruction::Mov(dst_type, r11.clone(), dst));
+                } else {
+                    instructions.push(AsmInstruction::Movsx(src_type, dst_type, src, dst));
+                }
+            }
+            AsmInstruction::Binary(
+                operator @ (AsmBinaryOperator::Add
+                | AsmBinaryOperator::Sub
+                | AsmBinaryOperator::And
+                | AsmBinaryOperator::Or
+                | AsmBinaryOperator::Xor
+                | AsmBinaryOperator::Mult),
+                ty,
+                src,
+                dst,
+            ) if is_large_immediate(&src) || (is_memory(&src) && is_memory(&dst) && operator != AsmBinaryOperator::Mult) =>
+            {
+                instructions.push(AsmInstruction::Mov(ty, src, r10.clone()));
+                push_binary(&mut instructions, operator, ty, r10.clone(), dst);
+            }
+            AsmInstruction::Binary(operator, ty, src, dst) => push_binary(&mut instructions, operator, ty, src, dst),
+            AsmInstruction::Idiv(ty, operand @ AsmOperand::Imm(_)) => {
+                instructions.push(AsmInstruction::Mov(ty, operand, r10.clone()));
+                instructions.push(AsmInstruction::Idiv(ty, r10.clone()));
+            }
+            AsmInstruction::Div(ty, operand @ AsmOperand::Imm(_)) => {
+                instructions.push(AsmInstruction::Mov(ty, operand, r10.clone()));
+                instructions.push(AsmInstruction::Div(ty, r10.clone()));
+            }
+            AsmInstruction::MovZeroExtend(AsmType::Byte, dst_type, src, dst) => {
+                let src = match src {
+                    AsmOperand::Imm(_) => {
+                        instructions.push(AsmInstruction::Mov(AsmType::Byte, src, r10.clone()));
+                        r10.clone()
+                    }
+                    src => src,
+                };
+                if is_memory(&dst) {
+                    instructions.push(AsmInstruction::MovZeroExtend(AsmType::Byte, dst_type, src, r11.clone()));
+                    instructions.push(AsmInstruction::Mov(dst_type, r11.clone(), dst));
+                } else {
+                    instructions.push(AsmInstruction::MovZeroExtend(AsmType::Byte, dst_type, src, dst));
+                }
+            }
+            AsmInstruction::MovZeroExtend(_, _, src, dst) => {
+                let src = match src {
+                    AsmOperand::Imm(value) => AsmOperand::Imm(i64::from(value as u32)),
+                    src => src,
+                };
+                if is_memory(&dst) {
+                    instructions.push(AsmInstruction::Mov(AsmType::Longword, src, r11.clone()));
+                    instructions.push(AsmInstruction::Mov(AsmType::Quadword, r11.clone(), dst));
+                } else {
+                    instructions.push(AsmInstruction::Mov(AsmType::Longword, src, dst));
+                }
+            }
+            AsmInstruction::Cmp(ty, src, dst) => {
+                let src = if (is_memory(&src) && is_memory(&dst)) || is_large_immediate(&src) {
+                    instructions.push(AsmInstruction::Mov(ty, src, r10.clone()));
+                    r10.clone()
+                } else {
+                    src
+                };
+                if let AsmOperand::Imm(_) = dst {
+                    instructions.push(AsmInstruction::Mov(ty, dst, r11.clone()));
+                    instructions.push(AsmInstruction::Cmp(ty, src, r11.clone()));
+                } else {
+                    instructions.push(AsmInstruction::Cmp(ty, src, dst));
+                }
+            }
+            AsmInstruction::Push(operand) if is_large_immediate(&operand) => {
+                instructions.push(AsmInstruction::Mov(AsmType::Quadword, operand, r10.clone()));
+                instructions.push(AsmInstruction::Push(r10.clone()));
+            }
+            other => instructions.push(other),
+        }
     }
+    function.instructions = instructions;
+}
+
+/// Appends a binary instruction, routing an `imul` into memory through `%r11`, since the
+/// destination of `imul` must be a register.
+///
+/// # Arguments
+///
+/// * `instructions` - The instruction list the instructions are appended to.
+/// * `operator` - The operator.
+/// * `ty` - The size of the operands.
+/// * `src` - The source operand, which can already be encoded.
+/// * `dst` - The destination operand.
+fn push_binary(
+    instructions: &mut Vec<AsmInstruction>,
+    operator: AsmBinaryOperator,
+    ty: AsmType,
+    src: AsmOperand,
+    dst: AsmOperand,
+) {
+    if operator == AsmBinaryOperator::Mult && is_memory(&dst) {
+        let r11 = AsmOperand::Reg(AsmRegister::R11);
+        instructions.push(AsmInstruction::Mov(ty, dst.clone(), r11.clone()));
+        instructions.push(AsmInstruction::Binary(operator, ty, src, r11.clone()));
+        instructions.push(AsmInstruction::Mov(ty, r11, dst));
+    } else {
+        instructions.push(AsmInstruction::Binary(operator, ty, src, dst));
+    }
+}
+
+/// Checks whether an immediate does not fit in the sign-extended 32 bits most instructions
+/// can encode.
+///
+/// # Arguments
+///
+/// * `value` - The immediate.
+///
+/// # Returns
+///
+/// * `bool` - `true` if the immediate needs all 64 bits.
+fn is_large(value: i64) -> bool {
+    i32::try_from(value).is_err()
+}
+
+/// Checks whether an operand is an immediate that does not fit in 32 bits.
+///
+/// # Arguments
+///
+/// * `operand` - The operand to be checked.
+///
+/// # Returns
+///
+/// * `bool` - `true` for large immediates.
+fn is_large_immediate(operand: &AsmOperand) -> bool {
+    matches!(operand, AsmOperand::Imm(value) if is_large(*value))
+}
+
+/// Checks whether an operand refers to memory.
+///
+/// # Arguments
+///
+/// * `operand` - The operand to be checked.
+///
+/// # Returns
+///
+/// * `bool` - `true` for stack slots, static variables, double constants, memory reached
+///   through a pointer and offsets into arrays.
+fn is_memory(operand: &AsmOperand) -> bool {
+    matches!(
+        operand,
+        AsmOperand::Stack(_)
+            | AsmOperand::Data(_)
+            | AsmOperand::Constant(_)
+            | AsmOperand::Memory(..)
+            | AsmOperand::Indexed(..)
+            | AsmOperand::PseudoMem(..)
+    )
 }
 
 /// Converts an assembly AST to a string representation of the assembly code.
@@ -45,26 +1085,443 @@ fn generate_operand(exp:Exp) -> Result<AsmOperand, String> {
 /// # Arguments
 ///
 /// * `assembly` - The assembly AST to be converted.
+/// * `os` - The operating system, which decides symbol names and platform directives.
+/// * `pic` - Whether to emit position-independent code, which calls functions defined in
+///   other files through the procedure linkage table.
+/// * `debug_file` - The path of the source file, if debug information is to be emitted: the
+///   file the `.loc` directives refer to, along with call frame information for each function.
 ///
 /// # Returns
 ///
 /// * `String` - The string representation of the assembly code.
-pub fn assembly_to_string(assembly: AsmProgram) -> String {
+pub fn assembly_to_string(assembly: AsmProgram, os: Os, pic: bool, debug_file: Option<&str>) -> String {
+    let mut asm: String = String::new();
+
+    if let Some(path) = debug_file {
+        asm.push_str(&file_directive(path));
+    }
+    let defined: HashSet<Name> = assembly.functions.iter().map(|function| function.name).collect();
+    for function in assembly.functions {
+        asm.push_str(&function_to_string(function, os, pic, &defined, debug_file.is_some()));
+    }
+    for variable in assembly.static_variables {
+        asm.push_str(&static_variable_to_string(variable, os));
+    }
+    for constant in assembly.constants {
+        asm.push_str(&constant_to_string(constant, os));
+    }
+    asm.push_str(os.stack_note());
+    asm
+}
+
+/// Converts one assembly function to its assembly code, including the prologue that sets
+/// up its stack frame.
+///
+/// # Arguments
+///
+/// * `function` - The assembly function to be converted.
+/// * `os` - The operating system, which decides symbol and label names.
+/// * `pic` - Whether to call functions defined elsewhere through the procedure linkage table.
+/// * `defined` - The names of the functions defined in this file.
+/// * `debug_info` - Whether to describe the stack frame with call frame information, so
+///   debuggers can unwind through the function.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the function.
+fn function_to_string(function: AsmFunction, os: Os, pic: bool, defined: &HashSet<Name>, debug_info: bool) -> String {
     let mut asm: String = String::new();
+    let cfi = |asm: &mut String, directive: &str| {
+        if debug_info {
+            asm.push_str(&format!("    .cfi_{}\n", directive));
+        }
+    };
 
-    asm.push_str(&format!(" .globl {}\n{}:\n", assembly.function.name, assembly.function.name));
-    for instruction in assembly.function.instructions {
+    let name = os.symbol(&function.name);
+    if function.global {
+        asm.push_str(&format!(" .globl {}\n", name));
+    }
+    asm.push_str(&format!("{}:\n", name));
+    cfi(&mut asm, "startproc");
+    if debug_info && function.span.line != 0 {
+        asm.push_str(&format!("    .loc 1 {} {}\n", function.span.line, function.span.column));
+    }
+    asm.push_str("    pushq %rbp\n");
+    cfi(&mut asm, "def_cfa_offset 16");
+    cfi(&mut asm, "offset %rbp, -16");
+    asm.push_str("    movq %rsp, %rbp\n");
+    cfi(&mut asm, "def_cfa_register %rbp");
+    for instruction in function.instructions {
         match instruction {
-            AsmInstruction::Mov(src, dst) => {
-                asm.push_str(&format!("    movl {}, {}\n", operand_to_str(src), operand_to_str(dst)));
+            AsmInstruction::Mov(ty, src, dst) => {
+                let size = type_size(ty);
+                asm.push_str(&format!(
+                    "    mov{} {}, {}\n",
+                    type_suffix(ty),
+                    operand_to_str(src, size, os),
+                    operand_to_str(dst, size, os)
+                ));
+            },
+            AsmInstruction::Movsx(src_type, dst_type, src, dst) => {
+                asm.push_str(&format!(
+                    "    movs{}{} {}, {}\n",
+                    type_suffix(src_type),
+                    type_suffix(dst_type),
+                    operand_to_str(src, type_size(src_type), os),
+                    operand_to_str(dst, type_size(dst_type), os)
+                ));
+            },
+            AsmInstruction::MovZeroExtend(AsmType::Byte, dst_type, src, dst) => {
+                asm.push_str(&format!(
+                    "    movzb{} {}, {}\n",
+                    type_suffix(dst_type),
+                    operand_to_str(src, 1, os),
+                    operand_to_str(dst, type_size(dst_type), os)
+                ));
+            },
+            AsmInstruction::MovZeroExtend(..) => unreachable!("zero extensions from 4 bytes are replaced by moves"),
+            AsmInstruction::Unary(operator, ty, operand) => {
+                let mnemonic = match operator {
+                    AsmUnaryOperator::Neg => "neg",
+                    AsmUnaryOperator::Not => "not",
+                };
+                asm.push_str(&format!("    {}{} {}\n", mnemonic, type_suffix(ty), operand_to_str(operand, type_size(ty), os)));
+            },
+            AsmInstruction::Binary(operator, ty, src, dst) => {
+                let size = type_size(ty);
+                let (mnemonic, src_size) = match operator {
+                    AsmBinaryOperator::Add => ("add", size),
+                    AsmBinaryOperator::DivDouble => ("div", size),
+                    AsmBinaryOperator::Sub => ("sub", size),
+                    AsmBinaryOperator::Mult => ("imul", size),
+                    AsmBinaryOperator::And => ("and", size),
+                    AsmBinaryOperator::Or => ("or", size),
+                    AsmBinaryOperator::Xor => ("xor", size),
+                    AsmBinaryOperator::Sal => ("sal", 1),
+                    AsmBinaryOperator::Sar => ("sar", 1),
+                    AsmBinaryOperator::Shr => ("shr", 1),
+                };
+                let mnemonic = match (operator, ty) {
+                    (AsmBinaryOperator::Mult, AsmType::Double) => "mulsd".to_string(),
+                    // There is no scalar xor, but only the low 8 bytes of the result are used
+                    (AsmBinaryOperator::Xor, AsmType::Double) => "xorpd".to_string(),
+                    _ => format!("{}{}", mnemonic, type_suffix(ty)),
+                };
+                asm.push_str(&format!(
+                    "    {} {}, {}\n",
+                    mnemonic,
+                    operand_to_str(src, src_size, os),
+                    operand_to_str(dst, size, os)
+                ));
+            },
+            AsmInstruction::Idiv(ty, operand) => {
+                asm.push_str(&format!("    idiv{} {}\n", type_suffix(ty), operand_to_str(operand, type_size(ty), os)));
+            },
+            AsmInstruction::Div(ty, operand) => {
+                asm.push_str(&format!("    div{} {}\n", type_suffix(ty), operand_to_str(operand, type_size(ty), os)));
+            },
+            AsmInstruction::Cdq(AsmType::Longword) => {
+                asm.push_str("    cdq\n");
+            },
+            AsmInstruction::Cdq(AsmType::Quadword) => {
+                asm.push_str("    cqo\n");
+            },
+            AsmInstruction::Cdq(AsmType::Byte | AsmType::Double) => unreachable!("only ints and longs are divided with idiv"),
+            AsmInstruction::Cmp(ty, left, right) => {
+                let size = type_size(ty);
+                let mnemonic = match ty {
+                    AsmType::Double => "comisd".to_string(),
+                    _ => format!("cmp{}", type_suffix(ty)),
+                };
+                asm.push_str(&format!(
+                    "    {} {}, {}\n",
+                    mnemonic,
+                    operand_to_str(left, size, os),
+                    operand_to_str(right, size, os)
+                ));
+            },
+            AsmInstruction::Xchg(ty, src, dst) => {
+                let size = type_size(ty);
+                asm.push_str(&format!(
+                    "    xchg{} {}, {}\n",
+                    type_suffix(ty),
+                    operand_to_str(src, size, os),
+                    operand_to_str(dst, size, os)
+                ));
+            },
+            AsmInstruction::Cmpxchg(ty, src, dst) => {
+                let size = type_size(ty);
+                asm.push_str(&format!(
+                    "    lock cmpxchg{} {}, {}\n",
+                    type_suffix(ty),
+                    operand_to_str(src, size, os),
+                    operand_to_str(dst, size, os)
+                ));
+            },
+            AsmInstruction::Cvttsd2si(ty, src, dst) => {
+                asm.push_str(&format!(
+                    "    cvttsd2si{} {}, {}\n",
+                    type_suffix(ty),
+                    operand_to_str(src, 8, os),
+                    operand_to_str(dst, type_size(ty), os)
+                ));
+            },
+            AsmInstruction::Cvtsi2sd(ty, src, dst) => {
+                asm.push_str(&format!(
+                    "    cvtsi2sd{} {}, {}\n",
+                    type_suffix(ty),
+                    operand_to_str(src, type_size(ty), os),
+                    operand_to_str(dst, 8, os)
+                ));
+            },
+            AsmInstruction::Lea(src, dst) => {
+                asm.push_str(&format!("    leaq {}, {}\n", operand_to_str(src, 8, os), operand_to_str(dst, 8, os)));
+            },
+            AsmInstruction::SetCC(cond, operand) => {
+                asm.push_str(&format!("    set{} {}\n", cond_code_to_str(cond), operand_to_str(operand, 1, os)));
+            },
+            AsmInstruction::Jmp(target) => {
+                asm.push_str(&format!("    jmp {}\n", os.local_label(&target)));
+            },
+            AsmInstruction::JmpCC(cond, target) => {
+                asm.push_str(&format!("    j{} {}\n", cond_code_to_str(cond), os.local_label(&target)));
+            },
+            AsmInstruction::Label(name) => {
+                asm.push_str(&format!("{}:\n", os.local_label(&name)));
+            },
+            AsmInstruction::AllocateStack(size) => {
+                asm.push_str(&format!("    subq ${}, %rsp\n", size));
+            },
+            AsmInstruction::DeallocateStack(size) => {
+                asm.push_str(&format!("    addq ${}, %rsp\n", size));
+            },
+            AsmInstruction::AllocateDynamic(register) => {
+                asm.push_str(&format!("    subq {}, %rsp\n", register_to_str(register, 8)));
+                asm.push_str(&format!("    movq %rsp, {}\n", register_to_str(register, 8)));
+            },
+            AsmInstruction::ResetStack(offset) => {
+                asm.push_str(&format!("    leaq -{}(%rbp), %rsp\n", offset));
+            },
+            AsmInstruction::Push(operand) => {
+                asm.push_str(&format!("    pushq {}\n", operand_to_str(operand, 8, os)));
+            },
+            AsmInstruction::Pop(register) => {
+                asm.push_str(&format!("    popq {}\n", register_to_str(register, 8)));
+            },
+            AsmInstruction::Call(name, _) => {
+                // Mach-O always routes calls to other images through stubs, so only ELF needs @PLT
+                let plt = if pic && os == Os::Linux && !defined.contains(&name) { "@PLT" } else { "" };
+                asm.push_str(&format!("    call {}{}\n", os.symbol(&name), plt));
             },
             AsmInstruction::Ret => {
+                // Code after an early return still runs with the frame set up
+                cfi(&mut asm, "remember_state");
+                asm.push_str("    movq %rbp, %rsp\n");
+                asm.push_str("    popq %rbp\n");
+                cfi(&mut asm, "def_cfa %rsp, 8");
                 asm.push_str("    ret\n");
+                cfi(&mut asm, "restore_state");
+            }
+            AsmInstruction::SystemCall(call, _) => asm.push_str(&system_call_to_string(call, os)),
+            AsmInstruction::Trap => asm.push_str("    ud2\n"),
+            AsmInstruction::Location(span) => {
+                asm.push_str(&format!("    .loc 1 {} {}\n", span.line, span.column));
+            }
+        }
+    }
+    cfi(&mut asm, "endproc");
+    asm
+}
+
+/// Returns the `.file` directive naming the source file that the `.loc` directives refer
+/// to as file 1.
+///
+/// # Arguments
+///
+/// * `path` - The path of the source file.
+///
+/// # Returns
+///
+/// * `String` - The directive, with the path quoted.
+pub(crate) fn file_directive(path: &str) -> String {
+    format!(" .file 1 \"{}\"\n", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Generates a minimal program entry point for freestanding executables that are linked
+/// without libc: it calls `main` and passes its return value to the `exit` system call.
+///
+/// # Arguments
+///
+/// * `entry` - The name of the entry symbol, `_start` unless the user asked otherwise.
+/// * `os` - The operating system, which decides symbol names and the system call number.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the entry point.
+pub fn entry_point_to_string(entry: &str, os: Os) -> String {
+    let mut asm: String = String::new();
+
+    let entry = os.symbol(entry);
+    let exit = match os {
+        Os::Linux => 60,
+        // BSD system calls are numbered from 0x2000000 on macOS
+        Os::Darwin => 0x2000001,
+    };
+    asm.push_str(&format!(" .globl {}\n{}:\n", entry, entry));
+    asm.push_str(&format!("    call {}\n", os.symbol("main")));
+    asm.push_str("    movl %eax, %edi\n");
+    asm.push_str(&format!("    movl ${}, %eax\n", exit));
+    asm.push_str("    syscall\n");
+    asm
+}
+
+/// Generates a system call whose arguments are in the argument registers, in the order the
+/// IR passes them: it moves them and the constants the operating system needs into the
+/// registers the kernel reads, and leaves the result in `%rax`, negative on failure.
+///
+/// # Arguments
+///
+/// * `call` - The system call.
+/// * `os` - The operating system, which decides the number and the arguments.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the system call.
+fn system_call_to_string(call: SystemCall, os: Os) -> String {
+    const KERNEL_REGISTERS: [AsmRegister; 6] =
+        [AsmRegister::DI, AsmRegister::SI, AsmRegister::DX, AsmRegister::R10, AsmRegister::R8, AsmRegister::R9];
+    let mut asm = String::new();
+    let (number, arguments) = Target { arch: Arch::X86_64, os }.system_call(call);
+    for (position, argument) in arguments.iter().enumerate().rev() {
+        let register = register_to_str(KERNEL_REGISTERS[position], 8);
+        match argument {
+            SystemCallArgument::Value(index) if *index == position => {}
+            SystemCallArgument::Value(index) => {
+                asm.push_str(&format!("    movq {}, {}\n", register_to_str(ARG_REGISTERS[*index], 8), register));
             }
+            SystemCallArgument::Constant(value) => asm.push_str(&format!("    movq ${}, {}\n", value, register)),
         }
     }
-    asm.push_str(r#"    .section .note.GNU-stack,"",@progbits"#);
-    return asm;
+    asm.push_str(&format!("    movl ${}, %eax\n", number));
+    asm.push_str("    syscall\n");
+    if os == Os::Darwin {
+        asm.push_str("    jnc 1f\n    negq %rax\n1:\n");
+    }
+    asm
+}
+
+/// Converts a static variable to the directives that reserve and initialize its memory, in
+/// `.data`, or in `.bss` if it starts as all zeros.
+///
+/// # Arguments
+///
+/// * `variable` - The static variable to be converted.
+/// * `os` - The operating system, which decides the symbol name.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the variable.
+fn static_variable_to_string(variable: AsmStaticVariable, os: Os) -> String {
+    let name = os.symbol(&variable.name);
+    let mut asm = String::new();
+    if variable.global {
+        asm.push_str(&format!(" .globl {}\n", name));
+    }
+    if variable.init.iter().all(StaticInit::is_zero) {
+        let size: usize = variable.init.iter().map(StaticInit::size).sum();
+        asm.push_str(&format!(" .bss\n .balign {}\n", variable.alignment));
+        asm.push_str(&format!("{}:\n    .zero {}\n", name, size));
+        return asm;
+    }
+    asm.push_str(&format!(" .data\n .balign {}\n{}:\n", variable.alignment, name));
+    for init in &variable.init {
+        asm.push_str(&static_init_to_string(init, os));
+    }
+    asm
+}
+
+/// Converts one piece of the initial value of a static object to the directive that stores
+/// it. The AArch64 backend shares it, since data directives are the same on both.
+///
+/// # Arguments
+///
+/// * `init` - The piece of the initial value.
+/// * `os` - The operating system, which decides the label of a string constant pointed to.
+///
+/// # Returns
+///
+/// * `String` - The directive, ending in a newline.
+pub(crate) fn static_init_to_string(init: &StaticInit, os: Os) -> String {
+    match init {
+        StaticInit::Scalar(constant) => {
+            let directive = match constant.ty().size() {
+                1 => ".byte",
+                4 => ".long",
+                _ => ".quad",
+            };
+            format!("    {} {}\n", directive, constant.storage_value())
+        }
+        StaticInit::Zero(size) => format!("    .zero {}\n", size),
+        StaticInit::String(string, true) => format!("    .asciz \"{}\"\n", escape_string(string)),
+        StaticInit::String(string, false) => format!("    .ascii \"{}\"\n", escape_string(string)),
+        StaticInit::WideString(string, null_terminated) => {
+            let mut elements: Vec<String> = string.chars().map(|ch| u32::from(ch).to_string()).collect();
+            if *null_terminated {
+                elements.push("0".to_string());
+            }
+            if elements.is_empty() { String::new() } else { format!("    .long {}\n", elements.join(", ")) }
+        }
+        StaticInit::Pointer(name) => format!("    .quad {}\n", os.local_label(name)),
+    }
+}
+
+/// Escapes a string for the quotes of an `.ascii` or `.asciz` directive: quotes and
+/// backslashes are preceded by a backslash and bytes that are not printable ASCII become
+/// octal escapes.
+///
+/// # Arguments
+///
+/// * `string` - The string to be escaped.
+///
+/// # Returns
+///
+/// * `String` - The escaped string.
+fn escape_string(string: &str) -> String {
+    string
+        .bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+/// Converts a constant to the directives that store it in read-only memory. A double
+/// aligned to 16 bytes is padded to 16 bytes, since `xorpd` reads that many.
+///
+/// # Arguments
+///
+/// * `constant` - The constant to be converted.
+/// * `os` - The operating system, which decides the section and the label name.
+///
+/// # Returns
+///
+/// * `String` - The assembly code of the constant.
+pub(crate) fn constant_to_string(constant: AsmConstant, os: Os) -> String {
+    let mut asm = String::new();
+    match constant.init {
+        StaticInit::String(..) => asm.push_str(os.string_section()),
+        StaticInit::WideString(..) => asm.push_str(os.constant_section()),
+        _ => asm.push_str(os.literal_section(constant.alignment)),
+    }
+    asm.push_str(&format!(" .balign {}\n", constant.alignment));
+    asm.push_str(&format!("{}:\n", os.local_label(&constant.name)));
+    asm.push_str(&static_init_to_string(&constant.init, os));
+    if constant.alignment == 16 {
+        asm.push_str("    .quad 0\n");
+    }
+    asm
 }
 
 /// Converts an operand to its string representation.
@@ -72,13 +1529,909 @@ pub fn assembly_to_string(assembly: AsmProgram) -> String {
 /// # Arguments
 ///
 /// * `op` - The operand to be converted.
+/// * `size` - The operand size in bytes (1, 4 or 8), which selects the register name.
+/// * `os` - The operating system, which decides the names of static variables and constants.
 ///
 /// # Returns
 ///
 /// * `String` - The string representation of the operand.
-fn operand_to_str(operand: AsmOperand) -> String {
+fn operand_to_str(operand: AsmOperand, size: u8, os: Os) -> String {
     match operand {
         AsmOperand::Imm(value) => format!("${}", value),
-        AsmOperand::Register => "%eax".to_string(),
+        AsmOperand::Reg(register) => register_to_str(register, size).to_string(),
+        AsmOperand::Stack(offset) => format!("{}(%rbp)", offset),
+        AsmOperand::Data(name) => format!("{}(%rip)", os.symbol(&name)),
+        AsmOperand::Constant(name) => format!("{}(%rip)", os.local_label(&name)),
+        AsmOperand::Memory(register, 0) => format!("({})", register_to_str(register, 8)),
+        AsmOperand::Memory(register, offset) => format!("{}({})", offset, register_to_str(register, 8)),
+        AsmOperand::Indexed(base, index, scale) => {
+            format!("({},{},{})", register_to_str(base, 8), register_to_str(index, 8), scale)
+        }
+        AsmOperand::Pseudo(name) | AsmOperand::PseudoMem(name, _) => panic!("Pseudo register {} was not replaced", name),
+    }
+}
+
+/// Returns the mnemonic suffix of an instruction operating on operands of a size.
+///
+/// # Arguments
+///
+/// * `ty` - The size of the operands.
+///
+/// # Returns
+///
+/// * `&str` - `b` for 1-byte operands, `l` for 4-byte operands, `q` for 8-byte operands and
+///   `sd` for doubles.
+fn type_suffix(ty: AsmType) -> &'static str {
+    match ty {
+        AsmType::Byte => "b",
+        AsmType::Longword => "l",
+        AsmType::Quadword => "q",
+        AsmType::Double => "sd",
+    }
+}
+
+/// Returns the size in bytes of the operands of an instruction, which selects the names of
+/// its registers.
+///
+/// # Arguments
+///
+/// * `ty` - The size of the operands.
+///
+/// # Returns
+///
+/// * `u8` - 1, 4 or 8.
+fn type_size(ty: AsmType) -> u8 {
+    match ty {
+        AsmType::Byte => 1,
+        AsmType::Longword => 4,
+        AsmType::Quadword | AsmType::Double => 8,
+    }
+}
+
+/// Converts a condition code to the suffix used by `jCC` and `setCC`.
+///
+/// # Arguments
+///
+/// * `cond` - The condition code to be converted.
+///
+/// # Returns
+///
+/// * `&str` - The mnemonic suffix.
+fn cond_code_to_str(cond: AsmCondCode) -> &'static str {
+    match cond {
+        AsmCondCode::E => "e",
+        AsmCondCode::NE => "ne",
+        AsmCondCode::G => "g",
+        AsmCondCode::GE => "ge",
+        AsmCondCode::L => "l",
+        AsmCondCode::LE => "le",
+        AsmCondCode::A => "a",
+        AsmCondCode::AE => "ae",
+        AsmCondCode::B => "b",
+        AsmCondCode::BE => "be",
+        AsmCondCode::P => "p",
+        AsmCondCode::NP => "np",
+    }
+}
+
+/// Converts a register to the name of its `size`-byte part.
+///
+/// # Arguments
+///
+/// * `register` - The register to be converted.
+/// * `size` - The size in bytes (1, 4 or 8), which XMM registers ignore.
+///
+/// # Returns
+///
+/// * `&str` - The AT&T name of the register.
+fn register_to_str(register: AsmRegister, size: u8) -> &'static str {
+    match (register, size) {
+        (AsmRegister::AX, 1) => "%al",
+        (AsmRegister::AX, 4) => "%eax",
+        (AsmRegister::AX, _) => "%rax",
+        (AsmRegister::BX, 1) => "%bl",
+        (AsmRegister::BX, 4) => "%ebx",
+        (AsmRegister::BX, _) => "%rbx",
+        (AsmRegister::CX, 1) => "%cl",
+        (AsmRegister::CX, 4) => "%ecx",
+        (AsmRegister::CX, _) => "%rcx",
+        (AsmRegister::DX, 1) => "%dl",
+        (AsmRegister::DX, 4) => "%edx",
+        (AsmRegister::DX, _) => "%rdx",
+        (AsmRegister::DI, 1) => "%dil",
+        (AsmRegister::DI, 4) => "%edi",
+        (AsmRegister::DI, _) => "%rdi",
+        (AsmRegister::SI, 1) => "%sil",
+        (AsmRegister::SI, 4) => "%esi",
+        (AsmRegister::SI, _) => "%rsi",
+        (AsmRegister::R8, 1) => "%r8b",
+        (AsmRegister::R8, 4) => "%r8d",
+        (AsmRegister::R8, _) => "%r8",
+        (AsmRegister::R9, 1) => "%r9b",
+        (AsmRegister::R9, 4) => "%r9d",
+        (AsmRegister::R9, _) => "%r9",
+        (AsmRegister::R10, 1) => "%r10b",
+        (AsmRegister::R10, 4) => "%r10d",
+        (AsmRegister::R10, _) => "%r10",
+        (AsmRegister::R11, 1) => "%r11b",
+        (AsmRegister::R11, 4) => "%r11d",
+        (AsmRegister::R11, _) => "%r11",
+        (AsmRegister::R12, 1) => "%r12b",
+        (AsmRegister::R12, 4) => "%r12d",
+        (AsmRegister::R12, _) => "%r12",
+        (AsmRegister::R13, 1) => "%r13b",
+        (AsmRegister::R13, 4) => "%r13d",
+        (AsmRegister::R13, _) => "%r13",
+        (AsmRegister::R14, 1) => "%r14b",
+        (AsmRegister::R14, 4) => "%r14d",
+        (AsmRegister::R14, _) => "%r14",
+        (AsmRegister::R15, 1) => "%r15b",
+        (AsmRegister::R15, 4) => "%r15d",
+        (AsmRegister::R15, _) => "%r15",
+        (AsmRegister::XMM0, _) => "%xmm0",
+        (AsmRegister::XMM1, _) => "%xmm1",
+        (AsmRegister::XMM2, _) => "%xmm2",
+        (AsmRegister::XMM3, _) => "%xmm3",
+        (AsmRegister::XMM4, _) => "%xmm4",
+        (AsmRegister::XMM5, _) => "%xmm5",
+        (AsmRegister::XMM6, _) => "%xmm6",
+        (AsmRegister::XMM7, _) => "%xmm7",
+        (AsmRegister::XMM14, _) => "%xmm14",
+        (AsmRegister::XMM15, _) => "%xmm15",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typecheck::SymbolTable;
+
+    fn program(exp: Exp) -> IrProgram {
+        let ast = Program {
+            declarations: vec![Declaration::Function(FunDecl {
+                name: "main".into(),
+                params: vec![],
+                body: Some(vec![BlockItem::Statement(Statement::Return(exp, Span::default()))]),
+                ty: Type::Function { params: vec![], ret: Box::new(Type::Int) },
+                storage_class: None,
+                span: Span::default(),
+            })],
+        };
+        generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None)
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        // -(~(!3))
+        let exp = Exp::UnOp(UnaryOperator::Negate, Box::new(
+            Exp::UnOp(UnaryOperator::Complement, Box::new(
+                Exp::UnOp(UnaryOperator::Not, Box::new(Exp::Const(Constant::Int(3))), Span::default())), Span::default())), Span::default());
+        let asm = assembly_to_string(generate_assembly(program(exp), false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $16, %rsp
+    movl $3, %r11d
+    cmpl $0, %r11d
+    movl $0, -4(%rbp)
+    sete -4(%rbp)
+    movl -4(%rbp), %r10d
+    movl %r10d, -8(%rbp)
+    notl -8(%rbp)
+    movl -8(%rbp), %r10d
+    movl %r10d, -12(%rbp)
+    negl -12(%rbp)
+    movl -12(%rbp), %eax
+    movq %rbp, %rsp
+    popq %rbp
+    ret
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_binary_operators() {
+        // 7 % 2
+        let exp = Exp::BinOp(BinaryOperator::Remainder, Box::new(Exp::Const(Constant::Int(7))), Box::new(Exp::Const(Constant::Int(2))), Span::default());
+        let asm = assembly_to_string(generate_assembly(program(exp), false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    subq $16, %rsp
+    movl $7, %eax
+    cdq
+    movl $2, %r10d
+    idivl %r10d
+    movl %edx, -4(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_relational_operators() {
+        // 1 <= 2
+        let exp = Exp::BinOp(BinaryOperator::LessOrEqual, Box::new(Exp::Const(Constant::Int(1))), Box::new(Exp::Const(Constant::Int(2))), Span::default());
+        let asm = assembly_to_string(generate_assembly(program(exp), false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movl $1, %r11d
+    cmpl $2, %r11d
+    movl $0, -4(%rbp)
+    setle -4(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_bitwise_operators_and_shifts() {
+        // (a << 3) ^ (a >> a)
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let binary = |op, src1, src2, dst| IrInstruction::Binary { op, src1, src2, dst: var(dst) };
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec!["a".into()],
+                body: vec![
+                    binary(BinaryOperator::LeftShift, var("a"), IrValue::Constant(Constant::Int(3)), "tmp.0"),
+                    binary(BinaryOperator::RightShift, var("a"), var("a"), "tmp.1"),
+                    binary(BinaryOperator::BitwiseXor, var("tmp.0"), var("tmp.1"), "tmp.2"),
+                    IrInstruction::Return(var("tmp.2")),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movl -4(%rbp), %r10d
+    movl %r10d, -8(%rbp)
+    sall $3, -8(%rbp)
+    movl -4(%rbp), %r10d
+    movl %r10d, -12(%rbp)
+    movl -4(%rbp), %ecx
+    sarl %cl, -12(%rbp)
+    movl -8(%rbp), %r10d
+    movl %r10d, -16(%rbp)
+    movl -12(%rbp), %r10d
+    xorl %r10d, -16(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_static_variables() {
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::Copy { src: IrValue::Var("x".into()), dst: IrValue::Var("y".into()) },
+                    IrInstruction::Return(IrValue::Var("y".into())),
+                ],
+            }],
+            static_variables: vec![
+                IrStaticVariable { name: "x".into(), global: true, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(3))] },
+                IrStaticVariable { name: "y".into(), global: false, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(0))] },
+            ],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, true).unwrap(), Os::Darwin, false, None);
+        let expected = "\
+    movl _x(%rip), %r10d
+    movl %r10d, _y(%rip)
+    movl _y(%rip), %eax
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains(" .globl _x\n .data\n .balign 4\n_x:\n    .long 3\n"));
+        assert!(asm.contains("\n .bss\n .balign 4\n_y:\n    .zero 4\n"));
+        assert!(!asm.contains(" .globl _y\n"));
+    }
+
+    #[test]
+    fn test_fix_up_instructions() {
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-4), AsmOperand::Stack(-8)),
+                AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Longword, AsmOperand::Stack(-4), AsmOperand::Stack(-8)),
+                AsmInstruction::Binary(AsmBinaryOperator::Mult, AsmType::Longword, AsmOperand::Imm(3), AsmOperand::Stack(-8)),
+                AsmInstruction::Binary(AsmBinaryOperator::Sub, AsmType::Longword, AsmOperand::Imm(3), AsmOperand::Stack(-8)),
+            ],
+        };
+        fix_up_instructions(&mut function, 8, &[]);
+        let r10 = AsmOperand::Reg(AsmRegister::R10);
+        let r11 = AsmOperand::Reg(AsmRegister::R11);
+        let expected = vec![
+            AsmInstruction::AllocateStack(16),
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-4), r10.clone()),
+            AsmInstruction::Mov(AsmType::Longword, r10.clone(), AsmOperand::Stack(-8)),
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-4), r10.clone()),
+            AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Longword, r10, AsmOperand::Stack(-8)),
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-8), r11.clone()),
+            AsmInstruction::Binary(AsmBinaryOperator::Mult, AsmType::Longword, AsmOperand::Imm(3), r11.clone()),
+            AsmInstruction::Mov(AsmType::Longword, r11, AsmOperand::Stack(-8)),
+            AsmInstruction::Binary(AsmBinaryOperator::Sub, AsmType::Longword, AsmOperand::Imm(3), AsmOperand::Stack(-8)),
+        ];
+        assert_eq!(function.instructions, expected);
+    }
+
+    #[test]
+    fn test_save_callee_saved_registers() {
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-4), AsmOperand::Reg(AsmRegister::BX)), AsmInstruction::Ret],
+        };
+        fix_up_instructions(&mut function, 4, &[AsmRegister::BX, AsmRegister::R12]);
+        // The two pushes keep %rsp aligned together with the 16 bytes allocated for the slot
+        assert_eq!(function.instructions, vec![
+            AsmInstruction::AllocateStack(16),
+            AsmInstruction::Push(AsmOperand::Reg(AsmRegister::BX)),
+            AsmInstruction::Push(AsmOperand::Reg(AsmRegister::R12)),
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(-4), AsmOperand::Reg(AsmRegister::BX)),
+            AsmInstruction::Pop(AsmRegister::R12),
+            AsmInstruction::Pop(AsmRegister::BX),
+            AsmInstruction::Ret,
+        ]);
+        let asm = assembly_to_string(AsmProgram { functions: vec![function], static_variables: vec![], constants: vec![] }, Os::Linux, false, None);
+        assert!(asm.contains("    pushq %r12\n"));
+        assert!(asm.contains("    popq %r12\n    popq %rbx\n    movq %rbp, %rsp\n"));
+    }
+
+    #[test]
+    fn test_reset_stack_after_dynamic_allocation() {
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![AsmInstruction::AllocateDynamic(AsmRegister::AX), AsmInstruction::Ret],
+        };
+        fix_up_instructions(&mut function, 4, &[AsmRegister::BX]);
+        // The registers were pushed right below the 8 bytes allocated for the slot
+        assert_eq!(function.instructions, vec![
+            AsmInstruction::AllocateStack(8),
+            AsmInstruction::Push(AsmOperand::Reg(AsmRegister::BX)),
+            AsmInstruction::AllocateDynamic(AsmRegister::AX),
+            AsmInstruction::ResetStack(16),
+            AsmInstruction::Pop(AsmRegister::BX),
+            AsmInstruction::Ret,
+        ]);
+        let asm = assembly_to_string(AsmProgram { functions: vec![function], static_variables: vec![], constants: vec![] }, Os::Linux, false, None);
+        assert!(asm.contains("    subq %rax, %rsp\n    movq %rsp, %rax\n    leaq -16(%rbp), %rsp\n    popq %rbx\n"), "{}", asm);
+    }
+
+    #[test]
+    fn test_function_call_arguments() {
+        // f(1, 2, 3, 4, 5, 6, 7, a), with the last two arguments passed on the stack
+        let mut args: Vec<IrValue> = (1..=7).map(|value| IrValue::Constant(Constant::Int(value))).collect();
+        args.push(IrValue::Var("a".into()));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec!["a".into()],
+                body: vec![IrInstruction::FunCall { name: "f".into(), args, dst: IrValue::Var("b".into()) }],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    subq $16, %rsp
+    movl %edi, -4(%rbp)
+    movl $1, %edi
+    movl $2, %esi
+    movl $3, %edx
+    movl $4, %ecx
+    movl $5, %r8d
+    movl $6, %r9d
+    movl -4(%rbp), %eax
+    pushq %rax
+    pushq $7
+    call f
+    addq $16, %rsp
+    movl %eax, -8(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_position_independent_calls() {
+        let call = |name: &str| IrInstruction::FunCall { name: Name::from(name), args: vec![], dst: IrValue::Var("a".into()) };
+        let ir = || IrProgram {
+            functions: vec![IrFunction { name: "main".into(), global: true, span: Span::default(), params: vec![], body: vec![call("putchar"), call("main")] }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir(), false).unwrap(), Os::Linux, true, None);
+        assert!(asm.contains("    call putchar@PLT\n"));
+        assert!(asm.contains("    call main\n"));
+        let asm = assembly_to_string(generate_assembly(ir(), false).unwrap(), Os::Linux, false, None);
+        assert!(asm.contains("    call putchar\n"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stack_parameters_and_padding() {
+        // int f(a, b, c, d, e, f, g) { return g; } calling g(1, ..., 7)
+        let params: Vec<Name> = (0..7).map(|i| Name::from(format!("p.{}", i))).collect();
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "f".into(),
+                global: true,
+                span: Span::default(),
+                params,
+                body: vec![
+                    IrInstruction::FunCall {
+                        name: "g".into(),
+                        args: (1..=7).map(|value| IrValue::Constant(Constant::Int(value))).collect(),
+                        dst: IrValue::Var("r".into()),
+                    },
+                    IrInstruction::Return(IrValue::Var("p.6".into())),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let function = generate_assembly(ir, false).unwrap().functions.remove(0);
+        let r10 = AsmOperand::Reg(AsmRegister::R10);
+        assert!(function.instructions.windows(2).any(|pair| pair == [
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Stack(16), r10.clone()),
+            AsmInstruction::Mov(AsmType::Longword, r10.clone(), AsmOperand::Stack(-28)),
+        ]));
+        assert!(function.instructions.contains(&AsmInstruction::AllocateStack(8)));
+        assert!(function.instructions.contains(&AsmInstruction::DeallocateStack(16)));
+    }
+
+    #[test]
+    fn test_entry_point_calls_main_and_exits() {
+        let asm = entry_point_to_string("_start", Os::Linux);
+        assert!(asm.starts_with(" .globl _start\n_start:\n"));
+        assert!(asm.contains("call main"));
+        assert!(asm.contains("movl $60, %eax\n    syscall"));
+    }
+
+    #[test]
+    fn test_darwin_symbols_and_directives() {
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::Jump("end.1".into()),
+                    IrInstruction::Label("end.1".into()),
+                    IrInstruction::FunCall { name: "f".into(), args: vec![], dst: IrValue::Var("a".into()) },
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Darwin, true, None);
+        assert!(asm.starts_with(" .globl _main\n_main:\n"));
+        assert!(asm.contains("    jmp Lend.1\nLend.1:\n"));
+        assert!(asm.contains("    call _f\n"));
+        assert!(!asm.contains("GNU-stack"));
+        assert!(!asm.contains("@PLT"));
+
+        let asm = entry_point_to_string("start", Os::Darwin);
+        assert!(asm.starts_with(" .globl _start\n_start:\n    call _main\n"));
+        assert!(asm.contains("movl $33554433, %eax\n    syscall"));
+    }
+
+    #[test]
+    fn test_long_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::SignExtend { src: var("i"), dst: var("l") },
+                    IrInstruction::Binary { op: BinaryOperator::Add, src1: var("l"), src2: IrValue::Constant(Constant::Long(4294967296)), dst: var("m") },
+                    IrInstruction::Truncate { src: var("m"), dst: var("i") },
+                    IrInstruction::Return(var("i")),
+                ],
+            }],
+            static_variables: vec![
+                IrStaticVariable { name: "i".into(), global: true, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(1))] },
+                IrStaticVariable { name: "l".into(), global: true, alignment: 8, init: vec![StaticInit::Scalar(Constant::Long(2))] },
+            ],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("i".into(), Type::Int),
+                ("l".into(), Type::Long),
+                ("m".into(), Type::Long),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movslq i(%rip), %r11
+    movq %r11, l(%rip)
+    movq l(%rip), %r10
+    movq %r10, -8(%rbp)
+    movq $4294967296, %r10
+    addq %r10, -8(%rbp)
+    movl -8(%rbp), %r10d
+    movl %r10d, i(%rip)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains(" .globl l\n .data\n .balign 8\nl:\n    .quad 2\n"), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_unsigned_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec!["a".into(), "b".into()],
+                body: vec![
+                    IrInstruction::Binary { op: BinaryOperator::Divide, src1: var("a"), src2: var("b"), dst: var("c") },
+                    IrInstruction::Binary { op: BinaryOperator::GreaterThan, src1: var("a"), src2: var("b"), dst: var("d") },
+                    IrInstruction::Binary { op: BinaryOperator::RightShift, src1: var("a"), src2: IrValue::Constant(Constant::Int(3)), dst: var("e") },
+                    IrInstruction::ZeroExtend { src: var("a"), dst: var("f") },
+                    IrInstruction::Return(var("d")),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("a".into(), Type::UInt),
+                ("b".into(), Type::UInt),
+                ("c".into(), Type::UInt),
+                ("e".into(), Type::UInt),
+                ("f".into(), Type::ULong),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movl -4(%rbp), %eax
+    movl $0, %edx
+    divl -8(%rbp)
+    movl %eax, -12(%rbp)
+    movl -8(%rbp), %r10d
+    cmpl %r10d, -4(%rbp)
+    movl $0, -16(%rbp)
+    seta -16(%rbp)
+    movl -4(%rbp), %r10d
+    movl %r10d, -20(%rbp)
+    shrl $3, -20(%rbp)
+    movl -4(%rbp), %r11d
+    movq %r11, -32(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_double_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec!["i".into(), "a".into()],
+                body: vec![
+                    IrInstruction::Binary { op: BinaryOperator::Add, src1: var("a"), src2: IrValue::Constant(Constant::Double(1.5)), dst: var("b") },
+                    IrInstruction::Binary { op: BinaryOperator::LessThan, src1: var("a"), src2: var("b"), dst: var("c") },
+                    IrInstruction::IntToDouble { src: var("i"), dst: var("d") },
+                    IrInstruction::Unary { op: UnaryOperator::Negate, src: var("d"), dst: var("e") },
+                    IrInstruction::Return(var("e")),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("a".into(), Type::Double),
+                ("b".into(), Type::Double),
+                ("d".into(), Type::Double),
+                ("e".into(), Type::Double),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movl %edi, -4(%rbp)
+    movsd %xmm0, -16(%rbp)
+    movsd -16(%rbp), %xmm15
+    movsd %xmm15, -24(%rbp)
+    movsd -24(%rbp), %xmm15
+    addsd .Ldouble.0(%rip), %xmm15
+    movsd %xmm15, -24(%rbp)
+    movsd -24(%rbp), %xmm15
+    comisd -16(%rbp), %xmm15
+    movl $0, -28(%rbp)
+    seta -28(%rbp)
+    cvtsi2sdl -4(%rbp), %xmm15
+    movsd %xmm15, -40(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    xorpd .Ldouble.1(%rip), %xmm15\n"), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("    movsd -48(%rbp), %xmm0\n"), "unexpected assembly:\n{}", asm);
+        // The sign bit is padded to the 16 bytes xorpd reads
+        assert!(asm.contains(" .balign 16\n.Ldouble.1:\n    .quad -9223372036854775808\n    .quad 0\n"));
+    }
+
+    #[test]
+    fn test_pointer_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::GetAddress { src: var("x"), dst: var("p") },
+                    IrInstruction::Store { src: IrValue::Constant(Constant::Long(5)), dst_ptr: var("p") },
+                    IrInstruction::Load { src_ptr: var("p"), dst: var("y") },
+                    IrInstruction::GetAddress { src: var("g"), dst: var("q") },
+                    IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec!["g".into()],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("x".into(), Type::Long),
+                ("y".into(), Type::Long),
+                ("g".into(), Type::Int),
+                ("p".into(), Type::Pointer(Box::new(Type::Long))),
+                ("q".into(), Type::Pointer(Box::new(Type::Int))),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    leaq -8(%rbp), %r11
+    movq %r11, -16(%rbp)
+    movq -16(%rbp), %rax
+    movq $5, (%rax)
+    movq -16(%rbp), %rax
+    movq (%rax), %r10
+    movq %r10, -24(%rbp)
+    leaq g(%rip), %r11
+    movq %r11, -32(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_atomic_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::AtomicStore { src: IrValue::Constant(Constant::Int(5)), dst_ptr: var("p") },
+                    IrInstruction::AtomicLoad { src_ptr: var("p"), dst: var("x") },
+                    IrInstruction::CompareExchange {
+                        ptr: var("p"),
+                        expected: var("x"),
+                        desired: IrValue::Constant(Constant::Int(6)),
+                        dst: var("ok"),
+                    },
+                    IrInstruction::Return(var("ok")),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("p".into(), Type::Pointer(Box::new(Type::Atomic(Box::new(Type::Int))))),
+                ("x".into(), Type::Int),
+                ("ok".into(), Type::Int),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movq -8(%rbp), %rax
+    movl $5, %edx
+    xchgl %edx, (%rax)
+    movq -8(%rbp), %rax
+    movl (%rax), %r10d
+    movl %r10d, -12(%rbp)
+    movq -8(%rbp), %rdx
+    movl $6, %ecx
+    movl -12(%rbp), %eax
+    lock cmpxchgl %ecx, (%rdx)
+    movl $0, -16(%rbp)
+    sete -16(%rbp)
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+    }
+
+    #[test]
+    fn test_array_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Int(7)), dst: "a".into(), offset: 4 },
+                    IrInstruction::GetAddress { src: var("a"), dst: var("p") },
+                    IrInstruction::AddPtr { ptr: var("p"), index: IrValue::Constant(Constant::Long(2)), scale: 4, dst: var("q") },
+                    IrInstruction::AddPtr { ptr: var("p"), index: var("i"), scale: 4, dst: var("q") },
+                    IrInstruction::AddPtr { ptr: var("p"), index: var("i"), scale: 12, dst: var("q") },
+                    IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+                ],
+            }],
+            static_variables: vec![
+                IrStaticVariable { name: "z".into(), global: true, alignment: 16, init: vec![StaticInit::Zero(40)] },
+                IrStaticVariable { name: "s".into(), global: true, alignment: 16, init: vec![StaticInit::Scalar(Constant::Int(1)), StaticInit::Zero(12)] },
+            ],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::from([
+                ("a".into(), Type::Array(Box::new(Type::Int), 5)),
+                ("i".into(), Type::Long),
+                ("p".into(), Type::Pointer(Box::new(Type::Int))),
+                ("q".into(), Type::Pointer(Box::new(Type::Int))),
+                ("z".into(), Type::Array(Box::new(Type::Long), 5)),
+                ("s".into(), Type::Array(Box::new(Type::Int), 4)),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movl $7, -28(%rbp)
+    leaq -32(%rbp), %r11
+    movq %r11, -40(%rbp)
+    movq -40(%rbp), %rax
+    leaq 8(%rax), %r11
+    movq %r11, -48(%rbp)
+    movq -40(%rbp), %rax
+    movq -56(%rbp), %rdx
+    leaq (%rax,%rdx,4), %r11
+    movq %r11, -48(%rbp)
+    movq -56(%rbp), %rdx
+    imulq $12, %rdx
+    movq -40(%rbp), %rax
+    leaq (%rax,%rdx,1), %r11
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains(" .bss\n .balign 16\nz:\n    .zero 40\n"));
+        assert!(asm.contains(" .data\n .balign 16\ns:\n    .long 1\n    .zero 12\n"));
+    }
+    #[test]
+    fn test_character_operations() {
+        let var = |name: &str| IrValue::Var(Name::from(name));
+        let ir = IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span::default(),
+                params: vec![],
+                body: vec![
+                    IrInstruction::Copy { src: IrValue::Constant(Constant::UChar(200)), dst: var("u") },
+                    IrInstruction::SignExtend { src: var("c"), dst: var("i") },
+                    IrInstruction::ZeroExtend { src: var("u"), dst: var("l") },
+                    IrInstruction::Truncate { src: var("l"), dst: var("c") },
+                    IrInstruction::IntToDouble { src: var("c"), dst: var("d") },
+                    IrInstruction::DoubleToInt { src: var("d"), dst: var("c") },
+                    IrInstruction::GetAddress { src: var("string.0"), dst: var("p") },
+                    IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+                ],
+            }],
+            static_variables: vec![
+                IrStaticVariable { name: "s".into(), global: true, alignment: 1, init: vec![StaticInit::String("a\"\\\n".to_string(), false), StaticInit::Zero(2)] },
+                IrStaticVariable { name: "q".into(), global: false, alignment: 8, init: vec![StaticInit::Pointer("string.0".into())] },
+            ],
+            extern_variables: vec![],
+            static_constants: vec![IrStaticConstant { name: "string.0".into(), alignment: 1, init: StaticInit::String("hi".to_string(), true) }],
+            types: BTreeMap::from([
+                ("c".into(), Type::Char),
+                ("u".into(), Type::UChar),
+                ("i".into(), Type::Int),
+                ("l".into(), Type::Long),
+                ("d".into(), Type::Double),
+                ("p".into(), Type::Pointer(Box::new(Type::Char))),
+                ("s".into(), Type::Array(Box::new(Type::Char), 6)),
+                ("q".into(), Type::Pointer(Box::new(Type::Char))),
+                ("string.0".into(), Type::Array(Box::new(Type::Char), 3)),
+            ]),
+        };
+        let asm = assembly_to_string(generate_assembly(ir, false).unwrap(), Os::Linux, false, None);
+        let expected = "\
+    movb $-56, -1(%rbp)
+    movsbl -2(%rbp), %r11d
+    movl %r11d, -8(%rbp)
+    movzbq -1(%rbp), %r11
+    movq %r11, -16(%rbp)
+    movb -16(%rbp), %r10b
+    movb %r10b, -2(%rbp)
+    movsbl -2(%rbp), %eax
+    cvtsi2sdl %eax, %xmm15
+    movsd %xmm15, -24(%rbp)
+    cvttsd2sil -24(%rbp), %eax
+    movb %al, -2(%rbp)
+    leaq .Lstring.0(%rip), %r11
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        assert!(asm.contains("s:\n    .ascii \"a\\\"\\\\\\012\"\n    .zero 2\n"), "{}", asm);
+        assert!(asm.contains("q:\n    .quad .Lstring.0\n"), "{}", asm);
+        assert!(asm.contains(" .section .rodata\n .balign 1\n.Lstring.0:\n    .asciz \"hi\"\n"), "{}", asm);
+
+        // Wide strings are 4-byte elements, and are not C strings on Darwin
+        let wide = || AsmConstant { name: "string.1".into(), alignment: 4, init: StaticInit::WideString("hi".to_string(), true) };
+        assert_eq!(constant_to_string(wide(), Os::Linux), " .section .rodata\n .balign 4\n.Lstring.1:\n    .long 104, 105, 0\n");
+        assert_eq!(constant_to_string(wide(), Os::Darwin), " .const\n .balign 4\nLstring.1:\n    .long 104, 105, 0\n");
+        assert_eq!(static_init_to_string(&StaticInit::WideString("ab".to_string(), false), Os::Linux), "    .long 97, 98\n");
+    }
+
+    #[test]
+    fn test_debug_information() {
+        let ir = || IrProgram {
+            functions: vec![IrFunction {
+                name: "main".into(),
+                global: true,
+                span: Span { line: 1, column: 5 },
+                params: vec![],
+                body: vec![
+                    IrInstruction::Location(Span { line: 2, column: 5 }),
+                    IrInstruction::Return(IrValue::Constant(Constant::Int(1))),
+                ],
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: BTreeMap::new(),
+        };
+        let asm = assembly_to_string(generate_assembly(ir(), false).unwrap(), Os::Linux, false, Some("dir/\"a\".c"));
+        assert!(asm.starts_with(" .file 1 \"dir/\\\"a\\\".c\"\n .globl main\n"), "unexpected assembly:\n{}", asm);
+        let expected = "\
+main:
+    .cfi_startproc
+    .loc 1 1 5
+    pushq %rbp
+    .cfi_def_cfa_offset 16
+    .cfi_offset %rbp, -16
+    movq %rsp, %rbp
+    .cfi_def_cfa_register %rbp
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+        let expected = "\
+    .loc 1 2 5
+    movl $1, %eax
+    .cfi_remember_state
+    movq %rbp, %rsp
+    popq %rbp
+    .cfi_def_cfa %rsp, 8
+    ret
+    .cfi_restore_state
+    .cfi_endproc
+";
+        assert!(asm.contains(expected), "unexpected assembly:\n{}", asm);
+
+        let asm = assembly_to_string(generate_assembly(ir(), false).unwrap(), Os::Linux, false, None);
+        assert!(!asm.contains(".file") && !asm.contains(".cfi") && !asm.contains(".loc 1 1"));
+    }
+}
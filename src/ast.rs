@@ -1,64 +1,1052 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use serde::Serialize;
+pub use crate::intern::Name;
+use crate::ir::SystemCall;
 /// Enum representing the different types of tokens that the lexer can recognize.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub enum Token {
     OpenBrace,
     CloseBrace,
     OpenParenthesis,
     CloseParenthesis,
+    OpenBracket,
+    CloseBracket,
     Semicolon,
     IntKeyword,
+    CharKeyword,
+    /// `wchar_t`, which the headers define as `int` on the targets scc supports. With no
+    /// typedefs to define it with, it is a type specifier of its own.
+    WcharKeyword,
+    LongKeyword,
+    SignedKeyword,
+    UnsignedKeyword,
+    DoubleKeyword,
     ReturnKeyword,
     VoidKeyword,
-    Identifier(String),
+    IfKeyword,
+    ElseKeyword,
+    DoKeyword,
+    WhileKeyword,
+    ForKeyword,
+    BreakKeyword,
+    ContinueKeyword,
+    SwitchKeyword,
+    CaseKeyword,
+    DefaultKeyword,
+    GotoKeyword,
+    StaticKeyword,
+    ExternKeyword,
+    StructKeyword,
+    SizeOfKeyword,
+    /// The `_Atomic` type qualifier.
+    AtomicKeyword,
+    Identifier(Name),
+    /// An integer constant without a suffix. Like the other integer constants, it keeps the
+    /// `0x`, `0b` or `0` prefix of a hexadecimal, binary or octal constant.
     IntegerLiteral(String),
+    /// An integer constant with an `l` or `L` suffix.
+    LongLiteral(String),
+    /// An integer constant with a `u` or `U` suffix.
+    UnsignedLiteral(String),
+    /// An integer constant with both an unsigned and a long suffix, such as `5ul` or `5LU`.
+    UnsignedLongLiteral(String),
+    /// A floating-point constant, with a decimal point, an exponent or both.
+    DoubleLiteral(String),
+    /// A character constant such as `'a'`, with its escape sequence decoded.
+    CharLiteral(char),
+    /// A string literal such as `"abc"`, with its escape sequences decoded.
+    StringLiteral(String),
+    WideCharLiteral(char),
+    WideStringLiteral(String),
     Negation,
     BitwiseComplement,
     LogicalNegation,
+    Increment,
     Decrement,
+    Addition,
+    Multiplication,
+    Division,
+    Remainder,
+    Assignment,
+    AdditionAssignment,
+    SubtractionAssignment,
+    MultiplicationAssignment,
+    DivisionAssignment,
+    RemainderAssignment,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    LeftShift,
+    RightShift,
+    BitwiseAndAssignment,
+    BitwiseOrAssignment,
+    BitwiseXorAssignment,
+    LeftShiftAssignment,
+    RightShiftAssignment,
+    QuestionMark,
+    Colon,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    LogicalAnd,
+    LogicalOr,
+    Comma,
+    /// `.`, which selects a member of a structure.
+    Dot,
+    /// `->`, which selects a member of the structure a pointer points to.
+    Arrow,
+}
+/// A position in the source file. Lines and columns start at 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+/// A token together with the position of its first character.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+/// Source text that separates tokens and that the parser never sees.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Trivia {
+    /// A run of spaces, tabs, carriage returns and newlines.
+    Whitespace,
+    /// A `//` comment, up to but not including the newline that ends it.
+    LineComment,
+    /// A `/* */` comment, which runs to the end of the file if it is never closed.
+    BlockComment,
+}
+/// What a piece of the lossless token stream is.
+#[derive(Debug, PartialEq, Clone)]
+pub enum LosslessKind {
+    Token(Token),
+    Trivia(Trivia),
+}
+/// A token or a piece of trivia, with the exact text of the source it was lexed from and
+/// the position of its first character. Joining the text of every piece in a file gives
+/// back the file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LosslessToken<'a> {
+    pub kind: LosslessKind,
+    pub text: &'a str,
+    pub span: Span,
 }
 
 // AST nodes
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Program {
-    pub func: FunDecl,
+    /// The file-scope declarations, in source order.
+    pub declarations: Vec<Declaration>,
 }
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct FunDecl {
-    pub name: String,
-    pub body: Statement,
+    pub name: Name,
+    pub params: Vec<Name>,
+    pub body: Option<Vec<BlockItem>>,
+    /// The function's type, a `Type::Function` with one parameter type per name in `params`.
+    pub ty: Type,
+    pub storage_class: Option<StorageClass>,
+    pub span: Span,
+}
+#[derive(Debug, Serialize)]
+pub enum BlockItem {
+    Statement(Statement),
+    Declaration(Declaration),
+}
+#[derive(Debug, Serialize)]
+pub enum Declaration {
+    Variable(VarDecl),
+    Function(FunDecl),
+    Struct(StructDecl),
+}
+/// `struct tag { members };`, which defines a structure type, or `struct tag;`, which
+/// declares one whose members are given later.
+#[derive(Debug, Serialize)]
+pub struct StructDecl {
+    pub tag: Name,
+    /// `None` for a declaration without a member list.
+    pub members: Option<Vec<MemberDecl>>,
+    pub span: Span,
+}
+/// A member in the member list of a structure, such as `int x;` in `struct s { int x; };`.
+#[derive(Debug, Serialize)]
+pub struct MemberDecl {
+    pub name: Name,
+    pub ty: Type,
+    pub span: Span,
+}
+#[derive(Debug, Serialize)]
+pub struct VarDecl {
+    pub name: Name,
+    pub init: Option<Initializer>,
+    pub ty: Type,
+    pub storage_class: Option<StorageClass>,
+    pub span: Span,
+}
+/// The initializer of a variable: a single expression, or a brace-enclosed list of
+/// initializers for the elements of an array.
+#[derive(Debug, Serialize)]
+pub enum Initializer {
+    Single(Exp),
+    /// `{a, b, ...}`, with the position of the opening brace. Elements without an
+    /// initializer are set to zero.
+    Compound(Vec<Initializer>, Span),
+}
+/// The storage-class specifier of a declaration, which decides the linkage of the name and
+/// how long a variable lives.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum StorageClass {
+    Static,
+    Extern,
+}
+/// The type of a variable, function or expression.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub enum Type {
+    /// Plain `char`, which is signed, but a distinct type from `signed char`.
+    Char,
+    SChar,
+    UChar,
+    Int,
+    Long,
+    UInt,
+    ULong,
+    Double,
+    /// A pointer to a value of the given type.
+    Pointer(Box<Type>),
+    /// An array of the given number of elements of the given type.
+    Array(Box<Type>, usize),
+    /// An array as written in a declarator or type name, whose number of elements is an
+    /// integer constant expression. The type checker evaluates it and replaces the type with
+    /// an `Array`, so no later phase sees one.
+    DeclaredArray(Box<Type>, Box<Exp>),
+    /// A structure type, named by its tag. After resolution the tag is unique in the
+    /// program, so two structures declared with the same tag in different scopes differ.
+    Struct(Name),
+    Function { params: Vec<Type>, ret: Box<Type> },
+    /// The given type qualified with `_Atomic`, which only integer types may be. Only objects
+    /// have atomic types: reading one yields a value of the unqualified type.
+    Atomic(Box<Type>),
+}
+/// The value of a constant, whose variant is its type.
+///
+/// Doubles compare and hash by their bits, so that a constant is always equal to itself
+/// even if it is a NaN, and `0.0` and `-0.0` stay apart.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum Constant {
+    /// A `char` or `signed char`. Character constants have type `int`, so these only come
+    /// from conversions.
+    Char(i8),
+    UChar(u8),
+    Int(i32),
+    Long(i64),
+    UInt(u32),
+    ULong(u64),
+    Double(f64),
+}
+/// A piece of the initial value of a variable with static storage, which is laid out as
+/// the pieces one after the other.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum StaticInit {
+    /// A scalar, already converted to the type of the object it initializes.
+    Scalar(Constant),
+    /// The given number of zero bytes, such as the elements an initializer leaves out.
+    Zero(usize),
+    /// The bytes of a string literal, followed by a null byte if the flag is set. A character
+    /// array exactly as long as the string it is initialized with has no room for one.
+    String(String, bool),
+    /// The characters of a wide string literal as 4-byte `wchar_t` elements, followed by a
+    /// null one if the flag is set.
+    WideString(String, bool),
+    /// The address of another object with static storage, such as a string literal a
+    /// `char *` points to.
+    Pointer(Name),
 }
-#[derive(Debug)]
+/// The layout of a structure type, worked out by the type checker from its member list.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct StructDef {
+    /// The largest alignment of its members.
+    pub alignment: usize,
+    /// The end of its last member, rounded up to a multiple of the alignment so the
+    /// elements of an array of the structure are all aligned.
+    pub size: usize,
+    pub members: Vec<MemberDef>,
+}
+/// A member of a structure type, placed at the first offset after the member before it
+/// that suits its alignment.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct MemberDef {
+    pub name: Name,
+    pub ty: Type,
+    pub offset: usize,
+}
+/// Maps the unique tag of every structure type given a member list to its layout.
+/// Structures only declared by `struct tag;` are missing until their members are given.
+pub type StructTable = HashMap<Name, StructDef>;
+#[derive(Debug, Serialize)]
 pub enum Statement {
-    Return(Exp),
+    Return(Exp, Span),
+    Expression(Exp),
+    If(Exp, Box<Statement>, Option<Box<Statement>>),
+    // Loops and the break/continue statements targeting them carry a label that
+    // is empty after parsing and filled in by the loop labeling pass
+    While { condition: Exp, body: Box<Statement>, label: Name },
+    DoWhile { body: Box<Statement>, condition: Exp, label: Name },
+    For { init: Box<ForInit>, condition: Option<Exp>, post: Option<Exp>, body: Box<Statement>, label: Name },
+    /// `switch (condition) body`, which `break` also targets. The labeling pass fills in
+    /// `cases` with the value of every `case` in the body, or `None` for `default`, along
+    /// with the label it jumps to.
+    Switch { condition: Exp, body: Box<Statement>, cases: Vec<(Option<Constant>, Name)>, label: Name, span: Span },
+    /// `case value: body`. The type checker folds the value into a constant of the type the
+    /// enclosing `switch` compares.
+    Case { value: Exp, body: Box<Statement>, label: Name, span: Span },
+    Default { body: Box<Statement>, label: Name, span: Span },
+    /// `label: body`. Labels are scoped to the function, and the labeling pass renames them
+    /// after it so they are unique in the program, as are the targets of `goto`.
+    Labeled(Name, Box<Statement>, Span),
+    Goto(Name, Span),
+    Break(Name, Span),
+    Continue(Name, Span),
+    /// `{ ... }`, a block nested in a function body. The declarations in it are only in scope
+    /// up to its end, and may shadow those of the enclosing blocks.
+    Compound(Vec<BlockItem>),
+    Null,
+}
+#[derive(Debug, Serialize)]
+pub enum ForInit {
+    Declaration(VarDecl),
+    Expression(Option<Exp>),
 }
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub enum Exp {
-    Const(i32),
-    //UnOp(Token, Box<Exp>),
+    Const(Constant),
+    Var(Name, Span),
+    /// A string literal, an array of `char` ending in a null byte. Adjacent literals have
+    /// already been joined into one.
+    String(String, Span),
+    /// A wide string literal, an array of `wchar_t` ending in a null character, with one
+    /// element for each character of the string.
+    WideString(String, Span),
+    Assignment(Box<Exp>, Box<Exp>, Span),
+    /// `a op= b`, which stores `a op b` in `a`. Prefix `++a` and `--a` are parsed as `a += 1`
+    /// and `a -= 1`.
+    CompoundAssignment(BinaryOperator, Box<Exp>, Box<Exp>, Span),
+    /// `a++` or `a--`, with `Add` or `Subtract`, whose value is that of `a` before the update.
+    PostfixUpdate(BinaryOperator, Box<Exp>, Span),
+    Conditional(Box<Exp>, Box<Exp>, Box<Exp>, Span),
+    /// `a, b`, which evaluates `a` only for its side effects, then `b`, whose value it has.
+    Comma(Box<Exp>, Box<Exp>, Span),
+    /// Converts a value to another type. The type checker adds these wherever C converts a
+    /// value implicitly, so both operands of an arithmetic operator have the same type, and
+    /// gives them the position of the construct that needed the conversion.
+    Cast(Type, Box<Exp>, Span),
+    FunctionCall(Name, Vec<Exp>, Span),
+    UnOp(UnaryOperator, Box<Exp>, Span),
+    /// `*p`, the object a pointer points to.
+    Dereference(Box<Exp>, Span),
+    /// `&x`, a pointer to an object.
+    AddressOf(Box<Exp>, Span),
+    /// `a[i]`, the element `i` places after the one `a` points to. The type checker puts the
+    /// pointer first and converts the index to `long`, as `i[a]` is the same element.
+    Subscript(Box<Exp>, Box<Exp>, Span),
+    /// `s.m`, a member of a structure. The parser turns `p->m` into `(*p).m`, and leaves the
+    /// offset of the member from the start of the structure and its type as zero and `int`
+    /// for the type checker to fill in.
+    Member { base: Box<Exp>, member: Name, offset: usize, ty: Type, span: Span },
+    BinOp(BinaryOperator, Box<Exp>, Box<Exp>, Span),
+    /// `sizeof e`, the size in bytes of the type of `e`, which is not evaluated. The type
+    /// checker replaces it with an `unsigned long` constant.
+    SizeOf(Box<Exp>, Span),
+    /// `sizeof(type)`, which the type checker also replaces with a constant.
+    SizeOfType(Type, Span),
+}
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub enum UnaryOperator {
+    Negate,
+    Complement,
+    Not,
+}
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    LeftShift,
+    RightShift,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    And,
+    Or,
 }
 // ---Define the structure for the Assembly AST----
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct AsmProgram {
-    pub function: AsmFunction,
+    pub functions: Vec<AsmFunction>,
+    pub static_variables: Vec<AsmStaticVariable>,
+    /// The floating-point constants the functions load from memory and the string literals.
+    pub constants: Vec<AsmConstant>,
 }
-#[derive(Debug)]
+/// A constant in read-only data: a double, since x86-64 has no immediate operands for
+/// doubles and every double constant a function uses is read from one of these, or a string
+/// literal.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AsmConstant {
+    pub name: Name,
+    /// 8, or 16 for constants used as the memory operand of a packed instruction such as
+    /// `xorpd`. 1 for strings.
+    pub alignment: usize,
+    pub init: StaticInit,
+}
+/// A variable with static storage, placed in `.data`, or in `.bss` if it starts as zero.
+#[derive(Debug, Serialize)]
+pub struct AsmStaticVariable {
+    pub name: Name,
+    /// Whether the symbol is visible to other files, which takes a `.globl` directive.
+    pub global: bool,
+    /// The alignment in bytes, that of the type of the variable.
+    pub alignment: usize,
+    /// The initial value, whose pieces decide the size of the variable.
+    pub init: Vec<StaticInit>,
+}
+#[derive(Debug, Serialize)]
 pub struct AsmFunction {
-    pub name: String,
+    pub name: Name,
+    /// Whether the symbol is visible to other files, which takes a `.globl` directive.
+    pub global: bool,
+    /// The position of the function's name, which debug information maps its prologue to.
+    pub span: Span,
     pub instructions: Vec<AsmInstruction>,
 }
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum AsmInstruction {
-    Mov(AsmOperand, AsmOperand),
+    Mov(AsmType, AsmOperand, AsmOperand),
+    /// Sign-extends a source of the first size into a larger destination of the second.
+    Movsx(AsmType, AsmType, AsmOperand, AsmOperand),
+    /// Zero-extends a source of the first size into a larger destination of the second. One
+    /// from 4 bytes is replaced by a plain move once operands are final, since writing a
+    /// 4-byte register clears its upper half.
+    MovZeroExtend(AsmType, AsmType, AsmOperand, AsmOperand),
+    Unary(AsmUnaryOperator, AsmType, AsmOperand),
+    /// Converts a double to a signed integer of the given size, rounding toward zero.
+    Cvttsd2si(AsmType, AsmOperand, AsmOperand),
+    /// Converts a signed integer of the given size to a double.
+    Cvtsi2sd(AsmType, AsmOperand, AsmOperand),
+    /// Loads the address of a memory operand into a register.
+    Lea(AsmOperand, AsmOperand),
+    Binary(AsmBinaryOperator, AsmType, AsmOperand, AsmOperand),
+    Idiv(AsmType, AsmOperand),
+    /// Divides `%edx:%eax`, or `%rdx:%rax`, as unsigned numbers.
+    Div(AsmType, AsmOperand),
+    /// Sign-extends `%eax` into `%edx`, or `%rax` into `%rdx`, before a division.
+    Cdq(AsmType),
+    Cmp(AsmType, AsmOperand, AsmOperand),
+    /// Swaps a register with memory, which locks the memory without a `lock` prefix and so
+    /// makes an atomic store.
+    Xchg(AsmType, AsmOperand, AsmOperand),
+    /// Compares `%eax`, or `%al` or `%rax`, with the memory destination and, if they are
+    /// equal, replaces the memory with the source register, setting ZF; otherwise loads the
+    /// memory into `%eax`. Emitted with a `lock` prefix, so it is atomic.
+    Cmpxchg(AsmType, AsmOperand, AsmOperand),
+    SetCC(AsmCondCode, AsmOperand),
+    Jmp(Name),
+    JmpCC(AsmCondCode, Name),
+    Label(Name),
+    AllocateStack(i32),
+    DeallocateStack(i32),
+    /// Moves `%rsp` down by the number of bytes in the register, a multiple of 16, and puts
+    /// the new `%rsp`, the address of the block allocated, in the register instead.
+    AllocateDynamic(AsmRegister),
+    /// Moves `%rsp` back to the given number of bytes below `%rbp`, freeing the blocks
+    /// allocated dynamically since, so that the callee-saved registers can be popped.
+    ResetStack(i32),
+    Push(AsmOperand),
+    /// Restores a callee-saved register before returning.
+    Pop(AsmRegister),
+    /// Calls a function, with the number of arguments passed in registers.
+    Call(Name, usize),
+    /// Makes a system call with the number of arguments in the argument registers, as for a
+    /// call, and leaves its result in `%rax`. Emitted once the operating system is known,
+    /// which decides where the arguments go. It changes the registers a call may change.
+    SystemCall(SystemCall, usize),
     Ret,
+    /// Executes `ud2`, an invalid instruction, which stops the program with `SIGILL`.
+    Trap,
+    /// Maps the instructions that follow to a source position, emitted as a `.loc` directive.
+    Location(Span),
+}
+/// The size of the operands of an instruction, which selects its `b`, `l` or `q` suffix.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+pub enum AsmType {
+    /// 1 byte, for the character types.
+    Byte,
+    /// 4 bytes, for `int`.
+    Longword,
+    /// 8 bytes, for `long`.
+    Quadword,
+    /// 8 bytes in an XMM register, for `double`.
+    Double,
+}
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum AsmUnaryOperator {
+    Neg,
+    Not,
 }
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum AsmBinaryOperator {
+    Add,
+    Sub,
+    Mult,
+    /// Divides doubles. Integers are divided by `Idiv` and `Div` instead.
+    DivDouble,
+    And,
+    Or,
+    Xor,
+    /// Shifts left by an immediate or by `%cl`.
+    Sal,
+    /// Shifts right, keeping the sign, by an immediate or by `%cl`.
+    Sar,
+    /// Shifts right, filling with zeros, by an immediate or by `%cl`.
+    Shr,
+}
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum AsmCondCode {
+    E,
+    NE,
+    G,
+    GE,
+    L,
+    LE,
+    /// Above, the unsigned greater than.
+    A,
+    AE,
+    /// Below, the unsigned less than.
+    B,
+    BE,
+    /// Parity set, which after `comisd` means the operands were unordered.
+    P,
+    NP,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
 pub enum AsmOperand {
-    Imm(i32),
-    Register,
+    Imm(i64),
+    Reg(AsmRegister),
+    Pseudo(Name),
+    Stack(i32),
+    /// A static variable, addressed relative to `%rip`.
+    Data(Name),
+    /// A floating-point constant in read-only data, addressed relative to `%rip` through its
+    /// local label.
+    Constant(Name),
+    /// The memory at an offset from the address held in a register.
+    Memory(AsmRegister, i32),
+    /// The memory at the address in the first register plus the second register times 1, 2,
+    /// 4 or 8.
+    Indexed(AsmRegister, AsmRegister, u8),
+    /// The memory at an offset into a variable that does not fit in a register, such as an
+    /// array, which becomes part of its stack slot.
+    PseudoMem(Name, i32),
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+pub enum AsmRegister {
+    AX,
+    BX,
+    CX,
+    DX,
+    DI,
+    SI,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+    XMM0,
+    XMM1,
+    XMM2,
+    XMM3,
+    XMM4,
+    XMM5,
+    XMM6,
+    XMM7,
+    XMM14,
+    XMM15,
 }
 
 
+impl Constant {
+    /// Returns the type of the constant.
+    pub fn ty(&self) -> Type {
+        match self {
+            Constant::Char(_) => Type::Char,
+            Constant::UChar(_) => Type::UChar,
+            Constant::Int(_) => Type::Int,
+            Constant::Long(_) => Type::Long,
+            Constant::UInt(_) => Type::UInt,
+            Constant::ULong(_) => Type::ULong,
+            Constant::Double(_) => Type::Double,
+        }
+    }
+
+    /// Converts the constant to another type, truncating, sign-extending or zero-extending
+    /// it as the conversion would at run time. Doubles convert to integers by rounding
+    /// toward zero, saturating where C leaves the result undefined.
+    ///
+    /// # Arguments
+    ///
+    /// * `ty` - The type to convert to.
+    ///
+    /// # Returns
+    ///
+    /// The converted constant.
+    pub fn convert_to(&self, ty: &Type) -> Constant {
+        if let Constant::Double(value) = *self {
+            return match ty {
+                Type::Double => *self,
+                Type::Char | Type::SChar => Constant::Char(value as i8),
+                Type::UChar => Constant::UChar(value as u8),
+                Type::Long => Constant::Long(value as i64),
+                Type::UInt => Constant::UInt(value as u32),
+                Type::ULong => Constant::ULong(value as u64),
+                _ => Constant::Int(value as i32),
+            };
+        }
+        match ty {
+            Type::Char | Type::SChar => Constant::Char(self.as_i64() as i8),
+            Type::UChar => Constant::UChar(self.as_i64() as u8),
+            Type::Long => Constant::Long(self.as_i64()),
+            Type::UInt => Constant::UInt(self.as_i64() as u32),
+            // Pointers are unsigned 8-byte values, and only the null pointer is a constant
+            Type::ULong | Type::Pointer(_) => Constant::ULong(self.as_i64() as u64),
+            Type::Double => match *self {
+                Constant::ULong(value) => Constant::Double(value as f64),
+                _ => Constant::Double(self.as_i64() as f64),
+            },
+            _ => Constant::Int(self.as_i64() as i32),
+        }
+    }
+
+    /// Returns the value of the constant as 64 bits: sign-extended if its type is signed,
+    /// zero-extended otherwise. An `unsigned long` keeps its bits, so large values come out
+    /// negative.
+    pub fn as_i64(&self) -> i64 {
+        match self {
+            Constant::Char(value) => i64::from(*value),
+            Constant::UChar(value) => i64::from(*value),
+            Constant::Int(value) => i64::from(*value),
+            Constant::Long(value) => *value,
+            Constant::UInt(value) => i64::from(*value),
+            Constant::ULong(value) => *value as i64,
+            Constant::Double(value) => *value as i64,
+        }
+    }
+
+    /// Returns whether the constant is zero. Both `0.0` and `-0.0` are.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Constant::Double(value) => *value == 0.0,
+            _ => self.as_i64() == 0,
+        }
+    }
+
+    /// Returns the value a `.byte`, `.long` or `.quad` directive stores for the constant: its integer
+    /// value, or the bits of a double.
+    pub fn storage_value(&self) -> i64 {
+        match self {
+            Constant::Double(value) => value.to_bits() as i64,
+            _ => self.as_i64(),
+        }
+    }
+
+    /// Applies a unary operator to the constant, wrapping on overflow like the generated code.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operator.
+    ///
+    /// # Returns
+    ///
+    /// * `Constant` - The result, of the constant's type except for `!`, which gives an `int`.
+    pub fn apply_unary(self, op: UnaryOperator) -> Constant {
+        match (op, self) {
+            (UnaryOperator::Not, value) => Constant::Int(value.is_zero() as i32),
+            (UnaryOperator::Negate, Constant::Int(value)) => Constant::Int(value.wrapping_neg()),
+            (UnaryOperator::Negate, Constant::Long(value)) => Constant::Long(value.wrapping_neg()),
+            (UnaryOperator::Complement, Constant::Int(value)) => Constant::Int(!value),
+            (UnaryOperator::Complement, Constant::Long(value)) => Constant::Long(!value),
+            (UnaryOperator::Negate, Constant::UInt(value)) => Constant::UInt(value.wrapping_neg()),
+            (UnaryOperator::Negate, Constant::ULong(value)) => Constant::ULong(value.wrapping_neg()),
+            (UnaryOperator::Complement, Constant::UInt(value)) => Constant::UInt(!value),
+            (UnaryOperator::Complement, Constant::ULong(value)) => Constant::ULong(!value),
+            (UnaryOperator::Negate, Constant::Double(value)) => Constant::Double(-value),
+            (UnaryOperator::Complement, Constant::Double(_)) => unreachable!("the type checker rejects ~ on doubles"),
+            (_, Constant::Char(_) | Constant::UChar(_)) => unreachable!("characters are promoted to int before arithmetic"),
+        }
+    }
+
+    /// Applies a binary operator to the constant and another one, wrapping on overflow like
+    /// the generated code. The type checker has converted the operands to a common type,
+    /// except for shifts, whose result has the type of the left operand. Doubles are
+    /// evaluated with the same IEEE arithmetic the generated code uses.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operator.
+    /// * `right` - The right operand, the constant being the left one.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Constant>` - The result, or `None` if it is undefined and must not be folded.
+    pub fn apply_binary(self, op: BinaryOperator, right: Constant) -> Option<Constant> {
+        let left = self;
+        if let (Constant::Double(left), Constant::Double(right)) = (left, right) {
+            return Some(apply_double_binary(op, left, right));
+        }
+        macro_rules! arithmetic {
+            ($left:expr, $right:expr) => {
+                match op {
+                    BinaryOperator::Add => $left.wrapping_add($right),
+                    BinaryOperator::Subtract => $left.wrapping_sub($right),
+                    BinaryOperator::Multiply => $left.wrapping_mul($right),
+                    BinaryOperator::Divide => $left.checked_div($right)?,
+                    BinaryOperator::Remainder => $left.checked_rem($right)?,
+                    BinaryOperator::BitwiseAnd => $left & $right,
+                    BinaryOperator::BitwiseOr => $left | $right,
+                    BinaryOperator::BitwiseXor => $left ^ $right,
+                    // Shifting by a negative count or by the width or more is undefined
+                    BinaryOperator::LeftShift => $left.checked_shl(u32::try_from(right.as_i64()).ok()?)?,
+                    BinaryOperator::RightShift => $left.checked_shr(u32::try_from(right.as_i64()).ok()?)?,
+                    _ => unreachable!("comparisons are evaluated separately"),
+                }
+            };
+        }
+        let (left_value, right_value) = (left.as_i64(), right.as_i64());
+        // Unsigned operands compare by their bits, so an `unsigned long` above `LONG_MAX` is large
+        let ordering = if left.ty().is_signed() {
+            left_value.cmp(&right_value)
+        } else {
+            (left_value as u64).cmp(&(right_value as u64))
+        };
+        let value = match op {
+            BinaryOperator::Equal => Constant::Int(ordering.is_eq() as i32),
+            BinaryOperator::NotEqual => Constant::Int(ordering.is_ne() as i32),
+            BinaryOperator::LessThan => Constant::Int(ordering.is_lt() as i32),
+            BinaryOperator::LessOrEqual => Constant::Int(ordering.is_le() as i32),
+            BinaryOperator::GreaterThan => Constant::Int(ordering.is_gt() as i32),
+            BinaryOperator::GreaterOrEqual => Constant::Int(ordering.is_ge() as i32),
+            BinaryOperator::And => Constant::Int((left_value != 0 && right_value != 0) as i32),
+            BinaryOperator::Or => Constant::Int((left_value != 0 || right_value != 0) as i32),
+            _ => match left {
+                Constant::Int(left) => Constant::Int(arithmetic!(left, right_value as i32)),
+                Constant::Long(left) => Constant::Long(arithmetic!(left, right_value)),
+                Constant::UInt(left) => Constant::UInt(arithmetic!(left, right_value as u32)),
+                Constant::ULong(left) => Constant::ULong(arithmetic!(left, right_value as u64)),
+                Constant::Double(_) => unreachable!("shifts of doubles are rejected by the type checker"),
+                Constant::Char(_) | Constant::UChar(_) => unreachable!("characters are promoted to int before arithmetic"),
+            },
+        };
+        Some(value)
+    }
+}
+
+/// Evaluates a binary operation on two doubles. Comparisons involving a NaN are false,
+/// except for `!=`, which is true.
+///
+/// # Arguments
+///
+/// * `op` - The operator, which the type checker allows on doubles.
+/// * `left` - The left operand.
+/// * `right` - The right operand.
+///
+/// # Returns
+///
+/// * `Constant` - The result: a double for arithmetic, or an `int` for a comparison.
+fn apply_double_binary(op: BinaryOperator, left: f64, right: f64) -> Constant {
+    let truth = |value: bool| Constant::Int(value as i32);
+    match op {
+        BinaryOperator::Add => Constant::Double(left + right),
+        BinaryOperator::Subtract => Constant::Double(left - right),
+        BinaryOperator::Multiply => Constant::Double(left * right),
+        BinaryOperator::Divide => Constant::Double(left / right),
+        BinaryOperator::Equal => truth(left == right),
+        BinaryOperator::NotEqual => truth(left != right),
+        BinaryOperator::LessThan => truth(left < right),
+        BinaryOperator::LessOrEqual => truth(left <= right),
+        BinaryOperator::GreaterThan => truth(left > right),
+        BinaryOperator::GreaterOrEqual => truth(left >= right),
+        BinaryOperator::And => truth(left != 0.0 && right != 0.0),
+        BinaryOperator::Or => truth(left != 0.0 || right != 0.0),
+        _ => unreachable!("the type checker rejects integer operators on doubles"),
+    }
+}
+
+impl StaticInit {
+    /// Returns whether the piece is all zero bytes. A double is stored as its bits, so
+    /// `-0.0` is not.
+    pub fn is_zero(&self) -> bool {
+        match self {
+            StaticInit::Scalar(constant) => constant.storage_value() == 0,
+            StaticInit::Zero(_) => true,
+            StaticInit::String(string, _) => string.bytes().all(|byte| byte == 0),
+            StaticInit::WideString(string, _) => string.chars().all(|ch| ch == '\0'),
+            StaticInit::Pointer(_) => false,
+        }
+    }
+
+    /// Returns the number of bytes the piece takes up.
+    pub fn size(&self) -> usize {
+        match self {
+            StaticInit::Scalar(constant) => constant.ty().size(),
+            StaticInit::Zero(bytes) => *bytes,
+            StaticInit::String(string, null_terminated) => string.len() + usize::from(*null_terminated),
+            StaticInit::WideString(string, null_terminated) => {
+                (string.chars().count() + usize::from(*null_terminated)) * Type::Int.size()
+            }
+            StaticInit::Pointer(_) => 8,
+        }
+    }
+}
+
+impl Exp {
+    /// Returns the position of the expression. Constants do not record one, so theirs is
+    /// the start of the file.
+    pub fn span(&self) -> Span {
+        match self {
+            Exp::Const(_) => Span::default(),
+            Exp::Var(_, span)
+            | Exp::String(_, span)
+            | Exp::WideString(_, span)
+            | Exp::Assignment(_, _, span)
+            | Exp::CompoundAssignment(_, _, _, span)
+            | Exp::PostfixUpdate(_, _, span)
+            | Exp::Conditional(_, _, _, span)
+            | Exp::Comma(_, _, span)
+            | Exp::Cast(_, _, span)
+            | Exp::FunctionCall(_, _, span)
+            | Exp::UnOp(_, _, span)
+            | Exp::Dereference(_, span)
+            | Exp::AddressOf(_, span)
+            | Exp::Subscript(_, _, span)
+            | Exp::Member { span, .. }
+            | Exp::BinOp(_, _, _, span)
+            | Exp::SizeOf(_, span)
+            | Exp::SizeOfType(_, span) => *span,
+        }
+    }
+}
+
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Double(left), Constant::Double(right)) => left.to_bits() == right.to_bits(),
+            (Constant::Double(_), _) | (_, Constant::Double(_)) => false,
+            _ => self.ty() == other.ty() && self.as_i64() == other.as_i64(),
+        }
+    }
+}
+
+impl Eq for Constant {}
+
+impl Hash for Constant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        self.storage_value().hash(state);
+    }
+}
+
+impl Type {
+    /// Returns the size in bytes of a value of the type: 1 for the character types, 4 for
+    /// `int` and `unsigned int`, 8 for `long`, `unsigned long`, `double` and pointers, and
+    /// the size of all their elements for arrays. The size of a structure depends on its
+    /// members, so types that may contain one are measured with `size_in` instead.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Char | Type::SChar | Type::UChar => 1,
+            Type::Long | Type::ULong | Type::Double | Type::Pointer(_) => 8,
+            Type::Array(element, count) => element.size() * count,
+            Type::Atomic(ty) => ty.size(),
+            Type::Struct(tag) => unreachable!("the size of struct {} is in the struct table", tag),
+            _ => 4,
+        }
+    }
+
+    /// Returns the alignment in bytes of the type: the size of a scalar type, and the
+    /// alignment of the elements of an array.
+    pub fn alignment(&self) -> usize {
+        match self {
+            Type::Array(element, _) => element.alignment(),
+            _ => self.size(),
+        }
+    }
+
+    /// Returns the size in bytes of a value of the type, like `size`, taking the size of
+    /// structures from their layouts.
+    ///
+    /// # Arguments
+    ///
+    /// * `structs` - The layouts of the structure types of the program.
+    ///
+    /// # Returns
+    ///
+    /// The size. The type must not be an incomplete structure.
+    pub fn size_in(&self, structs: &StructTable) -> usize {
+        match self {
+            Type::Struct(tag) => structs[tag].size,
+            Type::Array(element, count) => element.size_in(structs) * count,
+            Type::Atomic(ty) => ty.size_in(structs),
+            _ => self.size(),
+        }
+    }
+
+    /// Returns the alignment in bytes of the type, like `alignment`, taking the alignment
+    /// of structures from their layouts.
+    ///
+    /// # Arguments
+    ///
+    /// * `structs` - The layouts of the structure types of the program.
+    ///
+    /// # Returns
+    ///
+    /// The alignment. The type must not be an incomplete structure.
+    pub fn alignment_in(&self, structs: &StructTable) -> usize {
+        match self {
+            Type::Struct(tag) => structs[tag].alignment,
+            Type::Array(element, _) => element.alignment_in(structs),
+            Type::Atomic(ty) => ty.alignment_in(structs),
+            _ => self.size(),
+        }
+    }
+
+    /// Returns whether the type is a structure or an array of them, whose size depends on
+    /// the struct table.
+    pub fn contains_struct(&self) -> bool {
+        match self {
+            Type::Struct(_) => true,
+            Type::Array(element, _) | Type::Atomic(element) => element.contains_struct(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the type is an incomplete structure, one declared without a member
+    /// list so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `structs` - The layouts of the structure types given a member list so far.
+    pub fn is_incomplete(&self, structs: &StructTable) -> bool {
+        matches!(self, Type::Struct(tag) if !structs.contains_key(tag))
+    }
+
+    /// Returns whether the type is a signed integer type.
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Char | Type::SChar | Type::Int | Type::Long)
+    }
+
+    /// Returns whether the type is an integer type or `double`.
+    pub fn is_arithmetic(&self) -> bool {
+        self.is_integer() || *self == Type::Double
+    }
+
+    /// Returns whether the type is a pointer type.
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, Type::Pointer(_))
+    }
+
+    /// Returns whether the type is an integer type.
+    pub fn is_integer(&self) -> bool {
+        self.is_character() || matches!(self, Type::Int | Type::Long | Type::UInt | Type::ULong)
+    }
+
+    /// Returns whether the type is `char`, `signed char` or `unsigned char`.
+    pub fn is_character(&self) -> bool {
+        matches!(self, Type::Char | Type::SChar | Type::UChar)
+    }
+
+    /// Returns the type a value of the type is promoted to before an arithmetic operation:
+    /// `int` for the character types, and the type itself otherwise.
+    pub fn promoted(&self) -> Type {
+        if self.is_character() { Type::Int } else { self.clone() }
+    }
+
+    /// Returns whether the type is qualified with `_Atomic`.
+    pub fn is_atomic(&self) -> bool {
+        matches!(self, Type::Atomic(_))
+    }
+
+    /// Returns the type without its `_Atomic` qualifier, the type of the value of an object
+    /// of the type.
+    pub fn unqualified(&self) -> Type {
+        match self {
+            Type::Atomic(ty) => (**ty).clone(),
+            _ => self.clone(),
+        }
+    }
+
+    /// Returns whether a value of the type is a single number or address, rather than an
+    /// array or a function.
+    pub fn is_scalar(&self) -> bool {
+        self.is_arithmetic() || self.is_pointer()
+    }
+
+    /// Spells the type with a declarator wrapped around `inner`, the part of the declarator
+    /// already spelled, the way C nests declarators.
+    fn spell(&self, inner: String) -> String {
+        let base = match self {
+            Type::Char => "char",
+            Type::SChar => "signed char",
+            Type::UChar => "unsigned char",
+            Type::Int => "int",
+            Type::Long => "long",
+            Type::UInt => "unsigned int",
+            Type::ULong => "unsigned long",
+            Type::Double => "double",
+            Type::Struct(tag) => {
+                let tag = tag.source_name();
+                return if inner.is_empty() { format!("struct {}", tag) } else { format!("struct {} {}", tag, inner) };
+            }
+            Type::Atomic(ty) => {
+                return if inner.is_empty() { format!("_Atomic {}", ty) } else { format!("_Atomic {} {}", ty, inner) };
+            }
+            // A declarator that follows the name binds tighter than `*`
+            Type::Pointer(referenced) if matches!(**referenced, Type::Array(..) | Type::DeclaredArray(..) | Type::Function { .. }) => {
+                return referenced.spell(format!("(*{})", inner));
+            }
+            Type::Pointer(referenced) => return referenced.spell(format!("*{}", inner)),
+            Type::Array(element, count) => return element.spell(format!("{}[{}]", inner, count)),
+            // The size has not been evaluated yet
+            Type::DeclaredArray(element, _) => return element.spell(format!("{}[]", inner)),
+            Type::Function { params, ret } => {
+                let params: Vec<String> = params.iter().map(Type::to_string).collect();
+                let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+                return ret.spell(format!("{}({})", inner, params));
+            }
+        };
+        if inner.is_empty() { base.to_string() } else { format!("{} {}", base, inner) }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -66,17 +1054,104 @@ impl fmt::Display for Token {
             Token::CloseBrace => write!(f, "Close brace"),
             Token::OpenParenthesis => write!(f, "Open parenthesis"),
             Token::CloseParenthesis => write!(f, "Close parenthesis"),
+            Token::OpenBracket => write!(f, "Open bracket"),
+            Token::CloseBracket => write!(f, "Close bracket"),
             Token::Semicolon => write!(f, "Semicolon"),
             Token::IntKeyword => write!(f, "Int keyword"),
+            Token::CharKeyword => write!(f, "Char keyword"),
+            Token::WcharKeyword => write!(f, "Wchar_t keyword"),
+            Token::LongKeyword => write!(f, "Long keyword"),
+            Token::SignedKeyword => write!(f, "Signed keyword"),
+            Token::UnsignedKeyword => write!(f, "Unsigned keyword"),
+            Token::DoubleKeyword => write!(f, "Double keyword"),
             Token::ReturnKeyword => write!(f, "Return keyword"),
             Token::VoidKeyword => write!(f, "Void keyword"),
+            Token::IfKeyword => write!(f, "If keyword"),
+            Token::ElseKeyword => write!(f, "Else keyword"),
+            Token::DoKeyword => write!(f, "Do keyword"),
+            Token::WhileKeyword => write!(f, "While keyword"),
+            Token::ForKeyword => write!(f, "For keyword"),
+            Token::BreakKeyword => write!(f, "Break keyword"),
+            Token::ContinueKeyword => write!(f, "Continue keyword"),
+            Token::SwitchKeyword => write!(f, "Switch keyword"),
+            Token::CaseKeyword => write!(f, "Case keyword"),
+            Token::DefaultKeyword => write!(f, "Default keyword"),
+            Token::GotoKeyword => write!(f, "Goto keyword"),
+            Token::StaticKeyword => write!(f, "Static keyword"),
+            Token::ExternKeyword => write!(f, "Extern keyword"),
+            Token::StructKeyword => write!(f, "Struct keyword"),
+            Token::SizeOfKeyword => write!(f, "Sizeof keyword"),
+            Token::AtomicKeyword => write!(f, "_Atomic keyword"),
             Token::Identifier(val) => write!(f, "Identifier \"{}\"", val),
             Token::IntegerLiteral(val) => write!(f, "Constant \"{}\"", val),
+            Token::LongLiteral(val) => write!(f, "Long constant \"{}\"", val),
+            Token::UnsignedLiteral(val) => write!(f, "Unsigned constant \"{}\"", val),
+            Token::UnsignedLongLiteral(val) => write!(f, "Unsigned long constant \"{}\"", val),
+            Token::DoubleLiteral(val) => write!(f, "Floating-point constant \"{}\"", val),
+            Token::CharLiteral(val) => write!(f, "Character constant '{}'", val.escape_default()),
+            Token::StringLiteral(val) => write!(f, "String literal \"{}\"", val.escape_default()),
+            Token::WideCharLiteral(val) => write!(f, "Wide character constant L'{}'", val.escape_default()),
+            Token::WideStringLiteral(val) => write!(f, "Wide string literal L\"{}\"", val.escape_default()),
             Token::Negation => write!(f, "Negation"),
             Token::BitwiseComplement => write!(f, "Bitwise complement"),
             Token::LogicalNegation => write!(f, "Logcial negation"),
+            Token::Increment => write!(f, "Increment operator"),
             Token::Decrement => write!(f, "Decrement operator"),
+            Token::Addition => write!(f, "Addition"),
+            Token::Multiplication => write!(f, "Multiplication"),
+            Token::Division => write!(f, "Division"),
+            Token::Remainder => write!(f, "Remainder"),
+            Token::Assignment => write!(f, "Assignment"),
+            Token::AdditionAssignment => write!(f, "Addition assignment"),
+            Token::SubtractionAssignment => write!(f, "Subtraction assignment"),
+            Token::MultiplicationAssignment => write!(f, "Multiplication assignment"),
+            Token::DivisionAssignment => write!(f, "Division assignment"),
+            Token::RemainderAssignment => write!(f, "Remainder assignment"),
+            Token::BitwiseAnd => write!(f, "Bitwise and"),
+            Token::BitwiseOr => write!(f, "Bitwise or"),
+            Token::BitwiseXor => write!(f, "Bitwise xor"),
+            Token::LeftShift => write!(f, "Left shift"),
+            Token::RightShift => write!(f, "Right shift"),
+            Token::BitwiseAndAssignment => write!(f, "Bitwise and assignment"),
+            Token::BitwiseOrAssignment => write!(f, "Bitwise or assignment"),
+            Token::BitwiseXorAssignment => write!(f, "Bitwise xor assignment"),
+            Token::LeftShiftAssignment => write!(f, "Left shift assignment"),
+            Token::RightShiftAssignment => write!(f, "Right shift assignment"),
+            Token::QuestionMark => write!(f, "Question mark"),
+            Token::Colon => write!(f, "Colon"),
+            Token::Equal => write!(f, "Equal"),
+            Token::NotEqual => write!(f, "Not equal"),
+            Token::LessThan => write!(f, "Less than"),
+            Token::LessOrEqual => write!(f, "Less or equal"),
+            Token::GreaterThan => write!(f, "Greater than"),
+            Token::GreaterOrEqual => write!(f, "Greater or equal"),
+            Token::LogicalAnd => write!(f, "Logical and"),
+            Token::LogicalOr => write!(f, "Logical or"),
+            Token::Comma => write!(f, "Comma"),
+            Token::Dot => write!(f, "Dot"),
+            Token::Arrow => write!(f, "Arrow"),
         }
     }
 }
 
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Char(value) => write!(f, "{}", value),
+            Constant::UChar(value) => write!(f, "{}", value),
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Long(value) => write!(f, "{}L", value),
+            Constant::UInt(value) => write!(f, "{}U", value),
+            Constant::ULong(value) => write!(f, "{}UL", value),
+            Constant::Double(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    /// Spells the type the way C names it in a cast, such as `unsigned long`, `int **` or
+    /// `int (*)[3]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spell(String::new()))
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use crate::ast::Name;
+use crate::ir::*;
+
+/// A straight-line sequence of instructions that control only enters at the top and only
+/// leaves at the bottom.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub instructions: Vec<IrInstruction>,
+    /// The blocks control can continue to. A block without successors leaves the function.
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+/// The control-flow graph of a function. Blocks are kept in program order, so the first
+/// block is the entry and each block falls through to the one after it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// Splits a function body into basic blocks and connects them by their jumps and
+    /// fall-throughs.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The instructions of a function.
+    ///
+    /// # Returns
+    ///
+    /// * `Cfg` - The control-flow graph.
+    pub fn new(body: Vec<IrInstruction>) -> Self {
+        let mut blocks: Vec<Vec<IrInstruction>> = Vec::new();
+        let mut current: Vec<IrInstruction> = Vec::new();
+        for instruction in body {
+            match instruction {
+                IrInstruction::Label(_) => {
+                    if !current.is_empty() {
+                        blocks.push(std::mem::take(&mut current));
+                    }
+                    current.push(instruction);
+                }
+                IrInstruction::Jump(_)
+                | IrInstruction::JumpIfZero(..)
+                | IrInstruction::JumpIfNotZero(..)
+                | IrInstruction::Return(_)
+                | IrInstruction::Trap => {
+                    current.push(instruction);
+                    blocks.push(std::mem::take(&mut current));
+                }
+                _ => current.push(instruction),
+            }
+        }
+        if !current.is_empty() {
+            blocks.push(current);
+        }
+        let blocks = blocks
+            .into_iter()
+            .map(|instructions| BasicBlock { instructions, successors: Vec::new(), predecessors: Vec::new() })
+            .collect();
+        let mut cfg = Cfg { blocks };
+        cfg.connect();
+        cfg
+    }
+
+    /// Recomputes the successors and predecessors of every block from its last instruction.
+    pub fn connect(&mut self) {
+        let labels: HashMap<Name, usize> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| match block.instructions.first() {
+                Some(IrInstruction::Label(label)) => Some((*label, index)),
+                _ => None,
+            })
+            .collect();
+        let count = self.blocks.len();
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            let next = (index + 1 < count).then_some(index + 1);
+            block.successors = match block.instructions.last() {
+                Some(IrInstruction::Return(_) | IrInstruction::Trap) => Vec::new(),
+                Some(IrInstruction::Jump(label)) => vec![labels[label]],
+                Some(IrInstruction::JumpIfZero(_, label) | IrInstruction::JumpIfNotZero(_, label)) => {
+                    let mut successors: Vec<usize> = next.into_iter().collect();
+                    if !successors.contains(&labels[label]) {
+                        successors.push(labels[label]);
+                    }
+                    successors
+                }
+                _ => next.into_iter().collect(),
+            };
+            block.predecessors.clear();
+        }
+        for index in 0..count {
+            for successor in self.blocks[index].successors.clone() {
+                self.blocks[successor].predecessors.push(index);
+            }
+        }
+    }
+
+    /// Flattens the graph back into a function body, in block order.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<IrInstruction>` - The instructions of the function.
+    pub fn into_instructions(self) -> Vec<IrInstruction> {
+        self.blocks.into_iter().flat_map(|block| block.instructions).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Constant;
+
+    #[test]
+    fn test_build_cfg() {
+        // if (x) y = 1; return y;
+        let body = vec![
+            IrInstruction::JumpIfZero(IrValue::Var("x".into()), "end".into()),
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: IrValue::Var("y".into()) },
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(IrValue::Var("y".into())),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let cfg = Cfg::new(body.clone());
+        let edges: Vec<(Vec<usize>, Vec<usize>)> =
+            cfg.blocks.iter().map(|block| (block.successors.clone(), block.predecessors.clone())).collect();
+        assert_eq!(edges, vec![
+            (vec![1, 2], vec![]),
+            (vec![2], vec![0]),
+            (vec![], vec![0, 1]),
+            (vec![], vec![]),
+        ]);
+        assert_eq!(cfg.into_instructions(), body);
+    }
+
+    #[test]
+    fn test_loop_edges() {
+        let body = vec![
+            IrInstruction::Label("start".into()),
+            IrInstruction::JumpIfNotZero(IrValue::Var("x".into()), "start".into()),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let cfg = Cfg::new(body);
+        assert_eq!(cfg.blocks[0].successors, vec![1, 0]);
+        assert_eq!(cfg.blocks[0].predecessors, vec![0]);
+    }
+}
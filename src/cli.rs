@@ -0,0 +1,616 @@
+use std::path::Path;
+use scc::{optimize::Optimizations,profile::Profile,warnings::Warnings,Target};
+
+/// Usage text printed for `--help` and after argument errors.
+pub const USAGE: &str = "\
+Usage: scc [options] <file>...
+       scc test [-j <jobs>] [options] <dir>
+
+Each .c file is compiled; other files, such as .o, .s and .a files, are passed
+to the linker as they are.
+
+scc test builds every .c file under <dir> with scc, passing it the options, and
+with the reference compiler (--cc, else CC, else gcc), runs both programs and
+checks that their exit codes and output match. A file that contains the comment
+\"scc-test: xfail\" is expected to fail. -j <jobs> runs that many tests at once
+(default: one per CPU).
+
+Options:
+  -o <file>             Write the output to <file>
+  --lex                 Stop after lexing and print the tokens
+  --parse               Stop after parsing and print the AST
+  --dump-ast            Stop after parsing and print the AST as an indented tree
+  --check, --validate, -fsyntax-only
+                        Only check the program for errors, do not generate code
+  --tacky               Stop after lowering to the IR and print it
+  --codegen             Stop after assembly generation and print the assembly AST
+  --emit <stage>        Stop after tokens, ast, ir or asm and print that stage's output,
+                        like --lex, --parse, --tacky or --codegen, or print the program
+                        as LLVM IR with llvm
+  --format <format>     Print the output of --emit and the flags above as text or json
+                        (default: text)
+  -O<level>, -O         Optimization level (default: -O0). -O, -O1 and higher levels
+                        enable every optimization below
+  --fold-constants      Evaluate operations on constants at compile time
+  --eliminate-unreachable-code
+                        Remove code that can never run
+  --propagate-copies    Replace variables holding a copy of another value with that value
+  --eliminate-dead-stores
+                        Remove computations whose results are never used
+  --allocate-registers  Keep values in registers instead of on the stack (x86-64 only)
+  --profile-generate <file>
+                        Count how often each part of the program runs, and write the
+                        counts to <file> when main returns
+  --profile-use <file>  Optimize with the counts in <file>: lay out the code that ran most
+                        to fall through, and inline small functions where they were called
+  -fsanitize=null, --check-null
+                        Check every pointer before it is dereferenced, and abort with the
+                        source position if it is null
+  -g                    Emit debug information mapping the generated code to source lines
+  -W, -Wall             Warn about local variables that are never read, statements that
+                        can never run and functions that can end without returning a value
+  -Werror               Report those warnings as errors
+  --timings[=text|json], -ftime-report
+                        Print the time, output size and peak heap memory of each phase
+                        of the compiler for every input, as a table or as JSON, to stderr
+  -S                    Stop after emitting assembly and keep the .s file
+  -c                    Stop after assembling and keep the .o file
+  --preprocess          Run the source through the C preprocessor (gcc -E) first
+  --cc <program>        Preprocess, assemble and link with <program> instead of the
+                        CC environment variable or gcc
+  --keep-intermediates, -save-temps
+                        Keep the .s and .o files made for linking next to each input
+  -nostdlib, --freestanding
+                        Link without libc, using a minimal generated entry point
+  --entry <symbol>      Name of the generated entry point (default: _start)
+  -fpic, -fPIC, -fpie, -fPIE, -fno-pic, -fno-PIC, -fno-pie, -fno-PIE
+                        Whether to generate position-independent code and link a
+                        PIE executable (default: on)
+  --target <target>     Generate code for x86_64-linux, aarch64-linux, x86_64-macos
+                        or aarch64-macos (default: the host platform). wchar_t is
+                        always int, although the aarch64-linux ABI makes it unsigned
+  -fdiagnostics-color[=always|never|auto], -fno-diagnostics-color
+                        Whether to highlight error messages (default: auto, when
+                        printing to a terminal)
+  -h, --help            Print this help message";
+
+/// The pipeline stage after which the driver stops.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stage {
+    Lex,
+    Parse,
+    Check,
+    Ir,
+    /// Printing the program as LLVM IR, instead of generating assembly.
+    Llvm,
+    Codegen,
+    Assembly,
+    Object,
+    Link,
+}
+
+/// When to highlight diagnostics with ANSI colors.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// How the output of an intermediate stage is printed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    /// The human-readable form: one token per line, or the pretty-printed AST.
+    Text,
+    /// JSON, for tools and tests that consume the compiler's internals.
+    Json,
+}
+
+/// Options controlling a single invocation of the compiler driver.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub inputs: Vec<String>,
+    /// The output of a single input, or the linked executable of several.
+    pub output: String,
+    pub stop_after: Stage,
+    pub freestanding: bool,
+    pub entry: String,
+    pub color: ColorChoice,
+    /// Whether to expand `#include`, `#define` and other directives with `gcc -E` before lexing.
+    pub preprocess: bool,
+    pub target: Target,
+    /// Whether to generate position-independent code and link a position-independent executable.
+    pub pic: bool,
+    /// The compiler driver that preprocesses, assembles and links, if chosen with `--cc`.
+    pub cc: Option<String>,
+    /// Whether to keep the assembly and object files made on the way to an executable.
+    pub keep_intermediates: bool,
+    /// How to print the output when stopping after lexing, parsing, the IR or codegen.
+    pub format: Format,
+    /// Whether to print the AST as an indented tree instead of in `format` after parsing.
+    pub dump_ast: bool,
+    /// The optimizations to run on the IR, chosen with `-O` or flags for single passes.
+    pub optimizations: Optimizations,
+    /// Whether to emit line-number and call frame debug information, chosen with `-g`.
+    pub debug_info: bool,
+    /// Which warnings to report, chosen with `-W` and `-Werror`.
+    pub warnings: Warnings,
+    /// Whether to check that pointers are not null before they are dereferenced, chosen with
+    /// `-fsanitize=null`.
+    pub null_checks: bool,
+    /// Whether to count how often blocks run, or to optimize with the counts, chosen with
+    /// `--profile-generate` and `--profile-use`.
+    pub profile: Profile,
+    /// How to print what each phase of the compiler cost, if `--timings` was given.
+    pub timings: Option<Format>,
+}
+
+/// Options for `scc test`, which checks programs built by scc against a reference compiler.
+#[derive(Debug, PartialEq)]
+pub struct TestOptions {
+    /// The directory searched for `.c` files, each of which is one test.
+    pub dir: String,
+    /// The number of tests run at once.
+    pub jobs: usize,
+    /// The compiler that builds the expected programs.
+    pub reference: String,
+    /// The options scc compiles each test with, such as `-O` or `--allocate-registers`.
+    pub compile_args: Vec<String>,
+}
+
+/// The result of parsing the command line: options to compile with, a directory of tests to
+/// run, or a request for help.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Compile(Options),
+    Test(TestOptions),
+    Help,
+}
+
+/// Parses the driver's command line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The arguments, without the program name.
+///
+/// # Returns
+///
+/// The parsed `Command`, or an `Err` with an error message if the arguments are invalid.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some_and(|arg| arg == "test") {
+        args.next();
+        return parse_test_args(args);
+    }
+    let mut inputs: Vec<String> = Vec::new();
+    let mut output: Option<String> = None;
+    let mut stop_after = Stage::Link;
+    let mut freestanding = false;
+    let mut entry = String::from("_start");
+    let mut color = ColorChoice::Auto;
+    let mut preprocess = false;
+    let mut target = Target::host();
+    let mut pic = true;
+    let mut cc = None;
+    let mut keep_intermediates = false;
+    let mut format = Format::Text;
+    let mut dump_ast = false;
+    let mut optimizations = Optimizations::default();
+    let mut debug_info = false;
+    let mut warnings = Warnings::default();
+    let mut null_checks = false;
+    let mut profile = Profile::Off;
+    let mut timings = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" => output = Some(expect_value(&mut args, "-o")?),
+            "--lex" => stop_after = Stage::Lex,
+            "--parse" => stop_after = Stage::Parse,
+            "--dump-ast" => {
+                stop_after = Stage::Parse;
+                dump_ast = true;
+            }
+            "--check" | "--validate" | "-fsyntax-only" => stop_after = Stage::Check,
+            "--tacky" => stop_after = Stage::Ir,
+            "--codegen" => stop_after = Stage::Codegen,
+            "--emit" => {
+                stop_after = match expect_value(&mut args, "--emit")?.as_str() {
+                    "tokens" => Stage::Lex,
+                    "ast" => Stage::Parse,
+                    "ir" => Stage::Ir,
+                    "asm" => Stage::Codegen,
+                    "llvm" => Stage::Llvm,
+                    other => return Err(format!("Unknown --emit stage: {} (expected tokens, ast, ir, asm or llvm)", other)),
+                }
+            }
+            "--format" => {
+                format = match expect_value(&mut args, "--format")?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    other => return Err(format!("Unknown --format: {} (expected text or json)", other)),
+                }
+            }
+            "-S" => stop_after = Stage::Assembly,
+            "-c" => stop_after = Stage::Object,
+            "-O" => optimizations = Optimizations::level(1),
+            "--fold-constants" => optimizations.fold_constants = true,
+            "--eliminate-unreachable-code" => optimizations.eliminate_unreachable_code = true,
+            "--propagate-copies" => optimizations.propagate_copies = true,
+            "--eliminate-dead-stores" => optimizations.eliminate_dead_stores = true,
+            "--allocate-registers" => optimizations.allocate_registers = true,
+            "--profile-generate" => profile = Profile::Generate(expect_value(&mut args, "--profile-generate")?),
+            "--profile-use" => profile = Profile::Use(expect_value(&mut args, "--profile-use")?),
+            "-fsanitize=null" | "--check-null" => null_checks = true,
+            "-g" => debug_info = true,
+            "-W" | "-Wall" => warnings.enabled = true,
+            "-Werror" => warnings = Warnings { enabled: true, as_errors: true },
+            "--timings" | "--timings=text" | "-ftime-report" => timings = Some(Format::Text),
+            "--timings=json" => timings = Some(Format::Json),
+            _ if arg.starts_with("-O") => {
+                let level = arg[2..].parse().map_err(|_| format!("Invalid optimization level: {}", arg))?;
+                optimizations = Optimizations::level(level);
+            }
+            "-nostdlib" | "--freestanding" => freestanding = true,
+            "--preprocess" => preprocess = true,
+            "--cc" => cc = Some(expect_value(&mut args, "--cc")?),
+            "--keep-intermediates" | "-save-temps" => keep_intermediates = true,
+            "--entry" => entry = expect_value(&mut args, "--entry")?,
+            "-fpic" | "-fPIC" | "-fpie" | "-fPIE" => pic = true,
+            "-fno-pic" | "-fno-PIC" | "-fno-pie" | "-fno-PIE" => pic = false,
+            "--target" => target = expect_value(&mut args, "--target")?.parse()?,
+            "-fdiagnostics-color" | "-fdiagnostics-color=always" => color = ColorChoice::Always,
+            "-fdiagnostics-color=never" | "-fno-diagnostics-color" => color = ColorChoice::Never,
+            "-fdiagnostics-color=auto" => color = ColorChoice::Auto,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("Unknown option: {}", arg));
+            }
+            _ => inputs.push(arg),
+        }
+    }
+
+    let output = match (inputs.as_slice(), output) {
+        ([], _) => return Err("No input file given".to_string()),
+        // The counts of every file would go to, or be read from, the same profile
+        _ if profile != Profile::Off && inputs.iter().filter(|input| input.ends_with(".c")).count() > 1 => {
+            return Err("Cannot use --profile-generate or --profile-use with multiple .c files".to_string());
+        }
+        ([_, _, ..], Some(_)) if matches!(stop_after, Stage::Assembly | Stage::Object) => {
+            return Err("Cannot specify -o with -S or -c and multiple input files".to_string());
+        }
+        (_, Some(output)) => output,
+        ([input], None) => default_output(input, stop_after),
+        // Like other compilers, a program linked from several files is called a.out
+        (_, None) => String::from("a.out"),
+    };
+    Ok(Command::Compile(Options {
+        inputs,
+        output,
+        stop_after,
+        freestanding,
+        entry,
+        color,
+        preprocess,
+        target,
+        pic,
+        cc,
+        keep_intermediates,
+        format,
+        dump_ast,
+        optimizations,
+        debug_info,
+        warnings,
+        null_checks,
+        profile,
+        timings,
+    }))
+}
+
+/// Parses the arguments of `scc test`. Every option other than `-j` is checked like an option
+/// of a normal compilation, then passed on to scc for each test.
+///
+/// # Arguments
+///
+/// * `args` - The arguments after `test`.
+///
+/// # Returns
+///
+/// The parsed `Command`, or an `Err` with an error message if the arguments are invalid.
+fn parse_test_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut dir = None;
+    let mut jobs = std::thread::available_parallelism().map_or(1, |jobs| jobs.get());
+    let mut compile_args = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-j" => jobs = parse_jobs(&expect_value(&mut args, "-j")?)?,
+            _ if arg.starts_with("-j") => jobs = parse_jobs(&arg[2..])?,
+            // These options take a value, which must not be taken for the directory
+            "-o" | "--emit" | "--format" | "--cc" | "--entry" | "--target" | "--profile-generate" | "--profile-use" => {
+                let value = expect_value(&mut args, &arg)?;
+                compile_args.extend([arg, value]);
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => compile_args.push(arg),
+            _ if dir.is_none() => dir = Some(arg),
+            _ => return Err(format!("Unexpected argument: {} (scc test takes a single directory)", arg)),
+        }
+    }
+    let dir = dir.ok_or("No test directory given")?;
+
+    let options = match parse_args(compile_args.iter().cloned().chain([String::from("test.c")]))? {
+        Command::Compile(options) => options,
+        _ => return Ok(Command::Help),
+    };
+    if options.stop_after != Stage::Link || compile_args.iter().any(|arg| arg == "-o") {
+        return Err("scc test builds and runs executables, so it cannot stop early or take -o".to_string());
+    }
+    let reference = match options.cc {
+        Some(cc) => cc,
+        None => std::env::var("CC").ok().filter(|cc| !cc.is_empty()).unwrap_or_else(|| String::from("gcc")),
+    };
+    Ok(Command::Test(TestOptions { dir, jobs, reference, compile_args }))
+}
+
+/// Parses the number of tests `-j` runs at once.
+///
+/// # Arguments
+///
+/// * `value` - The value given with `-j`.
+///
+/// # Returns
+///
+/// The number of jobs, or an `Err` if it is not a positive number.
+fn parse_jobs(value: &str) -> Result<usize, String> {
+    value.parse().ok().filter(|&jobs| jobs > 0).ok_or_else(|| format!("Invalid number of jobs: {}", value))
+}
+
+/// Helper function to fetch the value of an option that takes an argument.
+///
+/// # Arguments
+///
+/// * `args` - The remaining arguments.
+/// * `option` - The option whose value is expected, used in the error message.
+///
+/// # Returns
+///
+/// The next argument, or an `Err` if there is none.
+fn expect_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("Missing argument after {}", option))
+}
+
+/// Derives the default output name from the input file by replacing its extension,
+/// so `dir/prog.c` is compiled to `dir/prog`, or to `dir/prog.s` with `-S` and
+/// `dir/prog.o` with `-c`.
+///
+/// # Arguments
+///
+/// * `input` - The path of the input file.
+/// * `stop_after` - The last stage that will run.
+///
+/// # Returns
+///
+/// The path of the output file.
+pub fn default_output(input: &str, stop_after: Stage) -> String {
+    let path = Path::new(input);
+    match (stop_after, path.extension()) {
+        (Stage::Assembly, _) => path.with_extension("s").to_string_lossy().into_owned(),
+        (Stage::Object, _) => path.with_extension("o").to_string_lossy().into_owned(),
+        (_, Some(_)) => path.with_extension("").to_string_lossy().into_owned(),
+        (_, None) => format!("{}.out", input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scc::{Arch,Os};
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_input_with_default_output() {
+        let result = parse_args(args(&["dir/prog.c"]));
+        assert_eq!(result, Ok(Command::Compile(Options {
+            inputs: vec!["dir/prog.c".to_string()],
+            output: "dir/prog".to_string(),
+            stop_after: Stage::Link,
+            freestanding: false,
+            entry: "_start".to_string(),
+            color: ColorChoice::Auto,
+            preprocess: false,
+            target: Target::host(),
+            pic: true,
+            cc: None,
+            keep_intermediates: false,
+            format: Format::Text,
+            dump_ast: false,
+            optimizations: Optimizations::default(),
+            debug_info: false,
+            warnings: Warnings::default(),
+            null_checks: false,
+            profile: Profile::Off,
+            timings: None,
+        })));
+    }
+
+    #[test]
+    fn test_all_options() {
+        let result = parse_args(args(&[
+            "-o", "exe", "--check", "-nostdlib", "--entry", "begin", "-fno-diagnostics-color", "--preprocess",
+            "--target", "aarch64-linux", "-fno-pie", "--cc", "clang", "-save-temps", "--format", "json", "--eliminate-dead-stores", "-g",
+            "-fsanitize=null", "-W", "--timings=json", "prog.c",
+        ]));
+        assert_eq!(result, Ok(Command::Compile(Options {
+            inputs: vec!["prog.c".to_string()],
+            output: "exe".to_string(),
+            stop_after: Stage::Check,
+            freestanding: true,
+            entry: "begin".to_string(),
+            color: ColorChoice::Never,
+            preprocess: true,
+            target: Target { arch: Arch::Aarch64, os: Os::Linux },
+            pic: false,
+            cc: Some("clang".to_string()),
+            keep_intermediates: true,
+            format: Format::Json,
+            dump_ast: false,
+            optimizations: Optimizations { eliminate_dead_stores: true, ..Optimizations::default() },
+            debug_info: true,
+            warnings: Warnings { enabled: true, as_errors: false },
+            null_checks: true,
+            profile: Profile::Off,
+            timings: Some(Format::Json),
+        })));
+    }
+
+    #[test]
+    fn test_warning_flags() {
+        let warnings = |list: &[&str]| match parse_args(args(list)) {
+            Ok(Command::Compile(options)) => options.warnings,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(warnings(&["prog.c"]), Warnings::default());
+        assert_eq!(warnings(&["-Wall", "prog.c"]), Warnings { enabled: true, as_errors: false });
+        assert_eq!(warnings(&["-Werror", "prog.c"]), Warnings { enabled: true, as_errors: true });
+        assert_eq!(warnings(&["-Werror", "-W", "prog.c"]), Warnings { enabled: true, as_errors: true });
+    }
+
+    #[test]
+    fn test_timings_flags() {
+        let timings = |list: &[&str]| match parse_args(args(list)) {
+            Ok(Command::Compile(options)) => options.timings,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(timings(&["prog.c"]), None);
+        assert_eq!(timings(&["--timings", "prog.c"]), Some(Format::Text));
+        assert_eq!(timings(&["-ftime-report", "prog.c"]), Some(Format::Text));
+        assert_eq!(timings(&["--timings=json", "prog.c"]), Some(Format::Json));
+        assert_eq!(parse_args(args(&["--timings=csv", "prog.c"])), Err("Unknown option: --timings=csv".to_string()));
+    }
+
+    #[test]
+    fn test_profile_flags() {
+        let profile = |list: &[&str]| match parse_args(args(list)) {
+            Ok(Command::Compile(options)) => options.profile,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(profile(&["prog.c"]), Profile::Off);
+        assert_eq!(profile(&["--profile-generate", "prog.prof", "prog.c"]), Profile::Generate("prog.prof".to_string()));
+        assert_eq!(profile(&["--profile-use", "prog.prof", "prog.c", "lib.o"]), Profile::Use("prog.prof".to_string()));
+        assert_eq!(
+            parse_args(args(&["--profile-use", "prog.prof", "a.c", "b.c"])),
+            Err("Cannot use --profile-generate or --profile-use with multiple .c files".to_string())
+        );
+        assert_eq!(parse_args(args(&["prog.c", "--profile-use"])), Err("Missing argument after --profile-use".to_string()));
+    }
+
+    #[test]
+    fn test_stop_stages() {
+        let stop_after = |flag: &str| match parse_args(args(&[flag, "dir/prog.c"])) {
+            Ok(Command::Compile(options)) => (options.stop_after, options.output),
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(stop_after("--lex"), (Stage::Lex, "dir/prog".to_string()));
+        assert_eq!(stop_after("--parse"), (Stage::Parse, "dir/prog".to_string()));
+        assert_eq!(stop_after("--tacky"), (Stage::Ir, "dir/prog".to_string()));
+        assert_eq!(stop_after("--codegen"), (Stage::Codegen, "dir/prog".to_string()));
+        assert_eq!(stop_after("-S"), (Stage::Assembly, "dir/prog.s".to_string()));
+        assert_eq!(stop_after("-c"), (Stage::Object, "dir/prog.o".to_string()));
+        let emit = |stage: &str| match parse_args(args(&["--emit", stage, "prog.c"])) {
+            Ok(Command::Compile(options)) => options.stop_after,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(emit("tokens"), Stage::Lex);
+        assert_eq!(emit("ast"), Stage::Parse);
+        assert_eq!(emit("ir"), Stage::Ir);
+        assert_eq!(emit("asm"), Stage::Codegen);
+        assert_eq!(emit("llvm"), Stage::Llvm);
+        match parse_args(args(&["--dump-ast", "prog.c"])) {
+            Ok(Command::Compile(options)) => assert_eq!((options.stop_after, options.dump_ast), (Stage::Parse, true)),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_multiple_inputs() {
+        let options = |list: &[&str]| match parse_args(args(list)) {
+            Ok(Command::Compile(options)) => (options.inputs, options.output),
+            other => panic!("Unexpected result {:?}", other),
+        };
+        let inputs = vec!["a.c".to_string(), "lib/b.c".to_string(), "c.o".to_string()];
+        assert_eq!(options(&["a.c", "lib/b.c", "c.o"]), (inputs.clone(), "a.out".to_string()));
+        assert_eq!(options(&["a.c", "lib/b.c", "c.o", "-o", "prog"]), (inputs, "prog".to_string()));
+    }
+
+    #[test]
+    fn test_optimization_levels() {
+        let optimizations = |flag: &str| match parse_args(args(&[flag, "prog.c"])) {
+            Ok(Command::Compile(options)) => options.optimizations,
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(optimizations("-O0"), Optimizations::default());
+        assert_eq!(optimizations("-O"), Optimizations::level(1));
+        assert_eq!(optimizations("-O2"), Optimizations::level(1));
+        assert_eq!(optimizations("--fold-constants"), Optimizations { fold_constants: true, ..Optimizations::default() });
+        assert_eq!(parse_args(args(&["-Ofast", "prog.c"])), Err("Invalid optimization level: -Ofast".to_string()));
+    }
+
+    #[test]
+    fn test_test_command() {
+        let result = parse_args(args(&["test", "-j", "3", "-O", "--cc", "clang", "--target", "x86_64-linux", "tests"]));
+        assert_eq!(result, Ok(Command::Test(TestOptions {
+            dir: "tests".to_string(),
+            jobs: 3,
+            reference: "clang".to_string(),
+            compile_args: args(&["-O", "--cc", "clang", "--target", "x86_64-linux"]),
+        })));
+        match parse_args(args(&["test", "-j2", "tests"])) {
+            Ok(Command::Test(options)) => assert_eq!((options.jobs, options.compile_args), (2, vec![])),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(parse_args(args(&["test"])), Err("No test directory given".to_string()));
+        assert_eq!(parse_args(args(&["test", "-j0", "tests"])), Err("Invalid number of jobs: 0".to_string()));
+        assert_eq!(parse_args(args(&["test", "a", "b"])), Err("Unexpected argument: b (scc test takes a single directory)".to_string()));
+        assert_eq!(parse_args(args(&["test", "--bogus", "tests"])), Err("Unknown option: --bogus".to_string()));
+        assert_eq!(
+            parse_args(args(&["test", "-S", "tests"])),
+            Err("scc test builds and runs executables, so it cannot stop early or take -o".to_string())
+        );
+        // Outside the first position, test is the name of an input
+        match parse_args(args(&["-O", "test"])) {
+            Ok(Command::Compile(options)) => assert_eq!(options.inputs, ["test"]),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(parse_args(args(&["prog.c", "--help"])), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_args(args(&[])), Err("No input file given".to_string()));
+        assert_eq!(parse_args(args(&["prog.c", "-o"])), Err("Missing argument after -o".to_string()));
+        assert_eq!(parse_args(args(&["--bogus", "prog.c"])), Err("Unknown option: --bogus".to_string()));
+        assert_eq!(
+            parse_args(args(&["--target", "mips", "prog.c"])),
+            Err("Unknown target: mips (expected x86_64-linux, aarch64-linux, x86_64-macos or aarch64-macos)".to_string())
+        );
+        assert_eq!(
+            parse_args(args(&["-c", "a.c", "b.c", "-o", "x.o"])),
+            Err("Cannot specify -o with -S or -c and multiple input files".to_string())
+        );
+        assert_eq!(
+            parse_args(args(&["--emit", "bytes", "prog.c"])),
+            Err("Unknown --emit stage: bytes (expected tokens, ast, ir, asm or llvm)".to_string())
+        );
+        assert_eq!(
+            parse_args(args(&["--format", "xml", "prog.c"])),
+            Err("Unknown --format: xml (expected text or json)".to_string())
+        );
+    }
+}
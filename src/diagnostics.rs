@@ -0,0 +1,194 @@
+use std::fmt;
+use crate::ast::Span;
+
+/// How serious a diagnostic is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    /// Code that is valid but likely a mistake, which does not stop the compilation.
+    Warning,
+}
+
+/// A message about the program being compiled, usually tied to a position in its source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is, or `None` if it cannot be pinned to a position, as when
+    /// the input ends too early or an external tool fails.
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// Creates an error pointing at `span`.
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Error, message: message.into(), span: Some(span) }
+    }
+
+    /// Creates an error that is not tied to a position in the source.
+    pub fn error_without_span(message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Error, message: message.into(), span: None }
+    }
+
+    /// Creates a warning pointing at `span`.
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Warning, message: message.into(), span: Some(span) }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}: {}", span, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Every diagnostic reported by a stage that keeps going after the first problem it finds.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// Turns every diagnostic into an error, as `-Werror` does with warnings.
+    pub fn into_errors(self) -> Self {
+        Diagnostics(self.0.into_iter().map(|diagnostic| Diagnostic { severity: Severity::Error, ..diagnostic }).collect())
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Diagnostics(vec![diagnostic])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const GREEN: &str = "\x1b[1;32m";
+const MAGENTA: &str = "\x1b[1;35m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders a diagnostic the way C compilers print them: the location and message, then the
+/// offending source line with a caret under the position of the problem.
+///
+/// # Arguments
+///
+/// * `diagnostic` - The diagnostic to be rendered.
+/// * `file_name` - The name of the source file, printed in front of the position.
+/// * `source` - The contents of the source file.
+/// * `color` - Whether to highlight the output with ANSI escape codes.
+///
+/// # Returns
+///
+/// * `String` - The rendered diagnostic, ending with a newline.
+pub fn render(diagnostic: &Diagnostic, file_name: &str, source: &str, color: bool) -> String {
+    let paint = |style: &str, text: &str| {
+        if color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+    let (severity, severity_style) = match diagnostic.severity {
+        Severity::Error => ("error", RED),
+        Severity::Warning => ("warning", MAGENTA),
+    };
+    let location = match diagnostic.span {
+        Some(span) => format!("{}:{}:", file_name, span),
+        None => format!("{}:", file_name),
+    };
+    let mut output = format!(
+        "{} {} {}\n",
+        paint(BOLD, &location),
+        paint(severity_style, &format!("{}:", severity)),
+        paint(BOLD, &diagnostic.message)
+    );
+
+    let Some(span) = diagnostic.span else {
+        return output;
+    };
+    let Some(line) = source.lines().nth(span.line.saturating_sub(1)) else {
+        return output;
+    };
+    // Tabs are kept in the padding so the caret lines up however wide they are displayed
+    let padding: String = line
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(span.line.to_string().len());
+    output.push_str(&format!(" {} | {}\n", span.line, line));
+    output.push_str(&format!(" {} | {}{}\n", gutter, padding, paint(GREEN, "^")));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_with_caret() {
+        let source = "int main(void) {\n\treturn y;\n}\n";
+        let diagnostic = Diagnostic::error(Span { line: 2, column: 9 }, "Use of undeclared variable 'y'");
+        let expected = "\
+prog.c:2:9: error: Use of undeclared variable 'y'
+ 2 | \treturn y;
+   | \t       ^
+";
+        assert_eq!(render(&diagnostic, "prog.c", source, false), expected);
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic = Diagnostic::error_without_span("Expected Semicolon, but found end of input");
+        assert_eq!(
+            render(&diagnostic, "prog.c", "int main(void) { return 0 }", false),
+            "prog.c: error: Expected Semicolon, but found end of input\n"
+        );
+    }
+
+    #[test]
+    fn test_render_with_color() {
+        let diagnostic = Diagnostic::error(Span { line: 1, column: 1 }, "Bad");
+        let rendered = render(&diagnostic, "a.c", "x", true);
+        assert!(rendered.starts_with("\x1b[1ma.c:1:1:\x1b[0m \x1b[1;31merror:\x1b[0m \x1b[1mBad\x1b[0m\n"));
+        assert!(rendered.ends_with(" | \x1b[1;32m^\x1b[0m\n"));
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let diagnostic = Diagnostic::warning(Span { line: 1, column: 5 }, "Variable 'x' is never read");
+        let rendered = render(&diagnostic, "a.c", "int x;", true);
+        assert!(rendered.starts_with("\x1b[1ma.c:1:5:\x1b[0m \x1b[1;35mwarning:\x1b[0m "));
+        let errors = Diagnostics::from(diagnostic).into_errors();
+        assert!(render(&errors.0[0], "a.c", "int x;", false).starts_with("a.c:1:5: error: Variable 'x' is never read\n"));
+    }
+
+    #[test]
+    fn test_display() {
+        let diagnostic = Diagnostic::error(Span { line: 3, column: 4 }, "Oops");
+        assert_eq!(diagnostic.to_string(), "3:4: Oops");
+        assert_eq!(Diagnostic::error_without_span("Oops").to_string(), "Oops");
+    }
+
+    #[test]
+    fn test_display_several() {
+        let diagnostics = Diagnostics(vec![
+            Diagnostic::error(Span { line: 1, column: 2 }, "First"),
+            Diagnostic::error_without_span("Second"),
+        ]);
+        assert_eq!(diagnostics.to_string(), "1:2: First\nSecond");
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex,OnceLock};
+use serde::{Serialize,Serializer};
+
+/// An interned identifier: the name of a variable, function, structure tag, member, label or
+/// assembly symbol. Each distinct string is stored once for the life of the process and
+/// names are small integer IDs, so copying, comparing and hashing them never touches the
+/// text, and reading the text takes no lock.
+///
+/// Names order by their text, not by their IDs. The IDs depend on everything the process
+/// interned before, such as the other files on the command line, while maps keyed by names
+/// must list them the same way whatever else was compiled. Like `String`, a name
+/// dereferences to `str`, is printed with quotes by `Debug`, which keeps the AST and IR
+/// dumps unchanged, and is serialized as its text.
+///
+/// Interned strings are never freed, so that they can be handed out as `&'static str`. The
+/// memory this keeps is bounded by the number of distinct names, which is at most
+/// `MAX_NAMES`: the temporaries and labels each compilation makes up are numbered from zero
+/// again, so compiling many files in one process only adds the identifiers they declare.
+/// Once the table is full, compiling a program with a new name fails with an error.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Name(u32);
+
+/// The number of names in each slab of the table of texts.
+const SLAB_SIZE: usize = 1 << 12;
+/// The number of slabs, which bounds the number of names.
+const SLAB_COUNT: usize = 1 << 12;
+/// The most names the process can intern.
+pub const MAX_NAMES: usize = SLAB_SIZE * SLAB_COUNT;
+
+/// The text of every name, indexed by its ID. Slabs are allocated as they fill up and each
+/// slot is written once, before the ID is handed out, so reading one needs no lock.
+static TEXTS: [OnceLock<Box<[OnceLock<&'static str>]>>; SLAB_COUNT] = [const { OnceLock::new() }; SLAB_COUNT];
+
+/// The payload `Name::new` unwinds with when `MAX_NAMES` names have been interned, which the
+/// compiler's entry points catch and report as an error.
+#[derive(Debug)]
+pub struct NamesExhausted;
+
+/// Maps every interned string to its name. Only interning a string takes this lock.
+fn ids() -> &'static Mutex<HashMap<&'static str, Name>> {
+    static IDS: OnceLock<Mutex<HashMap<&'static str, Name>>> = OnceLock::new();
+    IDS.get_or_init(Mutex::default)
+}
+
+impl Name {
+    /// Returns the name for a string, interning it the first time it is seen.
+    ///
+    /// # Panics
+    ///
+    /// If the string is new and `MAX_NAMES` names have already been interned, this unwinds
+    /// with `NamesExhausted`, without running the panic hook. `compile_to` and the functions
+    /// built on it catch that and return an error diagnostic instead.
+    pub fn new(name: &str) -> Self {
+        intern(name, MAX_NAMES)
+    }
+
+    /// Returns the text of the name.
+    pub fn as_str(self) -> &'static str {
+        let index = self.0 as usize;
+        TEXTS[index / SLAB_SIZE]
+            .get()
+            .and_then(|slab| slab[index % SLAB_SIZE].get())
+            .expect("a name's text is stored before its ID is handed out")
+    }
+
+    /// Returns the name as it is written in the source. Resolution makes the names of local
+    /// variables and structure tags unique by appending a `.` and a number, and identifiers
+    /// cannot contain a `.`, so this is the text before the last one.
+    pub fn source_name(self) -> &'static str {
+        let name = self.as_str();
+        name.rsplit_once('.').map_or(name, |(name, _)| name)
+    }
+}
+
+/// Returns the name for a string, interning it if it is new and fewer than `limit` names
+/// have been interned, and unwinding with `NamesExhausted` otherwise.
+fn intern(name: &str, limit: usize) -> Name {
+    let mut ids = ids().lock().unwrap();
+    if let Some(&id) = ids.get(name) {
+        return id;
+    }
+    let index = ids.len();
+    if index >= limit {
+        // Released first, so that the lock is not poisoned for whoever catches this
+        drop(ids);
+        std::panic::resume_unwind(Box::new(NamesExhausted));
+    }
+    let text: &'static str = Box::leak(name.into());
+    let slab = TEXTS[index / SLAB_SIZE].get_or_init(|| (0..SLAB_SIZE).map(|_| OnceLock::new()).collect());
+    slab[index % SLAB_SIZE].set(text).expect("each ID is handed out once");
+    let id = Name(index as u32);
+    ids.insert(text, id);
+    id
+}
+
+/// The empty name, which stands in for a label the loop labeling pass has yet to assign.
+impl Default for Name {
+    fn default() -> Self {
+        Name::new("")
+    }
+}
+
+impl std::ops::Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Name::new(name)
+    }
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Self {
+        Name::new(&name)
+    }
+}
+
+impl From<&String> for Name {
+    fn from(name: &String) -> Self {
+        Name::new(name)
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if self == other {
+            return std::cmp::Ordering::Equal;
+        }
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning() {
+        let name = Name::new("counter");
+        assert_eq!(name, Name::from("counter".to_string()));
+        assert_ne!(name, Name::new("counter.0"));
+        assert_eq!(name.as_str(), "counter");
+        assert_eq!(name, "counter");
+        assert!(name.starts_with("count"));
+        assert_eq!(format!("{} {:?}", name, name), "counter \"counter\"");
+        assert_eq!(Name::new("counter.12").source_name(), "counter");
+        assert_eq!(name.source_name(), "counter");
+    }
+
+    #[test]
+    fn test_interning_from_several_threads() {
+        let names: Vec<Vec<Name>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| (0..100).map(|i| Name::new(&format!("thread.{}", i))).collect()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert!(names.iter().all(|other| *other == names[0]));
+        for (i, name) in names[0].iter().enumerate() {
+            assert_eq!(name.as_str(), format!("thread.{}", i));
+        }
+    }
+
+    #[test]
+    fn test_exhausted_names() {
+        let counter = Name::new("counter");
+        // Names already interned are still found once no more fit
+        assert_eq!(intern("counter", 0), counter);
+        let payload = std::panic::catch_unwind(|| intern("exhausted.name", 0)).unwrap_err();
+        assert!(payload.is::<NamesExhausted>());
+        assert_eq!(Name::new("exhausted.name"), "exhausted.name");
+    }
+
+    #[test]
+    fn test_order_follows_text() {
+        let (late, early) = (Name::new("zz.order"), Name::new("aa.order"));
+        assert!(early < late);
+        let mut names = vec![late, early, Name::new("mm.order")];
+        names.sort();
+        assert_eq!(names, vec!["aa.order", "mm.order", "zz.order"]);
+    }
+}
@@ -0,0 +1,1849 @@
+use std::collections::{BTreeMap, HashSet};
+use serde::Serialize;
+use crate::ast::*;
+use crate::typecheck::{builtin_type, common_type, is_arithmetic, object_type_of, type_of, InitialValue, Symbol, SymbolTable};
+
+/// The function a null pointer check calls when the pointer is null. It prints the message
+/// it is given, the source position of the dereference, and ends the program with status 1.
+pub const NULL_DEREFERENCE: &str = "__scc_null_dereference";
+
+// ---Define the structure for the three-address intermediate representation----
+#[derive(Debug, Serialize)]
+pub struct IrProgram {
+    pub functions: Vec<IrFunction>,
+    pub static_variables: Vec<IrStaticVariable>,
+    /// Variables declared `extern` that are defined in another file.
+    pub extern_variables: Vec<Name>,
+    /// The string literals, which functions refer to like static variables but may not change.
+    pub static_constants: Vec<IrStaticConstant>,
+    /// The type of every variable and temporary the functions use, and of every variable with
+    /// static storage.
+    pub types: BTreeMap<Name, Type>,
+}
+#[derive(Debug, Serialize)]
+pub struct IrFunction {
+    pub name: Name,
+    /// Whether the function has external linkage, rather than being declared `static`.
+    pub global: bool,
+    /// The position of the function's name, which debug information maps its prologue to.
+    pub span: Span,
+    pub params: Vec<Name>,
+    pub body: Vec<IrInstruction>,
+}
+/// A variable with static storage, which exists for the whole run of the program. Functions
+/// refer to it through an `IrValue::Var` with its name.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct IrStaticVariable {
+    pub name: Name,
+    /// Whether the variable has external linkage, rather than being declared `static`.
+    pub global: bool,
+    /// The alignment in bytes, that of the type of the variable.
+    pub alignment: usize,
+    pub init: Vec<StaticInit>,
+}
+
+/// An object with static storage whose value never changes, such as a string literal,
+/// placed in read-only data.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct IrStaticConstant {
+    pub name: Name,
+    pub alignment: usize,
+    pub init: StaticInit,
+}
+
+impl IrProgram {
+    /// Returns the names of every variable with static storage the functions may refer to,
+    /// whether it is defined in this file or another.
+    pub fn static_names(&self) -> HashSet<Name> {
+        self.static_variables
+            .iter()
+            .map(|variable| variable.name)
+            .chain(self.extern_variables.iter().copied())
+            .collect()
+    }
+
+    /// Returns the number of instructions in the bodies of all of the functions.
+    pub fn instruction_count(&self) -> usize {
+        self.functions.iter().map(|function| function.body.len()).sum()
+    }
+}
+
+/// Returns the type of an IR value.
+///
+/// # Arguments
+///
+/// * `value` - The value.
+/// * `types` - The types of the variables of the program. Variables missing from it are
+///   taken to be `int`.
+///
+/// # Returns
+///
+/// * `Type` - The type of the value.
+pub fn value_type(value: &IrValue, types: &BTreeMap<Name, Type>) -> Type {
+    match value {
+        IrValue::Constant(constant) => constant.ty(),
+        IrValue::Var(name) => types.get(name).cloned().unwrap_or(Type::Int),
+    }
+}
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum IrInstruction {
+    Return(IrValue),
+    /// Stops the program abnormally. Control never continues past it.
+    Trap,
+    Copy { src: IrValue, dst: IrValue },
+    /// Widens a signed 4-byte value to 8 bytes, preserving its value.
+    SignExtend { src: IrValue, dst: IrValue },
+    /// Widens an unsigned 4-byte value to 8 bytes, filling the upper half with zeros.
+    ZeroExtend { src: IrValue, dst: IrValue },
+    /// Narrows an 8-byte value to 4 bytes, keeping its low 32 bits.
+    Truncate { src: IrValue, dst: IrValue },
+    /// Converts a double to a signed integer of the type of `dst`, rounding toward zero.
+    DoubleToInt { src: IrValue, dst: IrValue },
+    /// Converts a double to an unsigned integer of the type of `dst`, rounding toward zero.
+    DoubleToUInt { src: IrValue, dst: IrValue },
+    /// Converts a signed integer to a double.
+    IntToDouble { src: IrValue, dst: IrValue },
+    /// Converts an unsigned integer to a double.
+    UIntToDouble { src: IrValue, dst: IrValue },
+    Unary { op: UnaryOperator, src: IrValue, dst: IrValue },
+    Binary { op: BinaryOperator, src1: IrValue, src2: IrValue, dst: IrValue },
+    Jump(Name),
+    JumpIfZero(IrValue, Name),
+    JumpIfNotZero(IrValue, Name),
+    Label(Name),
+    FunCall { name: Name, args: Vec<IrValue>, dst: IrValue },
+    /// Asks the kernel directly for `call`, storing its `long` result in `dst`: the value it
+    /// returns, or minus the error number if it fails. Code the compiler adds to a program
+    /// uses it instead of calling libc, which the program may not link with, and whose
+    /// function names the program may have taken for its own.
+    SystemCall { call: SystemCall, args: Vec<IrValue>, dst: IrValue },
+    /// Stores the address of the variable `src` in `dst`.
+    GetAddress { src: IrValue, dst: IrValue },
+    /// Moves the stack pointer down by the `unsigned long` `size` bytes, rounded up to keep
+    /// it aligned, and stores the address of the block it frees up in `dst`. The block stays
+    /// allocated until the function returns.
+    Alloca { size: IrValue, dst: IrValue },
+    /// Copies the value `src_ptr` points to into `dst`.
+    Load { src_ptr: IrValue, dst: IrValue },
+    /// Copies `src` into the object `dst_ptr` points to.
+    Store { src: IrValue, dst_ptr: IrValue },
+    /// Copies the value of the atomic object `src_ptr` points to into `dst`, as one
+    /// sequentially consistent access.
+    AtomicLoad { src_ptr: IrValue, dst: IrValue },
+    /// Copies `src` into the atomic object `dst_ptr` points to, as one sequentially
+    /// consistent access.
+    AtomicStore { src: IrValue, dst_ptr: IrValue },
+    /// Replaces the value of the atomic object `ptr` points to with `desired` if it equals
+    /// `expected`, as one sequentially consistent access, and sets the `int` `dst` to 1 if
+    /// it did and 0 otherwise.
+    CompareExchange { ptr: IrValue, expected: IrValue, desired: IrValue, dst: IrValue },
+    /// Stores in `dst` the pointer `ptr` moved by `index` elements of `scale` bytes each.
+    /// `index` is a `long`, and negative to move backward.
+    AddPtr { ptr: IrValue, index: IrValue, scale: usize, dst: IrValue },
+    /// Copies `src` into the variable `dst` at `offset` bytes from its start, such as an
+    /// element of an array being initialized.
+    CopyToOffset { src: IrValue, dst: Name, offset: usize },
+    /// Marks the instructions that follow as coming from the source position `span`, for the
+    /// line-number debug information. Only emitted when debug information is requested.
+    Location(Span),
+}
+/// A system call the IR can make, whose number and arguments each backend works out for the
+/// operating system it generates code for.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub enum SystemCall {
+    /// Writes the `unsigned long` number of bytes the pointer points to to the `int` file
+    /// descriptor, like `write`.
+    Write,
+    /// Opens the file at the path the pointer points to for writing, creating it if it does
+    /// not exist and emptying it if it does, and returns its file descriptor, like `creat`.
+    Create,
+    /// Closes the `int` file descriptor, like `close`.
+    Close,
+    /// Ends the process with the `int` status. It does not return.
+    Exit,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize)]
+pub enum IrValue {
+    Constant(Constant),
+    Var(Name),
+}
+
+/// Lowers a resolved C AST to the intermediate representation. Only function definitions are
+/// lowered; declarations without a body produce no code. Every function ends with an implicit
+/// `return 0`, which gives `main` its required result when control falls off the end.
+/// The type checker has made every conversion explicit, so each cast becomes a sign extension
+/// or truncation and every other instruction works on operands of one type.
+/// Each variable with static storage defined in this file becomes one static variable, however
+/// often it is declared.
+///
+/// Structures are only ever handled through their addresses: members are read and written
+/// through a pointer to the structure moved by their offset, and a structure is copied a
+/// scalar at a time. The types of the program give structures as arrays of scalars of the
+/// same size and alignment, so later passes need not know about them.
+///
+/// # Arguments
+///
+/// * `ast` - The C AST to be lowered.
+/// * `symbols` - The symbol table built by the type checker, which holds the types of the
+///   variables, the linkage of the functions and the initial values of the variables with
+///   static storage.
+/// * `structs` - The layouts of the structures of the program, worked out by the type checker.
+/// * `debug_info` - Whether to mark the source position of each statement, so the backends
+///   can map the generated code back to source lines.
+/// * `null_checks` - The path of the source file, to check that every pointer is not null
+///   before it is dereferenced and report where it was, or `None` to not check. With checks,
+///   the function `declare_null_checks` adds to the symbol table must be declared.
+///
+/// # Returns
+///
+/// * `IrProgram` - The program as one flat list of three-address instructions per function.
+pub fn generate_ir(
+    ast: Program,
+    symbols: &SymbolTable,
+    structs: &StructTable,
+    debug_info: bool,
+    null_checks: Option<&str>,
+) -> IrProgram {
+    // Labels end up in one assembly file, so the counters are shared by all functions
+    let mut context = LoweringContext {
+        symbols,
+        structs,
+        types: BTreeMap::new(),
+        body: Vec::new(),
+        next_temporary: 0,
+        next_label: 0,
+        debug_info,
+        null_checks,
+        null_messages: Vec::new(),
+    };
+    let mut functions = Vec::new();
+    for declaration in ast.declarations {
+        let Declaration::Function(function) = declaration else { continue };
+        let Some(body) = function.body else { continue };
+        for item in body {
+            context.lower_block_item(item);
+        }
+        let zero = match &function.ty {
+            Type::Function { ret, .. } => Constant::Int(0).convert_to(ret),
+            _ => Constant::Int(0),
+        };
+        context.body.push(IrInstruction::Return(IrValue::Constant(zero)));
+        functions.push(IrFunction {
+            global: symbols.get(&function.name).is_none_or(|symbol| symbol.global),
+            span: function.span,
+            name: function.name,
+            params: function.params,
+            body: std::mem::take(&mut context.body),
+        });
+    }
+
+    if !context.null_messages.is_empty() {
+        functions.push(null_dereference_handler(&mut context.types));
+    }
+
+    // The symbol table is unordered, so sort by name to keep the output stable
+    let mut static_variables = Vec::new();
+    let mut extern_variables = Vec::new();
+    let mut static_constants = std::mem::take(&mut context.null_messages);
+    let mut types: BTreeMap<Name, Type> =
+        context.types.into_iter().map(|(name, ty)| (name, storage_type(&ty, structs))).collect();
+    for (name, symbol) in symbols {
+        if !matches!(symbol.ty, Type::Function { .. }) {
+            types.insert(*name, storage_type(&symbol.ty.unqualified(), structs));
+        }
+        let alignment = symbol.ty.alignment_in(structs);
+        match &symbol.initial_value {
+            Some(InitialValue::Initial(init)) => {
+                let init = init.clone();
+                static_variables.push(IrStaticVariable { name: *name, global: symbol.global, alignment, init });
+            }
+            Some(InitialValue::Tentative) => {
+                let init = vec![StaticInit::Zero(symbol.ty.size_in(structs))];
+                static_variables.push(IrStaticVariable { name: *name, global: symbol.global, alignment, init });
+            }
+            Some(InitialValue::NoInitializer) => extern_variables.push(*name),
+            Some(InitialValue::Constant(init)) => {
+                static_constants.push(IrStaticConstant { name: *name, alignment, init: init.clone() });
+            }
+            None => {}
+        }
+    }
+    static_variables.sort_by_key(|a| a.name);
+    extern_variables.sort();
+    static_constants.sort_by_key(|a| a.name);
+    IrProgram { functions, static_variables, extern_variables, static_constants, types }
+}
+
+/// Returns whether a pointer may be null, which the address of an object or of the first
+/// element of an array never is.
+fn may_be_null(ptr: &Exp, symbols: &SymbolTable) -> bool {
+    match ptr {
+        Exp::AddressOf(..) => false,
+        Exp::Cast(_, operand, _) => !matches!(type_of(operand, symbols), Type::Array(..)),
+        _ => true,
+    }
+}
+
+/// Returns the function null pointer checks call, which writes the message it is given to
+/// standard error and exits with status 1, and adds the types of its variables. It makes
+/// system calls rather than calling libc, so it works without libc and whatever functions
+/// the program defines.
+fn null_dereference_handler(types: &mut BTreeMap<Name, Type>) -> IrFunction {
+    let (message, length) = (Name::from("null_dereference.message"), Name::from("null_dereference.length"));
+    types.insert(message, Type::Pointer(Box::new(Type::Char)));
+    types.insert(length, Type::ULong);
+    types.insert("null_dereference.result".into(), Type::Long);
+    let args = vec![IrValue::Constant(Constant::Int(2)), IrValue::Var(message), IrValue::Var(length)];
+    IrFunction {
+        name: NULL_DEREFERENCE.into(),
+        global: false,
+        span: Span::default(),
+        params: vec![message, length],
+        body: vec![
+            IrInstruction::SystemCall { call: SystemCall::Write, args, dst: IrValue::Var("null_dereference.result".into()) },
+            IrInstruction::SystemCall {
+                call: SystemCall::Exit,
+                args: vec![IrValue::Constant(Constant::Int(1))],
+                dst: IrValue::Var("null_dereference.result".into()),
+            },
+            // Exiting does not return, but nothing tells the backends so
+            IrInstruction::Trap,
+        ],
+    }
+}
+
+/// Adds the function null pointer checks call, which reports the dereference, to a symbol
+/// table. It calls no other function.
+///
+/// # Arguments
+///
+/// * `symbols` - The symbol table of a program lowered with null pointer checks.
+pub fn declare_null_checks(symbols: &mut SymbolTable) {
+    let ty = Type::Function { params: vec![Type::Pointer(Box::new(Type::Char)), Type::ULong], ret: Box::new(Type::Int) };
+    symbols.insert(NULL_DEREFERENCE.into(), Symbol { ty, defined: true, initial_value: None, global: false });
+}
+
+/// Returns the type later passes see for a variable of the given type: an array of scalars
+/// as large and as aligned as the variable if it is a structure or an array of them, and the
+/// type itself otherwise.
+///
+/// # Arguments
+///
+/// * `ty` - The type of the variable.
+/// * `structs` - The layouts of the structures of the program.
+///
+/// # Returns
+///
+/// * `Type` - The type without structures.
+fn storage_type(ty: &Type, structs: &StructTable) -> Type {
+    if !ty.contains_struct() {
+        return ty.clone();
+    }
+    let alignment = ty.alignment_in(structs);
+    Type::Array(Box::new(scalar_of_size(alignment)), ty.size_in(structs) / alignment)
+}
+
+/// Returns the integer type of the given size, 1, 4 or 8 bytes, which are the alignments
+/// structures can have.
+fn scalar_of_size(size: usize) -> Type {
+    match size {
+        1 => Type::Char,
+        4 => Type::Int,
+        _ => Type::Long,
+    }
+}
+
+/// An object that an expression designates, which can be read, assigned to or have its
+/// address taken.
+enum Lvalue {
+    Variable(Name),
+    /// The object a pointer points to, with the value of the pointer.
+    Dereferenced(IrValue),
+    /// An atomic object, variable or not, with a pointer to it. It is only ever accessed
+    /// through the pointer, with atomic instructions.
+    Atomic(IrValue),
+}
+
+/// State shared while lowering the functions of a program.
+struct LoweringContext<'a> {
+    symbols: &'a SymbolTable,
+    structs: &'a StructTable,
+    /// The types of the temporaries created so far.
+    types: BTreeMap<Name, Type>,
+    body: Vec<IrInstruction>,
+    next_temporary: usize,
+    next_label: usize,
+    /// Whether to mark the source position of each statement with an `IrInstruction::Location`.
+    debug_info: bool,
+    /// The path of the source file, if pointers are checked before they are dereferenced.
+    null_checks: Option<&'a str>,
+    /// The messages the null pointer checks so far report, one per dereference.
+    null_messages: Vec<IrStaticConstant>,
+}
+
+impl LoweringContext<'_> {
+    /// Creates a fresh temporary variable of the given type, unique within the program.
+    fn make_temporary(&mut self, ty: Type) -> IrValue {
+        let name = Name::from(format!("tmp.{}", self.next_temporary));
+        self.next_temporary += 1;
+        self.types.insert(name, ty);
+        IrValue::Var(name)
+    }
+
+    /// Appends the instruction converting `src` from one type to another, and returns the
+    /// value holding the result. Values that already have the type are returned unchanged,
+    /// and a conversion between integer types of the same size is a plain copy of the bits.
+    fn convert(&mut self, src: IrValue, from: &Type, to: &Type) -> IrValue {
+        if from == to {
+            return src;
+        }
+        let dst = self.make_temporary(to.clone());
+        let instruction = if *to == Type::Double {
+            if from.is_signed() {
+                IrInstruction::IntToDouble { src, dst: dst.clone() }
+            } else {
+                IrInstruction::UIntToDouble { src, dst: dst.clone() }
+            }
+        } else if *from == Type::Double {
+            if to.is_signed() {
+                IrInstruction::DoubleToInt { src, dst: dst.clone() }
+            } else {
+                IrInstruction::DoubleToUInt { src, dst: dst.clone() }
+            }
+        } else if from.size() == to.size() {
+            IrInstruction::Copy { src, dst: dst.clone() }
+        } else if from.size() > to.size() {
+            IrInstruction::Truncate { src, dst: dst.clone() }
+        } else if from.is_signed() {
+            IrInstruction::SignExtend { src, dst: dst.clone() }
+        } else {
+            IrInstruction::ZeroExtend { src, dst: dst.clone() }
+        };
+        self.body.push(instruction);
+        dst
+    }
+
+    /// Creates a fresh label id, unique within the program. Labels built from the same id
+    /// belong to the same construct, like `else.3` and `end.3`.
+    fn make_label_id(&mut self) -> usize {
+        self.next_label += 1;
+        self.next_label - 1
+    }
+
+    /// Appends the instructions for a declaration's initializer or a statement.
+    fn lower_block_item(&mut self, item: BlockItem) {
+        match item {
+            BlockItem::Declaration(Declaration::Variable(declaration)) => self.lower_declaration(declaration),
+            // Local function and structure declarations only make a name visible
+            BlockItem::Declaration(Declaration::Function(_) | Declaration::Struct(_)) => {}
+            BlockItem::Statement(statement) => self.lower_statement(statement),
+        }
+    }
+
+    /// Appends the instructions initializing a variable, if it has an initializer. Local
+    /// `static` and `extern` declarations produce no code, since variables with static
+    /// storage are initialized before the program starts.
+    fn lower_declaration(&mut self, declaration: VarDecl) {
+        if declaration.storage_class.is_some() {
+            return;
+        }
+        if declaration.init.is_some() {
+            self.locate(declaration.span);
+        }
+        match declaration.init {
+            Some(Initializer::Single(exp)) if !matches!(exp, Exp::String(..) | Exp::WideString(..)) && !declaration.ty.contains_struct() => {
+                let src = self.lower_expression(exp);
+                self.body.push(IrInstruction::Copy { src, dst: IrValue::Var(declaration.name) });
+            }
+            Some(init) => self.lower_initializer(init, declaration.name, &declaration.ty, 0),
+            None => {}
+        }
+    }
+
+    /// Appends the instructions storing each scalar of an initializer in the variable `name`,
+    /// starting `offset` bytes into it. The elements and members the initializer leaves out
+    /// are set to zero one by one.
+    fn lower_initializer(&mut self, init: Initializer, name: Name, ty: &Type, offset: usize) {
+        match (init, ty) {
+            (Initializer::Single(Exp::String(string, _)), Type::Array(_, count)) => {
+                self.lower_string_initializer(string.into_bytes(), name, *count, offset);
+            }
+            (Initializer::Single(Exp::WideString(string, _)), Type::Array(_, count)) => {
+                let bytes = string.chars().flat_map(|ch| u32::from(ch).to_le_bytes()).collect();
+                self.lower_string_initializer(bytes, name, count * Type::Int.size(), offset);
+            }
+            (Initializer::Single(exp), Type::Struct(_)) => {
+                let src = self.lower_expression(exp);
+                let ptr_type = Type::Pointer(Box::new(ty.clone()));
+                let variable = self.make_temporary(ptr_type.clone());
+                self.body.push(IrInstruction::GetAddress { src: IrValue::Var(name), dst: variable.clone() });
+                let dst = self.offset_ptr(variable, offset, ptr_type);
+                self.copy_struct(src, dst, ty);
+            }
+            (Initializer::Single(exp), _) => {
+                let src = self.lower_expression(exp);
+                self.body.push(IrInstruction::CopyToOffset { src, dst: name, offset });
+            }
+            (Initializer::Compound(inits, _), Type::Array(element, count)) => {
+                let given = inits.len();
+                let size = element.size_in(self.structs);
+                for (i, init) in inits.into_iter().enumerate() {
+                    self.lower_initializer(init, name, element, offset + i * size);
+                }
+                for i in given..*count {
+                    self.lower_zero(name, element, offset + i * size);
+                }
+            }
+            (Initializer::Compound(inits, _), Type::Struct(tag)) => {
+                let members = &self.structs[tag].members;
+                let given = inits.len();
+                for (init, member) in inits.into_iter().zip(members) {
+                    self.lower_initializer(init, name, &member.ty, offset + member.offset);
+                }
+                for member in &members[given..] {
+                    self.lower_zero(name, &member.ty, offset + member.offset);
+                }
+            }
+            (Initializer::Compound(..), _) => unreachable!("the type checker only allows lists for arrays and structures"),
+        }
+    }
+
+    /// Appends the instructions copying the bytes of a string literal into an array of `count`
+    /// bytes, `offset` bytes into the variable `name`, padded with null bytes. The bytes are
+    /// copied eight or four at a time where they can be, and one by one at the end.
+    fn lower_string_initializer(&mut self, mut bytes: Vec<u8>, name: Name, count: usize, offset: usize) {
+        bytes.resize(count, 0);
+        let mut copied = 0;
+        while copied < count {
+            let rest = &bytes[copied..];
+            let (src, size) = if let Some(chunk) = rest.first_chunk::<8>() {
+                (Constant::Long(i64::from_le_bytes(*chunk)), 8)
+            } else if let Some(chunk) = rest.first_chunk::<4>() {
+                (Constant::Int(i32::from_le_bytes(*chunk)), 4)
+            } else {
+                (Constant::Char(rest[0] as i8), 1)
+            };
+            let src = IrValue::Constant(src);
+            self.body.push(IrInstruction::CopyToOffset { src, dst: name, offset: offset + copied });
+            copied += size;
+        }
+    }
+
+    /// Appends the instructions setting an object of the given type, `offset` bytes into the
+    /// variable `name`, to zero.
+    fn lower_zero(&mut self, name: Name, ty: &Type, offset: usize) {
+        match ty {
+            Type::Array(element, count) => {
+                for i in 0..*count {
+                    self.lower_zero(name, element, offset + i * element.size_in(self.structs));
+                }
+            }
+            Type::Struct(tag) => {
+                for member in &self.structs[tag].members {
+                    self.lower_zero(name, &member.ty, offset + member.offset);
+                }
+            }
+            _ => {
+                let src = IrValue::Constant(Constant::Int(0).convert_to(ty));
+                self.body.push(IrInstruction::CopyToOffset { src, dst: name, offset });
+            }
+        }
+    }
+
+    /// Appends a marker for the source position of the code that follows, if debug
+    /// information is requested. Constants carry no position, so nothing is marked for them.
+    fn locate(&mut self, span: Span) {
+        if self.debug_info && span.line != 0 {
+            self.body.push(IrInstruction::Location(span));
+        }
+    }
+
+    /// Appends a check that a pointer about to be dereferenced is not null, if checks are
+    /// requested, which otherwise reports the position of the dereference and aborts.
+    fn check_null(&mut self, ptr: &IrValue, span: Span) {
+        let Some(file) = self.null_checks else { return };
+        let id = self.make_label_id();
+        let (checked, name) = (Name::from(format!("null_checked.{}", id)), Name::from(format!("null_message.{}", id)));
+        let text = format!("{}:{}:{}: runtime error: null pointer dereference\n", file, span.line, span.column);
+        let length = IrValue::Constant(Constant::ULong(text.len() as u64));
+        self.types.insert(name, Type::Array(Box::new(Type::Char), text.len()));
+        self.null_messages.push(IrStaticConstant { name, alignment: 1, init: StaticInit::String(text, false) });
+
+        self.body.push(IrInstruction::JumpIfNotZero(ptr.clone(), checked));
+        let message = self.make_temporary(Type::Pointer(Box::new(Type::Char)));
+        self.body.push(IrInstruction::GetAddress { src: IrValue::Var(name), dst: message.clone() });
+        let dst = self.make_temporary(Type::Int);
+        self.body.push(IrInstruction::FunCall { name: NULL_DEREFERENCE.into(), args: vec![message, length], dst });
+        self.body.push(IrInstruction::Label(checked));
+    }
+
+    /// Appends the instructions for a statement.
+    fn lower_statement(&mut self, statement: Statement) {
+        match statement {
+            Statement::Return(exp, span) => {
+                self.locate(span);
+                let value = self.lower_expression(exp);
+                self.body.push(IrInstruction::Return(value));
+            }
+            Statement::Expression(exp) => {
+                self.locate(exp.span());
+                // Evaluated for its side effects only
+                self.lower_expression(exp);
+            }
+            Statement::If(condition, then, None) => {
+                let end = Name::from(format!("if_end.{}", self.make_label_id()));
+                self.locate(condition.span());
+                let condition = self.lower_expression(condition);
+                self.body.push(IrInstruction::JumpIfZero(condition, end));
+                self.lower_statement(*then);
+                self.body.push(IrInstruction::Label(end));
+            }
+            Statement::If(condition, then, Some(otherwise)) => {
+                let id = self.make_label_id();
+                let (else_label, end) = (Name::from(format!("if_else.{}", id)), Name::from(format!("if_end.{}", id)));
+                self.locate(condition.span());
+                let condition = self.lower_expression(condition);
+                self.body.push(IrInstruction::JumpIfZero(condition, else_label));
+                self.lower_statement(*then);
+                self.body.push(IrInstruction::Jump(end));
+                self.body.push(IrInstruction::Label(else_label));
+                self.lower_statement(*otherwise);
+                self.body.push(IrInstruction::Label(end));
+            }
+            Statement::While { condition, body, label } => {
+                let (continue_label, break_label) = (Name::from(format!("continue_{}", label)), Name::from(format!("break_{}", label)));
+                self.body.push(IrInstruction::Label(continue_label));
+                self.locate(condition.span());
+                let condition = self.lower_expression(condition);
+                self.body.push(IrInstruction::JumpIfZero(condition, break_label));
+                self.lower_statement(*body);
+                self.body.push(IrInstruction::Jump(continue_label));
+                self.body.push(IrInstruction::Label(break_label));
+            }
+            Statement::DoWhile { body, condition, label } => {
+                let start = Name::from(format!("start_{}", label));
+                self.body.push(IrInstruction::Label(start));
+                self.lower_statement(*body);
+                self.body.push(IrInstruction::Label(Name::from(format!("continue_{}", label))));
+                self.locate(condition.span());
+                let condition = self.lower_expression(condition);
+                self.body.push(IrInstruction::JumpIfNotZero(condition, start));
+                self.body.push(IrInstruction::Label(Name::from(format!("break_{}", label))));
+            }
+            Statement::For { init, condition, post, body, label } => {
+                let start = Name::from(format!("start_{}", label));
+                let break_label = Name::from(format!("break_{}", label));
+                match *init {
+                    ForInit::Declaration(declaration) => self.lower_declaration(declaration),
+                    ForInit::Expression(Some(exp)) => {
+                        self.locate(exp.span());
+                        self.lower_expression(exp);
+                    }
+                    ForInit::Expression(None) => {}
+                }
+                self.body.push(IrInstruction::Label(start));
+                // A missing condition is always true
+                if let Some(condition) = condition {
+                    self.locate(condition.span());
+                    let condition = self.lower_expression(condition);
+                    self.body.push(IrInstruction::JumpIfZero(condition, break_label));
+                }
+                self.lower_statement(*body);
+                self.body.push(IrInstruction::Label(Name::from(format!("continue_{}", label))));
+                if let Some(post) = post {
+                    self.locate(post.span());
+                    self.lower_expression(post);
+                }
+                self.body.push(IrInstruction::Jump(start));
+                self.body.push(IrInstruction::Label(break_label));
+            }
+            // A chain of comparisons, one per case, then a jump to the default or past the body
+            Statement::Switch { condition, body, cases, label, span } => {
+                let break_label = Name::from(format!("break_{}", label));
+                self.locate(span);
+                let value = self.lower_expression(condition);
+                let mut default = None;
+                for (constant, case_label) in cases {
+                    let Some(constant) = constant else {
+                        default = Some(case_label);
+                        continue;
+                    };
+                    let equal = self.make_temporary(Type::Int);
+                    self.body.push(IrInstruction::Binary {
+                        op: BinaryOperator::Equal,
+                        src1: value.clone(),
+                        src2: IrValue::Constant(constant),
+                        dst: equal.clone(),
+                    });
+                    self.body.push(IrInstruction::JumpIfNotZero(equal, case_label));
+                }
+                self.body.push(IrInstruction::Jump(default.unwrap_or(break_label)));
+                self.lower_statement(*body);
+                self.body.push(IrInstruction::Label(break_label));
+            }
+            Statement::Case { body, label, .. } | Statement::Default { body, label, .. } | Statement::Labeled(label, body, _) => {
+                self.body.push(IrInstruction::Label(label));
+                self.lower_statement(*body);
+            }
+            Statement::Goto(label, span) => {
+                self.locate(span);
+                self.body.push(IrInstruction::Jump(label));
+            }
+            Statement::Break(label, span) => {
+                self.locate(span);
+                self.body.push(IrInstruction::Jump(Name::from(format!("break_{}", label))));
+            }
+            Statement::Continue(label, span) => {
+                self.locate(span);
+                self.body.push(IrInstruction::Jump(Name::from(format!("continue_{}", label))));
+            }
+            Statement::Compound(items) => items.into_iter().for_each(|item| self.lower_block_item(item)),
+            Statement::Null => {}
+        }
+    }
+
+    /// Appends the instructions computing the address of the object an lvalue designates, if
+    /// it is reached through a pointer or is atomic, and returns the object.
+    fn lower_lvalue(&mut self, exp: Exp) -> Lvalue {
+        let ty = object_type_of(&exp, self.symbols);
+        let lvalue = match exp {
+            Exp::Var(name, _) => Lvalue::Variable(name),
+            Exp::Dereference(operand, span) => {
+                let may_be_null = may_be_null(&operand, self.symbols);
+                let ptr = self.lower_expression(*operand);
+                if may_be_null {
+                    self.check_null(&ptr, span);
+                }
+                Lvalue::Dereferenced(ptr)
+            }
+            Exp::Subscript(ptr, index, span) => {
+                let ptr_type = type_of(&ptr, self.symbols);
+                let may_be_null = may_be_null(&ptr, self.symbols);
+                let ptr = self.lower_expression(*ptr);
+                if may_be_null {
+                    self.check_null(&ptr, span);
+                }
+                let index = self.lower_expression(*index);
+                Lvalue::Dereferenced(self.add_ptr(ptr, index, ptr_type))
+            }
+            Exp::Member { base, offset, ty, .. } => {
+                let ptr = self.lower_expression(*base);
+                Lvalue::Dereferenced(self.offset_ptr(ptr, offset, Type::Pointer(Box::new(ty))))
+            }
+            _ => unreachable!("variable resolution rejects non-lvalues"),
+        };
+        if !ty.is_atomic() {
+            return lvalue;
+        }
+        match lvalue {
+            Lvalue::Variable(name) => {
+                let ptr = self.make_temporary(Type::Pointer(Box::new(ty)));
+                self.body.push(IrInstruction::GetAddress { src: IrValue::Var(name), dst: ptr.clone() });
+                Lvalue::Atomic(ptr)
+            }
+            Lvalue::Dereferenced(ptr) | Lvalue::Atomic(ptr) => Lvalue::Atomic(ptr),
+        }
+    }
+
+    /// Appends the instructions computing a structure, which does not fit in a value, and
+    /// returns a pointer to an object holding it: the object an lvalue designates, the one
+    /// an assignment stores to, or a temporary for the result of `?:`.
+    fn lower_struct(&mut self, exp: Exp) -> IrValue {
+        let ty = type_of(&exp, self.symbols);
+        let ptr_type = Type::Pointer(Box::new(ty.clone()));
+        match exp {
+            Exp::Assignment(left, right, _) => {
+                let dst = self.address_of(*left, ptr_type);
+                let src = self.lower_expression(*right);
+                self.copy_struct(src, dst.clone(), &ty);
+                dst
+            }
+            Exp::Conditional(condition, then, otherwise, _) => {
+                let id = self.make_label_id();
+                let (else_label, end) = (Name::from(format!("cond_else.{}", id)), Name::from(format!("cond_end.{}", id)));
+                let result = self.make_temporary(ty.clone());
+                let dst = self.make_temporary(ptr_type);
+                self.body.push(IrInstruction::GetAddress { src: result, dst: dst.clone() });
+                let condition = self.lower_expression(*condition);
+                self.body.push(IrInstruction::JumpIfZero(condition, else_label));
+                let src = self.lower_expression(*then);
+                self.copy_struct(src, dst.clone(), &ty);
+                self.body.push(IrInstruction::Jump(end));
+                self.body.push(IrInstruction::Label(else_label));
+                let src = self.lower_expression(*otherwise);
+                self.copy_struct(src, dst.clone(), &ty);
+                self.body.push(IrInstruction::Label(end));
+                dst
+            }
+            Exp::Comma(left, right, _) => {
+                self.lower_expression(*left);
+                self.lower_struct(*right)
+            }
+            exp => self.address_of(exp, ptr_type),
+        }
+    }
+
+    /// Appends the instructions copying a structure of the given type from the object `src`
+    /// points to into the one `dst` points to, one scalar as large as its alignment at a time.
+    fn copy_struct(&mut self, src: IrValue, dst: IrValue, ty: &Type) {
+        let (size, alignment) = (ty.size_in(self.structs), ty.alignment_in(self.structs));
+        let scalar = scalar_of_size(alignment);
+        let ptr_type = Type::Pointer(Box::new(scalar.clone()));
+        for offset in (0..size).step_by(alignment) {
+            let src_ptr = self.offset_ptr(src.clone(), offset, ptr_type.clone());
+            let dst_ptr = self.offset_ptr(dst.clone(), offset, ptr_type.clone());
+            let value = self.make_temporary(scalar.clone());
+            self.body.push(IrInstruction::Load { src_ptr, dst: value.clone() });
+            self.body.push(IrInstruction::Store { src: value, dst_ptr });
+        }
+    }
+
+    /// Appends the instruction moving a pointer by a number of bytes, unless it is zero, and
+    /// returns the value holding the result, a pointer of the given type.
+    fn offset_ptr(&mut self, ptr: IrValue, offset: usize, ptr_type: Type) -> IrValue {
+        if offset == 0 {
+            return ptr;
+        }
+        let dst = self.make_temporary(ptr_type);
+        let index = IrValue::Constant(Constant::Long(offset as i64));
+        self.body.push(IrInstruction::AddPtr { ptr, index, scale: 1, dst: dst.clone() });
+        dst
+    }
+
+    /// Appends the instructions computing the address of an lvalue, as `&` does, and returns
+    /// the pointer of the given type holding it.
+    fn address_of(&mut self, exp: Exp, ty: Type) -> IrValue {
+        match self.lower_lvalue(exp) {
+            Lvalue::Variable(name) => {
+                let dst = self.make_temporary(ty);
+                self.body.push(IrInstruction::GetAddress { src: IrValue::Var(name), dst: dst.clone() });
+                dst
+            }
+            // `&*p` is just `p`
+            Lvalue::Dereferenced(ptr) | Lvalue::Atomic(ptr) => ptr,
+        }
+    }
+
+    /// Appends the instruction moving a pointer of the given type by `index` elements, and
+    /// returns the value holding the result.
+    fn add_ptr(&mut self, ptr: IrValue, index: IrValue, ptr_type: Type) -> IrValue {
+        let Type::Pointer(referenced) = &ptr_type else {
+            unreachable!("the type checker only allows pointers to be offset")
+        };
+        let scale = referenced.size_in(self.structs);
+        let dst = self.make_temporary(ptr_type);
+        self.body.push(IrInstruction::AddPtr { ptr, index, scale, dst: dst.clone() });
+        dst
+    }
+
+    /// Appends the instruction negating a `long` index, so that adding it moves a pointer
+    /// backward, and returns the value holding the result.
+    fn negate_index(&mut self, index: IrValue) -> IrValue {
+        let dst = self.make_temporary(Type::Long);
+        self.body.push(IrInstruction::Unary { op: UnaryOperator::Negate, src: index, dst: dst.clone() });
+        dst
+    }
+
+    /// Appends the instructions a call to a builtin function expands to, and returns the value
+    /// the call produces.
+    fn lower_builtin(&mut self, name: Name, args: Vec<Exp>) -> IrValue {
+        let mut args: Vec<IrValue> = args.into_iter().map(|arg| self.lower_expression(arg)).collect();
+        match name.as_str() {
+            "__builtin_alloca" => {
+                let dst = self.make_temporary(Type::Pointer(Box::new(Type::Char)));
+                self.body.push(IrInstruction::Alloca { size: args.remove(0), dst: dst.clone() });
+                dst
+            }
+            // The expected value is evaluated, but nothing is laid out by it
+            "__builtin_expect" => args.remove(0),
+            // Reaching `__builtin_unreachable` is undefined, so it stops the program like a trap
+            "__builtin_trap" | "__builtin_unreachable" => {
+                self.body.push(IrInstruction::Trap);
+                IrValue::Constant(Constant::Int(0))
+            }
+            // The value shifted right by 31 is all sign bits, and flipping the bits of a
+            // negative value then subtracting -1 negates it
+            "__builtin_abs" => {
+                let mut binary = |op, src1, src2| {
+                    let dst = self.make_temporary(Type::Int);
+                    self.body.push(IrInstruction::Binary { op, src1, src2, dst: dst.clone() });
+                    dst
+                };
+                let value = args.remove(0);
+                let sign = binary(BinaryOperator::RightShift, value.clone(), IrValue::Constant(Constant::Int(31)));
+                let flipped = binary(BinaryOperator::BitwiseXor, value, sign.clone());
+                binary(BinaryOperator::Subtract, flipped, sign)
+            }
+            _ => unreachable!("'{}' is not a builtin", name),
+        }
+    }
+
+    /// Returns a value holding the current contents of an object of the given type, loading
+    /// it if it is reached through a pointer.
+    fn read(&mut self, lvalue: &Lvalue, ty: &Type) -> IrValue {
+        match lvalue {
+            Lvalue::Variable(name) => IrValue::Var(*name),
+            Lvalue::Dereferenced(ptr) => {
+                let dst = self.make_temporary(ty.clone());
+                self.body.push(IrInstruction::Load { src_ptr: ptr.clone(), dst: dst.clone() });
+                dst
+            }
+            Lvalue::Atomic(ptr) => {
+                let dst = self.make_temporary(ty.clone());
+                self.body.push(IrInstruction::AtomicLoad { src_ptr: ptr.clone(), dst: dst.clone() });
+                dst
+            }
+        }
+    }
+
+    /// Appends the instruction storing `src` in an object, and returns the value the
+    /// assignment expression produces. A variable that already holds `src` is left alone.
+    fn store(&mut self, lvalue: Lvalue, src: IrValue) -> IrValue {
+        match lvalue {
+            Lvalue::Variable(name) => {
+                let dst = IrValue::Var(name);
+                if src != dst {
+                    self.body.push(IrInstruction::Copy { src, dst: dst.clone() });
+                }
+                dst
+            }
+            Lvalue::Dereferenced(ptr) => {
+                self.body.push(IrInstruction::Store { src: src.clone(), dst_ptr: ptr });
+                src
+            }
+            Lvalue::Atomic(ptr) => {
+                self.body.push(IrInstruction::AtomicStore { src: src.clone(), dst_ptr: ptr });
+                src
+            }
+        }
+    }
+
+    /// Appends a loop that replaces the value of an atomic object with one computed from it,
+    /// retrying until no other thread has changed the object in between: it reads the
+    /// object, computes the new value with `update` and stores it with a compare-exchange
+    /// against the value read.
+    ///
+    /// # Arguments
+    ///
+    /// * `ptr` - A pointer to the object.
+    /// * `ty` - The unqualified type of the object.
+    /// * `update` - Appends the instructions computing the new value from the old one, in
+    ///   fresh temporaries, and returns the value holding it.
+    ///
+    /// # Returns
+    ///
+    /// * `(IrValue, IrValue)` - The values holding the old and the new value of the object.
+    fn update_atomic(&mut self, ptr: IrValue, ty: &Type, update: impl FnOnce(&mut Self, IrValue) -> IrValue) -> (IrValue, IrValue) {
+        let retry = Name::from(format!("atomic_retry.{}", self.make_label_id()));
+        self.body.push(IrInstruction::Label(retry));
+        let old = self.make_temporary(ty.clone());
+        self.body.push(IrInstruction::AtomicLoad { src_ptr: ptr.clone(), dst: old.clone() });
+        let new = update(self, old.clone());
+        let stored = self.make_temporary(Type::Int);
+        let exchange = IrInstruction::CompareExchange { ptr, expected: old.clone(), desired: new.clone(), dst: stored.clone() };
+        self.body.push(exchange);
+        self.body.push(IrInstruction::JumpIfZero(stored, retry));
+        (old, new)
+    }
+
+    /// Appends the instructions applying the operator of a compound assignment to the value
+    /// of its object and its right side, in the type the operation is carried out in, and
+    /// returns the value holding the result, converted back to the type of the object.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operator.
+    /// * `current` - The value of the object.
+    /// * `src2` - The value of the right side, already of the type of the operation.
+    /// * `left_type` - The type of the object.
+    /// * `operation_type` - The type the operation is carried out in.
+    /// * `in_place` - Whether `current` is the object itself, a variable that can hold the
+    ///   result of an operation carried out in its own type.
+    ///
+    /// # Returns
+    ///
+    /// * `IrValue` - The value holding the result.
+    fn apply_compound(
+        &mut self,
+        op: BinaryOperator,
+        current: IrValue,
+        src2: IrValue,
+        left_type: &Type,
+        operation_type: &Type,
+        in_place: bool,
+    ) -> IrValue {
+        if operation_type == left_type {
+            let dst = if in_place { current.clone() } else { self.make_temporary(left_type.clone()) };
+            self.body.push(IrInstruction::Binary { op, src1: current, src2, dst: dst.clone() });
+            dst
+        } else {
+            let src1 = self.convert(current, left_type, operation_type);
+            let result = self.make_temporary(operation_type.clone());
+            self.body.push(IrInstruction::Binary { op, src1, src2, dst: result.clone() });
+            self.convert(result, operation_type, left_type)
+        }
+    }
+
+    /// Appends the instructions adding one to or subtracting one from the value of an
+    /// arithmetic object, as `++` and `--` do, and returns the value holding the result.
+    /// Characters are promoted to `int` for the arithmetic, like everywhere else.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - `Add` or `Subtract`.
+    /// * `current` - The value of the object.
+    /// * `ty` - The type of the object.
+    /// * `in_place` - Whether `current` is the object itself, a variable that can be updated
+    ///   in place.
+    ///
+    /// # Returns
+    ///
+    /// * `IrValue` - The value holding the result.
+    fn step(&mut self, op: BinaryOperator, current: IrValue, ty: &Type, in_place: bool) -> IrValue {
+        if ty.is_character() {
+            let src1 = self.convert(current, ty, &Type::Int);
+            let sum = self.make_temporary(Type::Int);
+            self.body.push(IrInstruction::Binary { op, src1, src2: IrValue::Constant(Constant::Int(1)), dst: sum.clone() });
+            return self.convert(sum, &Type::Int, ty);
+        }
+        let one = IrValue::Constant(Constant::Int(1).convert_to(ty));
+        let updated = if in_place { current.clone() } else { self.make_temporary(ty.clone()) };
+        self.body.push(IrInstruction::Binary { op, src1: current, src2: one, dst: updated.clone() });
+        updated
+    }
+
+    /// Appends the instructions computing `exp` and returns the value holding its result, or
+    /// for a structure a pointer to it. Operands are lowered left to right, so temporaries
+    /// are defined in evaluation order.
+    fn lower_expression(&mut self, exp: Exp) -> IrValue {
+        if let Type::Struct(_) = type_of(&exp, self.symbols) {
+            return self.lower_struct(exp);
+        }
+        match exp {
+            Exp::Const(value) => IrValue::Constant(value),
+            Exp::String(..) | Exp::WideString(..) => unreachable!("the type checker turns string literals into variables"),
+            Exp::SizeOf(..) | Exp::SizeOfType(..) => unreachable!("the type checker folds sizeof into a constant"),
+            Exp::Cast(ty, operand, _) => {
+                let from = type_of(&operand, self.symbols);
+                // An array decays to the address of its first element
+                if let Type::Array(..) = from {
+                    return self.address_of(*operand, ty);
+                }
+                let src = self.lower_expression(*operand);
+                self.convert(src, &from, &ty)
+            }
+            Exp::Assignment(left, right, _) => {
+                let lvalue = self.lower_lvalue(*left);
+                let src = self.lower_expression(*right);
+                self.store(lvalue, src)
+            }
+            Exp::CompoundAssignment(op, left, right, _) => {
+                // The type checker converted the right side to the type the operation is
+                // carried out in, except for shifts, which keep the promoted type of the left side
+                let left_type = type_of(&left, self.symbols);
+                if left_type.is_pointer() {
+                    // `p += n` and `p -= n` move the pointer by `n` elements
+                    let lvalue = self.lower_lvalue(*left);
+                    let mut index = self.lower_expression(*right);
+                    let current = self.read(&lvalue, &left_type);
+                    if op == BinaryOperator::Subtract {
+                        index = self.negate_index(index);
+                    }
+                    let result = self.add_ptr(current, index, left_type);
+                    return self.store(lvalue, result);
+                }
+                let operation_type = match op {
+                    BinaryOperator::LeftShift | BinaryOperator::RightShift => left_type.promoted(),
+                    _ => common_type(&left_type, &type_of(&right, self.symbols)),
+                };
+                let lvalue = self.lower_lvalue(*left);
+                let src2 = self.lower_expression(*right);
+                if let Lvalue::Atomic(ptr) = lvalue {
+                    let (_, result) = self.update_atomic(ptr, &left_type, |context, current| {
+                        context.apply_compound(op, current, src2, &left_type, &operation_type, false)
+                    });
+                    return result;
+                }
+                let current = self.read(&lvalue, &left_type);
+                // A variable is updated in place
+                let in_place = matches!(lvalue, Lvalue::Variable(_));
+                let result = self.apply_compound(op, current, src2, &left_type, &operation_type, in_place);
+                self.store(lvalue, result)
+            }
+            Exp::PostfixUpdate(op, operand, _) => {
+                // The expression yields the value from before the update
+                let ty = type_of(&operand, self.symbols);
+                let lvalue = self.lower_lvalue(*operand);
+                if let Lvalue::Atomic(ptr) = lvalue {
+                    let (old, _) = self.update_atomic(ptr, &ty, |context, current| context.step(op, current, &ty, false));
+                    return old;
+                }
+                let current = self.read(&lvalue, &ty);
+                let dst = self.make_temporary(ty.clone());
+                self.body.push(IrInstruction::Copy { src: current.clone(), dst: dst.clone() });
+                if ty.is_pointer() {
+                    // A pointer moves by one element
+                    let index = if op == BinaryOperator::Add { 1 } else { -1 };
+                    let updated = self.add_ptr(current, IrValue::Constant(Constant::Long(index)), ty);
+                    self.store(lvalue, updated);
+                    return dst;
+                }
+                // A variable other than a character is updated in place
+                let in_place = matches!(lvalue, Lvalue::Variable(_));
+                let updated = self.step(op, current, &ty, in_place);
+                self.store(lvalue, updated);
+                dst
+            }
+            Exp::Conditional(condition, then, otherwise, _) => {
+                let id = self.make_label_id();
+                let (else_label, end) = (Name::from(format!("cond_else.{}", id)), Name::from(format!("cond_end.{}", id)));
+                let dst = self.make_temporary(type_of(&then, self.symbols));
+                let condition = self.lower_expression(*condition);
+                self.body.push(IrInstruction::JumpIfZero(condition, else_label));
+                let src = self.lower_expression(*then);
+                self.body.push(IrInstruction::Copy { src, dst: dst.clone() });
+                self.body.push(IrInstruction::Jump(end));
+                self.body.push(IrInstruction::Label(else_label));
+                let src = self.lower_expression(*otherwise);
+                self.body.push(IrInstruction::Copy { src, dst: dst.clone() });
+                self.body.push(IrInstruction::Label(end));
+                dst
+            }
+            // The value of the left operand is discarded, like that of an expression statement
+            Exp::Comma(left, right, _) => {
+                self.lower_expression(*left);
+                self.lower_expression(*right)
+            }
+            Exp::FunctionCall(name, args, _) if builtin_type(name).is_some() => self.lower_builtin(name, args),
+            Exp::FunctionCall(name, args, _) => {
+                let ty = match self.symbols.get(&name).map(|symbol| &symbol.ty) {
+                    Some(Type::Function { ret, .. }) => (**ret).clone(),
+                    _ => Type::Int,
+                };
+                let args = args.into_iter().map(|arg| self.lower_expression(arg)).collect();
+                let dst = self.make_temporary(ty);
+                self.body.push(IrInstruction::FunCall { name, args, dst: dst.clone() });
+                dst
+            }
+            Exp::UnOp(op, operand, _) => {
+                let ty = match op {
+                    UnaryOperator::Not => Type::Int,
+                    _ => type_of(&operand, self.symbols),
+                };
+                let src = self.lower_expression(*operand);
+                let dst = self.make_temporary(ty);
+                self.body.push(IrInstruction::Unary { op, src, dst: dst.clone() });
+                dst
+            }
+            // `&*p` is just `p`, which is not dereferenced, so it is not checked
+            Exp::AddressOf(operand, _) if matches!(*operand, Exp::Dereference(..)) => {
+                let Exp::Dereference(ptr, _) = *operand else { unreachable!("the operand is a dereference") };
+                self.lower_expression(*ptr)
+            }
+            Exp::AddressOf(operand, _) => {
+                let ty = Type::Pointer(Box::new(object_type_of(&operand, self.symbols)));
+                self.address_of(*operand, ty)
+            }
+            // Reading a variable is just using it, unless it is atomic
+            exp @ (Exp::Var(..) | Exp::Dereference(..) | Exp::Subscript(..) | Exp::Member { .. }) => {
+                let ty = type_of(&exp, self.symbols);
+                let lvalue = self.lower_lvalue(exp);
+                self.read(&lvalue, &ty)
+            }
+            Exp::BinOp(op @ (BinaryOperator::And | BinaryOperator::Or), left, right, _) => {
+                // The right operand is only evaluated if the left one does not decide the result:
+                // `&&` short-circuits to 0 on a zero operand, `||` to 1 on a non-zero operand
+                let id = self.make_label_id();
+                let (short_circuit, end, short_value) = if op == BinaryOperator::And {
+                    (Name::from(format!("and_false.{}", id)), Name::from(format!("and_end.{}", id)), 0)
+                } else {
+                    (Name::from(format!("or_true.{}", id)), Name::from(format!("or_end.{}", id)), 1)
+                };
+                let jump = |value, target| match op {
+                    BinaryOperator::And => IrInstruction::JumpIfZero(value, target),
+                    _ => IrInstruction::JumpIfNotZero(value, target),
+                };
+                let dst = self.make_temporary(Type::Int);
+                let src1 = self.lower_expression(*left);
+                self.body.push(jump(src1, short_circuit));
+                let src2 = self.lower_expression(*right);
+                self.body.push(jump(src2, short_circuit));
+                self.body.push(IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1 - short_value)), dst: dst.clone() });
+                self.body.push(IrInstruction::Jump(end));
+                self.body.push(IrInstruction::Label(short_circuit));
+                self.body.push(IrInstruction::Copy { src: IrValue::Constant(Constant::Int(short_value)), dst: dst.clone() });
+                self.body.push(IrInstruction::Label(end));
+                dst
+            }
+            Exp::BinOp(op @ (BinaryOperator::Add | BinaryOperator::Subtract), left, right, _)
+                if type_of(&left, self.symbols).is_pointer() =>
+            {
+                // The type checker put the pointer first. Subtracting another pointer counts
+                // the elements between them, anything else is an index.
+                let ptr_type = type_of(&left, self.symbols);
+                let subtracts_pointer = type_of(&right, self.symbols).is_pointer();
+                let src1 = self.lower_expression(*left);
+                let src2 = self.lower_expression(*right);
+                if subtracts_pointer {
+                    let scale = match &ptr_type {
+                        Type::Pointer(referenced) => referenced.size_in(self.structs) as i64,
+                        _ => 1,
+                    };
+                    let bytes = self.make_temporary(Type::Long);
+                    self.body.push(IrInstruction::Binary { op, src1, src2, dst: bytes.clone() });
+                    let dst = self.make_temporary(Type::Long);
+                    let src2 = IrValue::Constant(Constant::Long(scale));
+                    self.body.push(IrInstruction::Binary { op: BinaryOperator::Divide, src1: bytes, src2, dst: dst.clone() });
+                    return dst;
+                }
+                let index = if op == BinaryOperator::Subtract { self.negate_index(src2) } else { src2 };
+                self.add_ptr(src1, index, ptr_type)
+            }
+            Exp::BinOp(op, left, right, _) => {
+                let ty = if is_arithmetic(op) { type_of(&left, self.symbols) } else { Type::Int };
+                let src1 = self.lower_expression(*left);
+                let src2 = self.lower_expression(*right);
+                let dst = self.make_temporary(ty);
+                self.body.push(IrInstruction::Binary { op, src1, src2, dst: dst.clone() });
+                dst
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> IrValue {
+        IrValue::Var(Name::from(name))
+    }
+
+    fn int_function(param_count: usize) -> Type {
+        Type::Function { params: vec![Type::Int; param_count], ret: Box::new(Type::Int) }
+    }
+
+    fn main_program(body: Vec<BlockItem>) -> Program {
+        Program {
+            declarations: vec![Declaration::Function(FunDecl {
+                name: "main".into(),
+                params: vec![],
+                body: Some(body),
+                ty: int_function(0),
+                storage_class: None,
+                span: Span::default(),
+            })],
+        }
+    }
+
+    #[test]
+    fn test_lower_constant() {
+        let ast = main_program(vec![BlockItem::Statement(Statement::Return(Exp::Const(Constant::Int(2)), Span::default()))]);
+        let ir = generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None);
+        assert_eq!(ir.functions.len(), 1);
+        assert_eq!(ir.functions[0].name, "main");
+        assert_eq!(ir.functions[0].body, vec![
+            IrInstruction::Return(IrValue::Constant(Constant::Int(2))),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+    }
+
+    #[test]
+    fn test_lower_nested_expression() {
+        // -(1 + 2) * 3
+        let sum = Exp::BinOp(BinaryOperator::Add, Box::new(Exp::Const(Constant::Int(1))), Box::new(Exp::Const(Constant::Int(2))), Span::default());
+        let exp = Exp::BinOp(
+            BinaryOperator::Multiply,
+            Box::new(Exp::UnOp(UnaryOperator::Negate, Box::new(sum), Span::default())),
+            Box::new(Exp::Const(Constant::Int(3))),
+            Span::default(),
+        );
+        let ast = main_program(vec![BlockItem::Statement(Statement::Return(exp, Span::default()))]);
+        let expected = vec![
+            IrInstruction::Binary {
+                op: BinaryOperator::Add,
+                src1: IrValue::Constant(Constant::Int(1)),
+                src2: IrValue::Constant(Constant::Int(2)),
+                dst: var("tmp.0"),
+            },
+            IrInstruction::Unary { op: UnaryOperator::Negate, src: var("tmp.0"), dst: var("tmp.1") },
+            IrInstruction::Binary {
+                op: BinaryOperator::Multiply,
+                src1: var("tmp.1"),
+                src2: IrValue::Constant(Constant::Int(3)),
+                dst: var("tmp.2"),
+            },
+            IrInstruction::Return(var("tmp.2")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_declarations_and_assignments() {
+        // int a.0 = 1; a.0 = a.0 + 2;
+        let sum = Exp::BinOp(BinaryOperator::Add, Box::new(Exp::Var("a.0".into(), Span::default())), Box::new(Exp::Const(Constant::Int(2))), Span::default());
+        let ast = main_program(vec![
+            BlockItem::Declaration(Declaration::Variable(VarDecl { name: "a.0".into(), init: Some(Initializer::Single(Exp::Const(Constant::Int(1)))), ty: Type::Int, storage_class: None, span: Span::default() })),
+            BlockItem::Statement(Statement::Expression(
+                Exp::Assignment(Box::new(Exp::Var("a.0".into(), Span::default())), Box::new(sum), Span::default()))),
+        ]);
+        let expected = vec![
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("a.0") },
+            IrInstruction::Binary {
+                op: BinaryOperator::Add,
+                src1: var("a.0"),
+                src2: IrValue::Constant(Constant::Int(2)),
+                dst: var("tmp.0"),
+            },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("a.0") },
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_compound_assignment_and_postfix_update() {
+        // return (a.0 *= 3) + a.0--;
+        let a = || Box::new(Exp::Var("a.0".into(), Span::default()));
+        let compound = Exp::CompoundAssignment(BinaryOperator::Multiply, a(), Box::new(Exp::Const(Constant::Int(3))), Span::default());
+        let postfix = Exp::PostfixUpdate(BinaryOperator::Subtract, a(), Span::default());
+        let exp = Exp::BinOp(BinaryOperator::Add, Box::new(compound), Box::new(postfix), Span::default());
+        let ast = main_program(vec![BlockItem::Statement(Statement::Return(exp, Span::default()))]);
+        let expected = vec![
+            IrInstruction::Binary {
+                op: BinaryOperator::Multiply,
+                src1: var("a.0"),
+                src2: IrValue::Constant(Constant::Int(3)),
+                dst: var("a.0"),
+            },
+            IrInstruction::Copy { src: var("a.0"), dst: var("tmp.0") },
+            IrInstruction::Binary {
+                op: BinaryOperator::Subtract,
+                src1: var("a.0"),
+                src2: IrValue::Constant(Constant::Int(1)),
+                dst: var("a.0"),
+            },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("a.0"), src2: var("tmp.0"), dst: var("tmp.1") },
+            IrInstruction::Return(var("tmp.1")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_if_else_and_conditional() {
+        // if (a) return b ? 1 : 2; else ;
+        let conditional = Exp::Conditional(
+            Box::new(Exp::Var("b".into(), Span::default())),
+            Box::new(Exp::Const(Constant::Int(1))),
+            Box::new(Exp::Const(Constant::Int(2))),
+            Span::default(),
+        );
+        let statement = Statement::If(
+            Exp::Var("a".into(), Span::default()),
+            Box::new(Statement::Return(conditional, Span::default())),
+            Some(Box::new(Statement::Null)),
+        );
+        let ast = main_program(vec![BlockItem::Statement(statement)]);
+        let label = Name::from;
+        let expected = vec![
+            IrInstruction::JumpIfZero(var("a"), label("if_else.0")),
+            IrInstruction::JumpIfZero(var("b"), label("cond_else.1")),
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("tmp.0") },
+            IrInstruction::Jump(label("cond_end.1")),
+            IrInstruction::Label(label("cond_else.1")),
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(2)), dst: var("tmp.0") },
+            IrInstruction::Label(label("cond_end.1")),
+            IrInstruction::Return(var("tmp.0")),
+            IrInstruction::Jump(label("if_end.0")),
+            IrInstruction::Label(label("if_else.0")),
+            IrInstruction::Label(label("if_end.0")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_short_circuit_operators() {
+        // return a || b;
+        let exp = Exp::BinOp(BinaryOperator::Or, Box::new(Exp::Var("a".into(), Span::default())), Box::new(Exp::Var("b".into(), Span::default())), Span::default());
+        let ast = main_program(vec![BlockItem::Statement(Statement::Return(exp, Span::default()))]);
+        let label = Name::from;
+        let expected = vec![
+            IrInstruction::JumpIfNotZero(var("a"), label("or_true.0")),
+            IrInstruction::JumpIfNotZero(var("b"), label("or_true.0")),
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(0)), dst: var("tmp.0") },
+            IrInstruction::Jump(label("or_end.0")),
+            IrInstruction::Label(label("or_true.0")),
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("tmp.0") },
+            IrInstruction::Label(label("or_end.0")),
+            IrInstruction::Return(var("tmp.0")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_for_loop() {
+        // for (i = 0; i < 3; i = i + 1) continue;
+        let i = || Box::new(Exp::Var("i".into(), Span::default()));
+        let statement = Statement::For {
+            init: Box::new(ForInit::Expression(Some(Exp::Assignment(i(), Box::new(Exp::Const(Constant::Int(0))), Span::default())))),
+            condition: Some(Exp::BinOp(BinaryOperator::LessThan, i(), Box::new(Exp::Const(Constant::Int(3))), Span::default())),
+            post: Some(Exp::Assignment(
+                i(),
+                Box::new(Exp::BinOp(BinaryOperator::Add, i(), Box::new(Exp::Const(Constant::Int(1))), Span::default())),
+                Span::default(),
+            )),
+            body: Box::new(Statement::Continue("loop.0".into(), Span::default())),
+            label: "loop.0".into(),
+        };
+        let ast = main_program(vec![BlockItem::Statement(statement)]);
+        let label = Name::from;
+        let expected = vec![
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(0)), dst: var("i") },
+            IrInstruction::Label(label("start_loop.0")),
+            IrInstruction::Binary {
+                op: BinaryOperator::LessThan,
+                src1: var("i"),
+                src2: IrValue::Constant(Constant::Int(3)),
+                dst: var("tmp.0"),
+            },
+            IrInstruction::JumpIfZero(var("tmp.0"), label("break_loop.0")),
+            IrInstruction::Jump(label("continue_loop.0")),
+            IrInstruction::Label(label("continue_loop.0")),
+            IrInstruction::Binary {
+                op: BinaryOperator::Add,
+                src1: var("i"),
+                src2: IrValue::Constant(Constant::Int(1)),
+                dst: var("tmp.1"),
+            },
+            IrInstruction::Copy { src: var("tmp.1"), dst: var("i") },
+            IrInstruction::Jump(label("start_loop.0")),
+            IrInstruction::Label(label("break_loop.0")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        assert_eq!(generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None).functions[0].body, expected);
+    }
+
+    #[test]
+    fn test_lower_function_calls() {
+        // int f(int a.0); int main(void) { return f(f(1)); } int f(int a.1) { return a.1; }
+        let call = |arg| Exp::FunctionCall("f".into(), vec![arg], Span::default());
+        let ast = Program {
+            declarations: vec![
+                Declaration::Function(FunDecl { name: "f".into(), params: vec!["a.0".into()], body: None, ty: int_function(1), storage_class: None, span: Span::default() }),
+                Declaration::Function(FunDecl {
+                    name: "main".into(),
+                    params: vec![],
+                    body: Some(vec![BlockItem::Statement(Statement::Return(call(call(Exp::Const(Constant::Int(1)))), Span::default()))]),
+                    ty: int_function(0),
+                    storage_class: None,
+                    span: Span::default(),
+                }),
+                Declaration::Function(FunDecl {
+                    name: "f".into(),
+                    params: vec!["a.1".into()],
+                    body: Some(vec![BlockItem::Statement(Statement::Return(Exp::Var("a.1".into(), Span::default()), Span::default()))]),
+                    ty: int_function(1),
+                    storage_class: None,
+                    span: Span::default(),
+                }),
+            ],
+        };
+        let ir = generate_ir(ast, &SymbolTable::new(), &StructTable::new(), false, None);
+        assert_eq!(ir.functions.len(), 2);
+        assert_eq!(ir.functions[0].body, vec![
+            IrInstruction::FunCall { name: "f".into(), args: vec![IrValue::Constant(Constant::Int(1))], dst: var("tmp.0") },
+            IrInstruction::FunCall { name: "f".into(), args: vec![var("tmp.0")], dst: var("tmp.1") },
+            IrInstruction::Return(var("tmp.1")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+        assert_eq!(ir.functions[1].name, "f");
+        assert_eq!(ir.functions[1].params, vec!["a.1"]);
+    }
+
+    #[test]
+    fn test_lower_static_variables() {
+        let source = "int x; int y = 3; int main(void) { x = y; return x; } int x = 5; int z;";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.static_variables, vec![
+            IrStaticVariable { name: "x".into(), global: true, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(5))] },
+            IrStaticVariable { name: "y".into(), global: true, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(3))] },
+            IrStaticVariable { name: "z".into(), global: true, alignment: 4, init: vec![StaticInit::Zero(4)] },
+        ]);
+        assert_eq!(ir.functions[0].body, vec![
+            IrInstruction::Copy { src: var("y"), dst: var("x") },
+            IrInstruction::Return(var("x")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+    }
+
+    #[test]
+    fn test_lower_storage_classes() {
+        let source = "extern int e; static int s = 2; static int f(void) { static int n; n = n + s; return n; } \
+                      int main(void) { extern int u; return f() + e + u; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.static_variables, vec![
+            IrStaticVariable { name: "n.0".into(), global: false, alignment: 4, init: vec![StaticInit::Zero(4)] },
+            IrStaticVariable { name: "s".into(), global: false, alignment: 4, init: vec![StaticInit::Scalar(Constant::Int(2))] },
+        ]);
+        assert_eq!(ir.extern_variables, vec!["e", "u"]);
+        assert_eq!(ir.static_names().len(), 4);
+        assert!(!ir.functions[0].global && ir.functions[1].global);
+        // The static local is not initialized on every call
+        assert!(matches!(&ir.functions[0].body[0], IrInstruction::Binary { src1, .. } if *src1 == var("n.0")));
+    }
+
+    #[test]
+    fn test_lower_conversions() {
+        let source = "long l; int main(void) { int i = 3; l = i; return l; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.static_variables, vec![
+            IrStaticVariable { name: "l".into(), global: true, alignment: 8, init: vec![StaticInit::Zero(8)] },
+        ]);
+        assert_eq!(ir.functions[0].body, vec![
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(3)), dst: var("i.0") },
+            IrInstruction::SignExtend { src: var("i.0"), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("l") },
+            IrInstruction::Truncate { src: var("l"), dst: var("tmp.1") },
+            IrInstruction::Return(var("tmp.1")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+        assert_eq!(ir.types[&Name::new("tmp.0")], Type::Long);
+        assert_eq!(ir.types[&Name::new("tmp.1")], Type::Int);
+        assert_eq!(value_type(&var("l"), &ir.types), Type::Long);
+    }
+
+    #[test]
+    fn test_lower_unsigned_conversions() {
+        let source = "int main(void) { unsigned int u = 1; int i = u; unsigned long l = u; long m = i; return l < m; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.functions[0].body[..7], [
+            IrInstruction::Copy { src: IrValue::Constant(Constant::UInt(1)), dst: var("u.0") },
+            IrInstruction::Copy { src: var("u.0"), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("i.1") },
+            IrInstruction::ZeroExtend { src: var("u.0"), dst: var("tmp.1") },
+            IrInstruction::Copy { src: var("tmp.1"), dst: var("l.2") },
+            IrInstruction::SignExtend { src: var("i.1"), dst: var("tmp.2") },
+            IrInstruction::Copy { src: var("tmp.2"), dst: var("m.3") },
+        ]);
+        // The comparison converts m to unsigned long, the common type
+        assert!(ir.functions[0].body.contains(&IrInstruction::Copy { src: var("m.3"), dst: var("tmp.3") }));
+        assert_eq!(ir.types[&Name::new("tmp.0")], Type::Int);
+        assert_eq!(ir.types[&Name::new("tmp.3")], Type::ULong);
+    }
+
+    #[test]
+    fn test_lower_double_conversions() {
+        let source = "int main(void) { double d = 1; unsigned long u = d; int i = d + u; return (long) d; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.functions[0].body[..9], [
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Double(1.0)), dst: var("d.0") },
+            IrInstruction::DoubleToUInt { src: var("d.0"), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("u.1") },
+            IrInstruction::UIntToDouble { src: var("u.1"), dst: var("tmp.1") },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("d.0"), src2: var("tmp.1"), dst: var("tmp.2") },
+            IrInstruction::DoubleToInt { src: var("tmp.2"), dst: var("tmp.3") },
+            IrInstruction::Copy { src: var("tmp.3"), dst: var("i.2") },
+            IrInstruction::DoubleToInt { src: var("d.0"), dst: var("tmp.4") },
+            IrInstruction::Truncate { src: var("tmp.4"), dst: var("tmp.5") },
+        ]);
+        assert_eq!(ir.types[&Name::new("tmp.2")], Type::Double);
+        assert_eq!(ir.types[&Name::new("tmp.4")], Type::Long);
+    }
+
+    #[test]
+    fn test_lower_pointers() {
+        let source = "int main(void) { int x = 1; int *p = &x; *p = *p + 2; (*p)++; return *&x; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.functions[0].body[..11], [
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("x.0") },
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("p.1") },
+            IrInstruction::Load { src_ptr: var("p.1"), dst: var("tmp.1") },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("tmp.1"), src2: IrValue::Constant(Constant::Int(2)), dst: var("tmp.2") },
+            IrInstruction::Store { src: var("tmp.2"), dst_ptr: var("p.1") },
+            IrInstruction::Load { src_ptr: var("p.1"), dst: var("tmp.3") },
+            IrInstruction::Copy { src: var("tmp.3"), dst: var("tmp.4") },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("tmp.3"), src2: IrValue::Constant(Constant::Int(1)), dst: var("tmp.5") },
+            IrInstruction::Store { src: var("tmp.5"), dst_ptr: var("p.1") },
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.6") },
+        ]);
+        assert_eq!(ir.types[&Name::new("tmp.0")], Type::Pointer(Box::new(Type::Int)));
+    }
+
+    #[test]
+    fn test_lower_null_checks() {
+        // Neither an array nor `&*p` can be null, so only `*p` is checked
+        let source = "int main(void) { int a[2] = {1, 2}; int *p = a; return *p + a[1] + *&*p; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, Some("prog.c"));
+        let body = &ir.functions[0].body;
+        let check = body.iter().position(|instruction| matches!(instruction, IrInstruction::JumpIfNotZero(..))).unwrap();
+        assert_eq!(body[check..check + 5], [
+            IrInstruction::JumpIfNotZero(var("p.1"), "null_checked.0".into()),
+            IrInstruction::GetAddress { src: var("null_message.0"), dst: var("tmp.1") },
+            IrInstruction::FunCall {
+                name: NULL_DEREFERENCE.into(),
+                args: vec![var("tmp.1"), IrValue::Constant(Constant::ULong(53))],
+                dst: var("tmp.2"),
+            },
+            IrInstruction::Label("null_checked.0".into()),
+            IrInstruction::Load { src_ptr: var("p.1"), dst: var("tmp.3") },
+        ]);
+        let calls = body.iter().filter(|instruction| matches!(instruction, IrInstruction::FunCall { .. })).count();
+        assert_eq!(calls, 1);
+        let message = "prog.c:1:56: runtime error: null pointer dereference\n";
+        assert_eq!(ir.static_constants[0].init, StaticInit::String(message.into(), false));
+        assert_eq!(ir.types[&Name::new("null_message.0")], Type::Array(Box::new(Type::Char), 53));
+        assert_eq!(ir.functions[1].name, NULL_DEREFERENCE);
+        assert!(!ir.functions[1].global);
+        // The handler calls no function the program could define or leave undefined
+        let system_calls: Vec<SystemCall> = ir.functions[1]
+            .body
+            .iter()
+            .filter_map(|instruction| match instruction {
+                IrInstruction::SystemCall { call, .. } => Some(*call),
+                IrInstruction::Trap => None,
+                instruction => panic!("unexpected instruction in the handler: {:?}", instruction),
+            })
+            .collect();
+        assert_eq!(system_calls, [SystemCall::Write, SystemCall::Exit]);
+
+        let source = "int main(void) { int x = 0; return x; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        assert_eq!(generate_ir(ast, &symbols, &structs, false, Some("prog.c")).functions.len(), 1);
+    }
+
+    #[test]
+    fn test_lower_atomics() {
+        let source = "_Atomic int x; int main(void) { x = 1; x += 2; return x++; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        let compare_exchange = |ptr, expected, desired, dst| IrInstruction::CompareExchange {
+            ptr: var(ptr),
+            expected: var(expected),
+            desired: var(desired),
+            dst: var(dst),
+        };
+        assert_eq!(ir.functions[0].body[..16], [
+            IrInstruction::GetAddress { src: var("x"), dst: var("tmp.0") },
+            IrInstruction::AtomicStore { src: IrValue::Constant(Constant::Int(1)), dst_ptr: var("tmp.0") },
+            IrInstruction::GetAddress { src: var("x"), dst: var("tmp.1") },
+            IrInstruction::Label(Name::new("atomic_retry.0")),
+            IrInstruction::AtomicLoad { src_ptr: var("tmp.1"), dst: var("tmp.2") },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("tmp.2"), src2: IrValue::Constant(Constant::Int(2)), dst: var("tmp.3") },
+            compare_exchange("tmp.1", "tmp.2", "tmp.3", "tmp.4"),
+            IrInstruction::JumpIfZero(var("tmp.4"), Name::new("atomic_retry.0")),
+            IrInstruction::GetAddress { src: var("x"), dst: var("tmp.5") },
+            IrInstruction::Label(Name::new("atomic_retry.1")),
+            IrInstruction::AtomicLoad { src_ptr: var("tmp.5"), dst: var("tmp.6") },
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("tmp.6"), src2: IrValue::Constant(Constant::Int(1)), dst: var("tmp.7") },
+            compare_exchange("tmp.5", "tmp.6", "tmp.7", "tmp.8"),
+            IrInstruction::JumpIfZero(var("tmp.8"), Name::new("atomic_retry.1")),
+            IrInstruction::Return(var("tmp.6")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+        // The variable holds a plain int, which only the pointers to it say is atomic
+        assert_eq!(ir.types[&Name::new("x")], Type::Int);
+        assert_eq!(ir.types[&Name::new("tmp.0")], Type::Pointer(Box::new(Type::Atomic(Box::new(Type::Int)))));
+    }
+
+    #[test]
+    fn test_lower_builtins() {
+        let source = "int main(void) { long n = 3; char *p = __builtin_alloca(n); if (__builtin_expect(n, 1)) __builtin_trap(); return __builtin_abs(-2); }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.functions[0].body[1..4], [
+            IrInstruction::Copy { src: var("n.0"), dst: var("tmp.0") },
+            IrInstruction::Alloca { size: var("tmp.0"), dst: var("tmp.1") },
+            IrInstruction::Copy { src: var("tmp.1"), dst: var("p.1") },
+        ]);
+        let int = |value| IrValue::Constant(Constant::Int(value));
+        assert_eq!(ir.functions[0].body[4..], [
+            IrInstruction::JumpIfZero(var("n.0"), "if_end.0".into()),
+            IrInstruction::Trap,
+            IrInstruction::Label("if_end.0".into()),
+            IrInstruction::Unary { op: UnaryOperator::Negate, src: int(2), dst: var("tmp.2") },
+            IrInstruction::Binary { op: BinaryOperator::RightShift, src1: var("tmp.2"), src2: int(31), dst: var("tmp.3") },
+            IrInstruction::Binary { op: BinaryOperator::BitwiseXor, src1: var("tmp.2"), src2: var("tmp.3"), dst: var("tmp.4") },
+            IrInstruction::Binary { op: BinaryOperator::Subtract, src1: var("tmp.4"), src2: var("tmp.3"), dst: var("tmp.5") },
+            IrInstruction::Return(var("tmp.5")),
+            IrInstruction::Return(int(0)),
+        ]);
+        assert_eq!(ir.types[&Name::new("tmp.0")], Type::ULong);
+        assert_eq!(ir.types[&Name::new("tmp.1")], Type::Pointer(Box::new(Type::Char)));
+    }
+
+    #[test]
+    fn test_lower_arrays() {
+        let source = "int main(void) { int a[3] = {7, 8}; int *p = a + 1; return a[2] + (int) (p - a); }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        assert_eq!(ir.functions[0].body[..12], [
+            IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Int(7)), dst: "a.0".into(), offset: 0 },
+            IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Int(8)), dst: "a.0".into(), offset: 4 },
+            IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Int(0)), dst: "a.0".into(), offset: 8 },
+            IrInstruction::GetAddress { src: var("a.0"), dst: var("tmp.0") },
+            IrInstruction::AddPtr { ptr: var("tmp.0"), index: IrValue::Constant(Constant::Long(1)), scale: 4, dst: var("tmp.1") },
+            IrInstruction::Copy { src: var("tmp.1"), dst: var("p.1") },
+            IrInstruction::GetAddress { src: var("a.0"), dst: var("tmp.2") },
+            IrInstruction::AddPtr { ptr: var("tmp.2"), index: IrValue::Constant(Constant::Long(2)), scale: 4, dst: var("tmp.3") },
+            IrInstruction::Load { src_ptr: var("tmp.3"), dst: var("tmp.4") },
+            IrInstruction::GetAddress { src: var("a.0"), dst: var("tmp.5") },
+            IrInstruction::Binary { op: BinaryOperator::Subtract, src1: var("p.1"), src2: var("tmp.5"), dst: var("tmp.6") },
+            IrInstruction::Binary { op: BinaryOperator::Divide, src1: var("tmp.6"), src2: IrValue::Constant(Constant::Long(4)), dst: var("tmp.7") },
+        ]);
+        assert_eq!(ir.types[&Name::new("a.0")], Type::Array(Box::new(Type::Int), 3));
+    }
+    #[test]
+    fn test_lower_strings() {
+        let source = "int puts(char *s); int main(void) { char a[14] = \"hello, world!\"; return puts(\"hi\"); }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        let copy = |src: Constant, offset: usize| IrInstruction::CopyToOffset { src: IrValue::Constant(src), dst: "a.1".into(), offset };
+        assert_eq!(ir.functions[0].body[..5], [
+            copy(Constant::Long(i64::from_le_bytes(*b"hello, w")), 0),
+            copy(Constant::Int(i32::from_le_bytes(*b"orld")), 8),
+            copy(Constant::Char(b'!' as i8), 12),
+            copy(Constant::Char(0), 13),
+            IrInstruction::GetAddress { src: var("string.0"), dst: var("tmp.0") },
+        ]);
+        assert_eq!(ir.static_constants, [
+            IrStaticConstant { name: "string.0".into(), alignment: 1, init: StaticInit::String("hi".to_string(), true) },
+        ]);
+
+        // A wide string is copied as its 4-byte characters, and a constant one is aligned to 4
+        let source = "int main(void) { wchar_t a[3] = L\"ab\"; wchar_t *p = L\"c\"; return 0; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        let copy = |src: Constant, offset: usize| IrInstruction::CopyToOffset { src: IrValue::Constant(src), dst: "a.0".into(), offset };
+        assert_eq!(ir.functions[0].body[..2], [copy(Constant::Long(i64::from(b'a') | i64::from(b'b') << 32), 0), copy(Constant::Int(0), 8)]);
+        assert_eq!(ir.static_constants, [
+            IrStaticConstant { name: "string.0".into(), alignment: 4, init: StaticInit::WideString("c".to_string(), true) },
+        ]);
+    }
+
+    #[test]
+    fn test_lower_structures() {
+        let source = "struct s { char c; long l; }; int main(void) { struct s a = {1, 2}; struct s b; struct s *p = &b; b = a; return p->l; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        let long = |n: i64| IrValue::Constant(Constant::Long(n));
+        assert_eq!(ir.functions[0].body[..16], [
+            IrInstruction::CopyToOffset { src: IrValue::Constant(Constant::Char(1)), dst: "a.1".into(), offset: 0 },
+            IrInstruction::CopyToOffset { src: long(2), dst: "a.1".into(), offset: 8 },
+            IrInstruction::GetAddress { src: var("b.2"), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("p.3") },
+            // Assigning a structure copies it one alignment-sized chunk at a time
+            IrInstruction::GetAddress { src: var("b.2"), dst: var("tmp.1") },
+            IrInstruction::GetAddress { src: var("a.1"), dst: var("tmp.2") },
+            IrInstruction::Load { src_ptr: var("tmp.2"), dst: var("tmp.3") },
+            IrInstruction::Store { src: var("tmp.3"), dst_ptr: var("tmp.1") },
+            IrInstruction::AddPtr { ptr: var("tmp.2"), index: long(8), scale: 1, dst: var("tmp.4") },
+            IrInstruction::AddPtr { ptr: var("tmp.1"), index: long(8), scale: 1, dst: var("tmp.5") },
+            IrInstruction::Load { src_ptr: var("tmp.4"), dst: var("tmp.6") },
+            IrInstruction::Store { src: var("tmp.6"), dst_ptr: var("tmp.5") },
+            IrInstruction::AddPtr { ptr: var("p.3"), index: long(8), scale: 1, dst: var("tmp.7") },
+            IrInstruction::Load { src_ptr: var("tmp.7"), dst: var("tmp.8") },
+            IrInstruction::Truncate { src: var("tmp.8"), dst: var("tmp.9") },
+            IrInstruction::Return(var("tmp.9")),
+        ]);
+        // The backends see a structure as an array of chunks with its size and alignment
+        assert_eq!(ir.types[&Name::new("a.1")], Type::Array(Box::new(Type::Long), 2));
+    }
+
+    #[test]
+    fn test_lower_switch() {
+        let source = "int main(void) { int x = 2; switch (x) case 1: default: case 2: return 3; return 4; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ast = crate::label_loops::label_loops(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, false, None);
+        let int = |n: i32| IrValue::Constant(Constant::Int(n));
+        let label = |name: &str| IrInstruction::Label(Name::from(name));
+        assert_eq!(ir.functions[0].body, [
+            IrInstruction::Copy { src: int(2), dst: var("x.0") },
+            // Each case is compared in turn, and the default is only taken when none match
+            IrInstruction::Binary { op: BinaryOperator::Equal, src1: var("x.0"), src2: int(1), dst: var("tmp.0") },
+            IrInstruction::JumpIfNotZero(var("tmp.0"), "case.1".into()),
+            IrInstruction::Binary { op: BinaryOperator::Equal, src1: var("x.0"), src2: int(2), dst: var("tmp.1") },
+            IrInstruction::JumpIfNotZero(var("tmp.1"), "case.3".into()),
+            IrInstruction::Jump("default.2".into()),
+            label("case.1"),
+            label("default.2"),
+            label("case.3"),
+            IrInstruction::Return(int(3)),
+            label("break_switch.0"),
+            IrInstruction::Return(int(4)),
+            IrInstruction::Return(int(0)),
+        ]);
+    }
+
+    #[test]
+    fn test_lower_locations() {
+        let source = "int main(void) {\n    int x = 2;\n    while (x)\n        x = x - 1;\n    return x;\n}";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        let ast = crate::label_loops::label_loops(ast).unwrap();
+        let ir = generate_ir(ast, &symbols, &structs, true, None);
+        let at = |line, column| IrInstruction::Location(Span { line, column });
+        let int = |n: i32| IrValue::Constant(Constant::Int(n));
+        assert_eq!(ir.functions[0].span, Span { line: 1, column: 5 });
+        assert_eq!(ir.functions[0].body, [
+            at(2, 9),
+            IrInstruction::Copy { src: int(2), dst: var("x.0") },
+            IrInstruction::Label("continue_loop.0".into()),
+            // The condition is marked after the label, so each iteration passes through its line
+            at(3, 12),
+            IrInstruction::JumpIfZero(var("x.0"), "break_loop.0".into()),
+            at(4, 11),
+            IrInstruction::Binary { op: BinaryOperator::Subtract, src1: var("x.0"), src2: int(1), dst: var("tmp.0") },
+            IrInstruction::Copy { src: var("tmp.0"), dst: var("x.0") },
+            IrInstruction::Jump("continue_loop.0".into()),
+            IrInstruction::Label("break_loop.0".into()),
+            at(5, 5),
+            IrInstruction::Return(var("x.0")),
+            // The implicit return has no position of its own
+            IrInstruction::Return(int(0)),
+        ]);
+    }
+}
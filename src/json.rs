@@ -0,0 +1,138 @@
+use std::fmt::{self, Write};
+use serde::Serialize;
+use crate::diagnostics::Diagnostic;
+
+/// A JSON value built by hand, for reports such as the timings, whose numbers keep the
+/// precision they were formatted with.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// A number, kept as written so integers of any size and floats survive unchanged.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Serializes a value to compact JSON, in serde's externally tagged layout: a unit variant
+/// such as `Ret` becomes `"Ret"`, a variant with one field such as `Const(3)` becomes
+/// `{"Const":3}` and a struct becomes an object of its fields. Doubles that JSON has no
+/// numbers for, infinities and NaN, become `null`.
+///
+/// # Arguments
+///
+/// * `value` - The value to be serialized.
+///
+/// # Returns
+///
+/// * `Result<String, Diagnostic>` - The JSON text, or an error if the value has a map whose
+///   keys cannot be JSON keys.
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, Diagnostic> {
+    serde_json::to_string(value).map_err(|e| Diagnostic::error_without_span(format!("Cannot convert to JSON: {}", e)))
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Writes a string literal with the escapes JSON requires.
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for ch in value.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::*;
+    use crate::ast::*;
+
+    #[test]
+    fn test_enums_and_structs() {
+        let token = SpannedToken { token: Token::Identifier("main".into()), span: Span { line: 1, column: 5 } };
+        assert_eq!(to_json(&token).unwrap(), r#"{"token":{"Identifier":"main"},"span":{"line":1,"column":5}}"#);
+        assert_eq!(to_json(&Token::Semicolon).unwrap(), r#""Semicolon""#);
+        let exp = Exp::BinOp(BinaryOperator::Add, Box::new(Exp::Const(Constant::Int(-1))), Box::new(Exp::Const(Constant::Int(2))), Span { line: 1, column: 3 });
+        assert_eq!(
+            to_json(&exp).unwrap(),
+            r#"{"BinOp":["Add",{"Const":{"Int":-1}},{"Const":{"Int":2}},{"line":1,"column":3}]}"#
+        );
+        let exp = Exp::Member { base: Box::new(Exp::Var("s".into(), Span::default())), member: "x".into(), offset: 4, ty: Type::Int, span: Span::default() };
+        assert_eq!(
+            to_json(&exp).unwrap(),
+            r#"{"Member":{"base":{"Var":["s",{"line":0,"column":0}]},"member":"x","offset":4,"ty":"Int","span":{"line":0,"column":0}}}"#
+        );
+    }
+
+    #[test]
+    fn test_non_finite_doubles() {
+        let values = [Constant::Double(f64::INFINITY), Constant::Double(f64::NAN), Constant::Double(-0.5)];
+        assert_eq!(to_json(&values).unwrap(), r#"[{"Double":null},{"Double":null},{"Double":-0.5}]"#);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let map = BTreeMap::from([((1, 2), 3)]);
+        assert_eq!(to_json(&map), Err(Diagnostic::error_without_span("Cannot convert to JSON: key must be a string".to_string())));
+    }
+
+    #[test]
+    fn test_ir_program() {
+        let source = "int g = 3; int main(void) { int x = g; return x + 1; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let ir = crate::ir::generate_ir(ast, &symbols, &structs, false, None);
+        let json = to_json(&ir).unwrap();
+        assert!(json.starts_with(r#"{"functions":[{"name":"main","global":true,"span":{"line":1,"column":16},"params":[],"body":[{"Copy":"#), "{}", json);
+        assert!(json.contains(r#""types":{"g":"Int","tmp.0":"Int","x.0":"Int"}"#), "{}", json);
+    }
+
+    #[test]
+    fn test_display() {
+        let json = Json::Object(vec![
+            ("a\"b".to_string(), Json::Array(vec![Json::Null, Json::Bool(true), Json::Number("1.50".to_string())])),
+            ("c".to_string(), Json::String("\n\u{1}".to_string())),
+        ]);
+        assert_eq!(json.to_string(), r#"{"a\"b":[null,true,1.50],"c":"\n\u0001"}"#);
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+
+/// Gives every loop and `switch` in a program a unique label and annotates each `break`
+/// with the label of the innermost enclosing loop or switch, and each `continue` with that
+/// of the innermost enclosing loop, so lowering knows where they jump. Every `case` and
+/// `default` gets a label too, and is collected into its switch. The labels declared in a
+/// function are prefixed with its name, as every `goto` targeting them is.
+///
+/// # Arguments
+///
+/// * `ast` - The type-checked C AST to be labeled.
+///
+/// # Returns
+///
+/// * `Result<Program, Diagnostic>` - The labeled AST, or an error message if a `break` or
+///   `continue` appears outside of a loop, a `case` or `default` outside of a switch or
+///   more than once in the same one, a label more than once in a function, or a `goto`
+///   to a label its function does not have.
+pub fn label_loops(ast: Program) -> Result<Program, Diagnostic> {
+    let mut next_id = 0;
+    let mut declarations = Vec::new();
+    for declaration in ast.declarations {
+        let function = match declaration {
+            Declaration::Function(function) => function,
+            declaration @ (Declaration::Variable(_) | Declaration::Struct(_)) => {
+                declarations.push(declaration);
+                continue;
+            }
+        };
+        let body = function.body
+            .map(|body| {
+                let mut labels = HashMap::new();
+                for item in &body {
+                    if let BlockItem::Statement(statement) = item {
+                        collect_labels(statement, &function.name, &mut labels)?;
+                    }
+                }
+                body.into_iter()
+                    .map(|item| match item {
+                        BlockItem::Statement(statement) => {
+                            label_statement(statement, None, None, None, &labels, &mut next_id).map(BlockItem::Statement)
+                        }
+                        declaration => Ok(declaration),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        declarations.push(Declaration::Function(FunDecl { body, ..function }));
+    }
+    Ok(Program { declarations })
+}
+
+/// Records the labels declared in a statement and its sub-statements, along with the
+/// unique names they are given.
+///
+/// # Arguments
+///
+/// * `statement` - The statement to be searched.
+/// * `function` - The name of the function the statement is in.
+/// * `labels` - The labels found so far in the function, mapped to their unique names.
+///
+/// # Returns
+///
+/// * `Result<(), Diagnostic>` - An error message if a label is declared more than once.
+fn collect_labels(statement: &Statement, function: &str, labels: &mut HashMap<Name, Name>) -> Result<(), Diagnostic> {
+    match statement {
+        Statement::Labeled(label, body, span) => {
+            if labels.insert(*label, Name::from(format!("{}.{}", function, label))).is_some() {
+                return Err(Diagnostic::error(*span, format!("Duplicate label '{}'", label)));
+            }
+            collect_labels(body, function, labels)
+        }
+        Statement::If(_, then, otherwise) => {
+            collect_labels(then, function, labels)?;
+            otherwise.as_ref().map_or(Ok(()), |otherwise| collect_labels(otherwise, function, labels))
+        }
+        Statement::While { body, .. }
+        | Statement::DoWhile { body, .. }
+        | Statement::For { body, .. }
+        | Statement::Switch { body, .. }
+        | Statement::Case { body, .. }
+        | Statement::Default { body, .. } => collect_labels(body, function, labels),
+        Statement::Compound(items) => items.iter().try_for_each(|item| match item {
+            BlockItem::Statement(statement) => collect_labels(statement, function, labels),
+            BlockItem::Declaration(_) => Ok(()),
+        }),
+        Statement::Return(..)
+        | Statement::Expression(_)
+        | Statement::Goto(..)
+        | Statement::Break(..)
+        | Statement::Continue(..)
+        | Statement::Null => Ok(()),
+    }
+}
+
+/// Labels the loops and switches in a statement and its sub-statements.
+///
+/// # Arguments
+///
+/// * `statement` - The statement to be labeled.
+/// * `break_label` - The label of the innermost loop or switch enclosing the statement, if any.
+/// * `continue_label` - The label of the innermost loop enclosing the statement, if any.
+/// * `cases` - The cases collected so far for the innermost switch enclosing the statement,
+///   if any.
+/// * `labels` - The labels declared in the function, mapped to their unique names.
+/// * `next_id` - The counter used to make labels unique.
+///
+/// # Returns
+///
+/// * `Result<Statement, Diagnostic>` - The labeled statement, or an error message.
+fn label_statement(
+    statement: Statement,
+    break_label: Option<Name>,
+    continue_label: Option<Name>,
+    mut cases: Option<&mut Vec<(Option<Constant>, Name)>>,
+    labels: &HashMap<Name, Name>,
+    next_id: &mut usize,
+) -> Result<Statement, Diagnostic> {
+    match statement {
+        Statement::Goto(label, span) => match labels.get(&label) {
+            Some(&label) => Ok(Statement::Goto(label, span)),
+            None => Err(Diagnostic::error(span, format!("Use of undeclared label '{}'", label))),
+        },
+        Statement::Labeled(label, body, span) => {
+            let body = Box::new(label_statement(*body, break_label, continue_label, cases, labels, next_id)?);
+            Ok(Statement::Labeled(labels[&label], body, span))
+        }
+        Statement::Break(_, span) => match break_label {
+            Some(label) => Ok(Statement::Break(label, span)),
+            None => Err(Diagnostic::error(span, "'break' statement not in a loop or switch")),
+        },
+        Statement::Continue(_, span) => match continue_label {
+            Some(label) => Ok(Statement::Continue(label, span)),
+            None => Err(Diagnostic::error(span, "'continue' statement not in a loop")),
+        },
+        Statement::While { condition, body, .. } => {
+            let label = make_label("loop", next_id);
+            let body = Box::new(label_statement(*body, Some(label), Some(label), cases, labels, next_id)?);
+            Ok(Statement::While { condition, body, label })
+        }
+        Statement::DoWhile { body, condition, .. } => {
+            let label = make_label("loop", next_id);
+            let body = Box::new(label_statement(*body, Some(label), Some(label), cases, labels, next_id)?);
+            Ok(Statement::DoWhile { body, condition, label })
+        }
+        Statement::For { init, condition, post, body, .. } => {
+            let label = make_label("loop", next_id);
+            let body = Box::new(label_statement(*body, Some(label), Some(label), cases, labels, next_id)?);
+            Ok(Statement::For { init, condition, post, body, label })
+        }
+        // The cases of an inner switch belong to it alone, but `continue` still targets the
+        // enclosing loop
+        Statement::Switch { condition, body, span, .. } => {
+            let label = make_label("switch", next_id);
+            let mut cases = Vec::new();
+            let body = Box::new(label_statement(*body, Some(label), continue_label, Some(&mut cases), labels, next_id)?);
+            Ok(Statement::Switch { condition, body, cases, label, span })
+        }
+        Statement::Case { value, body, span, .. } => {
+            let Some(switch_cases) = cases.as_deref_mut() else {
+                return Err(Diagnostic::error(span, "'case' label not in a switch statement"));
+            };
+            let Exp::Const(constant) = value else {
+                unreachable!("the type checker folds the value of every case into a constant")
+            };
+            if switch_cases.iter().any(|(value, _)| *value == Some(constant)) {
+                return Err(Diagnostic::error(span, format!("Duplicate case value '{}'", constant)));
+            }
+            let label = make_label("case", next_id);
+            switch_cases.push((Some(constant), label));
+            let body = Box::new(label_statement(*body, break_label, continue_label, cases, labels, next_id)?);
+            Ok(Statement::Case { value, body, label, span })
+        }
+        Statement::Default { body, span, .. } => {
+            let Some(switch_cases) = cases.as_deref_mut() else {
+                return Err(Diagnostic::error(span, "'default' label not in a switch statement"));
+            };
+            if switch_cases.iter().any(|(value, _)| value.is_none()) {
+                return Err(Diagnostic::error(span, "Multiple default labels in one switch"));
+            }
+            let label = make_label("default", next_id);
+            switch_cases.push((None, label));
+            let body = Box::new(label_statement(*body, break_label, continue_label, cases, labels, next_id)?);
+            Ok(Statement::Default { body, label, span })
+        }
+        Statement::If(condition, then, otherwise) => {
+            let then = Box::new(label_statement(*then, break_label, continue_label, cases.as_deref_mut(), labels, next_id)?);
+            let otherwise = otherwise
+                .map(|otherwise| label_statement(*otherwise, break_label, continue_label, cases, labels, next_id).map(Box::new))
+                .transpose()?;
+            Ok(Statement::If(condition, then, otherwise))
+        }
+        // The cases in a block belong to the switch the block is the body of
+        Statement::Compound(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| match item {
+                    BlockItem::Statement(statement) => {
+                        label_statement(statement, break_label, continue_label, cases.as_deref_mut(), labels, next_id).map(BlockItem::Statement)
+                    }
+                    declaration => Ok(declaration),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Statement::Compound(items))
+        }
+        statement @ (Statement::Return(..) | Statement::Expression(_) | Statement::Null) => Ok(statement),
+    }
+}
+
+/// Creates a fresh label, unique within the program.
+///
+/// # Arguments
+///
+/// * `kind` - What the label is for, such as `loop` or `case`.
+/// * `next_id` - The counter used to make labels unique.
+///
+/// # Returns
+///
+/// * `Name` - The new label.
+fn make_label(kind: &str, next_id: &mut usize) -> Name {
+    *next_id += 1;
+    Name::from(format!("{}.{}", kind, *next_id - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(statement: Statement) -> Program {
+        Program {
+            declarations: vec![Declaration::Function(FunDecl {
+                name: "main".into(),
+                params: vec![],
+                body: Some(vec![BlockItem::Statement(statement)]),
+                ty: Type::Function { params: vec![], ret: Box::new(Type::Int) },
+                storage_class: None,
+                span: Span::default(),
+            })],
+        }
+    }
+
+    fn while_loop(body: Statement) -> Statement {
+        Statement::While { condition: Exp::Const(Constant::Int(1)), body: Box::new(body), label: Name::default() }
+    }
+
+    #[test]
+    fn test_break_and_continue_target_innermost_loop() {
+        // while (1) { if (1) break; else while (1) continue; }
+        let inner = while_loop(Statement::Continue(Name::default(), Span { line: 3, column: 1 }));
+        let outer = while_loop(Statement::If(
+            Exp::Const(Constant::Int(1)),
+            Box::new(Statement::Break(Name::default(), Span { line: 2, column: 7 })),
+            Some(Box::new(inner)),
+        ));
+        let Some(Declaration::Function(function)) = label_loops(program(outer)).unwrap().declarations.pop() else {
+            panic!("Expected a function");
+        };
+        let body = function.body.unwrap();
+        let [BlockItem::Statement(Statement::While { body, label, .. })] = &body[..] else {
+            panic!("Expected a while loop, found {:?}", body);
+        };
+        assert_eq!(label, "loop.0");
+        let Statement::If(_, then, Some(otherwise)) = &**body else {
+            panic!("Expected an if statement, found {:?}", body);
+        };
+        assert!(matches!(&**then, Statement::Break(label, _) if label == "loop.0"));
+        let Statement::While { body, label, .. } = &**otherwise else {
+            panic!("Expected a while loop, found {:?}", otherwise);
+        };
+        assert_eq!(label, "loop.1");
+        assert!(matches!(&**body, Statement::Continue(label, _) if label == "loop.1"));
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let result = label_loops(program(Statement::Break(Name::default(), Span { line: 2, column: 7 })));
+        assert_eq!(result.unwrap_err().to_string(), "2:7: 'break' statement not in a loop or switch");
+        let result = label_loops(program(Statement::Continue(Name::default(), Span { line: 3, column: 1 })));
+        assert_eq!(result.unwrap_err().to_string(), "3:1: 'continue' statement not in a loop");
+    }
+
+    fn switch(body: Statement) -> Statement {
+        Statement::Switch { condition: Exp::Const(Constant::Int(1)), body: Box::new(body), cases: vec![], label: Name::default(), span: Span::default() }
+    }
+
+    fn case(value: i32, body: Statement) -> Statement {
+        Statement::Case { value: Exp::Const(Constant::Int(value)), body: Box::new(body), label: Name::default(), span: Span { line: 4, column: 2 } }
+    }
+
+    fn default(body: Statement) -> Statement {
+        Statement::Default { body: Box::new(body), label: Name::default(), span: Span { line: 5, column: 3 } }
+    }
+
+    #[test]
+    fn test_switch_collects_cases_and_is_targeted_by_break() {
+        // while (1) switch (1) case 1: if (1) break; else default: case 2: continue;
+        let body = Statement::If(
+            Exp::Const(Constant::Int(1)),
+            Box::new(Statement::Break(Name::default(), Span::default())),
+            Some(Box::new(default(case(2, Statement::Continue(Name::default(), Span::default()))))),
+        );
+        let Some(Declaration::Function(function)) = label_loops(program(while_loop(switch(case(1, body))))).unwrap().declarations.pop() else {
+            panic!("Expected a function");
+        };
+        let body = function.body.unwrap();
+        let [BlockItem::Statement(Statement::While { body, label, .. })] = &body[..] else {
+            panic!("Expected a while loop, found {:?}", body);
+        };
+        assert_eq!(label, "loop.0");
+        let Statement::Switch { body, cases, label, .. } = &**body else {
+            panic!("Expected a switch, found {:?}", body);
+        };
+        assert_eq!(label, "switch.1");
+        assert_eq!(cases, &[
+            (Some(Constant::Int(1)), "case.2".into()),
+            (None, "default.3".into()),
+            (Some(Constant::Int(2)), "case.4".into()),
+        ]);
+        let Statement::Case { body, label, .. } = &**body else {
+            panic!("Expected a case, found {:?}", body);
+        };
+        assert_eq!(label, "case.2");
+        let Statement::If(_, then, Some(otherwise)) = &**body else {
+            panic!("Expected an if statement, found {:?}", body);
+        };
+        assert!(matches!(&**then, Statement::Break(label, _) if label == "switch.1"));
+        let Statement::Default { body, .. } = &**otherwise else {
+            panic!("Expected a default label, found {:?}", otherwise);
+        };
+        // `continue` skips the switch and targets the loop around it
+        assert!(matches!(&**body, Statement::Case { body, .. }
+            if matches!(&**body, Statement::Continue(label, _) if label == "loop.0")));
+    }
+
+    #[test]
+    fn test_invalid_case_labels() {
+        let error = |statement: Statement| label_loops(program(statement)).unwrap_err().to_string();
+        assert_eq!(error(case(1, Statement::Null)), "4:2: 'case' label not in a switch statement");
+        assert_eq!(error(default(Statement::Null)), "5:3: 'default' label not in a switch statement");
+        assert_eq!(error(switch(case(1, case(1, Statement::Null)))), "4:2: Duplicate case value '1'");
+        assert_eq!(error(switch(default(default(Statement::Null)))), "5:3: Multiple default labels in one switch");
+        // An inner switch has its own cases
+        assert!(label_loops(program(switch(case(1, switch(case(1, default(Statement::Null))))))).is_ok());
+        // but those in a block belong to the switch around it
+        let block = |statements: Vec<Statement>| Statement::Compound(statements.into_iter().map(BlockItem::Statement).collect());
+        assert_eq!(error(switch(block(vec![case(1, Statement::Null), case(1, Statement::Null)]))), "4:2: Duplicate case value '1'");
+        assert_eq!(error(block(vec![default(Statement::Null)])), "5:3: 'default' label not in a switch statement");
+    }
+
+    #[test]
+    fn test_goto_targets_labels_of_its_function() {
+        // int main(void) { goto end; while (1) end: goto top; top: ; }
+        let goto = |label: &str| Statement::Goto(Name::from(label), Span { line: 6, column: 4 });
+        let labeled = |label: &str, body: Statement| Statement::Labeled(Name::from(label), Box::new(body), Span { line: 7, column: 1 });
+        let mut ast = program(goto("end"));
+        let Declaration::Function(function) = &mut ast.declarations[0] else { unreachable!() };
+        let body = function.body.as_mut().unwrap();
+        body.push(BlockItem::Statement(while_loop(labeled("end", goto("top")))));
+        body.push(BlockItem::Statement(labeled("top", Statement::Null)));
+        let Some(Declaration::Function(function)) = label_loops(ast).unwrap().declarations.pop() else {
+            panic!("Expected a function");
+        };
+        let body = function.body.unwrap();
+        // Labels are prefixed with the function name, as labels are scoped to their function
+        assert!(matches!(&body[0], BlockItem::Statement(Statement::Goto(label, _)) if label == "main.end"));
+        let BlockItem::Statement(Statement::While { body: inner, .. }) = &body[1] else {
+            panic!("Expected a while loop, found {:?}", body[1]);
+        };
+        assert!(matches!(&**inner, Statement::Labeled(label, body, _)
+            if label == "main.end" && matches!(&**body, Statement::Goto(label, _) if label == "main.top")));
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::Labeled(label, _, _)) if label == "main.top"));
+
+        let result = label_loops(program(labeled("a", labeled("a", Statement::Null))));
+        assert_eq!(result.unwrap_err().to_string(), "7:1: Duplicate label 'a'");
+        let result = label_loops(program(labeled("a", goto("b"))));
+        assert_eq!(result.unwrap_err().to_string(), "6:4: Use of undeclared label 'b'");
+    }
+}
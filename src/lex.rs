@@ -1,184 +1,562 @@
-use std::fs::File;
-use std::io::Read;
 use crate::ast::*;
-/// Lexes the contents of the given file into a vector of tokens.
+use crate::diagnostics::Diagnostic;
+/// Lexes C source code into a vector of tokens.
 ///
 /// # Arguments
 ///
-/// * `file` - A `File` object representing the file to be lexed.
+/// * `source` - The source code to be lexed, such as the contents of a file.
 ///
 /// # Returns
 ///
-/// A vector of `Token` objects representing the lexed tokens from the input file.
-pub fn lex (mut file: File) -> Vec<Token> {
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).expect("Could not read file");
-
-    let mut tokens = Vec::new();
-    let mut chars = contents.chars().peekable();
-    while let Some(&ch) = chars.peek() {
-        match ch {
-            '{' => {
-                tokens.push(Token::OpenBrace);
-                chars.next();
-            },
-            '}' => {
-                tokens.push(Token::CloseBrace);
-                chars.next();
-            },
-            '(' => {
-                tokens.push(Token::OpenParenthesis);
-                chars.next();
-            },
-            ')' => {
-                tokens.push(Token::CloseParenthesis);
-                chars.next();
-            },
-            ';' => {
-                tokens.push(Token::Semicolon);
-                chars.next();
-            },
-            '-' => {
-                chars.next();
-                if let Some(&next_ch) = chars.peek() {
-                    if next_ch == '-' {
-                        tokens.push(Token::Decrement);
-                        chars.next();
-                    }
-                }
-                tokens.push(Token::Negation);  
+/// A vector of `SpannedToken` objects, each with the line and column it starts at, or an
+/// error if the source contains something that is not a token.
+pub fn lex(source: &str) -> Result<Vec<SpannedToken>, Diagnostic> {
+    Lexer::new(source).collect()
+}
+
+/// Lexes C source code into its tokens together with the whitespace and comments between
+/// them, keeping the text of each.
+///
+/// # Arguments
+///
+/// * `source` - The source code to be lexed, such as the contents of a file.
+///
+/// # Returns
+///
+/// A vector of `LosslessToken` objects whose text, joined, is `source`, or an error if the
+/// source contains something that is not a token.
+pub fn lex_lossless(source: &str) -> Result<Vec<LosslessToken<'_>>, Diagnostic> {
+    Lexer::new(source).lossless().collect()
+}
+
+/// A lexer that produces the tokens of a source file one at a time, as an iterator of
+/// `Result`s that ends after the first error.
+///
+/// The lexer walks the bytes of the source, since every token starts with an ASCII
+/// character, and only decodes UTF-8 inside identifiers, literals and error messages. The
+/// text of identifiers and constants is copied out of the source in one piece.
+pub struct Lexer<'a> {
+    source: &'a str,
+    /// The byte offset of the next character.
+    pos: usize,
+    line: usize,
+    column: usize,
+    /// Whether an error has been returned, which ends the tokens.
+    failed: bool,
+}
+
+impl<'a> Lexer<'a> {
+    /// Creates a lexer positioned at the start of `source`.
+    pub fn new(source: &'a str) -> Self {
+        Lexer { source, pos: 0, line: 1, column: 1, failed: false }
+    }
+
+    /// Returns the byte `offset` bytes after the next one, without consuming anything.
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.source.as_bytes().get(self.pos + offset).copied()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.peek_at(0)
+    }
+
+    /// Returns the next character, decoding it if it is not ASCII.
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    /// Returns the position of the next character.
+    fn location(&self) -> Span {
+        Span { line: self.line, column: self.column }
+    }
+
+    /// Consumes `count` bytes, which must end on a character boundary. Columns count
+    /// characters, so the continuation bytes of a UTF-8 sequence do not move the column.
+    fn advance(&mut self, count: usize) {
+        for &byte in &self.source.as_bytes()[self.pos..self.pos + count] {
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                self.column += 1;
             }
-            '~' => {
-                tokens.push(Token::BitwiseComplement);
-                chars.next();
+        }
+        self.pos += count;
+    }
+
+    /// Consumes the next character, returning it.
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.peek_char()?;
+        self.advance(ch.len_utf8());
+        Some(ch)
+    }
+
+    /// Consumes bytes while `predicate` holds for them, returning the text consumed.
+    fn take_while(&mut self, predicate: impl Fn(u8) -> bool) -> &'a str {
+        let start = self.pos;
+        let length = self.source.as_bytes()[start..].iter().take_while(|&&byte| predicate(byte)).count();
+        self.advance(length);
+        &self.source[start..self.pos]
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_whitespace_and_comments(&mut self) {
+        while self.trivia().is_some() {}
+    }
+
+    /// Consumes one run of whitespace or one comment, if the next character starts one. An
+    /// unterminated block comment runs to the end of the input.
+    ///
+    /// # Returns
+    ///
+    /// What was consumed, or `None` if the next character starts a token or the input has
+    /// ended.
+    fn trivia(&mut self) -> Option<Trivia> {
+        match (self.peek()?, self.peek_at(1)) {
+            (b' ' | b'\t' | b'\n' | b'\r', _) => {
+                self.take_while(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r'));
+                Some(Trivia::Whitespace)
+            }
+            (b'/', Some(b'/')) => {
+                self.take_while(|byte| byte != b'\n');
+                Some(Trivia::LineComment)
+            }
+            (b'/', Some(b'*')) => {
+                let length = self.source[self.pos + 2..].find("*/").map_or(self.source.len() - self.pos, |end| end + 4);
+                self.advance(length);
+                Some(Trivia::BlockComment)
+            }
+            _ => None,
+        }
+    }
+
+    /// Turns the lexer into one that also returns whitespace and comments, so that the
+    /// text of the pieces it returns adds up to the whole source, as a formatter or a
+    /// syntax highlighter needs.
+    pub fn lossless(self) -> LosslessLexer<'a> {
+        LosslessLexer(self)
+    }
+
+    /// Lexes the next token after any whitespace and comments.
+    ///
+    /// # Returns
+    ///
+    /// The token, `None` at the end of the input, or an error if the next character does
+    /// not start a token or the token is malformed.
+    fn next_token(&mut self) -> Result<Option<SpannedToken>, Diagnostic> {
+        self.skip_whitespace_and_comments();
+        let start = self.location();
+        let Some(byte) = self.peek() else {
+            return Ok(None);
+        };
+        let token = match (byte, self.peek_at(1)) {
+            (b'L', Some(b'\'')) => {
+                self.advance(1);
+                Token::WideCharLiteral(self.char_literal(start, "wide character literal", true)?)
             }
-            '!' => {
-                tokens.push(Token::LogicalNegation);
-                chars.next();
+            (b'L', Some(b'"')) => {
+                self.advance(1);
+                Token::WideStringLiteral(self.string_literal(start, "wide string literal", true)?)
             }
-            '/' => {
-                chars.next();
-                if let Some(&next_ch) = chars.peek() {
-                    match next_ch {
-                        '/' => {
-                            // Skip single-line comment
-                            chars.next();
-                            while let Some(&ch) = chars.peek() {
-                                if ch == '\n' {
-                                    break;
-                                }
-                                chars.next();
-                            }
-                        }
-                        '*' => {
-                            // Skip multi-line comment
-                            chars.next();
-                            while let Some(ch) = chars.next() {
-                                if ch == '*' {
-                                    if let Some(&next_ch) = chars.peek() {
-                                        if next_ch == '/' {
-                                            chars.next();
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            // Handle division or invalid character
-                            panic!("Unexpected character after '/': {:?}", next_ch);
-                        }
-                    }
+            (b'\'', _) => {
+                let value = self.char_literal(start, "character constant", false)?;
+                if !value.is_ascii() {
+                    return Err(Diagnostic::error(start, format!("Character constant '{}' does not fit in a char", value)));
                 }
+                Token::CharLiteral(value)
+            }
+            (b'"', _) => Token::StringLiteral(self.string_literal(start, "string literal", false)?),
+            (b'0'..=b'9', _) | (b'.', Some(b'0'..=b'9')) => self.number(start)?,
+            _ if byte.is_ascii_alphabetic() || byte == b'_' || self.peek_char().is_some_and(char::is_alphanumeric) => {
+                self.identifier_or_keyword()
+            }
+            _ => {
+                let Some((token, length)) = self.punctuator() else {
+                    let ch = self.peek_char().unwrap_or_default();
+                    return Err(Diagnostic::error(start, format!("Unexpected character: {:?}", ch)));
+                };
+                self.advance(length);
+                token
+            }
+        };
+        Ok(Some(SpannedToken { token, span: start }))
+    }
+
+    /// Recognizes the operator or punctuation mark at the next character, taking the
+    /// longest one that matches.
+    ///
+    /// # Returns
+    ///
+    /// The token and its length in bytes, or `None` if no operator starts here.
+    fn punctuator(&self) -> Option<(Token, usize)> {
+        let next = self.peek_at(1);
+        let token = match (self.peek()?, next) {
+            (b'{', _) => (Token::OpenBrace, 1),
+            (b'}', _) => (Token::CloseBrace, 1),
+            (b'(', _) => (Token::OpenParenthesis, 1),
+            (b')', _) => (Token::CloseParenthesis, 1),
+            (b'[', _) => (Token::OpenBracket, 1),
+            (b']', _) => (Token::CloseBracket, 1),
+            (b';', _) => (Token::Semicolon, 1),
+            (b',', _) => (Token::Comma, 1),
+            (b'.', _) => (Token::Dot, 1),
+            (b'?', _) => (Token::QuestionMark, 1),
+            (b':', _) => (Token::Colon, 1),
+            (b'~', _) => (Token::BitwiseComplement, 1),
+            (b'-', Some(b'-')) => (Token::Decrement, 2),
+            (b'-', Some(b'=')) => (Token::SubtractionAssignment, 2),
+            (b'-', Some(b'>')) => (Token::Arrow, 2),
+            (b'-', _) => (Token::Negation, 1),
+            (b'+', Some(b'+')) => (Token::Increment, 2),
+            (b'+', Some(b'=')) => (Token::AdditionAssignment, 2),
+            (b'+', _) => (Token::Addition, 1),
+            (b'*', Some(b'=')) => (Token::MultiplicationAssignment, 2),
+            (b'*', _) => (Token::Multiplication, 1),
+            (b'/', Some(b'=')) => (Token::DivisionAssignment, 2),
+            (b'/', _) => (Token::Division, 1),
+            (b'%', Some(b'=')) => (Token::RemainderAssignment, 2),
+            (b'%', _) => (Token::Remainder, 1),
+            (b'=', Some(b'=')) => (Token::Equal, 2),
+            (b'=', _) => (Token::Assignment, 1),
+            (b'!', Some(b'=')) => (Token::NotEqual, 2),
+            (b'!', _) => (Token::LogicalNegation, 1),
+            (b'&', Some(b'&')) => (Token::LogicalAnd, 2),
+            (b'&', Some(b'=')) => (Token::BitwiseAndAssignment, 2),
+            (b'&', _) => (Token::BitwiseAnd, 1),
+            (b'|', Some(b'|')) => (Token::LogicalOr, 2),
+            (b'|', Some(b'=')) => (Token::BitwiseOrAssignment, 2),
+            (b'|', _) => (Token::BitwiseOr, 1),
+            (b'^', Some(b'=')) => (Token::BitwiseXorAssignment, 2),
+            (b'^', _) => (Token::BitwiseXor, 1),
+            (b'<', Some(b'<')) if self.peek_at(2) == Some(b'=') => (Token::LeftShiftAssignment, 3),
+            (b'<', Some(b'<')) => (Token::LeftShift, 2),
+            (b'<', Some(b'=')) => (Token::LessOrEqual, 2),
+            (b'<', _) => (Token::LessThan, 1),
+            (b'>', Some(b'>')) if self.peek_at(2) == Some(b'=') => (Token::RightShiftAssignment, 3),
+            (b'>', Some(b'>')) => (Token::RightShift, 2),
+            (b'>', Some(b'=')) => (Token::GreaterOrEqual, 2),
+            (b'>', _) => (Token::GreaterThan, 1),
+            _ => return None,
+        };
+        Some(token)
+    }
+
+    /// Lexes a whole identifier, then returns its keyword token if it is one of `KEYWORDS`,
+    /// or an `Identifier` otherwise. Identifiers may contain any alphanumeric character.
+    fn identifier_or_keyword(&mut self) -> Token {
+        let start = self.pos;
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_alphanumeric() || byte == b'_' {
+                self.advance(1);
+            } else if byte.is_ascii() || !self.peek_char().is_some_and(char::is_alphanumeric) {
+                break;
+            } else {
+                self.next_char();
+            }
+        }
+        let identifier = &self.source[start..self.pos];
+        match KEYWORDS.iter().find(|(name, _)| *name == identifier) {
+            Some((_, token)) => token.clone(),
+            None => Token::Identifier(Name::new(identifier)),
+        }
+    }
+
+    /// Lexes an integer or floating-point constant. A decimal point or an exponent makes it
+    /// a floating-point constant, which takes no suffix; an integer constant may end in the
+    /// `u` and `l` suffixes. An integer constant is hexadecimal after a `0x` prefix, binary
+    /// after `0b` and octal after a leading `0`; the token keeps the prefix, in lowercase,
+    /// for the parser to read the value with, and the exponent of a floating-point constant
+    /// is written with a lowercase `e`.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The position of the constant, where errors are reported.
+    ///
+    /// # Returns
+    ///
+    /// The token, or an `Err` if an exponent or a prefix has no digits, or a digit is too
+    /// large for the base of the constant.
+    fn number(&mut self, start: Span) -> Result<Token, Diagnostic> {
+        if let (Some(b'0'), Some(prefix @ (b'x' | b'X' | b'b' | b'B'))) = (self.peek(), self.peek_at(1)) {
+            self.advance(2);
+            let hexadecimal = matches!(prefix, b'x' | b'X');
+            let prefix = if hexadecimal { "0x" } else { "0b" };
+            let digits = if hexadecimal { self.take_while(|byte| byte.is_ascii_hexdigit()) } else { self.take_while(|byte| byte.is_ascii_digit()) };
+            if let Some(digit) = digits.chars().find(|&digit| !hexadecimal && digit > '1') {
+                return Err(Diagnostic::error(start, format!("Invalid digit '{}' in binary constant", digit)));
+            }
+            if digits.is_empty() {
+                return Err(Diagnostic::error(start, format!("Constant \"{}\" has no digits", prefix)));
+            }
+            return Ok(self.integer_suffixes(format!("{}{}", prefix, digits)));
+        }
+
+        let begin = self.pos;
+        self.take_while(|byte| byte.is_ascii_digit());
+        let mut floating = false;
+        if self.peek() == Some(b'.') {
+            floating = true;
+            self.advance(1);
+            self.take_while(|byte| byte.is_ascii_digit());
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            floating = true;
+            self.advance(1);
+            if let Some(b'+' | b'-') = self.peek() {
+                self.advance(1);
+            }
+            if self.take_while(|byte| byte.is_ascii_digit()).is_empty() {
+                let number = self.source[begin..self.pos].replace('E', "e");
+                return Err(Diagnostic::error(start, format!("Exponent of constant \"{}\" has no digits", number)));
             }
-            'i' => {
-                if chars.clone().collect::<String>().starts_with("int") {
-                    //skip 3 and push
-                    for _ in 0..3 {
-                        chars.next();
-                    }
-                    tokens.push(Token::IntKeyword);
-                }else {
-                    lex_identifier_or_keyword(&mut chars, &mut tokens);
+        }
+        let number = &self.source[begin..self.pos];
+        if floating {
+            return Ok(Token::DoubleLiteral(number.replace('E', "e")));
+        }
+        if number.starts_with('0') {
+            if let Some(digit) = number.chars().find(|&digit| digit > '7') {
+                return Err(Diagnostic::error(start, format!("Invalid digit '{}' in octal constant", digit)));
+            }
+        }
+        Ok(self.integer_suffixes(number.to_string()))
+    }
+
+    /// Lexes the suffixes of an integer constant whose digits have been read.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The digits of the constant, with their prefix.
+    ///
+    /// # Returns
+    ///
+    /// The token for the constant, whose kind the suffixes decide.
+    fn integer_suffixes(&mut self, number: String) -> Token {
+        // The suffixes `u` and `l` may come in either order and either case
+        let mut unsigned = false;
+        let mut long = false;
+        loop {
+            match self.peek() {
+                Some(b'u' | b'U') if !unsigned => unsigned = true,
+                Some(b'l' | b'L') if !long => long = true,
+                _ => break,
+            }
+            self.advance(1);
+        }
+        match (unsigned, long) {
+            (false, false) => Token::IntegerLiteral(number),
+            (false, true) => Token::LongLiteral(number),
+            (true, false) => Token::UnsignedLiteral(number),
+            (true, true) => Token::UnsignedLongLiteral(number),
+        }
+    }
+
+    /// Lexes a character constant such as `'x'` or `L'x'`, starting at the opening quote.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The position of the constant, where errors are reported.
+    /// * `kind` - What the constant is called in errors, such as "wide character literal".
+    /// * `wide` - Whether the constant is wide, so that escapes may stand for any character.
+    ///
+    /// # Returns
+    ///
+    /// The character, or an error if the constant is empty or unterminated.
+    fn char_literal(&mut self, start: Span, kind: &str, wide: bool) -> Result<char, Diagnostic> {
+        self.advance(1); // Consume the opening quote
+        let value = match self.next_char() {
+            Some('\\') => self.escape_sequence(wide)?,
+            Some('\'') | Some('\n') | None => {
+                return Err(Diagnostic::error(start, format!("Empty or unterminated {}", kind)));
+            }
+            Some(ch) => ch,
+        };
+        if self.next_char() != Some('\'') {
+            return Err(Diagnostic::error(start, format!("Unterminated {}", kind)));
+        }
+        Ok(value)
+    }
+
+    /// Lexes a string literal such as `"abc"` or `L"abc"`, starting at the opening quote.
+    /// Runs of characters without escapes are copied in one piece.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The position of the literal, where errors are reported.
+    /// * `kind` - What the literal is called in errors, such as "wide string literal".
+    /// * `wide` - Whether the literal is wide, so that escapes may stand for any character.
+    ///
+    /// # Returns
+    ///
+    /// The contents of the literal, or an error if it is unterminated.
+    fn string_literal(&mut self, start: Span, kind: &str, wide: bool) -> Result<String, Diagnostic> {
+        self.advance(1); // Consume the opening quote
+        let mut value = String::new();
+        loop {
+            value.push_str(self.take_while(|byte| !matches!(byte, b'"' | b'\\' | b'\n')));
+            match self.peek() {
+                Some(b'"') => {
+                    self.advance(1);
+                    return Ok(value);
                 }
-            },
-            'r' => {
-                if chars.clone().collect::<String>().starts_with("return") {
-                    for _ in 0..6 {
-                        chars.next();
-                    }
-                    tokens.push(Token::ReturnKeyword);
-                }else {
-                    lex_identifier_or_keyword(&mut chars, &mut tokens);
+                Some(b'\\') => {
+                    self.advance(1);
+                    value.push(self.escape_sequence(wide)?);
                 }
-            },
-            c if c.is_digit(10) => {
-                lex_integer_literal(&mut chars, &mut tokens);
-            },
-            c if c.is_alphanumeric() || c == '_' => {
-                lex_identifier_or_keyword(&mut chars, &mut tokens);
-            },
-            ' ' | '\n' | '\r' => {
-                chars.next();
-            },
-            _ => {
-                panic!("Unexpected character: {:?}", ch);
+                _ => return Err(Diagnostic::error(start, format!("Unterminated {}", kind))),
+            }
+        }
+    }
+
+    /// Decodes the escape sequence following a backslash in a character or string literal:
+    /// a character such as `\n`, one to three octal digits or `x` and any number of hex
+    /// digits. Narrow literals hold text, so a numeric escape in one must stand for an ASCII
+    /// character; the bytes from `\x80` to `\xff` are not supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `wide` - Whether the literal is wide, so that a numeric escape may stand for any
+    ///   Unicode character.
+    ///
+    /// # Returns
+    ///
+    /// The character the escape sequence stands for, or an error if it is not a valid escape
+    /// or its value is out of range.
+    fn escape_sequence(&mut self, wide: bool) -> Result<char, Diagnostic> {
+        let location = self.location();
+        let start = self.pos;
+        let value = match self.peek() {
+            Some(b'0'..=b'7') => {
+                // Only the first three digits belong to the escape
+                let length = self.source.as_bytes()[start..].iter().take(3).take_while(|byte| matches!(byte, b'0'..=b'7')).count();
+                self.advance(length);
+                u32::from_str_radix(&self.source[start..self.pos], 8).ok()
+            }
+            Some(b'x') => {
+                self.advance(1);
+                let digits = self.take_while(|byte| byte.is_ascii_hexdigit());
+                if digits.is_empty() {
+                    return Err(Diagnostic::error(location, "\\x used with no following hex digits"));
+                }
+                u32::from_str_radix(digits, 16).ok()
+            }
+            _ => return self.simple_escape(location),
+        };
+        let text = &self.source[start..self.pos];
+        match value.and_then(char::from_u32) {
+            Some(ch) if wide || ch.is_ascii() => Ok(ch),
+            Some(ch) if (ch as u32) <= 0xFF => {
+                let message = format!("Escape sequence \\{} above \\x7f is not supported in a narrow literal", text);
+                Err(Diagnostic::error(location, message))
             }
+            _ => Err(Diagnostic::error(location, format!("Escape sequence \\{} is out of range", text))),
+        }
+    }
+
+    /// Decodes an escape sequence that stands for one character, such as `\n` or `\'`.
+    fn simple_escape(&mut self, location: Span) -> Result<char, Diagnostic> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('a') => Ok('\u{7}'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('v') => Ok('\u{b}'),
+            Some(ch @ ('\\' | '\'' | '"' | '?')) => Ok(ch),
+            Some(ch) => Err(Diagnostic::error(location, format!("Unknown escape sequence: \\{}", ch))),
+            None => Err(Diagnostic::error(location, "Unterminated escape sequence")),
         }
     }
-    return tokens;
 }
 
-fn lex_identifier_or_keyword(chars: &mut std::iter::Peekable<std::str::Chars>, tokens: &mut Vec<Token>) {
-    let mut identifier = String::new();
-    while let Some(&ch) = chars.peek() {
-        if ch.is_alphanumeric() || ch == '_' {
-            identifier.push(ch);
-            chars.next();
-        }else{
-            break;
+impl Iterator for Lexer<'_> {
+    type Item = Result<SpannedToken, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
         }
+        let token = self.next_token().transpose();
+        self.failed = matches!(token, Some(Err(_)));
+        token
     }
-    tokens.push(Token::Identifier(identifier));
 }
 
-fn lex_integer_literal(chars: &mut std::iter::Peekable<std::str::Chars>, tokens: &mut Vec<Token>) {
-    let mut number = String::new();
-    while let Some(&ch) = chars.peek() {
-        if ch.is_digit(10) {
-            number.push(ch);
-            chars.next();
-        }else{
-            break;
+/// A lexer that returns every piece of the source, trivia included, made by
+/// `Lexer::lossless`. Like `Lexer`, it ends after the first error.
+pub struct LosslessLexer<'a>(Lexer<'a>);
+
+impl<'a> Iterator for LosslessLexer<'a> {
+    type Item = Result<LosslessToken<'a>, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lexer = &mut self.0;
+        if lexer.failed {
+            return None;
         }
+        let (start, span) = (lexer.pos, lexer.location());
+        let kind = match lexer.trivia() {
+            Some(trivia) => LosslessKind::Trivia(trivia),
+            None => match lexer.next_token() {
+                Ok(token) => LosslessKind::Token(token?.token),
+                Err(error) => {
+                    lexer.failed = true;
+                    return Some(Err(error));
+                }
+            },
+        };
+        Some(Ok(LosslessToken { kind, text: &lexer.source[start..lexer.pos], span }))
     }
-    tokens.push(Token::IntegerLiteral(number));
 }
 
+/// The keywords of the language, each with its token. Any other identifier, including one
+/// that merely starts with a keyword such as `intx`, is an `Identifier`.
+const KEYWORDS: &[(&str, Token)] = &[
+    ("int", Token::IntKeyword),
+    ("return", Token::ReturnKeyword),
+    ("void", Token::VoidKeyword),
+    ("if", Token::IfKeyword),
+    ("else", Token::ElseKeyword),
+    ("do", Token::DoKeyword),
+    ("while", Token::WhileKeyword),
+    ("for", Token::ForKeyword),
+    ("break", Token::BreakKeyword),
+    ("continue", Token::ContinueKeyword),
+    ("switch", Token::SwitchKeyword),
+    ("case", Token::CaseKeyword),
+    ("default", Token::DefaultKeyword),
+    ("goto", Token::GotoKeyword),
+    ("static", Token::StaticKeyword),
+    ("extern", Token::ExternKeyword),
+    ("long", Token::LongKeyword),
+    ("signed", Token::SignedKeyword),
+    ("unsigned", Token::UnsignedKeyword),
+    ("double", Token::DoubleKeyword),
+    ("char", Token::CharKeyword),
+    ("wchar_t", Token::WcharKeyword),
+    ("struct", Token::StructKeyword),
+    ("sizeof", Token::SizeOfKeyword),
+    ("_Atomic", Token::AtomicKeyword),
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
-    use std::io::{Write,Seek,SeekFrom};
 
-    fn create_temp_file(content: &str) -> File {
-        let mut file = tempfile::tempfile().expect("Could not create temp file");
-        file.write_all(content.as_bytes()).expect("Could not write to temp file");
-        file.seek(SeekFrom::Start(0)).expect("Could not seek to start of temp file");
-        file
+    fn without_spans(tokens: Vec<SpannedToken>) -> Vec<Token> {
+        tokens.into_iter().map(|spanned| spanned.token).collect()
     }
 
     #[test]
     fn test_empty_file() {
-        let file = create_temp_file("");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("").unwrap());
         assert!(tokens.is_empty());
     }
 
     #[test]
     fn test_single_tokens() {
-        let file = create_temp_file("{ } ( ) ; int return - ~ !");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("{ } ( ) ; int return - ~ !").unwrap());
         let expected = vec![
             Token::OpenBrace,
             Token::CloseBrace,
@@ -196,22 +574,34 @@ mod tests {
 
     #[test]
     fn test_identifier_and_integer_literal() {
-        let file = create_temp_file("foo 123");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("foo 123").unwrap());
         let expected = vec![
-            Token::Identifier("foo".to_string()),
+            Token::Identifier("foo".into()),
             Token::IntegerLiteral("123".to_string()),
         ];
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn test_identifiers_starting_with_keywords() {
+        let tokens = without_spans(lex("intx returner int_ return1 ifelse int").unwrap());
+        let expected = vec![
+            Token::Identifier("intx".into()),
+            Token::Identifier("returner".into()),
+            Token::Identifier("int_".into()),
+            Token::Identifier("return1".into()),
+            Token::Identifier("ifelse".into()),
+            Token::IntKeyword,
+        ];
+        assert_eq!(tokens, expected);
+    }
+
     #[test]
     fn test_mixed_tokens() {
-        let file = create_temp_file("int main() { return 42; }");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("int main() { return 42; }").unwrap());
         let expected = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -225,11 +615,10 @@ mod tests {
 
     #[test]
     fn test_comments() {
-        let file = create_temp_file("int main() { // This is a comment\n return 42; /* This is another comment */ }");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("int main() { // This is a comment\n return 42; /* This is another comment */ }").unwrap());
         let expected = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -243,19 +632,63 @@ mod tests {
 
     #[test]
     fn test_unexpected_character() {
-        let file = create_temp_file("int main() { return 42 @; }");
-        let result = std::panic::catch_unwind(|| {
-            lex(file);
-        });
-        assert!(result.is_err());
+        let error = lex("int main() {\n  return 42 @; }").unwrap_err();
+        assert_eq!(error.to_string(), "2:13: Unexpected character: '@'");
+    }
+
+    #[test]
+    fn test_bitwise_operators_and_shifts() {
+        let tokens = without_spans(lex("a & b && c | d || e ^ f << g >> h < i > j &= |= ^= <<= >>=").unwrap());
+        let identifier = |name: &str| Token::Identifier(Name::from(name));
+        let expected = vec![
+            identifier("a"),
+            Token::BitwiseAnd,
+            identifier("b"),
+            Token::LogicalAnd,
+            identifier("c"),
+            Token::BitwiseOr,
+            identifier("d"),
+            Token::LogicalOr,
+            identifier("e"),
+            Token::BitwiseXor,
+            identifier("f"),
+            Token::LeftShift,
+            identifier("g"),
+            Token::RightShift,
+            identifier("h"),
+            Token::LessThan,
+            identifier("i"),
+            Token::GreaterThan,
+            identifier("j"),
+            Token::BitwiseAndAssignment,
+            Token::BitwiseOrAssignment,
+            Token::BitwiseXorAssignment,
+            Token::LeftShiftAssignment,
+            Token::RightShiftAssignment,
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_lexical_errors() {
+        let error = |source: &str| lex(source).unwrap_err().to_string();
+        assert_eq!(error("x = L'ab';"), "1:5: Unterminated wide character literal");
+        assert_eq!(error("L\"abc"), "1:1: Unterminated wide string literal");
+        assert_eq!(error("L'\\q'"), "1:4: Unknown escape sequence: \\q");
+        assert_eq!(error("return 1e+;"), "1:8: Exponent of constant \"1e+\" has no digits");
+        assert_eq!(error("\"\\x\""), "1:3: \\x used with no following hex digits");
+        assert_eq!(error("'\\400'"), "1:3: Escape sequence \\400 is out of range");
+        assert_eq!(error("\"\\x100\""), "1:3: Escape sequence \\x100 is out of range");
+        assert_eq!(error("L'\\xd800'"), "1:4: Escape sequence \\xd800 is out of range");
+        assert_eq!(error("L'\\x100000000'"), "1:4: Escape sequence \\x100000000 is out of range");
+        assert_eq!(error("\"\\377\""), "1:3: Escape sequence \\377 above \\x7f is not supported in a narrow literal");
     }
     #[test]
     fn test_negation() {
-        let file = create_temp_file("int main() {\n return -42;}");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("int main() {\n return -42;}").unwrap());
         let expected = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -269,11 +702,10 @@ mod tests {
     }
     #[test]
     fn test_bitwise_complement() {
-        let file = create_temp_file("int main() {\n return ~42;}");
-        let tokens = lex(file);
+        let tokens = without_spans(lex("int main() {\n return ~42;}").unwrap());
         let expected = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -285,4 +717,403 @@ mod tests {
         ];
         assert_eq!(tokens, expected);
     }
+    #[test]
+    fn test_wide_literals() {
+        let tokens = without_spans(lex("wchar_t L'a' L'\\n' L\"hi\\t\" Lfoo").unwrap());
+        let expected = vec![
+            Token::WcharKeyword,
+            Token::WideCharLiteral('a'),
+            Token::WideCharLiteral('\n'),
+            Token::WideStringLiteral("hi\t".to_string()),
+            Token::Identifier("Lfoo".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_numeric_escapes() {
+        let tokens = without_spans(lex("\"A\\012B\" '\\012' '\\0' \"\\101\\1011\" \"\\x41\\x4a\" L'\\x263A' L\"\\x263a!\"").unwrap());
+        let expected = vec![
+            // At most three octal digits belong to an escape, so `\1011` is `A1`
+            Token::StringLiteral("A\nB".to_string()),
+            Token::CharLiteral('\n'),
+            Token::CharLiteral('\0'),
+            Token::StringLiteral("AA1".to_string()),
+            Token::StringLiteral("AJ".to_string()),
+            Token::WideCharLiteral('\u{263A}'),
+            Token::WideStringLiteral("\u{263A}!".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+        let tokens = lex("'\\101' x").unwrap();
+        assert_eq!(tokens[1].span, Span { line: 1, column: 8 });
+    }
+
+    #[test]
+    fn test_char_and_string_literals() {
+        let tokens = without_spans(lex("char c = '\\''; puts(\"a\\\"b\\n\");").unwrap());
+        let expected = vec![
+            Token::CharKeyword,
+            Token::Identifier("c".into()),
+            Token::Assignment,
+            Token::CharLiteral('\''),
+            Token::Semicolon,
+            Token::Identifier("puts".into()),
+            Token::OpenParenthesis,
+            Token::StringLiteral("a\"b\n".to_string()),
+            Token::CloseParenthesis,
+            Token::Semicolon,
+        ];
+        assert_eq!(tokens, expected);
+        let error = |source: &str| lex(source).unwrap_err().to_string();
+        assert_eq!(error("''"), "1:1: Empty or unterminated character constant");
+        assert_eq!(error("x = \"abc\ny\";"), "1:5: Unterminated string literal");
+        assert_eq!(error("'\u{e9}'"), "1:1: Character constant '\u{e9}' does not fit in a char");
+    }
+    #[test]
+    fn test_struct_keyword_and_member_operators() {
+        let tokens = without_spans(lex("struct s x; x.a->b - .5").unwrap());
+        let expected = vec![
+            Token::StructKeyword,
+            Token::Identifier("s".into()),
+            Token::Identifier("x".into()),
+            Token::Semicolon,
+            Token::Identifier("x".into()),
+            Token::Dot,
+            Token::Identifier("a".into()),
+            Token::Arrow,
+            Token::Identifier("b".into()),
+            Token::Negation,
+            Token::DoubleLiteral(".5".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_sizeof_keyword() {
+        let tokens = without_spans(lex("sizeof(int) + sizeofx").unwrap());
+        let expected = vec![
+            Token::SizeOfKeyword,
+            Token::OpenParenthesis,
+            Token::IntKeyword,
+            Token::CloseParenthesis,
+            Token::Addition,
+            Token::Identifier("sizeofx".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_atomic_keyword() {
+        let tokens = without_spans(lex("_Atomic int _Atomicx;").unwrap());
+        let expected = vec![
+            Token::AtomicKeyword,
+            Token::IntKeyword,
+            Token::Identifier("_Atomicx".into()),
+            Token::Semicolon,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_decrement_is_a_single_token() {
+        let tokens = without_spans(lex("--x - -y").unwrap());
+        let expected = vec![
+            Token::Decrement,
+            Token::Identifier("x".into()),
+            Token::Negation,
+            Token::Negation,
+            Token::Identifier("y".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_compound_assignment_and_increment() {
+        let tokens = without_spans(lex("a += b++ -= c *= ++d /= e %= f+ +g").unwrap());
+        let expected = vec![
+            Token::Identifier("a".into()),
+            Token::AdditionAssignment,
+            Token::Identifier("b".into()),
+            Token::Increment,
+            Token::SubtractionAssignment,
+            Token::Identifier("c".into()),
+            Token::MultiplicationAssignment,
+            Token::Increment,
+            Token::Identifier("d".into()),
+            Token::DivisionAssignment,
+            Token::Identifier("e".into()),
+            Token::RemainderAssignment,
+            Token::Identifier("f".into()),
+            Token::Addition,
+            Token::Addition,
+            Token::Identifier("g".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_arithmetic_operators() {
+        let tokens = without_spans(lex("1 + 2 * 3 / 4 % 5 - 6 /* comment */ / 7").unwrap());
+        let expected = vec![
+            Token::IntegerLiteral("1".to_string()),
+            Token::Addition,
+            Token::IntegerLiteral("2".to_string()),
+            Token::Multiplication,
+            Token::IntegerLiteral("3".to_string()),
+            Token::Division,
+            Token::IntegerLiteral("4".to_string()),
+            Token::Remainder,
+            Token::IntegerLiteral("5".to_string()),
+            Token::Negation,
+            Token::IntegerLiteral("6".to_string()),
+            Token::Division,
+            Token::IntegerLiteral("7".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_conditional_tokens() {
+        let tokens = without_spans(lex("if (a) b ? c : d; else iffy;").unwrap());
+        let expected = vec![
+            Token::IfKeyword,
+            Token::OpenParenthesis,
+            Token::Identifier("a".into()),
+            Token::CloseParenthesis,
+            Token::Identifier("b".into()),
+            Token::QuestionMark,
+            Token::Identifier("c".into()),
+            Token::Colon,
+            Token::Identifier("d".into()),
+            Token::Semicolon,
+            Token::ElseKeyword,
+            Token::Identifier("iffy".into()),
+            Token::Semicolon,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_relational_operators() {
+        let tokens = without_spans(lex("a<b<=c>d>=e==f!=!g=h").unwrap());
+        let identifier = |name: &str| Token::Identifier(Name::from(name));
+        let expected = vec![
+            identifier("a"),
+            Token::LessThan,
+            identifier("b"),
+            Token::LessOrEqual,
+            identifier("c"),
+            Token::GreaterThan,
+            identifier("d"),
+            Token::GreaterOrEqual,
+            identifier("e"),
+            Token::Equal,
+            identifier("f"),
+            Token::NotEqual,
+            Token::LogicalNegation,
+            identifier("g"),
+            Token::Assignment,
+            identifier("h"),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_logical_operators() {
+        let tokens = without_spans(lex("a&&b||!c").unwrap());
+        let expected = vec![
+            Token::Identifier("a".into()),
+            Token::LogicalAnd,
+            Token::Identifier("b".into()),
+            Token::LogicalOr,
+            Token::LogicalNegation,
+            Token::Identifier("c".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_loop_keywords() {
+        let tokens = without_spans(lex("do while for break continue fortune").unwrap());
+        let expected = vec![
+            Token::DoKeyword,
+            Token::WhileKeyword,
+            Token::ForKeyword,
+            Token::BreakKeyword,
+            Token::ContinueKeyword,
+            Token::Identifier("fortune".into()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_switch_and_goto_keywords() {
+        let tokens = without_spans(lex("switch (x) case 1: default: cases goto").unwrap());
+        let expected = vec![
+            Token::SwitchKeyword,
+            Token::OpenParenthesis,
+            Token::Identifier("x".into()),
+            Token::CloseParenthesis,
+            Token::CaseKeyword,
+            Token::IntegerLiteral("1".to_string()),
+            Token::Colon,
+            Token::DefaultKeyword,
+            Token::Colon,
+            Token::Identifier("cases".into()),
+            Token::GotoKeyword,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_long_keyword_and_literals() {
+        let tokens = without_spans(lex("long x = 10L + 7l + 3 + longer;").unwrap());
+        let expected = vec![
+            Token::LongKeyword,
+            Token::Identifier("x".into()),
+            Token::Assignment,
+            Token::LongLiteral("10".to_string()),
+            Token::Addition,
+            Token::LongLiteral("7".to_string()),
+            Token::Addition,
+            Token::IntegerLiteral("3".to_string()),
+            Token::Addition,
+            Token::Identifier("longer".into()),
+            Token::Semicolon,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_unsigned_keywords_and_literals() {
+        let tokens = without_spans(lex("unsigned signed 1u 2U 3ul 4LU 5lu 6uL").unwrap());
+        let expected = vec![
+            Token::UnsignedKeyword,
+            Token::SignedKeyword,
+            Token::UnsignedLiteral("1".to_string()),
+            Token::UnsignedLiteral("2".to_string()),
+            Token::UnsignedLongLiteral("3".to_string()),
+            Token::UnsignedLongLiteral("4".to_string()),
+            Token::UnsignedLongLiteral("5".to_string()),
+            Token::UnsignedLongLiteral("6".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_integer_bases() {
+        let tokens = without_spans(lex("0x1F 0XabcUL 017 0b101 0B1l 0 09.5 0e1").unwrap());
+        let expected = vec![
+            Token::IntegerLiteral("0x1F".to_string()),
+            Token::UnsignedLongLiteral("0xabc".to_string()),
+            Token::IntegerLiteral("017".to_string()),
+            Token::IntegerLiteral("0b101".to_string()),
+            Token::LongLiteral("0b1".to_string()),
+            Token::IntegerLiteral("0".to_string()),
+            // A leading zero only makes an integer constant octal
+            Token::DoubleLiteral("09.5".to_string()),
+            Token::DoubleLiteral("0e1".to_string()),
+        ];
+        assert_eq!(tokens, expected);
+        let cases = [
+            ("x = 08;", "1:5: Invalid digit '8' in octal constant"),
+            ("x = 0b102;", "1:5: Invalid digit '2' in binary constant"),
+            ("x = 0x;", "1:5: Constant \"0x\" has no digits"),
+            ("x = 0bu;", "1:5: Constant \"0b\" has no digits"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(lex(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+    #[test]
+    fn test_double_keyword_and_literals() {
+        let tokens = without_spans(lex("double d = 1.5 + .25 + 3. + 1e10 + 2.5E-3 + 7e+2;").unwrap());
+        let expected = vec![
+            Token::DoubleKeyword,
+            Token::Identifier("d".into()),
+            Token::Assignment,
+            Token::DoubleLiteral("1.5".to_string()),
+            Token::Addition,
+            Token::DoubleLiteral(".25".to_string()),
+            Token::Addition,
+            Token::DoubleLiteral("3.".to_string()),
+            Token::Addition,
+            Token::DoubleLiteral("1e10".to_string()),
+            Token::Addition,
+            Token::DoubleLiteral("2.5e-3".to_string()),
+            Token::Addition,
+            Token::DoubleLiteral("7e+2".to_string()),
+            Token::Semicolon,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_comma() {
+        let tokens = without_spans(lex("f(a,b)").unwrap());
+        let expected = vec![
+            Token::Identifier("f".into()),
+            Token::OpenParenthesis,
+            Token::Identifier("a".into()),
+            Token::Comma,
+            Token::Identifier("b".into()),
+            Token::CloseParenthesis,
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_brackets() {
+        let tokens = without_spans(lex("a[1][i]").unwrap());
+        let expected = vec![
+            Token::Identifier("a".into()),
+            Token::OpenBracket,
+            Token::IntegerLiteral("1".to_string()),
+            Token::CloseBracket,
+            Token::OpenBracket,
+            Token::Identifier("i".into()),
+            Token::CloseBracket,
+        ];
+        assert_eq!(tokens, expected);
+    }
+    #[test]
+    fn test_token_positions() {
+        let positions: Vec<(usize, usize)> = lex("int main(void) {\n\treturn a<=1; // done\n}\n")
+            .unwrap()
+            .into_iter()
+            .map(|spanned| (spanned.span.line, spanned.span.column))
+            .collect();
+        let expected = vec![
+            (1, 1), (1, 5), (1, 9), (1, 10), (1, 14), (1, 16),
+            (2, 2), (2, 9), (2, 10), (2, 12), (2, 13),
+            (3, 1),
+        ];
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn test_lexer_iterator() {
+        let mut lexer = Lexer::new("/* café */ x = \"é\";\n  é2 @ y");
+        let mut next = || lexer.next().map(|result| result.map(|spanned| (spanned.token, spanned.span.line, spanned.span.column)));
+        assert_eq!(next(), Some(Ok((Token::Identifier("x".into()), 1, 12))));
+        assert_eq!(next(), Some(Ok((Token::Assignment, 1, 14))));
+        assert_eq!(next(), Some(Ok((Token::StringLiteral("é".to_string()), 1, 16))));
+        assert_eq!(next(), Some(Ok((Token::Semicolon, 1, 19))));
+        assert_eq!(next(), Some(Ok((Token::Identifier("é2".into()), 2, 3))));
+        assert_eq!(next().unwrap().unwrap_err().to_string(), "2:6: Unexpected character: '@'");
+        // The tokens end after an error
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn test_lossless_tokens() {
+        let source = "int main(void) { // entry\r\n\treturn L'é' /* a\n b */+1;\n} /* open";
+        let tokens = lex_lossless(source).unwrap();
+        assert_eq!(tokens.iter().map(|token| token.text).collect::<String>(), source);
+        let pieces: Vec<_> = tokens.iter().skip(8).take(7).map(|token| (&token.kind, token.text, token.span.line, token.span.column)).collect();
+        assert_eq!(pieces, vec![
+            (&LosslessKind::Trivia(Trivia::Whitespace), " ", 1, 17),
+            (&LosslessKind::Trivia(Trivia::LineComment), "// entry\r", 1, 18),
+            (&LosslessKind::Trivia(Trivia::Whitespace), "\n\t", 1, 27),
+            (&LosslessKind::Token(Token::ReturnKeyword), "return", 2, 2),
+            (&LosslessKind::Trivia(Trivia::Whitespace), " ", 2, 8),
+            (&LosslessKind::Token(Token::WideCharLiteral('é')), "L'é'", 2, 9),
+            (&LosslessKind::Trivia(Trivia::Whitespace), " ", 2, 13),
+        ]);
+        assert_eq!(tokens[tokens.len() - 1].kind, LosslessKind::Trivia(Trivia::BlockComment));
+        // The token stream without trivia is the one the parser sees
+        let significant: Vec<_> = tokens.into_iter().filter_map(|token| match token.kind {
+            LosslessKind::Token(token) => Some(token),
+            LosslessKind::Trivia(_) => None,
+        }).collect();
+        assert_eq!(significant, lex(source).unwrap().into_iter().map(|spanned| spanned.token).collect::<Vec<_>>());
+        let mut lexer = Lexer::new("x @").lossless();
+        assert_eq!(lexer.nth(2).unwrap().unwrap_err().to_string(), "1:3: Unexpected character: '@'");
+        assert_eq!(lexer.next(), None);
+    }
 }
@@ -0,0 +1,460 @@
+pub mod ast;
+pub mod cfg;
+pub mod diagnostics;
+pub mod intern;
+pub mod ir;
+pub mod json;
+pub mod optimize;
+pub mod label_loops;
+pub mod lex;
+pub mod llvm;
+pub mod parse;
+pub mod profile;
+pub mod regalloc;
+pub mod resolve;
+pub mod typecheck;
+pub mod assembly;
+pub mod aarch64;
+pub mod target;
+pub mod timings;
+pub mod warnings;
+
+use std::path::Path;
+use crate::{
+    ast::{AsmProgram,Program,SpannedToken,StructTable},
+    ir::{declare_null_checks,generate_ir,IrProgram},
+    optimize::{optimize,Optimizations},
+    profile::{apply_profile,instrument,Profile},
+    resolve::resolve_program,
+    typecheck::{typecheck_program,SymbolTable},
+    label_loops::label_loops,
+    warnings::{check_warnings,Warnings},
+    timings::{count_nodes,Timings},
+    assembly::assembly_to_string,
+    aarch64::generate_aarch64,
+    llvm::generate_llvm,
+    diagnostics::{Diagnostic,Diagnostics},
+    intern::{NamesExhausted,MAX_NAMES},
+};
+pub use crate::{
+    lex::{lex,lex_lossless},
+    parse::parse,
+    assembly::generate_assembly,
+    target::{Arch,Os,Target},
+};
+
+/// Options controlling how a translation unit is compiled to assembly.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompileOptions {
+    /// Whether to emit an entry point that calls `main` without libc. Only a file that
+    /// defines `main` gets it, so a program of several files has one.
+    pub freestanding: bool,
+    /// The name of the generated entry point when `freestanding` is set.
+    pub entry: String,
+    /// The platform to generate assembly for.
+    pub target: Target,
+    /// Whether to generate position-independent code, as needed for PIE executables.
+    pub pic: bool,
+    /// The optimizations to run on the IR.
+    pub optimizations: Optimizations,
+    /// The path of the source file to name in debug information, or `None` to emit none.
+    /// With a path, each statement is mapped to its source line and each function gets
+    /// call frame information.
+    pub debug_file: Option<String>,
+    /// Which warnings to report, and whether they fail the compilation.
+    pub warnings: Warnings,
+    /// Whether to count how often each block runs, or to optimize with the counts.
+    pub profile: Profile,
+    /// The path of the source file to name when a null pointer is dereferenced, or `None` to
+    /// not check. With a path, every pointer is compared with null before it is dereferenced,
+    /// and the program reports the source position and aborts if it is null.
+    pub null_checks: Option<String>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            freestanding: false,
+            entry: String::from("_start"),
+            target: Target::host(),
+            pic: true,
+            optimizations: Optimizations::default(),
+            debug_file: None,
+            warnings: Warnings::default(),
+            profile: Profile::Off,
+            null_checks: None,
+        }
+    }
+}
+
+/// The stage of the compiler after which `compile_to` stops.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StopAfter {
+    Lex,
+    Parse,
+    /// Resolving identifiers, type checking, labeling loops and looking for warnings.
+    Check,
+    /// Generating and optimizing the IR.
+    Ir,
+    /// Translating the IR to LLVM IR, instead of generating assembly.
+    Llvm,
+    Codegen,
+}
+
+/// The output of the stage `compile_to` stopped after.
+#[derive(Debug)]
+pub enum StageOutput {
+    Tokens(Vec<SpannedToken>),
+    Ast(Program),
+    /// The checked AST, with its symbol table and the layouts of its structures.
+    Checked(Program, SymbolTable, StructTable),
+    Ir(IrProgram),
+    Llvm(String),
+    /// The assembly AST, for x86-64.
+    Assembly(AsmProgram),
+    /// The assembly code, for AArch64, whose backend has no assembly AST.
+    AssemblyCode(String),
+}
+
+/// Compiles C source code to assembly for `options.target`, running every stage of the compiler.
+///
+/// # Arguments
+///
+/// * `source` - The source code of one translation unit.
+/// * `options` - The options to compile with.
+///
+/// # Returns
+///
+/// The assembly code, ready to be assembled and linked, or an `Err` with the diagnostics
+/// of the first stage that failed. Warnings are dropped unless they are errors.
+pub fn compile(source: &str, options: &CompileOptions) -> Result<String, Diagnostics> {
+    compile_with_warnings(source, options).map(|(assembly_code, _)| assembly_code)
+}
+
+/// Compiles C source code to assembly for `options.target`, like `compile`, and also returns
+/// the warnings found on the way.
+///
+/// # Arguments
+///
+/// * `source` - The source code of one translation unit.
+/// * `options` - The options to compile with.
+///
+/// # Returns
+///
+/// The assembly code and the warnings, or an `Err` with the diagnostics of the first stage
+/// that failed, which are the warnings themselves if they are errors.
+pub fn compile_with_warnings(source: &str, options: &CompileOptions) -> Result<(String, Diagnostics), Diagnostics> {
+    compile_with_timings(source, options, &mut Timings::default())
+}
+
+/// Compiles C source code to assembly for `options.target`, like `compile_with_warnings`, and
+/// records in `timings` what each phase cost, if it is enabled.
+///
+/// # Arguments
+///
+/// * `source` - The source code of one translation unit.
+/// * `options` - The options to compile with.
+/// * `timings` - Where the phases are recorded. The phases before a failure are recorded too.
+///
+/// # Returns
+///
+/// The assembly code and the warnings, or an `Err` with the diagnostics of the first stage
+/// that failed.
+pub fn compile_with_timings(source: &str, options: &CompileOptions, timings: &mut Timings) -> Result<(String, Diagnostics), Diagnostics> {
+    catch_names_exhausted(|| {
+        let (StageOutput::Ir(ir), warnings) = compile_to(source, options, StopAfter::Ir, timings)? else {
+            unreachable!("the pipeline runs up to the IR")
+        };
+        // Only the file that defines `main` gets the entry point, which calls it, so that the
+        // files of one program can be compiled separately and linked together
+        let defines_main = ir.functions.iter().any(|function| function.name == "main" && function.global);
+        let output = generate_code(ir, options, timings)?;
+        let start = timings.start();
+        let os = options.target.os;
+        let (assembly_code, entry_point) = match output {
+            StageOutput::Assembly(assembly_ast) => (
+                assembly_to_string(assembly_ast, os, options.pic, options.debug_file.as_deref()),
+                assembly::entry_point_to_string(&options.entry, os),
+            ),
+            StageOutput::AssemblyCode(assembly_code) => (assembly_code, aarch64::entry_point_to_string(&options.entry, os)),
+            _ => unreachable!("the pipeline runs up to code generation"),
+        };
+        let assembly_code = if options.freestanding && defines_main { entry_point + &assembly_code } else { assembly_code };
+        timings.finish("emit", start, || Some((assembly_code.lines().count(), "lines")));
+        Ok((assembly_code, warnings))
+    })
+}
+
+/// Runs the stages of the compiler on C source code up to `stop_after`, recording in
+/// `timings` what each one cost, and returns the output of the last one. The driver prints
+/// it to show what a stage made of the program.
+///
+/// # Arguments
+///
+/// * `source` - The source code of one translation unit.
+/// * `options` - The options to compile with. Those about the assembly text, such as
+///   `freestanding` and `pic`, are not used.
+/// * `stop_after` - The last stage to run.
+/// * `timings` - Where the phases are recorded. The phases before a failure are recorded too.
+///
+/// # Returns
+///
+/// The output of the last stage and the warnings, which are only looked for from
+/// `StopAfter::Check` on, or an `Err` with the diagnostics of the first stage that failed.
+/// It is an error too if the process has already interned as many names as it can, after
+/// compiling many other programs.
+pub fn compile_to(source: &str, options: &CompileOptions, stop_after: StopAfter, timings: &mut Timings) -> Result<(StageOutput, Diagnostics), Diagnostics> {
+    catch_names_exhausted(|| run_stages(source, options, stop_after, timings))
+}
+
+/// Runs the stages of the compiler up to `stop_after`, for `compile_to`.
+fn run_stages(source: &str, options: &CompileOptions, stop_after: StopAfter, timings: &mut Timings) -> Result<(StageOutput, Diagnostics), Diagnostics> {
+    let start = timings.start();
+    let tokens = lex(source)?;
+    timings.finish("lex", start, || Some((tokens.len(), "tokens")));
+    if stop_after == StopAfter::Lex {
+        return Ok((StageOutput::Tokens(tokens), Diagnostics::default()));
+    }
+    let start = timings.start();
+    let ast = parse(tokens)?;
+    timings.finish("parse", start, || Some((count_nodes(&ast), "nodes")));
+    if stop_after == StopAfter::Parse {
+        return Ok((StageOutput::Ast(ast), Diagnostics::default()));
+    }
+    let start = timings.start();
+    let ast = resolve_program(ast)?;
+    let (ast, mut symbols, structs) = typecheck_program(ast).map_err(Diagnostic::from)?;
+    let ast = label_loops(ast)?;
+    let warnings = check_warnings(&ast, options.warnings)?;
+    timings.finish("semantics", start, || Some((count_nodes(&ast), "nodes")));
+    if stop_after == StopAfter::Check {
+        return Ok((StageOutput::Checked(ast, symbols, structs), warnings));
+    }
+    let start = timings.start();
+    let mut ir = generate_ir(ast, &symbols, &structs, options.debug_file.is_some(), options.null_checks.as_deref());
+    if options.null_checks.is_some() {
+        declare_null_checks(&mut symbols);
+    }
+    match &options.profile {
+        Profile::Off => {}
+        Profile::Generate(path) => instrument(&mut ir, &mut symbols, path),
+        Profile::Use(path) => apply_profile(&mut ir, path)?,
+    }
+    timings.finish("ir", start, || Some((ir.instruction_count(), "instructions")));
+    let start = timings.start();
+    let ir = optimize(ir, options.optimizations);
+    timings.finish("optimize", start, || Some((ir.instruction_count(), "instructions")));
+    if stop_after == StopAfter::Ir {
+        return Ok((StageOutput::Ir(ir), warnings));
+    }
+
+    if stop_after == StopAfter::Llvm {
+        let start = timings.start();
+        let module = generate_llvm(ir, &symbols, options.target);
+        timings.finish("codegen", start, || Some((module.lines().count(), "lines")));
+        return Ok((StageOutput::Llvm(module), warnings));
+    }
+    Ok((generate_code(ir, options, timings)?, warnings))
+}
+
+/// Runs part of the compiler, turning the unwinding `Name::new` does once no more names fit
+/// in the table into an error, so that a process that compiles many programs, such as a
+/// fuzzer, reports it instead of crashing. Other panics are passed on.
+///
+/// # Arguments
+///
+/// * `stages` - The part of the compiler to run.
+///
+/// # Returns
+///
+/// What the stages returned, or an `Err` if they ran out of names.
+fn catch_names_exhausted<T>(stages: impl FnOnce() -> Result<T, Diagnostics>) -> Result<T, Diagnostics> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(stages)) {
+        Ok(result) => result,
+        Err(payload) if payload.is::<NamesExhausted>() => Err(Diagnostic::error_without_span(format!(
+            "Too many distinct names in one process (at most {}); compile the rest in a new process",
+            MAX_NAMES
+        ))
+        .into()),
+        Err(payload) => std::panic::resume_unwind(payload),
+    }
+}
+
+/// Generates the assembly for `options.target` from the optimized IR, recording the phase in
+/// `timings`: an assembly AST for x86-64, and assembly code for AArch64.
+fn generate_code(ir: IrProgram, options: &CompileOptions, timings: &mut Timings) -> Result<StageOutput, Diagnostics> {
+    let start = timings.start();
+    match options.target.arch {
+        Arch::X86_64 => {
+            let assembly_ast = generate_assembly(ir, options.optimizations.allocate_registers)?;
+            timings.finish("codegen", start, || None);
+            Ok(StageOutput::Assembly(assembly_ast))
+        }
+        Arch::Aarch64 => {
+            let assembly_code = generate_aarch64(ir, options.target.os, options.debug_file.as_deref())?;
+            timings.finish("codegen", start, || Some((assembly_code.lines().count(), "lines")));
+            Ok(StageOutput::AssemblyCode(assembly_code))
+        }
+    }
+}
+
+/// Compiles a C source file to assembly for `options.target`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the source file.
+/// * `options` - The options to compile with.
+///
+/// # Returns
+///
+/// The assembly code, or an `Err` with the diagnostics to report.
+pub fn compile_file(path: impl AsRef<Path>, options: &CompileOptions) -> Result<String, Diagnostics> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| Diagnostic::error_without_span(format!("cannot open source file: {}", e)))?;
+    compile(&source, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Name;
+
+    #[test]
+    fn test_compile() {
+        let options = CompileOptions { target: Target { arch: Arch::X86_64, os: Os::Linux }, ..CompileOptions::default() };
+        let assembly = compile("int main(void) { return 2; }", &options).unwrap();
+        assert!(assembly.contains("main:"));
+        assert!(assembly.contains("    movl $2, %eax\n"));
+        assert!(!assembly.contains("_start"));
+
+        let options = CompileOptions { freestanding: true, target: Target { arch: Arch::X86_64, os: Os::Linux }, ..CompileOptions::default() };
+        let assembly = compile("int main(void) { return 2; }", &options).unwrap();
+        assert!(assembly.starts_with(" .globl _start\n"));
+        // Another file of the same program must not define the entry point again
+        let assembly = compile("int helper(void) { return 2; }", &options).unwrap();
+        assert!(!assembly.contains("_start"));
+        let assembly = compile("static int main(void) { return 2; }", &options).unwrap();
+        assert!(!assembly.contains("_start"));
+    }
+
+    #[test]
+    fn test_compile_for_aarch64() {
+        let options = CompileOptions { freestanding: true, target: Target { arch: Arch::Aarch64, os: Os::Linux }, ..CompileOptions::default() };
+        let assembly = compile("int main(void) { return 2; }", &options).unwrap();
+        assert!(assembly.starts_with(" .globl _start\n_start:\n    bl main\n"));
+        assert!(assembly.contains("    mov w0, #2\n"));
+    }
+
+    #[test]
+    fn test_wchar_t_is_int_on_every_target() {
+        // The AArch64 Linux ABI makes wchar_t unsigned, which scc does not follow
+        let options = CompileOptions { target: Target { arch: Arch::Aarch64, os: Os::Linux }, ..CompileOptions::default() };
+        let wchar_t = compile("int negative(wchar_t c) { return c < 0; }", &options).unwrap();
+        assert_eq!(wchar_t, compile("int negative(int c) { return c < 0; }", &options).unwrap());
+    }
+
+    #[test]
+    fn test_compile_for_darwin() {
+        let target = Target { arch: Arch::X86_64, os: Os::Darwin };
+        let options = CompileOptions { target, ..CompileOptions::default() };
+        let assembly = compile("int main(void) { return 2; }", &options).unwrap();
+        assert!(assembly.starts_with(" .globl _main\n_main:\n"));
+        assert!(!assembly.contains(".note.GNU-stack"));
+    }
+
+    #[test]
+    fn test_compile_optimized() {
+        let target = Target { arch: Arch::X86_64, os: Os::Linux };
+        let options = CompileOptions { target, optimizations: Optimizations::level(1), ..CompileOptions::default() };
+        let assembly = compile("int main(void) { return 2 + 3 * 4; }", &options).unwrap();
+        assert!(assembly.contains("    movl $14, %eax\n"));
+        assert!(!assembly.contains("imull"));
+    }
+
+    #[test]
+    fn test_compile_with_debug_information() {
+        let target = Target { arch: Arch::X86_64, os: Os::Linux };
+        let options = CompileOptions { target, debug_file: Some("prog.c".to_string()), ..CompileOptions::default() };
+        let assembly = compile("int main(void) {\n    return 2;\n}", &options).unwrap();
+        assert!(assembly.starts_with(" .file 1 \"prog.c\"\n"));
+        assert!(assembly.contains("    .loc 1 1 5\n    pushq %rbp\n"));
+        assert!(assembly.contains("    .loc 1 2 5\n    movl $2, %eax\n"));
+        assert!(assembly.contains("    .cfi_endproc\n"));
+    }
+
+    #[test]
+    fn test_compile_with_null_checks() {
+        // The program's own `write` and `abort` are not the ones the check reports with
+        let source = "int write(int fd) { return fd; } int abort(int x) { return x; }\n\
+                      int main(void) { int *p = 0; return *p + write(1) + abort(2); }";
+        for target in [Target { arch: Arch::X86_64, os: Os::Linux }, Target { arch: Arch::Aarch64, os: Os::Linux }] {
+            let options = CompileOptions { target, null_checks: Some("prog.c".to_string()), ..CompileOptions::default() };
+            let assembly = compile(source, &options).unwrap();
+            let calls = |name: &str| {
+                let is_call = |line: &&str| matches!(line.split_whitespace().collect::<Vec<_>>()[..], ["call" | "bl", callee] if callee == name);
+                assembly.lines().filter(is_call).count()
+            };
+            assert_eq!((calls("write"), calls("abort")), (1, 1), "unexpected assembly:\n{}", assembly);
+            assert!(assembly.contains("syscall") || assembly.contains("svc #0"));
+            assert!(assembly.contains("prog.c:2:37: runtime error: null pointer dereference"));
+            let module = compile_to(source, &options, StopAfter::Llvm, &mut Timings::default()).unwrap().0;
+            let StageOutput::Llvm(module) = module else { panic!("expected an LLVM module") };
+            assert_eq!(module.matches("@abort(").count(), 2, "unexpected module:\n{}", module);
+            assert!(module.contains("asm sideeffect"));
+        }
+    }
+
+    #[test]
+    fn test_compile_with_warnings() {
+        let source = "int main(void) { int x = 1; return 0; }";
+        let (_, warnings) = compile_with_warnings(source, &CompileOptions::default()).unwrap();
+        assert!(warnings.0.is_empty());
+        let options = CompileOptions { warnings: Warnings { enabled: true, as_errors: false }, ..CompileOptions::default() };
+        let (assembly, warnings) = compile_with_warnings(source, &options).unwrap();
+        assert!(assembly.contains("main"));
+        assert_eq!(warnings.to_string(), "1:22: Variable 'x' is never read");
+        let options = CompileOptions { warnings: Warnings { enabled: true, as_errors: true }, ..CompileOptions::default() };
+        let errors = compile(source, &options).unwrap_err();
+        assert_eq!(errors.0[0].severity, diagnostics::Severity::Error);
+    }
+
+    #[test]
+    fn test_compile_to() {
+        let source = "int main(void) { return 2; }";
+        let options = CompileOptions { target: Target { arch: Arch::X86_64, os: Os::Linux }, ..CompileOptions::default() };
+        let run = |options: &CompileOptions, stop_after| compile_to(source, options, stop_after, &mut Timings::default()).unwrap().0;
+        assert!(matches!(run(&options, StopAfter::Lex), StageOutput::Tokens(tokens) if tokens.len() == 10));
+        assert!(matches!(run(&options, StopAfter::Parse), StageOutput::Ast(ast) if ast.declarations.len() == 1));
+        assert!(matches!(run(&options, StopAfter::Check), StageOutput::Checked(_, symbols, _) if symbols.contains_key(&Name::new("main"))));
+        assert!(matches!(run(&options, StopAfter::Ir), StageOutput::Ir(ir) if ir.functions.len() == 1));
+        assert!(matches!(run(&options, StopAfter::Llvm), StageOutput::Llvm(module) if module.contains("define i32 @main()")));
+        assert!(matches!(run(&options, StopAfter::Codegen), StageOutput::Assembly(assembly) if assembly.functions.len() == 1));
+        let options = CompileOptions { target: Target { arch: Arch::Aarch64, os: Os::Linux }, ..options };
+        assert!(matches!(run(&options, StopAfter::Codegen), StageOutput::AssemblyCode(code) if code.contains("    mov w0, #2\n")));
+
+        let options = CompileOptions { warnings: Warnings { enabled: true, as_errors: false }, ..options };
+        let (_, warnings) = compile_to("int main(void) { int x; return 0; }", &options, StopAfter::Check, &mut Timings::default()).unwrap();
+        assert_eq!(warnings.to_string(), "1:22: Variable 'x' is never read");
+        // Stages after the last one are not run, so their errors are not found
+        assert!(compile_to("int main(void) { return y; }", &options, StopAfter::Parse, &mut Timings::default()).is_ok());
+    }
+
+    #[test]
+    fn test_names_exhausted() {
+        let errors = catch_names_exhausted::<()>(|| std::panic::resume_unwind(Box::new(NamesExhausted))).unwrap_err();
+        assert!(errors.to_string().starts_with("Too many distinct names in one process"));
+        // Any other panic is not an error of the program being compiled
+        let payload = std::panic::catch_unwind(|| catch_names_exhausted::<()>(|| std::panic::resume_unwind(Box::new(7)))).unwrap_err();
+        assert_eq!(payload.downcast_ref::<i32>(), Some(&7));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let errors = compile("int main(void) { return y; }", &CompileOptions::default()).unwrap_err();
+        assert_eq!(errors.to_string(), "1:25: Use of undeclared variable 'y'");
+        let errors = compile("int main(void) { return 1 @ 2; }", &CompileOptions::default()).unwrap_err();
+        assert_eq!(errors.to_string(), "1:27: Unexpected character: '@'");
+        let errors = compile_file("does-not-exist.c", &CompileOptions::default()).unwrap_err();
+        assert!(errors.to_string().starts_with("cannot open source file"));
+    }
+}
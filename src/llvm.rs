@@ -0,0 +1,767 @@
+use std::collections::{BTreeMap,BTreeSet,HashSet};
+use crate::ast::*;
+use crate::ir::*;
+use crate::target::{Arch,Os,SystemCallArgument,Target};
+use crate::typecheck::SymbolTable;
+
+/// Converts an IR program to a textual LLVM IR module, which LLVM's optimizer and code
+/// generators can take over, such as for targets scc has no backend for.
+///
+/// Every IR variable that is not static gets an `alloca` in the entry block of its function,
+/// and each instruction loads its operands from there and stores its result back, as clang
+/// does without optimizations; LLVM's `mem2reg` turns the slots into registers. Operands are
+/// loaded with the type the instruction needs, so a pointer is read as an `i64` for pointer
+/// subtraction. Labels become basic blocks, and a block that falls through to a label ends
+/// with a branch to it. Pointers are opaque `ptr`s.
+///
+/// # Arguments
+///
+/// * `ir` - The IR program to be converted.
+/// * `symbols` - The symbol table built by the type checker, which holds the parameter and
+///   return types of the functions.
+/// * `target` - The platform, which decides the target triple.
+///
+/// # Returns
+///
+/// * `String` - The LLVM IR module.
+pub fn generate_llvm(ir: IrProgram, symbols: &SymbolTable, target: Target) -> String {
+    let mut module = format!("target triple = \"{}\"\n", target_triple(target));
+
+    let mut statics = ir.static_names();
+    statics.extend(ir.static_constants.iter().map(|constant| constant.name));
+    for variable in &ir.static_variables {
+        let linkage = if variable.global { "" } else { "internal " };
+        let (ty, init) = static_init_to_llvm(&variable.init);
+        module.push_str(&format!("@{} = {}global {} {}, align {}\n", variable.name, linkage, ty, init, variable.alignment));
+    }
+    for name in &ir.extern_variables {
+        module.push_str(&format!("@{} = external global {}\n", name, llvm_type(&ir.types[name])));
+    }
+    for constant in &ir.static_constants {
+        let (ty, init) = static_init_to_llvm(std::slice::from_ref(&constant.init));
+        module.push_str(&format!("@{} = private unnamed_addr constant {} {}, align {}\n", constant.name, ty, init, constant.alignment));
+    }
+
+    let defined: HashSet<Name> = ir.functions.iter().map(|function| function.name).collect();
+    let traps = ir.functions.iter().any(|function| function.body.contains(&IrInstruction::Trap));
+    for function in ir.functions {
+        module.push('\n');
+        module.push_str(&function_to_llvm(function, symbols, &statics, &ir.types, target));
+    }
+
+    // The symbol table is unordered, so sort by name to keep the output stable
+    let mut declared: Vec<(&Name, &Type)> = symbols
+        .iter()
+        .filter(|(name, symbol)| matches!(symbol.ty, Type::Function { .. }) && !defined.contains(*name))
+        .map(|(name, symbol)| (name, &symbol.ty))
+        .collect();
+    declared.sort_by_key(|(name, _)| *name);
+    if !declared.is_empty() || traps {
+        module.push('\n');
+    }
+    if traps {
+        module.push_str("declare void @llvm.trap()\n");
+    }
+    for (name, ty) in declared {
+        let Type::Function { params, ret } = ty else { unreachable!("only functions are declared") };
+        let params: Vec<String> = params.iter().map(parameter_type).collect();
+        module.push_str(&format!("declare {} @{}({})\n", return_type(ret), name, params.join(", ")));
+    }
+    module
+}
+
+/// Returns the LLVM target triple of a platform.
+fn target_triple(target: Target) -> &'static str {
+    match (target.arch, target.os) {
+        (Arch::X86_64, Os::Linux) => "x86_64-pc-linux-gnu",
+        (Arch::Aarch64, Os::Linux) => "aarch64-unknown-linux-gnu",
+        (Arch::X86_64, Os::Darwin) => "x86_64-apple-macosx",
+        (Arch::Aarch64, Os::Darwin) => "arm64-apple-macosx",
+    }
+}
+
+/// Returns the LLVM type of a value of the given type. Structures no longer appear in the
+/// types of the IR, and an atomic object is laid out like its unqualified type.
+fn llvm_type(ty: &Type) -> String {
+    match ty {
+        Type::Char | Type::SChar | Type::UChar => "i8".to_string(),
+        Type::Int | Type::UInt => "i32".to_string(),
+        Type::Long | Type::ULong => "i64".to_string(),
+        Type::Double => "double".to_string(),
+        Type::Pointer(_) => "ptr".to_string(),
+        Type::Array(element, count) => format!("[{} x {}]", count, llvm_type(element)),
+        Type::Atomic(ty) => llvm_type(ty),
+        Type::Struct(_) | Type::Function { .. } | Type::DeclaredArray(..) => {
+            unreachable!("the IR only has scalars and arrays of them")
+        }
+    }
+}
+
+/// Returns the LLVM type of a parameter. The calling conventions leave it to the caller or
+/// the callee to widen characters, which the attribute tells LLVM to do.
+fn parameter_type(ty: &Type) -> String {
+    match ty {
+        Type::Char | Type::SChar => "i8 signext".to_string(),
+        Type::UChar => "i8 zeroext".to_string(),
+        _ => llvm_type(ty),
+    }
+}
+
+/// Returns the LLVM type of a return value, whose attributes come before the type.
+fn return_type(ty: &Type) -> String {
+    match ty {
+        Type::Char | Type::SChar => "signext i8".to_string(),
+        Type::UChar => "zeroext i8".to_string(),
+        _ => llvm_type(ty),
+    }
+}
+
+/// Converts a constant to an LLVM constant of the given type, which has the size of the
+/// constant's own type. A pointer can only be given an integer constant, and zero is `null`.
+///
+/// # Arguments
+///
+/// * `constant` - The constant to be converted.
+/// * `ty` - The type the constant is used as.
+///
+/// # Returns
+///
+/// * `String` - The LLVM constant, without its type.
+fn constant_to_llvm(constant: &Constant, ty: &Type) -> String {
+    match (constant, ty) {
+        // LLVM writes doubles exactly as the hexadecimal form of their bits
+        (Constant::Double(value), _) => format!("0x{:016X}", value.to_bits()),
+        (_, Type::Double) => format!("0x{:016X}", (constant.as_i64() as f64).to_bits()),
+        (_, Type::Pointer(_)) if constant.is_zero() => "null".to_string(),
+        (_, Type::Pointer(_)) => format!("inttoptr (i64 {} to ptr)", constant.as_i64()),
+        // Integer constants are written as signed values of their width
+        (_, ty) => match ty.size() {
+            1 => (constant.as_i64() as i8).to_string(),
+            4 => (constant.as_i64() as i32).to_string(),
+            _ => constant.as_i64().to_string(),
+        },
+    }
+}
+
+/// Converts the pieces of a static initializer to an LLVM type and constant. Several pieces
+/// become a packed structure, so they are laid out one after the other as in the assembly.
+///
+/// # Arguments
+///
+/// * `init` - The pieces of the initializer.
+///
+/// # Returns
+///
+/// * `(String, String)` - The LLVM type and the constant.
+fn static_init_to_llvm(init: &[StaticInit]) -> (String, String) {
+    let pieces: Vec<(String, String)> = init
+        .iter()
+        .map(|piece| match piece {
+            StaticInit::Scalar(constant) => (llvm_type(&constant.ty()), constant_to_llvm(constant, &constant.ty())),
+            StaticInit::Zero(size) => (format!("[{} x i8]", size), "zeroinitializer".to_string()),
+            StaticInit::String(string, null_terminated) => {
+                let mut bytes = escape_string(string);
+                if *null_terminated {
+                    bytes.push_str("\\00");
+                }
+                (format!("[{} x i8]", string.len() + usize::from(*null_terminated)), format!("c\"{}\"", bytes))
+            }
+            StaticInit::WideString(string, null_terminated) => {
+                let mut elements: Vec<String> = string.chars().map(|ch| format!("i32 {}", u32::from(ch))).collect();
+                if *null_terminated {
+                    elements.push("i32 0".to_string());
+                }
+                let value = if elements.is_empty() { "zeroinitializer".to_string() } else { format!("[{}]", elements.join(", ")) };
+                (format!("[{} x i32]", elements.len()), value)
+            }
+            StaticInit::Pointer(name) => ("ptr".to_string(), format!("@{}", name)),
+        })
+        .collect();
+    if let [(ty, value)] = pieces.as_slice() {
+        return (ty.clone(), value.clone());
+    }
+    let types: Vec<&str> = pieces.iter().map(|(ty, _)| ty.as_str()).collect();
+    let values: Vec<String> = pieces.iter().map(|(ty, value)| format!("{} {}", ty, value)).collect();
+    (format!("<{{ {} }}>", types.join(", ")), format!("<{{ {} }}>", values.join(", ")))
+}
+
+/// Escapes a string for an LLVM `c"..."` constant: quotes, backslashes and bytes that are
+/// not printable ASCII become a backslash and two hexadecimal digits.
+fn escape_string(string: &str) -> String {
+    string
+        .bytes()
+        .map(|byte| match byte {
+            b'"' | b'\\' => format!("\\{:02X}", byte),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Converts one IR function to an LLVM function definition.
+///
+/// # Arguments
+///
+/// * `function` - The IR function to be converted.
+/// * `symbols` - The symbol table, which holds the function's type.
+/// * `statics` - The names of the variables with static storage, which are globals.
+/// * `types` - The types of the variables and temporaries of the program.
+/// * `target` - The platform, which decides how system calls are made.
+///
+/// # Returns
+///
+/// * `String` - The LLVM code of the function.
+fn function_to_llvm(
+    function: IrFunction,
+    symbols: &SymbolTable,
+    statics: &HashSet<Name>,
+    types: &BTreeMap<Name, Type>,
+    target: Target,
+) -> String {
+    let (param_types, ret) = match symbols.get(&function.name).map(|symbol| &symbol.ty) {
+        Some(Type::Function { params, ret }) => (params.clone(), (**ret).clone()),
+        _ => (function.params.iter().map(|param| value_type(&IrValue::Var(*param), types)).collect(), Type::Int),
+    };
+    let mut emitter = FunctionEmitter {
+        body: String::new(),
+        symbols,
+        statics,
+        types,
+        target,
+        ret: ret.clone(),
+        locals: BTreeSet::new(),
+        next_value: 0,
+        terminated: false,
+    };
+    let mut params = Vec::new();
+    let mut stores = String::new();
+    for (index, (param, ty)) in function.params.iter().zip(&param_types).enumerate() {
+        params.push(format!("{} %p.{}", parameter_type(ty), index));
+        stores.push_str(&format!("  store {} %p.{}, ptr {}\n", llvm_type(ty), index, emitter.slot(*param)));
+    }
+    for instruction in function.body {
+        emitter.instruction(instruction);
+    }
+
+    let linkage = if function.global { "" } else { "internal " };
+    let mut code = format!("define {}{} @{}({}) {{\nentry:\n", linkage, return_type(&ret), function.name, params.join(", "));
+    for name in &emitter.locals {
+        code.push_str(&format!("  %v.{} = alloca {}\n", name, llvm_type(&value_type(&IrValue::Var(*name), types))));
+    }
+    code.push_str(&stores);
+    code.push_str(&emitter.body);
+    code.push_str("}\n");
+    code
+}
+
+/// State kept while emitting the body of one function.
+struct FunctionEmitter<'a> {
+    body: String,
+    symbols: &'a SymbolTable,
+    statics: &'a HashSet<Name>,
+    types: &'a BTreeMap<Name, Type>,
+    target: Target,
+    /// The return type of the function.
+    ret: Type,
+    /// The variables that live in a slot of the function, which are allocated in its entry block.
+    locals: BTreeSet<Name>,
+    next_value: usize,
+    /// Whether the current block has ended with a branch or return, so that the next
+    /// instruction needs a new block.
+    terminated: bool,
+}
+
+impl FunctionEmitter<'_> {
+    /// Appends an instruction, starting a new block if the last one has ended. Such a block
+    /// follows a jump and cannot be reached, but LLVM requires every instruction to be in one.
+    fn emit(&mut self, instruction: &str) {
+        if self.terminated {
+            let label = format!("dead.{}", self.make_id());
+            self.body.push_str(&format!("{}:\n", label));
+            self.terminated = false;
+        }
+        self.body.push_str(&format!("  {}\n", instruction));
+    }
+
+    /// Appends an instruction that ends the current block.
+    fn terminate(&mut self, instruction: &str) {
+        self.emit(instruction);
+        self.terminated = true;
+    }
+
+    fn make_id(&mut self) -> usize {
+        self.next_value += 1;
+        self.next_value - 1
+    }
+
+    /// Creates a fresh name for a value computed by an instruction.
+    fn make_value(&mut self) -> String {
+        format!("%t.{}", self.make_id())
+    }
+
+    /// Returns the address of a variable: its global if it has static storage, otherwise its slot.
+    fn slot(&mut self, name: Name) -> String {
+        if self.statics.contains(&name) {
+            return format!("@{}", name);
+        }
+        self.locals.insert(name);
+        format!("%v.{}", name)
+    }
+
+    fn type_of(&self, value: &IrValue) -> Type {
+        value_type(value, self.types)
+    }
+
+    /// Returns an operand as a value of the given type, loading it from its slot if it is a
+    /// variable. The type must have the size of the operand's own type.
+    fn value(&mut self, value: &IrValue, ty: &Type) -> String {
+        match value {
+            IrValue::Constant(constant) => constant_to_llvm(constant, ty),
+            IrValue::Var(name) => {
+                let slot = self.slot(*name);
+                let result = self.make_value();
+                self.emit(&format!("{} = load {}, ptr {}", result, llvm_type(ty), slot));
+                result
+            }
+        }
+    }
+
+    /// Stores a value of the given type in the variable `dst`.
+    fn store(&mut self, value: &str, ty: &Type, dst: &IrValue) {
+        let IrValue::Var(name) = dst else { unreachable!("results are stored in variables") };
+        let slot = self.slot(*name);
+        self.emit(&format!("store {} {}, ptr {}", llvm_type(ty), value, slot));
+    }
+
+    /// Appends an instruction computing `value`, then stores its result in `dst`.
+    fn compute(&mut self, value: &str, ty: &Type, dst: &IrValue) {
+        let result = self.make_value();
+        self.emit(&format!("{} = {}", result, value));
+        self.store(&result, ty, dst);
+    }
+
+    /// Appends an `i1` comparison of a value with zero, and returns its result.
+    fn compare_with_zero(&mut self, value: &IrValue, equal: bool) -> String {
+        let ty = self.type_of(value);
+        let operand = self.value(value, &ty);
+        let result = self.make_value();
+        let comparison = match (&ty, equal) {
+            // A NaN is not equal to zero
+            (Type::Double, true) => format!("fcmp oeq double {}, 0.0", operand),
+            (Type::Double, false) => format!("fcmp une double {}, 0.0", operand),
+            (Type::Pointer(_), _) => format!("icmp {} ptr {}, null", if equal { "eq" } else { "ne" }, operand),
+            (ty, _) => format!("icmp {} {} {}, 0", if equal { "eq" } else { "ne" }, llvm_type(ty), operand),
+        };
+        self.emit(&format!("{} = {}", result, comparison));
+        result
+    }
+
+    /// Appends a conditional branch to `target`, continuing in a new block otherwise.
+    fn branch(&mut self, condition: &str, target: &str) {
+        let next = format!("next.{}", self.make_id());
+        self.terminate(&format!("br i1 {}, label %l.{}, label %{}", condition, target, next));
+        self.body.push_str(&format!("{}:\n", next));
+        self.terminated = false;
+    }
+
+    /// Converts one IR instruction to LLVM instructions.
+    fn instruction(&mut self, instruction: IrInstruction) {
+        match instruction {
+            IrInstruction::Return(value) => {
+                let ret = self.ret.clone();
+                let value = self.value(&value, &ret);
+                self.terminate(&format!("ret {} {}", llvm_type(&ret), value));
+            }
+            IrInstruction::Trap => {
+                self.emit("call void @llvm.trap()");
+                self.terminate("unreachable");
+            }
+            IrInstruction::Copy { src, dst } => {
+                let ty = self.type_of(&dst);
+                let value = self.value(&src, &ty);
+                self.store(&value, &ty, &dst);
+            }
+            IrInstruction::SignExtend { src, dst } => self.convert("sext", src, dst),
+            IrInstruction::ZeroExtend { src, dst } => self.convert("zext", src, dst),
+            IrInstruction::Truncate { src, dst } => self.convert("trunc", src, dst),
+            IrInstruction::DoubleToInt { src, dst } => self.convert("fptosi", src, dst),
+            IrInstruction::DoubleToUInt { src, dst } => self.convert("fptoui", src, dst),
+            IrInstruction::IntToDouble { src, dst } => self.convert("sitofp", src, dst),
+            IrInstruction::UIntToDouble { src, dst } => self.convert("uitofp", src, dst),
+            IrInstruction::Unary { op, src, dst } => {
+                let ty = match &src {
+                    IrValue::Var(_) => self.type_of(&src),
+                    IrValue::Constant(_) => self.type_of(&dst),
+                };
+                match op {
+                    UnaryOperator::Not => {
+                        let condition = self.compare_with_zero(&src, true);
+                        let dst_type = self.type_of(&dst);
+                        self.compute(&format!("zext i1 {} to {}", condition, llvm_type(&dst_type)), &dst_type, &dst);
+                    }
+                    UnaryOperator::Negate if ty == Type::Double => {
+                        let operand = self.value(&src, &ty);
+                        self.compute(&format!("fneg double {}", operand), &ty, &dst);
+                    }
+                    UnaryOperator::Negate => {
+                        let operand = self.value(&src, &ty);
+                        self.compute(&format!("sub {} 0, {}", llvm_type(&ty), operand), &ty, &dst);
+                    }
+                    UnaryOperator::Complement => {
+                        let operand = self.value(&src, &ty);
+                        self.compute(&format!("xor {} {}, -1", llvm_type(&ty), operand), &ty, &dst);
+                    }
+                }
+            }
+            IrInstruction::Binary { op, src1, src2, dst } => self.binary(op, src1, src2, dst),
+            IrInstruction::Jump(target) => self.terminate(&format!("br label %l.{}", target)),
+            IrInstruction::JumpIfZero(condition, target) => {
+                let condition = self.compare_with_zero(&condition, true);
+                self.branch(&condition, &target);
+            }
+            IrInstruction::JumpIfNotZero(condition, target) => {
+                let condition = self.compare_with_zero(&condition, false);
+                self.branch(&condition, &target);
+            }
+            IrInstruction::Label(name) => {
+                if !self.terminated {
+                    self.terminate(&format!("br label %l.{}", name));
+                }
+                self.body.push_str(&format!("l.{}:\n", name));
+                self.terminated = false;
+            }
+            IrInstruction::FunCall { name, args, dst } => {
+                let (params, ret) = match self.symbols.get(&name).map(|symbol| &symbol.ty) {
+                    Some(Type::Function { params, ret }) => (params.clone(), (**ret).clone()),
+                    _ => (args.iter().map(|arg| self.type_of(arg)).collect(), self.type_of(&dst)),
+                };
+                let args: Vec<String> = args
+                    .iter()
+                    .zip(&params)
+                    .map(|(arg, ty)| format!("{} {}", parameter_type(ty), self.value(arg, ty)))
+                    .collect();
+                self.compute(&format!("call {} @{}({})", return_type(&ret), name, args.join(", ")), &ret, &dst);
+            }
+            IrInstruction::SystemCall { call, args, dst } => {
+                let (number, arguments) = self.target.system_call(call);
+                let (result, registers, clobbers) = match self.target.arch {
+                    Arch::X86_64 => ("rax", ["rdi", "rsi", "rdx", "r10"], "~{rcx},~{r11},~{dirflag},~{fpsr},~{flags}"),
+                    // On Darwin, the kernel may change x1 as well, which returns a second result
+                    Arch::Aarch64 => ("x0", ["x0", "x1", "x2", "x3"], "~{x1},~{cc}"),
+                };
+                // Darwin sets the carry flag on failure, when the result is the error number
+                let (asm, number_register) = match (self.target.arch, self.target.os) {
+                    (Arch::X86_64, Os::Linux) => ("syscall", "rax"),
+                    (Arch::X86_64, Os::Darwin) => ("syscall\\0Ajnc 1f\\0Anegq %rax\\0A1:", "rax"),
+                    (Arch::Aarch64, Os::Linux) => ("svc #0", "x8"),
+                    (Arch::Aarch64, Os::Darwin) => ("svc #0x80\\0Acsneg x0, x0, x0, cc", "x16"),
+                };
+                let mut constraints = vec![format!("={{{}}}", result), format!("{{{}}}", number_register)];
+                let mut operands = vec![format!("i64 {}", number)];
+                for (argument, register) in arguments.iter().zip(registers) {
+                    constraints.push(format!("{{{}}}", register));
+                    operands.push(match argument {
+                        SystemCallArgument::Value(index) => {
+                            let ty = self.type_of(&args[*index]);
+                            format!("{} {}", llvm_type(&ty), self.value(&args[*index], &ty))
+                        }
+                        SystemCallArgument::Constant(value) => format!("i64 {}", value),
+                    });
+                }
+                let ty = self.type_of(&dst);
+                let call = format!(
+                    "call {} asm sideeffect \"{}\", \"{},{},~{{memory}}\"({})",
+                    llvm_type(&ty),
+                    asm,
+                    constraints.join(","),
+                    clobbers,
+                    operands.join(", ")
+                );
+                self.compute(&call, &ty, &dst);
+            }
+            IrInstruction::GetAddress { src, dst } => {
+                let IrValue::Var(name) = src else { unreachable!("only variables have an address") };
+                let address = self.slot(name);
+                self.store(&address, &Type::Pointer(Box::new(Type::Char)), &dst);
+            }
+            IrInstruction::Alloca { size, dst } => {
+                let size = self.value(&size, &Type::ULong);
+                self.compute(&format!("alloca i8, i64 {}, align 16", size), &Type::Pointer(Box::new(Type::Char)), &dst);
+            }
+            IrInstruction::Load { src_ptr, dst } => {
+                let ptr = self.value(&src_ptr, &Type::Pointer(Box::new(Type::Char)));
+                let ty = self.type_of(&dst);
+                self.compute(&format!("load {}, ptr {}", llvm_type(&ty), ptr), &ty, &dst);
+            }
+            IrInstruction::Store { src, dst_ptr } => {
+                let ty = self.type_of(&src);
+                let value = self.value(&src, &ty);
+                let ptr = self.value(&dst_ptr, &Type::Pointer(Box::new(Type::Char)));
+                self.emit(&format!("store {} {}, ptr {}", llvm_type(&ty), value, ptr));
+            }
+            IrInstruction::AtomicLoad { src_ptr, dst } => {
+                let ptr = self.value(&src_ptr, &Type::Pointer(Box::new(Type::Char)));
+                let ty = self.type_of(&dst);
+                let load = format!("load atomic {}, ptr {} seq_cst, align {}", llvm_type(&ty), ptr, ty.size());
+                self.compute(&load, &ty, &dst);
+            }
+            IrInstruction::AtomicStore { src, dst_ptr } => {
+                let ty = self.type_of(&src);
+                let value = self.value(&src, &ty);
+                let ptr = self.value(&dst_ptr, &Type::Pointer(Box::new(Type::Char)));
+                self.emit(&format!("store atomic {} {}, ptr {} seq_cst, align {}", llvm_type(&ty), value, ptr, ty.size()));
+            }
+            IrInstruction::CompareExchange { ptr, expected, desired, dst } => {
+                let ty = self.type_of(&expected);
+                let expected = self.value(&expected, &ty);
+                let desired = self.value(&desired, &ty);
+                let ptr = self.value(&ptr, &Type::Pointer(Box::new(Type::Char)));
+                let pair = self.make_value();
+                let llvm_ty = llvm_type(&ty);
+                self.emit(&format!("{} = cmpxchg ptr {}, {} {}, {} {} seq_cst seq_cst", pair, ptr, llvm_ty, expected, llvm_ty, desired));
+                let stored = self.make_value();
+                self.emit(&format!("{} = extractvalue {{ {}, i1 }} {}, 1", stored, llvm_ty, pair));
+                self.compute(&format!("zext i1 {} to i32", stored), &Type::Int, &dst);
+            }
+            IrInstruction::AddPtr { ptr, index, scale, dst } => {
+                let ptr_type = Type::Pointer(Box::new(Type::Char));
+                let ptr = self.value(&ptr, &ptr_type);
+                let index = self.value(&index, &Type::Long);
+                self.compute(&format!("getelementptr [{} x i8], ptr {}, i64 {}", scale, ptr, index), &ptr_type, &dst);
+            }
+            IrInstruction::CopyToOffset { src, dst, offset } => {
+                let ty = self.type_of(&src);
+                let value = self.value(&src, &ty);
+                let slot = self.slot(dst);
+                let address = self.make_value();
+                self.emit(&format!("{} = getelementptr i8, ptr {}, i64 {}", address, slot, offset));
+                self.emit(&format!("store {} {}, ptr {}", llvm_type(&ty), value, address));
+            }
+            // Debug information is left to the assembly backends
+            IrInstruction::Location(_) => {}
+        }
+    }
+
+    /// Appends a conversion instruction such as `sext` from the type of `src` to that of `dst`.
+    /// Pointers are converted as 8-byte integers.
+    fn convert(&mut self, conversion: &str, src: IrValue, dst: IrValue) {
+        let integer = |ty: Type| if ty.is_pointer() { Type::ULong } else { ty };
+        let (src_type, dst_type) = (integer(self.type_of(&src)), integer(self.type_of(&dst)));
+        let value = self.value(&src, &src_type);
+        if llvm_type(&src_type) == llvm_type(&dst_type) {
+            self.store(&value, &dst_type, &dst);
+            return;
+        }
+        self.compute(
+            &format!("{} {} {} to {}", conversion, llvm_type(&src_type), value, llvm_type(&dst_type)),
+            &dst_type,
+            &dst,
+        );
+    }
+
+    /// Appends the instructions for a binary operation. Both operands have the same type,
+    /// except for the count of a shift, which is resized to the type of the value shifted.
+    /// Arithmetic on pointers, which only pointer subtraction does, is done on 8-byte integers.
+    fn binary(&mut self, op: BinaryOperator, src1: IrValue, src2: IrValue, dst: IrValue) {
+        let ty = match (&src1, &op) {
+            (IrValue::Constant(_), BinaryOperator::LeftShift | BinaryOperator::RightShift) => self.type_of(&src1),
+            (IrValue::Constant(_), _) => self.type_of(&src2),
+            _ => self.type_of(&src1),
+        };
+        let dst_type = self.type_of(&dst);
+        let comparison = match (op, ty == Type::Double, ty.is_signed()) {
+            (BinaryOperator::Equal, true, _) => Some("fcmp oeq"),
+            (BinaryOperator::NotEqual, true, _) => Some("fcmp une"),
+            (BinaryOperator::LessThan, true, _) => Some("fcmp olt"),
+            (BinaryOperator::LessOrEqual, true, _) => Some("fcmp ole"),
+            (BinaryOperator::GreaterThan, true, _) => Some("fcmp ogt"),
+            (BinaryOperator::GreaterOrEqual, true, _) => Some("fcmp oge"),
+            (BinaryOperator::Equal, false, _) => Some("icmp eq"),
+            (BinaryOperator::NotEqual, false, _) => Some("icmp ne"),
+            (BinaryOperator::LessThan, false, true) => Some("icmp slt"),
+            (BinaryOperator::LessThan, false, false) => Some("icmp ult"),
+            (BinaryOperator::LessOrEqual, false, true) => Some("icmp sle"),
+            (BinaryOperator::LessOrEqual, false, false) => Some("icmp ule"),
+            (BinaryOperator::GreaterThan, false, true) => Some("icmp sgt"),
+            (BinaryOperator::GreaterThan, false, false) => Some("icmp ugt"),
+            (BinaryOperator::GreaterOrEqual, false, true) => Some("icmp sge"),
+            (BinaryOperator::GreaterOrEqual, false, false) => Some("icmp uge"),
+            _ => None,
+        };
+        if let Some(comparison) = comparison {
+            let left = self.value(&src1, &ty);
+            let right = self.value(&src2, &ty);
+            let result = self.make_value();
+            self.emit(&format!("{} = {} {} {}, {}", result, comparison, llvm_type(&ty), left, right));
+            self.compute(&format!("zext i1 {} to {}", result, llvm_type(&dst_type)), &dst_type, &dst);
+            return;
+        }
+
+        let ty = if ty.is_pointer() { Type::ULong } else { ty };
+        let double = ty == Type::Double;
+        let instruction = match op {
+            BinaryOperator::Add if double => "fadd",
+            BinaryOperator::Subtract if double => "fsub",
+            BinaryOperator::Multiply if double => "fmul",
+            BinaryOperator::Divide if double => "fdiv",
+            BinaryOperator::Add => "add",
+            BinaryOperator::Subtract => "sub",
+            BinaryOperator::Multiply => "mul",
+            BinaryOperator::Divide if ty.is_signed() => "sdiv",
+            BinaryOperator::Divide => "udiv",
+            BinaryOperator::Remainder if ty.is_signed() => "srem",
+            BinaryOperator::Remainder => "urem",
+            BinaryOperator::BitwiseAnd => "and",
+            BinaryOperator::BitwiseOr => "or",
+            BinaryOperator::BitwiseXor => "xor",
+            BinaryOperator::LeftShift => "shl",
+            BinaryOperator::RightShift if ty.is_signed() => "ashr",
+            BinaryOperator::RightShift => "lshr",
+            _ => unreachable!("&& and || are lowered to jumps and comparisons are handled above"),
+        };
+        let left = self.value(&src1, &ty);
+        let right = if matches!(op, BinaryOperator::LeftShift | BinaryOperator::RightShift) {
+            self.shift_count(&src2, &ty)
+        } else {
+            self.value(&src2, &ty)
+        };
+        self.compute(&format!("{} {} {}, {}", instruction, llvm_type(&ty), left, right), &ty, &dst);
+    }
+
+    /// Returns the count of a shift as a value of the type of the value shifted. Counts are
+    /// never negative, so a narrower count is zero-extended.
+    fn shift_count(&mut self, count: &IrValue, ty: &Type) -> String {
+        let count_type = match count {
+            IrValue::Constant(_) => ty.clone(),
+            IrValue::Var(_) => self.type_of(count),
+        };
+        let value = self.value(count, &count_type);
+        let conversion = match count_type.size().cmp(&ty.size()) {
+            std::cmp::Ordering::Equal => return value,
+            std::cmp::Ordering::Less => "zext",
+            std::cmp::Ordering::Greater => "trunc",
+        };
+        let result = self.make_value();
+        self.emit(&format!("{} = {} {} {} to {}", result, conversion, llvm_type(&count_type), value, llvm_type(ty)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typecheck::Symbol;
+
+    const LINUX: Target = Target { arch: Arch::X86_64, os: Os::Linux };
+
+    fn var(name: &str) -> IrValue {
+        IrValue::Var(Name::from(name))
+    }
+
+    fn program(body: Vec<IrInstruction>, types: &[(&str, Type)]) -> IrProgram {
+        IrProgram {
+            functions: vec![IrFunction {
+                name: "f".into(),
+                global: true,
+                span: Span::default(),
+                params: vec!["a".into()],
+                body,
+            }],
+            static_variables: vec![],
+            extern_variables: vec![],
+            static_constants: vec![],
+            types: types.iter().map(|&(name, ref ty)| (Name::new(name), ty.clone())).collect(),
+        }
+    }
+
+    fn function_symbol(params: Vec<Type>, ret: Type) -> Symbol {
+        Symbol {
+            ty: Type::Function { params, ret: Box::new(ret) },
+            defined: true,
+            initial_value: None,
+            global: true,
+        }
+    }
+
+    #[test]
+    fn test_function() {
+        let ir = program(
+            vec![
+                IrInstruction::Binary { op: BinaryOperator::LessThan, src1: var("a"), src2: IrValue::Constant(Constant::Long(3)), dst: var("t") },
+                IrInstruction::JumpIfZero(var("t"), "end".into()),
+                IrInstruction::Return(IrValue::Constant(Constant::Int(1))),
+                IrInstruction::Label("end".into()),
+                IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+            ],
+            &[("a", Type::ULong), ("t", Type::Int)],
+        );
+        let symbols = SymbolTable::from([("f".into(), function_symbol(vec![Type::ULong], Type::Int))]);
+        let expected = "\
+target triple = \"x86_64-pc-linux-gnu\"
+
+define i32 @f(i64 %p.0) {
+entry:
+  %v.a = alloca i64
+  %v.t = alloca i32
+  store i64 %p.0, ptr %v.a
+  %t.0 = load i64, ptr %v.a
+  %t.1 = icmp ult i64 %t.0, 3
+  %t.2 = zext i1 %t.1 to i32
+  store i32 %t.2, ptr %v.t
+  %t.3 = load i32, ptr %v.t
+  %t.4 = icmp eq i32 %t.3, 0
+  br i1 %t.4, label %l.end, label %next.5
+next.5:
+  ret i32 1
+l.end:
+  ret i32 0
+}
+";
+        assert_eq!(generate_llvm(ir, &symbols, LINUX), expected);
+    }
+
+    #[test]
+    fn test_trap() {
+        let ir = program(
+            vec![IrInstruction::Trap, IrInstruction::Return(IrValue::Constant(Constant::Int(0)))],
+            &[],
+        );
+        let symbols = SymbolTable::from([("f".into(), function_symbol(vec![], Type::Int))]);
+        let module = generate_llvm(ir, &symbols, LINUX);
+        assert!(module.contains("  call void @llvm.trap()\n  unreachable\ndead.0:\n  ret i32 0\n"), "unexpected module:\n{}", module);
+        assert!(module.ends_with("\ndeclare void @llvm.trap()\n"), "unexpected module:\n{}", module);
+    }
+
+    #[test]
+    fn test_globals_and_declarations() {
+        let mut ir = program(
+            vec![
+                IrInstruction::FunCall { name: "putchar".into(), args: vec![var("a")], dst: var("t") },
+                IrInstruction::Return(var("t")),
+            ],
+            &[("a", Type::Char), ("t", Type::Int)],
+        );
+        ir.static_variables.push(IrStaticVariable {
+            name: "counter".into(),
+            global: false,
+            alignment: 8,
+            init: vec![StaticInit::Scalar(Constant::Int(1)), StaticInit::Zero(4)],
+        });
+        ir.static_constants.push(IrStaticConstant {
+            name: "string.0".into(),
+            alignment: 1,
+            init: StaticInit::String("say \"hi\"\n".into(), true),
+        });
+        let symbols = SymbolTable::from([
+            ("f".into(), function_symbol(vec![Type::Char], Type::Int)),
+            ("putchar".into(), function_symbol(vec![Type::Char], Type::UChar)),
+        ]);
+        let module = generate_llvm(ir, &symbols, Target { arch: Arch::Aarch64, os: Os::Darwin });
+        assert!(module.starts_with("target triple = \"arm64-apple-macosx\"\n"));
+        assert!(module.contains("@counter = internal global <{ i32, [4 x i8] }> <{ i32 1, [4 x i8] zeroinitializer }>, align 8\n"));
+        assert!(module.contains("@string.0 = private unnamed_addr constant [10 x i8] c\"say \\22hi\\22\\0A\\00\", align 1\n"));
+        assert!(module.contains("define i32 @f(i8 signext %p.0) {"));
+        assert!(module.contains("  %t.1 = call zeroext i8 @putchar(i8 signext %t.0)\n  store i8 %t.1, ptr %v.t\n"));
+        assert!(module.ends_with("\ndeclare zeroext i8 @putchar(i8 signext)\n"), "unexpected module:\n{}", module);
+    }
+}
@@ -1,68 +1,415 @@
-mod ast;
-mod lex;
-mod parse;
-mod assembly;
+mod cli;
+mod runner;
 
-use std::fs::File;
-use std::io::stdin;
+use std::io::IsTerminal;
+use std::path::Path;
 use std::process::Command;
-use crate::{
-    lex::lex, 
-    parse::parse, 
-    assembly::{generate_assembly,assembly_to_string},
-    ast::*,
+use scc::{
+    parse::pretty_print,
+    compile_to,
+    compile_with_timings,
+    CompileOptions,
+    StageOutput,
+    StopAfter,
+    Arch,
+    Os,
+    Target,
+    json::to_json,
+    diagnostics::{render,Diagnostic,Diagnostics},
+    timings::{CountingAllocator,Timings},
 };
+use crate::cli::{default_output,parse_args,ColorChoice,Format,Options,Stage,USAGE};
+
+// Counting allocations lets --timings report the peak memory of each phase
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn main() {
-     // Read the file name from standard input
-     let mut input = String::new();
-     stdin().read_line(&mut input).expect("Failed to read input");
-     let input = input.trim(); // trim the input to remove any extraneous whitespace or newlines
- 
-     // Open the file and lex its contents
-     let file: File = File::open(input).expect("Failed to open file");
-     let tokens: Vec<Token> = lex(file);
-     // Parse the tokens into an AST
-     match parse(tokens) {
-        Ok(ast) => {
-            // Generate assembly from the AST
-            match generate_assembly(ast) {
-                Ok(assembly_ast) => {
-                    // Convert the assembly AST to assembly code
-                    let assembly_code = assembly_to_string(assembly_ast);
-
-                    // Write the assembly to a file
-                    let assembly_file = "assembly.s";
-                    if let Err(e) = std::fs::write(assembly_file, &assembly_code) {
-                        eprintln!("Failed to write assembly to file: {}", e);
-                        return;
-                    }
-
-                    // Assemble the file into an object file
-                    let output = Command::new("gcc")
-                        .args(&[assembly_file, "-o", "out"])
-                        .output()
-                        .expect("Failed to execute assembler");
-
-                    if !output.status.success() {
-                        eprintln!("Assembler error: {}", String::from_utf8_lossy(&output.stderr));
-                        return;
-                    }
-
-                    // Clean up intermediate files
-                    if let Err(e) = std::fs::remove_file(assembly_file) {
-                        eprintln!("Failed to delete assembly file: {}", e);
-                    }
-
-                    println!("Executable created successfully.");
-                }
-                Err(e) => {
-                    eprintln!("Assembly generation error: {}", e);
-                }
+    let options: Options = match parse_args(std::env::args().skip(1)) {
+        Ok(cli::Command::Compile(options)) => options,
+        Ok(cli::Command::Test(options)) => match runner::run_tests(&options) {
+            Ok(summary) => {
+                print!("{}", summary.report());
+                std::process::exit(if summary.succeeded() { 0 } else { 1 });
             }
+            Err(e) => {
+                eprintln!("scc: {}", e);
+                std::process::exit(2);
+            }
+        },
+        Ok(cli::Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("scc: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
         }
+    };
+
+    // Assembly and object files that are not kept go in a new directory only this user can
+    // use, so that nobody can make scc write through a file or link they put in its place
+    let needs_scratch = !options.keep_intermediates && matches!(options.stop_after, Stage::Object | Stage::Link);
+    let scratch = match needs_scratch.then(|| tempfile::Builder::new().prefix("scc-").tempdir()).transpose() {
+        Ok(scratch) => scratch,
         Err(e) => {
-            eprintln!("Parse error: {}", e);
+            eprintln!("scc: Cannot create a temporary directory: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let scratch_path = scratch.as_ref().map(|scratch| scratch.path());
+
+    // Every C file is compiled even after one fails, so all of their errors are reported
+    let mut failed = false;
+    let mut objects: Vec<String> = Vec::new();
+    for (index, input) in options.inputs.iter().enumerate() {
+        if !input.ends_with(".c") {
+            objects.push(input.clone());
+            continue;
+        }
+        let source = match read_source(&options, input) {
+            Ok(source) => source,
+            Err(diagnostic) => {
+                report(&options, input, &diagnostic.into(), "");
+                failed = true;
+                continue;
+            }
+        };
+        let mut timings = Timings::new(options.timings.is_some());
+        match run(&options, scratch_path, index, input, &source, &mut timings) {
+            Ok(object) => objects.extend(object),
+            Err(diagnostics) => {
+                report(&options, input, &diagnostics, &source);
+                failed = true;
+            }
+        }
+        print_timings(&options, input, &timings);
+    }
+
+    if !failed && options.stop_after == Stage::Link {
+        if let Err(diagnostic) = link(&options, &objects) {
+            report(&options, "scc", &diagnostic.into(), "");
+            failed = true;
+        }
+    }
+    // Exiting does not run destructors, so the directory is deleted here
+    if let Some(Err(e)) = scratch.map(|scratch| scratch.close()) {
+        eprintln!("Failed to delete temporary files: {}", e);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Prints diagnostics to stderr, each with the offending source line.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `file_name` - The name of the file the diagnostics are about.
+/// * `diagnostics` - The diagnostics to be printed.
+/// * `source` - The source the diagnostics refer to, after preprocessing if it was requested.
+fn report(options: &Options, file_name: &str, diagnostics: &Diagnostics, source: &str) {
+    let color = match options.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    };
+    for diagnostic in &diagnostics.0 {
+        eprint!("{}", render(diagnostic, file_name, source, color));
+    }
+}
+
+/// Prints what each phase of compiling an input cost to stderr, if `--timings` was given.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `input` - The path of the input file.
+/// * `timings` - The phases that ran for the input.
+fn print_timings(options: &Options, input: &str, timings: &Timings) {
+    match options.timings {
+        Some(Format::Text) => eprint!("Timings for {}:\n{}", input, timings.report()),
+        Some(Format::Json) => eprintln!("{}", timings.to_json(input)),
+        None => {}
+    }
+}
+
+/// Reads an input file, running it through the C preprocessor first if requested.
+///
+/// Line markers are left out of the preprocessed output (`-P`), so positions in
+/// diagnostics refer to the preprocessed source rather than the original file.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `input` - The path of the input file.
+///
+/// # Returns
+///
+/// The source code to compile, or an `Err` if it cannot be read or preprocessed.
+fn read_source(options: &Options, input: &str) -> Result<String, Diagnostic> {
+    if !options.preprocess {
+        return std::fs::read_to_string(input)
+            .map_err(|e| Diagnostic::error_without_span(format!("cannot open source file: {}", e)));
+    }
+    let program = compiler_program(options)?;
+    let output = Command::new(&program)
+        .args(["-E", "-P", input])
+        .output()
+        .map_err(|e| Diagnostic::error_without_span(format!("Failed to execute preprocessor {}: {}", program, e)))?;
+    if !output.status.success() {
+        let message = format!("Preprocessor error: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(Diagnostic::error_without_span(message));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| Diagnostic::error_without_span("Preprocessor output is not valid UTF-8"))
+}
+
+/// Runs the compiler for one input file, stopping after `options.stop_after` or, when the
+/// program is to be linked, after assembling it to an object file.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `scratch` - The directory for the intermediate files that are not kept, if there are any.
+/// * `index` - The position of the input on the command line, which keeps the names of
+///   intermediate files unique.
+/// * `input` - The path of the input file.
+/// * `source` - The source code of the input file.
+/// * `timings` - Where the cost of each phase is recorded, if it is enabled.
+///
+/// # Returns
+///
+/// The path of the object file to link, if one was made for linking, otherwise `None`, or
+/// an `Err` with the diagnostics to report.
+fn run(
+    options: &Options,
+    scratch: Option<&Path>,
+    index: usize,
+    input: &str,
+    source: &str,
+    timings: &mut Timings,
+) -> Result<Option<String>, Diagnostics> {
+    let compile_options = CompileOptions {
+        freestanding: options.freestanding,
+        entry: options.entry.clone(),
+        target: options.target,
+        pic: options.pic,
+        optimizations: options.optimizations,
+        debug_file: options.debug_info.then(|| input.to_string()),
+        warnings: options.warnings,
+        profile: options.profile.clone(),
+        null_checks: options.null_checks.then(|| input.to_string()),
+    };
+    if !matches!(options.stop_after, Stage::Assembly | Stage::Object | Stage::Link) {
+        inspect(options, &compile_options, input, source, timings)?;
+        return Ok(None);
+    }
+    let (assembly_code, warnings) = compile_with_timings(source, &compile_options, timings)?;
+    report(options, input, &warnings, source);
+    if options.stop_after == Stage::Assembly {
+        std::fs::write(output_path(options, input), &assembly_code)
+            .map_err(|e| Diagnostic::error_without_span(format!("Failed to write assembly to file: {}", e)))?;
+        return Ok(None);
+    }
+
+    let object = match options.stop_after {
+        Stage::Object => output_path(options, input),
+        _ => intermediate_path(scratch, index, input, Stage::Object),
+    };
+    // Write the assembly to a file and assemble it
+    let assembly_file = intermediate_path(scratch, index, input, Stage::Assembly);
+    std::fs::write(&assembly_file, &assembly_code)
+        .map_err(|e| Diagnostic::error_without_span(format!("Failed to write assembly to file: {}", e)))?;
+    let program = compiler_program(options)?;
+    let start = timings.start();
+    let output = Command::new(&program).args(["-c", &assembly_file, "-o", &object]).output();
+    timings.finish("assemble", start, || None);
+    let output = output
+        .map_err(|e| Diagnostic::error_without_span(format!("Failed to execute assembler {}: {}", program, e)))?;
+
+    if !output.status.success() {
+        let message = format!("Assembler error: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(Diagnostic::error_without_span(message).into());
+    }
+    Ok(Some(object))
+}
+
+/// Returns the path of an assembly or object file made on the way to the final output.
+/// Intermediates that are kept go next to the input; the rest go in the scratch directory,
+/// named after the position and name of the input, so files next to the input are left
+/// alone and inputs with the same name in different directories do not collide.
+///
+/// # Arguments
+///
+/// * `scratch` - The directory for the intermediates that are not kept, or `None` if they
+///   are kept.
+/// * `index` - The position of the input on the command line.
+/// * `input` - The path of the input file.
+/// * `stage` - `Stage::Assembly` or `Stage::Object`, for the kind of file.
+///
+/// # Returns
+///
+/// The path of the intermediate file.
+fn intermediate_path(scratch: Option<&Path>, index: usize, input: &str, stage: Stage) -> String {
+    let Some(scratch) = scratch else {
+        return default_output(input, stage);
+    };
+    let stem = Path::new(input).file_stem().unwrap_or_default().to_string_lossy();
+    let extension = if stage == Stage::Assembly { "s" } else { "o" };
+    scratch.join(format!("{}-{}.{}", index, stem, extension)).to_string_lossy().into_owned()
+}
+
+/// Returns where the output for one input goes when the driver stops before linking: the
+/// `-o` path if there is a single input, otherwise a name derived from the input.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `input` - The path of the input file.
+///
+/// # Returns
+///
+/// The path of the output file.
+fn output_path(options: &Options, input: &str) -> String {
+    if options.inputs.len() == 1 {
+        options.output.clone()
+    } else {
+        default_output(input, options.stop_after)
+    }
+}
+
+/// Chooses the compiler driver that preprocesses, assembles and links: the one given with
+/// `--cc`, else the `CC` environment variable, else gcc, or a cross gcc for other platforms.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+///
+/// # Returns
+///
+/// The program to run, or an `Err` if no compiler is known for the target on this host.
+fn compiler_program(options: &Options) -> Result<String, Diagnostic> {
+    if let Some(cc) = &options.cc {
+        return Ok(cc.clone());
+    }
+    if let Some(cc) = std::env::var("CC").ok().filter(|cc| !cc.is_empty()) {
+        return Ok(cc);
+    }
+    let program = match options.target {
+        target if target == Target::host() => "gcc",
+        Target { arch: Arch::X86_64, os: Os::Linux } => "x86_64-linux-gnu-gcc",
+        Target { arch: Arch::Aarch64, os: Os::Linux } => "aarch64-linux-gnu-gcc",
+        Target { os: Os::Darwin, .. } => {
+            let message = format!(
+                "Cannot assemble for {} on this host, use -S to only emit assembly or --cc to choose a compiler",
+                options.target
+            );
+            return Err(Diagnostic::error_without_span(message));
         }
+    };
+    Ok(program.to_string())
+}
+
+/// Links object files into an executable.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `objects` - The object files, and any other files to pass to the linker.
+///
+/// # Returns
+///
+/// `Ok(())` if linking succeeded, otherwise an `Err` with the linker's output.
+fn link(options: &Options, objects: &[String]) -> Result<(), Diagnostic> {
+    let program = compiler_program(options)?;
+    let mut gcc = Command::new(&program);
+    if !options.pic && options.target.os == Os::Linux {
+        gcc.arg("-no-pie");
+    }
+    if options.freestanding {
+        let entry = options.target.os.symbol(&options.entry);
+        gcc.args(["-nostdlib", "-e", &entry]);
+        // macOS does not support fully static executables
+        if options.target.os == Os::Linux {
+            gcc.arg("-static");
+        }
+    }
+    let output = gcc
+        .args(objects)
+        .args(["-o", &options.output])
+        .output()
+        .map_err(|e| Diagnostic::error_without_span(format!("Failed to execute linker {}: {}", program, e)))?;
+    if !output.status.success() {
+        let message = format!("Linker error: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(Diagnostic::error_without_span(message));
     }
+    Ok(())
 }
 
+/// Runs the pipeline up to one of the intermediate stages and prints that stage's output,
+/// as text or as JSON depending on `options.format`.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `compile_options` - The options to compile with.
+/// * `input` - The path of the input file.
+/// * `source` - The source code of the input file.
+/// * `timings` - Where the cost of each phase is recorded, if it is enabled.
+///
+/// # Returns
+///
+/// `Ok(())` if every stage that ran succeeded, otherwise an `Err` with the diagnostics to report.
+fn inspect(options: &Options, compile_options: &CompileOptions, input: &str, source: &str, timings: &mut Timings) -> Result<(), Diagnostics> {
+    let stop_after = match options.stop_after {
+        Stage::Lex => StopAfter::Lex,
+        Stage::Parse => StopAfter::Parse,
+        Stage::Check => StopAfter::Check,
+        Stage::Ir => StopAfter::Ir,
+        Stage::Llvm => StopAfter::Llvm,
+        Stage::Codegen | Stage::Assembly | Stage::Object | Stage::Link => StopAfter::Codegen,
+    };
+    let (output, warnings) = compile_to(source, compile_options, stop_after, timings)?;
+    report(options, input, &warnings, source);
+    match output {
+        StageOutput::Tokens(tokens) => match options.format {
+            Format::Text => {
+                for token in &tokens {
+                    println!("{}\t{}", token.span, token.token);
+                }
+            }
+            Format::Json => println!("{}", to_json(&tokens)?),
+        },
+        StageOutput::Ast(ast) if options.dump_ast => print!("{}", pretty_print(&ast)),
+        StageOutput::Ast(ast) => print_stage(options, &ast)?,
+        StageOutput::Checked(..) => {}
+        StageOutput::Ir(ir) => print_stage(options, &ir)?,
+        StageOutput::Assembly(assembly_ast) => print_stage(options, &assembly_ast)?,
+        // LLVM IR and the AArch64 assembly are only produced as text
+        StageOutput::Llvm(code) | StageOutput::AssemblyCode(code) => match options.format {
+            Format::Text => print!("{}", code),
+            Format::Json => println!("{}", to_json(&code)?),
+        },
+    }
+    Ok(())
+}
+
+/// Prints the output of an intermediate stage, pretty-printed or as JSON.
+///
+/// # Arguments
+///
+/// * `options` - The options the driver was invoked with.
+/// * `value` - The AST, IR or assembly AST to be printed.
+///
+/// # Returns
+///
+/// `Ok(())`, or an `Err` if the value could not be converted to JSON.
+fn print_stage<T: std::fmt::Debug + serde::Serialize>(options: &Options, value: &T) -> Result<(), Diagnostic> {
+    match options.format {
+        Format::Text => println!("{:#?}", value),
+        Format::Json => println!("{}", to_json(value)?),
+    }
+    Ok(())
+}
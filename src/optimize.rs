@@ -0,0 +1,1103 @@
+use std::collections::{BTreeMap,HashMap,HashSet};
+use crate::ast::*;
+use crate::cfg::Cfg;
+use crate::ir::*;
+
+/// The optimizations to run, chosen with `-O` or enabled one at a time.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Optimizations {
+    /// Evaluate operations on constants and simplify algebraic identities.
+    pub fold_constants: bool,
+    /// Remove code that control can never reach, and jumps and labels made redundant by it.
+    pub eliminate_unreachable_code: bool,
+    /// Replace variables that hold a copy of another value with that value.
+    pub propagate_copies: bool,
+    /// Remove instructions whose results are never used.
+    pub eliminate_dead_stores: bool,
+    /// Keep values in hardware registers instead of giving each its own stack slot. Only
+    /// the x86-64 backend allocates registers.
+    pub allocate_registers: bool,
+}
+
+impl Optimizations {
+    /// Returns the optimizations enabled at an optimization level, as in `-O1`.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - The optimization level. Level 0 disables every optimization and any
+    ///   higher level currently enables all of them.
+    ///
+    /// # Returns
+    ///
+    /// * `Optimizations` - The enabled optimizations.
+    pub fn level(level: u8) -> Self {
+        Optimizations {
+            fold_constants: level > 0,
+            eliminate_unreachable_code: level > 0,
+            propagate_copies: level > 0,
+            eliminate_dead_stores: level > 0,
+            allocate_registers: level > 0,
+        }
+    }
+}
+
+/// Optimizes every function of a program. The passes are repeated until none of them changes
+/// the code any more, as each can expose opportunities for the others.
+///
+/// Static variables can be read and written by any function a call reaches, and keep their
+/// values after a return, so the passes assume calls use and change them and returns use them.
+/// Variables whose address is taken are treated the same way, and are also assumed to be
+/// read by every load and changed by every store through a pointer.
+///
+/// # Arguments
+///
+/// * `program` - The program to be optimized.
+/// * `optimizations` - The optimizations to run.
+///
+/// # Returns
+///
+/// * `IrProgram` - The optimized program.
+pub fn optimize(mut program: IrProgram, optimizations: Optimizations) -> IrProgram {
+    let statics = program.static_names();
+    for function in &mut program.functions {
+        loop {
+            let mut body = std::mem::take(&mut function.body);
+            let before = body.clone();
+            let aliased = aliased_variables(&body, &statics);
+            if optimizations.fold_constants {
+                body = fold_constants(body, &aliased, &program.types);
+            }
+            let mut cfg = Cfg::new(body);
+            if optimizations.eliminate_unreachable_code {
+                cfg = eliminate_unreachable_code(cfg);
+            }
+            if optimizations.propagate_copies {
+                propagate_copies(&mut cfg, &aliased, &program.types);
+            }
+            if optimizations.eliminate_dead_stores {
+                eliminate_dead_stores(&mut cfg, &aliased);
+            }
+            let body = cfg.into_instructions();
+            let changed = body != before;
+            function.body = body;
+            if !changed {
+                break;
+            }
+        }
+    }
+    program
+}
+
+/// Returns the variables of a function that may be read or written other than by name: the
+/// static variables, and the variables whose address the function takes.
+///
+/// # Arguments
+///
+/// * `body` - The instructions of a function.
+/// * `statics` - The names of the static variables.
+///
+/// # Returns
+///
+/// * `HashSet<Name>` - The names of the aliased variables.
+fn aliased_variables(body: &[IrInstruction], statics: &HashSet<Name>) -> HashSet<Name> {
+    let mut aliased = statics.clone();
+    for instruction in body {
+        if let IrInstruction::GetAddress { src: IrValue::Var(name), .. } = instruction {
+            aliased.insert(*name);
+        }
+    }
+    aliased
+}
+
+/// Replaces operations whose operands are constants with their result, simplifies
+/// operations that leave one operand unchanged, such as `x + 0` and `x * 1`, and turns
+/// conditional jumps on constants into unconditional jumps or removes them.
+///
+/// Constants copied to a variable are substituted for its uses until the variable is
+/// redefined or a label is reached, so a whole expression like `2 + 3 * 4` folds even though
+/// each operation stores its result in a temporary. Operations whose result is undefined,
+/// such as division by zero or `INT_MIN / -1`, are left for the program to perform at run time.
+///
+/// # Arguments
+///
+/// * `body` - The instructions of a function.
+/// * `aliased` - The names of the aliased variables, which calls and stores may change.
+/// * `types` - The types of the variables of the program. A constant copied or converted
+///   to a variable takes the variable's type, so later operations on it fold with the right
+///   signedness.
+///
+/// # Returns
+///
+/// * `Vec<IrInstruction>` - The folded instructions.
+pub fn fold_constants(body: Vec<IrInstruction>, aliased: &HashSet<Name>, types: &BTreeMap<Name, Type>) -> Vec<IrInstruction> {
+    // Variables known to hold a constant at the current instruction
+    let mut constants: HashMap<Name, Constant> = HashMap::new();
+    let convert = |value: Constant, dst: &IrValue| IrValue::Constant(value.convert_to(&value_type(dst, types)));
+    let mut folded = Vec::with_capacity(body.len());
+    for instruction in body {
+        let substitute = |value: IrValue| match &value {
+            IrValue::Var(name) => constants.get(name).map_or(value, |constant| IrValue::Constant(*constant)),
+            IrValue::Constant(_) => value,
+        };
+        let instruction = match instruction {
+            IrInstruction::Return(value) => IrInstruction::Return(substitute(value)),
+            IrInstruction::Copy { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::Copy { src, dst },
+            },
+            IrInstruction::Unary { op, src, dst } => match substitute(src) {
+                IrValue::Constant(value) => {
+                    IrInstruction::Copy { src: IrValue::Constant(value.apply_unary(op)), dst }
+                }
+                src => IrInstruction::Unary { op, src, dst },
+            },
+            IrInstruction::SignExtend { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::SignExtend { src, dst },
+            },
+            IrInstruction::ZeroExtend { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::ZeroExtend { src, dst },
+            },
+            IrInstruction::Truncate { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::Truncate { src, dst },
+            },
+            IrInstruction::DoubleToInt { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::DoubleToInt { src, dst },
+            },
+            IrInstruction::DoubleToUInt { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::DoubleToUInt { src, dst },
+            },
+            IrInstruction::IntToDouble { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::IntToDouble { src, dst },
+            },
+            IrInstruction::UIntToDouble { src, dst } => match substitute(src) {
+                IrValue::Constant(value) => IrInstruction::Copy { src: convert(value, &dst), dst },
+                src => IrInstruction::UIntToDouble { src, dst },
+            },
+            IrInstruction::Binary { op, src1, src2, dst } => fold_binary(op, substitute(src1), substitute(src2), dst),
+            IrInstruction::JumpIfZero(condition, target) => match substitute(condition) {
+                IrValue::Constant(value) if value.is_zero() => IrInstruction::Jump(target),
+                IrValue::Constant(_) => continue,
+                condition => IrInstruction::JumpIfZero(condition, target),
+            },
+            IrInstruction::JumpIfNotZero(condition, target) => match substitute(condition) {
+                IrValue::Constant(value) if value.is_zero() => continue,
+                IrValue::Constant(_) => IrInstruction::Jump(target),
+                condition => IrInstruction::JumpIfNotZero(condition, target),
+            },
+            IrInstruction::FunCall { name, args, dst } => {
+                IrInstruction::FunCall { name, args: args.into_iter().map(substitute).collect(), dst }
+            }
+            IrInstruction::SystemCall { call, args, dst } => {
+                IrInstruction::SystemCall { call, args: args.into_iter().map(substitute).collect(), dst }
+            }
+            // The variable itself is needed, not its value
+            instruction @ IrInstruction::GetAddress { .. } => instruction,
+            IrInstruction::Alloca { size, dst } => IrInstruction::Alloca { size: substitute(size), dst },
+            IrInstruction::Load { src_ptr, dst } => IrInstruction::Load { src_ptr: substitute(src_ptr), dst },
+            IrInstruction::Store { src, dst_ptr } => IrInstruction::Store { src: substitute(src), dst_ptr: substitute(dst_ptr) },
+            IrInstruction::AtomicLoad { src_ptr, dst } => IrInstruction::AtomicLoad { src_ptr: substitute(src_ptr), dst },
+            IrInstruction::AtomicStore { src, dst_ptr } => {
+                IrInstruction::AtomicStore { src: substitute(src), dst_ptr: substitute(dst_ptr) }
+            }
+            IrInstruction::CompareExchange { ptr, expected, desired, dst } => IrInstruction::CompareExchange {
+                ptr: substitute(ptr),
+                expected: substitute(expected),
+                desired: substitute(desired),
+                dst,
+            },
+            IrInstruction::AddPtr { ptr, index, scale, dst } => {
+                IrInstruction::AddPtr { ptr: substitute(ptr), index: substitute(index), scale, dst }
+            }
+            IrInstruction::CopyToOffset { src, dst, offset } => IrInstruction::CopyToOffset { src: substitute(src), dst, offset },
+            instruction @ (IrInstruction::Jump(_) | IrInstruction::Trap | IrInstruction::Location(_)) => instruction,
+            IrInstruction::Label(label) => {
+                // Control can arrive here from elsewhere, where the variables may hold other values
+                constants.clear();
+                IrInstruction::Label(label)
+            }
+        };
+        match &instruction {
+            IrInstruction::Copy { src: IrValue::Constant(value), dst: IrValue::Var(name) } => {
+                constants.insert(*name, *value);
+            }
+            IrInstruction::Copy { dst: IrValue::Var(name), .. }
+            | IrInstruction::SignExtend { dst: IrValue::Var(name), .. }
+            | IrInstruction::ZeroExtend { dst: IrValue::Var(name), .. }
+            | IrInstruction::DoubleToInt { dst: IrValue::Var(name), .. }
+            | IrInstruction::DoubleToUInt { dst: IrValue::Var(name), .. }
+            | IrInstruction::IntToDouble { dst: IrValue::Var(name), .. }
+            | IrInstruction::UIntToDouble { dst: IrValue::Var(name), .. }
+            | IrInstruction::Truncate { dst: IrValue::Var(name), .. }
+            | IrInstruction::Unary { dst: IrValue::Var(name), .. }
+            | IrInstruction::Binary { dst: IrValue::Var(name), .. }
+            | IrInstruction::GetAddress { dst: IrValue::Var(name), .. }
+            | IrInstruction::Alloca { dst: IrValue::Var(name), .. }
+            | IrInstruction::Load { dst: IrValue::Var(name), .. }
+            | IrInstruction::AtomicLoad { dst: IrValue::Var(name), .. }
+            | IrInstruction::AddPtr { dst: IrValue::Var(name), .. }
+            | IrInstruction::CopyToOffset { dst: name, .. } => {
+                constants.remove(name);
+            }
+            IrInstruction::FunCall { dst: IrValue::Var(name), .. }
+            | IrInstruction::SystemCall { dst: IrValue::Var(name), .. }
+            | IrInstruction::CompareExchange { dst: IrValue::Var(name), .. } => {
+                constants.remove(name);
+                constants.retain(|name, _| !aliased.contains(name));
+            }
+            IrInstruction::Store { .. } | IrInstruction::AtomicStore { .. } => {
+                constants.retain(|name, _| !aliased.contains(name))
+            }
+            _ => {}
+        }
+        folded.push(instruction);
+    }
+    folded
+}
+
+/// Removes the blocks that cannot be reached from the entry of a function, then the jumps to
+/// the block that follows anyway and the labels no jump targets any more.
+///
+/// # Arguments
+///
+/// * `cfg` - The control-flow graph of a function.
+///
+/// # Returns
+///
+/// * `Cfg` - The graph without the unreachable code.
+pub fn eliminate_unreachable_code(mut cfg: Cfg) -> Cfg {
+    let mut reachable = vec![false; cfg.blocks.len()];
+    let mut stack: Vec<usize> = if cfg.blocks.is_empty() { Vec::new() } else { vec![0] };
+    while let Some(index) = stack.pop() {
+        if !reachable[index] {
+            reachable[index] = true;
+            stack.extend(&cfg.blocks[index].successors);
+        }
+    }
+    let mut reachable = reachable.into_iter();
+    cfg.blocks.retain(|_| reachable.next().unwrap_or(false));
+
+    for index in 0..cfg.blocks.len() {
+        let next_label = match cfg.blocks.get(index + 1).and_then(|block| block.instructions.first()) {
+            Some(IrInstruction::Label(label)) => Some(*label),
+            _ => None,
+        };
+        let instructions = &mut cfg.blocks[index].instructions;
+        if let Some(
+            IrInstruction::Jump(target) | IrInstruction::JumpIfZero(_, target) | IrInstruction::JumpIfNotZero(_, target),
+        ) = instructions.last()
+        {
+            if Some(target) == next_label.as_ref() {
+                instructions.pop();
+            }
+        }
+    }
+
+    let targets: HashSet<Name> = cfg
+        .blocks
+        .iter()
+        .filter_map(|block| match block.instructions.last() {
+            Some(
+                IrInstruction::Jump(target) | IrInstruction::JumpIfZero(_, target) | IrInstruction::JumpIfNotZero(_, target),
+            ) => Some(*target),
+            _ => None,
+        })
+        .collect();
+    for block in &mut cfg.blocks {
+        if matches!(block.instructions.first(), Some(IrInstruction::Label(label)) if !targets.contains(label)) {
+            block.instructions.remove(0);
+        }
+    }
+    cfg.blocks.retain(|block| !block.instructions.is_empty());
+    cfg.connect();
+    cfg
+}
+
+/// A copy `dst = src` known to hold at some point in a function.
+type ReachingCopy = (Name, IrValue);
+
+/// Replaces uses of variables that hold a copy of another value with that value, and removes
+/// copies whose destination already holds the value being copied.
+///
+/// The copies that reach the start of each block are found by iterating a forward dataflow
+/// analysis to a fixed point: a copy reaches a block only if it reaches the end of every
+/// predecessor, and it stops reaching once either of its variables is stored to.
+///
+/// Only copies between values of the same type are propagated: a copy between `int` and
+/// `unsigned int` changes how the operations that use it treat the bits.
+///
+/// # Arguments
+///
+/// * `cfg` - The control-flow graph of a function.
+/// * `aliased` - The names of the aliased variables, which calls and stores may change.
+/// * `types` - The types of the variables of the program.
+pub fn propagate_copies(cfg: &mut Cfg, aliased: &HashSet<Name>, types: &BTreeMap<Name, Type>) {
+    // Start from every copy in the function, so copies can flow around loops
+    let all_copies: HashSet<ReachingCopy> = cfg
+        .blocks
+        .iter()
+        .flat_map(|block| &block.instructions)
+        .filter_map(|instruction| match instruction {
+            IrInstruction::Copy { src, dst: IrValue::Var(dst) } if is_same_type_copy(src, *dst, types) => {
+                Some((*dst, src.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut reaching_out: Vec<HashSet<ReachingCopy>> = vec![all_copies; cfg.blocks.len()];
+    let reaching_in = |reaching_out: &[HashSet<ReachingCopy>], index: usize| -> HashSet<ReachingCopy> {
+        let predecessors = &cfg.blocks[index].predecessors;
+        if index == 0 || predecessors.is_empty() {
+            return HashSet::new();
+        }
+        let mut reaching = reaching_out[predecessors[0]].clone();
+        for predecessor in &predecessors[1..] {
+            reaching.retain(|copy| reaching_out[*predecessor].contains(copy));
+        }
+        reaching
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..cfg.blocks.len() {
+            let mut reaching = reaching_in(&reaching_out, index);
+            for instruction in &cfg.blocks[index].instructions {
+                update_reaching_copies(instruction, &mut reaching, aliased, types);
+            }
+            if reaching != reaching_out[index] {
+                reaching_out[index] = reaching;
+                changed = true;
+            }
+        }
+    }
+
+    let reaching_in: Vec<HashSet<ReachingCopy>> =
+        (0..cfg.blocks.len()).map(|index| reaching_in(&reaching_out, index)).collect();
+    for (block, mut reaching) in cfg.blocks.iter_mut().zip(reaching_in) {
+        let mut instructions: Vec<IrInstruction> = Vec::with_capacity(block.instructions.len());
+        for instruction in std::mem::take(&mut block.instructions) {
+            let replace = |value: IrValue| match &value {
+                IrValue::Var(name) => {
+                    reaching.iter().find(|(dst, _)| dst == name).map_or(value, |(_, src)| src.clone())
+                }
+                IrValue::Constant(_) => value,
+            };
+            let instruction = match instruction {
+                IrInstruction::Return(value) => IrInstruction::Return(replace(value)),
+                IrInstruction::Copy { src, dst } => IrInstruction::Copy { src: replace(src), dst },
+                IrInstruction::SignExtend { src, dst } => IrInstruction::SignExtend { src: replace(src), dst },
+                IrInstruction::ZeroExtend { src, dst } => IrInstruction::ZeroExtend { src: replace(src), dst },
+                IrInstruction::DoubleToInt { src, dst } => IrInstruction::DoubleToInt { src: replace(src), dst },
+                IrInstruction::DoubleToUInt { src, dst } => IrInstruction::DoubleToUInt { src: replace(src), dst },
+                IrInstruction::IntToDouble { src, dst } => IrInstruction::IntToDouble { src: replace(src), dst },
+                IrInstruction::UIntToDouble { src, dst } => IrInstruction::UIntToDouble { src: replace(src), dst },
+                IrInstruction::Truncate { src, dst } => IrInstruction::Truncate { src: replace(src), dst },
+                IrInstruction::Unary { op, src, dst } => IrInstruction::Unary { op, src: replace(src), dst },
+                IrInstruction::Binary { op, src1, src2, dst } => {
+                    IrInstruction::Binary { op, src1: replace(src1), src2: replace(src2), dst }
+                }
+                IrInstruction::JumpIfZero(condition, target) => IrInstruction::JumpIfZero(replace(condition), target),
+                IrInstruction::JumpIfNotZero(condition, target) => {
+                    IrInstruction::JumpIfNotZero(replace(condition), target)
+                }
+                IrInstruction::FunCall { name, args, dst } => {
+                    IrInstruction::FunCall { name, args: args.into_iter().map(replace).collect(), dst }
+                }
+                IrInstruction::SystemCall { call, args, dst } => {
+                    IrInstruction::SystemCall { call, args: args.into_iter().map(replace).collect(), dst }
+                }
+                IrInstruction::Alloca { size, dst } => IrInstruction::Alloca { size: replace(size), dst },
+                IrInstruction::Load { src_ptr, dst } => IrInstruction::Load { src_ptr: replace(src_ptr), dst },
+                IrInstruction::Store { src, dst_ptr } => IrInstruction::Store { src: replace(src), dst_ptr: replace(dst_ptr) },
+                IrInstruction::AtomicLoad { src_ptr, dst } => IrInstruction::AtomicLoad { src_ptr: replace(src_ptr), dst },
+                IrInstruction::AtomicStore { src, dst_ptr } => {
+                    IrInstruction::AtomicStore { src: replace(src), dst_ptr: replace(dst_ptr) }
+                }
+                IrInstruction::CompareExchange { ptr, expected, desired, dst } => IrInstruction::CompareExchange {
+                    ptr: replace(ptr),
+                    expected: replace(expected),
+                    desired: replace(desired),
+                    dst,
+                },
+                IrInstruction::AddPtr { ptr, index, scale, dst } => {
+                    IrInstruction::AddPtr { ptr: replace(ptr), index: replace(index), scale, dst }
+                }
+                IrInstruction::CopyToOffset { src, dst, offset } => IrInstruction::CopyToOffset { src: replace(src), dst, offset },
+                instruction @ (IrInstruction::Jump(_)
+                | IrInstruction::Trap
+                | IrInstruction::Label(_)
+                | IrInstruction::GetAddress { .. }
+                | IrInstruction::Location(_)) => instruction,
+            };
+            if let IrInstruction::Copy { src, dst: IrValue::Var(dst) } = &instruction {
+                if *src == IrValue::Var(*dst) || reaching.contains(&(*dst, src.clone())) {
+                    continue;
+                }
+            }
+            update_reaching_copies(&instruction, &mut reaching, aliased, types);
+            instructions.push(instruction);
+        }
+        block.instructions = instructions;
+    }
+}
+
+/// Updates the set of reaching copies from just before an instruction to just after it.
+///
+/// # Arguments
+///
+/// * `instruction` - The instruction.
+/// * `reaching` - The copies that reach the instruction, updated to those that reach past it.
+/// * `aliased` - The names of the aliased variables, which calls and stores may change.
+/// * `types` - The types of the variables of the program.
+fn update_reaching_copies(
+    instruction: &IrInstruction,
+    reaching: &mut HashSet<ReachingCopy>,
+    aliased: &HashSet<Name>,
+    types: &BTreeMap<Name, Type>,
+) {
+    if let IrInstruction::FunCall { .. }
+    | IrInstruction::SystemCall { .. }
+    | IrInstruction::Store { .. }
+    | IrInstruction::AtomicStore { .. }
+    | IrInstruction::CompareExchange { .. } = instruction
+    {
+        reaching.retain(|(copy_dst, copy_src)| {
+            !aliased.contains(copy_dst) && !matches!(copy_src, IrValue::Var(src) if aliased.contains(src))
+        });
+    }
+    let dst = match instruction {
+        IrInstruction::Copy { dst: IrValue::Var(dst), .. }
+        | IrInstruction::SignExtend { dst: IrValue::Var(dst), .. }
+        | IrInstruction::ZeroExtend { dst: IrValue::Var(dst), .. }
+        | IrInstruction::DoubleToInt { dst: IrValue::Var(dst), .. }
+        | IrInstruction::DoubleToUInt { dst: IrValue::Var(dst), .. }
+        | IrInstruction::IntToDouble { dst: IrValue::Var(dst), .. }
+        | IrInstruction::UIntToDouble { dst: IrValue::Var(dst), .. }
+        | IrInstruction::Truncate { dst: IrValue::Var(dst), .. }
+        | IrInstruction::Unary { dst: IrValue::Var(dst), .. }
+        | IrInstruction::Binary { dst: IrValue::Var(dst), .. }
+        | IrInstruction::FunCall { dst: IrValue::Var(dst), .. }
+        | IrInstruction::SystemCall { dst: IrValue::Var(dst), .. }
+        | IrInstruction::GetAddress { dst: IrValue::Var(dst), .. }
+        | IrInstruction::Alloca { dst: IrValue::Var(dst), .. }
+        | IrInstruction::Load { dst: IrValue::Var(dst), .. }
+        | IrInstruction::AtomicLoad { dst: IrValue::Var(dst), .. }
+        | IrInstruction::CompareExchange { dst: IrValue::Var(dst), .. }
+        | IrInstruction::AddPtr { dst: IrValue::Var(dst), .. }
+        | IrInstruction::CopyToOffset { dst, .. } => dst,
+        _ => return,
+    };
+    reaching.retain(|(copy_dst, copy_src)| copy_dst != dst && *copy_src != IrValue::Var(*dst));
+    if let IrInstruction::Copy { src, .. } = instruction {
+        if *src != IrValue::Var(*dst) && is_same_type_copy(src, *dst, types) {
+            reaching.insert((*dst, src.clone()));
+        }
+    }
+}
+
+/// Checks whether a copy keeps the type of the value, so its destination can be replaced by
+/// its source.
+///
+/// # Arguments
+///
+/// * `src` - The value copied.
+/// * `dst` - The variable copied to.
+/// * `types` - The types of the variables of the program.
+///
+/// # Returns
+///
+/// * `bool` - `true` if both have the same type.
+fn is_same_type_copy(src: &IrValue, dst: Name, types: &BTreeMap<Name, Type>) -> bool {
+    value_type(src, types) == value_type(&IrValue::Var(dst), types)
+}
+
+/// Removes copies and operations that store to a variable which is not read before it is
+/// stored to again or the function returns. Function calls and atomic accesses are kept
+/// for their side effects.
+///
+/// Which variables are live at the end of each block is found by iterating a backward
+/// dataflow analysis to a fixed point.
+///
+/// # Arguments
+///
+/// * `cfg` - The control-flow graph of a function.
+/// * `aliased` - The names of the aliased variables, which calls, returns and loads use.
+pub fn eliminate_dead_stores(cfg: &mut Cfg, aliased: &HashSet<Name>) {
+    let mut live_in: Vec<HashSet<Name>> = vec![HashSet::new(); cfg.blocks.len()];
+    let live_out = |live_in: &[HashSet<Name>], index: usize| -> HashSet<Name> {
+        cfg.blocks[index].successors.iter().flat_map(|successor| live_in[*successor].iter().cloned()).collect()
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in (0..cfg.blocks.len()).rev() {
+            let mut live = live_out(&live_in, index);
+            for instruction in cfg.blocks[index].instructions.iter().rev() {
+                update_liveness(instruction, &mut live, aliased);
+            }
+            if live != live_in[index] {
+                live_in[index] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let live_out: Vec<HashSet<Name>> = (0..cfg.blocks.len()).map(|index| live_out(&live_in, index)).collect();
+    for (block, mut live) in cfg.blocks.iter_mut().zip(live_out) {
+        let mut instructions: Vec<IrInstruction> = Vec::with_capacity(block.instructions.len());
+        for instruction in std::mem::take(&mut block.instructions).into_iter().rev() {
+            let dead = match &instruction {
+                IrInstruction::Copy { dst: IrValue::Var(name), .. }
+                | IrInstruction::SignExtend { dst: IrValue::Var(name), .. }
+                | IrInstruction::ZeroExtend { dst: IrValue::Var(name), .. }
+                | IrInstruction::DoubleToInt { dst: IrValue::Var(name), .. }
+                | IrInstruction::DoubleToUInt { dst: IrValue::Var(name), .. }
+                | IrInstruction::IntToDouble { dst: IrValue::Var(name), .. }
+                | IrInstruction::UIntToDouble { dst: IrValue::Var(name), .. }
+                | IrInstruction::Truncate { dst: IrValue::Var(name), .. }
+                | IrInstruction::Unary { dst: IrValue::Var(name), .. }
+                | IrInstruction::Binary { dst: IrValue::Var(name), .. }
+                | IrInstruction::GetAddress { dst: IrValue::Var(name), .. }
+                | IrInstruction::Alloca { dst: IrValue::Var(name), .. }
+                | IrInstruction::Load { dst: IrValue::Var(name), .. }
+                | IrInstruction::AddPtr { dst: IrValue::Var(name), .. }
+                | IrInstruction::CopyToOffset { dst: name, .. } => !live.contains(name),
+                _ => false,
+            };
+            if !dead {
+                update_liveness(&instruction, &mut live, aliased);
+                instructions.push(instruction);
+            }
+        }
+        instructions.reverse();
+        block.instructions = instructions;
+    }
+}
+
+/// Updates the set of live variables from just after an instruction to just before it.
+///
+/// # Arguments
+///
+/// * `instruction` - The instruction.
+/// * `live` - The variables live after the instruction, updated to those live before it.
+/// * `aliased` - The names of the aliased variables, which calls, returns and loads use.
+fn update_liveness(instruction: &IrInstruction, live: &mut HashSet<Name>, aliased: &HashSet<Name>) {
+    let (dst, sources): (Option<&IrValue>, Vec<&IrValue>) = match instruction {
+        IrInstruction::Return(value) => (None, vec![value]),
+        IrInstruction::Copy { src, dst }
+        | IrInstruction::SignExtend { src, dst }
+        | IrInstruction::ZeroExtend { src, dst }
+        | IrInstruction::DoubleToInt { src, dst }
+        | IrInstruction::DoubleToUInt { src, dst }
+        | IrInstruction::IntToDouble { src, dst }
+        | IrInstruction::UIntToDouble { src, dst }
+        | IrInstruction::Truncate { src, dst }
+        | IrInstruction::Unary { src, dst, .. } => (Some(dst), vec![src]),
+        IrInstruction::Binary { src1, src2, dst, .. } => (Some(dst), vec![src1, src2]),
+        IrInstruction::JumpIfZero(condition, _) | IrInstruction::JumpIfNotZero(condition, _) => (None, vec![condition]),
+        IrInstruction::FunCall { args, dst, .. } | IrInstruction::SystemCall { args, dst, .. } => {
+            (Some(dst), args.iter().collect())
+        }
+        // Taking the address of a variable does not read its value
+        IrInstruction::GetAddress { dst, .. } => (Some(dst), Vec::new()),
+        IrInstruction::Alloca { size, dst } => (Some(dst), vec![size]),
+        IrInstruction::Load { src_ptr, dst } => (Some(dst), vec![src_ptr]),
+        IrInstruction::Store { src, dst_ptr } | IrInstruction::AtomicStore { src, dst_ptr } => (None, vec![src, dst_ptr]),
+        IrInstruction::AtomicLoad { src_ptr, dst } => (Some(dst), vec![src_ptr]),
+        IrInstruction::CompareExchange { ptr, expected, desired, dst } => (Some(dst), vec![ptr, expected, desired]),
+        IrInstruction::AddPtr { ptr, index, dst, .. } => (Some(dst), vec![ptr, index]),
+        // Only part of the variable is stored to, so the rest stays live
+        IrInstruction::CopyToOffset { src, .. } => (None, vec![src]),
+        IrInstruction::Jump(_) | IrInstruction::Trap | IrInstruction::Label(_) | IrInstruction::Location(_) => {
+            (None, Vec::new())
+        }
+    };
+    if let Some(IrValue::Var(name)) = dst {
+        live.remove(name);
+    }
+    for source in sources {
+        if let IrValue::Var(name) = source {
+            live.insert(*name);
+        }
+    }
+    if let IrInstruction::Return(_)
+    | IrInstruction::FunCall { .. }
+    | IrInstruction::SystemCall { .. }
+    | IrInstruction::Load { .. }
+    | IrInstruction::AtomicLoad { .. }
+    | IrInstruction::CompareExchange { .. } = instruction
+    {
+        live.extend(aliased.iter().cloned());
+    }
+}
+
+/// Folds a binary operation if its operands are constants or it is an identity.
+///
+/// # Arguments
+///
+/// * `op` - The operator.
+/// * `src1` - The left operand.
+/// * `src2` - The right operand.
+/// * `dst` - Where the result is stored.
+///
+/// # Returns
+///
+/// * `IrInstruction` - A copy of the result if the operation could be folded, otherwise
+///   the original operation.
+fn fold_binary(op: BinaryOperator, src1: IrValue, src2: IrValue, dst: IrValue) -> IrInstruction {
+    if let (IrValue::Constant(left), IrValue::Constant(right)) = (&src1, &src2) {
+        if let Some(value) = left.apply_binary(op, *right) {
+            return IrInstruction::Copy { src: IrValue::Constant(value), dst };
+        }
+    }
+    let is = |value: &IrValue, expected: i32| {
+        matches!(value, IrValue::Constant(constant) if *constant == Constant::Int(expected).convert_to(&constant.ty()))
+    };
+    // Only the identities with 1 hold for doubles: adding 0.0 turns -0.0 into 0.0, and an
+    // infinity or NaN times 0.0 is NaN
+    let double = [&src1, &src2].iter().any(|value| matches!(value, IrValue::Constant(Constant::Double(_))));
+    let is_zero = |value: &IrValue| !double && is(value, 0);
+    let src = match op {
+        BinaryOperator::Add | BinaryOperator::BitwiseOr | BinaryOperator::BitwiseXor if is_zero(&src1) => src2,
+        BinaryOperator::Multiply if is(&src1, 1) => src2,
+        BinaryOperator::Add
+        | BinaryOperator::Subtract
+        | BinaryOperator::BitwiseOr
+        | BinaryOperator::BitwiseXor
+        | BinaryOperator::LeftShift
+        | BinaryOperator::RightShift
+            if is_zero(&src2) =>
+        {
+            src1
+        }
+        BinaryOperator::Multiply | BinaryOperator::Divide if is(&src2, 1) => src1,
+        // Operands are plain values without side effects, so they need not be evaluated
+        BinaryOperator::Multiply if is_zero(&src1) => src1,
+        BinaryOperator::Multiply if is_zero(&src2) => src2,
+        _ => return IrInstruction::Binary { op, src1, src2, dst },
+    };
+    IrInstruction::Copy { src, dst }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> IrValue {
+        IrValue::Var(Name::from(name))
+    }
+
+    fn binary(op: BinaryOperator, src1: IrValue, src2: IrValue, dst: &str) -> IrInstruction {
+        IrInstruction::Binary { op, src1, src2, dst: var(dst) }
+    }
+
+    fn copy(src: IrValue, dst: &str) -> IrInstruction {
+        IrInstruction::Copy { src, dst: var(dst) }
+    }
+
+    /// Lowers `source` to the IR without optimizing it.
+    fn lower(source: &str) -> IrProgram {
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let ast = crate::resolve::resolve_program(ast).unwrap();
+        let (ast, symbols, structs) = crate::typecheck::typecheck_program(ast).unwrap();
+        generate_ir(crate::label_loops::label_loops(ast).unwrap(), &symbols, &structs, false, None)
+    }
+
+    #[test]
+    fn test_fold_constant_operations() {
+        let body = vec![
+            binary(BinaryOperator::Multiply, IrValue::Constant(Constant::Int(3)), IrValue::Constant(Constant::Int(4)), "tmp.0"),
+            IrInstruction::Unary { op: UnaryOperator::Negate, src: IrValue::Constant(Constant::Int(5)), dst: var("tmp.1") },
+            binary(BinaryOperator::LessThan, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(2)), "tmp.2"),
+            binary(BinaryOperator::Add, IrValue::Constant(Constant::Int(i32::MAX)), IrValue::Constant(Constant::Int(1)), "tmp.3"),
+            binary(BinaryOperator::Divide, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(0)), "tmp.4"),
+            binary(BinaryOperator::Remainder, IrValue::Constant(Constant::Int(i32::MIN)), IrValue::Constant(Constant::Int(-1)), "tmp.5"),
+        ];
+        assert_eq!(fold_constants(body, &HashSet::new(), &BTreeMap::new()), vec![
+            copy(IrValue::Constant(Constant::Int(12)), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(-5)), "tmp.1"),
+            copy(IrValue::Constant(Constant::Int(1)), "tmp.2"),
+            copy(IrValue::Constant(Constant::Int(i32::MIN)), "tmp.3"),
+            binary(BinaryOperator::Divide, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(0)), "tmp.4"),
+            binary(BinaryOperator::Remainder, IrValue::Constant(Constant::Int(i32::MIN)), IrValue::Constant(Constant::Int(-1)), "tmp.5"),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_bitwise_operations() {
+        let body = vec![
+            binary(BinaryOperator::BitwiseAnd, IrValue::Constant(Constant::Int(12)), IrValue::Constant(Constant::Int(10)), "tmp.0"),
+            binary(BinaryOperator::BitwiseOr, IrValue::Constant(Constant::Int(12)), IrValue::Constant(Constant::Int(3)), "tmp.1"),
+            binary(BinaryOperator::BitwiseXor, IrValue::Constant(Constant::Int(5)), IrValue::Constant(Constant::Int(1)), "tmp.2"),
+            binary(BinaryOperator::LeftShift, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(31)), "tmp.3"),
+            binary(BinaryOperator::RightShift, IrValue::Constant(Constant::Int(-8)), IrValue::Constant(Constant::Int(1)), "tmp.4"),
+            binary(BinaryOperator::LeftShift, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(32)), "tmp.5"),
+            binary(BinaryOperator::RightShift, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(-1)), "tmp.6"),
+            binary(BinaryOperator::BitwiseXor, IrValue::Constant(Constant::Int(0)), var("x"), "tmp.7"),
+            binary(BinaryOperator::RightShift, var("x"), IrValue::Constant(Constant::Int(0)), "tmp.8"),
+        ];
+        assert_eq!(fold_constants(body, &HashSet::new(), &BTreeMap::new()), vec![
+            copy(IrValue::Constant(Constant::Int(8)), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(15)), "tmp.1"),
+            copy(IrValue::Constant(Constant::Int(4)), "tmp.2"),
+            copy(IrValue::Constant(Constant::Int(i32::MIN)), "tmp.3"),
+            copy(IrValue::Constant(Constant::Int(-4)), "tmp.4"),
+            binary(BinaryOperator::LeftShift, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(32)), "tmp.5"),
+            binary(BinaryOperator::RightShift, IrValue::Constant(Constant::Int(1)), IrValue::Constant(Constant::Int(-1)), "tmp.6"),
+            copy(var("x"), "tmp.7"),
+            copy(var("x"), "tmp.8"),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_identities() {
+        let body = vec![
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(0)), "tmp.0"),
+            binary(BinaryOperator::Add, IrValue::Constant(Constant::Int(0)), var("x"), "tmp.1"),
+            binary(BinaryOperator::Multiply, IrValue::Constant(Constant::Int(1)), var("x"), "tmp.2"),
+            binary(BinaryOperator::Divide, var("x"), IrValue::Constant(Constant::Int(1)), "tmp.3"),
+            binary(BinaryOperator::Multiply, var("x"), IrValue::Constant(Constant::Int(0)), "tmp.4"),
+            binary(BinaryOperator::Subtract, IrValue::Constant(Constant::Int(0)), var("x"), "tmp.5"),
+        ];
+        assert_eq!(fold_constants(body, &HashSet::new(), &BTreeMap::new()), vec![
+            copy(var("x"), "tmp.0"),
+            copy(var("x"), "tmp.1"),
+            copy(var("x"), "tmp.2"),
+            copy(var("x"), "tmp.3"),
+            copy(IrValue::Constant(Constant::Int(0)), "tmp.4"),
+            binary(BinaryOperator::Subtract, IrValue::Constant(Constant::Int(0)), var("x"), "tmp.5"),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_conditional_jumps() {
+        let body = vec![
+            IrInstruction::JumpIfZero(IrValue::Constant(Constant::Int(0)), "a".into()),
+            IrInstruction::JumpIfZero(IrValue::Constant(Constant::Int(3)), "b".into()),
+            IrInstruction::JumpIfNotZero(IrValue::Constant(Constant::Int(3)), "c".into()),
+            IrInstruction::JumpIfNotZero(var("x"), "d".into()),
+        ];
+        assert_eq!(fold_constants(body, &HashSet::new(), &BTreeMap::new()), vec![
+            IrInstruction::Jump("a".into()),
+            IrInstruction::Jump("c".into()),
+            IrInstruction::JumpIfNotZero(var("x"), "d".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_propagate_constants_within_blocks() {
+        let body = vec![
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(1)), "y"),
+            IrInstruction::JumpIfZero(var("y"), "end".into()),
+            IrInstruction::FunCall { name: "f".into(), args: vec![var("x")], dst: var("x") },
+            IrInstruction::Return(var("x")),
+            copy(IrValue::Constant(Constant::Int(1)), "z"),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(var("z")),
+        ];
+        assert_eq!(fold_constants(body, &HashSet::new(), &BTreeMap::new()), vec![
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            copy(IrValue::Constant(Constant::Int(3)), "y"),
+            IrInstruction::FunCall { name: "f".into(), args: vec![IrValue::Constant(Constant::Int(2))], dst: var("x") },
+            IrInstruction::Return(var("x")),
+            copy(IrValue::Constant(Constant::Int(1)), "z"),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(var("z")),
+        ]);
+    }
+
+    #[test]
+    fn test_optimize_program() {
+        // return 2 + 3 * 4;
+        let ir = lower("int main(void) { return 2 + 3 * 4; }");
+        assert_eq!(ir.functions[0].body, vec![
+            binary(BinaryOperator::Multiply, IrValue::Constant(Constant::Int(3)), IrValue::Constant(Constant::Int(4)), "tmp.0"),
+            binary(BinaryOperator::Add, IrValue::Constant(Constant::Int(2)), var("tmp.0"), "tmp.1"),
+            IrInstruction::Return(var("tmp.1")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+        let folded = optimize(ir, Optimizations { fold_constants: true, ..Optimizations::default() });
+        assert_eq!(folded.functions[0].body, vec![
+            copy(IrValue::Constant(Constant::Int(12)), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(14)), "tmp.1"),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(14))),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ]);
+        let optimized = optimize(folded, Optimizations::level(1));
+        assert_eq!(optimized.functions[0].body, vec![IrInstruction::Return(IrValue::Constant(Constant::Int(14)))]);
+        let ir = optimize(lower("int main(void) { return 2 + 3; }"), Optimizations::default());
+        assert_eq!(ir.functions[0].body[0], binary(BinaryOperator::Add, IrValue::Constant(Constant::Int(2)), IrValue::Constant(Constant::Int(3)), "tmp.0"));
+    }
+
+    #[test]
+    fn test_eliminate_unreachable_code() {
+        // if (1) x = 1; else x = 2; return x; return 0;
+        let body = vec![
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("c") },
+            IrInstruction::JumpIfZero(var("c"), "else".into()),
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::Jump("end".into()),
+            IrInstruction::Label("else".into()),
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(var("x")),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let body = fold_constants(body, &HashSet::new(), &BTreeMap::new());
+        let cfg = eliminate_unreachable_code(Cfg::new(body));
+        assert_eq!(cfg.into_instructions(), vec![
+            IrInstruction::Copy { src: IrValue::Constant(Constant::Int(1)), dst: var("c") },
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::Return(var("x")),
+        ]);
+    }
+
+    #[test]
+    fn test_eliminate_code_after_trap() {
+        // if (x) return 1; __builtin_unreachable(); return 2;
+        let body = vec![
+            IrInstruction::JumpIfZero(var("x"), "else".into()),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(1))),
+            IrInstruction::Label("else".into()),
+            IrInstruction::Trap,
+            IrInstruction::Return(IrValue::Constant(Constant::Int(2))),
+        ];
+        let cfg = eliminate_unreachable_code(Cfg::new(body));
+        assert_eq!(cfg.into_instructions(), vec![
+            IrInstruction::JumpIfZero(var("x"), "else".into()),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(1))),
+            IrInstruction::Label("else".into()),
+            IrInstruction::Trap,
+        ]);
+    }
+
+    #[test]
+    fn test_keep_loops_reachable() {
+        let body = vec![
+            IrInstruction::Label("start".into()),
+            IrInstruction::JumpIfZero(var("x"), "end".into()),
+            IrInstruction::Jump("start".into()),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ];
+        let cfg = eliminate_unreachable_code(Cfg::new(body.clone()));
+        assert_eq!(cfg.into_instructions(), body);
+    }
+
+    #[test]
+    fn test_eliminate_dead_stores() {
+        let body = vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            copy(IrValue::Constant(Constant::Int(2)), "y"),
+            IrInstruction::FunCall { name: "f".into(), args: vec![], dst: var("unused") },
+            IrInstruction::Label("loop".into()),
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(1)), "x"),
+            binary(BinaryOperator::Multiply, var("x"), var("x"), "dead"),
+            IrInstruction::JumpIfNotZero(var("x"), "loop".into()),
+            copy(IrValue::Constant(Constant::Int(3)), "y"),
+            IrInstruction::Return(var("y")),
+        ];
+        let mut cfg = Cfg::new(body);
+        eliminate_dead_stores(&mut cfg, &HashSet::new());
+        assert_eq!(cfg.into_instructions(), vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::FunCall { name: "f".into(), args: vec![], dst: var("unused") },
+            IrInstruction::Label("loop".into()),
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::JumpIfNotZero(var("x"), "loop".into()),
+            copy(IrValue::Constant(Constant::Int(3)), "y"),
+            IrInstruction::Return(var("y")),
+        ]);
+    }
+
+    #[test]
+    fn test_propagate_copies() {
+        let body = vec![
+            copy(var("a"), "x"),
+            copy(var("x"), "y"),
+            binary(BinaryOperator::Add, var("y"), var("x"), "z"),
+            copy(var("y"), "x"),
+            copy(IrValue::Constant(Constant::Int(1)), "a"),
+            IrInstruction::Return(var("y")),
+        ];
+        let mut cfg = Cfg::new(body);
+        propagate_copies(&mut cfg, &HashSet::new(), &BTreeMap::new());
+        assert_eq!(cfg.into_instructions(), vec![
+            copy(var("a"), "x"),
+            copy(var("a"), "y"),
+            binary(BinaryOperator::Add, var("a"), var("a"), "z"),
+            copy(IrValue::Constant(Constant::Int(1)), "a"),
+            IrInstruction::Return(var("y")),
+        ]);
+    }
+
+    #[test]
+    fn test_propagate_copies_across_blocks() {
+        // x = 1; if (c) y = x; else x = 2; return x;
+        let body = vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::JumpIfZero(var("c"), "else".into()),
+            copy(var("x"), "y"),
+            IrInstruction::Return(var("x")),
+            IrInstruction::Label("else".into()),
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            IrInstruction::Label("loop".into()),
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::JumpIfNotZero(var("x"), "loop".into()),
+            IrInstruction::Return(var("x")),
+        ];
+        let mut cfg = Cfg::new(body);
+        propagate_copies(&mut cfg, &HashSet::new(), &BTreeMap::new());
+        assert_eq!(cfg.into_instructions(), vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::JumpIfZero(var("c"), "else".into()),
+            copy(IrValue::Constant(Constant::Int(1)), "y"),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(1))),
+            IrInstruction::Label("else".into()),
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            IrInstruction::Label("loop".into()),
+            binary(BinaryOperator::Add, var("x"), IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::JumpIfNotZero(var("x"), "loop".into()),
+            IrInstruction::Return(var("x")),
+        ]);
+    }
+
+    #[test]
+    fn test_static_variables_survive_calls_and_returns() {
+        let ir = lower("int x; int f(void); int main(void) { int y = x; x = 1; f(); y = x; x = 2; return y; }");
+        let optimized = optimize(ir, Optimizations::level(1));
+        assert_eq!(optimized.functions[0].body, vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x"),
+            IrInstruction::FunCall { name: "f".into(), args: vec![], dst: var("tmp.0") },
+            copy(var("x"), "y.0"),
+            copy(IrValue::Constant(Constant::Int(2)), "x"),
+            IrInstruction::Return(var("y.0")),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_long_operations() {
+        let big = IrValue::Constant(Constant::Long(4294967297));
+        let body = vec![
+            binary(BinaryOperator::Multiply, big.clone(), IrValue::Constant(Constant::Long(2)), "tmp.0"),
+            IrInstruction::Truncate { src: big, dst: var("tmp.1") },
+            IrInstruction::SignExtend { src: IrValue::Constant(Constant::Int(-1)), dst: var("tmp.2") },
+            binary(BinaryOperator::Add, IrValue::Constant(Constant::Long(i64::MAX)), IrValue::Constant(Constant::Long(1)), "tmp.3"),
+        ];
+        let types = BTreeMap::from([("tmp.2".into(), Type::Long)]);
+        assert_eq!(fold_constants(body, &HashSet::new(), &types), vec![
+            copy(IrValue::Constant(Constant::Long(8589934594)), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(1)), "tmp.1"),
+            copy(IrValue::Constant(Constant::Long(-1)), "tmp.2"),
+            copy(IrValue::Constant(Constant::Long(i64::MIN)), "tmp.3"),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_unsigned_operations() {
+        let body = vec![
+            copy(IrValue::Constant(Constant::Int(-1)), "u"),
+            binary(BinaryOperator::Divide, var("u"), IrValue::Constant(Constant::UInt(2)), "tmp.0"),
+            binary(BinaryOperator::GreaterThan, var("u"), IrValue::Constant(Constant::UInt(1)), "tmp.1"),
+            binary(BinaryOperator::RightShift, var("u"), IrValue::Constant(Constant::Int(28)), "tmp.2"),
+            IrInstruction::ZeroExtend { src: var("u"), dst: var("tmp.3") },
+            IrInstruction::Unary { op: UnaryOperator::Negate, src: IrValue::Constant(Constant::ULong(1)), dst: var("tmp.4") },
+        ];
+        let types = BTreeMap::from([
+            ("u".into(), Type::UInt),
+            ("tmp.3".into(), Type::ULong),
+        ]);
+        assert_eq!(fold_constants(body, &HashSet::new(), &types), vec![
+            copy(IrValue::Constant(Constant::UInt(u32::MAX)), "u"),
+            copy(IrValue::Constant(Constant::UInt(i32::MAX as u32)), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(1)), "tmp.1"),
+            copy(IrValue::Constant(Constant::UInt(15)), "tmp.2"),
+            copy(IrValue::Constant(Constant::ULong(u64::from(u32::MAX))), "tmp.3"),
+            copy(IrValue::Constant(Constant::ULong(u64::MAX)), "tmp.4"),
+        ]);
+    }
+
+    #[test]
+    fn test_fold_double_operations() {
+        let double = |value: f64| IrValue::Constant(Constant::Double(value));
+        let body = vec![
+            binary(BinaryOperator::Divide, double(1.0), double(0.0), "tmp.0"),
+            binary(BinaryOperator::LessThan, double(f64::NAN), double(1.0), "tmp.1"),
+            binary(BinaryOperator::NotEqual, double(f64::NAN), double(f64::NAN), "tmp.2"),
+            IrInstruction::DoubleToInt { src: double(-2.7), dst: var("tmp.3") },
+            IrInstruction::UIntToDouble { src: IrValue::Constant(Constant::ULong(u64::MAX)), dst: var("tmp.4") },
+            // Adding 0.0 or multiplying by 0.0 is not an identity for doubles
+            binary(BinaryOperator::Add, var("d"), double(0.0), "tmp.5"),
+            binary(BinaryOperator::Multiply, var("d"), double(0.0), "tmp.6"),
+            binary(BinaryOperator::Multiply, var("d"), double(1.0), "tmp.7"),
+        ];
+        let types = BTreeMap::from([
+            ("d".into(), Type::Double),
+            ("tmp.3".into(), Type::Int),
+            ("tmp.4".into(), Type::Double),
+        ]);
+        assert_eq!(fold_constants(body, &HashSet::new(), &types), vec![
+            copy(double(f64::INFINITY), "tmp.0"),
+            copy(IrValue::Constant(Constant::Int(0)), "tmp.1"),
+            copy(IrValue::Constant(Constant::Int(1)), "tmp.2"),
+            copy(IrValue::Constant(Constant::Int(-2)), "tmp.3"),
+            copy(double(18446744073709551615.0), "tmp.4"),
+            binary(BinaryOperator::Add, var("d"), double(0.0), "tmp.5"),
+            binary(BinaryOperator::Multiply, var("d"), double(0.0), "tmp.6"),
+            copy(var("d"), "tmp.7"),
+        ]);
+    }
+
+    #[test]
+    fn test_stores_through_pointers_update_aliased_variables() {
+        let ir = lower("int main(void) { int x = 1; int *p = &x; int y = x; *p = 2; return x + y; }");
+        let optimized = optimize(ir, Optimizations::level(1));
+        assert_eq!(optimized.functions[0].body, vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x.0"),
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.0") },
+            IrInstruction::Store { src: IrValue::Constant(Constant::Int(2)), dst_ptr: var("tmp.0") },
+            binary(BinaryOperator::Add, var("x.0"), IrValue::Constant(Constant::Int(1)), "tmp.1"),
+            IrInstruction::Return(var("tmp.1")),
+        ]);
+    }
+
+    #[test]
+    fn test_keep_atomic_accesses() {
+        // Another thread may change the variable, so its loads are neither folded nor removed
+        let ir = lower("int main(void) { _Atomic int x = 1; int y = x; x = 2; x; return y; }");
+        let optimized = optimize(ir, Optimizations::level(1));
+        assert_eq!(optimized.functions[0].body, vec![
+            copy(IrValue::Constant(Constant::Int(1)), "x.0"),
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.0") },
+            IrInstruction::AtomicLoad { src_ptr: var("tmp.0"), dst: var("tmp.1") },
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.2") },
+            IrInstruction::AtomicStore { src: IrValue::Constant(Constant::Int(2)), dst_ptr: var("tmp.2") },
+            IrInstruction::GetAddress { src: var("x.0"), dst: var("tmp.3") },
+            IrInstruction::AtomicLoad { src_ptr: var("tmp.3"), dst: var("tmp.4") },
+            IrInstruction::Return(var("tmp.1")),
+        ]);
+    }
+
+    #[test]
+    fn test_keep_copies_that_change_signedness() {
+        let body = vec![
+            copy(var("i"), "u"),
+            binary(BinaryOperator::Divide, var("u"), IrValue::Constant(Constant::UInt(2)), "tmp.0"),
+            IrInstruction::Return(var("tmp.0")),
+        ];
+        let types = BTreeMap::from([
+            ("i".into(), Type::Int),
+            ("u".into(), Type::UInt),
+            ("tmp.0".into(), Type::UInt),
+        ]);
+        let mut cfg = Cfg::new(body.clone());
+        propagate_copies(&mut cfg, &HashSet::new(), &types);
+        assert_eq!(cfg.into_instructions(), body);
+    }
+}
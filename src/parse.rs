@@ -1,54 +1,1496 @@
 use crate::ast::*;
+use crate::diagnostics::{Diagnostic,Diagnostics};
 
-pub fn parse(tokens: Vec<Token>) -> Result<Program, String> {
+/// Parses a token stream into a program. After a syntax error the parser skips ahead to the
+/// end of the statement or block it was in and carries on, so one run reports every error
+/// it can find.
+///
+/// # Arguments
+///
+/// * `tokens` - The tokens produced by the lexer.
+///
+/// # Returns
+///
+/// The parsed `Program`, or an `Err` with all of the syntax errors found.
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<Program, Diagnostics> {
     let mut iter = tokens.into_iter().peekable();
+    let mut errors = Vec::new();
+
+    let mut declarations = Vec::new();
+    while iter.peek().is_some() {
+        match parse_declaration(&mut iter, &mut errors) {
+            Ok(declaration) => declarations.push(declaration),
+            Err(error) => {
+                errors.push(error);
+                synchronize(&mut iter);
+                // A stray closing brace does not end anything at file scope
+                if let Some(Token::CloseBrace) = peek_token(&mut iter) {
+                    iter.next();
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Program{declarations})
+    } else {
+        Err(Diagnostics(errors))
+    }
+}
+
+/// Skips tokens after a syntax error until a point where parsing can resume: just past the
+/// next `;` or the next brace-enclosed block, such as the body of a function whose header
+/// is malformed or the members of a structure along with the `;` that ends them, or just
+/// before the `}` that closes the enclosing block.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+fn synchronize(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) {
+    let mut depth = 0;
+    while let Some(token) = peek_token(iter) {
+        match token {
+            Token::Semicolon if depth == 0 => {
+                iter.next();
+                return;
+            }
+            Token::CloseBrace if depth == 0 => return,
+            Token::CloseBrace if depth == 1 => {
+                iter.next();
+                if let Some(Token::Semicolon) = peek_token(iter) {
+                    iter.next();
+                }
+                return;
+            }
+            Token::CloseBrace => depth -= 1,
+            Token::OpenBrace => depth += 1,
+            _ => {}
+        }
+        iter.next();
+    }
+}
+
+/// Parses a block item: a declaration if the next token is a type or storage-class specifier,
+/// otherwise a statement.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside nested blocks.
+///
+/// # Returns
+///
+/// The parsed `BlockItem`, or an `Err` with an error message.
+fn parse_block_item(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>) -> Result<BlockItem, Diagnostic> {
+    match peek_token(iter) {
+        Some(token) if starts_declaration(token) => Ok(BlockItem::Declaration(parse_declaration(iter, errors)?)),
+        _ => Ok(BlockItem::Statement(parse_statement(iter, errors)?)),
+    }
+}
+
+/// Checks whether a token can begin the specifiers of a declaration.
+///
+/// # Arguments
+///
+/// * `token` - The token to check.
+///
+/// # Returns
+///
+/// `true` for the type specifiers and the storage classes.
+fn starts_declaration(token: &Token) -> bool {
+    starts_type_name(token) || matches!(token, Token::StaticKeyword | Token::ExternKeyword)
+}
+
+/// Checks whether a token starts a type name, such as that of a cast: a type specifier or
+/// the `_Atomic` qualifier.
+fn starts_type_name(token: &Token) -> bool {
+    is_type_specifier(token) || *token == Token::AtomicKeyword
+}
+
+/// Checks whether a token is a type specifier.
+///
+/// # Arguments
+///
+/// * `token` - The token to check.
+///
+/// # Returns
+///
+/// `true` for `int`, `char`, `wchar_t`, `long`, `signed`, `unsigned`, `double` and `struct`.
+fn is_type_specifier(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::IntKeyword
+            | Token::CharKeyword
+            | Token::WcharKeyword
+            | Token::LongKeyword
+            | Token::SignedKeyword
+            | Token::UnsignedKeyword
+            | Token::DoubleKeyword
+            | Token::StructKeyword
+    )
+}
+
+/// Parses the specifiers at the start of a declaration, which may come in any order: the
+/// type specifiers, the `_Atomic` qualifier, as often as it is repeated, and at most one
+/// storage class. A structure type is named by `struct` and its tag, which take the place
+/// of all the other type specifiers.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The declared type and the storage class, if one was given, or an `Err` with an error message.
+fn parse_specifiers(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<(Type, Option<StorageClass>), Diagnostic> {
+    let mut type_specifiers = Vec::new();
+    let mut struct_tag = None;
+    let mut storage_class = None;
+    let mut atomic = false;
+    let start = peek_span(iter);
+    loop {
+        let span = peek_span(iter);
+        match peek_token(iter) {
+            Some(token) if is_type_specifier(token) => {
+                let token = iter.next().map(|spanned| spanned.token).expect("peeked above");
+                if type_specifiers.contains(&token) {
+                    return Err(Diagnostic::error(span, format!("Duplicate type specifier '{}'", type_specifier_name(&token))));
+                }
+                if token == Token::StructKeyword {
+                    struct_tag = Some(expect_identifier(iter)?);
+                }
+                type_specifiers.push(token);
+                continue;
+            }
+            Some(Token::StaticKeyword | Token::ExternKeyword) if storage_class.is_some() => {
+                return Err(Diagnostic::error(span, "More than one storage class in declaration"));
+            }
+            Some(Token::StaticKeyword) => storage_class = Some(StorageClass::Static),
+            Some(Token::ExternKeyword) => storage_class = Some(StorageClass::Extern),
+            Some(Token::AtomicKeyword) => atomic = true,
+            _ => break,
+        }
+        iter.next();
+    }
+    if type_specifiers.is_empty() {
+        return Err(unexpected(iter, "a type specifier"));
+    }
+    let ty = match struct_tag {
+        Some(_) if type_specifiers.len() > 1 => return Err(Diagnostic::error(start, "Invalid type specifier")),
+        Some(tag) => Type::Struct(tag),
+        None => type_from_specifiers(&type_specifiers, start)?,
+    };
+    let ty = if atomic { Type::Atomic(Box::new(ty)) } else { ty };
+    Ok((ty, storage_class))
+}
+
+/// Parses the type specifiers of a parameter, which has no storage class.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The type, or an `Err` with an error message.
+fn parse_type(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Type, Diagnostic> {
+    let span = peek_span(iter);
+    match parse_specifiers(iter)? {
+        (ty, None) => Ok(ty),
+        (_, Some(_)) => Err(Diagnostic::error(span, "Storage class in a parameter declaration")),
+    }
+}
+
+/// Works out the type named by a list of distinct type specifiers: `double` or `wchar_t` on
+/// its own, `char` on its own or with `signed` or `unsigned`, or `int`, `long`, `signed` and
+/// `unsigned` in any combination that does not mix `signed` with `unsigned`.
+///
+/// `wchar_t` is `int` on every target. The AArch64 Linux ABI makes it `unsigned int`, which
+/// is passed and stored the same way, but compares, divides and converts differently for
+/// values above `INT_MAX`, such as `(wchar_t)-1`. No character has such a value.
+///
+/// # Arguments
+///
+/// * `specifiers` - The type specifier tokens, in source order.
+/// * `span` - The position of the first specifier, where errors are reported.
+///
+/// # Returns
+///
+/// The type, or an `Err` if the specifiers do not name one.
+fn type_from_specifiers(specifiers: &[Token], span: Span) -> Result<Type, Diagnostic> {
+    if specifiers.is_empty()
+        || (specifiers.contains(&Token::SignedKeyword) && specifiers.contains(&Token::UnsignedKeyword))
+    {
+        return Err(Diagnostic::error(span, "Invalid type specifier"));
+    }
+    if specifiers.contains(&Token::DoubleKeyword) {
+        return match specifiers {
+            [_] => Ok(Type::Double),
+            _ => Err(Diagnostic::error(span, "Invalid type specifier")),
+        };
+    }
+    // Like any typedef name, wchar_t cannot be combined with other specifiers
+    if specifiers.contains(&Token::WcharKeyword) {
+        return match specifiers {
+            [_] => Ok(Type::Int),
+            _ => Err(Diagnostic::error(span, "Invalid type specifier")),
+        };
+    }
+    if specifiers.contains(&Token::CharKeyword) {
+        return match specifiers {
+            [_] => Ok(Type::Char),
+            [Token::SignedKeyword, _] | [_, Token::SignedKeyword] => Ok(Type::SChar),
+            [Token::UnsignedKeyword, _] | [_, Token::UnsignedKeyword] => Ok(Type::UChar),
+            _ => Err(Diagnostic::error(span, "Invalid type specifier")),
+        };
+    }
+    let long = specifiers.contains(&Token::LongKeyword);
+    match (specifiers.contains(&Token::UnsignedKeyword), long) {
+        (true, true) => Ok(Type::ULong),
+        (true, false) => Ok(Type::UInt),
+        (false, true) => Ok(Type::Long),
+        (false, false) => Ok(Type::Int),
+    }
+}
+
+/// Returns the C spelling of a type specifier keyword.
+fn type_specifier_name(token: &Token) -> &'static str {
+    match token {
+        Token::CharKeyword => "char",
+        Token::WcharKeyword => "wchar_t",
+        Token::LongKeyword => "long",
+        Token::SignedKeyword => "signed",
+        Token::UnsignedKeyword => "unsigned",
+        Token::DoubleKeyword => "double",
+        Token::StructKeyword => "struct",
+        _ => "int",
+    }
+}
+
+/// Parses a variable declaration with an optional initializer, such as `int x = 5;` or
+/// `int a[2] = {1, 2};`, or a function declaration with an optional body, such as
+/// `int f(int a, int b);`. Either may start with a storage class, as in `static int x;`,
+/// and the name may be wrapped in pointer and array declarators, as in `int **p;`,
+/// `long *f(int *a);` or `int (*p)[3];`. A structure type is declared by its specifier on
+/// its own, as in `struct s { int a; };` or `struct s;`.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside nested blocks.
+///
+/// # Returns
+///
+/// The parsed `Declaration`, or an `Err` with an error message.
+fn parse_declaration(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>) -> Result<Declaration, Diagnostic> {
+    let start = peek_span(iter);
+    let (base_type, storage_class) = parse_specifiers(iter)?;
+    if let Type::Struct(tag) = &base_type {
+        if matches!(peek_token(iter), Some(Token::OpenBrace | Token::Semicolon)) {
+            if storage_class.is_some() {
+                return Err(Diagnostic::error(start, "Storage class in a structure declaration"));
+            }
+            return Ok(Declaration::Struct(parse_struct_declaration(iter, errors, *tag, start)?));
+        }
+    }
+    let (name, ty, params, span) = apply_declarator(parse_declarator(iter)?, base_type)?;
+    if let Type::Function { .. } = ty {
+        let body = if let Some(Token::Semicolon) = peek_token(iter) {
+            iter.next();
+            None
+        } else {
+            Some(parse_block(iter, errors)?)
+        };
+        return Ok(Declaration::Function(FunDecl { name, params, body, ty, storage_class, span }));
+    }
+    match peek_token(iter) {
+        Some(Token::Assignment) => {
+            iter.next();
+            let init = Some(parse_initializer(iter)?);
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Declaration::Variable(VarDecl { name, init, ty, storage_class, span }))
+        }
+        _ => {
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Declaration::Variable(VarDecl { name, init: None, ty, storage_class, span }))
+        }
+    }
+}
+
+/// Parses the rest of a structure declaration after its tag: a brace-enclosed list of
+/// member declarations, which may not be empty, or nothing, followed by a `;`.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside the member list.
+/// * `tag` - The tag of the structure.
+/// * `span` - The position of the `struct` keyword.
+///
+/// # Returns
+///
+/// The parsed `StructDecl`, or an `Err` with an error message.
+fn parse_struct_declaration(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>, tag: Name, span: Span) -> Result<StructDecl, Diagnostic> {
+    let members = if let Some(Token::OpenBrace) = peek_token(iter) {
+        iter.next();
+        if let Some(Token::CloseBrace) = peek_token(iter) {
+            let close = peek_span(iter);
+            iter.next();
+            return Err(Diagnostic::error(close, format!("Structure '{}' has no members", tag)));
+        }
+        let mut members = Vec::new();
+        while peek_token(iter).is_some_and(|token| *token != Token::CloseBrace) {
+            match parse_member_declaration(iter) {
+                Ok(member) => members.push(member),
+                Err(error) => {
+                    errors.push(error);
+                    synchronize(iter);
+                }
+            }
+        }
+        expect_token(iter, Token::CloseBrace)?;
+        Some(members)
+    } else {
+        None
+    };
+    expect_token(iter, Token::Semicolon)?;
+    Ok(StructDecl { tag, members, span })
+}
+
+/// Parses the declaration of one member of a structure, such as `int x;` or `char *p[2];`,
+/// which has no storage class or initializer and cannot declare a function.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `MemberDecl`, or an `Err` with an error message.
+fn parse_member_declaration(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<MemberDecl, Diagnostic> {
+    let span = peek_span(iter);
+    let base_type = match parse_specifiers(iter)? {
+        (ty, None) => ty,
+        (_, Some(_)) => return Err(Diagnostic::error(span, "Storage class in a member declaration")),
+    };
+    let (name, ty, _, span) = apply_declarator(parse_declarator(iter)?, base_type)?;
+    if let Type::Function { .. } = ty {
+        return Err(Diagnostic::error(span, format!("Member '{}' declared as a function", name)));
+    }
+    expect_token(iter, Token::Semicolon)?;
+    Ok(MemberDecl { name, ty, span })
+}
+
+/// Parses the initializer of a variable: an expression, or a brace-enclosed list of
+/// initializers separated by commas, which may end with a comma.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `Initializer`, or an `Err` with an error message.
+fn parse_initializer(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Initializer, Diagnostic> {
+    if peek_token(iter) != Some(&Token::OpenBrace) {
+        return Ok(Initializer::Single(parse_exp(iter, ASSIGNMENT_PRECEDENCE)?));
+    }
+    let span = next_span(iter);
+    let mut elements = vec![parse_initializer(iter)?];
+    while let Some(Token::Comma) = peek_token(iter) {
+        iter.next();
+        if let Some(Token::CloseBrace) = peek_token(iter) {
+            break;
+        }
+        elements.push(parse_initializer(iter)?);
+    }
+    expect_token(iter, Token::CloseBrace)?;
+    Ok(Initializer::Compound(elements, span))
+}
+
+/// The part of a declaration after its specifiers, which names the declared identifier and
+/// derives its type from the specified one.
+#[derive(Debug)]
+enum Declarator {
+    Identifier(Name, Span),
+    /// `*d`, which makes a pointer to the type of `d`.
+    Pointer(Box<Declarator>),
+    /// `d[n]`, which makes an array of `n` elements of the type of `d`.
+    Array(Box<Declarator>, Exp),
+    /// `d(params)`, which makes a function returning the type of `d`. Each parameter has its
+    /// own specified type and declarator.
+    Function(Vec<(Type, Declarator)>, Box<Declarator>),
+}
+
+/// Parses a declarator: an identifier, possibly parenthesized, preceded by any number of
+/// `*` and followed by any number of parameter lists and array sizes.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `Declarator`, or an `Err` with an error message.
+fn parse_declarator(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Declarator, Diagnostic> {
+    if let Some(Token::Multiplication) = peek_token(iter) {
+        iter.next();
+        return Ok(Declarator::Pointer(Box::new(parse_declarator(iter)?)));
+    }
+    let declarator = if let Some(Token::OpenParenthesis) = peek_token(iter) {
+        iter.next();
+        let declarator = parse_declarator(iter)?;
+        expect_token(iter, Token::CloseParenthesis)?;
+        declarator
+    } else {
+        let span = peek_span(iter);
+        Declarator::Identifier(expect_identifier(iter)?, span)
+    };
+    let mut declarator = declarator;
+    loop {
+        declarator = match peek_token(iter) {
+            Some(Token::OpenParenthesis) => {
+                iter.next();
+                Declarator::Function(parse_parameter_list(iter)?, Box::new(declarator))
+            }
+            Some(Token::OpenBracket) => Declarator::Array(Box::new(declarator), parse_array_size(iter)?),
+            _ => return Ok(declarator),
+        };
+    }
+}
+
+/// Parses the size of an array declarator, an expression in brackets. The size may depend on
+/// the layouts of structures and the types of variables, as in `sizeof x`, so the type
+/// checker evaluates it, and checks that it is a positive integer constant.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The size expression, or an `Err` with an error message.
+fn parse_array_size(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Exp, Diagnostic> {
+    expect_token(iter, Token::OpenBracket)?;
+    let size = parse_exp(iter, 0)?;
+    expect_token(iter, Token::CloseBracket)?;
+    Ok(size)
+}
+
+/// Works out the name and type a declarator declares, given the type named by the specifiers.
+/// Functions may only be declared by name, so pointers to functions, arrays of functions and
+/// functions returning functions are rejected, as are functions returning arrays. Array
+/// types keep their size expressions for the type checker to evaluate.
+///
+/// # Arguments
+///
+/// * `declarator` - The declarator.
+/// * `base_type` - The type named by the specifiers of the declaration.
+///
+/// # Returns
+///
+/// The declared name, its type, the names of its parameters if it is a function, and the
+/// position of the name, or an `Err` if the declarator derives an unsupported type.
+fn apply_declarator(declarator: Declarator, base_type: Type) -> Result<(Name, Type, Vec<Name>, Span), Diagnostic> {
+    match declarator {
+        Declarator::Identifier(name, span) => Ok((name, base_type, Vec::new(), span)),
+        Declarator::Pointer(inner) => apply_declarator(*inner, Type::Pointer(Box::new(base_type))),
+        Declarator::Array(inner, size) => apply_declarator(*inner, Type::DeclaredArray(Box::new(base_type), Box::new(size))),
+        Declarator::Function(params, inner) => {
+            let (name, span) = match *inner {
+                Declarator::Identifier(_, span) if matches!(base_type, Type::DeclaredArray(..)) => {
+                    return Err(Diagnostic::error(span, "Function declared as returning an array"));
+                }
+                Declarator::Identifier(name, span) => (name, span),
+                Declarator::Pointer(inner) => {
+                    return Err(Diagnostic::error(declarator_span(&inner), "Pointers to functions are not supported"));
+                }
+                Declarator::Array(inner, _) => {
+                    return Err(Diagnostic::error(declarator_span(&inner), "Declared as an array of functions"));
+                }
+                Declarator::Function(_, inner) => {
+                    return Err(Diagnostic::error(declarator_span(&inner), "Function declared as returning a function"));
+                }
+            };
+            let mut param_types = Vec::new();
+            let mut param_names = Vec::new();
+            for (param_type, param) in params {
+                let (param_name, param_type, _, param_span) = apply_declarator(param, param_type)?;
+                if let Type::Function { .. } = param_type {
+                    return Err(Diagnostic::error(param_span, format!("Parameter '{}' declared as a function", param_name)));
+                }
+                param_types.push(param_type);
+                param_names.push(param_name);
+            }
+            Ok((name, Type::Function { params: param_types, ret: Box::new(base_type) }, param_names, span))
+        }
+    }
+}
+
+/// Returns the position of the identifier a declarator declares.
+fn declarator_span(declarator: &Declarator) -> Span {
+    match declarator {
+        Declarator::Identifier(_, span) => *span,
+        Declarator::Pointer(inner) | Declarator::Array(inner, _) | Declarator::Function(_, inner) => declarator_span(inner),
+    }
+}
+
+/// The declarator of a type name, as in the cast `(int (*)[3]) p`, which derives a type
+/// from the specified one like a `Declarator` but names no identifier.
+#[derive(Debug)]
+enum AbstractDeclarator {
+    Base,
+    /// `*d`, which makes a pointer to the type of `d`.
+    Pointer(Box<AbstractDeclarator>),
+    /// `d[n]`, which makes an array of `n` elements of the type of `d`.
+    Array(Box<AbstractDeclarator>, Exp),
+}
+
+/// Parses the abstract declarator of a type name: any number of `*`, followed by a
+/// parenthesized abstract declarator or nothing, followed by any number of array sizes.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `AbstractDeclarator`, or an `Err` with an error message.
+fn parse_abstract_declarator(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<AbstractDeclarator, Diagnostic> {
+    if let Some(Token::Multiplication) = peek_token(iter) {
+        iter.next();
+        return Ok(AbstractDeclarator::Pointer(Box::new(parse_abstract_declarator(iter)?)));
+    }
+    let mut declarator = AbstractDeclarator::Base;
+    if let Some(Token::OpenParenthesis) = peek_token(iter) {
+        iter.next();
+        // The parentheses must enclose a declarator, as `int ()` would name a function type
+        if !matches!(peek_token(iter), Some(Token::Multiplication | Token::OpenParenthesis | Token::OpenBracket)) {
+            expect_token(iter, Token::Multiplication)?;
+        }
+        declarator = parse_abstract_declarator(iter)?;
+        expect_token(iter, Token::CloseParenthesis)?;
+    }
+    while let Some(Token::OpenBracket) = peek_token(iter) {
+        declarator = AbstractDeclarator::Array(Box::new(declarator), parse_array_size(iter)?);
+    }
+    Ok(declarator)
+}
+
+/// Works out the type an abstract declarator derives from the type named by the specifiers.
+fn apply_abstract_declarator(declarator: AbstractDeclarator, base_type: Type) -> Type {
+    match declarator {
+        AbstractDeclarator::Base => base_type,
+        AbstractDeclarator::Pointer(inner) => apply_abstract_declarator(*inner, Type::Pointer(Box::new(base_type))),
+        AbstractDeclarator::Array(inner, size) => {
+            apply_abstract_declarator(*inner, Type::DeclaredArray(Box::new(base_type), Box::new(size)))
+        }
+    }
+}
+
+/// Parses a variable declaration, as allowed in the header of a `for` loop, which may not
+/// have a storage class.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside nested blocks.
+///
+/// # Returns
+///
+/// The parsed `VarDecl`, or an `Err` with an error message.
+fn parse_variable_declaration(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>) -> Result<VarDecl, Diagnostic> {
+    match parse_declaration(iter, errors)? {
+        Declaration::Variable(variable) => {
+            // The declaration is well formed otherwise, so parsing carries on after the error
+            if variable.storage_class.is_some() {
+                errors.push(Diagnostic::error(variable.span, "Storage class in the declaration of a for loop"));
+            }
+            Ok(variable)
+        }
+        Declaration::Function(function) => {
+            let message = format!("Function '{}' declared where a variable declaration was expected", function.name);
+            Err(Diagnostic::error(function.span, message))
+        }
+        Declaration::Struct(declaration) => {
+            let message = format!("Structure '{}' declared where a variable declaration was expected", declaration.tag);
+            Err(Diagnostic::error(declaration.span, message))
+        }
+    }
+}
+
+/// Parses a parameter list after its opening parenthesis, up to and including the closing one.
+/// The list is either `void` or a comma-separated list of typed parameters.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The specified type and the declarator of each parameter, or an `Err` with an error message.
+fn parse_parameter_list(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Vec<(Type, Declarator)>, Diagnostic> {
+    let mut params = Vec::new();
+    match peek_token(iter) {
+        Some(Token::VoidKeyword) => {
+            iter.next(); // Consume the void keyword
+        }
+        Some(Token::CloseParenthesis) => {
+            // No parameters, continue
+        }
+        _ => loop {
+            let ty = parse_type(iter)?;
+            params.push((ty, parse_declarator(iter)?));
+            if let Some(Token::Comma) = peek_token(iter) {
+                iter.next();
+            } else {
+                break;
+            }
+        },
+    }
+    expect_token(iter, Token::CloseParenthesis)?;
+    Ok(params)
+}
+
+/// Parses a brace-enclosed list of block items.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside nested blocks.
+///
+/// # Returns
+///
+/// The block items, or an `Err` with an error message.
+fn parse_block(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>) -> Result<Vec<BlockItem>, Diagnostic> {
+    expect_token(iter, Token::OpenBrace)?;
+    let mut body = Vec::new();
+    while peek_token(iter).is_some_and(|token| *token != Token::CloseBrace) {
+        match parse_block_item(iter, errors) {
+            Ok(item) => body.push(item),
+            Err(error) => {
+                errors.push(error);
+                synchronize(iter);
+            }
+        }
+    }
+    expect_token(iter, Token::CloseBrace)?;
+    Ok(body)
+}
+
+/// Parses a statement.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `errors` - Collects the syntax errors recovered from inside nested blocks.
+///
+/// # Returns
+///
+/// The parsed `Statement`, or an `Err` with an error message.
+fn parse_statement(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, errors: &mut Vec<Diagnostic>) -> Result<Statement, Diagnostic> {
+    match peek_token(iter) {
+        Some(Token::ReturnKeyword) => {
+            let span = next_span(iter);
+            let exp = parse_exp(iter, 0)?;
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Return(exp, span))
+        }
+        Some(Token::OpenBrace) => Ok(Statement::Compound(parse_block(iter, errors)?)),
+        Some(Token::IfKeyword) => {
+            iter.next();
+            expect_token(iter, Token::OpenParenthesis)?;
+            let condition = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            let then = parse_statement(iter, errors)?;
+            // A dangling `else` binds to the innermost `if`
+            let otherwise = if let Some(Token::ElseKeyword) = peek_token(iter) {
+                iter.next();
+                Some(Box::new(parse_statement(iter, errors)?))
+            } else {
+                None
+            };
+            Ok(Statement::If(condition, Box::new(then), otherwise))
+        }
+        Some(Token::WhileKeyword) => {
+            iter.next();
+            expect_token(iter, Token::OpenParenthesis)?;
+            let condition = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            let body = Box::new(parse_statement(iter, errors)?);
+            Ok(Statement::While { condition, body, label: Name::default() })
+        }
+        Some(Token::DoKeyword) => {
+            iter.next();
+            let body = Box::new(parse_statement(iter, errors)?);
+            expect_token(iter, Token::WhileKeyword)?;
+            expect_token(iter, Token::OpenParenthesis)?;
+            let condition = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::DoWhile { body, condition, label: Name::default() })
+        }
+        Some(Token::ForKeyword) => {
+            iter.next();
+            expect_token(iter, Token::OpenParenthesis)?;
+            let init = match peek_token(iter) {
+                // A declaration consumes its own semicolon
+                Some(token) if starts_declaration(token) => ForInit::Declaration(parse_variable_declaration(iter, errors)?),
+                _ => {
+                    let init = parse_optional_exp(iter, Token::Semicolon)?;
+                    expect_token(iter, Token::Semicolon)?;
+                    ForInit::Expression(init)
+                }
+            };
+            let condition = parse_optional_exp(iter, Token::Semicolon)?;
+            expect_token(iter, Token::Semicolon)?;
+            let post = parse_optional_exp(iter, Token::CloseParenthesis)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            let body = Box::new(parse_statement(iter, errors)?);
+            Ok(Statement::For { init: Box::new(init), condition, post, body, label: Name::default() })
+        }
+        Some(Token::BreakKeyword) => {
+            let span = next_span(iter);
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Break(Name::default(), span))
+        }
+        Some(Token::ContinueKeyword) => {
+            let span = next_span(iter);
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Continue(Name::default(), span))
+        }
+        Some(Token::SwitchKeyword) => {
+            let span = next_span(iter);
+            expect_token(iter, Token::OpenParenthesis)?;
+            let condition = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            let body = Box::new(parse_statement(iter, errors)?);
+            Ok(Statement::Switch { condition, body, cases: Vec::new(), label: Name::default(), span })
+        }
+        Some(Token::CaseKeyword) => {
+            let span = next_span(iter);
+            let value = parse_exp(iter, 0)?;
+            expect_token(iter, Token::Colon)?;
+            let body = Box::new(parse_statement(iter, errors)?);
+            Ok(Statement::Case { value, body, label: Name::default(), span })
+        }
+        Some(Token::DefaultKeyword) => {
+            let span = next_span(iter);
+            expect_token(iter, Token::Colon)?;
+            let body = Box::new(parse_statement(iter, errors)?);
+            Ok(Statement::Default { body, label: Name::default(), span })
+        }
+        Some(Token::GotoKeyword) => {
+            let span = next_span(iter);
+            let label = expect_identifier(iter)?;
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Goto(label, span))
+        }
+        Some(Token::Semicolon) => {
+            iter.next();
+            Ok(Statement::Null)
+        }
+        // An identifier followed by a colon labels the statement after it, and otherwise
+        // starts an expression
+        Some(Token::Identifier(_)) => {
+            let span = peek_span(iter);
+            let name = expect_identifier(iter)?;
+            if let Some(Token::Colon) = peek_token(iter) {
+                iter.next();
+                return Ok(Statement::Labeled(name, Box::new(parse_statement(iter, errors)?), span));
+            }
+            let primary = parse_identifier_exp(iter, name, span)?;
+            let operand = parse_postfix_exp(iter, primary)?;
+            let exp = parse_infix_exp(iter, operand, 0)?;
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Expression(exp))
+        }
+        _ => {
+            let exp = parse_exp(iter, 0)?;
+            expect_token(iter, Token::Semicolon)?;
+            Ok(Statement::Expression(exp))
+        }
+    }
+}
+
+/// Parses an expression unless the next token is `terminator`, as for the optional
+/// clauses of a `for` loop header. The terminator itself is not consumed.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `terminator` - The token that follows the clause.
+///
+/// # Returns
+///
+/// The parsed `Exp` if there is one, or an `Err` with an error message.
+fn parse_optional_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, terminator: Token) -> Result<Option<Exp>, Diagnostic> {
+    if peek_token(iter) == Some(&terminator) {
+        Ok(None)
+    } else {
+        parse_exp(iter, 0).map(Some)
+    }
+}
+
+/// Parses an expression using precedence climbing: binary operators whose precedence is
+/// at least `min_precedence` are folded into the result, left-associatively except for
+/// the assignment operators and the conditional operator, which are right-associative.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `min_precedence` - The lowest binary operator precedence this call may consume.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, min_precedence: u8) -> Result<Exp, Diagnostic> {
+    let left = parse_factor(iter)?;
+    parse_infix_exp(iter, left, min_precedence)
+}
+
+/// Parses the binary operators following the first operand of an expression, as
+/// `parse_exp` does, for a caller that has already parsed that operand.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `left` - The first operand, already parsed.
+/// * `min_precedence` - The lowest binary operator precedence this call may consume.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_infix_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, mut left: Exp, min_precedence: u8) -> Result<Exp, Diagnostic> {
+    while let Some((operator, precedence)) = peek_token(iter).and_then(binary_operator) {
+        if precedence < min_precedence {
+            break;
+        }
+        let span = next_span(iter);
+        left = match operator {
+            InfixOperator::Assignment => Exp::Assignment(Box::new(left), Box::new(parse_exp(iter, precedence)?), span),
+            InfixOperator::CompoundAssignment(operator) => {
+                Exp::CompoundAssignment(operator, Box::new(left), Box::new(parse_exp(iter, precedence)?), span)
+            }
+            InfixOperator::Conditional => {
+                // The middle operand is parsed as if it were parenthesized
+                let then = parse_exp(iter, 0)?;
+                expect_token(iter, Token::Colon)?;
+                let otherwise = parse_exp(iter, precedence)?;
+                Exp::Conditional(Box::new(left), Box::new(then), Box::new(otherwise), span)
+            }
+            InfixOperator::Binary(operator) => {
+                let right = parse_exp(iter, precedence + 1)?;
+                Exp::BinOp(operator, Box::new(left), Box::new(right), span)
+            }
+            InfixOperator::Comma => Exp::Comma(Box::new(left), Box::new(parse_exp(iter, precedence + 1)?), span),
+        };
+    }
+    Ok(left)
+}
+
+/// Parses a factor: a unary operator, prefix `++`/`--`, dereference, address-of or cast
+/// applied to a factor, or a postfix expression.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_factor(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Exp, Diagnostic> {
+    let operator = match peek_token(iter) {
+        Some(Token::Negation) => UnaryOperator::Negate,
+        Some(Token::BitwiseComplement) => UnaryOperator::Complement,
+        Some(Token::LogicalNegation) => UnaryOperator::Not,
+        Some(Token::Increment | Token::Decrement) => {
+            let operator = if peek_token(iter) == Some(&Token::Increment) { BinaryOperator::Add } else { BinaryOperator::Subtract };
+            let span = next_span(iter);
+            let operand = parse_factor(iter)?;
+            return Ok(Exp::CompoundAssignment(operator, Box::new(operand), Box::new(Exp::Const(Constant::Int(1))), span));
+        }
+        Some(Token::Multiplication) => {
+            let span = next_span(iter);
+            return Ok(Exp::Dereference(Box::new(parse_factor(iter)?), span));
+        }
+        Some(Token::BitwiseAnd) => {
+            let span = next_span(iter);
+            return Ok(Exp::AddressOf(Box::new(parse_factor(iter)?), span));
+        }
+        Some(Token::SizeOfKeyword) => {
+            let span = next_span(iter);
+            if peek_token(iter) != Some(&Token::OpenParenthesis) {
+                return Ok(Exp::SizeOf(Box::new(parse_factor(iter)?), span));
+            }
+            // `sizeof (int)` takes a type name, while `sizeof (x)` takes a parenthesized
+            // expression, which may be followed by postfix operators
+            iter.next();
+            if peek_token(iter).is_some_and(starts_type_name) {
+                let ty = parse_type(iter)?;
+                let ty = apply_abstract_declarator(parse_abstract_declarator(iter)?, ty);
+                expect_token(iter, Token::CloseParenthesis)?;
+                return Ok(Exp::SizeOfType(ty, span));
+            }
+            let exp = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            return Ok(Exp::SizeOf(Box::new(parse_postfix_exp(iter, exp)?), span));
+        }
+        Some(Token::OpenParenthesis) => {
+            // A type name in parentheses makes a cast rather than a parenthesized expression
+            let span = next_span(iter);
+            if peek_token(iter).is_some_and(starts_type_name) {
+                let ty = parse_type(iter)?;
+                let ty = apply_abstract_declarator(parse_abstract_declarator(iter)?, ty);
+                expect_token(iter, Token::CloseParenthesis)?;
+                let operand = parse_factor(iter)?;
+                return Ok(Exp::Cast(ty, Box::new(operand), span));
+            }
+            let exp = parse_exp(iter, 0)?;
+            expect_token(iter, Token::CloseParenthesis)?;
+            return parse_postfix_exp(iter, exp);
+        }
+        _ => {
+            let exp = parse_primary_exp(iter)?;
+            return parse_postfix_exp(iter, exp);
+        }
+    };
+    let span = next_span(iter);
+    let operand = parse_factor(iter)?;
+    Ok(Exp::UnOp(operator, Box::new(operand), span))
+}
+
+/// Parses any number of postfix `++` and `--` operators, subscripts and member accesses
+/// following a primary expression. `p->m` is parsed as `(*p).m`.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `exp` - The primary expression, already parsed.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_postfix_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, mut exp: Exp) -> Result<Exp, Diagnostic> {
+    loop {
+        exp = match peek_token(iter) {
+            Some(token @ (Token::Increment | Token::Decrement)) => {
+                let operator = if *token == Token::Increment { BinaryOperator::Add } else { BinaryOperator::Subtract };
+                let span = next_span(iter);
+                Exp::PostfixUpdate(operator, Box::new(exp), span)
+            }
+            Some(Token::OpenBracket) => {
+                let span = next_span(iter);
+                let index = parse_exp(iter, 0)?;
+                expect_token(iter, Token::CloseBracket)?;
+                Exp::Subscript(Box::new(exp), Box::new(index), span)
+            }
+            Some(token @ (Token::Dot | Token::Arrow)) => {
+                let arrow = *token == Token::Arrow;
+                let span = next_span(iter);
+                let member = expect_identifier(iter)?;
+                let base = if arrow { Exp::Dereference(Box::new(exp), span) } else { exp };
+                Exp::Member { base: Box::new(base), member, offset: 0, ty: Type::Int, span }
+            }
+            _ => return Ok(exp),
+        };
+    }
+}
+
+/// Parses a primary expression: a constant, a string literal, a variable, or a function call.
+/// Adjacent string literals are joined into one. Parenthesized expressions are parsed by
+/// `parse_factor`, which has to tell them apart from casts.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_primary_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Exp, Diagnostic> {
+    match peek_token(iter) {
+        Some(Token::Identifier(_)) => {
+            let span = peek_span(iter);
+            let name = expect_identifier(iter)?;
+            parse_identifier_exp(iter, name, span)
+        }
+        Some(Token::StringLiteral(_) | Token::WideStringLiteral(_)) => {
+            // Joining a string literal to a wide one makes the whole string wide
+            let span = peek_span(iter);
+            let mut string = String::new();
+            let mut wide = false;
+            while let Some(spanned) =
+                iter.next_if(|spanned| matches!(spanned.token, Token::StringLiteral(_) | Token::WideStringLiteral(_)))
+            {
+                match spanned.token {
+                    Token::WideStringLiteral(part) => {
+                        wide = true;
+                        string.push_str(&part);
+                    }
+                    Token::StringLiteral(part) => string.push_str(&part),
+                    _ => unreachable!("only string literals are taken above"),
+                }
+            }
+            Ok(if wide { Exp::WideString(string, span) } else { Exp::String(string, span) })
+        }
+        _ => Ok(Exp::Const(expect_integer_literal(iter)?)),
+    }
+}
+
+/// Parses a variable, or a function call if the identifier naming it is followed by an
+/// argument list.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `name` - The identifier, already consumed.
+/// * `span` - The position of the identifier.
+///
+/// # Returns
+///
+/// The parsed `Exp`, or an `Err` with an error message.
+fn parse_identifier_exp(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, name: Name, span: Span) -> Result<Exp, Diagnostic> {
+    if let Some(Token::OpenParenthesis) = peek_token(iter) {
+        iter.next();
+        return Ok(Exp::FunctionCall(name, parse_argument_list(iter)?, span));
+    }
+    Ok(Exp::Var(name, span))
+}
+
+/// The operators that can follow an operand in an expression.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum InfixOperator {
+    Binary(BinaryOperator),
+    Assignment,
+    CompoundAssignment(BinaryOperator),
+    Conditional,
+    Comma,
+}
+
+/// The precedence of the assignment operators, the lowest after the comma operator's. An
+/// initializer or a function argument is parsed at this precedence, so that a comma ends it.
+const ASSIGNMENT_PRECEDENCE: u8 = 2;
+
+/// Parses the arguments of a function call after its opening parenthesis, up to and
+/// including the closing one.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The argument expressions, or an `Err` with an error message.
+fn parse_argument_list(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Vec<Exp>, Diagnostic> {
+    let mut args = Vec::new();
+    if let Some(Token::CloseParenthesis) = peek_token(iter) {
+        iter.next();
+        return Ok(args);
+    }
+    loop {
+        args.push(parse_exp(iter, ASSIGNMENT_PRECEDENCE)?);
+        match iter.peek() {
+            Some(SpannedToken { token: Token::Comma, .. }) => {
+                iter.next();
+            }
+            Some(SpannedToken { token: Token::CloseParenthesis, .. }) => {
+                iter.next();
+                return Ok(args);
+            }
+            Some(SpannedToken { token, span }) => {
+                return Err(Diagnostic::error(*span, format!("Expected Comma or CloseParenthesis, found {:?}", token)));
+            }
+            None => return Err(Diagnostic::error_without_span("Expected CloseParenthesis, but found end of input")),
+        }
+    }
+}
+
+/// Maps a token to the infix operator it denotes and that operator's precedence.
+/// Higher numbers bind more tightly.
+///
+/// # Arguments
+///
+/// * `token` - The token to be classified.
+///
+/// # Returns
+///
+/// The operator and its precedence, or `None` if the token is not an infix operator.
+fn binary_operator(token: &Token) -> Option<(InfixOperator, u8)> {
+    match token {
+        Token::Multiplication => Some((InfixOperator::Binary(BinaryOperator::Multiply), 50)),
+        Token::Division => Some((InfixOperator::Binary(BinaryOperator::Divide), 50)),
+        Token::Remainder => Some((InfixOperator::Binary(BinaryOperator::Remainder), 50)),
+        Token::Addition => Some((InfixOperator::Binary(BinaryOperator::Add), 45)),
+        Token::Negation => Some((InfixOperator::Binary(BinaryOperator::Subtract), 45)),
+        Token::LeftShift => Some((InfixOperator::Binary(BinaryOperator::LeftShift), 40)),
+        Token::RightShift => Some((InfixOperator::Binary(BinaryOperator::RightShift), 40)),
+        Token::LessThan => Some((InfixOperator::Binary(BinaryOperator::LessThan), 35)),
+        Token::LessOrEqual => Some((InfixOperator::Binary(BinaryOperator::LessOrEqual), 35)),
+        Token::GreaterThan => Some((InfixOperator::Binary(BinaryOperator::GreaterThan), 35)),
+        Token::GreaterOrEqual => Some((InfixOperator::Binary(BinaryOperator::GreaterOrEqual), 35)),
+        Token::Equal => Some((InfixOperator::Binary(BinaryOperator::Equal), 30)),
+        Token::NotEqual => Some((InfixOperator::Binary(BinaryOperator::NotEqual), 30)),
+        Token::BitwiseAnd => Some((InfixOperator::Binary(BinaryOperator::BitwiseAnd), 25)),
+        Token::BitwiseXor => Some((InfixOperator::Binary(BinaryOperator::BitwiseXor), 20)),
+        Token::BitwiseOr => Some((InfixOperator::Binary(BinaryOperator::BitwiseOr), 15)),
+        Token::LogicalAnd => Some((InfixOperator::Binary(BinaryOperator::And), 10)),
+        Token::LogicalOr => Some((InfixOperator::Binary(BinaryOperator::Or), 5)),
+        Token::QuestionMark => Some((InfixOperator::Conditional, 3)),
+        Token::Assignment => Some((InfixOperator::Assignment, ASSIGNMENT_PRECEDENCE)),
+        Token::AdditionAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::Add), ASSIGNMENT_PRECEDENCE)),
+        Token::SubtractionAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::Subtract), ASSIGNMENT_PRECEDENCE)),
+        Token::MultiplicationAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::Multiply), ASSIGNMENT_PRECEDENCE)),
+        Token::DivisionAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::Divide), ASSIGNMENT_PRECEDENCE)),
+        Token::RemainderAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::Remainder), ASSIGNMENT_PRECEDENCE)),
+        Token::BitwiseAndAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::BitwiseAnd), ASSIGNMENT_PRECEDENCE)),
+        Token::BitwiseOrAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::BitwiseOr), ASSIGNMENT_PRECEDENCE)),
+        Token::BitwiseXorAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::BitwiseXor), ASSIGNMENT_PRECEDENCE)),
+        Token::LeftShiftAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::LeftShift), ASSIGNMENT_PRECEDENCE)),
+        Token::RightShiftAssignment => Some((InfixOperator::CompoundAssignment(BinaryOperator::RightShift), ASSIGNMENT_PRECEDENCE)),
+        Token::Comma => Some((InfixOperator::Comma, 1)),
+        _ => None,
+    }
+}
+
+/// Pretty-prints the AST as an indented tree, one declaration or statement per line
+/// and each expression on the line of the statement that contains it.
+///
+/// # Arguments
+///
+/// * `ast` - The AST to be printed.
+///
+/// # Returns
+///
+/// * `String` - The printed tree.
+pub fn pretty_print(ast: &Program) -> String {
+    let mut out = String::new();
+    for declaration in &ast.declarations {
+        print_declaration(&mut out, declaration, 0);
+    }
+    out
+}
 
-    expect_token(&mut iter, Token::IntKeyword)?;
-    let identifier = expect_identifier(&mut iter)?;
-    expect_token(&mut iter, Token::OpenParenthesis)?;
+/// Appends a line at the given nesting depth.
+fn print_line(out: &mut String, depth: usize, line: &str) {
+    out.push_str(&"    ".repeat(depth));
+    out.push_str(line);
+    out.push('\n');
+}
 
-    if let Some(Token::VoidKeyword) = iter.peek() {
-        iter.next(); // Consume the void keyword
-    } else if let Some(Token::CloseParenthesis) = iter.peek() {
-        // No parameters, continue
-    } else {
-        return Err("Expected 'void' or ')' after '(".to_string());
+/// Prints a function definition or declaration.
+///
+/// # Arguments
+///
+/// * `out` - The string the tree is printed into.
+/// * `function` - The function to be printed.
+/// * `depth` - How deeply the function is nested.
+fn print_function(out: &mut String, function: &FunDecl, depth: usize) {
+    let ret = match &function.ty {
+        Type::Function { ret, .. } => ret,
+        other => other,
+    };
+    let prefix = storage_class_prefix(function.storage_class);
+    print_line(out, depth, &format!("FUN {}{} {}:", prefix, type_to_string(ret), function.name));
+    print_line(out, depth + 1, &format!("params: ({})", function.params.iter().map(|param| param.as_str()).collect::<Vec<_>>().join(", ")));
+    match &function.body {
+        Some(body) => {
+            print_line(out, depth + 1, "body:");
+            for item in body {
+                print_block_item(out, item, depth + 2);
+            }
+        }
+        None => print_line(out, depth + 1, "body: none"),
     }
-    expect_token(&mut iter, Token::CloseParenthesis)?;
+}
 
-    expect_token(&mut iter, Token::OpenBrace)?;
-    expect_token(&mut iter, Token::ReturnKeyword)?;
-    let integer = expect_integer_literal(&mut iter)?;
-    let constant = Exp::Const(integer);
-    expect_token(&mut iter, Token::Semicolon)?;
-    expect_token(&mut iter, Token::CloseBrace)?;
+/// Prints a declaration or statement inside a block.
+fn print_block_item(out: &mut String, item: &BlockItem, depth: usize) {
+    match item {
+        BlockItem::Statement(statement) => print_statement(out, statement, depth),
+        BlockItem::Declaration(declaration) => print_declaration(out, declaration, depth),
+    }
+}
 
-    if iter.next().is_some() {
-        return Err("Unexpected tokens at after function delcaration".to_string());
+/// Prints a variable, function or structure declaration.
+///
+/// # Arguments
+///
+/// * `out` - The string the tree is printed into.
+/// * `declaration` - The declaration to be printed.
+/// * `depth` - How deeply the declaration is nested.
+fn print_declaration(out: &mut String, declaration: &Declaration, depth: usize) {
+    match declaration {
+        Declaration::Variable(declaration) => print_line(out, depth, &variable_to_string(declaration)),
+        Declaration::Function(function) => print_function(out, function, depth),
+        Declaration::Struct(declaration) => match &declaration.members {
+            Some(members) => {
+                print_line(out, depth, &format!("STRUCT {}:", declaration.tag));
+                for member in members {
+                    print_line(out, depth + 1, &format!("{} {}", type_to_string(&member.ty), member.name));
+                }
+            }
+            None => print_line(out, depth, &format!("STRUCT {}", declaration.tag)),
+        },
     }
-    let fn_decl = FunDecl {
-        name: identifier,
-        body: Statement::Return(constant),
-    };
+}
+
+/// Prints a statement, with the statements nested in it one level deeper.
+///
+/// # Arguments
+///
+/// * `out` - The string the tree is printed into.
+/// * `statement` - The statement to be printed.
+/// * `depth` - How deeply the statement is nested.
+fn print_statement(out: &mut String, statement: &Statement, depth: usize) {
+    match statement {
+        Statement::Return(exp, _) => print_line(out, depth, &format!("RETURN {}", exp_to_string(exp))),
+        Statement::Expression(exp) => print_line(out, depth, &format!("EXPR {}", exp_to_string(exp))),
+        Statement::If(condition, then, otherwise) => {
+            print_line(out, depth, &format!("IF {}:", exp_to_string(condition)));
+            print_statement(out, then, depth + 1);
+            if let Some(otherwise) = otherwise {
+                print_line(out, depth, "ELSE:");
+                print_statement(out, otherwise, depth + 1);
+            }
+        }
+        Statement::While { condition, body, label } => {
+            print_line(out, depth, &format!("WHILE{} {}:", label_suffix(label), exp_to_string(condition)));
+            print_statement(out, body, depth + 1);
+        }
+        Statement::DoWhile { body, condition, label } => {
+            print_line(out, depth, &format!("DO{}:", label_suffix(label)));
+            print_statement(out, body, depth + 1);
+            print_line(out, depth, &format!("WHILE {}", exp_to_string(condition)));
+        }
+        Statement::For { init, condition, post, body, label } => {
+            print_line(out, depth, &format!("FOR{}:", label_suffix(label)));
+            let init = match &**init {
+                ForInit::Declaration(declaration) => variable_to_string(declaration),
+                ForInit::Expression(exp) => optional_exp_to_string(exp.as_ref()),
+            };
+            print_line(out, depth + 1, &format!("init: {}", init));
+            print_line(out, depth + 1, &format!("condition: {}", optional_exp_to_string(condition.as_ref())));
+            print_line(out, depth + 1, &format!("post: {}", optional_exp_to_string(post.as_ref())));
+            print_line(out, depth + 1, "body:");
+            print_statement(out, body, depth + 2);
+        }
+        Statement::Switch { condition, body, label, .. } => {
+            print_line(out, depth, &format!("SWITCH{} {}:", label_suffix(label), exp_to_string(condition)));
+            print_statement(out, body, depth + 1);
+        }
+        Statement::Case { value, body, label, .. } => {
+            print_line(out, depth, &format!("CASE{} {}:", label_suffix(label), exp_to_string(value)));
+            print_statement(out, body, depth + 1);
+        }
+        Statement::Default { body, label, .. } => {
+            print_line(out, depth, &format!("DEFAULT{}:", label_suffix(label)));
+            print_statement(out, body, depth + 1);
+        }
+        Statement::Labeled(label, body, _) => {
+            print_line(out, depth, &format!("LABEL {}:", label));
+            print_statement(out, body, depth + 1);
+        }
+        Statement::Goto(label, _) => print_line(out, depth, &format!("GOTO {}", label)),
+        Statement::Break(label, _) => print_line(out, depth, &format!("BREAK{}", label_suffix(label))),
+        Statement::Continue(label, _) => print_line(out, depth, &format!("CONTINUE{}", label_suffix(label))),
+        Statement::Compound(items) => {
+            print_line(out, depth, "BLOCK:");
+            items.iter().for_each(|item| print_block_item(out, item, depth + 1));
+        }
+        Statement::Null => print_line(out, depth, "NULL"),
+    }
+}
+
+/// Formats the loop label of a statement, which is empty until loops are labeled.
+fn label_suffix(label: &str) -> String {
+    if label.is_empty() { String::new() } else { format!(" <{}>", label) }
+}
+
+/// Formats a variable declaration such as `INT x = Int<1>`.
+fn variable_to_string(declaration: &VarDecl) -> String {
+    let prefix = storage_class_prefix(declaration.storage_class);
+    let ty = type_to_string(&declaration.ty);
+    match &declaration.init {
+        Some(init) => format!("{}{} {} = {}", prefix, ty, declaration.name, initializer_to_string(init)),
+        None => format!("{}{} {}", prefix, ty, declaration.name),
+    }
+}
+
+/// Formats an initializer, with a compound one as `{Int<1>, Int<2>}`.
+fn initializer_to_string(init: &Initializer) -> String {
+    match init {
+        Initializer::Single(exp) => exp_to_string(exp),
+        Initializer::Compound(elements, _) => {
+            let elements: Vec<String> = elements.iter().map(initializer_to_string).collect();
+            format!("{{{}}}", elements.join(", "))
+        }
+    }
+}
 
-    return Ok(Program{func: fn_decl});
+/// Formats a type as the declarations in the tree show it.
+fn type_to_string(ty: &Type) -> String {
+    match ty {
+        Type::Char => "CHAR".to_string(),
+        Type::SChar => "SCHAR".to_string(),
+        Type::UChar => "UCHAR".to_string(),
+        Type::Int => "INT".to_string(),
+        Type::Long => "LONG".to_string(),
+        Type::UInt => "UINT".to_string(),
+        Type::ULong => "ULONG".to_string(),
+        Type::Double => "DOUBLE".to_string(),
+        Type::Pointer(referenced) => format!("PTR({})", type_to_string(referenced)),
+        Type::Array(element, size) => format!("ARRAY({}, {})", type_to_string(element), size),
+        Type::DeclaredArray(element, size) => match &**size {
+            Exp::Const(size) => format!("ARRAY({}, {})", type_to_string(element), size),
+            size => format!("ARRAY({}, {})", type_to_string(element), exp_to_string(size)),
+        },
+        Type::Struct(tag) => format!("STRUCT({})", tag),
+        Type::Atomic(ty) => format!("ATOMIC({})", type_to_string(ty)),
+        Type::Function { params, ret } => {
+            let params: Vec<String> = params.iter().map(type_to_string).collect();
+            format!("FUN({}) -> {}", params.join(", "), type_to_string(ret))
+        }
+    }
+}
 
+/// Formats the storage class of a declaration, followed by a space, or nothing if it has none.
+fn storage_class_prefix(storage_class: Option<StorageClass>) -> &'static str {
+    match storage_class {
+        Some(StorageClass::Static) => "STATIC ",
+        Some(StorageClass::Extern) => "EXTERN ",
+        None => "",
+    }
+}
 
+/// Formats an expression that may be missing, such as the clauses of a `for` loop.
+fn optional_exp_to_string(exp: Option<&Exp>) -> String {
+    exp.map_or_else(|| "none".to_string(), exp_to_string)
 }
-/// Pretty-print function to display the AST in a readable way.
+
+/// Formats an expression on a single line.
 ///
 /// # Arguments
 ///
-/// * `ast` - The AST to be printed.
-// pub fn pretty_print(ast: &Program) {
-//     println!("FUN INT {}:", ast.func.name);
-//     println!("    params: ()");
-//     match &ast.func.body {
-//         Statement::Return(exp) => match exp {
-//             Exp::Const(value) => println!("    body:\n        RETURN Int<{}>", value),
-//         },
-//     }
-// }
+/// * `exp` - The expression to be formatted.
+///
+/// # Returns
+///
+/// * `String` - The expression, with operators in prefix form such as `Binary(+, Int<1>, Var<x>)`.
+fn exp_to_string(exp: &Exp) -> String {
+    match exp {
+        Exp::Const(Constant::Char(value)) => format!("Char<{}>", value),
+        Exp::Const(Constant::UChar(value)) => format!("UChar<{}>", value),
+        Exp::Const(Constant::Int(value)) => format!("Int<{}>", value),
+        Exp::Const(Constant::Long(value)) => format!("Long<{}>", value),
+        Exp::Const(Constant::UInt(value)) => format!("UInt<{}>", value),
+        Exp::Const(Constant::ULong(value)) => format!("ULong<{}>", value),
+        Exp::Const(Constant::Double(value)) => format!("Double<{:?}>", value),
+        Exp::Var(name, _) => format!("Var<{}>", name),
+        Exp::String(string, _) => format!("String<{:?}>", string),
+        Exp::WideString(string, _) => format!("WideString<{:?}>", string),
+        Exp::Assignment(lhs, rhs, _) => format!("Assign({}, {})", exp_to_string(lhs), exp_to_string(rhs)),
+        Exp::CompoundAssignment(operator, lhs, rhs, _) => {
+            format!("Assign({}=, {}, {})", binary_symbol(*operator), exp_to_string(lhs), exp_to_string(rhs))
+        }
+        Exp::PostfixUpdate(operator, operand, _) => {
+            let symbol = if *operator == BinaryOperator::Add { "++" } else { "--" };
+            format!("Postfix({}, {})", symbol, exp_to_string(operand))
+        }
+        Exp::Conditional(condition, then, otherwise, _) => format!(
+            "Cond({}, {}, {})",
+            exp_to_string(condition),
+            exp_to_string(then),
+            exp_to_string(otherwise)
+        ),
+        Exp::Comma(left, right, _) => format!("Comma({}, {})", exp_to_string(left), exp_to_string(right)),
+        Exp::Cast(ty, operand, _) => format!("Cast<{}>({})", type_to_string(ty), exp_to_string(operand)),
+        Exp::FunctionCall(name, args, _) => {
+            let args: Vec<String> = args.iter().map(exp_to_string).collect();
+            format!("Call<{}>({})", name, args.join(", "))
+        }
+        Exp::UnOp(operator, operand, _) => {
+            let symbol = match operator {
+                UnaryOperator::Negate => "-",
+                UnaryOperator::Complement => "~",
+                UnaryOperator::Not => "!",
+            };
+            format!("Unary({}, {})", symbol, exp_to_string(operand))
+        }
+        Exp::Dereference(operand, _) => format!("Deref({})", exp_to_string(operand)),
+        Exp::AddressOf(operand, _) => format!("AddrOf({})", exp_to_string(operand)),
+        Exp::Subscript(array, index, _) => format!("Subscript({}, {})", exp_to_string(array), exp_to_string(index)),
+        Exp::Member { base, member, .. } => format!("Member({}, {})", exp_to_string(base), member),
+        Exp::BinOp(operator, lhs, rhs, _) => {
+            format!("Binary({}, {}, {})", binary_symbol(*operator), exp_to_string(lhs), exp_to_string(rhs))
+        }
+        Exp::SizeOf(operand, _) => format!("SizeOf({})", exp_to_string(operand)),
+        Exp::SizeOfType(ty, _) => format!("SizeOf<{}>", type_to_string(ty)),
+    }
+}
+
+/// Returns the C spelling of a binary operator.
+pub fn binary_symbol(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Remainder => "%",
+        BinaryOperator::BitwiseAnd => "&",
+        BinaryOperator::BitwiseOr => "|",
+        BinaryOperator::BitwiseXor => "^",
+        BinaryOperator::LeftShift => "<<",
+        BinaryOperator::RightShift => ">>",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::LessThan => "<",
+        BinaryOperator::LessOrEqual => "<=",
+        BinaryOperator::GreaterThan => ">",
+        BinaryOperator::GreaterOrEqual => ">=",
+        BinaryOperator::And => "&&",
+        BinaryOperator::Or => "||",
+    }
+}
+
+/// Returns the next token without consuming it.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The next token, or `None` at the end of input.
+fn peek_token(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Option<&Token> {
+    iter.peek().map(|spanned| &spanned.token)
+}
+
+/// Returns the position of the next token without consuming it.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The position of the next token, or the default position at the end of input.
+fn peek_span(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Span {
+    iter.peek().map(|spanned| spanned.span).unwrap_or_default()
+}
+
+/// Consumes the next token, which the caller has already peeked at, and returns its position.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+///
+/// # Returns
+///
+/// The position of the consumed token.
+fn next_span(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Span {
+    iter.next().map(|spanned| spanned.span).unwrap_or_default()
+}
 
 /// Helper function to check if the next token matches the expected token type.
 ///
@@ -61,14 +1503,32 @@ pub fn parse(tokens: Vec<Token>) -> Result<Program, String> {
 ///
 /// If the token matches, it consumes the token and returns `Ok(())`.
 /// Otherwise, it returns an `Err` with an error message.
-fn expect_token(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>, expected: Token) -> Result<(), String> {
+fn expect_token(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, expected: Token) -> Result<(), Diagnostic> {
     match iter.peek() {
-        Some(token) if *token == expected => {
+        Some(spanned) if spanned.token == expected => {
             iter.next();
             Ok(())
         }
-        Some(token) => Err(format!("Expected {:?}, found {:?}", expected, token)),
-        None => Err(format!("Expected {:?}, but found end of input", expected)),
+        Some(spanned) => Err(Diagnostic::error(spanned.span, format!("Expected {:?}, found {:?}", expected, spanned.token))),
+        None => Err(Diagnostic::error_without_span(format!("Expected {:?}, but found end of input", expected))),
+    }
+}
+
+/// Returns the error for a token that cannot start what the parser expected, which is left
+/// in place so error recovery can see it.
+///
+/// # Arguments
+///
+/// * `iter` - A mutable reference to a Peekable iterator over the tokens.
+/// * `expected` - What the parser expected, such as "an expression".
+///
+/// # Returns
+///
+/// * `Diagnostic` - The error, at the unexpected token or at the end of the input.
+fn unexpected(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>, expected: &str) -> Diagnostic {
+    match iter.peek() {
+        Some(spanned) => Diagnostic::error(spanned.span, format!("Expected {}, found {:?}", expected, spanned.token)),
+        None => Diagnostic::error_without_span(format!("Expected {}, but found end of input", expected)),
     }
 }
 
@@ -82,11 +1542,16 @@ fn expect_token(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>, expec
 ///
 /// If the token is an identifier, it consumes the token and returns its value.
 /// Otherwise, it returns an `Err` with an error message.
-fn expect_identifier(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<String, String> {
-    match iter.next() {
-        Some(Token::Identifier(name)) => Ok(name),
-        Some(token) => Err(format!("Expected identifier, found {:?}", token)),
-        None => Err("Expected identifier, but found end of input".to_string()),
+fn expect_identifier(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Name, Diagnostic> {
+    // The unexpected token is left in place so error recovery can see it
+    if let Some(SpannedToken { token: Token::Identifier(name), .. }) =
+        iter.next_if(|spanned| matches!(spanned.token, Token::Identifier(_)))
+    {
+        return Ok(name);
+    }
+    match iter.peek() {
+        Some(SpannedToken { token, span }) => Err(Diagnostic::error(*span, format!("Expected identifier, found {:?}", token))),
+        None => Err(Diagnostic::error_without_span("Expected identifier, but found end of input")),
     }
 }
 
@@ -98,18 +1563,89 @@ fn expect_identifier(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>)
 ///
 /// # Returns
 ///
-/// If the token is an integer literal, it consumes the token and returns its value.
-/// Otherwise, it returns an `Err` with an error message.
-fn expect_integer_literal(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<i32, String> {
-    match iter.next() {
-        Some(Token::IntegerLiteral(value)) => {
-            value.parse::<i32>().map_err(|_| "Invalid integer literal".to_string())
+/// If the token is an integer or floating-point literal or a character constant, it
+/// consumes the token and returns its value, typed as C types the literal (character
+/// constants are `int`, as is `wchar_t` on every target scc supports).
+/// Otherwise, it returns an `Err` saying that an expression was expected.
+fn expect_integer_literal(iter: &mut std::iter::Peekable<std::vec::IntoIter<SpannedToken>>) -> Result<Constant, Diagnostic> {
+    // The unexpected token is left in place so error recovery can see it
+    let literal = iter.next_if(|spanned| {
+        matches!(
+            spanned.token,
+            Token::IntegerLiteral(_)
+                | Token::LongLiteral(_)
+                | Token::UnsignedLiteral(_)
+                | Token::UnsignedLongLiteral(_)
+                | Token::DoubleLiteral(_)
+                | Token::CharLiteral(_)
+                | Token::WideCharLiteral(_)
+        )
+    });
+    match literal {
+        Some(SpannedToken { token: Token::IntegerLiteral(value), span }) => integer_constant(&value, false, false, span),
+        Some(SpannedToken { token: Token::LongLiteral(value), span }) => integer_constant(&value, false, true, span),
+        Some(SpannedToken { token: Token::UnsignedLiteral(value), span }) => integer_constant(&value, true, false, span),
+        Some(SpannedToken { token: Token::UnsignedLongLiteral(value), span }) => integer_constant(&value, true, true, span),
+        // Constants too large for a double are infinite, as in C
+        Some(SpannedToken { token: Token::DoubleLiteral(value), span }) => value
+            .parse::<f64>()
+            .map(Constant::Double)
+            .map_err(|_| Diagnostic::error(span, "Invalid floating-point constant")),
+        Some(SpannedToken { token: Token::CharLiteral(value) | Token::WideCharLiteral(value), .. }) => {
+            Ok(Constant::Int(value as i32))
         }
-        Some(token) => Err(format!("Expected integer literal, found {:?}", token)),
-        None => Err("Expected integer literal, but found end of input".to_string()),
+        Some(_) => unreachable!("only literals are taken above"),
+        // A constant is the last kind of expression the parser tries
+        None => Err(unexpected(iter, "an expression")),
     }
 }
 
+/// Reads the value of an integer constant and gives it the first type in which it fits,
+/// among those C allows for its suffixes: a decimal constant only becomes unsigned with a
+/// `u` suffix, but a hexadecimal, octal or binary one may also take an unsigned type
+/// without it.
+///
+/// # Arguments
+///
+/// * `number` - The digits of the constant, after a `0x`, `0b` or `0` prefix if it has one.
+/// * `unsigned` - Whether the constant has a `u` suffix.
+/// * `long` - Whether the constant has an `l` suffix.
+/// * `span` - The position of the constant, where errors are reported.
+///
+/// # Returns
+///
+/// The constant, or an `Err` if it does not fit in any of its types.
+fn integer_constant(number: &str, unsigned: bool, long: bool, span: Span) -> Result<Constant, Diagnostic> {
+    let (digits, radix) = if let Some(digits) = number.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = number.strip_prefix("0b") {
+        (digits, 2)
+    } else if number.len() > 1 && number.starts_with('0') {
+        (&number[1..], 8)
+    } else {
+        (number, 10)
+    };
+    let candidates: &[Type] = match (unsigned, long, radix == 10) {
+        (false, false, true) => &[Type::Int, Type::Long],
+        (false, false, false) => &[Type::Int, Type::UInt, Type::Long, Type::ULong],
+        (false, true, true) => &[Type::Long],
+        (false, true, false) => &[Type::Long, Type::ULong],
+        (true, false, _) => &[Type::UInt, Type::ULong],
+        (true, true, _) => &[Type::ULong],
+    };
+    let value = u64::from_str_radix(digits, radix).ok();
+    let constant = candidates.iter().find_map(|ty| match (ty, value?) {
+        (Type::Int, value) => i32::try_from(value).ok().map(Constant::Int),
+        (Type::UInt, value) => u32::try_from(value).ok().map(Constant::UInt),
+        (Type::Long, value) => i64::try_from(value).ok().map(Constant::Long),
+        (_, value) => Some(Constant::ULong(value)),
+    });
+    constant.ok_or_else(|| {
+        let largest = candidates.last().expect("every constant has a type");
+        Diagnostic::error(span, format!("Integer constant '{}' is too large for type '{}'", number, largest))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,60 +1653,67 @@ mod tests {
     #[test]
     fn test_expect_token_success() {
         let tokens = vec![Token::IntKeyword];
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = spanned(tokens).into_iter().peekable();
         assert!(expect_token(&mut iter, Token::IntKeyword).is_ok());
     }
 
     #[test]
     fn test_expect_token_failure() {
         let tokens = vec![Token::ReturnKeyword];
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = spanned(tokens).into_iter().peekable();
         let result = expect_token(&mut iter, Token::IntKeyword);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Expected IntKeyword, found ReturnKeyword");
+        assert_eq!(result.unwrap_err().to_string(), "1:1: Expected IntKeyword, found ReturnKeyword");
     }
 
     #[test]
     fn test_expect_identifier_success() {
-        let tokens = vec![Token::Identifier("myFunc".to_string())];
-        let mut iter = tokens.into_iter().peekable();
+        let tokens = vec![Token::Identifier("myFunc".into())];
+        let mut iter = spanned(tokens).into_iter().peekable();
         let result = expect_identifier(&mut iter);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "myFunc".to_string());
+        assert_eq!(result.unwrap(), "myFunc");
     }
 
     #[test]
     fn test_expect_identifier_failure() {
         let tokens = vec![Token::IntKeyword];
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = spanned(tokens).into_iter().peekable();
         let result = expect_identifier(&mut iter);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Expected identifier, found IntKeyword");
+        assert_eq!(result.unwrap_err().to_string(), "1:1: Expected identifier, found IntKeyword");
     }
 
     #[test]
     fn test_expect_integer_literal_success() {
         let tokens = vec![Token::IntegerLiteral("42".to_string())];
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = spanned(tokens).into_iter().peekable();
         let result = expect_integer_literal(&mut iter);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 42);
+        assert_eq!(result.unwrap(), Constant::Int(42));
     }
 
     #[test]
     fn test_expect_integer_literal_failure() {
         let tokens = vec![Token::IntKeyword];
-        let mut iter = tokens.into_iter().peekable();
+        let mut iter = spanned(tokens).into_iter().peekable();
         let result = expect_integer_literal(&mut iter);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Expected integer literal, found IntKeyword");
+        assert_eq!(result.unwrap_err().to_string(), "1:1: Expected an expression, found IntKeyword");
+    }
+
+    #[test]
+    fn test_expect_integer_literal_wide_char() {
+        let tokens = vec![Token::WideCharLiteral('A')];
+        let mut iter = spanned(tokens).into_iter().peekable();
+        assert_eq!(expect_integer_literal(&mut iter), Ok(Constant::Int(65)));
     }
 
     #[test]
     fn test_parse_valid_program() {
         let tokens = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -179,12 +1722,14 @@ mod tests {
             Token::Semicolon,
             Token::CloseBrace,
         ];
-        let result = parse(tokens);
+        let result = parse(spanned(tokens));
         assert!(result.is_ok());
         let program = result.unwrap();
-        assert_eq!(program.func.name, "main");
-        if let Statement::Return(Exp::Const(value)) = program.func.body {
-            assert_eq!(value, 42);
+        let functions = functions(&program);
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].name, "main");
+        if let Some([BlockItem::Statement(Statement::Return(Exp::Const(value), _))]) = functions[0].body.as_deref() {
+            assert_eq!(*value, Constant::Int(42));
         } else {
             panic!("Expected return statement with constant value");
         }
@@ -194,7 +1739,7 @@ mod tests {
     fn test_parse_invalid_program_unexpected_token() {
         let tokens = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -203,16 +1748,595 @@ mod tests {
             Token::Semicolon,
             Token::CloseBrace,
         ];
-        let result = parse(tokens);
+        let result = parse(spanned(tokens));
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Expected integer literal, found IntKeyword");
+        assert_eq!(result.unwrap_err().to_string(), "1:7: Expected an expression, found IntKeyword");
+    }
+
+    #[test]
+    fn test_parse_nested_unary_operators() {
+        // -(~(!5))
+        let tokens = vec![
+            Token::Negation,
+            Token::OpenParenthesis,
+            Token::BitwiseComplement,
+            Token::OpenParenthesis,
+            Token::LogicalNegation,
+            Token::IntegerLiteral("5".to_string()),
+            Token::CloseParenthesis,
+            Token::CloseParenthesis,
+        ];
+        let mut iter = spanned(tokens).into_iter().peekable();
+        let exp = parse_exp(&mut iter, 0).unwrap();
+        match exp {
+            Exp::UnOp(UnaryOperator::Negate, inner, _) => match *inner {
+                Exp::UnOp(UnaryOperator::Complement, inner, _) => match *inner {
+                    Exp::UnOp(UnaryOperator::Not, inner, _) => assert!(matches!(*inner, Exp::Const(Constant::Int(5)))),
+                    other => panic!("Expected logical not, found {:?}", other),
+                },
+                other => panic!("Expected complement, found {:?}", other),
+            },
+            other => panic!("Expected negation, found {:?}", other),
+        }
+        assert!(iter.next().is_none());
+    }
+
+    fn lex_str(source: &str) -> Vec<SpannedToken> {
+        crate::lex::lex(source).unwrap()
+    }
+
+    /// Gives each token a position of its own, as if they were all on the first line.
+    fn spanned(tokens: Vec<Token>) -> Vec<SpannedToken> {
+        tokens
+            .into_iter()
+            .enumerate()
+            .map(|(index, token)| SpannedToken { token, span: Span { line: 1, column: index + 1 } })
+            .collect()
+    }
+
+    /// Returns the functions declared at file scope in `program`.
+    fn functions(program: &Program) -> Vec<&FunDecl> {
+        program
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Function(function) => Some(function),
+                Declaration::Variable(_) | Declaration::Struct(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the body of the only declaration in `program`, which must be a function.
+    fn main_body(mut program: Program) -> Vec<BlockItem> {
+        assert_eq!(program.declarations.len(), 1);
+        match program.declarations.pop() {
+            Some(Declaration::Function(function)) => function.body.expect("function has a body"),
+            other => panic!("Expected a function, found {:?}", other),
+        }
+    }
+
+    /// Renders an expression fully parenthesized, to make precedence visible in assertions.
+    fn render(exp: &Exp) -> String {
+        match exp {
+            Exp::Const(value) => value.to_string(),
+            Exp::Var(name, _) => name.to_string(),
+            Exp::String(string, _) => format!("{:?}", string),
+            Exp::WideString(string, _) => format!("L{:?}", string),
+            Exp::Assignment(left, right, _) => format!("({} = {})", render(left), render(right)),
+            Exp::CompoundAssignment(operator, left, right, _) => {
+                format!("({} {:?}= {})", render(left), operator, render(right))
+            }
+            Exp::PostfixUpdate(operator, operand, _) => format!("({} {:?}{:?})", render(operand), operator, operator),
+            Exp::Conditional(condition, then, otherwise, _) => {
+                format!("({} ? {} : {})", render(condition), render(then), render(otherwise))
+            }
+            Exp::Comma(left, right, _) => format!("({} , {})", render(left), render(right)),
+            Exp::FunctionCall(name, args, _) => {
+                format!("{}({})", name, args.iter().map(render).collect::<Vec<_>>().join(", "))
+            }
+            Exp::Cast(ty, operand, _) => format!("(({:?}) {})", ty, render(operand)),
+            Exp::UnOp(operator, operand, _) => format!("({:?} {})", operator, render(operand)),
+            Exp::Dereference(operand, _) => format!("(* {})", render(operand)),
+            Exp::AddressOf(operand, _) => format!("(& {})", render(operand)),
+            Exp::Subscript(array, index, _) => format!("{}[{}]", render(array), render(index)),
+            Exp::Member { base, member, .. } => format!("{}.{}", render(base), member),
+            Exp::BinOp(operator, left, right, _) => {
+                format!("({} {:?} {})", render(left), operator, render(right))
+            }
+            Exp::SizeOf(operand, _) => format!("(sizeof {})", render(operand)),
+            Exp::SizeOfType(ty, _) => format!("(sizeof {:?})", ty),
+        }
+    }
+
+    #[test]
+    fn test_parse_precedence_and_associativity() {
+        let cases = [
+            ("1 + 2 * 3", "(1 Add (2 Multiply 3))"),
+            ("1 - 2 - 3", "((1 Subtract 2) Subtract 3)"),
+            ("(1 + 2) % 3 / 4", "(((1 Add 2) Remainder 3) Divide 4)"),
+            ("-1 * -2 + ~3", "(((Negate 1) Multiply (Negate 2)) Add (Complement 3))"),
+            ("a = b = c * 2", "(a = (b = (c Multiply 2)))"),
+            ("1 + 2 < 3 == 4 >= 5 - 6", "(((1 Add 2) LessThan 3) Equal (4 GreaterOrEqual (5 Subtract 6)))"),
+            ("a != b <= c > d", "(a NotEqual ((b LessOrEqual c) GreaterThan d))"),
+            ("a || b && c == d", "(a Or (b And (c Equal d)))"),
+            ("a && b || c ? 1 : 2", "(((a And b) Or c) ? 1 : 2)"),
+            ("f() + g(1, a = 2, h(b)) * 3", "(f() Add (g(1, (a = 2), h(b)) Multiply 3))"),
+            ("a = 1 ? 2 : b ? 3 : 4", "(a = (1 ? 2 : (b ? 3 : 4)))"),
+            ("a ? b = 1 : 2 + 3", "(a ? (b = 1) : (2 Add 3))"),
+            ("a += b -= 2 * c", "(a Add= (b Subtract= (2 Multiply c)))"),
+            ("a *= b /= c %= 2", "(a Multiply= (b Divide= (c Remainder= 2)))"),
+            ("-a++ + --b", "((Negate (a AddAdd)) Add (b Subtract= 1))"),
+            ("++a - -c--", "((a Add= 1) Subtract (Negate (c SubtractSubtract)))"),
+            ("a | b ^ c & d == e", "(a BitwiseOr (b BitwiseXor (c BitwiseAnd (d Equal e))))"),
+            ("a << 1 + b < c >> 2", "((a LeftShift (1 Add b)) LessThan (c RightShift 2))"),
+            ("a && b | c || d", "((a And (b BitwiseOr c)) Or d)"),
+            ("a <<= b &= c ^ 1", "(a LeftShift= (b BitwiseAnd= (c BitwiseXor 1)))"),
+            ("a = 1, b += 2, c", "(((a = 1) , (b Add= 2)) , c)"),
+            ("a ? b, c : d, e", "((a ? (b , c) : d) , e)"),
+            ("f(a, (b, c), d = 1)", "f(a, (b , c), (d = 1))"),
+        ];
+        for (source, expected) in cases {
+            let mut iter = lex_str(source).into_iter().peekable();
+            let exp = parse_exp(&mut iter, 0).unwrap();
+            assert_eq!(render(&exp), expected, "while parsing {}", source);
+            assert!(iter.next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_parse_block_items() {
+        let program = parse(lex_str("int main(void) { int x; int y = 2; x = y + 1; ; return x; }")).unwrap();
+        let body = main_body(program);
+        assert_eq!(body.len(), 5);
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl { name, init: None, .. })) if name == "x"));
+        assert!(matches!(&body[1], BlockItem::Declaration(Declaration::Variable(VarDecl { name, init: Some(Initializer::Single(Exp::Const(Constant::Int(2)))), .. })) if name == "y"));
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::Expression(Exp::Assignment(..)))));
+        assert!(matches!(&body[3], BlockItem::Statement(Statement::Null)));
+        assert!(matches!(&body[4], BlockItem::Statement(Statement::Return(Exp::Var(..), _))));
+    }
+
+    #[test]
+    fn test_parse_nested_blocks() {
+        let source = "int main(void) { { int x = 1; {} } while (1) { x; break; } return 0; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "FUN INT main:\n    params: ()\n    body:\n        \
+             BLOCK:\n            \
+             INT x = Int<1>\n            \
+             BLOCK:\n        \
+             WHILE Int<1>:\n            \
+             BLOCK:\n                \
+             EXPR Var<x>\n                \
+             BREAK\n        \
+             RETURN Int<0>\n"
+        );
+        // Parsing goes on after an error inside a nested block
+        let source = "int main(void) {\n    {\n        int x = ;\n        { return 1 }\n    }\n    return +;\n}";
+        assert_eq!(
+            parse(lex_str(source)).unwrap_err().to_string(),
+            "3:17: Expected an expression, found Semicolon\n\
+             4:20: Expected Semicolon, found CloseBrace\n\
+             6:12: Expected an expression, found Addition"
+        );
+    }
+
+    #[test]
+    fn test_parse_dangling_else() {
+        let program = parse(lex_str("int main(void) { if (1) if (2) return 3; else return 4; }")).unwrap();
+        match &main_body(program)[..] {
+            [BlockItem::Statement(Statement::If(Exp::Const(Constant::Int(1)), inner, None))] => {
+                assert!(matches!(&**inner, Statement::If(Exp::Const(Constant::Int(2)), _, Some(_))));
+            }
+            other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        let source = "int main(void) { while (1) break; do continue; while (0); for (int i = 0; ; ) ; for (;;) ; }";
+        let body = main_body(parse(lex_str(source)).unwrap());
+        assert!(matches!(&body[0], BlockItem::Statement(Statement::While { condition: Exp::Const(Constant::Int(1)), body, .. })
+            if matches!(**body, Statement::Break(..))));
+        assert!(matches!(&body[1], BlockItem::Statement(Statement::DoWhile { condition: Exp::Const(Constant::Int(0)), body, .. })
+            if matches!(**body, Statement::Continue(..))));
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::For { init, condition: None, post: None, .. })
+            if matches!(&**init, ForInit::Declaration(VarDecl { init: Some(Initializer::Single(Exp::Const(Constant::Int(0)))), .. }))));
+        assert!(matches!(&body[3], BlockItem::Statement(Statement::For { init, condition: None, post: None, .. })
+            if matches!(&**init, ForInit::Expression(None))));
+    }
+
+    #[test]
+    fn test_parse_function_declarations() {
+        let source = "int f(int a, int b); int g(void) { int h(int c); return f(1, 2); } int main() { return 0; }";
+        let program = parse(lex_str(source)).unwrap();
+        let functions = functions(&program);
+        let names: Vec<_> = functions.iter().map(|f| (f.name.as_str(), f.params.clone(), f.body.is_some())).collect();
+        assert_eq!(names, vec![
+            ("f", vec!["a".into(), "b".into()], false),
+            ("g", vec![], true),
+            ("main", vec![], true),
+        ]);
+        let body = functions[1].body.as_ref().unwrap();
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Function(FunDecl { name, body: None, .. })) if name == "h"));
+    }
+
+    #[test]
+    fn test_parse_file_scope_variables() {
+        let program = parse(lex_str("int x = 3; int y; int main(void) { return x + y; }")).unwrap();
+        assert_eq!(program.declarations.len(), 3);
+        assert!(matches!(&program.declarations[0], Declaration::Variable(VarDecl { name, init: Some(Initializer::Single(Exp::Const(Constant::Int(3)))), .. }) if name == "x"));
+        assert!(matches!(&program.declarations[1], Declaration::Variable(VarDecl { name, init: None, .. }) if name == "y"));
+        assert_eq!(functions(&program).len(), 1);
+    }
+
+    #[test]
+    fn test_parse_storage_classes() {
+        let source = "static int x; int extern y = 1; static int f(void); int main(void) { extern int y; static int z = 2; return z; }";
+        let program = parse(lex_str(source)).unwrap();
+        assert!(matches!(&program.declarations[0], Declaration::Variable(VarDecl { storage_class: Some(StorageClass::Static), .. })));
+        assert!(matches!(&program.declarations[1], Declaration::Variable(VarDecl { storage_class: Some(StorageClass::Extern), .. })));
+        assert!(matches!(&program.declarations[2], Declaration::Function(FunDecl { storage_class: Some(StorageClass::Static), .. })));
+        assert!(matches!(&program.declarations[3], Declaration::Function(FunDecl { storage_class: None, .. })));
+        let body = functions(&program)[1].body.as_ref().unwrap();
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl { storage_class: Some(StorageClass::Extern), .. }))));
+        assert!(matches!(&body[1], BlockItem::Declaration(Declaration::Variable(VarDecl { storage_class: Some(StorageClass::Static), .. }))));
+    }
+
+    #[test]
+    fn test_parse_integer_types() {
+        let source = "long a = 1L; unsigned b = 2u; unsigned long c = 3lu; long unsigned int d = 4294967296u; \
+                      signed e = 2147483648; int long signed f;";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "LONG a = Long<1>\n\
+             UINT b = UInt<2>\n\
+             ULONG c = ULong<3>\n\
+             ULONG d = ULong<4294967296>\n\
+             INT e = Long<2147483648>\n\
+             LONG f\n"
+        );
+        let cases = [
+            ("signed unsigned x;", "1:1: Invalid type specifier"),
+            ("unsigned int unsigned x;", "1:14: Duplicate type specifier 'unsigned'"),
+            ("int x = 18446744073709551616u;", "1:9: Integer constant '18446744073709551616' is too large for type 'unsigned long'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_integer_bases() {
+        // Hexadecimal, octal and binary constants may take an unsigned type without a suffix
+        let source = "int a = 0x1F; int b = 017; int c = 0b101; int d = 0; int e = 0xFFFFFFFF; int f = 0x7fffffffffffffff; \
+                      int g = 0xFFFFFFFFFFFFFFFF; int h = 01777777777777777777777; int i = 0x100000000l; int j = 0xFFFFFFFFFFFFFFFFl; \
+                      int k = 0b11u;";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "INT a = Int<31>\n\
+             INT b = Int<15>\n\
+             INT c = Int<5>\n\
+             INT d = Int<0>\n\
+             INT e = UInt<4294967295>\n\
+             INT f = Long<9223372036854775807>\n\
+             INT g = ULong<18446744073709551615>\n\
+             INT h = ULong<18446744073709551615>\n\
+             INT i = Long<4294967296>\n\
+             INT j = ULong<18446744073709551615>\n\
+             INT k = UInt<3>\n"
+        );
+        let cases = [
+            ("int x = 9223372036854775808;", "1:9: Integer constant '9223372036854775808' is too large for type 'long'"),
+            ("int x = 9223372036854775808l;", "1:9: Integer constant '9223372036854775808' is too large for type 'long'"),
+            ("int x = 0x10000000000000000;", "1:9: Integer constant '0x10000000000000000' is too large for type 'unsigned long'"),
+            ("int x = 0b10000000000000000000000000000000000000000000000000000000000000000u;",
+             "1:9: Integer constant '0b10000000000000000000000000000000000000000000000000000000000000000' is too large for type 'unsigned long'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_double() {
+        let source = "double a = 1.5; double b = (double) 3 * .5e1; static double c = 1e400;";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "DOUBLE a = Double<1.5>\n\
+             DOUBLE b = Binary(*, Cast<DOUBLE>(Int<3>), Double<5.0>)\n\
+             STATIC DOUBLE c = Double<inf>\n"
+        );
+        let cases = [
+            ("long double x;", "1:1: Invalid type specifier"),
+            ("double unsigned x;", "1:1: Invalid type specifier"),
+            ("double double x;", "1:8: Duplicate type specifier 'double'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_characters() {
+        let source = "char a = 'a'; signed char b; char unsigned c; char *d = \"one\" \"two\"; wchar_t *e = \"a\" L\"b\";";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "CHAR a = Int<97>\n\
+             SCHAR b\n\
+             UCHAR c\n\
+             PTR(CHAR) d = String<\"onetwo\">\n\
+             PTR(INT) e = WideString<\"ab\">\n"
+        );
+        let cases = [
+            ("long char x;", "1:1: Invalid type specifier"),
+            ("unsigned wchar_t x;", "1:1: Invalid type specifier"),
+            ("char int x;", "1:1: Invalid type specifier"),
+            ("char char x;", "1:6: Duplicate type specifier 'char'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_atomic() {
+        let source = "_Atomic int a; static unsigned _Atomic long _Atomic b; _Atomic char *c;";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "ATOMIC(INT) a\n\
+             STATIC ATOMIC(ULONG) b\n\
+             PTR(ATOMIC(CHAR)) c\n"
+        );
+        let source = "int main(void) { return sizeof(_Atomic int) + (_Atomic long) x; }";
+        let body = main_body(parse(lex_str(source)).unwrap());
+        let BlockItem::Statement(Statement::Return(exp, _)) = &body[0] else {
+            panic!("Expected a return statement, found {:?}", body[0]);
+        };
+        assert_eq!(exp_to_string(exp), "Binary(+, SizeOf<ATOMIC(INT)>, Cast<ATOMIC(LONG)>(Var<x>))");
+    }
+
+    #[test]
+    fn test_parse_casts() {
+        let source = "int main(void) { return (int) x + (unsigned long) -x++ * (signed)(x) + (long) (x); }";
+        let body = main_body(parse(lex_str(source)).unwrap());
+        let BlockItem::Statement(Statement::Return(exp, _)) = &body[0] else {
+            panic!("Expected a return statement, found {:?}", body[0]);
+        };
+        assert_eq!(
+            exp_to_string(exp),
+            "Binary(+, Binary(+, Cast<INT>(Var<x>), Binary(*, Cast<ULONG>(Unary(-, Postfix(++, Var<x>))), Cast<INT>(Var<x>))), \
+             Cast<LONG>(Var<x>))"
+        );
+        assert_eq!(
+            parse(lex_str("int main(void) { return (static int) x; }")).unwrap_err().to_string(),
+            "1:26: Expected an expression, found StaticKeyword"
+        );
+    }
+
+    #[test]
+    fn test_parse_sizeof() {
+        let cases = [
+            ("sizeof x + 1", "((sizeof x) Add 1)"),
+            ("sizeof -x++", "(sizeof (Negate (x AddAdd)))"),
+            ("sizeof (x)[1] * 2", "((sizeof x[1]) Multiply 2)"),
+            ("sizeof (long *) + 1", "((sizeof Pointer(Long)) Add 1)"),
+            ("sizeof (struct s [3])", "(sizeof DeclaredArray(Struct(\"s\"), Const(Int(3))))"),
+            ("sizeof sizeof (char)", "(sizeof (sizeof Char))"),
+        ];
+        for (source, expected) in cases {
+            let mut iter = lex_str(source).into_iter().peekable();
+            let exp = parse_exp(&mut iter, 0).unwrap();
+            assert_eq!(render(&exp), expected, "while parsing {}", source);
+            assert!(iter.next().is_none());
+        }
+        let body = main_body(parse(lex_str("int main(void) { return sizeof(int) + sizeof x; }")).unwrap());
+        let BlockItem::Statement(Statement::Return(exp, _)) = &body[0] else {
+            panic!("Expected a return statement, found {:?}", body[0]);
+        };
+        assert_eq!(exp_to_string(exp), "Binary(+, SizeOf<INT>, SizeOf(Var<x>))");
+    }
+
+    #[test]
+    fn test_parse_pointers() {
+        let source = "int *p = 0; static long **q; int **f(int *a, unsigned long (*b));
+int main(void) { return *&*p + (int) *(long *) &q + (double *(*)) 0 + *p * *p & &x; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "PTR(INT) p = Int<0>\n\
+             STATIC PTR(PTR(LONG)) q\n\
+             FUN PTR(PTR(INT)) f:\n    params: (a, b)\n    body: none\n\
+             FUN INT main:\n    params: ()\n    body:\n        \
+             RETURN Binary(&, Binary(+, Binary(+, Binary(+, Deref(AddrOf(Deref(Var<p>))), \
+             Cast<INT>(Deref(Cast<PTR(LONG)>(AddrOf(Var<q>))))), Cast<PTR(PTR(DOUBLE))>(Int<0>)), \
+             Binary(*, Deref(Var<p>), Deref(Var<p>))), AddrOf(Var<x>))\n"
+        );
+        let cases = [
+            ("int (*f)(void);", "1:7: Pointers to functions are not supported"),
+            ("int (f(void))(void);", "1:6: Function declared as returning a function"),
+            ("int f(int g(void));", "1:11: Parameter 'g' declared as a function"),
+            ("int main(void) { return (int *()) 0; }", "1:32: Expected Multiplication, found CloseParenthesis"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_arrays() {
+        let source = "int a[3] = {1, 2, 3,}; long g[2][3] = {{1}, {2, 3}}; int f(int x[4], double (*m)[2]);
+int main(void) { int (*p)[3] = &a; return a[1] + 2[a] + (*p)[0] + (int) (long (*)[3]) 0 + g[1][2]++; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "ARRAY(INT, 3) a = {Int<1>, Int<2>, Int<3>}\n\
+             ARRAY(ARRAY(LONG, 3), 2) g = {{Int<1>}, {Int<2>, Int<3>}}\n\
+             FUN INT f:\n    params: (x, m)\n    body: none\n\
+             FUN INT main:\n    params: ()\n    body:\n        \
+             PTR(ARRAY(INT, 3)) p = AddrOf(Var<a>)\n        \
+             RETURN Binary(+, Binary(+, Binary(+, Binary(+, Subscript(Var<a>, Int<1>), Subscript(Int<2>, Var<a>)), \
+             Subscript(Deref(Var<p>), Int<0>)), Cast<INT>(Cast<PTR(ARRAY(LONG, 3))>(Int<0>))), \
+             Postfix(++, Subscript(Subscript(Var<g>, Int<1>), Int<2>)))\n"
+        );
+        // Array sizes are left for the type checker to evaluate, as is the type of an array
+        // parameter, which it turns into a pointer
+        let program = parse(lex_str("char b[sizeof(long) * 2 - n]; int f(int x[4]);")).unwrap();
+        assert_eq!(
+            pretty_print(&program),
+            "ARRAY(CHAR, Binary(-, Binary(*, SizeOf<LONG>, Int<2>), Var<n>)) b\n\
+             FUN INT f:\n    params: (x)\n    body: none\n"
+        );
+        let Declaration::Function(function) = &program.declarations[1] else { panic!("Expected a function") };
+        let params = vec![Type::DeclaredArray(Box::new(Type::Int), Box::new(Exp::Const(Constant::Int(4))))];
+        assert_eq!(function.ty, Type::Function { params, ret: Box::new(Type::Int) });
+        let cases = [
+            ("int f(void)[3];", "1:5: Function declared as returning an array"),
+            ("int a[3](void);", "1:5: Declared as an array of functions"),
+            ("int main(void) { return a[1; }", "1:28: Expected CloseBracket, found Semicolon"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_switch() {
+        let source = "int main(void) { switch (x + 1) case 1: case -2: return 3; switch (y) default: break; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "FUN INT main:\n    params: ()\n    body:\n        \
+             SWITCH Binary(+, Var<x>, Int<1>):\n            \
+             CASE Int<1>:\n                \
+             CASE Unary(-, Int<2>):\n                    \
+             RETURN Int<3>\n        \
+             SWITCH Var<y>:\n            \
+             DEFAULT:\n                \
+             BREAK\n"
+        );
+        let cases = [
+            ("int main(void) { switch x case 1: return 0; }", "1:25: Expected OpenParenthesis, found Identifier(\"x\")"),
+            ("int main(void) { switch (x) case 1 return 0; }", "1:36: Expected Colon, found ReturnKeyword"),
+            ("int main(void) { switch (x) default return 0; }", "1:37: Expected Colon, found ReturnKeyword"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_goto() {
+        let source = "int main(void) { goto end; x: x = f(1) + 2; end: if (x) y: ; return x; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "FUN INT main:\n    params: ()\n    body:\n        \
+             GOTO end\n        \
+             LABEL x:\n            \
+             EXPR Assign(Var<x>, Binary(+, Call<f>(Int<1>), Int<2>))\n        \
+             LABEL end:\n            \
+             IF Var<x>:\n                \
+             LABEL y:\n                    \
+             NULL\n        \
+             RETURN Var<x>\n"
+        );
+        let cases = [
+            ("int main(void) { goto 3; }", "1:23: Expected identifier, found IntegerLiteral(\"3\")"),
+            ("int main(void) { goto end }", "1:27: Expected Semicolon, found CloseBrace"),
+            ("int main(void) { end: }", "1:23: Expected an expression, found CloseBrace"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_structs() {
+        let source = "struct s; struct s { int a; struct s *next; char name[4]; };
+int main(void) { struct s x; struct s *p = &x; return x.a + p->next->a + x.name[1]; }";
+        assert_eq!(
+            pretty_print(&parse(lex_str(source)).unwrap()),
+            "STRUCT s\n\
+             STRUCT s:\n    INT a\n    PTR(STRUCT(s)) next\n    ARRAY(CHAR, 4) name\n\
+             FUN INT main:\n    params: ()\n    body:\n        \
+             STRUCT(s) x\n        \
+             PTR(STRUCT(s)) p = AddrOf(Var<x>)\n        \
+             RETURN Binary(+, Binary(+, Member(Var<x>, a), Member(Deref(Member(Deref(Var<p>), next)), a)), \
+             Subscript(Member(Var<x>, name), Int<1>))\n"
+        );
+        let cases = [
+            ("struct s {};", "1:11: Structure 's' has no members"),
+            ("static struct s { int a; };", "1:1: Storage class in a structure declaration"),
+            ("struct s { static int a; };", "1:12: Storage class in a member declaration"),
+            ("struct s { int f(void); };", "1:16: Member 'f' declared as a function"),
+            ("struct int x;", "1:8: Expected identifier, found IntKeyword"),
+            ("struct s long x;", "1:1: Invalid type specifier"),
+            ("int main(void) { return p->*q; }", "1:28: Expected identifier, found Multiplication"),
+            // Every malformed member is reported, and parsing resumes after the structure
+            ("struct s { static int a; int f(void); };\nint 3;", "1:12: Storage class in a member declaration\n\
+              1:30: Member 'f' declared as a function\n2:5: Expected identifier, found IntegerLiteral(\"3\")"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_storage_classes() {
+        let cases = [
+            ("static extern int x;", "1:8: More than one storage class in declaration"),
+            ("int static int x;", "1:12: Duplicate type specifier 'int'"),
+            ("static x;", "1:8: Expected a type specifier, found Identifier(\"x\")"),
+            ("int main(void) { for (static int i = 0; i < 3; i = i + 1) ; return 0; }", "1:34: Storage class in the declaration of a for loop"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_expected_type_or_expression() {
+        // The error names what was expected, not the one token tried last
+        let cases = [
+            ("void f(void);", "1:1: Expected a type specifier, found VoidKeyword"),
+            ("int printf(char *format, ...);", "1:26: Expected a type specifier, found Dot"),
+            ("int f(int a, ", "Expected a type specifier, but found end of input"),
+            ("int main(void) { int x = 0; if (x > ) return 1; return 0; }", "1:37: Expected an expression, found CloseParenthesis"),
+            ("int main(void) { return -; }", "1:26: Expected an expression, found Semicolon"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(lex_str(source)).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let program = parse(lex_str("int main(void) {\n    int x;\n    x = y;\n    break;\n}")).unwrap();
+        assert_eq!(functions(&program)[0].span, Span { line: 1, column: 5 });
+        let body = main_body(program);
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl { span, .. }))
+            if *span == Span { line: 2, column: 9 }));
+        match &body[1] {
+            BlockItem::Statement(Statement::Expression(Exp::Assignment(left, _, span))) => {
+                assert_eq!(*span, Span { line: 3, column: 7 });
+                assert!(matches!(**left, Exp::Var(_, Span { line: 3, column: 5 })));
+            }
+            other => panic!("Expected an assignment, found {:?}", other),
+        }
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::Break(_, Span { line: 4, column: 5 }))));
+    }
+
+    #[test]
+    fn test_parse_unbalanced_parentheses() {
+        let tokens = vec![Token::OpenParenthesis, Token::IntegerLiteral("1".to_string())];
+        let mut iter = spanned(tokens).into_iter().peekable();
+        assert_eq!(
+            parse_exp(&mut iter, 0).unwrap_err().to_string().to_string(),
+            "Expected CloseParenthesis, but found end of input"
+        );
     }
 
     #[test]
     fn test_parse_invalid_program_extra_tokens() {
         let tokens = vec![
             Token::IntKeyword,
-            Token::Identifier("main".to_string()),
+            Token::Identifier("main".into()),
             Token::OpenParenthesis,
             Token::CloseParenthesis,
             Token::OpenBrace,
@@ -222,8 +2346,82 @@ mod tests {
             Token::CloseBrace,
             Token::Semicolon, // Extra token here
         ];
-        let result = parse(tokens);
+        let result = parse(spanned(tokens));
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Unexpected tokens at after function delcaration");
+        assert_eq!(result.unwrap_err().to_string(), "1:10: Expected a type specifier, found Semicolon");
+    }
+
+    #[test]
+    fn test_parse_reports_several_errors() {
+        let source = "int main(void) {\n    int x = ;\n    return x +;\n    x = 1;\n}\nint f(int) { return 0; }\nint g(void) { return 1 }";
+        let errors = parse(lex_str(source)).unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "2:13: Expected an expression, found Semicolon\n\
+             3:15: Expected an expression, found Semicolon\n\
+             6:10: Expected identifier, found CloseParenthesis\n\
+             7:24: Expected Semicolon, found CloseBrace"
+        );
+    }
+
+    #[test]
+    fn test_synchronize_skips_nested_blocks() {
+        let mut iter = lex_str("x + { y; { z; } } w").into_iter().peekable();
+        synchronize(&mut iter);
+        assert_eq!(peek_token(&mut iter), Some(&Token::Identifier("w".into())));
+        let mut iter = lex_str("x y; z").into_iter().peekable();
+        synchronize(&mut iter);
+        assert_eq!(peek_token(&mut iter), Some(&Token::Identifier("z".into())));
+        let mut iter = lex_str("s { int a; }; z").into_iter().peekable();
+        synchronize(&mut iter);
+        assert_eq!(peek_token(&mut iter), Some(&Token::Identifier("z".into())));
+        let mut iter = lex_str("x y } z").into_iter().peekable();
+        synchronize(&mut iter);
+        assert_eq!(peek_token(&mut iter), Some(&Token::CloseBrace));
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let source = "int f(int a, int b);\n\
+                      int g = 4;\n\
+                      static int h(void);\n\
+                      extern int k;\n\
+                      int main(void) {\n\
+                          int x = -1;\n\
+                          if (x < 2) x = f(x, 3); else ;\n\
+                          for (int i = 0; ; i = i + 1) break;\n\
+                          do continue; while (!x);\n\
+                          return x ? x : ~x;\n\
+                      }";
+        let ast = parse(lex_str(source)).unwrap();
+        assert_eq!(
+            pretty_print(&ast),
+            "FUN INT f:\n\
+             \x20   params: (a, b)\n\
+             \x20   body: none\n\
+             INT g = Int<4>\n\
+             FUN STATIC INT h:\n\
+             \x20   params: ()\n\
+             \x20   body: none\n\
+             EXTERN INT k\n\
+             FUN INT main:\n\
+             \x20   params: ()\n\
+             \x20   body:\n\
+             \x20       INT x = Unary(-, Int<1>)\n\
+             \x20       IF Binary(<, Var<x>, Int<2>):\n\
+             \x20           EXPR Assign(Var<x>, Call<f>(Var<x>, Int<3>))\n\
+             \x20       ELSE:\n\
+             \x20           NULL\n\
+             \x20       FOR:\n\
+             \x20           init: INT i = Int<0>\n\
+             \x20           condition: none\n\
+             \x20           post: Assign(Var<i>, Binary(+, Var<i>, Int<1>))\n\
+             \x20           body:\n\
+             \x20               BREAK\n\
+             \x20       DO:\n\
+             \x20           CONTINUE\n\
+             \x20       WHILE Unary(!, Var<x>)\n\
+             \x20       RETURN Cond(Var<x>, Var<x>, Unary(~, Var<x>))\n"
+        );
     }
 }
@@ -0,0 +1,594 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::ast::*;
+use crate::cfg::Cfg;
+use crate::diagnostics::Diagnostic;
+use crate::ir::*;
+use crate::typecheck::{Symbol, SymbolTable};
+
+/// Whether a program is instrumented to count how often each of its blocks runs, or
+/// optimized with the counts a run of the instrumented program wrote.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub enum Profile {
+    #[default]
+    Off,
+    /// Count every block, and write the counts to the file at the path when `main` returns.
+    Generate(String),
+    /// Read the counts from the file at the path, lay out each function so that the paths
+    /// taken most fall through, and inline small functions where they were called.
+    Use(String),
+}
+
+/// The static array of counters. Its first element is a fingerprint of the functions and
+/// their blocks, so that counts of another program are not applied.
+const COUNTERS: &str = "__scc_profile";
+/// The function that writes the counters to the profile, called before `main` returns.
+const WRITE: &str = "__scc_profile_write";
+/// The most instructions a function may have, besides source positions, to be inlined.
+const INLINE_LIMIT: usize = 32;
+
+/// Instruments a program to count how often each block of each function runs. The counters
+/// are written as native-endian 8-byte integers, after the fingerprint, to the file at `path`
+/// when `main` returns, so a program whose `main` is in another file writes nothing. They are
+/// written with system calls, so the program needs no libc and may define functions named
+/// like libc's. The function this adds is added to the symbol table too.
+///
+/// # Arguments
+///
+/// * `ir` - The program, before it is optimized.
+/// * `symbols` - The symbol table of the program.
+/// * `path` - Where the running program writes the counts.
+pub fn instrument(ir: &mut IrProgram, symbols: &mut SymbolTable, path: &str) {
+    let mut shape = Vec::new();
+    let mut counter = 1;
+    for function in &mut ir.functions {
+        let mut cfg = Cfg::new(std::mem::take(&mut function.body));
+        let counted = counted_blocks(&cfg);
+        for index in &counted {
+            // A block's label has to stay first, so that jumps to it are counted too
+            let instructions = &mut cfg.blocks[*index].instructions;
+            let at = usize::from(matches!(instructions.first(), Some(IrInstruction::Label(_))));
+            instructions.splice(at..at, increment(counter));
+            counter += 1;
+        }
+        shape.push((function.name, counted.len()));
+        function.body = cfg.into_instructions();
+        if function.name == "main" {
+            function.body = std::mem::take(&mut function.body)
+                .into_iter()
+                .flat_map(|instruction| match instruction {
+                    IrInstruction::Return(_) => {
+                        vec![IrInstruction::FunCall { name: WRITE.into(), args: Vec::new(), dst: var("profile.written") }, instruction]
+                    }
+                    instruction => vec![instruction],
+                })
+                .collect();
+        }
+    }
+    if shape.is_empty() {
+        return;
+    }
+
+    ir.static_variables.push(IrStaticVariable {
+        name: COUNTERS.into(),
+        global: false,
+        alignment: 8,
+        init: vec![StaticInit::Scalar(Constant::Long(fingerprint(&shape))), StaticInit::Zero(8 * (counter - 1))],
+    });
+    ir.static_constants.push(IrStaticConstant {
+        name: "profile.path".into(),
+        alignment: 1,
+        init: StaticInit::String(path.to_string(), true),
+    });
+    ir.types.insert("profile.path".into(), Type::Array(Box::new(Type::Char), path.len() + 1));
+    ir.types.insert(COUNTERS.into(), Type::Array(Box::new(Type::Long), counter));
+    for (name, ty) in [
+        ("profile.address", Type::Pointer(Box::new(Type::Long))),
+        ("profile.count", Type::Long),
+        ("profile.path_ptr", Type::Pointer(Box::new(Type::Char))),
+        ("profile.file", Type::Long),
+        ("profile.failed", Type::Int),
+        ("profile.result", Type::Long),
+        ("profile.written", Type::Int),
+    ] {
+        ir.types.insert(name.into(), ty);
+    }
+
+    // System calls return minus the error number when they fail
+    let end: Name = "profile.end".into();
+    let file = var("profile.file");
+    let bytes = IrValue::Constant(Constant::ULong(8 * counter as u64));
+    ir.functions.push(IrFunction {
+        name: WRITE.into(),
+        global: false,
+        span: Span::default(),
+        params: Vec::new(),
+        body: vec![
+            IrInstruction::GetAddress { src: var("profile.path"), dst: var("profile.path_ptr") },
+            IrInstruction::SystemCall { call: SystemCall::Create, args: vec![var("profile.path_ptr")], dst: file.clone() },
+            IrInstruction::Binary {
+                op: BinaryOperator::LessThan,
+                src1: file.clone(),
+                src2: IrValue::Constant(Constant::Long(0)),
+                dst: var("profile.failed"),
+            },
+            IrInstruction::JumpIfNotZero(var("profile.failed"), end),
+            IrInstruction::GetAddress { src: var(COUNTERS), dst: var("profile.address") },
+            IrInstruction::SystemCall {
+                call: SystemCall::Write,
+                args: vec![file.clone(), var("profile.address"), bytes],
+                dst: var("profile.result"),
+            },
+            IrInstruction::SystemCall { call: SystemCall::Close, args: vec![file], dst: var("profile.result") },
+            IrInstruction::Label(end),
+            IrInstruction::Return(IrValue::Constant(Constant::Int(0))),
+        ],
+    });
+    let ty = Type::Function { params: Vec::new(), ret: Box::new(Type::Int) };
+    symbols.insert(WRITE.into(), Symbol { ty, defined: true, initial_value: None, global: false });
+}
+
+/// Optimizes a program with the counts an instrumented build of it wrote. Each function is
+/// laid out by following, from its entry, the successor that ran most, so that it falls
+/// through instead of jumping; a conditional jump is inverted when its target is placed
+/// next. Blocks that never ran are moved to the end. Then calls that ran are replaced by
+/// the body of the function they call, if it is small, defined in this file and does not
+/// allocate stack dynamically.
+///
+/// # Arguments
+///
+/// * `ir` - The program, before it is optimized and as it was when it was instrumented.
+/// * `path` - The file the instrumented program wrote the counts to.
+///
+/// # Returns
+///
+/// * `Result<(), Diagnostic>` - An error if the file cannot be read or was written by a
+///   program with other functions or blocks.
+pub fn apply_profile(ir: &mut IrProgram, path: &str) -> Result<(), Diagnostic> {
+    let bytes = std::fs::read(path).map_err(|e| Diagnostic::error_without_span(format!("Cannot read profile '{}': {}", path, e)))?;
+    let counts: Vec<u64> = bytes.chunks_exact(8).map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap())).collect();
+
+    let cfgs: Vec<Cfg> = ir.functions.iter().map(|function| Cfg::new(function.body.clone())).collect();
+    let counted: Vec<Vec<usize>> = cfgs.iter().map(counted_blocks).collect();
+    let shape: Vec<(Name, usize)> = ir.functions.iter().zip(&counted).map(|(function, blocks)| (function.name, blocks.len())).collect();
+    let expected = 1 + counted.iter().map(Vec::len).sum::<usize>();
+    if bytes.len() != 8 * expected || counts[0] != fingerprint(&shape) as u64 {
+        return Err(Diagnostic::error_without_span(format!("Profile '{}' was not written by this program", path)));
+    }
+
+    let callees: HashMap<Name, (Vec<Name>, Vec<IrInstruction>)> = ir
+        .functions
+        .iter()
+        .filter(|function| can_inline(&function.body))
+        .map(|function| (function.name, (function.params.clone(), function.body.clone())))
+        .collect();
+    let mut statics = ir.static_names();
+    statics.extend(ir.static_constants.iter().map(|constant| constant.name));
+    let mut next_id = 0;
+    let mut next_count = counts[1..].iter().copied();
+    for ((function, cfg), counted) in ir.functions.iter_mut().zip(cfgs).zip(counted) {
+        let mut block_counts = vec![0; cfg.blocks.len()];
+        for index in counted {
+            block_counts[index] = next_count.next().unwrap();
+        }
+        let order = layout(&cfg, &block_counts);
+        let blocks = arrange(cfg, &order, &mut next_id);
+        let mut body = Vec::new();
+        for (instructions, index) in blocks.into_iter().zip(order) {
+            if block_counts[index] == 0 {
+                body.extend(instructions);
+                continue;
+            }
+            for instruction in instructions {
+                match instruction {
+                    IrInstruction::FunCall { name, args, dst } if name != function.name && callees.contains_key(&name) => {
+                        let (params, callee) = &callees[&name];
+                        let id = next_id;
+                        next_id += 1;
+                        body.extend(inline(params, callee, args, dst, id, &statics, &mut ir.types));
+                    }
+                    instruction => body.push(instruction),
+                }
+            }
+        }
+        function.body = body;
+    }
+    Ok(())
+}
+
+/// Returns the blocks of a function that get a counter: the entry, the blocks that start
+/// with a label and those a conditional jump falls through to. Any other block follows a
+/// jump or a return, so it can never run.
+fn counted_blocks(cfg: &Cfg) -> Vec<usize> {
+    (0..cfg.blocks.len())
+        .filter(|&index| {
+            index == 0
+                || matches!(cfg.blocks[index].instructions.first(), Some(IrInstruction::Label(_)))
+                || matches!(
+                    cfg.blocks[index - 1].instructions.last(),
+                    Some(IrInstruction::JumpIfZero(..) | IrInstruction::JumpIfNotZero(..))
+                )
+        })
+        .collect()
+}
+
+/// Returns the instructions adding one to the counter at `index`.
+fn increment(index: usize) -> Vec<IrInstruction> {
+    let (address, count) = (var("profile.address"), var("profile.count"));
+    vec![
+        IrInstruction::GetAddress { src: var(COUNTERS), dst: address.clone() },
+        IrInstruction::AddPtr {
+            ptr: address.clone(),
+            index: IrValue::Constant(Constant::Long(index as i64)),
+            scale: 8,
+            dst: address.clone(),
+        },
+        IrInstruction::Load { src_ptr: address.clone(), dst: count.clone() },
+        IrInstruction::Binary { op: BinaryOperator::Add, src1: count.clone(), src2: IrValue::Constant(Constant::Long(1)), dst: count.clone() },
+        IrInstruction::Store { src: count, dst_ptr: address },
+    ]
+}
+
+/// Returns a hash of the names of the functions and the number of counters in each, with
+/// FNV-1a, which unlike the standard library's hasher gives the same result in every run.
+fn fingerprint(shape: &[(Name, usize)]) -> i64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (name, count) in shape {
+        for byte in name.as_str().bytes().chain([0]).chain((*count as u64).to_le_bytes()) {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash as i64
+}
+
+/// Returns the order to lay out the blocks of a function in: from the entry, each block is
+/// followed by its successor that ran most and is not placed yet, or else by the first such
+/// block that ran at all. The blocks that never ran come last, in their original order.
+fn layout(cfg: &Cfg, counts: &[u64]) -> Vec<usize> {
+    let mut placed = vec![false; cfg.blocks.len()];
+    let mut order = Vec::with_capacity(cfg.blocks.len());
+    let mut next = Some(0);
+    while let Some(index) = next {
+        placed[index] = true;
+        order.push(index);
+        next = cfg.blocks[index]
+            .successors
+            .iter()
+            .copied()
+            .filter(|&successor| !placed[successor] && counts[successor] > 0)
+            .max_by_key(|&successor| (counts[successor], Reverse(successor)))
+            .or_else(|| (0..cfg.blocks.len()).find(|&other| !placed[other] && counts[other] > 0));
+    }
+    order.extend((0..cfg.blocks.len()).filter(|&index| !placed[index]));
+    order
+}
+
+/// Puts the blocks of a function in a new order, making every fall-through that no longer
+/// reaches the block it did an explicit jump. A conditional jump to the block now placed
+/// after it is inverted to jump to the block it fell through to instead.
+///
+/// # Returns
+///
+/// * `Vec<Vec<IrInstruction>>` - The instructions of each block, in the new order.
+fn arrange(cfg: Cfg, order: &[usize], next_id: &mut usize) -> Vec<Vec<IrInstruction>> {
+    let mut blocks: Vec<Vec<IrInstruction>> = cfg.blocks.into_iter().map(|block| block.instructions).collect();
+    for (position, &index) in order.iter().enumerate() {
+        let follower = order.get(position + 1).copied();
+        let fallthrough = index + 1;
+        if follower == Some(fallthrough) || fallthrough == blocks.len() {
+            continue;
+        }
+        let follower_label = follower.and_then(|follower| match blocks[follower].first() {
+            Some(IrInstruction::Label(label)) => Some(*label),
+            _ => None,
+        });
+        match blocks[index].last() {
+            Some(IrInstruction::Return(_) | IrInstruction::Jump(_) | IrInstruction::Trap) => continue,
+            Some(IrInstruction::JumpIfZero(_, target) | IrInstruction::JumpIfNotZero(_, target)) if follower_label == Some(*target) => {
+                let label = block_label(&mut blocks[fallthrough], next_id);
+                let inverted = match blocks[index].pop() {
+                    Some(IrInstruction::JumpIfZero(condition, _)) => IrInstruction::JumpIfNotZero(condition, label),
+                    Some(IrInstruction::JumpIfNotZero(condition, _)) => IrInstruction::JumpIfZero(condition, label),
+                    _ => unreachable!("the block ends with a conditional jump"),
+                };
+                blocks[index].push(inverted);
+            }
+            _ => {
+                let label = block_label(&mut blocks[fallthrough], next_id);
+                blocks[index].push(IrInstruction::Jump(label));
+            }
+        }
+    }
+    let mut blocks: Vec<Option<Vec<IrInstruction>>> = blocks.into_iter().map(Some).collect();
+    order.iter().map(|&index| blocks[index].take().unwrap()).collect()
+}
+
+/// Returns the label a block starts with, giving it one first if it has none.
+fn block_label(block: &mut Vec<IrInstruction>, next_id: &mut usize) -> Name {
+    if let Some(IrInstruction::Label(label)) = block.first() {
+        return *label;
+    }
+    let label = Name::from(format!("layout.{}", next_id));
+    *next_id += 1;
+    block.insert(0, IrInstruction::Label(label));
+    label
+}
+
+/// Returns whether a function is small enough to inline, and does not allocate stack that
+/// its caller would only free when it returns.
+fn can_inline(body: &[IrInstruction]) -> bool {
+    body.iter().filter(|instruction| !matches!(instruction, IrInstruction::Location(_))).count() <= INLINE_LIMIT
+        && !body.iter().any(|instruction| matches!(instruction, IrInstruction::Alloca { .. }))
+}
+
+/// Returns the instructions replacing a call with the body of the function it calls. The
+/// callee's variables and labels get the suffix `.inline.<id>`, its parameters are assigned
+/// the arguments and each return assigns the result and jumps past the body.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the callee.
+/// * `callee` - The body of the callee.
+/// * `args` - The arguments of the call, already converted to the types of the parameters.
+/// * `dst` - Where the call stores its result.
+/// * `id` - The number that tells this copy of the callee from the others.
+/// * `statics` - The variables with static storage, which keep their names.
+/// * `types` - The types of the variables of the program, which the renamed ones are added to.
+fn inline(
+    params: &[Name],
+    callee: &[IrInstruction],
+    args: Vec<IrValue>,
+    dst: IrValue,
+    id: usize,
+    statics: &HashSet<Name>,
+    types: &mut BTreeMap<Name, Type>,
+) -> Vec<IrInstruction> {
+    let mut renamed: Vec<(Name, Name)> = Vec::new();
+    let mut value = |value: IrValue| match value {
+        IrValue::Var(name) if !statics.contains(&name) => {
+            let new = Name::from(format!("{}.inline.{}", name, id));
+            renamed.push((name, new));
+            IrValue::Var(new)
+        }
+        value => value,
+    };
+    let label = |label: Name| Name::from(format!("{}.inline.{}", label, id));
+    let end = Name::from(format!("inline_end.{}", id));
+    let mut instructions: Vec<IrInstruction> =
+        params.iter().zip(args).map(|(param, arg)| IrInstruction::Copy { src: arg, dst: value(IrValue::Var(*param)) }).collect();
+    for instruction in callee {
+        match instruction.clone() {
+            // The final `return 0` of every function may not have the callee's return type
+            IrInstruction::Return(IrValue::Constant(constant)) => {
+                let src = IrValue::Constant(constant.convert_to(&value_type(&dst, types)));
+                instructions.push(IrInstruction::Copy { src, dst: dst.clone() });
+                instructions.push(IrInstruction::Jump(end));
+            }
+            IrInstruction::Return(result) => {
+                instructions.push(IrInstruction::Copy { src: value(result), dst: dst.clone() });
+                instructions.push(IrInstruction::Jump(end));
+            }
+            instruction => instructions.push(rename(instruction, &mut value, &label)),
+        }
+    }
+    instructions.push(IrInstruction::Label(end));
+    for (name, new) in renamed {
+        if let Some(ty) = types.get(&name).cloned() {
+            types.insert(new, ty);
+        }
+    }
+    instructions
+}
+
+/// Returns an instruction with its values and labels replaced.
+fn rename(instruction: IrInstruction, value: &mut impl FnMut(IrValue) -> IrValue, label: &impl Fn(Name) -> Name) -> IrInstruction {
+    match instruction {
+        IrInstruction::Return(result) => IrInstruction::Return(value(result)),
+        IrInstruction::Trap => IrInstruction::Trap,
+        IrInstruction::Copy { src, dst } => IrInstruction::Copy { src: value(src), dst: value(dst) },
+        IrInstruction::SignExtend { src, dst } => IrInstruction::SignExtend { src: value(src), dst: value(dst) },
+        IrInstruction::ZeroExtend { src, dst } => IrInstruction::ZeroExtend { src: value(src), dst: value(dst) },
+        IrInstruction::Truncate { src, dst } => IrInstruction::Truncate { src: value(src), dst: value(dst) },
+        IrInstruction::DoubleToInt { src, dst } => IrInstruction::DoubleToInt { src: value(src), dst: value(dst) },
+        IrInstruction::DoubleToUInt { src, dst } => IrInstruction::DoubleToUInt { src: value(src), dst: value(dst) },
+        IrInstruction::IntToDouble { src, dst } => IrInstruction::IntToDouble { src: value(src), dst: value(dst) },
+        IrInstruction::UIntToDouble { src, dst } => IrInstruction::UIntToDouble { src: value(src), dst: value(dst) },
+        IrInstruction::Unary { op, src, dst } => IrInstruction::Unary { op, src: value(src), dst: value(dst) },
+        IrInstruction::Binary { op, src1, src2, dst } => {
+            IrInstruction::Binary { op, src1: value(src1), src2: value(src2), dst: value(dst) }
+        }
+        IrInstruction::Jump(target) => IrInstruction::Jump(label(target)),
+        IrInstruction::JumpIfZero(condition, target) => IrInstruction::JumpIfZero(value(condition), label(target)),
+        IrInstruction::JumpIfNotZero(condition, target) => IrInstruction::JumpIfNotZero(value(condition), label(target)),
+        IrInstruction::Label(name) => IrInstruction::Label(label(name)),
+        IrInstruction::FunCall { name, args, dst } => {
+            IrInstruction::FunCall { name, args: args.into_iter().map(&mut *value).collect(), dst: value(dst) }
+        }
+        IrInstruction::SystemCall { call, args, dst } => {
+            IrInstruction::SystemCall { call, args: args.into_iter().map(&mut *value).collect(), dst: value(dst) }
+        }
+        IrInstruction::GetAddress { src, dst } => IrInstruction::GetAddress { src: value(src), dst: value(dst) },
+        IrInstruction::Alloca { size, dst } => IrInstruction::Alloca { size: value(size), dst: value(dst) },
+        IrInstruction::Load { src_ptr, dst } => IrInstruction::Load { src_ptr: value(src_ptr), dst: value(dst) },
+        IrInstruction::Store { src, dst_ptr } => IrInstruction::Store { src: value(src), dst_ptr: value(dst_ptr) },
+        IrInstruction::AtomicLoad { src_ptr, dst } => IrInstruction::AtomicLoad { src_ptr: value(src_ptr), dst: value(dst) },
+        IrInstruction::AtomicStore { src, dst_ptr } => IrInstruction::AtomicStore { src: value(src), dst_ptr: value(dst_ptr) },
+        IrInstruction::CompareExchange { ptr, expected, desired, dst } => IrInstruction::CompareExchange {
+            ptr: value(ptr),
+            expected: value(expected),
+            desired: value(desired),
+            dst: value(dst),
+        },
+        IrInstruction::AddPtr { ptr, index, scale, dst } => {
+            IrInstruction::AddPtr { ptr: value(ptr), index: value(index), scale, dst: value(dst) }
+        }
+        IrInstruction::CopyToOffset { src, dst, offset } => {
+            let IrValue::Var(dst) = value(IrValue::Var(dst)) else { unreachable!("a variable is renamed to a variable") };
+            IrInstruction::CopyToOffset { src: value(src), dst, offset }
+        }
+        IrInstruction::Location(span) => IrInstruction::Location(span),
+    }
+}
+
+fn var(name: &str) -> IrValue {
+    IrValue::Var(name.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(functions: Vec<IrFunction>) -> IrProgram {
+        IrProgram { functions, static_variables: Vec::new(), extern_variables: Vec::new(), static_constants: Vec::new(), types: BTreeMap::new() }
+    }
+
+    fn function(name: &str, params: &[&str], body: Vec<IrInstruction>) -> IrFunction {
+        IrFunction { name: name.into(), global: true, span: Span::default(), params: params.iter().map(|&param| param.into()).collect(), body }
+    }
+
+    fn int(value: i32) -> IrValue {
+        IrValue::Constant(Constant::Int(value))
+    }
+
+    fn copy(src: IrValue, dst: &str) -> IrInstruction {
+        IrInstruction::Copy { src, dst: var(dst) }
+    }
+
+    // if (x) y = 1; else y = 2; return y;
+    fn branch() -> Vec<IrInstruction> {
+        vec![
+            IrInstruction::JumpIfZero(var("x"), "else".into()),
+            copy(int(1), "y"),
+            IrInstruction::Jump("end".into()),
+            IrInstruction::Label("else".into()),
+            copy(int(2), "y"),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(var("y")),
+        ]
+    }
+
+    fn write_profile(dir: &tempfile::TempDir, counts: &[i64]) -> String {
+        let path = dir.path().join("prog.prof");
+        std::fs::write(&path, counts.iter().flat_map(|count| count.to_ne_bytes()).collect::<Vec<u8>>()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_counted_blocks() {
+        let mut body = branch();
+        // A block after a jump that nothing jumps to is never counted
+        body.insert(3, copy(int(3), "y"));
+        assert_eq!(counted_blocks(&Cfg::new(body)), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_instrument() {
+        let mut ir = program(vec![function("main", &[], branch())]);
+        let mut symbols = SymbolTable::new();
+        instrument(&mut ir, &mut symbols, "prog.prof");
+
+        let body = &ir.functions[0].body;
+        assert_eq!(body[..5], increment(1)[..]);
+        assert_eq!(body[5], IrInstruction::JumpIfZero(var("x"), "else".into()));
+        assert_eq!(body[6..11], increment(2)[..]);
+        assert_eq!(body[13], IrInstruction::Label("else".into()));
+        assert_eq!(body[14..19], increment(3)[..]);
+        assert_eq!(body[body.len() - 2..], [
+            IrInstruction::FunCall { name: WRITE.into(), args: Vec::new(), dst: var("profile.written") },
+            IrInstruction::Return(var("y")),
+        ]);
+
+        let fingerprint = fingerprint(&[("main".into(), 4)]);
+        assert_eq!(ir.static_variables[0].init, vec![StaticInit::Scalar(Constant::Long(fingerprint)), StaticInit::Zero(32)]);
+        assert_eq!(ir.types[&Name::from(COUNTERS)], Type::Array(Box::new(Type::Long), 5));
+        assert_eq!(ir.functions[1].name, WRITE);
+        assert!(!ir.functions[1].global);
+        // The counts are written without calling any function the program could define
+        assert_eq!(symbols.keys().copied().collect::<Vec<Name>>(), [Name::from(WRITE)]);
+        let calls: Vec<&IrInstruction> = ir.functions[1]
+            .body
+            .iter()
+            .filter(|instruction| matches!(instruction, IrInstruction::FunCall { .. } | IrInstruction::SystemCall { .. }))
+            .collect();
+        assert_eq!(calls, [
+            &IrInstruction::SystemCall { call: SystemCall::Create, args: vec![var("profile.path_ptr")], dst: var("profile.file") },
+            &IrInstruction::SystemCall {
+                call: SystemCall::Write,
+                args: vec![var("profile.file"), var("profile.address"), IrValue::Constant(Constant::ULong(40))],
+                dst: var("profile.result"),
+            },
+            &IrInstruction::SystemCall { call: SystemCall::Close, args: vec![var("profile.file")], dst: var("profile.result") },
+        ]);
+    }
+
+    #[test]
+    fn test_layout_hot_branch_falls_through() {
+        let mut ir = program(vec![function("f", &[], branch())]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_profile(&dir, &[fingerprint(&[("f".into(), 4)]), 10, 1, 9, 10]);
+        apply_profile(&mut ir, &path).unwrap();
+        assert_eq!(ir.functions[0].body, vec![
+            IrInstruction::JumpIfNotZero(var("x"), "layout.0".into()),
+            IrInstruction::Label("else".into()),
+            copy(int(2), "y"),
+            IrInstruction::Label("end".into()),
+            IrInstruction::Return(var("y")),
+            IrInstruction::Label("layout.0".into()),
+            copy(int(1), "y"),
+            IrInstruction::Jump("end".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_inline_hot_calls() {
+        // int g(int a) { return a + 1; }
+        let callee = vec![
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("a"), src2: int(1), dst: var("tmp") },
+            IrInstruction::Return(var("tmp")),
+            IrInstruction::Return(int(0)),
+        ];
+        let call = |dst: &str| IrInstruction::FunCall { name: "g".into(), args: vec![var("x")], dst: var(dst) };
+        let caller = vec![
+            call("r"),
+            IrInstruction::JumpIfZero(var("r"), "cold".into()),
+            IrInstruction::Return(var("r")),
+            IrInstruction::Label("cold".into()),
+            call("s"),
+            IrInstruction::Return(var("s")),
+        ];
+        let mut ir = program(vec![function("g", &["a"], callee), function("f", &[], caller)]);
+        for name in ["a", "tmp", "r", "s", "x"] {
+            ir.types.insert(name.into(), Type::Int);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_profile(&dir, &[fingerprint(&[("g".into(), 1), ("f".into(), 3)]), 5, 5, 5, 0]);
+        apply_profile(&mut ir, &path).unwrap();
+
+        assert_eq!(ir.functions[1].body, vec![
+            copy(var("x"), "a.inline.0"),
+            IrInstruction::Binary { op: BinaryOperator::Add, src1: var("a.inline.0"), src2: int(1), dst: var("tmp.inline.0") },
+            copy(var("tmp.inline.0"), "r"),
+            IrInstruction::Jump("inline_end.0".into()),
+            copy(int(0), "r"),
+            IrInstruction::Jump("inline_end.0".into()),
+            IrInstruction::Label("inline_end.0".into()),
+            IrInstruction::JumpIfZero(var("r"), "cold".into()),
+            IrInstruction::Return(var("r")),
+            IrInstruction::Label("cold".into()),
+            call("s"),
+            IrInstruction::Return(var("s")),
+        ]);
+        assert_eq!(ir.types[&Name::from("tmp.inline.0")], Type::Int);
+    }
+
+    #[test]
+    fn test_reject_profile_of_another_program() {
+        let mut ir = program(vec![function("f", &[], branch())]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_profile(&dir, &[fingerprint(&[("g".into(), 4)]), 10, 1, 9, 10]);
+        let expected = Diagnostic::error_without_span(format!("Profile '{}' was not written by this program", path));
+        assert_eq!(apply_profile(&mut ir, &path), Err(expected.clone()));
+        let path = write_profile(&dir, &[fingerprint(&[("f".into(), 4)]), 10]);
+        assert_eq!(apply_profile(&mut ir, &path), Err(expected));
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap,HashMap,HashSet};
+use crate::ast::*;
+use crate::assembly::ARG_REGISTERS;
+
+/// The registers pseudo registers can be assigned to, in the order they are tried. `%r10` and
+/// `%r11` are missing because the instruction fix-up pass uses them as scratch registers.
+/// Caller-saved registers come first, as the callee-saved ones must be saved and restored.
+const ALLOCATABLE: [AsmRegister; 12] = [
+    AsmRegister::AX,
+    AsmRegister::CX,
+    AsmRegister::DX,
+    AsmRegister::SI,
+    AsmRegister::DI,
+    AsmRegister::R8,
+    AsmRegister::R9,
+    AsmRegister::BX,
+    AsmRegister::R12,
+    AsmRegister::R13,
+    AsmRegister::R14,
+    AsmRegister::R15,
+];
+
+/// The registers a called function may overwrite, according to the System V ABI.
+const CALLER_SAVED: [AsmRegister; 9] = [
+    AsmRegister::AX,
+    AsmRegister::CX,
+    AsmRegister::DX,
+    AsmRegister::SI,
+    AsmRegister::DI,
+    AsmRegister::R8,
+    AsmRegister::R9,
+    AsmRegister::R10,
+    AsmRegister::R11,
+];
+
+/// Assigns the pseudo registers of a function to hardware registers by coloring its
+/// interference graph, in which two operands are connected if one is written while the
+/// other still holds a value that is needed later. Pseudo registers that cannot be colored
+/// are left for `replace_pseudo_registers` to put on the stack, and moves from a register
+/// to itself are removed. Doubles are never assigned a register, since only general-purpose
+/// registers are allocated, and neither are pseudo registers whose address is taken, since
+/// they must stay in memory for pointers to reach them.
+///
+/// # Arguments
+///
+/// * `function` - The function whose instructions are rewritten in place.
+/// * `types` - The types of the variables and temporaries of the program.
+///
+/// # Returns
+///
+/// * `Vec<AsmRegister>` - The callee-saved registers the function now uses, which it must
+///   save in its prologue and restore before returning.
+pub fn allocate_registers(function: &mut AsmFunction, types: &BTreeMap<Name, Type>) -> Vec<AsmRegister> {
+    let graph = build_interference_graph(&function.instructions, types);
+    let colors = color_graph(&graph);
+
+    let replace = |operand: &mut AsmOperand| {
+        if let Some(register) = colors.get(operand) {
+            *operand = AsmOperand::Reg(*register);
+        }
+    };
+    for instruction in &mut function.instructions {
+        match instruction {
+            AsmInstruction::Mov(_, src, dst)
+            | AsmInstruction::Movsx(_, _, src, dst)
+            | AsmInstruction::MovZeroExtend(_, _, src, dst)
+            | AsmInstruction::Binary(_, _, src, dst)
+            | AsmInstruction::Cmp(_, src, dst)
+            | AsmInstruction::Xchg(_, src, dst)
+            | AsmInstruction::Cmpxchg(_, src, dst)
+            | AsmInstruction::Cvttsd2si(_, src, dst)
+            | AsmInstruction::Cvtsi2sd(_, src, dst)
+            | AsmInstruction::Lea(src, dst) => {
+                replace(src);
+                replace(dst);
+            }
+            AsmInstruction::Unary(_, _, operand)
+            | AsmInstruction::Idiv(_, operand)
+            | AsmInstruction::Div(_, operand)
+            | AsmInstruction::SetCC(_, operand)
+            | AsmInstruction::Push(operand) => replace(operand),
+            _ => {}
+        }
+    }
+    function.instructions.retain(|instruction| !matches!(instruction, AsmInstruction::Mov(_, src, dst) if src == dst));
+
+    let used: HashSet<AsmRegister> = colors.values().copied().collect();
+    ALLOCATABLE.iter().copied().filter(|register| used.contains(register) && !CALLER_SAVED.contains(register)).collect()
+}
+
+/// The operands an interference graph is built from: pseudo registers and the hardware
+/// registers they can be assigned to, in order of first appearance.
+#[derive(Debug, Default)]
+struct InterferenceGraph {
+    nodes: Vec<AsmOperand>,
+    neighbors: Vec<HashSet<usize>>,
+    /// The nodes each node is moved to or from, whose register it prefers so the move can go.
+    move_partners: Vec<Vec<usize>>,
+    /// The pseudo registers whose address is taken, which are never nodes.
+    address_taken: HashSet<AsmOperand>,
+}
+
+impl InterferenceGraph {
+    /// Returns the node of an operand, adding it if it is an integer pseudo register whose
+    /// address is not taken or an allocatable register seen for the first time.
+    fn node(
+        &mut self,
+        operand: &AsmOperand,
+        types: &BTreeMap<Name, Type>,
+        index: &mut HashMap<AsmOperand, usize>,
+    ) -> Option<usize> {
+        match operand {
+            AsmOperand::Pseudo(name) if types.get(name) != Some(&Type::Double) && !self.address_taken.contains(operand) => {}
+            AsmOperand::Reg(register) if ALLOCATABLE.contains(register) => {}
+            _ => return None,
+        }
+        Some(*index.entry(operand.clone()).or_insert_with(|| {
+            self.nodes.push(operand.clone());
+            self.neighbors.push(HashSet::new());
+            self.move_partners.push(Vec::new());
+            self.nodes.len() - 1
+        }))
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize) {
+        if a != b {
+            self.neighbors[a].insert(b);
+            self.neighbors[b].insert(a);
+        }
+    }
+}
+
+/// Returns the operands an instruction reads and the operands it writes, including the
+/// registers it uses implicitly. Memory reached through a pointer counts as a read of the
+/// registers its address is made of, whether the memory is read or written.
+///
+/// # Arguments
+///
+/// * `instruction` - The instruction.
+///
+/// # Returns
+///
+/// * `(Vec<AsmOperand>, Vec<AsmOperand>)` - The operands read and the operands written.
+fn uses_and_defs(instruction: &AsmInstruction) -> (Vec<AsmOperand>, Vec<AsmOperand>) {
+    let reg = AsmOperand::Reg;
+    let (uses, defs) = match instruction {
+        AsmInstruction::Mov(_, src, dst)
+        | AsmInstruction::Movsx(_, _, src, dst)
+        | AsmInstruction::MovZeroExtend(_, _, src, dst)
+        | AsmInstruction::Cvttsd2si(_, src, dst)
+        | AsmInstruction::Cvtsi2sd(_, src, dst) => (vec![src.clone()], vec![dst.clone()]),
+        // leaq only computes the address of its source without reading it, so only the
+        // registers the address is made of are used
+        AsmInstruction::Lea(src, dst) => (vec![src.clone()], vec![dst.clone()]),
+        AsmInstruction::Binary(_, _, src, dst) => (vec![src.clone(), dst.clone()], vec![dst.clone()]),
+        AsmInstruction::Unary(_, _, operand) => (vec![operand.clone()], vec![operand.clone()]),
+        // setCC only writes the low byte, so the rest of the destination must be preserved
+        AsmInstruction::SetCC(_, operand) => (vec![operand.clone()], vec![operand.clone()]),
+        AsmInstruction::Cmp(_, left, right) => (vec![left.clone(), right.clone()], Vec::new()),
+        AsmInstruction::Xchg(_, src, dst) => (vec![src.clone(), dst.clone()], vec![src.clone(), dst.clone()]),
+        AsmInstruction::Cmpxchg(_, src, dst) => {
+            (vec![src.clone(), dst.clone(), reg(AsmRegister::AX)], vec![dst.clone(), reg(AsmRegister::AX)])
+        }
+        AsmInstruction::Idiv(_, operand) | AsmInstruction::Div(_, operand) => (
+            vec![operand.clone(), reg(AsmRegister::AX), reg(AsmRegister::DX)],
+            vec![reg(AsmRegister::AX), reg(AsmRegister::DX)],
+        ),
+        AsmInstruction::Cdq(_) => (vec![reg(AsmRegister::AX)], vec![reg(AsmRegister::DX)]),
+        AsmInstruction::AllocateDynamic(register) => (vec![reg(*register)], vec![reg(*register)]),
+        AsmInstruction::Push(operand) => (vec![operand.clone()], Vec::new()),
+        AsmInstruction::Call(_, register_args) | AsmInstruction::SystemCall(_, register_args) => (
+            ARG_REGISTERS[..*register_args].iter().copied().map(reg).collect(),
+            CALLER_SAVED.iter().copied().map(reg).collect(),
+        ),
+        AsmInstruction::Ret => (vec![reg(AsmRegister::AX)], Vec::new()),
+        AsmInstruction::Jmp(_)
+        | AsmInstruction::JmpCC(..)
+        | AsmInstruction::Label(_)
+        | AsmInstruction::AllocateStack(_)
+        | AsmInstruction::DeallocateStack(_)
+        | AsmInstruction::ResetStack(_)
+        | AsmInstruction::Pop(_)
+        | AsmInstruction::Trap
+        | AsmInstruction::Location(_) => (Vec::new(), Vec::new()),
+    };
+    let (memory_defs, defs): (Vec<AsmOperand>, Vec<AsmOperand>) =
+        defs.into_iter().partition(|operand| matches!(operand, AsmOperand::Memory(..) | AsmOperand::Indexed(..)));
+    let uses = uses
+        .into_iter()
+        .chain(memory_defs)
+        .flat_map(|operand| match operand {
+            AsmOperand::Memory(register, _) => vec![reg(register)],
+            AsmOperand::Indexed(base, index, _) => vec![reg(base), reg(index)],
+            operand => vec![operand],
+        })
+        .collect();
+    (uses, defs)
+}
+
+/// Builds the interference graph of a function from the operands live after each of its
+/// instructions. Liveness is found by iterating a backward dataflow analysis over the
+/// instructions to a fixed point.
+///
+/// # Arguments
+///
+/// * `instructions` - The instructions of the function.
+/// * `types` - The types of the variables and temporaries of the program.
+///
+/// # Returns
+///
+/// * `InterferenceGraph` - The interference graph.
+fn build_interference_graph(instructions: &[AsmInstruction], types: &BTreeMap<Name, Type>) -> InterferenceGraph {
+    let address_taken = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            AsmInstruction::Lea(src @ AsmOperand::Pseudo(_), _) => Some(src.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut graph = InterferenceGraph { address_taken, ..InterferenceGraph::default() };
+    let mut index: HashMap<AsmOperand, usize> = HashMap::new();
+    let mut node_sets = |operands: Vec<AsmOperand>| -> Vec<usize> {
+        operands.iter().filter_map(|operand| graph.node(operand, types, &mut index)).collect()
+    };
+    let (uses, defs): (Vec<Vec<usize>>, Vec<Vec<usize>>) = instructions
+        .iter()
+        .map(|instruction| {
+            let (uses, defs) = uses_and_defs(instruction);
+            (node_sets(uses), node_sets(defs))
+        })
+        .unzip();
+
+    let labels: HashMap<&str, usize> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(position, instruction)| match instruction {
+            AsmInstruction::Label(label) => Some((label.as_str(), position)),
+            _ => None,
+        })
+        .collect();
+    let successors: Vec<Vec<usize>> = instructions
+        .iter()
+        .enumerate()
+        .map(|(position, instruction)| {
+            let next = (position + 1 < instructions.len()).then_some(position + 1);
+            match instruction {
+                AsmInstruction::Ret | AsmInstruction::Trap => Vec::new(),
+                AsmInstruction::Jmp(label) => vec![labels[label.as_str()]],
+                AsmInstruction::JmpCC(_, label) => next.into_iter().chain([labels[label.as_str()]]).collect(),
+                _ => next.into_iter().collect(),
+            }
+        })
+        .collect();
+
+    let mut live_in: Vec<HashSet<usize>> = vec![HashSet::new(); instructions.len()];
+    let mut live_out: Vec<HashSet<usize>> = vec![HashSet::new(); instructions.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for position in (0..instructions.len()).rev() {
+            let out: HashSet<usize> = successors[position].iter().flat_map(|successor| live_in[*successor].iter().copied()).collect();
+            let mut live: HashSet<usize> = out.iter().copied().filter(|node| !defs[position].contains(node)).collect();
+            live.extend(&uses[position]);
+            live_out[position] = out;
+            if live != live_in[position] {
+                live_in[position] = live;
+                changed = true;
+            }
+        }
+    }
+
+    for (position, instruction) in instructions.iter().enumerate() {
+        // The destination of a move may share a register with its source, as both hold the same value
+        let source = match instruction {
+            AsmInstruction::Mov(_, src, _) => index.get(src).copied(),
+            _ => None,
+        };
+        if let (Some(source), [destination]) = (source, defs[position].as_slice()) {
+            graph.move_partners[source].push(*destination);
+            graph.move_partners[*destination].push(source);
+        }
+        for def in &defs[position] {
+            for live in &live_out[position] {
+                if Some(*live) != source {
+                    graph.add_edge(*def, *live);
+                }
+            }
+        }
+    }
+    graph
+}
+
+/// Colors the pseudo registers of an interference graph with the allocatable registers.
+///
+/// Nodes with fewer neighbors than there are registers can always be colored, so they are
+/// removed from the graph one by one and colored in reverse order. When only nodes with
+/// many neighbors remain, the one with the most neighbors is removed optimistically; it is
+/// spilled if its neighbors end up using every register. A node prefers the register of an
+/// operand it is moved to or from, which makes the move redundant.
+///
+/// # Arguments
+///
+/// * `graph` - The interference graph.
+///
+/// # Returns
+///
+/// * `HashMap<AsmOperand, AsmRegister>` - The register of each pseudo register that was colored.
+fn color_graph(graph: &InterferenceGraph) -> HashMap<AsmOperand, AsmRegister> {
+    let mut remaining: Vec<usize> =
+        (0..graph.nodes.len()).filter(|node| matches!(graph.nodes[*node], AsmOperand::Pseudo(_))).collect();
+    let mut removed: HashSet<usize> = HashSet::new();
+    let mut order: Vec<usize> = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let degree = |node: usize| graph.neighbors[node].iter().filter(|neighbor| !removed.contains(neighbor)).count();
+        let position = remaining
+            .iter()
+            .position(|node| degree(*node) < ALLOCATABLE.len())
+            .unwrap_or_else(|| {
+                let most = remaining.iter().map(|node| degree(*node)).max().unwrap_or(0);
+                remaining.iter().position(|node| degree(*node) == most).unwrap_or(0)
+            });
+        let node = remaining.remove(position);
+        removed.insert(node);
+        order.push(node);
+    }
+
+    let mut colors: HashMap<usize, AsmRegister> = HashMap::new();
+    for node in order.into_iter().rev() {
+        let taken: HashSet<AsmRegister> = graph.neighbors[node]
+            .iter()
+            .filter_map(|neighbor| match &graph.nodes[*neighbor] {
+                AsmOperand::Reg(register) => Some(*register),
+                _ => colors.get(neighbor).copied(),
+            })
+            .collect();
+        let preferred = graph.move_partners[node].iter().find_map(|partner| {
+            let register = match &graph.nodes[*partner] {
+                AsmOperand::Reg(register) => Some(*register),
+                _ => colors.get(partner).copied(),
+            };
+            register.filter(|register| !taken.contains(register))
+        });
+        if let Some(register) = preferred.or_else(|| ALLOCATABLE.iter().copied().find(|register| !taken.contains(register))) {
+            colors.insert(node, register);
+        }
+    }
+    colors.into_iter().map(|(node, register)| (graph.nodes[node].clone(), register)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo(name: &str) -> AsmOperand {
+        AsmOperand::Pseudo(Name::from(name))
+    }
+
+    #[test]
+    fn test_allocate_simple_function() {
+        // return a + b, with a and b arriving in %edi and %esi
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Reg(AsmRegister::DI), pseudo("a")),
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Reg(AsmRegister::SI), pseudo("b")),
+                AsmInstruction::Mov(AsmType::Longword, pseudo("a"), pseudo("c")),
+                AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Longword, pseudo("b"), pseudo("c")),
+                AsmInstruction::Mov(AsmType::Longword, pseudo("c"), AsmOperand::Reg(AsmRegister::AX)),
+                AsmInstruction::Ret,
+            ],
+        };
+        let callee_saved = allocate_registers(&mut function, &BTreeMap::new());
+        assert!(callee_saved.is_empty());
+        // b stays where it arrived and c is computed where it is returned
+        assert_eq!(function.instructions, vec![
+            AsmInstruction::Mov(AsmType::Longword, AsmOperand::Reg(AsmRegister::DI), AsmOperand::Reg(AsmRegister::AX)),
+            AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Longword, AsmOperand::Reg(AsmRegister::SI), AsmOperand::Reg(AsmRegister::AX)),
+            AsmInstruction::Ret,
+        ]);
+    }
+
+    #[test]
+    fn test_values_live_across_calls_use_callee_saved_registers() {
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(1), pseudo("x")),
+                AsmInstruction::Call("g".into(), 0),
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Reg(AsmRegister::AX), pseudo("y")),
+                AsmInstruction::Binary(AsmBinaryOperator::Add, AsmType::Longword, pseudo("x"), pseudo("y")),
+                AsmInstruction::Mov(AsmType::Longword, pseudo("y"), AsmOperand::Reg(AsmRegister::AX)),
+                AsmInstruction::Ret,
+            ],
+        };
+        let callee_saved = allocate_registers(&mut function, &BTreeMap::new());
+        assert_eq!(callee_saved, vec![AsmRegister::BX]);
+        assert_eq!(function.instructions[0], AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(1), AsmOperand::Reg(AsmRegister::BX)));
+    }
+
+    #[test]
+    fn test_spill_when_registers_run_out() {
+        // Fourteen values that are all live at once cannot fit in twelve registers
+        let names: Vec<String> = (0..14).map(|i| format!("v{}", i)).collect();
+        let mut instructions: Vec<AsmInstruction> =
+            names.iter().map(|name| AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(1), pseudo(name))).collect();
+        instructions.extend(names.iter().map(|name| AsmInstruction::Push(pseudo(name))));
+        instructions.push(AsmInstruction::Ret);
+        let mut function = AsmFunction { name: "f".into(), global: true, span: Span::default(), instructions };
+        allocate_registers(&mut function, &BTreeMap::new());
+        let spilled = function
+            .instructions
+            .iter()
+            .filter(|instruction| matches!(instruction, AsmInstruction::Push(AsmOperand::Pseudo(_))))
+            .count();
+        // %eax is live at the return, which leaves eleven registers for values live until the end
+        assert_eq!(spilled, 3);
+    }
+
+    #[test]
+    fn test_address_taken_pseudo_stays_in_memory() {
+        // x = 1; p = &x; *p = 2; return x
+        let mut function = AsmFunction {
+            name: "f".into(),
+            global: true,
+            span: Span::default(),
+            instructions: vec![
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(1), pseudo("x")),
+                AsmInstruction::Lea(pseudo("x"), pseudo("p")),
+                AsmInstruction::Mov(AsmType::Quadword, pseudo("p"), AsmOperand::Reg(AsmRegister::AX)),
+                AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(2), AsmOperand::Memory(AsmRegister::AX, 0)),
+                AsmInstruction::Mov(AsmType::Longword, pseudo("x"), AsmOperand::Reg(AsmRegister::AX)),
+                AsmInstruction::Ret,
+            ],
+        };
+        allocate_registers(&mut function, &BTreeMap::new());
+        assert_eq!(function.instructions[1], AsmInstruction::Lea(pseudo("x"), AsmOperand::Reg(AsmRegister::AX)));
+        assert_eq!(function.instructions[2], AsmInstruction::Mov(AsmType::Longword, AsmOperand::Imm(2), AsmOperand::Memory(AsmRegister::AX, 0)));
+        assert_eq!(function.instructions[3], AsmInstruction::Mov(AsmType::Longword, pseudo("x"), AsmOperand::Reg(AsmRegister::AX)));
+    }
+}
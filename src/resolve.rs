@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+use crate::typecheck::builtin_type;
+
+/// Resolves the identifiers of a program: every local variable and parameter is renamed to a
+/// name that is unique in the program, and every use is rewritten to the name of the
+/// declaration it refers to. Functions, file-scope variables and local `extern` declarations
+/// keep their names, since they are visible to the linker. Structure tags live in a namespace
+/// of their own, scoped like variables, and every tag is renamed the same way.
+///
+/// # Arguments
+///
+/// * `ast` - The C AST to be resolved.
+///
+/// # Returns
+///
+/// * `Result<Program, Diagnostic>` - The resolved AST, or an error message if an identifier or
+///   tag is used before it is declared, declared twice, or assigned to when it is not an lvalue.
+pub fn resolve_program(ast: Program) -> Result<Program, Diagnostic> {
+    let mut resolver = Resolver { scope: HashMap::new(), tags: HashMap::new(), next_id: 0 };
+    let declarations = ast.declarations
+        .into_iter()
+        .map(|declaration| match declaration {
+            Declaration::Function(function) => resolver.resolve_function_declaration(function).map(Declaration::Function),
+            Declaration::Variable(variable) => resolver.resolve_file_scope_variable_declaration(variable).map(Declaration::Variable),
+            Declaration::Struct(declaration) => resolver.resolve_struct_declaration(declaration).map(Declaration::Struct),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Program { declarations })
+}
+
+/// An identifier visible at some point of the program.
+#[derive(Clone)]
+struct ScopeEntry {
+    unique_name: Name,
+    /// Whether the identifier was declared in the innermost scope, which makes
+    /// another declaration of the same name a redefinition rather than shadowing.
+    from_current_scope: bool,
+    /// Whether the identifier refers to the same entity in every declaration, as
+    /// functions do, so that it may be declared again in the same scope.
+    has_linkage: bool,
+}
+
+/// State shared while resolving a program.
+struct Resolver {
+    /// Maps each visible source name to the entity it currently refers to.
+    scope: HashMap<Name, ScopeEntry>,
+    /// Maps each visible structure tag to the structure type it currently names. Tags have
+    /// no linkage.
+    tags: HashMap<Name, ScopeEntry>,
+    next_id: usize,
+}
+
+impl Resolver {
+    /// Declares a function, then resolves its parameters and body in a new scope.
+    fn resolve_function_declaration(&mut self, function: FunDecl) -> Result<FunDecl, Diagnostic> {
+        if self.scope.get(&function.name).is_some_and(|entry| entry.from_current_scope && !entry.has_linkage) {
+            return Err(Diagnostic::error(function.span, format!("Duplicate declaration of function '{}'", function.name)));
+        }
+        let ty = self.resolve_type(function.ty, function.span)?;
+        let entry = ScopeEntry { unique_name: function.name, from_current_scope: true, has_linkage: true };
+        self.scope.insert(function.name, entry);
+        // The parameters and the outermost block of the body share a scope
+        self.in_new_scope(|resolver| {
+            let params = function.params
+                .into_iter()
+                .map(|param| resolver.declare_variable(param, function.span))
+                .collect::<Result<Vec<_>, _>>()?;
+            let body = function.body
+                .map(|body| body.into_iter().map(|item| resolver.resolve_block_item(item)).collect())
+                .transpose()?;
+            Ok(FunDecl { params, body, ty, ..function })
+        })
+    }
+
+    /// Declares a file-scope variable under its own name. Like a function, it may be declared
+    /// more than once, since every declaration refers to the same object.
+    fn resolve_file_scope_variable_declaration(&mut self, declaration: VarDecl) -> Result<VarDecl, Diagnostic> {
+        // The name is only in scope after its declarator, so the size of an array may not use it
+        let ty = self.resolve_type(declaration.ty, declaration.span)?;
+        let entry = ScopeEntry { unique_name: declaration.name, from_current_scope: true, has_linkage: true };
+        self.scope.insert(declaration.name, entry);
+        let init = declaration.init.map(|init| self.resolve_initializer(init)).transpose()?;
+        Ok(VarDecl { init, ty, ..declaration })
+    }
+
+    /// Declares a local `extern` variable, which refers to the file-scope object of the same
+    /// name and so keeps that name.
+    fn resolve_local_extern_declaration(&mut self, declaration: VarDecl) -> Result<VarDecl, Diagnostic> {
+        if self.scope.get(&declaration.name).is_some_and(|entry| entry.from_current_scope && !entry.has_linkage) {
+            let message = format!("Conflicting local declarations of '{}'", declaration.name);
+            return Err(Diagnostic::error(declaration.span, message));
+        }
+        self.resolve_file_scope_variable_declaration(declaration)
+    }
+
+    /// Resolves a declaration or statement in a function body.
+    fn resolve_block_item(&mut self, item: BlockItem) -> Result<BlockItem, Diagnostic> {
+        match item {
+            BlockItem::Declaration(Declaration::Variable(declaration)) => {
+                let declaration = match declaration.storage_class {
+                    Some(StorageClass::Extern) => self.resolve_local_extern_declaration(declaration)?,
+                    _ => self.resolve_variable_declaration(declaration)?,
+                };
+                Ok(BlockItem::Declaration(Declaration::Variable(declaration)))
+            }
+            BlockItem::Declaration(Declaration::Function(function)) => {
+                if function.body.is_some() {
+                    let message = format!("Function '{}' defined inside another function", function.name);
+                    return Err(Diagnostic::error(function.span, message));
+                }
+                if function.storage_class == Some(StorageClass::Static) {
+                    let message = format!("Function '{}' declared static inside another function", function.name);
+                    return Err(Diagnostic::error(function.span, message));
+                }
+                Ok(BlockItem::Declaration(Declaration::Function(self.resolve_function_declaration(function)?)))
+            }
+            BlockItem::Declaration(Declaration::Struct(declaration)) => {
+                Ok(BlockItem::Declaration(Declaration::Struct(self.resolve_struct_declaration(declaration)?)))
+            }
+            BlockItem::Statement(statement) => Ok(BlockItem::Statement(self.resolve_statement(statement)?)),
+        }
+    }
+
+    /// Declares a structure tag and resolves the types of its members. A tag already declared
+    /// in the current scope names the same structure, which lets `struct s;` be followed by
+    /// the member list; otherwise the declaration introduces a new structure type under a
+    /// fresh unique tag, shadowing any from an enclosing scope. The tag is in scope in its
+    /// own member list, so a structure can hold a pointer to another of its kind.
+    fn resolve_struct_declaration(&mut self, declaration: StructDecl) -> Result<StructDecl, Diagnostic> {
+        let tag = match self.tags.get(&declaration.tag) {
+            Some(entry) if entry.from_current_scope => entry.unique_name,
+            _ => self.declare_tag(declaration.tag),
+        };
+        let members = declaration.members
+            .map(|members| {
+                members
+                    .into_iter()
+                    .map(|member| Ok(MemberDecl { ty: self.resolve_type(member.ty, member.span)?, ..member }))
+                    .collect::<Result<Vec<_>, Diagnostic>>()
+            })
+            .transpose()?;
+        Ok(StructDecl { tag, members, ..declaration })
+    }
+
+    /// Adds a structure tag to the current scope under a fresh unique name.
+    fn declare_tag(&mut self, tag: Name) -> Name {
+        let unique_name = Name::from(format!("{}.{}", tag, self.next_id));
+        self.next_id += 1;
+        let entry = ScopeEntry { unique_name, from_current_scope: true, has_linkage: false };
+        self.tags.insert(tag, entry);
+        unique_name
+    }
+
+    /// Rewrites the structure tags in a type to their unique names, and resolves the names in
+    /// the sizes of its arrays. A pointer to a structure
+    /// whose tag is not declared declares it in the current scope, as an incomplete type that
+    /// a later declaration in the scope can complete, as `struct s;` would. `span` is where
+    /// any other use of an undeclared tag is reported.
+    fn resolve_type(&mut self, ty: Type, span: Span) -> Result<Type, Diagnostic> {
+        match ty {
+            Type::Struct(tag) => match self.tags.get(&tag) {
+                Some(entry) => Ok(Type::Struct(entry.unique_name)),
+                None => Err(Diagnostic::error(span, format!("Use of undeclared structure type 'struct {}'", tag))),
+            },
+            Type::Pointer(referenced) => match *referenced {
+                Type::Struct(tag) if !self.tags.contains_key(&tag) => {
+                    Ok(Type::Pointer(Box::new(Type::Struct(self.declare_tag(tag)))))
+                }
+                referenced => Ok(Type::Pointer(Box::new(self.resolve_type(referenced, span)?))),
+            },
+            Type::Atomic(ty) => Ok(Type::Atomic(Box::new(self.resolve_type(*ty, span)?))),
+            Type::DeclaredArray(element, size) => {
+                Ok(Type::DeclaredArray(Box::new(self.resolve_type(*element, span)?), Box::new(self.resolve_exp(*size)?)))
+            }
+            Type::Function { params, ret } => Ok(Type::Function {
+                params: params.into_iter().map(|param| self.resolve_type(param, span)).collect::<Result<_, _>>()?,
+                ret: Box::new(self.resolve_type(*ret, span)?),
+            }),
+            ty => Ok(ty),
+        }
+    }
+
+    /// Declares a variable under a fresh unique name and resolves its initializer. A local
+    /// `static` variable is renamed too, since it is not visible outside its block.
+    fn resolve_variable_declaration(&mut self, declaration: VarDecl) -> Result<VarDecl, Diagnostic> {
+        let ty = self.resolve_type(declaration.ty, declaration.span)?;
+        let name = self.declare_variable(declaration.name, declaration.span)?;
+        // The variable is in scope in its own initializer, as in `int x = x + 1;`
+        let init = declaration.init.map(|init| self.resolve_initializer(init)).transpose()?;
+        Ok(VarDecl { name, init, ty, ..declaration })
+    }
+
+    /// Adds a local variable or parameter to the current scope under a fresh unique name.
+    /// `span` is where a duplicate declaration is reported.
+    fn declare_variable(&mut self, name: Name, span: Span) -> Result<Name, Diagnostic> {
+        if self.scope.get(&name).is_some_and(|entry| entry.from_current_scope) {
+            return Err(Diagnostic::error(span, format!("Duplicate declaration of variable '{}'", name)));
+        }
+        // The unique name contains a '.', so it can never clash with a source identifier
+        let unique_name = Name::from(format!("{}.{}", name, self.next_id));
+        self.next_id += 1;
+        let entry = ScopeEntry { unique_name, from_current_scope: true, has_linkage: false };
+        self.scope.insert(name, entry);
+        Ok(unique_name)
+    }
+
+    /// Runs `resolve` in a new scope nested in the current one, restoring the current
+    /// scope afterwards so declarations made inside are forgotten.
+    fn in_new_scope<T>(&mut self, resolve: impl FnOnce(&mut Self) -> Result<T, Diagnostic>) -> Result<T, Diagnostic> {
+        let (outer, outer_tags) = (self.scope.clone(), self.tags.clone());
+        for entry in self.scope.values_mut().chain(self.tags.values_mut()) {
+            entry.from_current_scope = false;
+        }
+        let result = resolve(self);
+        self.scope = outer;
+        self.tags = outer_tags;
+        result
+    }
+
+    /// Resolves the expressions in a statement.
+    fn resolve_statement(&mut self, statement: Statement) -> Result<Statement, Diagnostic> {
+        match statement {
+            Statement::Return(exp, span) => Ok(Statement::Return(self.resolve_exp(exp)?, span)),
+            Statement::Expression(exp) => Ok(Statement::Expression(self.resolve_exp(exp)?)),
+            Statement::If(condition, then, otherwise) => Ok(Statement::If(
+                self.resolve_exp(condition)?,
+                Box::new(self.resolve_statement(*then)?),
+                otherwise.map(|otherwise| self.resolve_statement(*otherwise).map(Box::new)).transpose()?,
+            )),
+            Statement::While { condition, body, label } => Ok(Statement::While {
+                condition: self.resolve_exp(condition)?,
+                body: Box::new(self.resolve_statement(*body)?),
+                label,
+            }),
+            Statement::DoWhile { body, condition, label } => Ok(Statement::DoWhile {
+                body: Box::new(self.resolve_statement(*body)?),
+                condition: self.resolve_exp(condition)?,
+                label,
+            }),
+            // A declaration in the loop header is scoped to the loop
+            Statement::For { init, condition, post, body, label } => self.in_new_scope(|resolver| {
+                let init = match *init {
+                    ForInit::Declaration(declaration) => ForInit::Declaration(resolver.resolve_variable_declaration(declaration)?),
+                    ForInit::Expression(exp) => ForInit::Expression(resolver.resolve_optional_exp(exp)?),
+                };
+                Ok(Statement::For {
+                    init: Box::new(init),
+                    condition: resolver.resolve_optional_exp(condition)?,
+                    post: resolver.resolve_optional_exp(post)?,
+                    body: Box::new(resolver.resolve_statement(*body)?),
+                    label,
+                })
+            }),
+            Statement::Switch { condition, body, cases, label, span } => Ok(Statement::Switch {
+                condition: self.resolve_exp(condition)?,
+                body: Box::new(self.resolve_statement(*body)?),
+                cases,
+                label,
+                span,
+            }),
+            Statement::Case { value, body, label, span } => Ok(Statement::Case {
+                value: self.resolve_exp(value)?,
+                body: Box::new(self.resolve_statement(*body)?),
+                label,
+                span,
+            }),
+            Statement::Default { body, label, span } => Ok(Statement::Default {
+                body: Box::new(self.resolve_statement(*body)?),
+                label,
+                span,
+            }),
+            Statement::Labeled(label, body, span) => Ok(Statement::Labeled(label, Box::new(self.resolve_statement(*body)?), span)),
+            Statement::Goto(label, span) => Ok(Statement::Goto(label, span)),
+            Statement::Break(label, span) => Ok(Statement::Break(label, span)),
+            Statement::Continue(label, span) => Ok(Statement::Continue(label, span)),
+            Statement::Compound(items) => self.in_new_scope(|resolver| {
+                let items = items.into_iter().map(|item| resolver.resolve_block_item(item)).collect::<Result<_, _>>()?;
+                Ok(Statement::Compound(items))
+            }),
+            Statement::Null => Ok(Statement::Null),
+        }
+    }
+
+    /// Resolves the expressions in a variable's initializer.
+    fn resolve_initializer(&mut self, init: Initializer) -> Result<Initializer, Diagnostic> {
+        match init {
+            Initializer::Single(exp) => Ok(Initializer::Single(self.resolve_exp(exp)?)),
+            Initializer::Compound(elements, span) => {
+                let elements = elements.into_iter().map(|element| self.resolve_initializer(element)).collect::<Result<_, _>>()?;
+                Ok(Initializer::Compound(elements, span))
+            }
+        }
+    }
+
+    /// Resolves an optional expression, such as a clause of a `for` loop header.
+    fn resolve_optional_exp(&mut self, exp: Option<Exp>) -> Result<Option<Exp>, Diagnostic> {
+        exp.map(|exp| self.resolve_exp(exp)).transpose()
+    }
+
+    /// Rewrites variable references in an expression to their unique names.
+    fn resolve_exp(&mut self, exp: Exp) -> Result<Exp, Diagnostic> {
+        match exp {
+            Exp::Const(value) => Ok(Exp::Const(value)),
+            Exp::String(string, span) => Ok(Exp::String(string, span)),
+            Exp::WideString(string, span) => Ok(Exp::WideString(string, span)),
+            Exp::Var(name, span) => match self.scope.get(&name) {
+                Some(entry) => Ok(Exp::Var(entry.unique_name, span)),
+                None => Err(Diagnostic::error(span, format!("Use of undeclared variable '{}'", name))),
+            },
+            Exp::Assignment(left, right, span) => {
+                if !is_lvalue(&left) {
+                    return Err(Diagnostic::error(span, "Invalid lvalue on the left side of an assignment"));
+                }
+                let left = self.resolve_exp(*left)?;
+                let right = self.resolve_exp(*right)?;
+                Ok(Exp::Assignment(Box::new(left), Box::new(right), span))
+            }
+            Exp::CompoundAssignment(operator, left, right, span) => {
+                if !is_lvalue(&left) {
+                    return Err(Diagnostic::error(span, "Invalid lvalue on the left side of an assignment"));
+                }
+                let left = self.resolve_exp(*left)?;
+                let right = self.resolve_exp(*right)?;
+                Ok(Exp::CompoundAssignment(operator, Box::new(left), Box::new(right), span))
+            }
+            Exp::PostfixUpdate(operator, operand, span) => {
+                if !is_lvalue(&operand) {
+                    return Err(Diagnostic::error(span, "Invalid lvalue operand of an increment or decrement"));
+                }
+                Ok(Exp::PostfixUpdate(operator, Box::new(self.resolve_exp(*operand)?), span))
+            }
+            Exp::Conditional(condition, then, otherwise, span) => Ok(Exp::Conditional(
+                Box::new(self.resolve_exp(*condition)?),
+                Box::new(self.resolve_exp(*then)?),
+                Box::new(self.resolve_exp(*otherwise)?),
+                span,
+            )),
+            Exp::Comma(left, right, span) => {
+                let left = self.resolve_exp(*left)?;
+                let right = self.resolve_exp(*right)?;
+                Ok(Exp::Comma(Box::new(left), Box::new(right), span))
+            }
+            Exp::FunctionCall(name, args, span) => {
+                let name = match self.scope.get(&name) {
+                    Some(entry) => entry.unique_name,
+                    None if builtin_type(name).is_some() => name,
+                    None => return Err(Diagnostic::error(span, format!("Call to undeclared function '{}'", name))),
+                };
+                let args = args.into_iter().map(|arg| self.resolve_exp(arg)).collect::<Result<Vec<_>, _>>()?;
+                Ok(Exp::FunctionCall(name, args, span))
+            }
+            Exp::Cast(ty, operand, span) => {
+                let ty = self.resolve_type(ty, span)?;
+                Ok(Exp::Cast(ty, Box::new(self.resolve_exp(*operand)?), span))
+            }
+            Exp::UnOp(operator, operand, span) => Ok(Exp::UnOp(operator, Box::new(self.resolve_exp(*operand)?), span)),
+            Exp::SizeOf(operand, span) => Ok(Exp::SizeOf(Box::new(self.resolve_exp(*operand)?), span)),
+            Exp::SizeOfType(ty, span) => Ok(Exp::SizeOfType(self.resolve_type(ty, span)?, span)),
+            Exp::Dereference(operand, span) => Ok(Exp::Dereference(Box::new(self.resolve_exp(*operand)?), span)),
+            Exp::AddressOf(operand, span) => {
+                if !is_lvalue(&operand) {
+                    return Err(Diagnostic::error(span, "Invalid lvalue operand of '&'"));
+                }
+                Ok(Exp::AddressOf(Box::new(self.resolve_exp(*operand)?), span))
+            }
+            Exp::Subscript(array, index, span) => {
+                let array = self.resolve_exp(*array)?;
+                let index = self.resolve_exp(*index)?;
+                Ok(Exp::Subscript(Box::new(array), Box::new(index), span))
+            }
+            Exp::Member { base, member, offset, ty, span } => {
+                Ok(Exp::Member { base: Box::new(self.resolve_exp(*base)?), member, offset, ty, span })
+            }
+            Exp::BinOp(operator, left, right, span) => {
+                let left = self.resolve_exp(*left)?;
+                let right = self.resolve_exp(*right)?;
+                Ok(Exp::BinOp(operator, Box::new(left), Box::new(right), span))
+            }
+        }
+    }
+}
+
+/// Checks whether an expression designates an object, so that it can be assigned to or have
+/// its address taken: a variable, a dereferenced pointer, an array element, a string
+/// literal, or a member of a structure that is one of these. Assigning to a string literal,
+/// an array, is left to the type checker to reject.
+fn is_lvalue(exp: &Exp) -> bool {
+    match exp {
+        Exp::Member { base, .. } => is_lvalue(base),
+        _ => matches!(exp, Exp::Var(..) | Exp::String(..) | Exp::WideString(..) | Exp::Dereference(..) | Exp::Subscript(..)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(body: Vec<BlockItem>) -> Program {
+        Program { declarations: vec![Declaration::Function(function("main", &[], Some(body)))] }
+    }
+
+    fn function(name: &str, params: &[&str], body: Option<Vec<BlockItem>>) -> FunDecl {
+        let params: Vec<Name> = params.iter().map(|&p| Name::new(p)).collect();
+        let ty = Type::Function { params: vec![Type::Int; params.len()], ret: Box::new(Type::Int) };
+        FunDecl { name: Name::from(name), params, body, ty, storage_class: None, span: Span { line: 1, column: 5 } }
+    }
+
+    fn main_body(mut program: Program) -> Vec<BlockItem> {
+        match program.declarations.pop() {
+            Some(Declaration::Function(function)) => function.body.unwrap(),
+            other => panic!("Expected a function, found {:?}", other),
+        }
+    }
+
+    fn as_function(declaration: &Declaration) -> &FunDecl {
+        match declaration {
+            Declaration::Function(function) => function,
+            other => panic!("Expected a function, found {:?}", other),
+        }
+    }
+
+    fn declare(name: &str, init: Option<Exp>) -> BlockItem {
+        declare_with(name, init, None)
+    }
+
+    fn declare_with(name: &str, init: Option<Exp>, storage_class: Option<StorageClass>) -> BlockItem {
+        let declaration = VarDecl { name: Name::from(name), init: init.map(Initializer::Single), ty: Type::Int, storage_class, span: Span { line: 2, column: 9 } };
+        BlockItem::Declaration(Declaration::Variable(declaration))
+    }
+
+    fn var(name: &str) -> Box<Exp> {
+        Box::new(Exp::Var(Name::from(name), Span { line: 3, column: 12 }))
+    }
+
+    #[test]
+    fn test_variables_are_renamed_consistently() {
+        let ast = program(vec![
+            declare("a", Some(Exp::Const(Constant::Int(1)))),
+            declare("b", None),
+            BlockItem::Statement(Statement::Expression(Exp::Assignment(var("b"), var("a"), Span::default()))),
+            BlockItem::Statement(Statement::Return(*var("b"), Span::default())),
+        ]);
+        let body = main_body(resolve_program(ast).unwrap());
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl { name, .. })) if name == "a.0"));
+        assert!(matches!(&body[1], BlockItem::Declaration(Declaration::Variable(VarDecl { name, .. })) if name == "b.1"));
+        match &body[2] {
+            BlockItem::Statement(Statement::Expression(Exp::Assignment(left, right, _))) => {
+                assert!(matches!(&**left, Exp::Var(name, _) if name == "b.1"));
+                assert!(matches!(&**right, Exp::Var(name, _) if name == "a.0"));
+            }
+            other => panic!("Expected an assignment, found {:?}", other),
+        }
+        assert!(matches!(&body[3], BlockItem::Statement(Statement::Return(Exp::Var(name, _), _)) if name == "b.1"));
+    }
+
+    #[test]
+    fn test_use_before_declaration() {
+        let ast = program(vec![
+            BlockItem::Statement(Statement::Return(*var("x"), Span::default())),
+            declare("x", None),
+        ]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "3:12: Use of undeclared variable 'x'");
+        // A name is only in scope after its declarator, which includes the sizes of its arrays
+        for source in ["int a[sizeof a];", "int main(void) { int a[sizeof a]; return 0; }"] {
+            let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+            let error = resolve_program(ast).unwrap_err().to_string();
+            assert!(error.ends_with("Use of undeclared variable 'a'"), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_declaration() {
+        let ast = program(vec![declare("x", None), declare("x", Some(Exp::Const(Constant::Int(2))))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "2:9: Duplicate declaration of variable 'x'");
+    }
+
+    #[test]
+    fn test_for_loop_declaration_is_scoped_to_the_loop() {
+        let for_loop = |init: Option<Exp>| BlockItem::Statement(Statement::For {
+            init: Box::new(ForInit::Declaration(VarDecl { name: "i".into(), init: init.map(Initializer::Single), ty: Type::Int, storage_class: None, span: Span::default() })),
+            condition: Some(*var("i")),
+            post: None,
+            body: Box::new(Statement::Null),
+            label: Name::default(),
+        });
+        let ast = program(vec![
+            declare("i", None),
+            for_loop(Some(*var("i"))),
+            for_loop(None),
+            BlockItem::Statement(Statement::Return(*var("i"), Span::default())),
+        ]);
+        let body = main_body(resolve_program(ast).unwrap());
+        let BlockItem::Statement(Statement::For { init, condition: Some(Exp::Var(condition, _)), .. }) = &body[1] else {
+            panic!("Unexpected statement {:?}", body[1]);
+        };
+        let ForInit::Declaration(VarDecl { name, init: Some(Initializer::Single(Exp::Var(init, _))), .. }) = &**init else {
+            panic!("Unexpected loop initializer {:?}", init);
+        };
+        assert_eq!(name, "i.1");
+        // The initializer already sees the loop variable, like any other declaration
+        assert_eq!(init, "i.1");
+        assert_eq!(condition, "i.1");
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::For { init, .. })
+            if matches!(&**init, ForInit::Declaration(VarDecl { name, .. }) if name == "i.2")));
+        assert!(matches!(&body[3], BlockItem::Statement(Statement::Return(Exp::Var(name, _), _)) if name == "i.0"));
+    }
+
+    #[test]
+    fn test_invalid_lvalue() {
+        let assignment = Exp::Assignment(Box::new(Exp::Const(Constant::Int(2))), Box::new(Exp::Const(Constant::Int(3))), Span { line: 4, column: 7 });
+        let ast = program(vec![BlockItem::Statement(Statement::Expression(assignment))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "4:7: Invalid lvalue on the left side of an assignment");
+        let increment = Exp::PostfixUpdate(BinaryOperator::Add, Box::new(Exp::Const(Constant::Int(2))), Span { line: 5, column: 2 });
+        let ast = program(vec![BlockItem::Statement(Statement::Expression(increment))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "5:2: Invalid lvalue operand of an increment or decrement");
+        let address = Exp::AddressOf(Box::new(Exp::Const(Constant::Int(2))), Span { line: 6, column: 9 });
+        let ast = program(vec![BlockItem::Statement(Statement::Expression(address))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "6:9: Invalid lvalue operand of '&'");
+        // A dereferenced pointer is an lvalue, whatever the pointer expression is
+        let pointer = Box::new(Exp::Dereference(Box::new(Exp::Const(Constant::Int(0))), Span::default()));
+        let assignment = Exp::Assignment(pointer, Box::new(Exp::Const(Constant::Int(3))), Span::default());
+        assert!(resolve_program(program(vec![BlockItem::Statement(Statement::Expression(assignment))])).is_ok());
+    }
+
+    #[test]
+    fn test_functions_keep_their_names_and_parameters_are_renamed() {
+        let call = Exp::FunctionCall("add".into(), vec![*var("a"), Exp::Const(Constant::Int(1))], Span::default());
+        let ast = Program {
+            declarations: vec![
+                Declaration::Function(function("add", &["a", "b"], None)),
+                Declaration::Function(function("inc", &["a"], Some(vec![BlockItem::Statement(Statement::Return(call, Span::default()))]))),
+            ],
+        };
+        let program = resolve_program(ast).unwrap();
+        assert_eq!(as_function(&program.declarations[0]).params, vec!["a.0", "b.1"]);
+        assert_eq!(as_function(&program.declarations[1]).params, vec!["a.2"]);
+        match &as_function(&program.declarations[1]).body.as_deref() {
+            Some([BlockItem::Statement(Statement::Return(Exp::FunctionCall(name, args, _), _))]) => {
+                assert_eq!(name, "add");
+                assert!(matches!(&args[0], Exp::Var(name, _) if name == "a.2"));
+            }
+            other => panic!("Expected a call, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_file_scope_variables_keep_their_names() {
+        // int x; int main(void) { int y = x; int x = y; return x; }
+        let global = VarDecl { name: "x".into(), init: None, ty: Type::Int, storage_class: None, span: Span::default() };
+        let body = vec![
+            declare("y", Some(*var("x"))),
+            declare("x", Some(*var("y"))),
+            BlockItem::Statement(Statement::Return(*var("x"), Span::default())),
+        ];
+        let ast = Program {
+            declarations: vec![Declaration::Variable(global), Declaration::Function(function("main", &[], Some(body)))],
+        };
+        let body = main_body(resolve_program(ast).unwrap());
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl { init: Some(Initializer::Single(Exp::Var(name, _))), .. })) if name == "x"));
+        assert!(matches!(&body[1], BlockItem::Declaration(Declaration::Variable(VarDecl { name, .. })) if name == "x.1"));
+        assert!(matches!(&body[2], BlockItem::Statement(Statement::Return(Exp::Var(name, _), _)) if name == "x.1"));
+    }
+
+    #[test]
+    fn test_parameter_redeclared_in_body() {
+        let ast = Program { declarations: vec![Declaration::Function(function("f", &["a"], Some(vec![declare("a", None)])))] };
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "2:9: Duplicate declaration of variable 'a'");
+    }
+
+    #[test]
+    fn test_call_to_undeclared_function() {
+        let call = Exp::FunctionCall("f".into(), vec![], Span { line: 3, column: 12 });
+        let ast = program(vec![BlockItem::Statement(Statement::Return(call, Span::default()))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "3:12: Call to undeclared function 'f'");
+    }
+
+    #[test]
+    fn test_call_to_builtin() {
+        let call = Exp::FunctionCall("__builtin_alloca".into(), vec![Exp::Const(Constant::Int(8))], Span::default());
+        let ast = program(vec![BlockItem::Statement(Statement::Return(call, Span::default()))]);
+        let program = resolve_program(ast).unwrap();
+        match &as_function(&program.declarations[0]).body.as_deref() {
+            Some([BlockItem::Statement(Statement::Return(Exp::FunctionCall(name, _, _), _))]) => {
+                assert_eq!(name, "__builtin_alloca");
+            }
+            other => panic!("Expected a call, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_function_definition() {
+        let nested = function("f", &[], Some(vec![]));
+        let ast = program(vec![BlockItem::Declaration(Declaration::Function(nested))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "1:5: Function 'f' defined inside another function");
+    }
+
+    #[test]
+    fn test_local_function_declaration_conflicts_with_variable() {
+        let ast = program(vec![declare("f", None), BlockItem::Declaration(Declaration::Function(function("f", &[], None)))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "1:5: Duplicate declaration of function 'f'");
+    }
+
+    #[test]
+    fn test_local_storage_classes() {
+        // int main(void) { static int s = 1; extern int e; return s + e; }
+        let ast = program(vec![
+            declare_with("s", Some(Exp::Const(Constant::Int(1))), Some(StorageClass::Static)),
+            declare_with("e", None, Some(StorageClass::Extern)),
+            BlockItem::Statement(Statement::Return(Exp::BinOp(BinaryOperator::Add, var("s"), var("e"), Span::default()), Span::default())),
+        ]);
+        let body = main_body(resolve_program(ast).unwrap());
+        assert!(matches!(&body[0], BlockItem::Declaration(Declaration::Variable(VarDecl {
+            name, storage_class: Some(StorageClass::Static), ..
+        })) if name == "s.0"));
+        assert!(matches!(&body[1], BlockItem::Declaration(Declaration::Variable(VarDecl { name, .. })) if name == "e"));
+    }
+
+    #[test]
+    fn test_invalid_local_storage_classes() {
+        let ast = program(vec![declare("x", None), declare_with("x", None, Some(StorageClass::Extern))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "2:9: Conflicting local declarations of 'x'");
+        let ast = program(vec![declare_with("x", None, Some(StorageClass::Extern)), declare("x", None)]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "2:9: Duplicate declaration of variable 'x'");
+        let mut nested = function("f", &[], None);
+        nested.storage_class = Some(StorageClass::Static);
+        let ast = program(vec![BlockItem::Declaration(Declaration::Function(nested))]);
+        assert_eq!(resolve_program(ast).unwrap_err().to_string(), "1:5: Function 'f' declared static inside another function");
+    }
+
+    #[test]
+    fn test_structure_tags() {
+        let source = "struct s { int a; }; struct s *p; int x;
+int main(void) { struct s *q = p; struct s; struct s { struct s *next; }; struct s x; return q->a; }";
+        let ast = resolve_program(crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap()).unwrap();
+        // Tags get unique names like variables, and a local declaration shadows the file-scope tag
+        assert_eq!(
+            crate::parse::pretty_print(&ast),
+            "STRUCT s.0:\n    INT a\n\
+             PTR(STRUCT(s.0)) p\n\
+             INT x\n\
+             FUN INT main:\n    params: ()\n    body:\n        \
+             PTR(STRUCT(s.0)) q.1 = Var<p>\n        \
+             STRUCT s.2\n        \
+             STRUCT s.2:\n            PTR(STRUCT(s.2)) next\n        \
+             STRUCT(s.2) x.3\n        \
+             RETURN Member(Deref(Var<q.1>), a)\n"
+        );
+        // A pointer to an undeclared structure declares its tag in the current scope, so the
+        // definition that follows completes the same type
+        let source = "struct s *p; struct s { int a; }; int main(void) { struct t *q = (struct t *) 0; struct u **r; return p->a; }";
+        let ast = resolve_program(crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.starts_with("PTR(STRUCT(s.0)) p\nSTRUCT s.0:\n"), "{}", printed);
+        assert!(printed.contains("PTR(STRUCT(t.1)) q.2 = Cast<PTR(STRUCT(t.1))>(Int<0>)"), "{}", printed);
+        assert!(printed.contains("PTR(PTR(STRUCT(u.3))) r.4"), "{}", printed);
+
+        let cases = [
+            ("struct s x;", "1:10: Use of undeclared structure type 'struct s'"),
+            ("int main(void) { struct s { int a; }; return 0; } struct s x;", "1:60: Use of undeclared structure type 'struct s'"),
+            ("int main(void) { return sizeof(struct t); }", "1:25: Use of undeclared structure type 'struct t'"),
+        ];
+        for (source, expected) in cases {
+            let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+            assert_eq!(resolve_program(ast).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_blocks_are_scoped() {
+        let source = "int main(void) { int x = 1; { int y = x; int x = y; { x = 2; long x; } } return x; }";
+        let ast = resolve_program(crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap()).unwrap();
+        // A declaration in a block shadows the outer one from its own declarator on
+        assert_eq!(
+            crate::parse::pretty_print(&ast),
+            "FUN INT main:\n    params: ()\n    body:\n        \
+             INT x.0 = Int<1>\n        \
+             BLOCK:\n            \
+             INT y.1 = Var<x.0>\n            \
+             INT x.2 = Var<y.1>\n            \
+             BLOCK:\n                \
+             EXPR Assign(Var<x.2>, Int<2>)\n                \
+             LONG x.3\n        \
+             RETURN Var<x.0>\n"
+        );
+        let cases = [
+            ("int main(void) { { int x; } return x; }", "1:36: Use of undeclared variable 'x'"),
+            ("int main(void) { { int x; int x; } return 0; }", "1:31: Duplicate declaration of variable 'x'"),
+        ];
+        for (source, expected) in cases {
+            let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+            assert_eq!(resolve_program(ast).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+}
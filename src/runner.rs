@@ -0,0 +1,332 @@
+use std::path::{Path,PathBuf};
+use std::process::{Command,Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::time::{Duration,Instant};
+use crate::cli::TestOptions;
+
+/// The comment that marks a test as expected to fail, such as one for a known bug.
+const XFAIL_MARKER: &str = "scc-test: xfail";
+
+/// How long a test program may run before it is taken to hang and is killed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a test program ended.
+#[derive(Debug, PartialEq)]
+pub struct Run {
+    /// The exit code, or `None` if the program was killed by a signal.
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+}
+
+/// The outcome of one test.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    /// The test failed, for the given reason.
+    Fail(String),
+    /// The test failed, as its annotation said it would.
+    ExpectedFail,
+    /// The test is annotated as failing, but passed.
+    UnexpectedPass,
+}
+
+/// The outcomes of all the tests in a directory, in the order of their paths.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub results: Vec<(PathBuf, Outcome)>,
+}
+
+impl Summary {
+    /// Whether every test passed or failed as expected.
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(|(_, outcome)| matches!(outcome, Outcome::Pass | Outcome::ExpectedFail))
+    }
+
+    /// Formats the report printed after the tests have run: one line for each test that did
+    /// not pass, then the counts of each outcome.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The report.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let (mut passed, mut failed, mut expected_failures) = (0, 0, 0);
+        for (path, outcome) in &self.results {
+            match outcome {
+                Outcome::Pass => passed += 1,
+                Outcome::Fail(reason) => {
+                    failed += 1;
+                    report.push_str(&format!("FAIL {}: {}\n", path.display(), reason));
+                }
+                Outcome::ExpectedFail => {
+                    expected_failures += 1;
+                    report.push_str(&format!("XFAIL {}\n", path.display()));
+                }
+                Outcome::UnexpectedPass => {
+                    failed += 1;
+                    report.push_str(&format!("XPASS {}: passed, but is marked '{}'\n", path.display(), XFAIL_MARKER));
+                }
+            }
+        }
+        report.push_str(&format!(
+            "{} tests: {} passed, {} failed, {} expected failures\n",
+            self.results.len(),
+            passed,
+            failed,
+            expected_failures
+        ));
+        report
+    }
+}
+
+/// Builds every test in `options.dir` with scc and with the reference compiler, runs both
+/// programs and compares how they end. Tests run on `options.jobs` threads at once.
+///
+/// # Arguments
+///
+/// * `options` - The options `scc test` was invoked with.
+///
+/// # Returns
+///
+/// * `Result<Summary, String>` - The outcome of each test, or an `Err` if the tests cannot be
+///   found or there is nowhere to build them.
+pub fn run_tests(options: &TestOptions) -> Result<Summary, String> {
+    let mut tests = Vec::new();
+    find_tests(Path::new(&options.dir), &mut tests)
+        .map_err(|e| format!("Cannot read test directory {}: {}", options.dir, e))?;
+    tests.sort();
+    let scc = std::env::current_exe().map_err(|e| format!("Cannot find the scc executable: {}", e))?;
+    let scratch = tempfile::tempdir().map_err(|e| format!("Cannot create a temporary directory: {}", e))?;
+
+    // Each worker takes the next test not yet started until none are left
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Outcome)>> = Mutex::new(Vec::with_capacity(tests.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.min(tests.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(test) = tests.get(index) else { break };
+                let binary = scratch.path().join(index.to_string());
+                let outcome = run_test(test, &binary, &scc, options);
+                results.lock().unwrap().push((index, outcome));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    Ok(Summary { results: results.into_iter().map(|(index, outcome)| (tests[index].clone(), outcome)).collect() })
+}
+
+/// Collects the `.c` files in a directory and its subdirectories.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to search.
+/// * `tests` - The list the paths of the files are added to.
+///
+/// # Returns
+///
+/// * `std::io::Result<()>` - An `Err` if a directory cannot be read.
+fn find_tests(dir: &Path, tests: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_tests(&path, tests)?;
+        } else if path.extension().is_some_and(|extension| extension == "c") {
+            tests.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Runs one test: builds it with both compilers, runs both programs and compares them.
+///
+/// # Arguments
+///
+/// * `test` - The path of the test's source file.
+/// * `binary` - The path, without extension, to build the two programs at.
+/// * `scc` - The path of the scc executable.
+/// * `options` - The options `scc test` was invoked with.
+///
+/// # Returns
+///
+/// * `Outcome` - The outcome of the test, taking its annotation into account.
+fn run_test(test: &Path, binary: &Path, scc: &Path, options: &TestOptions) -> Outcome {
+    let expect_failure = match std::fs::read_to_string(test) {
+        Ok(source) => source.contains(XFAIL_MARKER),
+        Err(e) => return Outcome::Fail(format!("cannot read the test: {}", e)),
+    };
+    let result = check(test, binary, scc, options);
+    match (result, expect_failure) {
+        (Ok(()), false) => Outcome::Pass,
+        (Ok(()), true) => Outcome::UnexpectedPass,
+        (Err(_), true) => Outcome::ExpectedFail,
+        (Err(reason), false) => Outcome::Fail(reason),
+    }
+}
+
+/// Builds a test with both compilers and checks that the two programs end the same way.
+///
+/// # Arguments
+///
+/// * `test` - The path of the test's source file.
+/// * `binary` - The path, without extension, to build the two programs at.
+/// * `scc` - The path of the scc executable.
+/// * `options` - The options `scc test` was invoked with.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - `Ok(())` if the programs agree, otherwise an `Err` saying how the
+///   test failed.
+fn check(test: &Path, binary: &Path, scc: &Path, options: &TestOptions) -> Result<(), String> {
+    let expected_binary = binary.with_extension("expected");
+    let mut reference = Command::new(&options.reference);
+    reference.arg("-w").arg(test).arg("-o").arg(&expected_binary);
+    build(reference).map_err(|e| format!("{} cannot build the test: {}", options.reference, e))?;
+    let actual_binary = binary.with_extension("actual");
+    let mut scc = Command::new(scc);
+    scc.args(&options.compile_args).arg(test).arg("-o").arg(&actual_binary);
+    build(scc).map_err(|e| format!("scc cannot build the test: {}", e))?;
+
+    let expected = run(&expected_binary, &options.reference)?;
+    let actual = run(&actual_binary, "scc")?;
+    compare(&expected, &actual)
+}
+
+/// Runs a compiler and waits for it to finish.
+///
+/// # Arguments
+///
+/// * `command` - The compiler command line.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - An `Err` with the first line of the compiler's errors if it failed.
+fn build(mut command: Command) -> Result<(), String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(stderr.lines().next().unwrap_or("no error message").to_string())
+}
+
+/// Runs a test program, killing it if it runs for longer than `TIMEOUT`.
+///
+/// # Arguments
+///
+/// * `binary` - The path of the program.
+/// * `compiler` - The compiler that built the program, to name in errors.
+///
+/// # Returns
+///
+/// * `Result<Run, String>` - How the program ended, or an `Err` if it could not be started or
+///   did not finish in time.
+fn run(binary: &Path, compiler: &str) -> Result<Run, String> {
+    let mut child = Command::new(binary)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run the program built by {}: {}", compiler, e))?;
+    // Output is read on another thread so a program that fills the pipe does not block
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut stdout, &mut output).map(|_| output)
+    });
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("the program built by {} did not finish within {} seconds", compiler, TIMEOUT.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    };
+    let stdout = reader.join().expect("reading output does not panic").map_err(|e| e.to_string())?;
+    Ok(Run { code: status.code(), stdout })
+}
+
+/// Compares how the program built by scc ended with how the reference program did.
+///
+/// # Arguments
+///
+/// * `expected` - How the program built by the reference compiler ended.
+/// * `actual` - How the program built by scc ended.
+///
+/// # Returns
+///
+/// * `Result<(), String>` - `Ok(())` if both exited with the same code and printed the same
+///   output, otherwise an `Err` describing the difference.
+pub fn compare(expected: &Run, actual: &Run) -> Result<(), String> {
+    let describe = |code: Option<i32>| code.map_or_else(|| String::from("a crash"), |code| format!("exit code {}", code));
+    if expected.code != actual.code {
+        return Err(format!("expected {}, got {}", describe(expected.code), describe(actual.code)));
+    }
+    if expected.stdout != actual.stdout {
+        return Err(format!(
+            "output differs: expected {:?}, got {:?}",
+            String::from_utf8_lossy(&expected.stdout),
+            String::from_utf8_lossy(&actual.stdout)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let run = |code, stdout: &str| Run { code, stdout: stdout.as_bytes().to_vec() };
+        assert_eq!(compare(&run(Some(3), "a"), &run(Some(3), "a")), Ok(()));
+        assert_eq!(compare(&run(Some(3), ""), &run(Some(4), "")), Err("expected exit code 3, got exit code 4".to_string()));
+        assert_eq!(compare(&run(Some(0), ""), &run(None, "")), Err("expected exit code 0, got a crash".to_string()));
+        assert_eq!(
+            compare(&run(Some(0), "hi\n"), &run(Some(0), "ho\n")),
+            Err("output differs: expected \"hi\\n\", got \"ho\\n\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let summary = Summary {
+            results: vec![
+                (PathBuf::from("t/a.c"), Outcome::Pass),
+                (PathBuf::from("t/b.c"), Outcome::Fail("expected exit code 1, got exit code 2".to_string())),
+                (PathBuf::from("t/c.c"), Outcome::ExpectedFail),
+                (PathBuf::from("t/d.c"), Outcome::UnexpectedPass),
+            ],
+        };
+        assert_eq!(summary.report(), "\
+FAIL t/b.c: expected exit code 1, got exit code 2
+XFAIL t/c.c
+XPASS t/d.c: passed, but is marked 'scc-test: xfail'
+4 tests: 1 passed, 2 failed, 1 expected failures
+");
+        assert!(!summary.succeeded());
+        let summary = Summary { results: vec![(PathBuf::from("a.c"), Outcome::Pass), (PathBuf::from("b.c"), Outcome::ExpectedFail)] };
+        assert!(summary.succeeded());
+    }
+
+    #[test]
+    fn test_find_tests() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["b.c", "a.c", "notes.txt", "sub/c.c"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let mut tests = Vec::new();
+        find_tests(dir.path(), &mut tests).unwrap();
+        tests.sort();
+        let names: Vec<_> = tests.iter().map(|test| test.strip_prefix(dir.path()).unwrap().to_path_buf()).collect();
+        assert_eq!(names, [PathBuf::from("a.c"), PathBuf::from("b.c"), PathBuf::from("sub/c.c")]);
+    }
+}
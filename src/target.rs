@@ -0,0 +1,278 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::ir::SystemCall;
+
+/// The architecture assembly is generated for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+/// The operating system the generated code runs on, which decides symbol names and
+/// which assembler directives are available.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Os {
+    Linux,
+    /// macOS, whose Mach-O object files prefix C symbols with an underscore.
+    Darwin,
+}
+
+/// The platform assembly is generated for.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Target {
+    pub arch: Arch,
+    pub os: Os,
+}
+
+impl Target {
+    /// Returns the platform scc itself was built for.
+    pub fn host() -> Self {
+        let arch = if cfg!(target_arch = "aarch64") { Arch::Aarch64 } else { Arch::X86_64 };
+        let os = if cfg!(target_os = "macos") { Os::Darwin } else { Os::Linux };
+        Target { arch, os }
+    }
+
+    /// Returns how to make a system call on the platform: the number the kernel knows it by
+    /// and the arguments it takes, in order.
+    ///
+    /// Linux has no `open` on AArch64, so files are created with `openat` from the working
+    /// directory on both architectures. On Darwin, the kernel reports a failure by setting
+    /// the carry flag and returning the error number, which callers negate to match Linux.
+    ///
+    /// # Arguments
+    ///
+    /// * `call` - The system call.
+    ///
+    /// # Returns
+    ///
+    /// * `(i64, Vec<SystemCallArgument>)` - The number of the system call and its arguments.
+    pub fn system_call(&self, call: SystemCall) -> (i64, Vec<SystemCallArgument>) {
+        use SystemCallArgument::{Constant, Value};
+        // O_WRONLY | O_CREAT | O_TRUNC, and a mode of 0644 that the umask narrows
+        let (create_flags, mode) = match self.os {
+            Os::Linux => (0o1101, 0o644),
+            Os::Darwin => (0x601, 0o644),
+        };
+        let number = match (self.arch, self.os, call) {
+            (Arch::X86_64, Os::Linux, SystemCall::Write) => 1,
+            (Arch::X86_64, Os::Linux, SystemCall::Create) => 257,
+            (Arch::X86_64, Os::Linux, SystemCall::Close) => 3,
+            (Arch::X86_64, Os::Linux, SystemCall::Exit) => 231,
+            (Arch::Aarch64, Os::Linux, SystemCall::Write) => 64,
+            (Arch::Aarch64, Os::Linux, SystemCall::Create) => 56,
+            (Arch::Aarch64, Os::Linux, SystemCall::Close) => 57,
+            (Arch::Aarch64, Os::Linux, SystemCall::Exit) => 94,
+            (arch, Os::Darwin, call) => {
+                let number = match call {
+                    SystemCall::Write => 4,
+                    SystemCall::Create => 5,
+                    SystemCall::Close => 6,
+                    SystemCall::Exit => 1,
+                };
+                // BSD system calls are numbered from 0x2000000 on x86-64 macOS
+                if arch == Arch::X86_64 { 0x2000000 + number } else { number }
+            }
+        };
+        let arguments = match (call, self.os) {
+            (SystemCall::Write, _) => vec![Value(0), Value(1), Value(2)],
+            // AT_FDCWD
+            (SystemCall::Create, Os::Linux) => vec![Constant(-100), Value(0), Constant(create_flags), Constant(mode)],
+            (SystemCall::Create, Os::Darwin) => vec![Value(0), Constant(create_flags), Constant(mode)],
+            (SystemCall::Close | SystemCall::Exit, _) => vec![Value(0)],
+        };
+        (number, arguments)
+    }
+}
+
+/// An argument of a system call, as `Target::system_call` lays them out for a platform.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SystemCallArgument {
+    /// The value the IR passes at this position. Values keep their order, and no value comes
+    /// later in the arguments than in the IR, so they can be moved into place from last to
+    /// first.
+    Value(usize),
+    /// A constant the platform's interface needs, such as flags.
+    Constant(i64),
+}
+
+impl Os {
+    /// Converts the name of a C function to the symbol the object file uses for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The C name.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The symbol name, with a leading underscore on Darwin.
+    pub fn symbol(&self, name: &str) -> String {
+        match self {
+            Os::Linux => name.to_string(),
+            Os::Darwin => format!("_{}", name),
+        }
+    }
+
+    /// Converts a label to an assembler-local symbol, which cannot clash with function names
+    /// and is left out of the object file's symbol table.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The label name.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The local symbol name: `.L` on ELF platforms, `L` on Darwin.
+    pub fn local_label(&self, name: &str) -> String {
+        match self {
+            Os::Linux => format!(".L{}", name),
+            Os::Darwin => format!("L{}", name),
+        }
+    }
+
+    /// Returns the directive that switches to the section for read-only constants of a size.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of the constants in bytes, 8 or 16.
+    ///
+    /// # Returns
+    ///
+    /// * `&str` - `.section .rodata` on ELF platforms, the literal section of the size on Darwin.
+    pub fn literal_section(&self, size: usize) -> &'static str {
+        match (self, size) {
+            (Os::Linux, _) => " .section .rodata\n",
+            (Os::Darwin, 16) => " .literal16\n",
+            (Os::Darwin, _) => " .literal8\n",
+        }
+    }
+
+    /// Returns the directive that switches to the section for string literals.
+    ///
+    /// # Returns
+    ///
+    /// * `&str` - `.section .rodata` on ELF platforms, the C string section on Darwin.
+    pub fn string_section(&self) -> &'static str {
+        match self {
+            Os::Linux => " .section .rodata\n",
+            Os::Darwin => " .cstring\n",
+        }
+    }
+
+    /// Returns the directive that switches to the section for other read-only data, such as
+    /// wide string literals, which are not C strings.
+    ///
+    /// # Returns
+    ///
+    /// * `&str` - `.section .rodata` on ELF platforms, the constant section on Darwin.
+    pub fn constant_section(&self) -> &'static str {
+        match self {
+            Os::Linux => " .section .rodata\n",
+            Os::Darwin => " .const\n",
+        }
+    }
+
+    /// Returns the directive that marks the stack as non-executable, which only ELF
+    /// platforms need.
+    ///
+    /// # Returns
+    ///
+    /// * `&str` - The directive, or an empty string on Darwin.
+    pub fn stack_note(&self) -> &'static str {
+        match self {
+            Os::Linux => "    .section .note.GNU-stack,\"\",@progbits\n",
+            Os::Darwin => "",
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    /// Parses a target such as `aarch64-linux` or `x86_64-apple-darwin`. A bare architecture
+    /// name targets the host operating system.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "Unknown target: {} (expected x86_64-linux, aarch64-linux, x86_64-macos or aarch64-macos)",
+                name
+            )
+        };
+        let (arch, os) = match name.split_once('-') {
+            Some((arch, os)) => (arch, Some(os)),
+            None => (name, None),
+        };
+        let arch = match arch {
+            "x86_64" => Arch::X86_64,
+            "aarch64" | "arm64" => Arch::Aarch64,
+            _ => return Err(error()),
+        };
+        let os = match os {
+            None => Target::host().os,
+            Some("linux" | "linux-gnu" | "unknown-linux-gnu") => Os::Linux,
+            Some("macos" | "darwin" | "apple-darwin" | "apple-macos") => Os::Darwin,
+            Some(_) => return Err(error()),
+        };
+        Ok(Target { arch, os })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arch = match self.arch {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        };
+        let os = match self.os {
+            Os::Linux => "linux",
+            Os::Darwin => "macos",
+        };
+        write!(f, "{}-{}", arch, os)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("aarch64-linux".parse(), Ok(Target { arch: Arch::Aarch64, os: Os::Linux }));
+        assert_eq!("x86_64-linux".parse(), Ok(Target { arch: Arch::X86_64, os: Os::Linux }));
+        assert_eq!("arm64-apple-darwin".parse(), Ok(Target { arch: Arch::Aarch64, os: Os::Darwin }));
+        assert_eq!("x86_64-macos".parse(), Ok(Target { arch: Arch::X86_64, os: Os::Darwin }));
+        assert_eq!("aarch64".parse::<Target>().map(|target| target.os), Ok(Target::host().os));
+        assert!("mips".parse::<Target>().is_err());
+        assert!("x86_64-windows".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for name in ["x86_64-linux", "aarch64-linux", "x86_64-macos", "aarch64-macos"] {
+            assert_eq!(name.parse::<Target>().unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(Os::Linux.symbol("main"), "main");
+        assert_eq!(Os::Darwin.symbol("main"), "_main");
+        assert_eq!(Os::Linux.local_label("end.1"), ".Lend.1");
+        assert_eq!(Os::Darwin.local_label("end.1"), "Lend.1");
+        assert_eq!(Os::Darwin.stack_note(), "");
+    }
+
+    #[test]
+    fn test_system_calls() {
+        use SystemCallArgument::{Constant, Value};
+        let linux = Target { arch: Arch::X86_64, os: Os::Linux };
+        assert_eq!(linux.system_call(SystemCall::Write), (1, vec![Value(0), Value(1), Value(2)]));
+        assert_eq!(linux.system_call(SystemCall::Create), (257, vec![Constant(-100), Value(0), Constant(0o1101), Constant(0o644)]));
+        let aarch64 = Target { arch: Arch::Aarch64, os: Os::Linux };
+        assert_eq!(aarch64.system_call(SystemCall::Create).0, 56);
+        assert_eq!(aarch64.system_call(SystemCall::Exit), (94, vec![Value(0)]));
+        let darwin = Target { arch: Arch::X86_64, os: Os::Darwin };
+        assert_eq!(darwin.system_call(SystemCall::Create), (0x2000005, vec![Value(0), Constant(0x601), Constant(0o644)]));
+        assert_eq!(Target { arch: Arch::Aarch64, os: Os::Darwin }.system_call(SystemCall::Close), (6, vec![Value(0)]));
+    }
+}
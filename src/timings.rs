@@ -0,0 +1,278 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use crate::ast::*;
+use crate::json::Json;
+
+/// The bytes of heap memory in use, while `CountingAllocator` is the global allocator.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+/// The most bytes in use at once since the current phase started.
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// A global allocator that counts the bytes of heap memory in use, so that `--timings` can
+/// report the peak of each phase. The driver installs it with `#[global_allocator]`; without
+/// it no memory is reported.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn allocated(size: usize) {
+        let now = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn freed(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        Self::freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            Self::freed(layout.size());
+            Self::allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// What one phase of the compiler cost.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PhaseTiming {
+    /// The name of the phase, such as `lex` or `codegen`.
+    pub phase: &'static str,
+    pub time: Duration,
+    /// The size of what the phase produced, such as the number of tokens, and its unit.
+    pub output: Option<(usize, &'static str)>,
+    /// The most bytes of heap memory in use at once during the phase, or `None` if
+    /// allocations are not counted.
+    pub peak_memory: Option<usize>,
+}
+
+/// The cost of each phase of compiling one file, recorded for `--timings`. Nothing is
+/// recorded unless it is enabled, so the compiler can always be run with one.
+#[derive(Debug, Default)]
+pub struct Timings {
+    enabled: bool,
+    pub phases: Vec<PhaseTiming>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Timings { enabled, phases: Vec::new() }
+    }
+
+    /// Starts timing a phase, and restarts the count of its peak memory from the memory
+    /// already in use.
+    ///
+    /// # Returns
+    ///
+    /// * `Instant` - When the phase started, to be passed to `finish`.
+    pub fn start(&self) -> Instant {
+        if self.enabled {
+            PEAK.store(ALLOCATED.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        Instant::now()
+    }
+
+    /// Records a phase that has finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - The name of the phase.
+    /// * `start` - When the phase started, as returned by `start`.
+    /// * `output` - Measures what the phase produced. It is only called if timings are
+    ///   enabled, as walking a large AST takes time of its own.
+    pub fn finish(&mut self, phase: &'static str, start: Instant, output: impl FnOnce() -> Option<(usize, &'static str)>) {
+        if !self.enabled {
+            return;
+        }
+        let time = start.elapsed();
+        let peak = PEAK.load(Ordering::Relaxed);
+        let output = output();
+        self.phases.push(PhaseTiming { phase, time, output, peak_memory: (peak > 0).then_some(peak) });
+    }
+
+    /// Returns the time taken by every phase recorded.
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|phase| phase.time).sum()
+    }
+
+    /// Formats the phases as a table, one row per phase and a total at the end. The columns
+    /// of a phase that produced nothing measurable, such as running the assembler, are blank.
+    pub fn report(&self) -> String {
+        let mut out = format!("{:<10} {:>10} {:>20} {:>12}\n", "phase", "time (ms)", "output", "peak memory");
+        for phase in &self.phases {
+            let output = phase.output.map_or(String::new(), |(count, unit)| format!("{} {}", count, unit));
+            let memory = phase.peak_memory.map_or(String::new(), |bytes| format!("{:.1} KiB", bytes as f64 / 1024.0));
+            let row = format!("{:<10} {:>10.3} {:>20} {:>12}", phase.phase, milliseconds(phase.time), output, memory);
+            out += row.trim_end();
+            out.push('\n');
+        }
+        out += &format!("{:<10} {:>10.3}\n", "total", milliseconds(self.total()));
+        out
+    }
+
+    /// Converts the phases to JSON, as an object with the file they are about, the phases
+    /// in order and the total time.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The path of the file that was compiled.
+    pub fn to_json(&self, file: &str) -> Json {
+        let number = |value: String| Json::Number(value);
+        let phases = self.phases.iter().map(|phase| {
+            let mut fields = vec![
+                ("phase".to_string(), Json::String(phase.phase.to_string())),
+                ("time_ms".to_string(), number(format!("{:.3}", milliseconds(phase.time)))),
+            ];
+            if let Some((count, unit)) = phase.output {
+                fields.push(("output".to_string(), number(count.to_string())));
+                fields.push(("unit".to_string(), Json::String(unit.to_string())));
+            }
+            if let Some(bytes) = phase.peak_memory {
+                fields.push(("peak_memory_bytes".to_string(), number(bytes.to_string())));
+            }
+            Json::Object(fields)
+        });
+        Json::Object(vec![
+            ("file".to_string(), Json::String(file.to_string())),
+            ("phases".to_string(), Json::Array(phases.collect())),
+            ("total_ms".to_string(), number(format!("{:.3}", milliseconds(self.total())))),
+        ])
+    }
+}
+
+fn milliseconds(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+/// Counts the nodes of an AST: its declarations, statements, initializers and expressions.
+pub fn count_nodes(program: &Program) -> usize {
+    program.declarations.iter().map(count_declaration).sum()
+}
+
+fn count_declaration(declaration: &Declaration) -> usize {
+    1 + match declaration {
+        Declaration::Variable(declaration) => declaration.init.as_ref().map_or(0, count_initializer),
+        Declaration::Function(function) => function.body.iter().flatten().map(count_block_item).sum(),
+        Declaration::Struct(_) => 0,
+    }
+}
+
+fn count_block_item(item: &BlockItem) -> usize {
+    match item {
+        BlockItem::Statement(statement) => count_statement(statement),
+        BlockItem::Declaration(declaration) => count_declaration(declaration),
+    }
+}
+
+fn count_initializer(init: &Initializer) -> usize {
+    match init {
+        Initializer::Single(exp) => count_exp(exp),
+        Initializer::Compound(inits, _) => 1 + inits.iter().map(count_initializer).sum::<usize>(),
+    }
+}
+
+fn count_statement(statement: &Statement) -> usize {
+    1 + match statement {
+        Statement::Return(exp, _) | Statement::Expression(exp) => count_exp(exp),
+        Statement::If(condition, then, otherwise) => {
+            count_exp(condition) + count_statement(then) + otherwise.as_deref().map_or(0, count_statement)
+        }
+        Statement::While { condition, body, .. } | Statement::DoWhile { body, condition, .. } => {
+            count_exp(condition) + count_statement(body)
+        }
+        Statement::For { init, condition, post, body, .. } => {
+            let init = match init.as_ref() {
+                ForInit::Declaration(declaration) => 1 + declaration.init.as_ref().map_or(0, count_initializer),
+                ForInit::Expression(exp) => exp.as_ref().map_or(0, count_exp),
+            };
+            init + condition.iter().chain(post).map(count_exp).sum::<usize>() + count_statement(body)
+        }
+        Statement::Switch { condition, body, .. } | Statement::Case { value: condition, body, .. } => {
+            count_exp(condition) + count_statement(body)
+        }
+        Statement::Default { body, .. } | Statement::Labeled(_, body, _) => count_statement(body),
+        Statement::Compound(items) => items.iter().map(count_block_item).sum(),
+        Statement::Goto(..) | Statement::Break(..) | Statement::Continue(..) | Statement::Null => 0,
+    }
+}
+
+fn count_exp(exp: &Exp) -> usize {
+    1 + match exp {
+        Exp::Const(_) | Exp::Var(..) | Exp::String(..) | Exp::WideString(..) | Exp::SizeOfType(..) => 0,
+        Exp::Assignment(left, right, _)
+        | Exp::CompoundAssignment(_, left, right, _)
+        | Exp::Comma(left, right, _)
+        | Exp::Subscript(left, right, _)
+        | Exp::BinOp(_, left, right, _) => count_exp(left) + count_exp(right),
+        Exp::PostfixUpdate(_, operand, _)
+        | Exp::Cast(_, operand, _)
+        | Exp::UnOp(_, operand, _)
+        | Exp::Dereference(operand, _)
+        | Exp::AddressOf(operand, _)
+        | Exp::Member { base: operand, .. }
+        | Exp::SizeOf(operand, _) => count_exp(operand),
+        Exp::Conditional(condition, then, otherwise, _) => count_exp(condition) + count_exp(then) + count_exp(otherwise),
+        Exp::FunctionCall(_, args, _) => args.iter().map(count_exp).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_nodes() {
+        // main, the declaration of x and its initializer 1 + 2, the block, the return and x
+        let source = "int main(void) { int x = 1 + 2; { return x; } }";
+        let program = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        assert_eq!(count_nodes(&program), 8);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut timings = Timings::new(true);
+        timings.phases.push(PhaseTiming { phase: "lex", time: Duration::from_micros(1500), output: Some((12, "tokens")), peak_memory: Some(2048) });
+        timings.phases.push(PhaseTiming { phase: "assemble", time: Duration::from_millis(2), output: None, peak_memory: None });
+        assert_eq!(
+            timings.report(),
+            "phase       time (ms)               output  peak memory\n\
+             lex             1.500            12 tokens      2.0 KiB\n\
+             assemble        2.000\n\
+             total           3.500\n"
+        );
+        assert_eq!(
+            timings.to_json("a.c").to_string(),
+            "{\"file\":\"a.c\",\"phases\":[{\"phase\":\"lex\",\"time_ms\":1.500,\"output\":12,\"unit\":\"tokens\",\"peak_memory_bytes\":2048},\
+             {\"phase\":\"assemble\",\"time_ms\":2.000}],\"total_ms\":3.500}"
+        );
+        // A disabled recorder records nothing
+        let mut timings = Timings::default();
+        let start = timings.start();
+        timings.finish("lex", start, || unreachable!("the output is only measured when enabled"));
+        assert!(timings.phases.is_empty());
+    }
+}
@@ -0,0 +1,2049 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::ast::*;
+use crate::diagnostics::Diagnostic;
+use crate::parse::binary_symbol;
+
+/// The value a variable with static storage starts out with.
+#[derive(Debug, PartialEq, Clone)]
+pub enum InitialValue {
+    /// Declared without an initializer, so zero unless another declaration provides one.
+    Tentative,
+    /// Initialized with constants, already converted to the types of the objects they
+    /// initialize, with zeros in place of the elements the initializer leaves out.
+    Initial(Vec<StaticInit>),
+    /// Declared `extern` without an initializer, so defined in another file unless a later
+    /// declaration in this one defines it.
+    NoInitializer,
+    /// A string literal, which goes in read-only data since the program may not change it.
+    Constant(StaticInit),
+}
+
+/// What the type checker knows about one identifier.
+#[derive(Debug)]
+pub struct Symbol {
+    pub ty: Type,
+    /// Whether a function has been given a body, or a file-scope variable an initializer.
+    /// Always `true` for local variables.
+    pub defined: bool,
+    /// The initial value of a variable with static storage. `None` for local variables and
+    /// functions.
+    pub initial_value: Option<InitialValue>,
+    /// Whether a function or variable with static storage has external linkage, so that other
+    /// files can refer to it. `false` for names declared `static` and for local variables.
+    pub global: bool,
+}
+
+/// Maps every identifier of a resolved program to its symbol. Local variables have already
+/// been given unique names, so a single flat table covers all scopes.
+pub type SymbolTable = HashMap<Name, Symbol>;
+
+/// An error found while type checking a program.
+#[derive(Debug, PartialEq)]
+pub enum TypeError {
+    /// A function is declared twice with different numbers of parameters.
+    IncompatibleDeclarations { name: Name, previous: usize, found: usize, span: Span },
+    /// A name is declared twice with different types.
+    ConflictingTypes { name: Name, span: Span },
+    /// A function is defined more than once.
+    Redefinition { name: Name, span: Span },
+    /// A file-scope variable is given an initializer more than once.
+    VariableRedefinition { name: Name, span: Span },
+    /// A variable with static storage is initialized with something other than a constant.
+    NonConstantInitializer { name: Name, span: Span },
+    /// A file-scope name is declared both as a function and as a variable.
+    ConflictingKinds { name: Name, span: Span },
+    /// A name is declared `static` after a declaration that gave it external linkage, or
+    /// a file-scope variable is declared without `static` after one that was.
+    ConflictingLinkage { name: Name, span: Span },
+    /// A local `extern` declaration has an initializer.
+    ExternInitializer { name: Name, span: Span },
+    /// A function name is used where a variable is expected.
+    FunctionUsedAsVariable { name: Name, span: Span },
+    /// A variable is called as if it were a function.
+    VariableCalledAsFunction { name: Name, span: Span },
+    /// A function is called with the wrong number of arguments.
+    WrongArgumentCount { name: Name, expected: usize, found: usize, span: Span },
+    /// An operator that only applies to integers, such as `%` or `~`, is given a `double`.
+    DoubleOperand { operator: String, span: Span },
+    /// An operator that only applies to arithmetic types, such as `*` or `-`, is given a
+    /// pointer or a structure.
+    PointerOperand { operator: String, ty: Type, span: Span },
+    /// A pointer is compared with, or chosen by `?:` alongside, a value of another type.
+    IncompatibleOperands { operator: String, left: Type, right: Type, span: Span },
+    /// A value is assigned, passed or returned where a value of a type it does not
+    /// implicitly convert to is expected.
+    IncompatibleTypes { from: Type, to: Type, span: Span },
+    /// A cast between a pointer and a `double`.
+    InvalidCast { from: Type, to: Type, span: Span },
+    /// A value that is not a pointer is dereferenced.
+    InvalidDereference { ty: Type, span: Span },
+    /// An array is assigned to or updated, which C does not allow.
+    NotAssignable { ty: Type, span: Span },
+    /// A subscript is not a pointer and an integer, in either order.
+    InvalidSubscript { left: Type, right: Type, span: Span },
+    /// A scalar is initialized with a brace-enclosed list, or an array with a single value.
+    InvalidInitializer { ty: Type, span: Span },
+    /// An array or structure initializer has more elements than the array or members.
+    TooManyInitializers { ty: Type, span: Span },
+    /// A structure is given a member list more than once in the same scope.
+    StructRedefinition { ty: Type, span: Span },
+    /// Two members of a structure have the same name.
+    DuplicateMember { member: Name, ty: Type, span: Span },
+    /// An object of a structure type without a member list is declared or used, or such a
+    /// structure is the element of an array.
+    IncompleteType { ty: Type, span: Span },
+    /// A member is selected from a value that is not a structure.
+    NotAStructure { ty: Type, span: Span },
+    /// A member is selected that the structure does not have.
+    UnknownMember { member: Name, ty: Type, span: Span },
+    /// A function takes or returns a structure, which is not supported yet.
+    StructByValue { name: Name, span: Span },
+    /// A structure is used as a condition or as the operand of `!`, `&&`, `||`, `++` or `--`,
+    /// which need a scalar.
+    ScalarRequired { ty: Type, span: Span },
+    /// The value a `switch` compares does not have an integer type.
+    InvalidSwitch { ty: Type, span: Span },
+    /// A `case` label is not an integer constant.
+    NonConstantCase { span: Span },
+    /// The size of an array is not a constant.
+    NonConstantArraySize { span: Span },
+    /// The size of an array is a constant without an integer type.
+    NonIntegerArraySize { span: Span },
+    /// The size of an array is zero or negative.
+    NonPositiveArraySize { span: Span },
+    /// A type other than an integer type is qualified with `_Atomic`.
+    InvalidAtomic { ty: Type, span: Span },
+}
+
+impl TypeError {
+    /// Returns the position of the declaration or expression the error is about.
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::IncompatibleDeclarations { span, .. }
+            | TypeError::ConflictingTypes { span, .. }
+            | TypeError::Redefinition { span, .. }
+            | TypeError::VariableRedefinition { span, .. }
+            | TypeError::NonConstantInitializer { span, .. }
+            | TypeError::ConflictingKinds { span, .. }
+            | TypeError::ConflictingLinkage { span, .. }
+            | TypeError::ExternInitializer { span, .. }
+            | TypeError::FunctionUsedAsVariable { span, .. }
+            | TypeError::VariableCalledAsFunction { span, .. }
+            | TypeError::WrongArgumentCount { span, .. }
+            | TypeError::DoubleOperand { span, .. }
+            | TypeError::PointerOperand { span, .. }
+            | TypeError::IncompatibleOperands { span, .. }
+            | TypeError::IncompatibleTypes { span, .. }
+            | TypeError::InvalidCast { span, .. }
+            | TypeError::InvalidDereference { span, .. }
+            | TypeError::NotAssignable { span, .. }
+            | TypeError::InvalidSubscript { span, .. }
+            | TypeError::InvalidInitializer { span, .. }
+            | TypeError::TooManyInitializers { span, .. }
+            | TypeError::StructRedefinition { span, .. }
+            | TypeError::DuplicateMember { span, .. }
+            | TypeError::IncompleteType { span, .. }
+            | TypeError::NotAStructure { span, .. }
+            | TypeError::UnknownMember { span, .. }
+            | TypeError::StructByValue { span, .. }
+            | TypeError::ScalarRequired { span, .. }
+            | TypeError::InvalidSwitch { span, .. }
+            | TypeError::InvalidAtomic { span, .. }
+            | TypeError::NonConstantCase { span }
+            | TypeError::NonConstantArraySize { span }
+            | TypeError::NonIntegerArraySize { span }
+            | TypeError::NonPositiveArraySize { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::IncompatibleDeclarations { name, previous, found, .. } => write!(
+                f,
+                "Conflicting declarations of function '{}': declared with {} parameter(s), then with {}",
+                name, previous, found
+            ),
+            TypeError::ConflictingTypes { name, .. } => write!(f, "Conflicting types for '{}'", name),
+            TypeError::Redefinition { name, .. } => write!(f, "Function '{}' is defined more than once", name),
+            TypeError::VariableRedefinition { name, .. } => write!(f, "Variable '{}' is defined more than once", name),
+            TypeError::NonConstantInitializer { name, .. } => {
+                write!(f, "Initializer of static variable '{}' is not a constant", name.source_name())
+            }
+            TypeError::ConflictingKinds { name, .. } => {
+                write!(f, "'{}' is declared both as a function and as a variable", name)
+            }
+            TypeError::ConflictingLinkage { name, .. } => {
+                write!(f, "'{}' is declared with both internal and external linkage", name)
+            }
+            TypeError::ExternInitializer { name, .. } => {
+                write!(f, "Local extern declaration of '{}' has an initializer", name)
+            }
+            TypeError::FunctionUsedAsVariable { name, .. } => write!(f, "Function '{}' used as a variable", name),
+            TypeError::VariableCalledAsFunction { name, .. } => {
+                write!(f, "Variable '{}' called as a function", name.source_name())
+            }
+            TypeError::WrongArgumentCount { name, expected, found, .. } => write!(
+                f,
+                "Function '{}' expects {} argument(s), but {} were given",
+                name, expected, found
+            ),
+            TypeError::DoubleOperand { operator, .. } => {
+                write!(f, "Operand of '{}' has type 'double', but must have an integer type", operator)
+            }
+            TypeError::PointerOperand { operator, ty, .. } => {
+                write!(f, "Operand of '{}' has type '{}', but must have an arithmetic type", operator, ty)
+            }
+            TypeError::IncompatibleOperands { operator, left, right, .. } => {
+                write!(f, "Operands of '{}' have incompatible types '{}' and '{}'", operator, left, right)
+            }
+            TypeError::IncompatibleTypes { from, to, .. } => {
+                write!(f, "Cannot convert a value of type '{}' to '{}'", from, to)
+            }
+            TypeError::InvalidCast { from, to, .. } => write!(f, "Cannot cast a value of type '{}' to '{}'", from, to),
+            TypeError::InvalidDereference { ty, .. } => {
+                write!(f, "Cannot dereference a value of type '{}', which is not a pointer", ty)
+            }
+            TypeError::NotAssignable { ty, .. } => write!(f, "Cannot assign to a value of type '{}'", ty),
+            TypeError::InvalidSubscript { left, right, .. } => {
+                write!(f, "Cannot subscript a value of type '{}' with a value of type '{}'", left, right)
+            }
+            TypeError::InvalidInitializer { ty, .. } => write!(f, "Invalid initializer for a value of type '{}'", ty),
+            TypeError::TooManyInitializers { ty, .. } => {
+                write!(f, "Too many initializers for a value of type '{}'", ty)
+            }
+            TypeError::StructRedefinition { ty, .. } => write!(f, "Structure '{}' is defined more than once", ty),
+            TypeError::DuplicateMember { member, ty, .. } => write!(f, "Duplicate member '{}' in '{}'", member, ty),
+            TypeError::IncompleteType { ty, .. } => write!(f, "Use of incomplete type '{}'", ty),
+            TypeError::NotAStructure { ty, .. } => {
+                write!(f, "Cannot select a member of a value of type '{}', which is not a structure", ty)
+            }
+            TypeError::UnknownMember { member, ty, .. } => write!(f, "'{}' has no member named '{}'", ty, member),
+            TypeError::StructByValue { name, .. } => {
+                write!(f, "Function '{}' takes or returns a structure, which is not supported", name)
+            }
+            TypeError::ScalarRequired { ty, .. } => {
+                write!(f, "A value of type '{}' is used where a scalar is required", ty)
+            }
+            TypeError::InvalidSwitch { ty, .. } => {
+                write!(f, "Cannot switch on a value of type '{}', which is not an integer", ty)
+            }
+            TypeError::NonConstantCase { .. } => write!(f, "Case label is not an integer constant"),
+            TypeError::NonConstantArraySize { .. } => write!(f, "Array size is not a constant"),
+            TypeError::NonIntegerArraySize { .. } => write!(f, "Array size is not an integer"),
+            TypeError::NonPositiveArraySize { .. } => write!(f, "Array size must be positive"),
+            TypeError::InvalidAtomic { ty, .. } => {
+                write!(f, "Cannot qualify type '{}' with '_Atomic', which is only supported for integer types", ty)
+            }
+        }
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(error: TypeError) -> Self {
+        Diagnostic::error(error.span(), error.to_string())
+    }
+}
+
+/// Checks that a resolved program uses its identifiers consistently: functions are declared
+/// with matching types, defined at most once, and called with the right number of
+/// arguments, variables with static storage are initialized at most once and only with
+/// constants, every declaration of a name agrees on its type and linkage, and variables and
+/// functions are not used in place of each other. Pointers are only dereferenced, compared,
+/// assigned, converted and offset where their types allow it, and arrays are only
+/// initialized with lists that fit them. Every structure given a member list is laid out,
+/// and members are only selected from complete structures that have them.
+///
+/// The implicit conversions of C are made explicit on the way: the checked program has a
+/// cast wherever a value changes type, so that the operands of arithmetic operators, the
+/// value stored by an assignment and the arguments and results of functions already have
+/// the type they are used at. An array used as a value is cast to a pointer to its first
+/// element, and the character types are promoted to `int` before any arithmetic. Each
+/// string literal used as a value becomes a variable of its own, named `string.` and a
+/// number, with a constant initial value.
+///
+/// # Arguments
+///
+/// * `ast` - The resolved C AST to be checked.
+///
+/// # Returns
+///
+/// * `Result<(Program, SymbolTable, StructTable), TypeError>` - The program with its
+///   conversions made explicit, the sizes of its arrays evaluated and the offsets and types
+///   of its member accesses filled in, its symbol table and the layouts of its structures,
+///   or the first error found.
+pub fn typecheck_program(ast: Program) -> Result<(Program, SymbolTable, StructTable), TypeError> {
+    let mut checker = TypeChecker { symbols: HashMap::new(), structs: HashMap::new(), return_type: Type::Int, next_string: 0, switch_types: Vec::new() };
+    let declarations = ast.declarations
+        .into_iter()
+        .map(|declaration| match declaration {
+            Declaration::Function(function) => checker.check_function_declaration(function).map(Declaration::Function),
+            Declaration::Variable(variable) => {
+                checker.check_file_scope_variable_declaration(variable).map(Declaration::Variable)
+            }
+            Declaration::Struct(declaration) => checker.check_struct_declaration(declaration).map(Declaration::Struct),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((Program { declarations }, checker.symbols, checker.structs))
+}
+
+/// Returns the type of a builtin function, which programs call without declaring it and
+/// which code generation expands in place rather than calling. There is no `void`, so
+/// `__builtin_alloca` returns a `char *`, and the builtins that never return an `int`.
+///
+/// # Arguments
+///
+/// * `name` - The name the function is called by.
+///
+/// # Returns
+///
+/// * `Option<Type>` - The function type of the builtin, or `None` if no builtin has the name.
+pub fn builtin_type(name: Name) -> Option<Type> {
+    let function = |params, ret| Some(Type::Function { params, ret: Box::new(ret) });
+    match name.as_str() {
+        "__builtin_alloca" => function(vec![Type::ULong], Type::Pointer(Box::new(Type::Char))),
+        "__builtin_expect" => function(vec![Type::Long, Type::Long], Type::Long),
+        "__builtin_unreachable" | "__builtin_trap" => function(Vec::new(), Type::Int),
+        "__builtin_abs" => function(vec![Type::Int], Type::Int),
+        _ => None,
+    }
+}
+
+/// Returns the type of an expression of a checked program. Its operands have already been
+/// converted to the types they are used at, so the type follows from the expression itself.
+/// The value of an object is never atomic, even if the object is.
+///
+/// # Arguments
+///
+/// * `exp` - The expression.
+/// * `symbols` - The symbol table of the program.
+///
+/// # Returns
+///
+/// * `Type` - The type of the value the expression produces.
+pub fn type_of(exp: &Exp, symbols: &SymbolTable) -> Type {
+    match exp {
+        Exp::Const(constant) => constant.ty(),
+        Exp::Var(..) | Exp::Dereference(..) | Exp::Subscript(..) | Exp::Member { .. } => {
+            object_type_of(exp, symbols).unqualified()
+        }
+        Exp::String(string, _) => Type::Array(Box::new(Type::Char), string.len() + 1),
+        Exp::WideString(string, _) => Type::Array(Box::new(Type::Int), string.chars().count() + 1),
+        Exp::Cast(ty, ..) => ty.clone(),
+        Exp::Assignment(left, _, _) | Exp::CompoundAssignment(_, left, _, _) | Exp::PostfixUpdate(_, left, _) => {
+            type_of(left, symbols)
+        }
+        Exp::Conditional(_, then, ..) => type_of(then, symbols),
+        Exp::Comma(_, right, _) => type_of(right, symbols),
+        Exp::SizeOf(..) | Exp::SizeOfType(..) => Type::ULong,
+        Exp::FunctionCall(name, _, _) => {
+            match builtin_type(*name).or_else(|| symbols.get(name).map(|symbol| symbol.ty.clone())) {
+                Some(Type::Function { ret, .. }) => *ret,
+                _ => Type::Int,
+            }
+        }
+        Exp::UnOp(UnaryOperator::Not, ..) => Type::Int,
+        Exp::UnOp(_, operand, _) => type_of(operand, symbols),
+        Exp::AddressOf(operand, _) => Type::Pointer(Box::new(object_type_of(operand, symbols))),
+        // The difference of two pointers counts elements
+        Exp::BinOp(BinaryOperator::Subtract, left, right, _)
+            if type_of(left, symbols).is_pointer() && type_of(right, symbols).is_pointer() =>
+        {
+            Type::Long
+        }
+        Exp::BinOp(operator, left, ..) if is_arithmetic(*operator) => type_of(left, symbols),
+        // Comparisons and logical operators produce 0 or 1
+        Exp::BinOp(..) => Type::Int,
+    }
+}
+
+/// Returns the type of the object an expression of a checked program designates, which
+/// unlike the type of its value keeps any `_Atomic` qualifier. An expression that does not
+/// designate an object has the type of its value.
+///
+/// # Arguments
+///
+/// * `exp` - The expression.
+/// * `symbols` - The symbol table of the program.
+///
+/// # Returns
+///
+/// * `Type` - The type of the object.
+pub fn object_type_of(exp: &Exp, symbols: &SymbolTable) -> Type {
+    match exp {
+        Exp::Var(name, _) => symbols.get(name).map_or(Type::Int, |symbol| symbol.ty.clone()),
+        Exp::Dereference(ptr, _) | Exp::Subscript(ptr, ..) => match type_of(ptr, symbols) {
+            Type::Pointer(referenced) => *referenced,
+            _ => Type::Int,
+        },
+        Exp::Member { ty, .. } => ty.clone(),
+        _ => type_of(exp, symbols),
+    }
+}
+
+/// Checks whether a binary operator computes a value of its operands' type, rather than
+/// producing a truth value.
+///
+/// # Arguments
+///
+/// * `operator` - The operator.
+///
+/// # Returns
+///
+/// * `bool` - `true` for the arithmetic, bitwise and shift operators.
+pub fn is_arithmetic(operator: BinaryOperator) -> bool {
+    matches!(
+        operator,
+        BinaryOperator::Add
+            | BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Remainder
+            | BinaryOperator::BitwiseAnd
+            | BinaryOperator::BitwiseOr
+            | BinaryOperator::BitwiseXor
+            | BinaryOperator::LeftShift
+            | BinaryOperator::RightShift
+    )
+}
+
+/// Checks whether a binary operator only applies to integers.
+///
+/// # Arguments
+///
+/// * `operator` - The operator.
+///
+/// # Returns
+///
+/// * `bool` - `true` for `%`, the bitwise operators and the shifts.
+fn takes_integers(operator: BinaryOperator) -> bool {
+    matches!(
+        operator,
+        BinaryOperator::Remainder
+            | BinaryOperator::BitwiseAnd
+            | BinaryOperator::BitwiseOr
+            | BinaryOperator::BitwiseXor
+            | BinaryOperator::LeftShift
+            | BinaryOperator::RightShift
+    )
+}
+
+/// Returns the type two operands are converted to before an arithmetic operation, following
+/// the usual arithmetic conversions: `double` if either is, otherwise the wider of the two
+/// after promoting the character types to `int`, or the unsigned one if they have the same
+/// size.
+///
+/// # Arguments
+///
+/// * `left` - The type of one operand.
+/// * `right` - The type of the other.
+///
+/// # Returns
+///
+/// * `Type` - The common type.
+pub fn common_type(left: &Type, right: &Type) -> Type {
+    let (left, right) = (&left.promoted(), &right.promoted());
+    if left == right {
+        left.clone()
+    } else if *left == Type::Double || *right == Type::Double {
+        Type::Double
+    } else if left.size() == right.size() {
+        if left.is_signed() { right.clone() } else { left.clone() }
+    } else if left.size() > right.size() {
+        left.clone()
+    } else {
+        right.clone()
+    }
+}
+
+/// Checks whether an expression is a null pointer constant: an integer constant equal to zero,
+/// which converts to a pointer of any type.
+///
+/// # Arguments
+///
+/// * `exp` - The expression.
+///
+/// # Returns
+///
+/// * `bool` - `true` for a constant such as `0` or `0UL`.
+fn is_null_pointer_constant(exp: &Exp) -> bool {
+    matches!(exp, Exp::Const(constant) if !matches!(constant, Constant::Double(_)) && constant.is_zero())
+}
+
+/// State shared while checking a program.
+struct TypeChecker {
+    symbols: SymbolTable,
+    /// The layouts of the structures given a member list so far.
+    structs: StructTable,
+    /// The return type of the function being checked, which `return` converts its value to.
+    return_type: Type,
+    /// The number of string literals turned into variables so far.
+    next_string: usize,
+    /// The promoted types of the values compared by the `switch` statements enclosing the
+    /// statement being checked, innermost last.
+    switch_types: Vec<Type>,
+}
+
+impl TypeChecker {
+    /// Records a function declaration, checking it against earlier declarations of the same
+    /// name, then checks its body. A function without `static` takes the linkage of an
+    /// earlier declaration, if there is one.
+    fn check_function_declaration(&mut self, function: FunDecl) -> Result<FunDecl, TypeError> {
+        let function = FunDecl { ty: self.evaluate_type(function.ty, function.span)?, ..function };
+        let Type::Function { params: param_types, ret } = &function.ty else {
+            unreachable!("the parser gives every function a function type")
+        };
+        self.check_type(&function.ty, function.span)?;
+        if param_types.iter().chain([&**ret]).any(|ty| matches!(ty, Type::Struct(_))) {
+            return Err(TypeError::StructByValue { name: function.name, span: function.span });
+        }
+        let has_body = function.body.is_some();
+        let mut already_defined = false;
+        let mut global = function.storage_class != Some(StorageClass::Static);
+        if let Some(previous) = self.symbols.get(&function.name) {
+            match &previous.ty {
+                Type::Function { params, .. } if params.len() != function.params.len() => {
+                    return Err(TypeError::IncompatibleDeclarations {
+                        name: function.name,
+                        previous: params.len(),
+                        found: function.params.len(),
+                        span: function.span,
+                    });
+                }
+                ty @ Type::Function { .. } if *ty != function.ty => {
+                    return Err(TypeError::ConflictingTypes { name: function.name, span: function.span });
+                }
+                Type::Function { .. } => {}
+                // Local variables have been renamed, so this is a file-scope variable
+                _ => {
+                    return Err(TypeError::ConflictingKinds { name: function.name, span: function.span });
+                }
+            }
+            if previous.defined && has_body {
+                return Err(TypeError::Redefinition { name: function.name, span: function.span });
+            }
+            if previous.global && !global {
+                return Err(TypeError::ConflictingLinkage { name: function.name, span: function.span });
+            }
+            already_defined = previous.defined;
+            global = previous.global;
+        }
+        let symbol = Symbol { ty: function.ty.clone(), defined: already_defined || has_body, initial_value: None, global };
+        self.symbols.insert(function.name, symbol);
+
+        let body = match function.body {
+            Some(body) => {
+                for (param, ty) in function.params.iter().zip(param_types) {
+                    let symbol = Symbol { ty: ty.clone(), defined: true, initial_value: None, global: false };
+                    self.symbols.insert(*param, symbol);
+                }
+                self.return_type = (**ret).clone();
+                Some(body.into_iter().map(|item| self.check_block_item(item)).collect::<Result<Vec<_>, _>>()?)
+            }
+            None => None,
+        };
+        Ok(FunDecl { body, ..function })
+    }
+
+    /// Records a file-scope variable, merging its initial value and linkage with those of
+    /// earlier declarations of the same name. An `extern` declaration takes the linkage of an
+    /// earlier one; any other declaration must agree with it.
+    fn check_file_scope_variable_declaration(&mut self, declaration: VarDecl) -> Result<VarDecl, TypeError> {
+        let declaration = VarDecl { ty: self.evaluate_type(declaration.ty, declaration.span)?, ..declaration };
+        self.check_object_type(&declaration.ty, declaration.span)?;
+        let mut initial_value = match &declaration.init {
+            Some(init) => InitialValue::Initial(self.static_initializer(&declaration, init)?),
+            None if declaration.storage_class == Some(StorageClass::Extern) => InitialValue::NoInitializer,
+            None => InitialValue::Tentative,
+        };
+        let mut global = declaration.storage_class != Some(StorageClass::Static);
+        if let Some(previous) = self.symbols.get(&declaration.name) {
+            let Some(previous_value) = previous.initial_value.clone() else {
+                return Err(TypeError::ConflictingKinds { name: declaration.name, span: declaration.span });
+            };
+            if previous.ty != declaration.ty {
+                return Err(TypeError::ConflictingTypes { name: declaration.name, span: declaration.span });
+            }
+            if declaration.storage_class == Some(StorageClass::Extern) {
+                global = previous.global;
+            } else if previous.global != global {
+                return Err(TypeError::ConflictingLinkage { name: declaration.name, span: declaration.span });
+            }
+            match (&previous_value, &initial_value) {
+                (InitialValue::Initial(_), InitialValue::Initial(_)) => {
+                    return Err(TypeError::VariableRedefinition { name: declaration.name, span: declaration.span });
+                }
+                (InitialValue::Initial(_), _) | (InitialValue::Tentative, InitialValue::NoInitializer) => {
+                    initial_value = previous_value;
+                }
+                _ => {}
+            }
+        }
+        let defined = matches!(initial_value, InitialValue::Initial(_));
+        let symbol = Symbol { ty: declaration.ty.clone(), defined, initial_value: Some(initial_value), global };
+        self.symbols.insert(declaration.name, symbol);
+        Ok(declaration)
+    }
+
+    /// Records a local `extern` declaration, which refers to a variable with static storage
+    /// defined at file scope or in another file.
+    fn check_local_extern_declaration(&mut self, declaration: &VarDecl) -> Result<(), TypeError> {
+        if declaration.init.is_some() {
+            return Err(TypeError::ExternInitializer { name: declaration.name, span: declaration.span });
+        }
+        match self.symbols.get(&declaration.name) {
+            Some(Symbol { initial_value: None, .. }) => {
+                Err(TypeError::ConflictingKinds { name: declaration.name, span: declaration.span })
+            }
+            Some(previous) if previous.ty != declaration.ty => {
+                Err(TypeError::ConflictingTypes { name: declaration.name, span: declaration.span })
+            }
+            Some(_) => Ok(()),
+            None => {
+                let symbol = Symbol {
+                    ty: declaration.ty.clone(),
+                    defined: false,
+                    initial_value: Some(InitialValue::NoInitializer),
+                    global: true,
+                };
+                self.symbols.insert(declaration.name, symbol);
+                Ok(())
+            }
+        }
+    }
+
+    /// Lays out a structure given a member list: each member is placed at the first offset
+    /// after the one before it that suits its alignment. A declaration without a member
+    /// list needs no checking, as the resolver already declared its tag.
+    fn check_struct_declaration(&mut self, declaration: StructDecl) -> Result<StructDecl, TypeError> {
+        let Some(members) = declaration.members else { return Ok(declaration) };
+        let ty = Type::Struct(declaration.tag);
+        if self.structs.contains_key(&declaration.tag) {
+            return Err(TypeError::StructRedefinition { ty, span: declaration.span });
+        }
+        let members = members
+            .into_iter()
+            .map(|member| Ok(MemberDecl { ty: self.evaluate_type(member.ty, member.span)?, ..member }))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut layout = StructDef { alignment: 1, size: 0, members: Vec::new() };
+        for member in &members {
+            if layout.members.iter().any(|previous| previous.name == member.name) {
+                return Err(TypeError::DuplicateMember { member: member.name, ty, span: member.span });
+            }
+            // The structure itself is still incomplete, so it cannot contain itself
+            self.check_object_type(&member.ty, member.span)?;
+            let alignment = member.ty.alignment_in(&self.structs);
+            let offset = layout.size.next_multiple_of(alignment);
+            layout.size = offset + member.ty.size_in(&self.structs);
+            layout.alignment = layout.alignment.max(alignment);
+            layout.members.push(MemberDef { name: member.name, ty: member.ty.clone(), offset });
+        }
+        layout.size = layout.size.next_multiple_of(layout.alignment);
+        self.structs.insert(declaration.tag, layout);
+        Ok(StructDecl { members: Some(members), ..declaration })
+    }
+
+    /// Evaluates the sizes of the arrays a type is built from, giving the type the later
+    /// phases see. Each size must be a positive integer constant, and only integer types
+    /// may be atomic. A parameter declared as an array is a pointer to its first element,
+    /// and the `_Atomic` qualifiers of the parameters and the result of a function are not
+    /// part of its type.
+    ///
+    /// # Arguments
+    ///
+    /// * `ty` - The type as the declaration or type name wrote it.
+    /// * `span` - Where an invalid size is reported.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Type, TypeError>` - The type with every array sized, or an error if a size
+    ///   is invalid.
+    fn evaluate_type(&mut self, ty: Type, span: Span) -> Result<Type, TypeError> {
+        match ty {
+            Type::Pointer(referenced) => Ok(Type::Pointer(Box::new(self.evaluate_type(*referenced, span)?))),
+            Type::Atomic(ty) => match self.evaluate_type(*ty, span)? {
+                ty if ty.is_integer() => Ok(Type::Atomic(Box::new(ty))),
+                ty => Err(TypeError::InvalidAtomic { ty, span }),
+            },
+            Type::DeclaredArray(element, size) => {
+                let element = self.evaluate_type(*element, span)?;
+                let size = self.check_exp(*size)?;
+                let count = evaluate_constant(&size).ok_or(TypeError::NonConstantArraySize { span })?;
+                if !self.type_of(&size).is_integer() {
+                    return Err(TypeError::NonIntegerArraySize { span });
+                }
+                if count.is_zero() || (count.ty().is_signed() && count.as_i64() < 0) {
+                    return Err(TypeError::NonPositiveArraySize { span });
+                }
+                Ok(Type::Array(Box::new(element), count.as_i64() as usize))
+            }
+            Type::Function { params, ret } => {
+                let params = params
+                    .into_iter()
+                    .map(|param| match self.evaluate_type(param, span)? {
+                        Type::Array(element, _) => Ok(Type::Pointer(element)),
+                        param => Ok(param.unqualified()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Type::Function { params, ret: Box::new(self.evaluate_type(*ret, span)?.unqualified()) })
+            }
+            ty => Ok(ty),
+        }
+    }
+
+    /// Checks that the element types of the arrays a type is built from, even behind a
+    /// pointer, are complete.
+    fn check_type(&self, ty: &Type, span: Span) -> Result<(), TypeError> {
+        match ty {
+            Type::Array(element, _) if element.is_incomplete(&self.structs) => {
+                Err(TypeError::IncompleteType { ty: (**element).clone(), span })
+            }
+            Type::Array(element, _) | Type::Pointer(element) => self.check_type(element, span),
+            Type::Function { params, ret } => {
+                params.iter().chain([&**ret]).try_for_each(|ty| self.check_type(ty, span))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the type of a variable or member, which must be complete so that its size is
+    /// known.
+    fn check_object_type(&self, ty: &Type, span: Span) -> Result<(), TypeError> {
+        if ty.is_incomplete(&self.structs) {
+            return Err(TypeError::IncompleteType { ty: ty.clone(), span });
+        }
+        self.check_type(ty, span)
+    }
+
+    /// Checks a declaration or statement in a function body.
+    fn check_block_item(&mut self, item: BlockItem) -> Result<BlockItem, TypeError> {
+        match item {
+            BlockItem::Declaration(Declaration::Variable(declaration)) => {
+                let declaration = VarDecl { ty: self.evaluate_type(declaration.ty, declaration.span)?, ..declaration };
+                self.check_object_type(&declaration.ty, declaration.span)?;
+                let declaration = match declaration.storage_class {
+                    Some(StorageClass::Extern) => {
+                        self.check_local_extern_declaration(&declaration)?;
+                        declaration
+                    }
+                    Some(StorageClass::Static) => {
+                        let value = match &declaration.init {
+                            Some(init) => self.static_initializer(&declaration, init)?,
+                            None => vec![StaticInit::Zero(declaration.ty.size_in(&self.structs))],
+                        };
+                        let symbol = Symbol {
+                            ty: declaration.ty.clone(),
+                            defined: true,
+                            initial_value: Some(InitialValue::Initial(value)),
+                            global: false,
+                        };
+                        self.symbols.insert(declaration.name, symbol);
+                        declaration
+                    }
+                    None => self.check_variable_declaration(declaration)?,
+                };
+                Ok(BlockItem::Declaration(Declaration::Variable(declaration)))
+            }
+            BlockItem::Declaration(Declaration::Function(function)) => {
+                Ok(BlockItem::Declaration(Declaration::Function(self.check_function_declaration(function)?)))
+            }
+            BlockItem::Declaration(Declaration::Struct(declaration)) => {
+                Ok(BlockItem::Declaration(Declaration::Struct(self.check_struct_declaration(declaration)?)))
+            }
+            BlockItem::Statement(statement) => Ok(BlockItem::Statement(self.check_statement(statement)?)),
+        }
+    }
+
+    /// Records a local variable and checks its initializer, converting it to the variable's type.
+    fn check_variable_declaration(&mut self, declaration: VarDecl) -> Result<VarDecl, TypeError> {
+        let symbol = Symbol { ty: declaration.ty.clone(), defined: true, initial_value: None, global: false };
+        self.symbols.insert(declaration.name, symbol);
+        let init = match declaration.init {
+            Some(init) => Some(self.check_initializer(init, &declaration.ty, declaration.span)?),
+            None => None,
+        };
+        Ok(VarDecl { init, ..declaration })
+    }
+
+    /// Checks the initializer of an object of the given type, converting each value to the
+    /// type of the scalar it initializes. A list may leave out trailing elements of an array
+    /// or members of a structure, but not have more. Conversion errors get the position of
+    /// the declaration.
+    fn check_initializer(&mut self, init: Initializer, ty: &Type, span: Span) -> Result<Initializer, TypeError> {
+        let ty = &ty.unqualified();
+        match (init, ty) {
+            // A character array is copied from a string literal, which may leave out the
+            // null byte if the array has no room for it
+            (Initializer::Single(Exp::String(string, string_span)), Type::Array(element, count)) if element.is_character() => {
+                if string.len() > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: string_span });
+                }
+                Ok(Initializer::Single(Exp::String(string, string_span)))
+            }
+            // Likewise a wchar_t array from a wide string literal
+            (Initializer::Single(Exp::WideString(string, string_span)), Type::Array(element, count)) if **element == Type::Int => {
+                if string.chars().count() > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: string_span });
+                }
+                Ok(Initializer::Single(Exp::WideString(string, string_span)))
+            }
+            (Initializer::Single(_), Type::Array(..)) => Err(TypeError::InvalidInitializer { ty: ty.clone(), span }),
+            (Initializer::Single(exp), _) => {
+                let exp = self.check_exp(exp)?;
+                Ok(Initializer::Single(self.convert_by_assignment(exp, ty, span)?))
+            }
+            (Initializer::Compound(inits, brace), Type::Array(element, count)) => {
+                if inits.len() > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: brace });
+                }
+                let inits = inits
+                    .into_iter()
+                    .map(|init| self.check_initializer(init, element, span))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Initializer::Compound(inits, brace))
+            }
+            (Initializer::Compound(inits, brace), Type::Struct(tag)) => {
+                let members = self.structs[tag].members.clone();
+                if inits.len() > members.len() {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: brace });
+                }
+                let inits = inits
+                    .into_iter()
+                    .zip(&members)
+                    .map(|(init, member)| self.check_initializer(init, &member.ty, span))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Initializer::Compound(inits, brace))
+            }
+            (Initializer::Compound(_, brace), _) => Err(TypeError::InvalidInitializer { ty: ty.clone(), span: brace }),
+        }
+    }
+
+    /// Checks the expressions and sub-statements of a statement.
+    fn check_statement(&mut self, statement: Statement) -> Result<Statement, TypeError> {
+        match statement {
+            Statement::Return(exp, span) => {
+                let exp = self.check_exp(exp)?;
+                Ok(Statement::Return(self.convert_by_assignment(exp, &self.return_type.clone(), span)?, span))
+            }
+            Statement::Expression(exp) => Ok(Statement::Expression(self.check_exp(exp)?)),
+            Statement::If(condition, then, otherwise) => Ok(Statement::If(
+                self.check_condition(condition)?,
+                Box::new(self.check_statement(*then)?),
+                otherwise.map(|otherwise| self.check_statement(*otherwise).map(Box::new)).transpose()?,
+            )),
+            Statement::While { condition, body, label } => Ok(Statement::While {
+                condition: self.check_condition(condition)?,
+                body: Box::new(self.check_statement(*body)?),
+                label,
+            }),
+            Statement::DoWhile { body, condition, label } => Ok(Statement::DoWhile {
+                body: Box::new(self.check_statement(*body)?),
+                condition: self.check_condition(condition)?,
+                label,
+            }),
+            Statement::For { init, condition, post, body, label } => {
+                let init = match *init {
+                    ForInit::Declaration(declaration) => {
+                        let declaration = VarDecl { ty: self.evaluate_type(declaration.ty, declaration.span)?, ..declaration };
+                        self.check_object_type(&declaration.ty, declaration.span)?;
+                        ForInit::Declaration(self.check_variable_declaration(declaration)?)
+                    }
+                    ForInit::Expression(exp) => ForInit::Expression(self.check_optional_exp(exp)?),
+                };
+                Ok(Statement::For {
+                    init: Box::new(init),
+                    condition: condition.map(|condition| self.check_condition(condition)).transpose()?,
+                    post: self.check_optional_exp(post)?,
+                    body: Box::new(self.check_statement(*body)?),
+                    label,
+                })
+            }
+            Statement::Switch { condition, body, cases, label, span } => {
+                let condition = self.check_exp(condition)?;
+                let ty = self.type_of(&condition);
+                if !ty.is_integer() {
+                    return Err(TypeError::InvalidSwitch { ty, span });
+                }
+                let condition = self.promote(condition, span);
+                self.switch_types.push(ty.promoted());
+                let body = self.check_statement(*body);
+                self.switch_types.pop();
+                Ok(Statement::Switch { condition, body: Box::new(body?), cases, label, span })
+            }
+            // The value is compared with that of the enclosing switch, so it is folded now into
+            // a constant of the same type. A case outside of any switch is left for the
+            // labeling pass to report
+            Statement::Case { value, body, label, span } => {
+                let value = self.check_exp(value)?;
+                let constant = evaluate_constant(&value)
+                    .filter(|_| self.type_of(&value).is_integer())
+                    .ok_or(TypeError::NonConstantCase { span })?;
+                let constant = match self.switch_types.last() {
+                    Some(ty) => constant.convert_to(ty),
+                    None => constant,
+                };
+                Ok(Statement::Case { value: Exp::Const(constant), body: Box::new(self.check_statement(*body)?), label, span })
+            }
+            Statement::Default { body, label, span } => {
+                Ok(Statement::Default { body: Box::new(self.check_statement(*body)?), label, span })
+            }
+            Statement::Labeled(label, body, span) => Ok(Statement::Labeled(label, Box::new(self.check_statement(*body)?), span)),
+            Statement::Compound(items) => {
+                Ok(Statement::Compound(items.into_iter().map(|item| self.check_block_item(item)).collect::<Result<_, _>>()?))
+            }
+            statement @ (Statement::Goto(..) | Statement::Break(..) | Statement::Continue(..) | Statement::Null) => Ok(statement),
+        }
+    }
+
+    /// Checks an optional expression, such as a clause of a `for` loop header.
+    fn check_optional_exp(&mut self, exp: Option<Exp>) -> Result<Option<Exp>, TypeError> {
+        exp.map(|exp| self.check_exp(exp)).transpose()
+    }
+
+    /// Checks an expression whose value is only compared with zero, such as the condition of
+    /// a statement, which must be a scalar.
+    fn check_condition(&mut self, exp: Exp) -> Result<Exp, TypeError> {
+        let exp = self.check_exp(exp)?;
+        match self.type_of(&exp) {
+            ty if ty.is_scalar() => Ok(exp),
+            ty => Err(TypeError::ScalarRequired { ty, span: exp.span() }),
+        }
+    }
+
+    /// Checks that the identifiers in an expression are used according to their types, and
+    /// converts operands to the types they are used at. An array is cast to a pointer to its
+    /// first element, as it is everywhere but as the operand of `&`.
+    fn check_exp(&mut self, exp: Exp) -> Result<Exp, TypeError> {
+        let exp = self.check_exp_keeping_arrays(exp)?;
+        match self.type_of(&exp) {
+            Type::Array(element, _) => {
+                let span = exp.span();
+                Ok(Exp::Cast(Type::Pointer(element), Box::new(exp), span))
+            }
+            _ => Ok(exp),
+        }
+    }
+
+    /// Checks an expression like `check_exp`, but leaves a value of array type as it is, for
+    /// the operand of `&` and the objects assignments store to.
+    fn check_exp_keeping_arrays(&mut self, exp: Exp) -> Result<Exp, TypeError> {
+        match exp {
+            Exp::Const(_) => Ok(exp),
+            Exp::String(string, span) => Ok(Exp::Var(self.string_constant(string), span)),
+            Exp::WideString(string, span) => Ok(Exp::Var(self.wide_string_constant(string), span)),
+            Exp::Var(ref name, span) => match self.symbols.get(name) {
+                Some(Symbol { ty: Type::Function { .. }, .. }) => {
+                    Err(TypeError::FunctionUsedAsVariable { name: *name, span })
+                }
+                _ => Ok(exp),
+            },
+            // The operand is only checked for its type, and is never evaluated
+            Exp::SizeOf(operand, span) => {
+                let operand = self.check_exp_keeping_arrays(*operand)?;
+                let ty = self.type_of(&operand);
+                self.check_object_type(&ty, span)?;
+                Ok(Exp::Const(Constant::ULong(ty.size_in(&self.structs) as u64)))
+            }
+            Exp::SizeOfType(ty, span) => {
+                let ty = self.evaluate_type(ty, span)?;
+                self.check_object_type(&ty, span)?;
+                Ok(Exp::Const(Constant::ULong(ty.size_in(&self.structs) as u64)))
+            }
+            // A cast to an atomic type gives a value of the unqualified type
+            Exp::Cast(ty, operand, span) => {
+                let ty = self.evaluate_type(ty, span)?.unqualified();
+                let operand = self.check_exp(*operand)?;
+                let from = self.type_of(&operand);
+                self.check_type(&ty, span)?;
+                if (from.is_pointer() && ty == Type::Double) || (from == Type::Double && ty.is_pointer()) || !ty.is_scalar() || !from.is_scalar() {
+                    return Err(TypeError::InvalidCast { from, to: ty, span });
+                }
+                Ok(Exp::Cast(ty, Box::new(operand), span))
+            }
+            // A builtin keeps its own type even if the program declares the name
+            Exp::FunctionCall(name, args, span) => {
+                let ty = builtin_type(name).or_else(|| self.symbols.get(&name).map(|symbol| symbol.ty.clone()));
+                let param_types = match ty {
+                    Some(Type::Function { params, .. }) if params.len() != args.len() => {
+                        return Err(TypeError::WrongArgumentCount {
+                            name,
+                            expected: params.len(),
+                            found: args.len(),
+                            span,
+                        });
+                    }
+                    Some(Type::Function { params, .. }) => params,
+                    _ => return Err(TypeError::VariableCalledAsFunction { name, span }),
+                };
+                let args = args
+                    .into_iter()
+                    .zip(&param_types)
+                    .map(|(arg, ty)| {
+                        let arg = self.check_exp(arg)?;
+                        self.convert_by_assignment(arg, ty, span)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Exp::FunctionCall(name, args, span))
+            }
+            Exp::Assignment(left, right, span) => {
+                let left = self.check_assignable(*left, span)?;
+                let right = self.check_exp(*right)?;
+                let right = self.convert_by_assignment(right, &self.type_of(&left), span)?;
+                Ok(Exp::Assignment(Box::new(left), Box::new(right), span))
+            }
+            Exp::CompoundAssignment(operator, left, right, span) => {
+                // The operation is carried out in the common type, or the left type for a
+                // shift, and its result converted back to the type of the left side
+                let left = self.check_assignable(*left, span)?;
+                let mut right = self.check_exp(*right)?;
+                let symbol = format!("{}=", binary_symbol(operator));
+                if takes_integers(operator) && self.has_double(&left, &right) {
+                    return Err(TypeError::DoubleOperand { operator: symbol, span });
+                }
+                // A pointer moves by a number of elements
+                if self.type_of(&left).is_pointer() && matches!(operator, BinaryOperator::Add | BinaryOperator::Subtract) {
+                    let right = self.convert_offset(&symbol, &left, right, span)?;
+                    return Ok(Exp::CompoundAssignment(operator, Box::new(left), Box::new(right), span));
+                }
+                self.check_arithmetic_operands(&symbol, &[&left, &right], span)?;
+                if matches!(operator, BinaryOperator::LeftShift | BinaryOperator::RightShift) {
+                    right = self.promote(right, span);
+                } else {
+                    let ty = common_type(&self.type_of(&left), &self.type_of(&right));
+                    right = self.convert(right, &ty, span);
+                }
+                Ok(Exp::CompoundAssignment(operator, Box::new(left), Box::new(right), span))
+            }
+            // Any scalar can be incremented, a pointer by one element
+            Exp::PostfixUpdate(operator, operand, span) => {
+                let operand = self.check_assignable(*operand, span)?;
+                match self.type_of(&operand) {
+                    ty if !ty.is_scalar() => return Err(TypeError::ScalarRequired { ty, span }),
+                    ty => self.check_complete_referenced(&ty, span)?,
+                }
+                Ok(Exp::PostfixUpdate(operator, Box::new(operand), span))
+            }
+            Exp::Conditional(condition, then, otherwise, span) => {
+                let condition = self.check_condition(*condition)?;
+                let then = self.check_exp(*then)?;
+                let otherwise = self.check_exp(*otherwise)?;
+                let (then_type, otherwise_type) = (self.type_of(&then), self.type_of(&otherwise));
+                let ty = if then_type.is_pointer() || otherwise_type.is_pointer() {
+                    self.common_pointer_type("?:", &then, &otherwise, span)?
+                } else if then_type.is_arithmetic() && otherwise_type.is_arithmetic() {
+                    common_type(&then_type, &otherwise_type)
+                } else if then_type == otherwise_type {
+                    // Two structures of the same type
+                    then_type
+                } else {
+                    return Err(TypeError::IncompatibleOperands { operator: "?:".to_string(), left: then_type, right: otherwise_type, span });
+                };
+                let then = self.convert(then, &ty, span);
+                let otherwise = self.convert(otherwise, &ty, span);
+                Ok(Exp::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise), span))
+            }
+            // Neither operand is an lvalue, so an array on either side decays
+            Exp::Comma(left, right, span) => {
+                let left = self.check_exp(*left)?;
+                let right = self.check_exp(*right)?;
+                Ok(Exp::Comma(Box::new(left), Box::new(right), span))
+            }
+            Exp::UnOp(operator, operand, span) => {
+                // Any scalar can be compared with zero
+                if operator == UnaryOperator::Not {
+                    return Ok(Exp::UnOp(operator, Box::new(self.check_condition(*operand)?), span));
+                }
+                let operand = self.check_exp(*operand)?;
+                let symbol = if operator == UnaryOperator::Negate { "-" } else { "~" };
+                if operator == UnaryOperator::Complement && self.type_of(&operand) == Type::Double {
+                    return Err(TypeError::DoubleOperand { operator: symbol.to_string(), span });
+                }
+                self.check_arithmetic_operands(symbol, &[&operand], span)?;
+                let operand = self.promote(operand, span);
+                Ok(Exp::UnOp(operator, Box::new(operand), span))
+            }
+            Exp::Dereference(operand, span) => {
+                let operand = self.check_exp(*operand)?;
+                match self.type_of(&operand) {
+                    Type::Pointer(referenced) if referenced.is_incomplete(&self.structs) => {
+                        Err(TypeError::IncompleteType { ty: *referenced, span })
+                    }
+                    Type::Pointer(_) => Ok(Exp::Dereference(Box::new(operand), span)),
+                    ty => Err(TypeError::InvalidDereference { ty, span }),
+                }
+            }
+            Exp::AddressOf(operand, span) => {
+                Ok(Exp::AddressOf(Box::new(self.check_exp_keeping_arrays(*operand)?), span))
+            }
+            Exp::Subscript(left, right, span) => {
+                let left = self.check_exp(*left)?;
+                let right = self.check_exp(*right)?;
+                let (left_type, right_type) = (self.type_of(&left), self.type_of(&right));
+                let (ptr, index) = if left_type.is_pointer() && right_type.is_integer() {
+                    (left, right)
+                } else if left_type.is_integer() && right_type.is_pointer() {
+                    (right, left)
+                } else {
+                    return Err(TypeError::InvalidSubscript { left: left_type, right: right_type, span });
+                };
+                self.check_complete_referenced(&self.type_of(&ptr), span)?;
+                let index = self.convert(index, &Type::Long, span);
+                Ok(Exp::Subscript(Box::new(ptr), Box::new(index), span))
+            }
+            Exp::Member { base, member, span, .. } => {
+                let base = self.check_exp(*base)?;
+                let ty = self.type_of(&base);
+                let Type::Struct(tag) = &ty else {
+                    return Err(TypeError::NotAStructure { ty, span });
+                };
+                let Some(layout) = self.structs.get(tag) else {
+                    return Err(TypeError::IncompleteType { ty, span });
+                };
+                let Some(found) = layout.members.iter().find(|candidate| candidate.name == member) else {
+                    return Err(TypeError::UnknownMember { member, ty, span });
+                };
+                let (offset, member_type) = (found.offset, found.ty.clone());
+                Ok(Exp::Member { base: Box::new(base), member, offset, ty: member_type, span })
+            }
+            // Each operand of `&&` and `||` is only compared with zero
+            Exp::BinOp(operator @ (BinaryOperator::And | BinaryOperator::Or), left, right, span) => {
+                let left = self.check_condition(*left)?;
+                let right = self.check_condition(*right)?;
+                Ok(Exp::BinOp(operator, Box::new(left), Box::new(right), span))
+            }
+            Exp::BinOp(operator, left, right, span) => {
+                let left = self.check_exp(*left)?;
+                let right = self.check_exp(*right)?;
+                if takes_integers(operator) && self.has_double(&left, &right) {
+                    return Err(TypeError::DoubleOperand { operator: binary_symbol(operator).to_string(), span });
+                }
+                let has_pointer = self.type_of(&left).is_pointer() || self.type_of(&right).is_pointer();
+                let ty = match operator {
+                    BinaryOperator::Add | BinaryOperator::Subtract if has_pointer => {
+                        return self.check_pointer_arithmetic(operator, left, right, span);
+                    }
+                    // Pointers compare for equality with pointers of the same type and with null
+                    BinaryOperator::Equal | BinaryOperator::NotEqual if has_pointer => {
+                        self.common_pointer_type(binary_symbol(operator), &left, &right, span)?
+                    }
+                    // and are ordered only against pointers of the same type
+                    BinaryOperator::LessThan
+                    | BinaryOperator::LessOrEqual
+                    | BinaryOperator::GreaterThan
+                    | BinaryOperator::GreaterOrEqual
+                        if has_pointer =>
+                    {
+                        let (left_type, right_type) = (self.type_of(&left), self.type_of(&right));
+                        if left_type != right_type {
+                            let operator = binary_symbol(operator).to_string();
+                            return Err(TypeError::IncompatibleOperands { operator, left: left_type, right: right_type, span });
+                        }
+                        left_type
+                    }
+                    _ => {
+                        self.check_arithmetic_operands(binary_symbol(operator), &[&left, &right], span)?;
+                        // A shift has the type of its promoted left operand
+                        if matches!(operator, BinaryOperator::LeftShift | BinaryOperator::RightShift) {
+                            let (left, right) = (self.promote(left, span), self.promote(right, span));
+                            return Ok(Exp::BinOp(operator, Box::new(left), Box::new(right), span));
+                        }
+                        common_type(&self.type_of(&left), &self.type_of(&right))
+                    }
+                };
+                let left = self.convert(left, &ty, span);
+                let right = self.convert(right, &ty, span);
+                Ok(Exp::BinOp(operator, Box::new(left), Box::new(right), span))
+            }
+        }
+    }
+
+    /// Checks the object an assignment or update stores to, which cannot be an array.
+    fn check_assignable(&mut self, exp: Exp, span: Span) -> Result<Exp, TypeError> {
+        let exp = self.check_exp_keeping_arrays(exp)?;
+        match self.type_of(&exp) {
+            ty @ Type::Array(..) => Err(TypeError::NotAssignable { ty, span }),
+            _ => Ok(exp),
+        }
+    }
+
+    /// Checks `+` or `-` with a pointer operand. A pointer is offset by an integer, which
+    /// is converted to `long` and put second, and two pointers of the same type are
+    /// subtracted to count the elements between them.
+    fn check_pointer_arithmetic(&self, operator: BinaryOperator, left: Exp, right: Exp, span: Span) -> Result<Exp, TypeError> {
+        let (left_type, right_type) = (self.type_of(&left), self.type_of(&right));
+        let symbol = binary_symbol(operator);
+        let (left, right) = if left_type.is_pointer() && right_type.is_pointer() && operator == BinaryOperator::Subtract {
+            if left_type != right_type {
+                return Err(TypeError::IncompatibleOperands { operator: symbol.to_string(), left: left_type, right: right_type, span });
+            }
+            self.check_complete_referenced(&left_type, span)?;
+            (left, right)
+        } else if left_type.is_pointer() {
+            let right = self.convert_offset(symbol, &left, right, span)?;
+            (left, right)
+        } else if operator == BinaryOperator::Add && left_type.is_integer() {
+            let left = self.convert_offset(symbol, &right, left, span)?;
+            (right, left)
+        } else {
+            return Err(TypeError::IncompatibleOperands { operator: symbol.to_string(), left: left_type, right: right_type, span });
+        };
+        Ok(Exp::BinOp(operator, Box::new(left), Box::new(right), span))
+    }
+
+    /// Converts the number of elements a pointer is moved by to `long`, after checking that
+    /// it is an integer.
+    fn convert_offset(&self, operator: &str, ptr: &Exp, offset: Exp, span: Span) -> Result<Exp, TypeError> {
+        let offset_type = self.type_of(&offset);
+        if !offset_type.is_integer() {
+            return Err(TypeError::IncompatibleOperands { operator: operator.to_string(), left: self.type_of(ptr), right: offset_type, span });
+        }
+        self.check_complete_referenced(&self.type_of(ptr), span)?;
+        Ok(self.convert(offset, &Type::Long, span))
+    }
+
+    /// Checks that a pointer that is moved or subscripted points to a complete type, whose
+    /// size is the distance between elements. Other types pass.
+    fn check_complete_referenced(&self, ty: &Type, span: Span) -> Result<(), TypeError> {
+        match ty {
+            Type::Pointer(referenced) if referenced.is_incomplete(&self.structs) => {
+                Err(TypeError::IncompleteType { ty: (**referenced).clone(), span })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a string literal as a variable of its own with the string as its constant
+    /// initial value, and returns the name of the variable.
+    fn string_constant(&mut self, string: String) -> Name {
+        let ty = Type::Array(Box::new(Type::Char), string.len() + 1);
+        self.literal_constant(ty, StaticInit::String(string, true))
+    }
+
+    /// Records a wide string literal as a variable like `string_constant`, as an array of
+    /// `wchar_t`.
+    fn wide_string_constant(&mut self, string: String) -> Name {
+        let ty = Type::Array(Box::new(Type::Int), string.chars().count() + 1);
+        self.literal_constant(ty, StaticInit::WideString(string, true))
+    }
+
+    /// Records a variable of the given type holding a string literal, named `string.<n>`.
+    fn literal_constant(&mut self, ty: Type, init: StaticInit) -> Name {
+        let name = Name::from(format!("string.{}", self.next_string));
+        self.next_string += 1;
+        let symbol = Symbol { ty, defined: true, initial_value: Some(InitialValue::Constant(init)), global: false };
+        self.symbols.insert(name, symbol);
+        name
+    }
+
+    /// Evaluates the initializer of a variable with static storage and converts it to the
+    /// variable's type.
+    ///
+    /// # Arguments
+    ///
+    /// * `declaration` - The declaration of the variable.
+    /// * `init` - Its initializer.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<StaticInit>, TypeError>` - The initial value, or an error if the
+    ///   initializer is not made of constants or does not fit the variable.
+    fn static_initializer(&mut self, declaration: &VarDecl, init: &Initializer) -> Result<Vec<StaticInit>, TypeError> {
+        let mut pieces = Vec::new();
+        self.add_static_initializer(declaration, init, &declaration.ty, &mut pieces)?;
+        Ok(pieces)
+    }
+
+    /// Appends the initial value of an object with static storage, part of the variable a
+    /// declaration defines, to those of the objects before it. Elements an array initializer
+    /// leaves out are zero, as are the members a structure initializer leaves out and the
+    /// padding between members, and a `char *` initialized with a string literal points to
+    /// a variable holding the string.
+    ///
+    /// # Arguments
+    ///
+    /// * `declaration` - The declaration of the variable.
+    /// * `init` - The initializer of the object.
+    /// * `ty` - The type of the object.
+    /// * `pieces` - The initial value laid out so far.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), TypeError>` - An error if the initializer does not fit the object.
+    fn add_static_initializer(
+        &mut self,
+        declaration: &VarDecl,
+        init: &Initializer,
+        ty: &Type,
+        pieces: &mut Vec<StaticInit>,
+    ) -> Result<(), TypeError> {
+        let ty = &ty.unqualified();
+        match (init, ty) {
+            (Initializer::Single(Exp::String(string, span)), Type::Array(element, count)) if element.is_character() => {
+                if string.len() > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: *span });
+                }
+                pieces.push(StaticInit::String(string.clone(), string.len() < *count));
+                if string.len() + 1 < *count {
+                    pieces.push(StaticInit::Zero(count - string.len() - 1));
+                }
+            }
+            (Initializer::Single(Exp::WideString(string, span)), Type::Array(element, count)) if **element == Type::Int => {
+                let length = string.chars().count();
+                if length > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: *span });
+                }
+                pieces.push(StaticInit::WideString(string.clone(), length < *count));
+                if length + 1 < *count {
+                    pieces.push(StaticInit::Zero((count - length - 1) * Type::Int.size()));
+                }
+            }
+            (Initializer::Single(Exp::String(string, _)), Type::Pointer(referenced)) if **referenced == Type::Char => {
+                pieces.push(StaticInit::Pointer(self.string_constant(string.clone())));
+            }
+            (Initializer::Single(Exp::WideString(string, _)), Type::Pointer(referenced)) if **referenced == Type::Int => {
+                pieces.push(StaticInit::Pointer(self.wide_string_constant(string.clone())));
+            }
+            (Initializer::Single(Exp::String(..) | Exp::WideString(..)), Type::Array(..)) => {
+                return Err(TypeError::InvalidInitializer { ty: ty.clone(), span: declaration.span });
+            }
+            (Initializer::Single(string @ (Exp::String(..) | Exp::WideString(..))), _) => {
+                let element = if matches!(string, Exp::WideString(..)) { Type::Int } else { Type::Char };
+                let from = Type::Pointer(Box::new(element));
+                return Err(TypeError::IncompatibleTypes { from, to: ty.clone(), span: declaration.span });
+            }
+            (Initializer::Single(_), Type::Array(..)) => {
+                return Err(TypeError::InvalidInitializer { ty: ty.clone(), span: declaration.span });
+            }
+            (Initializer::Single(exp), _) => {
+                let exp = self.check_exp(exp.clone())?;
+                pieces.push(StaticInit::Scalar(static_scalar(declaration, &exp, ty)?));
+            }
+            (Initializer::Compound(inits, span), Type::Array(element, count)) => {
+                if inits.len() > *count {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: *span });
+                }
+                for init in inits {
+                    self.add_static_initializer(declaration, init, element, pieces)?;
+                }
+                add_zeros(pieces, (count - inits.len()) * element.size_in(&self.structs));
+            }
+            (Initializer::Compound(inits, span), Type::Struct(tag)) => {
+                let layout = self.structs[tag].clone();
+                if inits.len() > layout.members.len() {
+                    return Err(TypeError::TooManyInitializers { ty: ty.clone(), span: *span });
+                }
+                let start: usize = pieces.iter().map(StaticInit::size).sum();
+                for (init, member) in inits.iter().zip(&layout.members) {
+                    let end: usize = pieces.iter().map(StaticInit::size).sum();
+                    add_zeros(pieces, start + member.offset - end);
+                    self.add_static_initializer(declaration, init, &member.ty, pieces)?;
+                }
+                let end: usize = pieces.iter().map(StaticInit::size).sum();
+                add_zeros(pieces, start + layout.size - end);
+            }
+            (Initializer::Compound(_, span), _) => {
+                return Err(TypeError::InvalidInitializer { ty: ty.clone(), span: *span });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the type of a checked expression.
+    fn type_of(&self, exp: &Exp) -> Type {
+        type_of(exp, &self.symbols)
+    }
+
+    /// Checks whether either operand of a binary operator is a double.
+    fn has_double(&self, left: &Exp, right: &Exp) -> bool {
+        self.type_of(left) == Type::Double || self.type_of(right) == Type::Double
+    }
+
+    /// Checks that none of the operands of an operator that only applies to arithmetic types
+    /// is a pointer or a structure.
+    fn check_arithmetic_operands(&self, operator: &str, operands: &[&Exp], span: Span) -> Result<(), TypeError> {
+        match operands.iter().map(|operand| self.type_of(operand)).find(|ty| !ty.is_arithmetic()) {
+            Some(ty) => Err(TypeError::PointerOperand { operator: operator.to_string(), ty, span }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the type two operands are converted to when at least one of them is a pointer:
+    /// their type if they have the same one, or the type of the other operand if one of
+    /// them is a null pointer constant.
+    fn common_pointer_type(&self, operator: &str, left: &Exp, right: &Exp, span: Span) -> Result<Type, TypeError> {
+        let (left_type, right_type) = (self.type_of(left), self.type_of(right));
+        if left_type == right_type || is_null_pointer_constant(right) {
+            Ok(left_type)
+        } else if is_null_pointer_constant(left) {
+            Ok(right_type)
+        } else {
+            Err(TypeError::IncompatibleOperands { operator: operator.to_string(), left: left_type, right: right_type, span })
+        }
+    }
+
+    /// Converts a checked expression to a type, wrapping it in a cast unless it already has
+    /// that type. Constants are converted right away. The cast gets the position of the
+    /// construct that needed the conversion.
+    fn convert(&self, exp: Exp, ty: &Type, span: Span) -> Exp {
+        match exp {
+            Exp::Const(constant) => Exp::Const(constant.convert_to(ty)),
+            exp if self.type_of(&exp) == *ty => exp,
+            exp => Exp::Cast(ty.clone(), Box::new(exp), span),
+        }
+    }
+
+    /// Converts a checked expression of a character type to `int`, leaving other types alone.
+    fn promote(&self, exp: Exp, span: Span) -> Exp {
+        let ty = self.type_of(&exp).promoted();
+        self.convert(exp, &ty, span)
+    }
+
+    /// Converts a checked expression to the type of the object it is stored in, as an
+    /// assignment, initializer, argument or `return` does. Arithmetic values convert to any
+    /// arithmetic type, but a pointer only takes a value of its own type or a null pointer
+    /// constant.
+    fn convert_by_assignment(&self, exp: Exp, ty: &Type, span: Span) -> Result<Exp, TypeError> {
+        let from = self.type_of(&exp);
+        if from == *ty || (from.is_arithmetic() && ty.is_arithmetic()) || (ty.is_pointer() && is_null_pointer_constant(&exp)) {
+            Ok(self.convert(exp, ty, span))
+        } else {
+            Err(TypeError::IncompatibleTypes { from, to: ty.clone(), span })
+        }
+    }
+}
+
+/// Appends the given number of zero bytes to an initial value, merging them with zeros it
+/// already ends with.
+fn add_zeros(pieces: &mut Vec<StaticInit>, zeros: usize) {
+    if zeros == 0 {
+        return;
+    }
+    match pieces.last_mut() {
+        Some(StaticInit::Zero(bytes)) => *bytes += zeros,
+        _ => pieces.push(StaticInit::Zero(zeros)),
+    }
+}
+
+/// Evaluates the initializer of a scalar with static storage and converts it to its type.
+///
+/// # Arguments
+///
+/// * `declaration` - The declaration of the variable the scalar is part of.
+/// * `init` - The initializer.
+/// * `to` - The type of the scalar.
+///
+/// # Returns
+///
+/// * `Result<Constant, TypeError>` - The value, or an error if the initializer is not a constant.
+fn static_scalar(declaration: &VarDecl, init: &Exp, to: &Type) -> Result<Constant, TypeError> {
+    let constant = evaluate_constant(init)
+        .ok_or(TypeError::NonConstantInitializer { name: declaration.name, span: declaration.span })?;
+    // The same rules as for an assignment apply, so a pointer starts out null or at an
+    // address given by an explicit cast
+    let from = match init {
+        Exp::Cast(ty, ..) => ty.clone(),
+        _ => constant.ty(),
+    };
+    if from != *to && !(from.is_arithmetic() && to.is_arithmetic()) && !(to.is_pointer() && is_null_pointer_constant(init)) {
+        return Err(TypeError::IncompatibleTypes { from, to: to.clone(), span: declaration.span });
+    }
+    Ok(constant.convert_to(to))
+}
+
+/// Evaluates a type checked integer or arithmetic constant expression, such as the value of
+/// a `case`, the initializer of a variable with static storage or the size of an array.
+/// The checker has already replaced `sizeof` with its value. The usual arithmetic
+/// conversions are applied to the values of the operands, which leaves those the checker
+/// has already converted as they are.
+///
+/// # Arguments
+///
+/// * `exp` - The expression.
+///
+/// # Returns
+///
+/// * `Option<Constant>` - The value, or `None` if the expression is not a constant or its
+///   value is undefined, as for a division by zero.
+fn evaluate_constant(exp: &Exp) -> Option<Constant> {
+    match exp {
+        Exp::Const(constant) => Some(*constant),
+        Exp::Cast(ty, operand, _) => Some(evaluate_constant(operand)?.convert_to(ty)),
+        Exp::UnOp(op, operand, _) => {
+            let value = evaluate_constant(operand)?;
+            let value = value.convert_to(&value.ty().promoted());
+            if *op == UnaryOperator::Complement && value.ty() == Type::Double {
+                return None;
+            }
+            Some(value.apply_unary(*op))
+        }
+        Exp::BinOp(op @ (BinaryOperator::And | BinaryOperator::Or), left, right, _) => {
+            let left = !evaluate_constant(left)?.is_zero();
+            if left == (*op == BinaryOperator::Or) {
+                return Some(Constant::Int(left as i32));
+            }
+            Some(Constant::Int(!evaluate_constant(right)?.is_zero() as i32))
+        }
+        Exp::BinOp(op, left, right, _) => {
+            let (left, right) = (evaluate_constant(left)?, evaluate_constant(right)?);
+            // The result of a shift has the promoted type of its left operand alone
+            let (left_type, right_type) = match op {
+                BinaryOperator::LeftShift | BinaryOperator::RightShift => (left.ty().promoted(), right.ty().promoted()),
+                _ => {
+                    let ty = common_type(&left.ty(), &right.ty());
+                    (ty.clone(), ty)
+                }
+            };
+            if takes_integers(*op) && (left_type == Type::Double || right_type == Type::Double) {
+                return None;
+            }
+            left.convert_to(&left_type).apply_binary(*op, right.convert_to(&right_type))
+        }
+        Exp::Conditional(condition, then, otherwise, _) => {
+            let condition = evaluate_constant(condition)?;
+            let (then, otherwise) = (evaluate_constant(then)?, evaluate_constant(otherwise)?);
+            let ty = common_type(&then.ty(), &otherwise.ty());
+            let chosen = if condition.is_zero() { otherwise } else { then };
+            Some(chosen.convert_to(&ty))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses and resolves `source`, then type checks it.
+    fn check(source: &str) -> Result<SymbolTable, TypeError> {
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        typecheck_program(crate::resolve::resolve_program(ast).unwrap()).map(|(_, symbols, _)| symbols)
+    }
+
+    #[test]
+    fn test_symbol_table() {
+        let symbols = check("int f(int a, int b); int main(void) { int x = f(1, 2); return x; }").unwrap();
+        assert_eq!(symbols[&Name::new("f")].ty, Type::Function { params: vec![Type::Int; 2], ret: Box::new(Type::Int) });
+        assert!(!symbols[&Name::new("f")].defined);
+        assert!(symbols[&Name::new("main")].defined);
+        assert_eq!(symbols[&Name::new("x.2")].ty, Type::Int);
+    }
+
+    #[test]
+    fn test_wrong_argument_count() {
+        let result = check("int f(int a); int main(void) { return f(1, 2); }");
+        assert_eq!(
+            result.unwrap_err(),
+            TypeError::WrongArgumentCount { name: "f".into(), expected: 1, found: 2, span: Span { line: 1, column: 39 } }
+        );
+    }
+
+    #[test]
+    fn test_conflicting_declarations() {
+        let result = check("int f(int a); int f(int a, int b) { return a; }");
+        assert_eq!(
+            result.unwrap_err(),
+            TypeError::IncompatibleDeclarations { name: "f".into(), previous: 1, found: 2, span: Span { line: 1, column: 19 } }
+        );
+        let result = check("int main(void) { int f(void); return 0; } int f(int a);");
+        assert!(matches!(result, Err(TypeError::IncompatibleDeclarations { .. })));
+    }
+
+    #[test]
+    fn test_redefinition() {
+        let result = check("int f(void) { return 1; } int f(void); int f(void) { return 2; }");
+        let error = result.unwrap_err();
+        assert_eq!(error, TypeError::Redefinition { name: "f".into(), span: Span { line: 1, column: 44 } });
+        assert_eq!(Diagnostic::from(error).to_string(), "1:44: Function 'f' is defined more than once");
+    }
+
+    #[test]
+    fn test_functions_and_variables_are_not_interchangeable() {
+        let result = check("int f(void); int main(void) { return f + 1; }");
+        assert!(matches!(result, Err(TypeError::FunctionUsedAsVariable { .. })));
+        let result = check("int f(void); int main(void) { int f = 3; return f(); }");
+        assert!(matches!(result, Err(TypeError::VariableCalledAsFunction { .. })));
+        // Errors name local variables as they are written, not by their unique names
+        let cases = [
+            ("int main(void) { int x = 3; return x(); }", "Variable 'x' called as a function"),
+            ("int main(void) { int y = 1; static int x = y; return x; }", "Initializer of static variable 'x' is not a constant"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_file_scope_variables() {
+        let symbols = check("int x; int y = 3; int x = -2; int x; int y; int z; int main(void) { return x + y + z; }").unwrap();
+        assert_eq!(symbols[&Name::new("x")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(-2))])));
+        assert_eq!(symbols[&Name::new("y")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(3))])));
+        assert_eq!(symbols[&Name::new("z")].initial_value, Some(InitialValue::Tentative));
+        assert!(symbols[&Name::new("x")].defined && !symbols[&Name::new("z")].defined);
+        assert_eq!(symbols[&Name::new("main")].initial_value, None);
+    }
+
+    #[test]
+    fn test_invalid_file_scope_variables() {
+        let error = |source: &str| Diagnostic::from(check(source).unwrap_err()).to_string();
+        assert_eq!(error("int x = 1;\nint x = 2;"), "2:5: Variable 'x' is defined more than once");
+        assert_eq!(error("int y = 1;\nint x = y;"), "2:5: Initializer of static variable 'x' is not a constant");
+        assert_eq!(error("int f(void);\nint f;"), "2:5: 'f' is declared both as a function and as a variable");
+        assert_eq!(error("int f;\nint f(void);"), "2:5: 'f' is declared both as a function and as a variable");
+    }
+
+    #[test]
+    fn test_linkage() {
+        let source = "static int f(void); int f(void) { return 0; } extern int g(void); static int s; extern int s; \
+                      extern int e; int t; extern int t; int main(void) { extern int u; static int l = 2; return l; }";
+        let symbols = check(source).unwrap();
+        assert!(!symbols[&Name::new("f")].global && symbols[&Name::new("f")].defined);
+        assert!(symbols[&Name::new("g")].global);
+        assert!(!symbols[&Name::new("s")].global);
+        assert_eq!(symbols[&Name::new("s")].initial_value, Some(InitialValue::Tentative));
+        assert_eq!(symbols[&Name::new("e")].initial_value, Some(InitialValue::NoInitializer));
+        assert_eq!(symbols[&Name::new("t")].initial_value, Some(InitialValue::Tentative));
+        assert!(symbols[&Name::new("t")].global && symbols[&Name::new("main")].global);
+        assert_eq!(symbols[&Name::new("u")].initial_value, Some(InitialValue::NoInitializer));
+        assert_eq!(symbols[&Name::new("l.0")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(2))])));
+        assert!(!symbols[&Name::new("l.0")].global);
+    }
+
+    #[test]
+    fn test_invalid_linkage() {
+        let error = |source: &str| Diagnostic::from(check(source).unwrap_err()).to_string();
+        assert_eq!(error("int f(void);\nstatic int f(void);"), "2:12: 'f' is declared with both internal and external linkage");
+        assert_eq!(error("int x;\nstatic int x;"), "2:12: 'x' is declared with both internal and external linkage");
+        assert_eq!(error("static int x;\nint x;"), "2:5: 'x' is declared with both internal and external linkage");
+        assert_eq!(error("int main(void) {\n    extern int x = 1;\n    return x;\n}"), "2:16: Local extern declaration of 'x' has an initializer");
+        assert_eq!(error("int main(void) {\n    int y = 1;\n    static int x = y;\n    return x;\n}"), "3:16: Initializer of static variable 'x' is not a constant");
+        assert_eq!(error("int f(void);\nint main(void) {\n    extern int f;\n    return f;\n}"), "3:16: 'f' is declared both as a function and as a variable");
+    }
+
+    #[test]
+    fn test_implicit_conversions() {
+        let source = "long f(long a, int b); int main(void) { int i = 1; long l = i + 2; i = l; l += i; return f(i, l) * 3; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("LONG l.3 = Cast<LONG>(Binary(+, Var<i.2>, Int<2>))"), "{}", printed);
+        assert!(printed.contains("EXPR Assign(Var<i.2>, Cast<INT>(Var<l.3>))"), "{}", printed);
+        assert!(printed.contains("EXPR Assign(+=, Var<l.3>, Cast<LONG>(Var<i.2>))"), "{}", printed);
+        // Constants are converted right away instead of being cast at run time
+        assert!(printed.contains("RETURN Cast<INT>(Binary(*, Call<f>(Cast<LONG>(Var<i.2>), Cast<INT>(Var<l.3>)), Long<3>))"), "{}", printed);
+    }
+
+    #[test]
+    fn test_long_variables() {
+        let symbols = check("long x = -1; static long y; long z = 4294967297; int w = 4294967297; int main(void) { long l; return 0; }").unwrap();
+        assert_eq!(symbols[&Name::new("x")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(-1))])));
+        assert_eq!(symbols[&Name::new("y")].ty, Type::Long);
+        assert_eq!(symbols[&Name::new("z")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(4294967297))])));
+        // A constant too large for an int is truncated like any conversion to int
+        assert_eq!(symbols[&Name::new("w")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(1))])));
+        assert_eq!(symbols[&Name::new("l.0")].ty, Type::Long);
+    }
+
+    #[test]
+    fn test_conflicting_types() {
+        let error = |source: &str| Diagnostic::from(check(source).unwrap_err()).to_string();
+        assert_eq!(error("int x;
+long x;"), "2:6: Conflicting types for 'x'");
+        assert_eq!(error("int f(int a);
+int f(long a);"), "2:5: Conflicting types for 'f'");
+        assert_eq!(error("int f(void);
+long f(void);"), "2:6: Conflicting types for 'f'");
+        assert_eq!(error("long x;
+int main(void) {
+    extern int x;
+    return x;
+}"), "3:16: Conflicting types for 'x'");
+    }
+
+    #[test]
+    fn test_common_type() {
+        assert_eq!(common_type(&Type::Int, &Type::UInt), Type::UInt);
+        assert_eq!(common_type(&Type::ULong, &Type::Long), Type::ULong);
+        assert_eq!(common_type(&Type::UInt, &Type::Long), Type::Long);
+        assert_eq!(common_type(&Type::Int, &Type::ULong), Type::ULong);
+        let symbols = check("unsigned int u = -1; unsigned long v = -1; long w = 4294967295u;").unwrap();
+        assert_eq!(symbols[&Name::new("u")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::UInt(u32::MAX))])));
+        assert_eq!(symbols[&Name::new("v")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::ULong(u64::MAX))])));
+        assert_eq!(symbols[&Name::new("w")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(4294967295))])));
+    }
+
+    #[test]
+    fn test_cast_initializers() {
+        let symbols = check("long x = (int) 4294967297L; static unsigned y = -(unsigned long) 1;").unwrap();
+        assert_eq!(symbols[&Name::new("x")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(1))])));
+        assert_eq!(symbols[&Name::new("y")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::UInt(u32::MAX))])));
+    }
+
+    #[test]
+    fn test_double_conversions() {
+        assert_eq!(common_type(&Type::ULong, &Type::Double), Type::Double);
+        assert_eq!(common_type(&Type::Double, &Type::Int), Type::Double);
+        let symbols = check("double d = 1; double e = -2.5; long l = 3.9; unsigned u = (unsigned) 4.7;").unwrap();
+        assert_eq!(symbols[&Name::new("d")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Double(1.0))])));
+        assert_eq!(symbols[&Name::new("e")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Double(-2.5))])));
+        assert_eq!(symbols[&Name::new("l")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(3))])));
+        assert_eq!(symbols[&Name::new("u")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::UInt(4))])));
+    }
+
+    #[test]
+    fn test_integer_operators_reject_doubles() {
+        let cases = [
+            ("int main(void) { double d = 1.0; return d % 2; }", "Operand of '%' has type 'double', but must have an integer type"),
+            ("int main(void) { double d = 1.0; return 1 << d; }", "Operand of '<<' has type 'double', but must have an integer type"),
+            ("int main(void) { double d = 1.0; return ~d; }", "Operand of '~' has type 'double', but must have an integer type"),
+            ("int main(void) { double d = 1.0; d |= 1; return 0; }", "Operand of '|=' has type 'double', but must have an integer type"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+        assert_eq!(
+            check("int main(void) { double d = 1.0; return d ^ 1; }").unwrap_err().span(),
+            Span { line: 1, column: 43 }
+        );
+    }
+
+    #[test]
+    fn test_pointers() {
+        let symbols = check("static int *p = 0; long *q = (long *) 0; int *f(int *a, double *d) {
+    int **pp = &a;
+    int *c = 1 ? *pp : 0;
+    return a == 0 || a != c || !a ? *pp : (int *) d;
+}").unwrap();
+        assert_eq!(symbols[&Name::new("p")].ty, Type::Pointer(Box::new(Type::Int)));
+        assert_eq!(symbols[&Name::new("p")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::ULong(0))])));
+        assert_eq!(symbols[&Name::new("q")].initial_value, Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::ULong(0))])));
+        assert_eq!(Type::Pointer(Box::new(Type::Pointer(Box::new(Type::ULong)))).to_string(), "unsigned long **");
+        let cases = [
+            ("int main(void) { int x = 1; return *x; }", "Cannot dereference a value of type 'int', which is not a pointer"),
+            ("int main(void) { int *p = 5; return 0; }", "Cannot convert a value of type 'int' to 'int *'"),
+            ("int *p = 1;", "Cannot convert a value of type 'int' to 'int *'"),
+            ("long *f(int *p) { return p; }", "Cannot convert a value of type 'int *' to 'long *'"),
+            ("int main(void) { int x; long *p = &x; return 0; }", "Cannot convert a value of type 'int *' to 'long *'"),
+            ("int f(int *p); int main(void) { return f(1.0); }", "Cannot convert a value of type 'double' to 'int *'"),
+            ("int main(void) { int *p = 0; long *q = 0; return p == q; }", "Operands of '==' have incompatible types 'int *' and 'long *'"),
+            ("int main(void) { int *p = 0; return p < 1; }", "Operands of '<' have incompatible types 'int *' and 'int'"),
+            ("int main(void) { int *p = 0; long *q = 0; return *(1 ? p : q); }", "Operands of '?:' have incompatible types 'int *' and 'long *'"),
+            ("int main(void) { int *p = 0; return p * 2; }", "Operand of '*' has type 'int *', but must have an arithmetic type"),
+            ("int main(void) { int *p = 0; p += 1.5; return 0; }", "Operands of '+=' have incompatible types 'int *' and 'double'"),
+            ("int main(void) { int *p = 0; return -p; }", "Operand of '-' has type 'int *', but must have an arithmetic type"),
+            ("int main(void) { int *p = 0; return p + p; }", "Operands of '+' have incompatible types 'int *' and 'int *'"),
+            ("int main(void) { int *p = 0; long *q = 0; return p - q; }", "Operands of '-' have incompatible types 'int *' and 'long *'"),
+            ("int main(void) { int *p = 0; return (double) p; }", "Cannot cast a value of type 'int *' to 'double'"),
+            ("int main(void) { double d = 0; return *(int *) d; }", "Cannot cast a value of type 'double' to 'int *'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_arrays() {
+        let symbols = check("int a[4] = {1, 2}; long g[2][3] = {{1}, {2, 3, 4}}; static double z[3]; int main(void) { int b[2] = {3}; return a[1]; }").unwrap();
+        let init = |inits: Vec<StaticInit>| Some(InitialValue::Initial(inits));
+        assert_eq!(symbols[&Name::new("a")].initial_value, init(vec![StaticInit::Scalar(Constant::Int(1)), StaticInit::Scalar(Constant::Int(2)), StaticInit::Zero(8)]));
+        assert_eq!(
+            symbols[&Name::new("g")].initial_value,
+            init(vec![StaticInit::Scalar(Constant::Long(1)), StaticInit::Zero(16), StaticInit::Scalar(Constant::Long(2)), StaticInit::Scalar(Constant::Long(3)), StaticInit::Scalar(Constant::Long(4))])
+        );
+        assert_eq!(symbols[&Name::new("z")].initial_value, Some(InitialValue::Tentative));
+        assert_eq!(symbols[&Name::new("b.0")].ty, Type::Array(Box::new(Type::Int), 2));
+
+        // Arrays decay to pointers, indices become longs and the pointer goes first
+        let source = "int main(void) { int a[3]; int *p = a + 1; long n = p - a; return 2[a] + *(1 + p) + (&a)[0][1]; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("PTR(INT) p.1 = Binary(+, Cast<PTR(INT)>(Var<a.0>), Long<1>)"), "{}", printed);
+        assert!(printed.contains("LONG n.2 = Binary(-, Var<p.1>, Cast<PTR(INT)>(Var<a.0>))"), "{}", printed);
+        assert!(printed.contains("Subscript(Cast<PTR(INT)>(Var<a.0>), Long<2>)"), "{}", printed);
+        assert!(printed.contains("Deref(Binary(+, Var<p.1>, Long<1>))"), "{}", printed);
+        assert!(printed.contains("Subscript(Cast<PTR(INT)>(Subscript(AddrOf(Var<a.0>), Long<0>)), Long<1>)"), "{}", printed);
+
+        let cases = [
+            ("int main(void) { int a[3]; int b[3]; a = b; return 0; }", "Cannot assign to a value of type 'int [3]'"),
+            ("int main(void) { int a[3]; a++; return 0; }", "Cannot assign to a value of type 'int [3]'"),
+            ("int main(void) { int a[3]; double d = 1.0; return a[d]; }", "Cannot subscript a value of type 'int *' with a value of type 'double'"),
+            ("int main(void) { int x = 1; return x[2]; }", "Cannot subscript a value of type 'int' with a value of type 'int'"),
+            ("int main(void) { int a[2] = 3; return 0; }", "Invalid initializer for a value of type 'int [2]'"),
+            ("int main(void) { int x = {3}; return x; }", "Invalid initializer for a value of type 'int'"),
+            ("int main(void) { int a[2] = {1, 2, 3}; return 0; }", "Too many initializers for a value of type 'int [2]'"),
+            ("long g[2][2] = {{1}, 2};", "Invalid initializer for a value of type 'long [2]'"),
+            ("int main(void) { int a[3]; long *p = a; return 0; }", "Cannot convert a value of type 'int *' to 'long *'"),
+            ("int main(void) { int a[3]; return (int [3]) a; }", "Cannot cast a value of type 'int *' to 'int [3]'"),
+            ("int main(void) { int a[3]; int b[4]; return &a == &b; }", "Operands of '==' have incompatible types 'int (*)[3]' and 'int (*)[4]'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+        assert_eq!(check("int a[2] = {1, 2, 3};").unwrap_err().span(), Span { line: 1, column: 12 });
+    }
+    #[test]
+    fn test_characters_and_strings() {
+        let symbols = check("char s[4] = \"ab\"; unsigned char t[2] = \"ab\"; char *p = \"hi\"; int main(void) { char *q = \"x\"; return 0; }").unwrap();
+        let init = |inits: Vec<StaticInit>| Some(InitialValue::Initial(inits));
+        assert_eq!(symbols[&Name::new("s")].initial_value, init(vec![StaticInit::String("ab".to_string(), true), StaticInit::Zero(1)]));
+        assert_eq!(symbols[&Name::new("t")].initial_value, init(vec![StaticInit::String("ab".to_string(), false)]));
+        assert_eq!(symbols[&Name::new("p")].initial_value, init(vec![StaticInit::Pointer("string.0".into())]));
+        assert_eq!(symbols[&Name::new("string.0")].ty, Type::Array(Box::new(Type::Char), 3));
+        assert_eq!(symbols[&Name::new("string.1")].initial_value, Some(InitialValue::Constant(StaticInit::String("x".to_string(), true))));
+        assert!(!symbols[&Name::new("string.1")].global);
+
+        // Characters are promoted to int before arithmetic, negation and shifts
+        let source = "int main(void) { char c = 'a'; unsigned char u = 1; return -c + (u << c) + (c == u); }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("CHAR c.0 = Char<97>"), "{}", printed);
+        assert!(printed.contains("Unary(-, Cast<INT>(Var<c.0>))"), "{}", printed);
+        assert!(printed.contains("Binary(<<, Cast<INT>(Var<u.1>), Cast<INT>(Var<c.0>))"), "{}", printed);
+        assert!(printed.contains("Binary(==, Cast<INT>(Var<c.0>), Cast<INT>(Var<u.1>))"), "{}", printed);
+
+        // Wide strings are arrays of wchar_t, which is int
+        let symbols = check("wchar_t w[4] = L\"ab\"; wchar_t x[2] = L\"ab\"; wchar_t *p = L\"hi\"; int main(void) { return sizeof L\"abc\"; }").unwrap();
+        assert_eq!(symbols[&Name::new("w")].initial_value, init(vec![StaticInit::WideString("ab".to_string(), true), StaticInit::Zero(4)]));
+        assert_eq!(symbols[&Name::new("x")].initial_value, init(vec![StaticInit::WideString("ab".to_string(), false)]));
+        assert_eq!(symbols[&Name::new("p")].initial_value, init(vec![StaticInit::Pointer("string.0".into())]));
+        assert_eq!(symbols[&Name::new("string.0")].ty, Type::Array(Box::new(Type::Int), 3));
+        assert_eq!(symbols[&Name::new("string.0")].initial_value, Some(InitialValue::Constant(StaticInit::WideString("hi".to_string(), true))));
+
+        let cases = [
+            ("char s[2] = \"abc\";", "Too many initializers for a value of type 'char [2]'"),
+            ("int a[3] = \"ab\";", "Invalid initializer for a value of type 'int [3]'"),
+            ("wchar_t a[1] = L\"ab\";", "Too many initializers for a value of type 'int [1]'"),
+            ("char a[3] = L\"ab\";", "Invalid initializer for a value of type 'char [3]'"),
+            ("char *p = L\"ab\";", "Cannot convert a value of type 'int *' to 'char *'"),
+            ("int main(void) { char a[3] = L\"ab\"; return 0; }", "Invalid initializer for a value of type 'char [3]'"),
+            ("long *p = \"ab\";", "Cannot convert a value of type 'char *' to 'long *'"),
+            ("int main(void) { \"ab\" = 0; return 0; }", "Cannot assign to a value of type 'char [3]'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_structures() {
+        let source = "struct inner { char c; double d; }; struct s { char a; struct inner i; int n[3]; char b; };
+struct s g = {'x', {1, 2.5}}; int main(void) { struct s l; struct s *p = &l; return p->i.c + l.n[2]; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, symbols, structs) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        // Members are aligned, and the size is padded to a multiple of the strictest alignment
+        let member = |name: &str, ty: Type, offset: usize| MemberDef { name: Name::from(name), ty, offset };
+        assert_eq!(structs[&Name::new("inner.0")].members, vec![member("c", Type::Char, 0), member("d", Type::Double, 8)]);
+        assert_eq!((structs[&Name::new("inner.0")].size, structs[&Name::new("inner.0")].alignment), (16, 8));
+        assert_eq!(
+            structs[&Name::new("s.1")].members,
+            vec![
+                member("a", Type::Char, 0),
+                member("i", Type::Struct("inner.0".into()), 8),
+                member("n", Type::Array(Box::new(Type::Int), 3), 24),
+                member("b", Type::Char, 36),
+            ]
+        );
+        assert_eq!((structs[&Name::new("s.1")].size, structs[&Name::new("s.1")].alignment), (40, 8));
+        assert_eq!(
+            symbols[&Name::new("g")].initial_value,
+            Some(InitialValue::Initial(vec![
+                StaticInit::Scalar(Constant::Char(b'x' as i8)),
+                StaticInit::Zero(7),
+                StaticInit::Scalar(Constant::Char(1)),
+                StaticInit::Zero(7),
+                StaticInit::Scalar(Constant::Double(2.5)),
+                StaticInit::Zero(16),
+            ]))
+        );
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("Cast<INT>(Member(Member(Deref(Var<p.3>), i), c))"), "{}", printed);
+
+        let cases = [
+            ("struct s { int a; int a; };", "Duplicate member 'a' in 'struct s'"),
+            ("struct s { int a; }; struct s { int b; };", "Structure 'struct s' is defined more than once"),
+            ("struct s; struct s x;", "Use of incomplete type 'struct s'"),
+            ("struct s; struct s *p; int main(void) { return p[1] == 0; }", "Use of incomplete type 'struct s'"),
+            ("struct s *p; int main(void) { return p->a; }", "Use of incomplete type 'struct s'"),
+            ("struct s { struct s next; };", "Use of incomplete type 'struct s'"),
+            ("struct s; struct s (*p)[2];", "Use of incomplete type 'struct s'"),
+            ("struct s { int a; }; struct s f(void);", "Function 'f' takes or returns a structure, which is not supported"),
+            ("struct s { int a; }; int main(void) { struct s x; return x.b; }", "'struct s' has no member named 'b'"),
+            ("int main(void) { int *p = 0; return p->a; }", "Cannot select a member of a value of type 'int', which is not a structure"),
+            ("struct s { int a; }; int main(void) { struct s x; return !x; }", "A value of type 'struct s' is used where a scalar is required"),
+            ("struct s { int a; }; int main(void) { struct s x; return (int) x; }", "Cannot cast a value of type 'struct s' to 'int'"),
+            ("struct s { int a; }; int main(void) { struct s x; return x + 1; }", "Operand of '+' has type 'struct s', but must have an arithmetic type"),
+            ("struct s { int a; }; struct t { int a; }; int main(void) { struct s x; struct t y; x = y; return 0; }", "Cannot convert a value of type 'struct t' to 'struct s'"),
+            ("struct s { int a; }; struct s x = {1, 2};", "Too many initializers for a value of type 'struct s'"),
+            ("struct s { int a; }; struct s x = 1;", "Cannot convert a value of type 'int' to 'struct s'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_atomic() {
+        let symbols = check("_Atomic int x; _Atomic char *p = 0; long f(_Atomic int a);
+_Atomic int f2(_Atomic int a) {
+    _Atomic long l = a;
+    _Atomic int *q = &x;
+    l += (_Atomic long) *q;
+    return x++ + (int) sizeof(_Atomic char) + *p;
+}").unwrap();
+        let atomic = |ty: Type| Type::Atomic(Box::new(ty));
+        assert_eq!(symbols[&Name::new("x")].ty, atomic(Type::Int));
+        assert_eq!(symbols[&Name::new("p")].ty, Type::Pointer(Box::new(atomic(Type::Char))));
+        assert_eq!(symbols[&Name::new("f2")].ty, Type::Function { params: vec![Type::Int], ret: Box::new(Type::Int) });
+        assert_eq!(atomic(Type::ULong).to_string(), "_Atomic unsigned long");
+        assert_eq!(Type::Pointer(Box::new(atomic(Type::Int))).to_string(), "_Atomic int *");
+        let cases = [
+            ("_Atomic double d;", "Cannot qualify type 'double' with '_Atomic', which is only supported for integer types"),
+            ("struct s { int a; }; _Atomic struct s v;", "Cannot qualify type 'struct s' with '_Atomic', which is only supported for integer types"),
+            ("_Atomic int x; int main(void) { int *p = &x; return 0; }", "Cannot convert a value of type '_Atomic int *' to 'int *'"),
+            ("int x; int f(_Atomic int *p); int main(void) { return f(&x); }", "Cannot convert a value of type 'int *' to '_Atomic int *'"),
+            ("_Atomic int x; long x;", "Conflicting types for 'x'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        // Builtins are called without a declaration and never enter the symbol table
+        let symbols = check("int main(void) {
+    char *p = __builtin_alloca(10);
+    long *q = (long *) __builtin_alloca(sizeof(long));
+    if (__builtin_expect(p == 0, 0))
+        __builtin_trap();
+    return __builtin_abs(p[0] + q[0]);
+    __builtin_unreachable();
+}").unwrap();
+        assert!(!symbols.contains_key(&Name::new("__builtin_alloca")));
+        assert!(!symbols.contains_key(&Name::new("__builtin_abs")));
+        let cases = [
+            ("int main(void) { int *p = __builtin_alloca(4); return 0; }", "Cannot convert a value of type 'char *' to 'int *'"),
+            ("int main(void) { int *p = 0; return __builtin_abs(p); }", "Cannot convert a value of type 'int *' to 'int'"),
+            ("int main(void) { return __builtin_trap(1); }", "Function '__builtin_trap' expects 0 argument(s), but 1 were given"),
+            ("int main(void) { return __builtin_alloca(1, 2)[0]; }", "Function '__builtin_alloca' expects 1 argument(s), but 2 were given"),
+            ("int main(void) { char *p = 0; return __builtin_alloca(p)[0]; }", "Cannot convert a value of type 'char *' to 'unsigned long'"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_switch() {
+        // The value is promoted, and every case is folded into a constant of its type
+        let source = "int main(void) { char c = 1; switch (c) case 'a': case (long) -1: switch (3L) case 2: return 1; return 0; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("SWITCH Cast<INT>(Var<c.0>):"), "{}", printed);
+        assert!(printed.contains("CASE Int<97>:"), "{}", printed);
+        assert!(printed.contains("CASE Int<-1>:"), "{}", printed);
+        assert!(printed.contains("SWITCH Long<3>:"), "{}", printed);
+        assert!(printed.contains("CASE Long<2>:"), "{}", printed);
+
+        let cases = [
+            ("int main(void) { double d = 1.0; switch (d) return 0; return 1; }", "Cannot switch on a value of type 'double', which is not an integer"),
+            ("int main(void) { int *p = 0; switch (p) return 0; return 1; }", "Cannot switch on a value of type 'int *', which is not an integer"),
+            ("int main(void) { int x = 1; switch (x) case x: return 0; return 1; }", "Case label is not an integer constant"),
+            ("int main(void) { switch (1) case 1.5: return 0; return 1; }", "Case label is not an integer constant"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_sizeof() {
+        let source = "struct s { char c; long l; }; struct s g; int a[3][2];
+int main(void) { int x = 1; long n = sizeof(struct s) + sizeof g + sizeof a + sizeof a[0] + sizeof x++ + sizeof(char *);
+switch (x) case sizeof(int): return n; return 0; }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        // The operand of sizeof is not evaluated, so `x` is never incremented
+        assert!(printed.contains("Binary(+, ULong<16>, ULong<16>), ULong<24>), ULong<8>), ULong<4>), ULong<8>)"), "{}", printed);
+        assert!(printed.contains("CASE Int<4>:"), "{}", printed);
+        assert!(!printed.contains("Postfix"), "{}", printed);
+
+        let cases = [
+            ("struct s; int main(void) { return sizeof(struct s); }", "Use of incomplete type 'struct s'"),
+            ("struct s; struct s *p; int main(void) { return sizeof *p; }", "Use of incomplete type 'struct s'"),
+            ("int f(void); int main(void) { return sizeof f; }", "Function 'f' used as a variable"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_constant_expressions() {
+        let source = "struct s { int a; char b; }; unsigned long u = sizeof(struct s) * 2; int i = (1 << 4) - 3 % 2;
+long l = 1 ? -1 : 2u; double d = 1 / 2 + 0.5; int b = 0 && 1 / 0; int c = ~0 == -1;";
+        let symbols = check(source).unwrap();
+        let value = |name: &str| symbols[&Name::new(name)].initial_value.clone();
+        assert_eq!(value("u"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::ULong(16))])));
+        assert_eq!(value("i"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(15))])));
+        // The branches are converted to their common type, `unsigned int`, before `long`
+        assert_eq!(value("l"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Long(u32::MAX as i64))])));
+        assert_eq!(value("d"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Double(0.5))])));
+        assert_eq!(value("b"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(0))])));
+        assert_eq!(value("c"), Some(InitialValue::Initial(vec![StaticInit::Scalar(Constant::Int(1))])));
+        assert!(check("int main(void) { switch (3) case 1 + 2: return 1; return 0; }").is_ok());
+
+        // An array size may be any integer constant expression, including the size of a
+        // structure or of the type of an expression
+        let source = "struct s { int a; char b; }; char b[sizeof(long) * 2 - (1 ? 1 : 3)]; int c[sizeof(struct s)];
+int f(int x[4], double (*m)[2]); int main(void) { double x; long a[sizeof x][sizeof c / sizeof c[0] / 4]; return 0; }";
+        let symbols = check(source).unwrap();
+        let array = |element: Type, count: usize| Type::Array(Box::new(element), count);
+        assert_eq!(symbols[&Name::new("b")].ty, array(Type::Char, 15));
+        assert_eq!(symbols[&Name::new("c")].ty, array(Type::Int, 8));
+        assert_eq!(symbols[&Name::new("a.4")].ty, array(array(Type::Long, 2), 8));
+        // Array parameters are pointers to their first element
+        let params = vec![Type::Pointer(Box::new(Type::Int)), Type::Pointer(Box::new(array(Type::Double, 2)))];
+        assert_eq!(symbols[&Name::new("f")].ty, Type::Function { params, ret: Box::new(Type::Int) });
+
+        let cases = [
+            ("int x = 1 / 0;", "Initializer of static variable 'x' is not a constant"),
+            ("int y; int x = y + 1;", "Initializer of static variable 'x' is not a constant"),
+            ("int main(void) { switch (1) case 1 << 40: return 0; return 1; }", "Case label is not an integer constant"),
+            ("int a[0];", "Array size must be positive"),
+            ("int a[1.5];", "Array size is not an integer"),
+            ("int a[2 - 3];", "Array size must be positive"),
+            ("int n; int a[n];", "Array size is not a constant"),
+            ("int a[1 / 0];", "Array size is not a constant"),
+            ("int g; int a[(long) &g];", "Array size is not a constant"),
+            ("int f(int a[0]);", "Array size must be positive"),
+            ("struct s { int a[-1]; };", "Array size must be positive"),
+            ("int main(void) { return sizeof(char [0]); }", "Array size must be positive"),
+            ("int main(void) { int x; int a[x]; return 0; }", "Array size is not a constant"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(check(source).unwrap_err().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_comma() {
+        // The comma operator has the type of its right operand, after an array decays
+        let source = "int a[3]; int main(void) { int *p = (1.5, a); long n = sizeof(0, a); return (p, n, 2); }";
+        let ast = crate::parse::parse(crate::lex::lex(source).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(crate::resolve::resolve_program(ast).unwrap()).unwrap();
+        let printed = crate::parse::pretty_print(&ast);
+        assert!(printed.contains("PTR(INT) p.0 = Comma(Double<1.5>, Cast<PTR(INT)>(Var<a>))"), "{}", printed);
+        assert!(printed.contains("LONG n.1 = Long<8>"), "{}", printed);
+        assert!(printed.contains("RETURN Comma(Comma(Var<p.0>, Var<n.1>), Int<2>)"), "{}", printed);
+        assert_eq!(
+            check("int main(void) { int *p = 0; double d = (1, p); return 0; }").unwrap_err().to_string(),
+            "Cannot convert a value of type 'int *' to 'double'"
+        );
+    }
+}
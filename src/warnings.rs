@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+use crate::ast::*;
+use crate::diagnostics::{Diagnostic,Diagnostics};
+
+/// Which warnings to report and how, chosen with `-W` and `-Werror`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Warnings {
+    /// Whether to look for code that is valid but likely a mistake.
+    pub enabled: bool,
+    /// Whether warnings are reported as errors, which fail the compilation.
+    pub as_errors: bool,
+}
+
+/// Looks for likely mistakes in a program that has been resolved, type checked and had its
+/// loops labeled: local variables that are never read, statements that can never run
+/// because they follow a `return`, `goto`, `break` or `continue`, and functions other than
+/// `main` that can reach the end of their body without returning a value.
+///
+/// # Arguments
+///
+/// * `program` - The checked AST.
+/// * `warnings` - Whether to look for mistakes, and whether to report them as errors.
+///
+/// # Returns
+///
+/// * `Result<Diagnostics, Diagnostics>` - The warnings, in source order, or an `Err` with
+///   them turned into errors if there are any and they are to be treated as errors.
+pub fn check_warnings(program: &Program, warnings: Warnings) -> Result<Diagnostics, Diagnostics> {
+    if !warnings.enabled {
+        return Ok(Diagnostics::default());
+    }
+    let mut found: Vec<Diagnostic> = Vec::new();
+    for declaration in &program.declarations {
+        if let Declaration::Function(FunDecl { name, body: Some(body), span, .. }) = declaration {
+            let mut checker = FunctionChecker::default();
+            // C returns 0 from main when it reaches the end of its body
+            if checker.check_block(body) && name != "main" {
+                checker.warnings.push(Diagnostic::warning(*span, format!("Function '{}' can reach its end without returning a value", name)));
+            }
+            for (unique_name, span) in &checker.declared {
+                if !checker.reads.contains(unique_name) {
+                    let name = unique_name.source_name();
+                    checker.warnings.push(Diagnostic::warning(*span, format!("Variable '{}' is never read", name)));
+                }
+            }
+            checker.warnings.sort_by_key(|warning| warning.span.map(|span| (span.line, span.column)));
+            found.extend(checker.warnings);
+        }
+    }
+    let found = Diagnostics(found);
+    if warnings.as_errors && !found.0.is_empty() {
+        return Err(found.into_errors());
+    }
+    Ok(found)
+}
+
+/// What is learned about one function while walking its body.
+#[derive(Default)]
+struct FunctionChecker {
+    /// The local variables declared in the function, other than `extern` ones, with where
+    /// they are declared.
+    declared: Vec<(Name, Span)>,
+    /// The variables whose value is used somewhere in the function. Assigning to a variable
+    /// does not read it, but taking its address or updating it with `+=` or `++` does.
+    reads: HashSet<Name>,
+    /// The labels of the loops and switches some `break` leaves.
+    broken: HashSet<Name>,
+    /// The labels of the loops some `continue` goes on with.
+    continued: HashSet<Name>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl FunctionChecker {
+    /// Walks a block, warning about the first statement after one that never completes,
+    /// unless a label makes it reachable. A `break` after a `return` is left alone, as it
+    /// is usually there to guard against falling through to the next case of a switch.
+    ///
+    /// # Arguments
+    ///
+    /// * `items` - The statements and declarations of the block.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether control can reach the end of the block.
+    fn check_block(&mut self, items: &[BlockItem]) -> bool {
+        let mut reachable = true;
+        let mut warned = false;
+        for item in items {
+            match item {
+                BlockItem::Declaration(Declaration::Variable(declaration)) => self.declare(declaration),
+                BlockItem::Declaration(_) => {}
+                BlockItem::Statement(statement) => {
+                    if matches!(statement, Statement::Case { .. } | Statement::Default { .. }) || contains_label(statement) {
+                        // A jump may lead here even if the statements before never complete
+                        reachable = true;
+                        warned = false;
+                    } else if !reachable && !warned && !matches!(statement, Statement::Break(..)) {
+                        if let Some(span) = statement_span(statement) {
+                            self.warnings.push(Diagnostic::warning(span, "Statement is never executed"));
+                            warned = true;
+                        }
+                    }
+                    let completes = self.check_statement(statement);
+                    reachable = reachable && completes;
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Records a local variable and the variables its initializer reads.
+    fn declare(&mut self, declaration: &VarDecl) {
+        if declaration.storage_class != Some(StorageClass::Extern) {
+            self.declared.push((declaration.name, declaration.span));
+        }
+        if let Some(init) = &declaration.init {
+            self.read_initializer(init);
+        }
+    }
+
+    fn read_initializer(&mut self, init: &Initializer) {
+        match init {
+            Initializer::Single(exp) => self.read(exp),
+            Initializer::Compound(inits, _) => inits.iter().for_each(|init| self.read_initializer(init)),
+        }
+    }
+
+    /// Walks a statement, recording the variables it reads and the loops it breaks out of.
+    ///
+    /// # Arguments
+    ///
+    /// * `statement` - The statement to be walked.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether control can go on to the statement after it.
+    fn check_statement(&mut self, statement: &Statement) -> bool {
+        match statement {
+            Statement::Return(exp, _) => {
+                self.read(exp);
+                false
+            }
+            Statement::Expression(exp) => {
+                self.read(exp);
+                true
+            }
+            Statement::If(condition, then, otherwise) => {
+                self.read(condition);
+                let then_completes = self.check_statement(then);
+                let otherwise_completes = otherwise.as_ref().is_none_or(|otherwise| self.check_statement(otherwise));
+                then_completes || otherwise_completes
+            }
+            Statement::While { condition, body, label } => {
+                self.read(condition);
+                self.check_statement(body);
+                !is_always_true(condition) || self.broken.contains(label)
+            }
+            Statement::DoWhile { body, condition, label } => {
+                let body_completes = self.check_statement(body);
+                self.read(condition);
+                let tests_condition = body_completes || self.continued.contains(label);
+                (tests_condition && !is_always_true(condition)) || self.broken.contains(label)
+            }
+            Statement::For { init, condition, post, body, label } => {
+                match init.as_ref() {
+                    ForInit::Declaration(declaration) => self.declare(declaration),
+                    ForInit::Expression(exp) => exp.iter().for_each(|exp| self.read(exp)),
+                }
+                condition.iter().chain(post).for_each(|exp| self.read(exp));
+                self.check_statement(body);
+                condition.as_ref().is_some_and(|condition| !is_always_true(condition)) || self.broken.contains(label)
+            }
+            Statement::Switch { condition, body, cases, label, .. } => {
+                self.read(condition);
+                let body_completes = self.check_statement(body);
+                let has_default = cases.iter().any(|(value, _)| value.is_none());
+                body_completes || !has_default || self.broken.contains(label)
+            }
+            Statement::Case { body, .. } | Statement::Default { body, .. } | Statement::Labeled(_, body, _) => {
+                self.check_statement(body)
+            }
+            Statement::Goto(..) => false,
+            Statement::Break(label, _) => {
+                self.broken.insert(*label);
+                false
+            }
+            Statement::Continue(label, _) => {
+                self.continued.insert(*label);
+                false
+            }
+            Statement::Compound(items) => self.check_block(items),
+            Statement::Null => true,
+        }
+    }
+
+    /// Records the variables an expression reads.
+    fn read(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Const(_) | Exp::String(..) | Exp::WideString(..) | Exp::SizeOf(..) | Exp::SizeOfType(..) => {}
+            Exp::Var(name, _) => {
+                self.reads.insert(*name);
+            }
+            Exp::Assignment(lhs, rhs, _) => {
+                if !matches!(lhs.as_ref(), Exp::Var(..)) {
+                    self.read(lhs);
+                }
+                self.read(rhs);
+            }
+            Exp::CompoundAssignment(_, left, right, _)
+            | Exp::Subscript(left, right, _)
+            | Exp::BinOp(_, left, right, _)
+            | Exp::Comma(left, right, _) => {
+                self.read(left);
+                self.read(right);
+            }
+            Exp::PostfixUpdate(_, inner, _)
+            | Exp::Cast(_, inner, _)
+            | Exp::UnOp(_, inner, _)
+            | Exp::Dereference(inner, _)
+            | Exp::AddressOf(inner, _)
+            | Exp::Member { base: inner, .. } => self.read(inner),
+            Exp::Conditional(condition, then, otherwise, _) => {
+                self.read(condition);
+                self.read(then);
+                self.read(otherwise);
+            }
+            Exp::FunctionCall(_, args, _) => args.iter().for_each(|arg| self.read(arg)),
+        }
+    }
+}
+
+/// Returns whether a statement is or contains a labeled statement, which `goto` can jump to.
+fn contains_label(statement: &Statement) -> bool {
+    match statement {
+        Statement::Labeled(..) => true,
+        Statement::If(_, then, otherwise) => contains_label(then) || otherwise.as_deref().is_some_and(contains_label),
+        Statement::While { body, .. }
+        | Statement::DoWhile { body, .. }
+        | Statement::For { body, .. }
+        | Statement::Switch { body, .. }
+        | Statement::Case { body, .. }
+        | Statement::Default { body, .. } => contains_label(body),
+        Statement::Compound(items) => items.iter().any(|item| matches!(item, BlockItem::Statement(statement) if contains_label(statement))),
+        _ => false,
+    }
+}
+
+/// Returns whether a condition is a nonzero constant, as in `while (1)`.
+fn is_always_true(condition: &Exp) -> bool {
+    matches!(condition, Exp::Const(constant) if !constant.is_zero())
+}
+
+/// Returns the position of the start of a statement, or `None` if it has no position, as
+/// for `;` or an expression statement that is only a constant.
+fn statement_span(statement: &Statement) -> Option<Span> {
+    let span = match statement {
+        Statement::Return(_, span)
+        | Statement::Switch { span, .. }
+        | Statement::Case { span, .. }
+        | Statement::Default { span, .. }
+        | Statement::Labeled(_, _, span)
+        | Statement::Goto(_, span)
+        | Statement::Break(_, span)
+        | Statement::Continue(_, span) => *span,
+        Statement::Expression(exp) | Statement::If(exp, ..) | Statement::While { condition: exp, .. } => exp.span(),
+        Statement::DoWhile { body, .. } => return statement_span(body),
+        Statement::For { init, .. } => match init.as_ref() {
+            ForInit::Declaration(declaration) => declaration.span,
+            ForInit::Expression(exp) => exp.as_ref().map_or(Span::default(), Exp::span),
+        },
+        // A block starts where its first statement or declaration does
+        Statement::Compound(items) => match items.first()? {
+            BlockItem::Statement(statement) => return statement_span(statement),
+            BlockItem::Declaration(Declaration::Variable(declaration)) => declaration.span,
+            BlockItem::Declaration(Declaration::Function(function)) => function.span,
+            BlockItem::Declaration(Declaration::Struct(declaration)) => declaration.span,
+        },
+        Statement::Null => return None,
+    };
+    // Constants carry no position
+    (span.line != 0).then_some(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Severity;
+    use crate::{lex::lex, parse::parse, resolve::resolve_program, typecheck::typecheck_program, label_loops::label_loops};
+
+    fn warnings(source: &str) -> Vec<String> {
+        let ast = resolve_program(parse(lex(source).unwrap()).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(ast).unwrap();
+        let ast = label_loops(ast).unwrap();
+        let found = check_warnings(&ast, Warnings { enabled: true, as_errors: false }).unwrap();
+        found.0.iter().map(Diagnostic::to_string).collect()
+    }
+
+    #[test]
+    fn test_unused_variables() {
+        let source = "int main(void) {\n    int a = 1;\n    int b;\n    int c;\n    int d;\n    b = 2;\n    c = 3;\n    int *p = &d;\n    return c;\n}";
+        assert_eq!(warnings(source), vec!["2:9: Variable 'a' is never read", "3:9: Variable 'b' is never read", "8:10: Variable 'p' is never read"]);
+        assert!(warnings("int main(void) { int i = 0; i++; extern int x; return 0; }").is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let source = "int main(void) {\n    return 1;\n    main();\n    main();\nend:\n    return 2;\n    goto end;\n}";
+        assert_eq!(warnings(source), vec!["3:5: Statement is never executed", "7:5: Statement is never executed"]);
+        assert!(warnings("int f(int x) { switch (x) case 1: return 1; return 0; }").is_empty());
+        assert!(warnings("int f(int i) { goto inside; while (i) inside: i = i - 1; return i; }").is_empty());
+        let source = "int main(void) {\n    {\n        return 1;\n        {\n            main();\n        }\n    }\n    main();\n}";
+        assert_eq!(warnings(source), vec!["5:13: Statement is never executed", "8:5: Statement is never executed"]);
+        assert!(warnings("int f(int x) { switch (x) { case 1: return 1; case 2: { x = 3; } } return x; }").is_empty());
+    }
+
+    #[test]
+    fn test_missing_return() {
+        let source = "int f(int x) {\n    if (x)\n        return 1;\n}\nint g(int x) {\n    if (x)\n        return 1;\n    else\n        return 2;\n}\nint h(void) {\n    while (1)\n        ;\n}\nint k(void) {\n    for (;;)\n        break;\n}\nint main(void) {\n}";
+        assert_eq!(
+            warnings(source),
+            vec![
+                "1:5: Function 'f' can reach its end without returning a value",
+                "15:5: Function 'k' can reach its end without returning a value",
+            ]
+        );
+        assert!(warnings("int f(int x) { { if (x) { return 1; } else { return 2; } } }").is_empty());
+    }
+
+    #[test]
+    fn test_warnings_as_errors() {
+        let ast = resolve_program(parse(lex("int f(void) { int x; return 0; }").unwrap()).unwrap()).unwrap();
+        let (ast, _, _) = typecheck_program(ast).unwrap();
+        let ast = label_loops(ast).unwrap();
+        assert_eq!(check_warnings(&ast, Warnings::default()), Ok(Diagnostics::default()));
+        let errors = check_warnings(&ast, Warnings { enabled: true, as_errors: true }).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].severity, Severity::Error);
+    }
+}